@@ -10,6 +10,26 @@ fn main() {
             .file("src/app_delegate.m")
             .flag("-fobjc-arc") // Enable Automatic Reference Counting
             .compile("app_delegate");
+
+        // Printing rendered pages
+        println!("cargo:rustc-link-lib=framework=ApplicationServices");
+        cc::Build::new()
+            .file("src/print.m")
+            .flag("-fobjc-arc")
+            .compile("print");
+
+        // The share sheet
+        cc::Build::new()
+            .file("src/share.m")
+            .flag("-fobjc-arc")
+            .compile("share");
+
+        // Keychain access for signing documents and saved passwords
+        println!("cargo:rustc-link-lib=framework=Security");
+        cc::Build::new()
+            .file("src/keychain.m")
+            .flag("-fobjc-arc")
+            .compile("keychain");
     }
 
     tauri_build::build()
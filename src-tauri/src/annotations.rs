@@ -0,0 +1,1259 @@
+// Comments written into the PDF: highlights, underlines, strikeouts, notes,
+// rectangles and freehand ink.
+//
+// They are regular annotations, each with an appearance stream of its own,
+// so Acrobat, Preview and browsers show them as drawn here rather than
+// relying on their own defaults. Highlights are drawn with the multiply
+// blend mode, which darkens the text under them instead of covering it.
+// Ink is drawn as the lines between the points of each stroke, with round
+// ends and joins; strokes of another color or width go in an annotation of
+// their own.
+// Annotations of these kinds made by other tools are listed and can be
+// changed too, which draws them anew; annotations of other kinds are left
+// alone.
+//
+// Annotations go by their object number and generation, e.g. "12 0", which
+// stay the same across edits and saves.
+//
+// The summary lists the comments of every kind, page by page, in Markdown:
+// the text under highlights and other markup as quotes, followed by what was
+// written about it, so it can go straight into notes apps like Obsidian.
+//
+// Merging brings in the annotations of another copy of the same document,
+// say one annotated on another machine. Annotations are matched by their
+// unique names (/NM), or failing that by kind and place. Those only the other
+// copy has are added; those that differ between the copies, or that overlap
+// an annotation of the same kind here, are reported for the user to settle
+// rather than one copy's taking the place of the other's. Merging can't tell
+// an annotation deleted here from one added there, so it's added back.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+
+use crate::compose::load_document;
+use crate::convert::escape_markdown;
+use crate::edit::{apply_edit, apply_edit_with, EditedDocument};
+use crate::engine::Engine;
+use crate::metadata::{format_pdf_date, parse_pdf_date};
+use crate::objects::{self, PageSpace};
+use crate::pdf::{blocking, Bounds, DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scheduler::RenderScheduler;
+use crate::scope;
+use crate::text::page_runs;
+use crate::tiles::TileCache;
+
+// Annotation flags (PDF 32000-1, 12.5.3)
+const PRINT: i64 = 1 << 2;
+const NO_ZOOM: i64 = 1 << 3;
+const NO_ROTATE: i64 = 1 << 4;
+
+// Size of the icon of a note, in points
+const NOTE_SIZE: f32 = 20.0;
+// Line widths of rectangles and ink, unless told otherwise
+const RECTANGLE_LINE_WIDTH: f32 = 1.5;
+const INK_LINE_WIDTH: f32 = 2.0;
+
+// How far positions, in points, and colors and opacities may differ for two
+// copies of an annotation to be alike
+const POSITION_TOLERANCE: f32 = 0.5;
+const COLOR_TOLERANCE: f32 = 0.01;
+// How much of the smaller of two annotations the other must cover for them
+// to overlap
+const OVERLAP: f32 = 0.5;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum AnnotationKind {
+    Highlight,
+    Underline,
+    StrikeOut,
+    /// A sticky note, shown as an icon
+    Note,
+    Rectangle,
+    /// Freehand strokes from a pen, stylus or trackpad
+    Ink,
+}
+
+impl AnnotationKind {
+    pub fn subtype(self) -> &'static str {
+        match self {
+            AnnotationKind::Highlight => "Highlight",
+            AnnotationKind::Underline => "Underline",
+            AnnotationKind::StrikeOut => "StrikeOut",
+            AnnotationKind::Note => "Text",
+            AnnotationKind::Rectangle => "Square",
+            AnnotationKind::Ink => "Ink",
+        }
+    }
+
+    pub fn from_subtype(subtype: &[u8]) -> Option<Self> {
+        match subtype {
+            b"Highlight" => Some(AnnotationKind::Highlight),
+            b"Underline" => Some(AnnotationKind::Underline),
+            b"StrikeOut" => Some(AnnotationKind::StrikeOut),
+            b"Text" => Some(AnnotationKind::Note),
+            b"Square" => Some(AnnotationKind::Rectangle),
+            b"Ink" => Some(AnnotationKind::Ink),
+            _ => None,
+        }
+    }
+
+    // Marked up text is covered by quadrilaterals, one to each line
+    fn is_markup(self) -> bool {
+        matches!(
+            self,
+            AnnotationKind::Highlight | AnnotationKind::Underline | AnnotationKind::StrikeOut
+        )
+    }
+
+    pub fn default_color(self) -> [f32; 3] {
+        match self {
+            AnnotationKind::Highlight | AnnotationKind::Note => [1.0, 0.85, 0.0],
+            AnnotationKind::Underline => [0.0, 0.45, 0.85],
+            AnnotationKind::StrikeOut | AnnotationKind::Rectangle => [0.85, 0.1, 0.1],
+            AnnotationKind::Ink => [0.0, 0.0, 0.0],
+        }
+    }
+
+    pub fn default_width(self) -> f32 {
+        match self {
+            AnnotationKind::Ink => INK_LINE_WIDTH,
+            _ => RECTANGLE_LINE_WIDTH,
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Annotation {
+    pub id: String,
+    pub page: i32,
+    pub kind: AnnotationKind,
+    /// Where the annotation sits, in page space
+    pub bounds: Bounds,
+    /// The lines of marked up text, or just `bounds`
+    pub rects: Vec<Bounds>,
+    /// The points of each stroke of ink, in page space
+    pub strokes: Vec<Vec<[f32; 2]>>,
+    pub color: [f32; 3],
+    pub opacity: f32,
+    /// Line width of rectangles and ink
+    pub width: Option<f32>,
+    /// The comment
+    pub contents: Option<String>,
+    pub author: Option<String>,
+    /// ISO 8601
+    pub modified: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewAnnotation {
+    pub kind: AnnotationKind,
+    /// In page space: the lines of text to mark up, the rectangle, or where
+    /// to put a note (its top left corner). Ink takes `strokes` instead
+    #[serde(default)]
+    pub rects: Vec<Bounds>,
+    /// The points of each stroke of ink, in page space
+    #[serde(default)]
+    pub strokes: Vec<Vec<[f32; 2]>>,
+    pub color: Option<[f32; 3]>,
+    pub opacity: Option<f32>,
+    pub width: Option<f32>,
+    pub contents: Option<String>,
+    pub author: Option<String>,
+}
+
+impl NewAnnotation {
+    /// What to write the annotation from, with the defaults filled in.
+    pub fn into_spec(self) -> Spec {
+        let kind = self.kind;
+        Spec {
+            kind,
+            rects: self.rects,
+            strokes: self.strokes,
+            color: self.color.unwrap_or(kind.default_color()),
+            opacity: self.opacity.unwrap_or(1.0),
+            width: self.width.unwrap_or(kind.default_width()),
+            contents: self.contents,
+            author: self.author,
+            name: None,
+            modified: None,
+        }
+    }
+}
+
+/// Changes to an annotation; what is left out stays as it is.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnnotationChanges {
+    pub rects: Option<Vec<Bounds>>,
+    pub strokes: Option<Vec<Vec<[f32; 2]>>>,
+    pub color: Option<[f32; 3]>,
+    pub opacity: Option<f32>,
+    pub width: Option<f32>,
+    /// An empty comment removes it
+    pub contents: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddedAnnotation {
+    pub document: EditedDocument,
+    pub id: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictKind {
+    /// Both copies have the annotation, but not alike
+    Changed,
+    /// The other copy has an annotation of the same kind over this one
+    Overlapping,
+}
+
+/// An annotation merging leaves for the user to settle. Theirs is taken with
+/// `update_annotation` on ours (changed) or `add_annotation` (overlapping);
+/// its id is that in the other file.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnnotationConflict {
+    pub kind: ConflictKind,
+    pub ours: Annotation,
+    pub theirs: Annotation,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergedAnnotations {
+    pub document: EditedDocument,
+    /// Annotations only the other copy had
+    pub added: usize,
+    /// Annotations both copies have alike
+    pub unchanged: usize,
+    pub conflicts: Vec<AnnotationConflict>,
+}
+
+/// Everything an annotation is written from, in page space.
+pub struct Spec {
+    pub kind: AnnotationKind,
+    pub rects: Vec<Bounds>,
+    pub strokes: Vec<Vec<[f32; 2]>>,
+    pub color: [f32; 3],
+    pub opacity: f32,
+    pub width: f32,
+    pub contents: Option<String>,
+    pub author: Option<String>,
+    /// The unique name (/NM) other tools know the annotation by; new
+    /// annotations get one if it's left out
+    pub name: Option<String>,
+    /// PDF date of the last change; now, if left out
+    pub modified: Option<String>,
+}
+
+fn format_id((number, generation): ObjectId) -> String {
+    format!("{} {}", number, generation)
+}
+
+fn parse_id(id: &str) -> Result<ObjectId, String> {
+    let mut parts = id.split_whitespace().map(str::parse::<u32>);
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(number)), Some(Ok(generation)), None) if generation <= u16::MAX as u32 => {
+            Ok((number, generation as u16))
+        }
+        _ => Err(format!("{} isn't an annotation id", id)),
+    }
+}
+
+// Colors of one, three or four components, as RGB
+fn rgb(doc: &Document, color: &[Object]) -> Option<[f32; 3]> {
+    let values: Vec<f32> = color
+        .iter()
+        .filter_map(|c| objects::number(objects::resolve(doc, c)))
+        .collect();
+    match *values.as_slice() {
+        [gray] => Some([gray; 3]),
+        [r, g, b] => Some([r, g, b]),
+        [c, m, y, k] => Some([
+            (1.0 - c) * (1.0 - k),
+            (1.0 - m) * (1.0 - k),
+            (1.0 - y) * (1.0 - k),
+        ]),
+        _ => None,
+    }
+}
+
+fn read_annotation(
+    doc: &Document,
+    id: ObjectId,
+    page: i32,
+    space: &PageSpace,
+) -> Option<(Annotation, Spec)> {
+    let annot = doc.get_dictionary(id).ok()?;
+    let kind = AnnotationKind::from_subtype(objects::get_name(doc, annot, b"Subtype")?)?;
+    let rect = objects::rect(doc, objects::get(doc, annot, b"Rect")?)?;
+    let bounds = space.rect(rect);
+    let quads: Vec<f32> = objects::get_array(doc, annot, b"QuadPoints")
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|v| objects::number(objects::resolve(doc, v)))
+        .collect();
+    let rects = match kind.is_markup() && quads.len() >= 8 {
+        true => quads
+            .chunks_exact(8)
+            .map(|quad| {
+                let xs = [quad[0], quad[2], quad[4], quad[6]];
+                let ys = [quad[1], quad[3], quad[5], quad[7]];
+                space.rect([
+                    xs.iter().copied().fold(f32::INFINITY, f32::min),
+                    ys.iter().copied().fold(f32::INFINITY, f32::min),
+                    xs.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+                    ys.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+                ])
+            })
+            .collect(),
+        false => vec![bounds],
+    };
+    let strokes: Vec<Vec<[f32; 2]>> = objects::get_array(doc, annot, b"InkList")
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|stroke| objects::resolve(doc, stroke).as_array().ok())
+        .map(|stroke| {
+            let values: Vec<f32> = stroke
+                .iter()
+                .filter_map(|v| objects::number(objects::resolve(doc, v)))
+                .collect();
+            values
+                .chunks_exact(2)
+                .map(|point| {
+                    let (x, y) = space.point(point[0], point[1]);
+                    [x, y]
+                })
+                .collect()
+        })
+        .collect();
+    let width = objects::get_dict(doc, annot, b"BS")
+        .and_then(|bs| objects::get(doc, bs, b"W"))
+        .and_then(objects::number)
+        .unwrap_or(kind.default_width());
+    let color = objects::get_array(doc, annot, b"C")
+        .and_then(|c| rgb(doc, c))
+        .unwrap_or(kind.default_color());
+    let opacity = objects::get(doc, annot, b"CA")
+        .and_then(objects::number)
+        .unwrap_or(1.0);
+    let contents = objects::get_text(doc, annot, b"Contents").filter(|c| !c.is_empty());
+    let author = objects::get_text(doc, annot, b"T");
+    let modified = objects::get_text(doc, annot, b"M").and_then(|m| parse_pdf_date(&m));
+    let spec = Spec {
+        kind,
+        rects: rects.clone(),
+        strokes: strokes.clone(),
+        color,
+        opacity,
+        width,
+        contents: contents.clone(),
+        author: author.clone(),
+        name: objects::get_text(doc, annot, b"NM"),
+        modified: None,
+    };
+    let annotation = Annotation {
+        id: format_id(id),
+        page,
+        kind,
+        bounds,
+        rects,
+        strokes,
+        color,
+        opacity,
+        width: matches!(kind, AnnotationKind::Rectangle | AnnotationKind::Ink).then_some(width),
+        contents,
+        author,
+        modified,
+    };
+    Some((annotation, spec))
+}
+
+fn page_annotations(doc: &Document, page: i32) -> Vec<Annotation> {
+    let Some(page_id) = doc.get_pages().get(&(page as u32 + 1)).copied() else {
+        return Vec::new();
+    };
+    let Ok(page_dict) = doc.get_dictionary(page_id) else {
+        return Vec::new();
+    };
+    let space = PageSpace::new(doc, page_id);
+    objects::get_array(doc, page_dict, b"Annots")
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|annot| annot.as_reference().ok())
+        .filter_map(|id| read_annotation(doc, id, page, &space).map(|(annotation, _)| annotation))
+        .collect()
+}
+
+// The annotations of a page, which may be an array of its own
+fn annots_mut(doc: &mut Document, page_id: ObjectId) -> Result<&mut Vec<Object>, String> {
+    let page = doc.get_dictionary(page_id).map_err(|e| e.to_string())?;
+    if let Ok(Object::Reference(id)) = page.get(b"Annots") {
+        let id = *id;
+        return doc
+            .get_object_mut(id)
+            .and_then(Object::as_array_mut)
+            .map_err(|e| e.to_string());
+    }
+    let page = doc.get_dictionary_mut(page_id).map_err(|e| e.to_string())?;
+    if !matches!(page.get(b"Annots"), Ok(Object::Array(_))) {
+        page.set("Annots", Vec::<Object>::new());
+    }
+    page.get_mut(b"Annots")
+        .and_then(Object::as_array_mut)
+        .map_err(|e| e.to_string())
+}
+
+// The page an annotation is on, with its index
+fn annotation_page(doc: &Document, id: ObjectId) -> Result<(i32, ObjectId), String> {
+    for (number, page_id) in doc.get_pages() {
+        let Ok(page) = doc.get_dictionary(page_id) else {
+            continue;
+        };
+        let annots = objects::get_array(doc, page, b"Annots").unwrap_or(&[]);
+        if annots
+            .iter()
+            .any(|annot| annot.as_reference().ok() == Some(id))
+        {
+            return Ok((number as i32 - 1, page_id));
+        }
+    }
+    Err(format!("There is no annotation {}", format_id(id)))
+}
+
+// A name no other annotation has, like the UUIDs other tools use
+fn unique_name() -> Result<String, String> {
+    let mut bytes = [0u8; 16];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| "Failed to name the annotation".to_string())?;
+    Ok(hex::encode(bytes))
+}
+
+fn union(rects: &[[f32; 4]]) -> [f32; 4] {
+    rects.iter().fold(
+        [
+            f32::INFINITY,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            f32::NEG_INFINITY,
+        ],
+        |[x0, y0, x1, y1], r| [x0.min(r[0]), y0.min(r[1]), x1.max(r[2]), y1.max(r[3])],
+    )
+}
+
+fn fill_rect(r: [f32; 4]) -> Operation {
+    Operation::new(
+        "re",
+        vec![
+            r[0].into(),
+            r[1].into(),
+            (r[2] - r[0]).into(),
+            (r[3] - r[1]).into(),
+        ],
+    )
+}
+
+/// Writes an annotation from `spec`, as a new object or over `id`. Returns
+/// its id.
+pub fn write_annotation(
+    doc: &mut Document,
+    page_id: ObjectId,
+    id: Option<ObjectId>,
+    spec: &Spec,
+) -> Result<ObjectId, String> {
+    let space = PageSpace::new(doc, page_id);
+    // A stroke is a line through points in user space
+    let strokes: Vec<Vec<(f32, f32)>> = spec
+        .strokes
+        .iter()
+        .filter(|stroke| !stroke.is_empty())
+        .map(|stroke| {
+            stroke
+                .iter()
+                .map(|&[x, y]| {
+                    let r = space.user_rect(Bounds {
+                        x,
+                        y,
+                        width: 0.0,
+                        height: 0.0,
+                    });
+                    (r[0], r[1])
+                })
+                .collect()
+        })
+        .collect();
+    let placed = match spec.kind {
+        AnnotationKind::Ink => !strokes.is_empty(),
+        _ => !spec.rects.is_empty(),
+    };
+    if !placed {
+        return Err("Select where the annotation goes".to_string());
+    }
+    let width = spec.width.max(0.1);
+    let kind = spec.kind;
+    let [r, g, b] = spec.color.map(|c| c.clamp(0.0, 1.0));
+    let opacity = spec.opacity.clamp(0.0, 1.0);
+    let color: Vec<Object> = vec![r.into(), g.into(), b.into()];
+
+    // Where the annotation is drawn, in user space, and what covers it
+    let user_rects: Vec<[f32; 4]> = spec.rects.iter().map(|&r| space.user_rect(r)).collect();
+    let mut operations = vec![Operation::new("gs", vec!["GS0".into()])];
+    let rect = match kind {
+        AnnotationKind::Highlight => {
+            operations.push(Operation::new("rg", color.clone()));
+            for &r in &user_rects {
+                operations.push(fill_rect(r));
+            }
+            operations.push(Operation::new("f", vec![]));
+            union(&user_rects)
+        }
+        AnnotationKind::Underline | AnnotationKind::StrikeOut => {
+            operations.push(Operation::new("rg", color.clone()));
+            for line in &spec.rects {
+                // The line is drawn in page space, so it follows the text on
+                // rotated pages
+                let thickness = (line.height / 14.0).max(0.5);
+                let y = match kind {
+                    AnnotationKind::Underline => line.y + line.height * 0.95 - thickness,
+                    _ => line.y + line.height * 0.55 - thickness / 2.0,
+                };
+                operations.push(fill_rect(space.user_rect(Bounds {
+                    y,
+                    height: thickness,
+                    ..*line
+                })));
+            }
+            operations.push(Operation::new("f", vec![]));
+            union(&user_rects)
+        }
+        AnnotationKind::Note => {
+            let corner = spec.rects[0];
+            let r = space.user_rect(Bounds {
+                x: corner.x,
+                y: corner.y,
+                width: NOTE_SIZE,
+                height: NOTE_SIZE,
+            });
+            // A page with lines of writing
+            let (x, y, size) = (r[0], r[1], NOTE_SIZE);
+            operations.push(Operation::new("rg", color.clone()));
+            operations.push(Operation::new("G", vec![0.25.into()]));
+            operations.push(Operation::new("w", vec![1.into()]));
+            operations.push(fill_rect([
+                x + 0.5,
+                y + 0.5,
+                x + size - 0.5,
+                y + size - 0.5,
+            ]));
+            operations.push(Operation::new("B", vec![]));
+            for i in 1..=3 {
+                let line_y = y + size * (1.0 - i as f32 / 4.0);
+                operations.push(Operation::new("m", vec![(x + 4.0).into(), line_y.into()]));
+                operations.push(Operation::new(
+                    "l",
+                    vec![(x + size - 4.0).into(), line_y.into()],
+                ));
+            }
+            operations.push(Operation::new("S", vec![]));
+            [x, y, x + size, y + size]
+        }
+        AnnotationKind::Rectangle => {
+            let r = user_rects[0];
+            let half = width / 2.0;
+            operations.push(Operation::new("RG", color.clone()));
+            operations.push(Operation::new("w", vec![width.into()]));
+            operations.push(fill_rect([
+                r[0] + half,
+                r[1] + half,
+                r[2] - half,
+                r[3] - half,
+            ]));
+            operations.push(Operation::new("S", vec![]));
+            r
+        }
+        AnnotationKind::Ink => {
+            operations.push(Operation::new("RG", color.clone()));
+            operations.push(Operation::new("w", vec![width.into()]));
+            operations.push(Operation::new("J", vec![1.into()]));
+            operations.push(Operation::new("j", vec![1.into()]));
+            for stroke in &strokes {
+                let (x, y) = stroke[0];
+                operations.push(Operation::new("m", vec![x.into(), y.into()]));
+                // A single point still leaves a dot, with round ends
+                let rest = match stroke.len() {
+                    1 => &stroke[..],
+                    _ => &stroke[1..],
+                };
+                for &(x, y) in rest {
+                    operations.push(Operation::new("l", vec![x.into(), y.into()]));
+                }
+            }
+            operations.push(Operation::new("S", vec![]));
+            let points: Vec<[f32; 4]> = strokes
+                .iter()
+                .flatten()
+                .map(|&(x, y)| [x, y, x, y])
+                .collect();
+            let [x0, y0, x1, y1] = union(&points);
+            let margin = width / 2.0 + 1.0;
+            [x0 - margin, y0 - margin, x1 + margin, y1 + margin]
+        }
+    };
+    let content = Content { operations }
+        .encode()
+        .map_err(|e| format!("Failed to draw the annotation: {}", e))?;
+
+    let mut state = dictionary! {
+        "Type" => "ExtGState",
+        "ca" => opacity,
+        "CA" => opacity,
+    };
+    if kind == AnnotationKind::Highlight {
+        state.set("BM", "Multiply");
+    }
+    let rect_object: Vec<Object> = rect.iter().map(|&v| v.into()).collect();
+    let appearance = doc.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => rect_object.clone(),
+            "Resources" => dictionary! {
+                "ExtGState" => dictionary! { "GS0" => state },
+            },
+        },
+        content,
+    ));
+
+    let id = match id {
+        Some(id) => id,
+        None => {
+            let id = doc.add_object(Dictionary::new());
+            annots_mut(doc, page_id)?.push(Object::Reference(id));
+            id
+        }
+    };
+    let annot = doc.get_dictionary_mut(id).map_err(|e| e.to_string())?;
+    annot.set("Type", "Annot");
+    annot.set("Subtype", kind.subtype());
+    annot.set("Rect", rect_object);
+    annot.set("P", Object::Reference(page_id));
+    annot.set("C", color);
+    annot.set("CA", opacity);
+    let modified = spec
+        .modified
+        .clone()
+        .unwrap_or_else(|| format_pdf_date(SystemTime::now()));
+    annot.set("M", Object::string_literal(modified));
+    match &spec.name {
+        Some(name) => annot.set("NM", objects::encode_text(name)),
+        None if !annot.has(b"NM") => annot.set("NM", Object::string_literal(unique_name()?)),
+        None => {}
+    }
+    annot.set("AP", dictionary! { "N" => Object::Reference(appearance) });
+    let flags = match kind {
+        // Notes keep their icon upright and the same size at any zoom
+        AnnotationKind::Note => PRINT | NO_ZOOM | NO_ROTATE,
+        _ => PRINT,
+    };
+    annot.set("F", flags);
+    match &spec.contents {
+        Some(contents) if !contents.is_empty() => {
+            annot.set("Contents", objects::encode_text(contents))
+        }
+        _ => {
+            annot.remove(b"Contents");
+        }
+    }
+    if let Some(author) = &spec.author {
+        annot.set("T", objects::encode_text(author));
+    }
+    match kind {
+        _ if kind.is_markup() => {
+            let quads: Vec<Object> = user_rects
+                .iter()
+                // Top left, top right, bottom left, bottom right, as Acrobat
+                // writes them
+                .flat_map(|r| [r[0], r[3], r[2], r[3], r[0], r[1], r[2], r[1]])
+                .map(Object::from)
+                .collect();
+            annot.set("QuadPoints", quads);
+        }
+        AnnotationKind::Note => {
+            annot.set("Name", "Note");
+            annot.set("Open", false);
+        }
+        AnnotationKind::Ink => {
+            let strokes: Vec<Object> = strokes
+                .iter()
+                .map(|stroke| {
+                    let points: Vec<Object> = stroke
+                        .iter()
+                        .flat_map(|&(x, y)| [x.into(), y.into()])
+                        .collect();
+                    Object::Array(points)
+                })
+                .collect();
+            annot.set("InkList", strokes);
+            annot.set("BS", dictionary! { "W" => width, "S" => "S" });
+        }
+        _ => {
+            annot.set("BS", dictionary! { "W" => width, "S" => "S" });
+        }
+    }
+    Ok(id)
+}
+
+fn update(doc: &mut Document, id: ObjectId, changes: AnnotationChanges) -> Result<(), String> {
+    let (page, page_id) = annotation_page(doc, id)?;
+    let space = PageSpace::new(doc, page_id);
+    let (_, mut spec) = read_annotation(doc, id, page, &space)
+        .ok_or_else(|| format!("Annotation {} can't be changed here", format_id(id)))?;
+    if let Some(rects) = changes.rects {
+        spec.rects = rects;
+    }
+    if let Some(strokes) = changes.strokes {
+        spec.strokes = strokes;
+    }
+    if let Some(width) = changes.width {
+        spec.width = width;
+    }
+    if let Some(color) = changes.color {
+        spec.color = color;
+    }
+    if let Some(opacity) = changes.opacity {
+        spec.opacity = opacity;
+    }
+    if let Some(contents) = changes.contents {
+        spec.contents = Some(contents);
+    }
+    write_annotation(doc, page_id, Some(id), &spec)?;
+    Ok(())
+}
+
+fn delete(doc: &mut Document, id: ObjectId) -> Result<(), String> {
+    let (_, page_id) = annotation_page(doc, id)?;
+    let popup = doc
+        .get_dictionary(id)
+        .ok()
+        .and_then(|annot| annot.get(b"Popup").ok())
+        .and_then(|popup| popup.as_reference().ok());
+    annots_mut(doc, page_id)?.retain(|annot| {
+        let reference = annot.as_reference().ok();
+        reference != Some(id) && (popup.is_none() || reference != popup)
+    });
+    doc.objects.remove(&id);
+    if let Some(popup) = popup {
+        doc.objects.remove(&popup);
+    }
+    Ok(())
+}
+
+// The annotations of every page, each with the date it was last changed
+fn entries(doc: &Document) -> Vec<(Annotation, Spec)> {
+    let mut entries = Vec::new();
+    for (number, page_id) in doc.get_pages() {
+        let Ok(page) = doc.get_dictionary(page_id) else {
+            continue;
+        };
+        let space = PageSpace::new(doc, page_id);
+        for annot in objects::get_array(doc, page, b"Annots").unwrap_or(&[]) {
+            let Ok(id) = annot.as_reference() else {
+                continue;
+            };
+            if let Some((annotation, mut spec)) =
+                read_annotation(doc, id, number as i32 - 1, &space)
+            {
+                spec.modified = doc
+                    .get_dictionary(id)
+                    .ok()
+                    .and_then(|annot| objects::get_text(doc, annot, b"M"));
+                entries.push((annotation, spec));
+            }
+        }
+    }
+    entries
+}
+
+fn near(a: &[f32], b: &[f32], tolerance: f32) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| (a - b).abs() <= tolerance)
+}
+
+// Whether two annotations look the same and say the same
+fn alike(a: &Spec, b: &Spec) -> bool {
+    let rects = |spec: &Spec| -> Vec<f32> {
+        spec.rects
+            .iter()
+            .flat_map(|r| [r.x, r.y, r.width, r.height])
+            .collect()
+    };
+    let points =
+        |spec: &Spec| -> Vec<f32> { spec.strokes.iter().flatten().flatten().copied().collect() };
+    a.kind == b.kind
+        && a.contents == b.contents
+        && near(&a.color, &b.color, COLOR_TOLERANCE)
+        && near(&[a.opacity], &[b.opacity], COLOR_TOLERANCE)
+        && near(&[a.width], &[b.width], POSITION_TOLERANCE)
+        && a.strokes.len() == b.strokes.len()
+        && near(&rects(a), &rects(b), POSITION_TOLERANCE)
+        && near(&points(a), &points(b), POSITION_TOLERANCE)
+}
+
+fn overlapping(a: Bounds, b: Bounds) -> bool {
+    let width = (a.x + a.width).min(b.x + b.width) - a.x.max(b.x);
+    let height = (a.y + a.height).min(b.y + b.height) - a.y.max(b.y);
+    if width <= 0.0 || height <= 0.0 {
+        return false;
+    }
+    let smaller = (a.width * a.height).min(b.width * b.height).max(1.0);
+    width * height / smaller >= OVERLAP
+}
+
+// Adds the annotations only `other` has. Returns the numbers added and
+// alike, and the conflicts
+fn merge(
+    doc: &mut Document,
+    other: &Document,
+) -> Result<(usize, usize, Vec<AnnotationConflict>), String> {
+    let pages = doc.get_pages();
+    let other_pages = other.get_pages().len();
+    if other_pages != pages.len() {
+        return Err(format!(
+            "The other file has {} pages and this one {}; only copies of the same document can be merged",
+            other_pages,
+            pages.len()
+        ));
+    }
+    let ours = entries(doc);
+    let (mut added, mut unchanged, mut conflicts) = (0, 0, Vec::new());
+    for (theirs, spec) in entries(other) {
+        let named = spec.name.as_ref().and_then(|name| {
+            ours.iter()
+                .find(|(_, ours)| ours.name.as_ref() == Some(name))
+        });
+        if let Some((ours, our_spec)) = named {
+            if ours.page == theirs.page && alike(our_spec, &spec) {
+                unchanged += 1;
+            } else {
+                conflicts.push(AnnotationConflict {
+                    kind: ConflictKind::Changed,
+                    ours: ours.clone(),
+                    theirs,
+                });
+            }
+            continue;
+        }
+
+        // Annotations of other tools may have no names
+        let covered: Vec<&(Annotation, Spec)> = ours
+            .iter()
+            .filter(|(ours, _)| {
+                ours.page == theirs.page
+                    && ours.kind == theirs.kind
+                    && overlapping(ours.bounds, theirs.bounds)
+            })
+            .collect();
+        if covered.iter().any(|(_, our_spec)| alike(our_spec, &spec)) {
+            unchanged += 1;
+        } else if let Some((ours, _)) = covered.first() {
+            conflicts.push(AnnotationConflict {
+                kind: ConflictKind::Overlapping,
+                ours: ours.clone(),
+                theirs,
+            });
+        } else {
+            let page_id = pages[&(theirs.page as u32 + 1)];
+            write_annotation(doc, page_id, None, &spec)?;
+            added += 1;
+        }
+    }
+    Ok((added, unchanged, conflicts))
+}
+
+// An annotation of any kind, as the summary shows it
+struct SummaryItem {
+    label: &'static str,
+    // Marked up text, in page space
+    quoted: Vec<Bounds>,
+    bounds: Bounds,
+    contents: Option<String>,
+}
+
+fn summary_label(subtype: &[u8]) -> Option<&'static str> {
+    Some(match subtype {
+        b"Highlight" => "Highlight",
+        b"Underline" => "Underline",
+        b"StrikeOut" => "Strikeout",
+        b"Squiggly" => "Squiggly underline",
+        b"Text" => "Note",
+        b"FreeText" => "Text box",
+        b"Square" => "Rectangle",
+        b"Circle" => "Ellipse",
+        b"Line" | b"PolyLine" | b"Polygon" => "Shape",
+        b"Ink" => "Drawing",
+        b"Stamp" => "Stamp",
+        b"Caret" => "Insertion",
+        b"FileAttachment" => "Attachment",
+        b"Sound" => "Sound",
+        // Links, form fields, pop-ups and the like aren't comments
+        _ => return None,
+    })
+}
+
+// The comments of each page that has any, in reading order
+fn summary_items(doc: &Document) -> BTreeMap<i32, Vec<SummaryItem>> {
+    let mut pages = BTreeMap::new();
+    for (number, page_id) in doc.get_pages() {
+        let Ok(page) = doc.get_dictionary(page_id) else {
+            continue;
+        };
+        let space = PageSpace::new(doc, page_id);
+        let mut items = Vec::new();
+        for annot in objects::get_array(doc, page, b"Annots").unwrap_or(&[]) {
+            let Ok(annot) = objects::resolve(doc, annot).as_dict() else {
+                continue;
+            };
+            let Some(subtype) = objects::get_name(doc, annot, b"Subtype") else {
+                continue;
+            };
+            let Some(label) = summary_label(subtype) else {
+                continue;
+            };
+            let Some(rect) = objects::get(doc, annot, b"Rect").and_then(|r| objects::rect(doc, r))
+            else {
+                continue;
+            };
+            let quads: Vec<f32> = objects::get_array(doc, annot, b"QuadPoints")
+                .unwrap_or(&[])
+                .iter()
+                .filter_map(|v| objects::number(objects::resolve(doc, v)))
+                .collect();
+            let marks_text = matches!(
+                subtype,
+                b"Highlight" | b"Underline" | b"StrikeOut" | b"Squiggly"
+            );
+            let quoted = match marks_text {
+                true => quads
+                    .chunks_exact(8)
+                    .map(|q| {
+                        let xs = [q[0], q[2], q[4], q[6]];
+                        let ys = [q[1], q[3], q[5], q[7]];
+                        space.rect([
+                            xs.iter().copied().fold(f32::INFINITY, f32::min),
+                            ys.iter().copied().fold(f32::INFINITY, f32::min),
+                            xs.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+                            ys.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+                        ])
+                    })
+                    .collect(),
+                false => Vec::new(),
+            };
+            items.push(SummaryItem {
+                label,
+                quoted,
+                bounds: space.rect(rect),
+                contents: objects::get_text(doc, annot, b"Contents")
+                    .map(|c| c.trim().to_string())
+                    .filter(|c| !c.is_empty()),
+            });
+        }
+        items.retain(|item| !item.quoted.is_empty() || item.contents.is_some());
+        items.sort_by(|a, b| {
+            (a.bounds.y, a.bounds.x)
+                .partial_cmp(&(b.bounds.y, b.bounds.x))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        if !items.is_empty() {
+            pages.insert(number as i32 - 1, items);
+        }
+    }
+    pages
+}
+
+// The text under marked up lines, taking the characters whose middle is
+// inside one of them
+fn quoted_text(text_page: &mupdf::TextPage, lines: &[Bounds]) -> String {
+    let inside = |glyph: &Bounds| {
+        let (x, y) = (glyph.x + glyph.width / 2.0, glyph.y + glyph.height / 2.0);
+        lines
+            .iter()
+            .any(|r| x >= r.x && x <= r.x + r.width && y >= r.y && y <= r.y + r.height)
+    };
+    let mut quote = String::new();
+    for run in page_runs(text_page) {
+        let part: String = run
+            .text
+            .chars()
+            .zip(&run.glyphs)
+            .filter(|(_, glyph)| inside(glyph))
+            .map(|(c, _)| c)
+            .collect();
+        let part = part.trim();
+        if part.is_empty() {
+            continue;
+        }
+        // Words broken across lines are joined again
+        if quote.ends_with('-') {
+            quote.pop();
+        } else if !quote.is_empty() {
+            quote.push(' ');
+        }
+        quote.push_str(part);
+    }
+    quote.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+type QuotedPages = Vec<(i32, Vec<(SummaryItem, Option<String>)>)>;
+
+// Reads the quoted text of the pages with markup
+fn quote_pages(
+    handle: &mupdf::Document,
+    items: BTreeMap<i32, Vec<SummaryItem>>,
+) -> Result<QuotedPages, String> {
+    let mut pages = Vec::new();
+    for (page, items) in items {
+        let text_page = match items.iter().any(|item| !item.quoted.is_empty()) {
+            true => Some(
+                handle
+                    .load_page(page)
+                    .and_then(|p| p.to_text_page(mupdf::TextPageOptions::empty()))
+                    .map_err(|e| e.to_string())?,
+            ),
+            false => None,
+        };
+        let items = items
+            .into_iter()
+            .map(|item| {
+                let quote = text_page
+                    .as_ref()
+                    .filter(|_| !item.quoted.is_empty())
+                    .map(|text_page| quoted_text(text_page, &item.quoted));
+                (item, quote)
+            })
+            .collect();
+        pages.push((page, items));
+    }
+    Ok(pages)
+}
+
+fn to_summary(title: &str, pages: &QuotedPages) -> String {
+    let mut out = format!("# {}\n", escape_markdown(title));
+    for (page, items) in pages {
+        out.push_str(&format!("\n## Page {}\n", page + 1));
+        for (item, quote) in items {
+            out.push('\n');
+            match quote.as_deref().filter(|q| !q.is_empty()) {
+                Some(quote) => {
+                    out.push_str(&format!("> {}\n", escape_markdown(quote)));
+                    if item.label != "Highlight" {
+                        out.push_str(&format!(">\n> *{}*\n", item.label));
+                    }
+                    if let Some(contents) = &item.contents {
+                        out.push('\n');
+                        for line in contents.lines() {
+                            out.push_str(&format!("{}\n", escape_markdown(line)));
+                        }
+                    }
+                }
+                None => {
+                    let contents = item.contents.as_deref().unwrap_or_default();
+                    let mut lines = contents.lines();
+                    out.push_str(&format!(
+                        "**{}:** {}\n",
+                        item.label,
+                        escape_markdown(lines.next().unwrap_or_default())
+                    ));
+                    for line in lines {
+                        out.push_str(&format!("{}\n", escape_markdown(line)));
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Lists the highlights, underlines, strikeouts, notes, rectangles and ink
+/// of a page.
+#[tauri::command]
+pub async fn get_annotations(
+    doc_id: DocId,
+    page: i32,
+    store: State<'_, DocumentStore>,
+) -> Result<Vec<Annotation>, String> {
+    let doc = store.get(doc_id)?;
+    doc.check_page(page)?;
+    blocking(move || doc.with_objects(|objects| Ok(page_annotations(objects, page)))).await
+}
+
+/// Adds an annotation to a page. Like other edits, it is written when the
+/// document is saved.
+#[tauri::command]
+pub async fn add_annotation(
+    doc_id: DocId,
+    page: i32,
+    annotation: NewAnnotation,
+    store: State<'_, DocumentStore>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<AddedAnnotation, String> {
+    let doc = store.get(doc_id)?;
+    doc.check_page(page)?;
+    policy.check(&doc, Action::Annotate).await?;
+    let kind = annotation.kind;
+    let spec = annotation.into_spec();
+    let (document, id) = apply_edit_with(doc, &store, &tiles, &scheduler, move |objects| {
+        let page_id = objects.get_pages()[&(page as u32 + 1)];
+        write_annotation(objects, page_id, None, &spec)
+    })
+    .await?;
+    eprintln!(
+        "Added {} annotation {} to page {} of document {}",
+        kind.subtype(),
+        format_id(id),
+        page,
+        doc_id
+    );
+    Ok(AddedAnnotation {
+        document,
+        id: format_id(id),
+    })
+}
+
+/// Changes where an annotation is, its color, opacity, line width or comment.
+#[tauri::command]
+pub async fn update_annotation(
+    doc_id: DocId,
+    id: String,
+    changes: AnnotationChanges,
+    store: State<'_, DocumentStore>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<EditedDocument, String> {
+    let object_id = parse_id(&id)?;
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Annotate).await?;
+    let edited = apply_edit(doc, &store, &tiles, &scheduler, move |objects| {
+        update(objects, object_id, changes)
+    })
+    .await?;
+    eprintln!("Changed annotation {} of document {}", id, doc_id);
+    Ok(edited)
+}
+
+/// Deletes an annotation, along with the pop-up it opens in.
+#[tauri::command]
+pub async fn delete_annotation(
+    doc_id: DocId,
+    id: String,
+    store: State<'_, DocumentStore>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<EditedDocument, String> {
+    let object_id = parse_id(&id)?;
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Annotate).await?;
+    let edited = apply_edit(doc, &store, &tiles, &scheduler, move |objects| {
+        delete(objects, object_id)
+    })
+    .await?;
+    eprintln!("Deleted annotation {} of document {}", id, doc_id);
+    Ok(edited)
+}
+
+/// Saves a summary of the comments of a document at `path`, in Markdown.
+/// Returns the number of comments in it.
+#[tauri::command]
+pub async fn export_annotation_summary(
+    app: AppHandle,
+    doc_id: DocId,
+    path: String,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<usize, String> {
+    scope::check_write(&app, Path::new(&path))?;
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Copy).await?;
+    let items = {
+        let doc = Arc::clone(&doc);
+        blocking(move || doc.with_objects(|objects| Ok(summary_items(objects)))).await?
+    };
+    if items.is_empty() {
+        return Err(format!("{} has no comments", doc.path.display()));
+    }
+    let title = doc.path.file_stem().map_or("Document".into(), |stem| {
+        stem.to_string_lossy().into_owned()
+    });
+
+    let pages = engine
+        .run(move |worker| quote_pages(worker.document(&doc)?, items))
+        .await?;
+    let count = pages.iter().map(|(_, items)| items.len()).sum();
+    blocking(move || {
+        std::fs::write(Path::new(&path), to_summary(&title, &pages))
+            .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        eprintln!(
+            "Exported {} comments of document {} to {}",
+            count, doc_id, path
+        );
+        Ok(count)
+    })
+    .await
+}
+
+/// Merges the annotations of another copy of the document, at `path`. Those
+/// only it has are added; those that differ from or overlap this copy's are
+/// reported instead of overwriting them.
+#[tauri::command]
+pub async fn merge_annotations(
+    app: AppHandle,
+    doc_id: DocId,
+    path: String,
+    store: State<'_, DocumentStore>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<MergedAnnotations, String> {
+    scope::check(&app, Path::new(&path))?;
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Annotate).await?;
+    let (document, (added, unchanged, conflicts)) = {
+        let path = path.clone();
+        apply_edit_with(doc, &store, &tiles, &scheduler, move |objects| {
+            merge(objects, &load_document(Path::new(&path))?)
+        })
+        .await?
+    };
+    eprintln!(
+        "Merged annotations from {} into document {}: {} added, {} alike, {} conflicts",
+        path,
+        doc_id,
+        added,
+        unchanged,
+        conflicts.len()
+    );
+    Ok(MergedAnnotations {
+        document,
+        added,
+        unchanged,
+        conflicts,
+    })
+}
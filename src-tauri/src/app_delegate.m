@@ -1,53 +1,187 @@
 #import <Cocoa/Cocoa.h>
 #import <Foundation/Foundation.h>
 
-// Global function pointer to Rust callback
-extern void (*file_opened_callback)(const char*);
+// Apple Event handlers for files opened from the Finder or the Dock.
+//
+// Tauri's event loop owns the application delegate, so rather than replacing
+// it the handlers are registered when the application is about to finish
+// launching. That's where AppKit expects them, and it's before the files the
+// app was launched with are delivered.
+//
+// Opened documents are also noted with the shared NSDocumentController, which
+// lists them in the Dock menu; choosing one there sends an open documents
+// event. The list is the app's own recent files, which replace it whenever
+// they change. The app has no NSDocument classes, so the File ▸ Open Recent
+// menu is filled here from the same list, and its items open files the same
+// way; clearing it clears the app's list too.
 
-// AppDelegate class to handle Apple Events
-@interface AppDelegate : NSObject <NSApplicationDelegate>
+typedef void (*OpenedCallback)(const char *);
+typedef void (*ClearedCallback)(void);
+
+// Rust callbacks, called on the main thread with each file path or link
+static OpenedCallback file_opened_callback = NULL;
+static OpenedCallback link_opened_callback = NULL;
+// And when the user clears Open Recent
+static ClearedCallback recent_cleared_callback = NULL;
+
+@interface OpenEventHandler : NSObject <NSMenuDelegate>
 @end
 
-@implementation AppDelegate
+@implementation OpenEventHandler
 
-- (void)applicationDidFinishLaunching:(NSNotification *)notification {
-    // Register for Apple Events
-    [[NSAppleEventManager sharedAppleEventManager] setEventHandler:self
-                                                       andSelector:@selector(handleOpenFiles:)
-                                                     forEventClass:kCoreEventClass
-                                                        andEventID:kAEOpenDocuments];
+- (void)openURL:(NSURL *)url {
+    if (url.isFileURL && url.path && file_opened_callback) {
+        file_opened_callback(url.path.fileSystemRepresentation);
+    } else if (!url.isFileURL && url.absoluteString && link_opened_callback) {
+        link_opened_callback(url.absoluteString.UTF8String);
+    }
+}
+
+// Files double-clicked, dropped on the Dock icon or chosen with "Open With"
+- (void)handleOpenDocuments:(NSAppleEventDescriptor *)event
+             withReplyEvent:(NSAppleEventDescriptor *)reply {
+    NSAppleEventDescriptor *files = [event paramDescriptorForKeyword:keyDirectObject];
+    for (NSInteger i = 1; i <= [files numberOfItems]; i++) {
+        NSAppleEventDescriptor *file =
+            [[files descriptorAtIndex:i] coerceToDescriptorType:typeFileURL];
+        NSString *string = [file stringValue];
+        if (string) {
+            [self openURL:[NSURL URLWithString:string]];
+        }
+    }
 }
 
-// Handle file opening events from double-click or file association
-- (void)handleOpenFiles:(NSAppleEventDescriptor *)event {
-    NSAppleEventDescriptor *filesDescriptor = [event paramDescriptorForKeyword:keyDirectObject];
+// An item of the Open Recent menu
+- (void)openRecent:(NSMenuItem *)item {
+    [self openURL:item.representedObject];
+}
 
-    if (filesDescriptor) {
-        // Get all file URLs
-        for (NSInteger i = 1; i <= [filesDescriptor numberOfItems]; i++) {
-            NSAppleEventDescriptor *fileDescriptor = [filesDescriptor descriptorAtIndex:i];
-            NSString *filePath = [[fileDescriptor stringValue] stringByRemovingPercentEncoding];
+- (void)clearRecent:(NSMenuItem *)item {
+    [[NSDocumentController sharedDocumentController] clearRecentDocuments:item];
+    if (recent_cleared_callback) {
+        recent_cleared_callback();
+    }
+}
 
-            if (filePath) {
-                // Call the Rust callback with the file path
-                if (file_opened_callback) {
-                    file_opened_callback([filePath UTF8String]);
-                }
-            }
-        }
+// Lists the recent documents each time the Open Recent menu opens
+- (void)menuNeedsUpdate:(NSMenu *)menu {
+    NSDocumentController *documents = [NSDocumentController sharedDocumentController];
+    [menu removeAllItems];
+    for (NSURL *url in documents.recentDocumentURLs) {
+        NSString *title = [[NSFileManager defaultManager] displayNameAtPath:url.path];
+        NSMenuItem *item = [[NSMenuItem alloc] initWithTitle:title
+                                                      action:@selector(openRecent:)
+                                               keyEquivalent:@""];
+        item.target = self;
+        item.representedObject = url;
+        NSImage *icon = [[NSWorkspace sharedWorkspace] iconForFile:url.path];
+        icon.size = NSMakeSize(16, 16);
+        item.image = icon;
+        [menu addItem:item];
+    }
+    if (menu.numberOfItems > 0) {
+        [menu addItem:[NSMenuItem separatorItem]];
+    }
+    NSMenuItem *clear = [[NSMenuItem alloc] initWithTitle:@"Clear Menu"
+                                                   action:@selector(clearRecent:)
+                                            keyEquivalent:@""];
+    clear.target = self;
+    [menu addItem:clear];
+}
+
+// file:// URLs and nanodoc:// links opened with the app
+- (void)handleGetURL:(NSAppleEventDescriptor *)event
+      withReplyEvent:(NSAppleEventDescriptor *)reply {
+    NSString *string = [[event paramDescriptorForKeyword:keyDirectObject] stringValue];
+    if (string) {
+        [self openURL:[NSURL URLWithString:string]];
     }
 }
 
 @end
 
-// Global reference to keep the delegate alive
-static AppDelegate *globalDelegate = nil;
+// Kept alive for the life of the app
+static OpenEventHandler *handler = nil;
+static id observer = nil;
 
-// Function to set up the app delegate
-void setup_app_delegate(void (*callback)(const char*)) {
-    file_opened_callback = callback;
+// Registers the handlers once the application is about to finish launching.
+// Must be called before the event loop starts.
+void register_open_handlers(OpenedCallback file_callback, OpenedCallback link_callback) {
+    file_opened_callback = file_callback;
+    link_opened_callback = link_callback;
+    handler = [[OpenEventHandler alloc] init];
+    observer = [[NSNotificationCenter defaultCenter]
+        addObserverForName:NSApplicationWillFinishLaunchingNotification
+                    object:nil
+                     queue:nil
+                usingBlock:^(NSNotification *notification) {
+                    NSAppleEventManager *manager = [NSAppleEventManager sharedAppleEventManager];
+                    [manager setEventHandler:handler
+                                 andSelector:@selector(handleOpenDocuments:withReplyEvent:)
+                               forEventClass:kCoreEventClass
+                                  andEventID:kAEOpenDocuments];
+                    [manager setEventHandler:handler
+                                 andSelector:@selector(handleGetURL:withReplyEvent:)
+                               forEventClass:kInternetEventClass
+                                  andEventID:kAEGetURL];
+                }];
+}
 
-    // Create and set the app delegate
-    globalDelegate = [[AppDelegate alloc] init];
-    [NSApp setDelegate:globalDelegate];
+// Adds a document to the recent documents. Safe to call from any thread.
+void note_recent_document(const char *path) {
+    NSURL *url = [NSURL fileURLWithPath:[NSString stringWithUTF8String:path]];
+    if (!url) {
+        return;
+    }
+    dispatch_async(dispatch_get_main_queue(), ^{
+        [[NSDocumentController sharedDocumentController] noteNewRecentDocumentURL:url];
+    });
+}
+
+// Replaces the recent documents with `paths`, the most recent first. Safe to
+// call from any thread.
+void set_recent_documents(const char *const *paths, size_t count) {
+    NSMutableArray<NSURL *> *urls = [NSMutableArray arrayWithCapacity:count];
+    for (size_t i = 0; i < count; i++) {
+        NSURL *url = [NSURL fileURLWithPath:[NSString stringWithUTF8String:paths[i]]];
+        if (url) {
+            [urls addObject:url];
+        }
+    }
+    dispatch_async(dispatch_get_main_queue(), ^{
+        NSDocumentController *documents = [NSDocumentController sharedDocumentController];
+        [documents clearRecentDocuments:nil];
+        // Each one noted goes to the top
+        for (NSURL *url in urls.reverseObjectEnumerator) {
+            [documents noteNewRecentDocumentURL:url];
+        }
+    });
+}
+
+// Adds File ▸ Open Recent to the menu bar, and a File menu if there's none.
+// `cleared` is called when the user clears it. Must be called on the main
+// thread once the menu bar is in place.
+void install_open_recent_menu(ClearedCallback cleared) {
+    recent_cleared_callback = cleared;
+    NSMenu *menuBar = [NSApp mainMenu];
+    if (!menuBar || !handler) {
+        return;
+    }
+    NSMenuItem *file = [menuBar itemWithTitle:@"File"];
+    if (!file) {
+        file = [[NSMenuItem alloc] initWithTitle:@"File" action:nil keyEquivalent:@""];
+        file.submenu = [[NSMenu alloc] initWithTitle:@"File"];
+        [menuBar insertItem:file atIndex:MIN(1, menuBar.numberOfItems)];
+    }
+    if ([file.submenu itemWithTitle:@"Open Recent"]) {
+        return;
+    }
+    NSMenu *recent = [[NSMenu alloc] initWithTitle:@"Open Recent"];
+    recent.delegate = handler;
+    NSMenuItem *item = [[NSMenuItem alloc] initWithTitle:@"Open Recent" action:nil keyEquivalent:@""];
+    item.submenu = recent;
+    // Below "Open…" when the menu bar has it (see `menu_bar.rs`), at the top
+    // otherwise
+    NSInteger open = [file.submenu indexOfItemWithTitle:@"Open…"];
+    [file.submenu insertItem:item atIndex:open + 1];
 }
@@ -0,0 +1,390 @@
+// Embedded files, from the /EmbeddedFiles name tree and file attachment
+// annotations. New attachments go into the name tree.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use lopdf::{dictionary, Dictionary, Document, Object, Stream};
+use serde::Serialize;
+use tauri::{AppHandle, State};
+use tauri_plugin_dialog::{DialogExt, MessageDialogButtons, MessageDialogKind};
+
+use crate::edit::{apply_edit_with, EditedDocument};
+use crate::metadata::{format_pdf_date, parse_pdf_date};
+use crate::objects;
+use crate::outline::walk_name_tree;
+use crate::pdf::{blocking, DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scheduler::RenderScheduler;
+use crate::scope;
+use crate::tiles::TileCache;
+
+// Extensions that get a stronger warning before saving
+const EXECUTABLE_EXTENSIONS: &[&str] = &[
+    "app", "bat", "cmd", "com", "dll", "dmg", "exe", "jar", "js", "msi", "pkg", "ps1", "scr", "sh",
+    "vbs",
+];
+
+// MIME types of common attachments, by extension
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("csv", "text/csv"),
+    ("doc", "application/msword"),
+    (
+        "docx",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    ),
+    ("gif", "image/gif"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("json", "application/json"),
+    ("md", "text/markdown"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    (
+        "pptx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    ),
+    ("svg", "image/svg+xml"),
+    ("txt", "text/plain"),
+    ("xls", "application/vnd.ms-excel"),
+    (
+        "xlsx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    ("xml", "application/xml"),
+    ("zip", "application/zip"),
+];
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attachment {
+    /// Index in the list, used to refer to the attachment when saving
+    pub id: usize,
+    pub name: String,
+    pub description: Option<String>,
+    pub size: Option<u64>,
+    pub mime_type: Option<String>,
+    /// ISO 8601
+    pub modified: Option<String>,
+    /// Set for attachments pinned to a page with an annotation
+    pub page: Option<i32>,
+}
+
+fn read_file_spec<'a>(
+    doc: &'a Document,
+    spec: &'a Dictionary,
+    page: Option<i32>,
+    id: usize,
+) -> (Attachment, Option<&'a Stream>) {
+    let stream = objects::get_dict(doc, spec, b"EF")
+        .and_then(|ef| objects::get(doc, ef, b"UF").or_else(|| objects::get(doc, ef, b"F")))
+        .and_then(|obj| obj.as_stream().ok());
+    let params = stream.and_then(|s| objects::get_dict(doc, &s.dict, b"Params"));
+
+    let attachment = Attachment {
+        id,
+        name: objects::get_text(doc, spec, b"UF")
+            .or_else(|| objects::get_text(doc, spec, b"F"))
+            .unwrap_or_else(|| format!("attachment-{}", id + 1)),
+        description: objects::get_text(doc, spec, b"Desc"),
+        size: params
+            .and_then(|p| objects::get(doc, p, b"Size"))
+            .and_then(|size| size.as_i64().ok())
+            .map(|size| size as u64),
+        mime_type: stream
+            .and_then(|s| objects::get_name(doc, &s.dict, b"Subtype"))
+            .map(objects::decode_text),
+        modified: params
+            .and_then(|p| objects::get_text(doc, p, b"ModDate"))
+            .and_then(|date| parse_pdf_date(&date)),
+        page,
+    };
+    (attachment, stream)
+}
+
+/// Lists the embedded files of a document with their data streams.
+pub fn attachments(doc: &Document) -> Vec<(Attachment, Option<&Stream>)> {
+    let mut found = Vec::new();
+    let tree = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| objects::get_dict(doc, catalog, b"Names"))
+        .and_then(|names| objects::get_dict(doc, names, b"EmbeddedFiles"));
+    if let Some(tree) = tree {
+        walk_name_tree(doc, tree, 0, &mut |_, value| {
+            if let Ok(spec) = value.as_dict() {
+                found.push(read_file_spec(doc, spec, None, found.len()));
+            }
+        });
+    }
+
+    for (number, page_id) in doc.get_pages() {
+        let Some(annots) = doc
+            .get_dictionary(page_id)
+            .ok()
+            .and_then(|page| objects::get_array(doc, page, b"Annots"))
+        else {
+            continue;
+        };
+        for annot in annots {
+            let Ok(annot) = objects::resolve(doc, annot).as_dict() else {
+                continue;
+            };
+            if objects::get_name(doc, annot, b"Subtype") != Some(b"FileAttachment") {
+                continue;
+            }
+            if let Some(spec) = objects::get_dict(doc, annot, b"FS") {
+                let page = Some(number as i32 - 1);
+                found.push(read_file_spec(doc, spec, page, found.len()));
+            }
+        }
+    }
+    found
+}
+
+#[tauri::command]
+pub async fn list_attachments(
+    doc_id: DocId,
+    store: State<'_, DocumentStore>,
+) -> Result<Vec<Attachment>, String> {
+    let doc = store.get(doc_id)?;
+    blocking(move || {
+        doc.with_objects(|objects| {
+            Ok(attachments(objects)
+                .into_iter()
+                .map(|(attachment, _)| attachment)
+                .collect())
+        })
+    })
+    .await
+}
+
+// A name not used by any attachment yet, numbering copies like "notes (2).txt"
+fn unique_name(name: &str, taken: &HashSet<String>) -> String {
+    if !taken.contains(name) {
+        return name.to_string();
+    }
+    let path = Path::new(name);
+    let stem = path
+        .file_stem()
+        .map_or(name.into(), |s| s.to_string_lossy());
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (2..)
+        .map(|n| format!("{} ({}){}", stem, n, extension))
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap_or_default()
+}
+
+// Adds files to the /EmbeddedFiles name tree, returning the names they got
+fn attach(
+    doc: &mut Document,
+    paths: &[String],
+    description: Option<&str>,
+) -> Result<Vec<String>, String> {
+    let mut taken: HashSet<String> = attachments(doc)
+        .into_iter()
+        .map(|(attachment, _)| attachment.name)
+        .collect();
+    // Name tree leaves must be sorted by their keys
+    let mut entries: BTreeMap<Vec<u8>, Object> = BTreeMap::new();
+    let catalog = doc.catalog().map_err(|e| e.to_string())?;
+    let mut names = objects::get_dict(doc, catalog, b"Names")
+        .cloned()
+        .unwrap_or_default();
+    if let Some(tree) = objects::get_dict(doc, &names, b"EmbeddedFiles") {
+        walk_name_tree(doc, tree, 0, &mut |name, value| {
+            entries.insert(name.to_vec(), value.clone());
+        });
+    }
+
+    let mut added = Vec::new();
+    for path in paths {
+        let path = Path::new(path);
+        let data =
+            std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .ok_or_else(|| format!("{} is not a file", path.display()))?;
+        let name = unique_name(&file_name, &taken);
+        taken.insert(name.clone());
+
+        let mut params = dictionary! { "Size" => data.len() as i64 };
+        if let Ok(modified) = std::fs::metadata(path).and_then(|m| m.modified()) {
+            params.set("ModDate", Object::string_literal(format_pdf_date(modified)));
+        }
+        let mut file = dictionary! {
+            "Type" => "EmbeddedFile",
+            "Params" => params,
+        };
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        if let Some((_, mime_type)) = MIME_TYPES
+            .iter()
+            .find(|(ext, _)| extension.as_deref() == Some(*ext))
+        {
+            file.set("Subtype", Object::Name(mime_type.as_bytes().to_vec()));
+        }
+        let mut stream = Stream::new(file, data);
+        let _ = stream.compress();
+        let stream = doc.add_object(stream);
+
+        let mut spec = dictionary! {
+            "Type" => "Filespec",
+            "F" => objects::encode_text(&name),
+            "UF" => objects::encode_text(&name),
+            "EF" => dictionary! { "F" => stream, "UF" => stream },
+        };
+        if let Some(description) = description.filter(|d| !d.trim().is_empty()) {
+            spec.set("Desc", objects::encode_text(description));
+        }
+        let key = match objects::encode_text(&name) {
+            Object::String(bytes, _) => bytes,
+            _ => name.clone().into_bytes(),
+        };
+        entries.insert(key, doc.add_object(spec).into());
+        added.push(name);
+    }
+
+    let leaves: Vec<Object> = entries
+        .into_iter()
+        .flat_map(|(name, value)| [Object::String(name, lopdf::StringFormat::Literal), value])
+        .collect();
+    names.set("EmbeddedFiles", dictionary! { "Names" => leaves });
+    doc.catalog_mut()
+        .map_err(|e| e.to_string())?
+        .set("Names", names);
+    Ok(added)
+}
+
+/// Embeds the files at `paths` in the document as attachments, under their
+/// file names. Like other edits, they are written when the document is saved.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn add_attachments(
+    app: AppHandle,
+    doc_id: DocId,
+    paths: Vec<String>,
+    description: Option<String>,
+    store: State<'_, DocumentStore>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<EditedDocument, String> {
+    if paths.is_empty() {
+        return Err("Select at least one file to attach".to_string());
+    }
+    for path in &paths {
+        scope::check(&app, Path::new(path))?;
+    }
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Modify).await?;
+    let (edited, added) = apply_edit_with(doc, &store, &tiles, &scheduler, move |objects| {
+        attach(objects, &paths, description.as_deref())
+    })
+    .await?;
+    eprintln!("Attached {} to document {}", added.join(", "), doc_id);
+    Ok(edited)
+}
+
+/// Asks for a target file and saves an attachment there, after warning the
+/// user that attachments may be unsafe. Returns the saved path, or `None` if
+/// the user cancelled.
+#[tauri::command]
+pub async fn save_attachment(
+    app: AppHandle,
+    doc_id: DocId,
+    id: usize,
+    store: State<'_, DocumentStore>,
+) -> Result<Option<String>, String> {
+    let doc = store.get(doc_id)?;
+    let (name, data) = {
+        let doc = doc.clone();
+        blocking(move || {
+            doc.with_objects(|objects| {
+                let found = attachments(objects);
+                let (attachment, stream) = found
+                    .into_iter()
+                    .find(|(attachment, _)| attachment.id == id)
+                    .ok_or_else(|| format!("No attachment with id {}", id))?;
+                let stream = stream.ok_or_else(|| {
+                    format!("Attachment {} has no embedded data", attachment.name)
+                })?;
+                let data = stream
+                    .decompressed_content()
+                    .unwrap_or_else(|_| stream.content.clone());
+                Ok((attachment.name, data))
+            })
+        })
+        .await?
+    };
+
+    // Never trust the name from the file to be a plain file name
+    let file_name = Path::new(&name)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| format!("attachment-{}", id + 1));
+    let executable = Path::new(&file_name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| EXECUTABLE_EXTENSIONS.contains(&ext.as_str()));
+    let directory = doc.path.parent().map(Path::to_path_buf);
+
+    // Dialogs block until closed, so keep them off the async runtime
+    blocking(move || {
+        let (kind, message) = if executable {
+            (
+                MessageDialogKind::Warning,
+                format!(
+                    "\"{}\" is a program or script. Running it could harm your computer. \
+                     Only save it if you trust the sender of this document.",
+                    file_name
+                ),
+            )
+        } else {
+            (
+                MessageDialogKind::Info,
+                format!(
+                    "Attachments can contain harmful content. Only open \"{}\" if you \
+                     trust the sender of this document.",
+                    file_name
+                ),
+            )
+        };
+        let confirmed = app
+            .dialog()
+            .message(message)
+            .title("Save Attachment")
+            .kind(kind)
+            .buttons(MessageDialogButtons::OkCancelCustom(
+                "Save...".to_string(),
+                "Cancel".to_string(),
+            ))
+            .blocking_show();
+        if !confirmed {
+            return Ok(None);
+        }
+
+        let mut picker = app.dialog().file().set_file_name(&file_name);
+        if let Some(directory) = directory {
+            picker = picker.set_directory(directory);
+        }
+        let Some(target) = picker.blocking_save_file() else {
+            return Ok(None);
+        };
+        let target = target.into_path().map_err(|e| e.to_string())?;
+        std::fs::write(&target, &data)
+            .map_err(|e| format!("Failed to write {}: {}", target.display(), e))?;
+        eprintln!("Saved attachment {} to {}", file_name, target.display());
+        Ok(Some(target.to_string_lossy().into_owned()))
+    })
+    .await
+}
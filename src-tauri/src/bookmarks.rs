@@ -0,0 +1,554 @@
+// The user's own bookmarks, apart from the outline the PDF may have.
+//
+// A bookmark names a place in a document: a page, the position on it as
+// fractions of the page's width and height (as in `reading.rs`) and a color
+// to tell bookmarks apart. They live in a SQLite database at
+// `<app data>/bookmarks.sqlite3` keyed by the fingerprint of the file, so the
+// file itself is never changed and a moved copy keeps its bookmarks. They can
+// be exported to a JSON file and imported into another copy of the document,
+// or another edition of it.
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::pdf::{blocking, DocId, DocumentStore};
+use crate::scope;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS bookmarks (
+        id INTEGER PRIMARY KEY,
+        fingerprint TEXT NOT NULL,
+        name TEXT NOT NULL,
+        page INTEGER NOT NULL,
+        scroll_x REAL NOT NULL,
+        scroll_y REAL NOT NULL,
+        color INTEGER NOT NULL,
+        created INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS bookmarks_fingerprint ON bookmarks (fingerprint);
+";
+const COLUMNS: &str = "id, name, page, scroll_x, scroll_y, color, created";
+const DEFAULT_COLOR: [f32; 3] = [1.0, 0.8, 0.0];
+// For files written by later versions to be turned down
+const EXPORT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Bookmark {
+    pub id: i64,
+    pub name: String,
+    /// Counted from 0
+    pub page: i32,
+    /// How far across the page the place is, from 0 to 1
+    pub scroll_x: f64,
+    /// How far down the page the place is, from 0 to 1
+    pub scroll_y: f64,
+    pub color: [f32; 3],
+    /// Milliseconds since the Unix epoch
+    pub created: i64,
+}
+
+/// A bookmark to add, or the new values of one.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BookmarkSpec {
+    pub name: String,
+    pub page: i32,
+    #[serde(default)]
+    pub scroll_x: f64,
+    #[serde(default)]
+    pub scroll_y: f64,
+    pub color: Option<[f32; 3]>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedBookmark {
+    name: String,
+    page: i32,
+    scroll_x: f64,
+    scroll_y: f64,
+    color: [f32; 3],
+}
+
+// The file bookmarks are exported to
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BookmarksFile {
+    version: u32,
+    /// The name of the document they were exported from
+    document: String,
+    bookmarks: Vec<ExportedBookmark>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportedBookmarks {
+    pub bookmarks: Vec<Bookmark>,
+    /// Ones the document has already, or for pages it doesn't have
+    pub skipped: usize,
+}
+
+fn database_file(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("bookmarks.sqlite3"))
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))
+}
+
+fn open(file: &Path) -> Result<Connection, String> {
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let db =
+        Connection::open(file).map_err(|e| format!("Failed to open {}: {}", file.display(), e))?;
+    db.execute_batch(SCHEMA)
+        .map_err(|e| format!("Failed to set up {}: {}", file.display(), e))?;
+    Ok(db)
+}
+
+// Colors are stored as 0xRRGGBB
+fn pack(color: [f32; 3]) -> i64 {
+    color.iter().fold(0, |packed, c| {
+        packed << 8 | (c.clamp(0.0, 1.0) * 255.0).round() as i64
+    })
+}
+
+fn unpack(packed: i64) -> [f32; 3] {
+    [16, 8, 0].map(|shift| ((packed >> shift) & 0xff) as f32 / 255.0)
+}
+
+fn fraction(value: f64) -> f64 {
+    match value.is_finite() {
+        true => value.clamp(0.0, 1.0),
+        false => 0.0,
+    }
+}
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<Bookmark> {
+    Ok(Bookmark {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        page: row.get(2)?,
+        scroll_x: row.get(3)?,
+        scroll_y: row.get(4)?,
+        color: unpack(row.get(5)?),
+        created: row.get(6)?,
+    })
+}
+
+fn list(db: &Connection, fingerprint: &str) -> Result<Vec<Bookmark>, String> {
+    let mut statement = db
+        .prepare(&format!(
+            "SELECT {} FROM bookmarks WHERE fingerprint = ?1
+             ORDER BY page, scroll_y, scroll_x, id",
+            COLUMNS
+        ))
+        .map_err(|e| e.to_string())?;
+    let rows = statement
+        .query_map(params![fingerprint], from_row)
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+}
+
+fn get(db: &Connection, fingerprint: &str, id: i64) -> Result<Bookmark, String> {
+    db.query_row(
+        &format!(
+            "SELECT {} FROM bookmarks WHERE fingerprint = ?1 AND id = ?2",
+            COLUMNS
+        ),
+        params![fingerprint, id],
+        from_row,
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("No bookmark {}", id))
+}
+
+fn insert(db: &Connection, fingerprint: &str, bookmark: &ExportedBookmark) -> Result<i64, String> {
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis() as i64);
+    db.execute(
+        "INSERT INTO bookmarks
+             (fingerprint, name, page, scroll_x, scroll_y, color, created)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            fingerprint,
+            bookmark.name,
+            bookmark.page,
+            bookmark.scroll_x,
+            bookmark.scroll_y,
+            pack(bookmark.color),
+            created
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(db.last_insert_rowid())
+}
+
+// A bookmark as it's stored, checked against the document's pages
+fn checked(spec: BookmarkSpec, page_count: i32) -> Result<ExportedBookmark, String> {
+    let name = spec.name.trim();
+    if name.is_empty() {
+        return Err("A bookmark needs a name".to_string());
+    }
+    if spec.page < 0 || spec.page >= page_count {
+        return Err(format!("Page {} is out of range", spec.page));
+    }
+    Ok(ExportedBookmark {
+        name: name.to_string(),
+        page: spec.page,
+        scroll_x: fraction(spec.scroll_x),
+        scroll_y: fraction(spec.scroll_y),
+        color: spec.color.unwrap_or(DEFAULT_COLOR),
+    })
+}
+
+/// A bookmark with the document it's in, as settings archives keep it (see
+/// `transfer.rs`).
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedBookmark {
+    pub fingerprint: String,
+    pub name: String,
+    pub page: i32,
+    pub scroll_x: f64,
+    pub scroll_y: f64,
+    pub color: [f32; 3],
+    pub created: i64,
+}
+
+/// Every bookmark of every document.
+pub fn to_archive(app: &AppHandle) -> Result<Vec<ArchivedBookmark>, String> {
+    let db = open(&database_file(app)?)?;
+    let mut statement = db
+        .prepare(
+            "SELECT fingerprint, name, page, scroll_x, scroll_y, color, created FROM bookmarks
+             ORDER BY fingerprint, page, id",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = statement
+        .query_map(params![], |row| {
+            Ok(ArchivedBookmark {
+                fingerprint: row.get(0)?,
+                name: row.get(1)?,
+                page: row.get(2)?,
+                scroll_x: row.get(3)?,
+                scroll_y: row.get(4)?,
+                color: unpack(row.get(5)?),
+                created: row.get(6)?,
+            })
+        })
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+}
+
+/// Adds archived bookmarks, leaving out those there are already. Returns the
+/// number added.
+pub fn from_archive(app: &AppHandle, bookmarks: Vec<ArchivedBookmark>) -> Result<usize, String> {
+    let mut db = open(&database_file(app)?)?;
+    let transaction = db.transaction().map_err(|e| e.to_string())?;
+    let mut added = 0;
+    for bookmark in bookmarks {
+        let name = bookmark.name.trim();
+        if name.is_empty() || bookmark.page < 0 {
+            continue;
+        }
+        added += transaction
+            .execute(
+                "INSERT INTO bookmarks
+                     (fingerprint, name, page, scroll_x, scroll_y, color, created)
+                 SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7 WHERE NOT EXISTS
+                     (SELECT 1 FROM bookmarks WHERE fingerprint = ?1 AND name = ?2
+                      AND page = ?3 AND abs(scroll_x - ?4) < 1e-6 AND abs(scroll_y - ?5) < 1e-6)",
+                params![
+                    bookmark.fingerprint,
+                    name,
+                    bookmark.page,
+                    fraction(bookmark.scroll_x),
+                    fraction(bookmark.scroll_y),
+                    pack(bookmark.color),
+                    bookmark.created
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+    }
+    transaction.commit().map_err(|e| e.to_string())?;
+    Ok(added)
+}
+
+/// The bookmarks of a document, in the order of the places they mark.
+#[tauri::command]
+pub async fn get_bookmarks(
+    app: AppHandle,
+    doc_id: DocId,
+    store: State<'_, DocumentStore>,
+) -> Result<Vec<Bookmark>, String> {
+    let doc = store.get(doc_id)?;
+    let file = database_file(&app)?;
+    blocking(move || list(&open(&file)?, doc.fingerprint())).await
+}
+
+#[tauri::command]
+pub async fn add_bookmark(
+    app: AppHandle,
+    doc_id: DocId,
+    bookmark: BookmarkSpec,
+    store: State<'_, DocumentStore>,
+) -> Result<Bookmark, String> {
+    let doc = store.get(doc_id)?;
+    let bookmark = checked(bookmark, doc.page_count)?;
+    let file = database_file(&app)?;
+    blocking(move || {
+        let db = open(&file)?;
+        let id = insert(&db, doc.fingerprint(), &bookmark)?;
+        get(&db, doc.fingerprint(), id)
+    })
+    .await
+}
+
+/// Renames, moves or recolors a bookmark.
+#[tauri::command]
+pub async fn update_bookmark(
+    app: AppHandle,
+    doc_id: DocId,
+    id: i64,
+    bookmark: BookmarkSpec,
+    store: State<'_, DocumentStore>,
+) -> Result<Bookmark, String> {
+    let doc = store.get(doc_id)?;
+    let bookmark = checked(bookmark, doc.page_count)?;
+    let file = database_file(&app)?;
+    blocking(move || {
+        let db = open(&file)?;
+        let changed = db
+            .execute(
+                "UPDATE bookmarks SET name = ?3, page = ?4, scroll_x = ?5, scroll_y = ?6,
+                     color = ?7
+                 WHERE fingerprint = ?1 AND id = ?2",
+                params![
+                    doc.fingerprint(),
+                    id,
+                    bookmark.name,
+                    bookmark.page,
+                    bookmark.scroll_x,
+                    bookmark.scroll_y,
+                    pack(bookmark.color)
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+        if changed == 0 {
+            return Err(format!("No bookmark {}", id));
+        }
+        get(&db, doc.fingerprint(), id)
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn delete_bookmark(
+    app: AppHandle,
+    doc_id: DocId,
+    id: i64,
+    store: State<'_, DocumentStore>,
+) -> Result<(), String> {
+    let doc = store.get(doc_id)?;
+    let file = database_file(&app)?;
+    blocking(move || {
+        let db = open(&file)?;
+        db.execute(
+            "DELETE FROM bookmarks WHERE fingerprint = ?1 AND id = ?2",
+            params![doc.fingerprint(), id],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    })
+    .await
+}
+
+/// Saves the bookmarks of a document at `path`, as JSON. Returns the number
+/// saved.
+#[tauri::command]
+pub async fn export_bookmarks(
+    app: AppHandle,
+    doc_id: DocId,
+    path: String,
+    store: State<'_, DocumentStore>,
+) -> Result<usize, String> {
+    scope::check_write(&app, Path::new(&path))?;
+    let doc = store.get(doc_id)?;
+    let file = database_file(&app)?;
+    blocking(move || {
+        let bookmarks = list(&open(&file)?, doc.fingerprint())?;
+        if bookmarks.is_empty() {
+            return Err(format!("{} has no bookmarks to export", doc.path.display()));
+        }
+        let exported = BookmarksFile {
+            version: EXPORT_VERSION,
+            document: doc
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            bookmarks: bookmarks
+                .into_iter()
+                .map(|bookmark| ExportedBookmark {
+                    name: bookmark.name,
+                    page: bookmark.page,
+                    scroll_x: bookmark.scroll_x,
+                    scroll_y: bookmark.scroll_y,
+                    color: bookmark.color,
+                })
+                .collect(),
+        };
+        let json = serde_json::to_vec_pretty(&exported).map_err(|e| e.to_string())?;
+        std::fs::write(Path::new(&path), json)
+            .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        eprintln!(
+            "Exported {} bookmarks of document {} to {}",
+            exported.bookmarks.len(),
+            doc_id,
+            path
+        );
+        Ok(exported.bookmarks.len())
+    })
+    .await
+}
+
+/// Adds the bookmarks exported to `path` to a document. Ones it has already,
+/// and ones for pages past its end, are skipped.
+#[tauri::command]
+pub async fn import_bookmarks(
+    app: AppHandle,
+    doc_id: DocId,
+    path: String,
+    store: State<'_, DocumentStore>,
+) -> Result<ImportedBookmarks, String> {
+    scope::check(&app, Path::new(&path))?;
+    let doc = store.get(doc_id)?;
+    let file = database_file(&app)?;
+    blocking(move || {
+        let json = std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let imported: BookmarksFile = serde_json::from_slice(&json)
+            .map_err(|e| format!("{} isn't a bookmarks file: {}", path, e))?;
+        if imported.version > EXPORT_VERSION {
+            return Err(format!(
+                "{} was written by a newer version of Nanodoc",
+                path
+            ));
+        }
+        let fingerprint = doc.fingerprint();
+        let mut db = open(&file)?;
+        let existing = list(&db, fingerprint)?;
+        let same = |a: &ExportedBookmark, b: &Bookmark| {
+            a.name == b.name
+                && a.page == b.page
+                && (a.scroll_x - b.scroll_x).abs() < 1e-6
+                && (a.scroll_y - b.scroll_y).abs() < 1e-6
+        };
+        let total = imported.bookmarks.len();
+        let transaction = db.transaction().map_err(|e| e.to_string())?;
+        let mut ids = Vec::new();
+        for bookmark in imported.bookmarks {
+            let spec = BookmarkSpec {
+                name: bookmark.name,
+                page: bookmark.page,
+                scroll_x: bookmark.scroll_x,
+                scroll_y: bookmark.scroll_y,
+                color: Some(bookmark.color),
+            };
+            let Ok(bookmark) = checked(spec, doc.page_count) else {
+                continue;
+            };
+            if existing.iter().any(|other| same(&bookmark, other)) {
+                continue;
+            }
+            ids.push(insert(&transaction, fingerprint, &bookmark)?);
+        }
+        transaction.commit().map_err(|e| e.to_string())?;
+        let bookmarks = ids
+            .into_iter()
+            .map(|id| get(&db, fingerprint, id))
+            .collect::<Result<Vec<_>, _>>()?;
+        eprintln!(
+            "Imported {} bookmarks into document {} from {} ({} skipped)",
+            bookmarks.len(),
+            doc_id,
+            path,
+            total - bookmarks.len()
+        );
+        Ok(ImportedBookmarks {
+            skipped: total - bookmarks.len(),
+            bookmarks,
+        })
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use lopdf::Object;
+
+    use super::*;
+    use crate::edit::apply_edit;
+    use crate::fixtures;
+    use crate::pdf::OpenDocument;
+    use crate::scheduler::RenderScheduler;
+    use crate::tiles::TileCache;
+
+    #[test]
+    fn bookmarks_outlive_unsaved_edits() {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(SCHEMA).unwrap();
+        let store = DocumentStore::default();
+        let doc = store.insert(OpenDocument::new(
+            store.next_id(),
+            PathBuf::from("document.pdf"),
+            fixtures::pdf(2),
+            2,
+        ));
+        let bookmark = ExportedBookmark {
+            name: "Results".into(),
+            page: 1,
+            scroll_x: 0.0,
+            scroll_y: 0.5,
+            color: DEFAULT_COLOR,
+        };
+        insert(&db, doc.fingerprint(), &bookmark).unwrap();
+
+        let edited = tauri::async_runtime::block_on(apply_edit(
+            doc.clone(),
+            &store,
+            &TileCache::default(),
+            &RenderScheduler::default(),
+            |objects| {
+                let info = objects
+                    .trailer
+                    .get(b"Info")
+                    .and_then(Object::as_reference)
+                    .map_err(|e| e.to_string())?;
+                objects
+                    .get_dictionary_mut(info)
+                    .map_err(|e| e.to_string())?
+                    .set("Title", Object::string_literal("Results"));
+                Ok(())
+            },
+        ))
+        .unwrap();
+
+        let edited = store.get(edited.doc_id).unwrap();
+        assert!(edited.unsaved);
+        assert!(!Arc::ptr_eq(&doc, &edited));
+        let bookmarks = list(&db, edited.fingerprint()).unwrap();
+        assert_eq!(bookmarks.len(), 1);
+        assert_eq!(bookmarks[0].name, "Results");
+    }
+}
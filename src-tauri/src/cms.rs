@@ -0,0 +1,919 @@
+// Reading CMS signatures (RFC 5652), the format of PDF signatures.
+//
+// Only what checking a detached signature needs is parsed: the certificates
+// carried along, the signer's signed attributes and the signature itself. The
+// DER reader also accepts the indefinite lengths of BER, which some signing
+// software still writes. Certificate chains are left to webpki, which parses
+// the certificates again on its own terms.
+//
+// Signatures made here follow the PAdES baseline: detached, SHA-256, and with
+// the signer's certificate named in a signed attribute, but no signing time,
+// which PAdES takes from the signature dictionary instead. A timestamp token
+// (RFC 3161) from a timestamp authority can be added as an unsigned
+// attribute; it is itself signed data, over a hash of the signature value,
+// and proves that the signature existed at a time the signer can't fake.
+
+use std::borrow::Cow;
+use std::time::{Duration, UNIX_EPOCH};
+
+use ring::digest::{self, Algorithm};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{self, UnparsedPublicKey, VerificationAlgorithm};
+
+use crate::metadata::{format_pdf_date, parse_pdf_date};
+use crate::objects::MAX_DEPTH;
+
+const BOOLEAN: u8 = 0x01;
+pub const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OID: u8 = 0x06;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+pub const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+// Context-specific tags, constructed and primitive
+const EXPLICIT_0: u8 = 0xA0;
+const EXPLICIT_1: u8 = 0xA1;
+const EXPLICIT_3: u8 = 0xA3;
+const IMPLICIT_0: u8 = 0x80;
+// Octet strings split into chunks (BER)
+const CONSTRUCTED_OCTET_STRING: u8 = 0x24;
+
+// Object identifiers, as encoded
+const ID_DATA: &[u8] = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x07, 0x01];
+const ID_SIGNED_DATA: &[u8] = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x07, 0x02];
+const ID_CONTENT_TYPE: &[u8] = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x09, 0x03];
+const ID_MESSAGE_DIGEST: &[u8] = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x09, 0x04];
+const ID_SIGNING_TIME: &[u8] = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x09, 0x05];
+const ID_SIGNING_CERTIFICATE_V2: &[u8] = &[
+    0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x09, 0x10, 0x02, 0x2F,
+];
+const ID_TIMESTAMP_TOKEN: &[u8] = &[
+    0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x09, 0x10, 0x02, 0x0E,
+];
+const ID_TST_INFO: &[u8] = &[
+    0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x09, 0x10, 0x01, 0x04,
+];
+const ID_SHA1: &[u8] = &[0x2B, 0x0E, 0x03, 0x02, 0x1A];
+const ID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+const ID_SHA384: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02];
+const ID_SHA512: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03];
+const ID_RSA_ENCRYPTION: &[u8] = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x01];
+const ID_SHA256_WITH_RSA: &[u8] = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x0B];
+const ID_RSASSA_PSS: &[u8] = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x0A];
+const ID_EC_PUBLIC_KEY: &[u8] = &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01];
+const ID_ECDSA_WITH_SHA256: &[u8] = &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02];
+const ID_P256: &[u8] = &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07];
+const ID_P384: &[u8] = &[0x2B, 0x81, 0x04, 0x00, 0x22];
+const ID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+const ID_ORGANIZATION: &[u8] = &[0x55, 0x04, 0x0A];
+const ID_SUBJECT_KEY_ID: &[u8] = &[0x55, 0x1D, 0x0E];
+
+fn malformed() -> String {
+    "The signature data is malformed".to_string()
+}
+
+/// One DER element.
+#[derive(Clone, Copy)]
+pub struct Element<'a> {
+    pub tag: u8,
+    /// Contents, without tag and length
+    pub value: &'a [u8],
+    /// The whole element
+    pub raw: &'a [u8],
+}
+
+impl<'a> Element<'a> {
+    pub fn reader(&self) -> Reader<'a> {
+        Reader::new(self.value)
+    }
+}
+
+/// Reads consecutive DER elements.
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn read(&mut self) -> Result<Element<'a>, String> {
+        let (element, rest) = parse(self.data, 0)?;
+        self.data = rest;
+        Ok(element)
+    }
+
+    pub fn expect(&mut self, tag: u8) -> Result<Element<'a>, String> {
+        let element = self.read()?;
+        if element.tag != tag {
+            return Err(malformed());
+        }
+        Ok(element)
+    }
+
+    pub fn optional(&mut self, tag: u8) -> Result<Option<Element<'a>>, String> {
+        match self.data.first() {
+            Some(&next) if next == tag => self.read().map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+fn split(data: &[u8], len: usize) -> Result<(&[u8], &[u8]), String> {
+    if data.len() < len {
+        return Err(malformed());
+    }
+    Ok(data.split_at(len))
+}
+
+// Splits the element starting `data` from what follows it
+fn parse(data: &[u8], depth: usize) -> Result<(Element<'_>, &[u8]), String> {
+    if depth > MAX_DEPTH {
+        return Err(malformed());
+    }
+    let (&tag, rest) = data.split_first().ok_or_else(malformed)?;
+    // CMS never needs tag numbers above 30
+    if tag & 0x1F == 0x1F {
+        return Err(malformed());
+    }
+    let (&first, rest) = rest.split_first().ok_or_else(malformed)?;
+    let (value, rest) = match first {
+        0x80 => {
+            // Indefinite length, ended by two zero bytes
+            if tag & 0x20 == 0 {
+                return Err(malformed());
+            }
+            let mut inner = rest;
+            while !inner.starts_with(&[0, 0]) {
+                inner = parse(inner, depth + 1)?.1;
+            }
+            (&rest[..rest.len() - inner.len()], &inner[2..])
+        }
+        short if short < 0x80 => split(rest, short as usize)?,
+        long => {
+            let count = (long & 0x7F) as usize;
+            if count > 4 || rest.len() < count {
+                return Err(malformed());
+            }
+            let len = rest[..count]
+                .iter()
+                .fold(0usize, |len, &b| len << 8 | b as usize);
+            split(&rest[count..], len)?
+        }
+    };
+    let raw = &data[..data.len() - rest.len()];
+    Ok((Element { tag, value, raw }, rest))
+}
+
+/// Encodes one DER element.
+pub fn encode(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    if value.len() < 0x80 {
+        out.push(value.len() as u8);
+    } else {
+        let len = value.len().to_be_bytes();
+        let skip = len.iter().take_while(|&&b| b == 0).count();
+        out.push(0x80 | (len.len() - skip) as u8);
+        out.extend_from_slice(&len[skip..]);
+    }
+    out.extend_from_slice(value);
+    out
+}
+
+fn sequence(parts: &[&[u8]]) -> Vec<u8> {
+    encode(SEQUENCE, &parts.concat())
+}
+
+// DER sorts the elements of a SET OF by their encoding
+fn set_of(mut elements: Vec<Vec<u8>>) -> Vec<u8> {
+    elements.sort();
+    encode(SET, &elements.concat())
+}
+
+fn encode_attribute(oid: &[u8], value: &[u8]) -> Vec<u8> {
+    sequence(&[&encode(OID, oid), &encode(SET, value)])
+}
+
+// Contents of an octet string, joining the chunks of a BER one
+fn octets(element: Element<'_>) -> Result<Cow<'_, [u8]>, String> {
+    match element.tag {
+        OCTET_STRING => Ok(Cow::Borrowed(element.value)),
+        CONSTRUCTED_OCTET_STRING => {
+            let mut joined = Vec::new();
+            let mut chunks = element.reader();
+            while !chunks.is_empty() {
+                joined.extend_from_slice(&octets(chunks.read()?)?);
+            }
+            Ok(Cow::Owned(joined))
+        }
+        _ => Err(malformed()),
+    }
+}
+
+/// Seconds since 1970 of a UTC date and time.
+pub fn unix_time(year: i64, month: i64, day: i64, hour: i64, minute: i64, second: i64) -> i64 {
+    // Civil date to days since 1970 (Howard Hinnant's algorithm)
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    days * 86_400 + hour * 3600 + minute * 60 + second
+}
+
+/// Formats seconds since 1970 as ISO 8601 in UTC.
+pub fn iso_time(seconds: i64) -> String {
+    let time = UNIX_EPOCH + Duration::from_secs(seconds.max(0) as u64);
+    parse_pdf_date(&format_pdf_date(time)).unwrap_or_default()
+}
+
+// Reads a UTCTime or GeneralizedTime as seconds since 1970
+fn time(element: Element<'_>) -> Result<i64, String> {
+    let digits = |range: std::ops::Range<usize>| {
+        element
+            .value
+            .get(range)
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| d.parse::<i64>().ok())
+            .ok_or_else(malformed)
+    };
+    let (year, at) = match element.tag {
+        // Two-digit years are 1950 to 2049
+        UTC_TIME => match digits(0..2)? {
+            year if year < 50 => (2000 + year, 2),
+            year => (1900 + year, 2),
+        },
+        GENERALIZED_TIME => (digits(0..4)?, 4),
+        _ => return Err(malformed()),
+    };
+    Ok(unix_time(
+        year,
+        digits(at..at + 2)?,
+        digits(at + 2..at + 4)?,
+        digits(at + 4..at + 6)?,
+        digits(at + 6..at + 8)?,
+        digits(at + 8..at + 10).unwrap_or(0),
+    ))
+}
+
+// Decodes the string types used in names
+fn name_string(element: Element<'_>) -> String {
+    match element.tag {
+        // BMPString
+        0x1E => {
+            let units: Vec<u16> = element
+                .value
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        // T61String, taken as Latin-1
+        0x14 => element.value.iter().map(|&b| b as char).collect(),
+        _ => String::from_utf8_lossy(element.value).into_owned(),
+    }
+}
+
+// The most telling part of a distinguished name: its common name, else its
+// organization
+fn display_name(name: Element<'_>) -> String {
+    let mut parts: Vec<(&[u8], String)> = Vec::new();
+    let mut rdns = name.reader();
+    while let Ok(rdn) = rdns.expect(SET) {
+        let mut attributes = rdn.reader();
+        while let Ok(attribute) = attributes.expect(SEQUENCE) {
+            let mut attribute = attribute.reader();
+            if let (Ok(oid), Ok(value)) = (attribute.expect(OID), attribute.read()) {
+                parts.push((oid.value, name_string(value)));
+            }
+        }
+    }
+    [ID_COMMON_NAME, ID_ORGANIZATION]
+        .iter()
+        .find_map(|&wanted| parts.iter().find(|(oid, _)| *oid == wanted))
+        .or(parts.first())
+        .map(|(_, value)| value.clone())
+        .unwrap_or_default()
+}
+
+/// An X.509 certificate, as far as signatures need it.
+pub struct Certificate<'a> {
+    /// The whole certificate
+    pub der: &'a [u8],
+    serial: &'a [u8],
+    issuer: Element<'a>,
+    subject: Element<'a>,
+    /// Validity, in seconds since 1970
+    pub not_before: i64,
+    pub not_after: i64,
+    key_algorithm: &'a [u8],
+    key_parameters: Option<Element<'a>>,
+    public_key: &'a [u8],
+    key_id: Option<&'a [u8]>,
+}
+
+impl<'a> Certificate<'a> {
+    pub fn from_der(der: &'a [u8]) -> Result<Self, String> {
+        Certificate::parse(Reader::new(der).expect(SEQUENCE)?)
+    }
+
+    pub fn parse(element: Element<'a>) -> Result<Self, String> {
+        let mut certificate = element.reader();
+        let mut tbs = certificate.expect(SEQUENCE)?.reader();
+        tbs.optional(EXPLICIT_0)?;
+        let serial = tbs.expect(INTEGER)?.value;
+        tbs.expect(SEQUENCE)?;
+        let issuer = tbs.expect(SEQUENCE)?;
+        let mut validity = tbs.expect(SEQUENCE)?.reader();
+        let not_before = time(validity.read()?)?;
+        let not_after = time(validity.read()?)?;
+        let subject = tbs.expect(SEQUENCE)?;
+
+        let mut key_info = tbs.expect(SEQUENCE)?.reader();
+        let mut algorithm = key_info.expect(SEQUENCE)?.reader();
+        let key_algorithm = algorithm.expect(OID)?.value;
+        let key_parameters = match algorithm.is_empty() {
+            true => None,
+            false => Some(algorithm.read()?),
+        };
+        // The key is a bit string; its first byte counts the unused bits
+        let public_key = key_info
+            .expect(BIT_STRING)?
+            .value
+            .get(1..)
+            .ok_or_else(malformed)?;
+
+        let mut key_id = None;
+        while !tbs.is_empty() {
+            let field = tbs.read()?;
+            if field.tag != EXPLICIT_3 {
+                continue;
+            }
+            let mut extensions = field.reader().expect(SEQUENCE)?.reader();
+            while !extensions.is_empty() {
+                let mut extension = extensions.expect(SEQUENCE)?.reader();
+                if extension.expect(OID)?.value != ID_SUBJECT_KEY_ID {
+                    continue;
+                }
+                // Skips the critical flag
+                let mut value = extension.read()?;
+                if value.tag != OCTET_STRING {
+                    value = extension.expect(OCTET_STRING)?;
+                }
+                key_id = Some(value.reader().expect(OCTET_STRING)?.value);
+            }
+        }
+
+        Ok(Certificate {
+            der: element.raw,
+            serial,
+            issuer,
+            subject,
+            not_before,
+            not_after,
+            key_algorithm,
+            key_parameters,
+            public_key,
+            key_id,
+        })
+    }
+
+    pub fn subject_name(&self) -> String {
+        display_name(self.subject)
+    }
+
+    pub fn issuer_name(&self) -> String {
+        display_name(self.issuer)
+    }
+}
+
+enum SignerId<'a> {
+    IssuerSerial { issuer: &'a [u8], serial: &'a [u8] },
+    KeyId(&'a [u8]),
+}
+
+/// The signed-data content of a CMS signature, with its first signer.
+pub struct SignedData<'a> {
+    pub certificates: Vec<Certificate<'a>>,
+    content_type: &'a [u8],
+    // Encapsulated content; detached signatures have none
+    content: Option<Cow<'a, [u8]>>,
+    signer: SignerId<'a>,
+    digest_algorithm: &'a [u8],
+    signed_attributes: Option<Element<'a>>,
+    signature_algorithm: &'a [u8],
+    signature: &'a [u8],
+    unsigned_attributes: Option<Element<'a>>,
+}
+
+/// A timestamp token over a signature.
+pub struct Timestamp<'a> {
+    /// The token, signed by the timestamp authority
+    pub token: SignedData<'a>,
+    /// When the authority saw the signature, in seconds since 1970
+    pub time: i64,
+}
+
+// The parts of a token's TSTInfo that matter here
+struct TstInfo<'a> {
+    digest: Digest,
+    hashed_message: &'a [u8],
+    time: i64,
+    nonce: Option<&'a [u8]>,
+}
+
+impl<'a> TstInfo<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, String> {
+        let mut info = Reader::new(data).expect(SEQUENCE)?.reader();
+        info.expect(INTEGER)?;
+        // The authority's policy
+        info.expect(OID)?;
+        let mut imprint = info.expect(SEQUENCE)?.reader();
+        let digest = Digest::from_oid(imprint.expect(SEQUENCE)?.reader().expect(OID)?.value)
+            .ok_or("The timestamp uses an unsupported hash function")?;
+        let hashed_message = imprint.expect(OCTET_STRING)?.value;
+        info.expect(INTEGER)?;
+        let time = time(info.expect(GENERALIZED_TIME)?)?;
+        // Accuracy and ordering may come before the nonce
+        info.optional(SEQUENCE)?;
+        info.optional(BOOLEAN)?;
+        let nonce = info.optional(INTEGER)?.map(|nonce| nonce.value);
+        Ok(TstInfo {
+            digest,
+            hashed_message,
+            time,
+            nonce,
+        })
+    }
+}
+
+/// Hash functions signatures are made with.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Digest {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Digest {
+    fn from_oid(oid: &[u8]) -> Option<Self> {
+        match oid {
+            ID_SHA1 => Some(Digest::Sha1),
+            ID_SHA256 => Some(Digest::Sha256),
+            ID_SHA384 => Some(Digest::Sha384),
+            ID_SHA512 => Some(Digest::Sha512),
+            _ => None,
+        }
+    }
+
+    fn algorithm(self) -> &'static Algorithm {
+        match self {
+            Digest::Sha1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+            Digest::Sha256 => &digest::SHA256,
+            Digest::Sha384 => &digest::SHA384,
+            Digest::Sha512 => &digest::SHA512,
+        }
+    }
+
+    /// Hashes `parts` as one message.
+    pub fn hash(self, parts: &[&[u8]]) -> Vec<u8> {
+        let mut context = digest::Context::new(self.algorithm());
+        for part in parts {
+            context.update(part);
+        }
+        context.finish().as_ref().to_vec()
+    }
+}
+
+// Value of the attribute `oid` in a set of attributes
+fn attribute<'a>(attributes: Element<'a>, oid: &[u8]) -> Result<Option<Element<'a>>, String> {
+    let mut reader = attributes.reader();
+    while !reader.is_empty() {
+        let mut attribute = reader.expect(SEQUENCE)?.reader();
+        if attribute.expect(OID)?.value == oid {
+            return attribute.expect(SET)?.reader().read().map(Some);
+        }
+    }
+    Ok(None)
+}
+
+impl<'a> SignedData<'a> {
+    /// Parses a DER (or BER) encoded ContentInfo holding signed data. Bytes
+    /// after it, such as the zero padding of PDF signatures, are ignored.
+    pub fn parse(data: &'a [u8]) -> Result<Self, String> {
+        let mut content_info = Reader::new(data).expect(SEQUENCE)?.reader();
+        if content_info.expect(OID)?.value != ID_SIGNED_DATA {
+            return Err("The signature isn't CMS signed data".to_string());
+        }
+        let mut signed_data = content_info
+            .expect(EXPLICIT_0)?
+            .reader()
+            .expect(SEQUENCE)?
+            .reader();
+        signed_data.expect(INTEGER)?;
+        signed_data.expect(SET)?;
+        let mut encapsulated = signed_data.expect(SEQUENCE)?.reader();
+        let content_type = encapsulated.expect(OID)?.value;
+        let content = match encapsulated.optional(EXPLICIT_0)? {
+            Some(content) => Some(octets(content.reader().read()?)?),
+            None => None,
+        };
+
+        let mut certificates = Vec::new();
+        if let Some(set) = signed_data.optional(EXPLICIT_0)? {
+            let mut reader = set.reader();
+            while !reader.is_empty() {
+                // Attribute certificates and the like are of no use here
+                let element = reader.read()?;
+                if element.tag != SEQUENCE {
+                    continue;
+                }
+                match Certificate::parse(element) {
+                    Ok(certificate) => certificates.push(certificate),
+                    Err(e) => eprintln!("Skipped a certificate in a signature: {}", e),
+                }
+            }
+        }
+        signed_data.optional(EXPLICIT_1)?;
+
+        let mut signer_info = signed_data.expect(SET)?.reader().expect(SEQUENCE)?.reader();
+        signer_info.expect(INTEGER)?;
+        let id = signer_info.read()?;
+        let signer = match id.tag {
+            SEQUENCE => {
+                let mut id = id.reader();
+                SignerId::IssuerSerial {
+                    issuer: id.expect(SEQUENCE)?.raw,
+                    serial: id.expect(INTEGER)?.value,
+                }
+            }
+            IMPLICIT_0 => SignerId::KeyId(id.value),
+            _ => return Err(malformed()),
+        };
+        let digest_algorithm = signer_info.expect(SEQUENCE)?.reader().expect(OID)?.value;
+        let signed_attributes = signer_info.optional(EXPLICIT_0)?;
+        let signature_algorithm = signer_info.expect(SEQUENCE)?.reader().expect(OID)?.value;
+        let signature = signer_info.expect(OCTET_STRING)?.value;
+        let unsigned_attributes = signer_info.optional(EXPLICIT_1)?;
+
+        Ok(SignedData {
+            certificates,
+            content_type,
+            content,
+            signer,
+            digest_algorithm,
+            signed_attributes,
+            signature_algorithm,
+            signature,
+            unsigned_attributes,
+        })
+    }
+
+    /// Index of the certificate that made the signature, if it is included.
+    pub fn signer(&self) -> Option<usize> {
+        self.certificates
+            .iter()
+            .position(|certificate| match &self.signer {
+                SignerId::IssuerSerial { issuer, serial } => {
+                    certificate.issuer.raw == *issuer && certificate.serial == *serial
+                }
+                SignerId::KeyId(id) => certificate.key_id == Some(*id),
+            })
+    }
+
+    /// The time the signer claims to have signed at, in seconds since 1970.
+    pub fn signing_time(&self) -> Option<i64> {
+        let attributes = self.signed_attributes?;
+        attribute(attributes, ID_SIGNING_TIME)
+            .ok()
+            .flatten()
+            .and_then(|value| time(value).ok())
+    }
+
+    /// Checks the signature over `parts`, the signed bytes. When the signature
+    /// encapsulates content, as adbe.pkcs7.sha1 signatures do, that has to be
+    /// the SHA-1 digest of the bytes.
+    ///
+    /// Returns whether the bytes are unchanged and the signature matches, or
+    /// an error if the signature can't be checked at all.
+    pub fn verify(&self, parts: &[&[u8]]) -> Result<bool, String> {
+        match &self.content {
+            Some(content) => {
+                if **content != *Digest::Sha1.hash(parts) {
+                    return Ok(false);
+                }
+                self.matches(&[content])
+            }
+            None => self.matches(parts),
+        }
+    }
+
+    // Checks the signature over `parts`, the content that was signed
+    fn matches(&self, parts: &[&[u8]]) -> Result<bool, String> {
+        let digest = Digest::from_oid(self.digest_algorithm)
+            .ok_or("The signature uses an unsupported hash function")?;
+        let signer = self
+            .signer()
+            .map(|index| &self.certificates[index])
+            .ok_or("The signer's certificate isn't included in the signature")?;
+        let algorithm = verification_algorithm(signer, digest, self.signature_algorithm)?;
+
+        let message = match self.signed_attributes {
+            Some(attributes) => {
+                let expected = attribute(attributes, ID_MESSAGE_DIGEST)?
+                    .ok_or_else(malformed)
+                    .and_then(octets)?;
+                if *expected != *digest.hash(parts) {
+                    return Ok(false);
+                }
+                // The attributes are signed as a SET, not as tagged on the wire
+                let mut der = attributes.raw.to_vec();
+                der[0] = SET;
+                der
+            }
+            None => parts.concat(),
+        };
+        Ok(UnparsedPublicKey::new(algorithm, signer.public_key)
+            .verify(&message, self.signature)
+            .is_ok())
+    }
+
+    // Checks that this is a timestamp token signed by its authority, and reads
+    // it. Whether it is over the right signature is up to the caller.
+    fn tst_info(&self) -> Result<TstInfo<'_>, String> {
+        let content = match (&self.content, self.content_type) {
+            (Some(content), ID_TST_INFO) => content,
+            _ => return Err("The timestamp isn't a timestamp token".to_string()),
+        };
+        if !self.matches(&[content])? {
+            return Err("The timestamp's signature doesn't match it".to_string());
+        }
+        TstInfo::parse(content)
+    }
+
+    /// The timestamp token added to the signature, if any, once checked to
+    /// be signed by its authority and to be over this signature. The
+    /// authority's certificate is left to the caller.
+    pub fn timestamp(&self) -> Result<Option<Timestamp<'a>>, String> {
+        let Some(attributes) = self.unsigned_attributes else {
+            return Ok(None);
+        };
+        let Some(token) = attribute(attributes, ID_TIMESTAMP_TOKEN)? else {
+            return Ok(None);
+        };
+        let token = SignedData::parse(token.raw)?;
+        let info = token.tst_info()?;
+        if info.hashed_message != info.digest.hash(&[self.signature]) {
+            return Err("The timestamp is over a different signature".to_string());
+        }
+        let time = info.time;
+        Ok(Some(Timestamp { token, time }))
+    }
+}
+
+// The ring algorithm for a signature by `signer`
+fn verification_algorithm(
+    signer: &Certificate<'_>,
+    digest: Digest,
+    signature_algorithm: &[u8],
+) -> Result<&'static dyn VerificationAlgorithm, String> {
+    let unsupported = || "The signature uses an unsupported algorithm".to_string();
+    match signer.key_algorithm {
+        ID_RSA_ENCRYPTION | ID_RSASSA_PSS => {
+            let pss = signature_algorithm == ID_RSASSA_PSS;
+            Ok(match (pss, digest) {
+                // Old signatures often have 1024-bit keys
+                (false, Digest::Sha1) => &signature::RSA_PKCS1_1024_8192_SHA1_FOR_LEGACY_USE_ONLY,
+                (false, Digest::Sha256) => {
+                    &signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY
+                }
+                (false, Digest::Sha384) => &signature::RSA_PKCS1_2048_8192_SHA384,
+                (false, Digest::Sha512) => {
+                    &signature::RSA_PKCS1_1024_8192_SHA512_FOR_LEGACY_USE_ONLY
+                }
+                (true, Digest::Sha256) => &signature::RSA_PSS_2048_8192_SHA256,
+                (true, Digest::Sha384) => &signature::RSA_PSS_2048_8192_SHA384,
+                (true, Digest::Sha512) => &signature::RSA_PSS_2048_8192_SHA512,
+                (true, Digest::Sha1) => return Err(unsupported()),
+            })
+        }
+        ID_EC_PUBLIC_KEY => {
+            let curve = signer.key_parameters.map(|p| p.value);
+            match (curve, digest) {
+                (Some(ID_P256), Digest::Sha256) => Ok(&signature::ECDSA_P256_SHA256_ASN1),
+                (Some(ID_P256), Digest::Sha384) => Ok(&signature::ECDSA_P256_SHA384_ASN1),
+                (Some(ID_P384), Digest::Sha256) => Ok(&signature::ECDSA_P384_SHA256_ASN1),
+                (Some(ID_P384), Digest::Sha384) => Ok(&signature::ECDSA_P384_SHA384_ASN1),
+                _ => Err(unsupported()),
+            }
+        }
+        _ => Err(unsupported()),
+    }
+}
+
+/// The signed attributes of a signature over content with the SHA-256
+/// `digest`, encoded as they are signed.
+pub fn signed_attributes(signer: &Certificate<'_>, digest: &[u8]) -> Vec<u8> {
+    // ESS signing-certificate-v2; SHA-256 is its default, so it isn't named
+    let certificate_hash = Digest::Sha256.hash(&[signer.der]);
+    let signing_certificate = sequence(&[&sequence(&[&sequence(&[&encode(
+        OCTET_STRING,
+        &certificate_hash,
+    )])])]);
+    set_of(vec![
+        encode_attribute(ID_CONTENT_TYPE, &encode(OID, ID_DATA)),
+        encode_attribute(ID_MESSAGE_DIGEST, &encode(OCTET_STRING, digest)),
+        encode_attribute(ID_SIGNING_CERTIFICATE_V2, &signing_certificate),
+    ])
+}
+
+/// Builds a detached signature from `attributes`, as `signed_attributes`
+/// returned them, and the signer's SHA-256 signature over them, along with a
+/// timestamp token over the signature from `timestamp_token`.
+pub fn signed_data(
+    signer: &Certificate<'_>,
+    attributes: &[u8],
+    signature: &[u8],
+    timestamp: Option<&[u8]>,
+) -> Result<Vec<u8>, String> {
+    let signature_algorithm = match signer.key_algorithm {
+        ID_RSA_ENCRYPTION => sequence(&[&encode(OID, ID_SHA256_WITH_RSA), &encode(NULL, &[])]),
+        ID_EC_PUBLIC_KEY => sequence(&[&encode(OID, ID_ECDSA_WITH_SHA256)]),
+        _ => return Err("Certificates with this kind of key can't sign".to_string()),
+    };
+    let digest_algorithm = sequence(&[&encode(OID, ID_SHA256)]);
+    // The attributes are sent tagged instead of as a SET
+    let mut tagged = attributes.to_vec();
+    tagged[0] = EXPLICIT_0;
+
+    let unsigned_attributes = match timestamp {
+        Some(token) => encode(EXPLICIT_1, &encode_attribute(ID_TIMESTAMP_TOKEN, token)),
+        None => Vec::new(),
+    };
+
+    let signer_info = sequence(&[
+        &encode(INTEGER, &[1]),
+        &sequence(&[signer.issuer.raw, &encode(INTEGER, signer.serial)]),
+        &digest_algorithm,
+        &tagged,
+        &signature_algorithm,
+        &encode(OCTET_STRING, signature),
+        &unsigned_attributes,
+    ]);
+    let signed_data = sequence(&[
+        &encode(INTEGER, &[1]),
+        &encode(SET, &digest_algorithm),
+        &sequence(&[&encode(OID, ID_DATA)]),
+        &encode(EXPLICIT_0, signer.der),
+        &encode(SET, &signer_info),
+    ]);
+    Ok(sequence(&[
+        &encode(OID, ID_SIGNED_DATA),
+        &encode(EXPLICIT_0, &signed_data),
+    ]))
+}
+
+/// A timestamp request (RFC 3161) for a token over `signature`, a signer's
+/// signature value. Returns the request and the random nonce it carries.
+pub fn timestamp_request(signature: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let mut nonce = vec![0u8; 8];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| "Failed to make a timestamp request".to_string())?;
+    // A positive integer, in as few bytes as DER wants
+    nonce[0] = (nonce[0] & 0x7F) | 0x01;
+    let imprint = sequence(&[
+        &sequence(&[&encode(OID, ID_SHA256)]),
+        &encode(OCTET_STRING, &Digest::Sha256.hash(&[signature])),
+    ]);
+    let request = sequence(&[
+        &encode(INTEGER, &[1]),
+        &imprint,
+        &encode(INTEGER, &nonce),
+        // Asks for the authority's certificate in the token
+        &encode(BOOLEAN, &[0xFF]),
+    ]);
+    Ok((request, nonce))
+}
+
+/// The token in a timestamp authority's response to `timestamp_request`,
+/// once checked to answer it.
+pub fn timestamp_token(response: &[u8], signature: &[u8], nonce: &[u8]) -> Result<Vec<u8>, String> {
+    let mut response = Reader::new(response).expect(SEQUENCE)?.reader();
+    let mut status_info = response.expect(SEQUENCE)?.reader();
+    let status = status_info.expect(INTEGER)?.value;
+    // Granted, or granted with modifications
+    if status != [0] && status != [1] {
+        let reason = status_info
+            .optional(SEQUENCE)?
+            .and_then(|text| text.reader().read().ok())
+            .map(name_string);
+        return Err(match reason {
+            Some(reason) => format!("The timestamp authority refused the request: {}", reason),
+            None => "The timestamp authority refused the request".to_string(),
+        });
+    }
+    let token = response.expect(SEQUENCE)?.raw;
+    let signed_data = SignedData::parse(token)?;
+    let info = signed_data.tst_info()?;
+    if info.hashed_message != info.digest.hash(&[signature]) || info.nonce != Some(nonce) {
+        return Err("The timestamp authority answered a different request".to_string());
+    }
+    Ok(token.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::Signer;
+
+    // A token from `authority` over `signature`, as a timestamp authority
+    // would send it
+    fn token(authority: &Signer, signature: &[u8]) -> Vec<u8> {
+        let imprint = sequence(&[
+            &sequence(&[&encode(OID, ID_SHA256)]),
+            &encode(OCTET_STRING, &Digest::Sha256.hash(&[signature])),
+        ]);
+        let tst_info = sequence(&[
+            &encode(INTEGER, &[1]),
+            &encode(OID, &[0x2A, 0x03]),
+            &imprint,
+            &encode(INTEGER, &[7]),
+            &encode(GENERALIZED_TIME, b"20260101120000Z"),
+        ]);
+        let certificate = Certificate::from_der(&authority.der).unwrap();
+        let signer_info = sequence(&[
+            &encode(INTEGER, &[1]),
+            &sequence(&[certificate.issuer.raw, &encode(INTEGER, certificate.serial)]),
+            &sequence(&[&encode(OID, ID_SHA256)]),
+            &sequence(&[&encode(OID, ID_ECDSA_WITH_SHA256)]),
+            &encode(OCTET_STRING, &authority.sign(&tst_info)),
+        ]);
+        let signed_data = sequence(&[
+            &encode(INTEGER, &[3]),
+            &encode(SET, &sequence(&[&encode(OID, ID_SHA256)])),
+            &sequence(&[
+                &encode(OID, ID_TST_INFO),
+                &encode(EXPLICIT_0, &encode(OCTET_STRING, &tst_info)),
+            ]),
+            &encode(EXPLICIT_0, authority.der.as_slice()),
+            &encode(SET, &signer_info),
+        ]);
+        sequence(&[
+            &encode(OID, ID_SIGNED_DATA),
+            &encode(EXPLICIT_0, &signed_data),
+        ])
+    }
+
+    // A signature over `content`, with a token over `timestamped`, or over
+    // its own signature value if not given
+    fn signed(content: &[u8], timestamped: Option<&[u8]>) -> Vec<u8> {
+        let signer = Signer::new("Signer");
+        let certificate = Certificate::from_der(&signer.der).unwrap();
+        let attributes = signed_attributes(&certificate, &Digest::Sha256.hash(&[content]));
+        let signature = signer.sign(&attributes);
+        let token = token(
+            &Signer::new("Timestamps"),
+            timestamped.unwrap_or(&signature),
+        );
+        signed_data(&certificate, &attributes, &signature, Some(&token)).unwrap()
+    }
+
+    #[test]
+    fn signature_verifies() {
+        let data = signed(b"content", None);
+        let signed_data = SignedData::parse(&data).unwrap();
+        assert!(signed_data.verify(&[b"content"]).unwrap());
+        assert!(!signed_data.verify(&[b"altered"]).unwrap());
+    }
+
+    #[test]
+    fn timestamp_over_signature_is_read() {
+        let data = signed(b"content", None);
+        let timestamp = SignedData::parse(&data).unwrap().timestamp().unwrap();
+        assert_eq!(
+            timestamp.map(|timestamp| timestamp.time),
+            Some(unix_time(2026, 1, 1, 12, 0, 0))
+        );
+    }
+
+    #[test]
+    fn timestamp_over_other_signature_is_rejected() {
+        let data = signed(b"content", Some(b"another signature"));
+        let error = SignedData::parse(&data).unwrap().timestamp().err();
+        assert_eq!(
+            error.as_deref(),
+            Some("The timestamp is over a different signature")
+        );
+    }
+}
@@ -0,0 +1,77 @@
+// Color remapping for night mode.
+//
+// Inverting the whole bitmap, as a CSS filter would, turns photos into
+// negatives. Instead, text and vector art have their luminance flipped while
+// keeping their hue, and embedded images are left alone.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ColorMode {
+    #[default]
+    Normal,
+    Night,
+}
+
+// Target luminance of paper and ink in night mode. Pure black and white are
+// harsh on the eyes in the dark
+const NIGHT_PAPER: f32 = 30.0;
+const NIGHT_INK: f32 = 225.0;
+
+/// Remaps the colors of a pixmap rendered from `list` with `ctm`.
+pub fn apply(
+    mode: ColorMode,
+    list: &mupdf::DisplayList,
+    ctm: &mupdf::Matrix,
+    pixmap: &mut mupdf::Pixmap,
+) -> Result<(), mupdf::Error> {
+    if mode == ColorMode::Normal {
+        return Ok(());
+    }
+
+    let text_page = list.to_text_page(mupdf::TextPageOptions::PRESERVE_IMAGES)?;
+    let images: Vec<mupdf::IRect> = text_page
+        .blocks()
+        .filter(|block| block.r#type() == mupdf::TextBlockType::Image)
+        .map(|block| block.bounds().transform(ctm).round())
+        .collect();
+
+    let (x0, y0) = (pixmap.x(), pixmap.y());
+    let n = pixmap.n() as usize;
+    let stride = pixmap.stride() as usize;
+    let row_len = pixmap.width() as usize * n;
+    let height = pixmap.height() as usize;
+    for (row_index, row) in pixmap
+        .samples_mut()
+        .chunks_mut(stride)
+        .take(height)
+        .enumerate()
+    {
+        let y = y0 + row_index as i32;
+        let row_images: Vec<_> = images.iter().filter(|r| y >= r.y0 && y < r.y1).collect();
+        for (col, px) in row[..row_len].chunks_exact_mut(n).enumerate() {
+            let x = x0 + col as i32;
+            if row_images.iter().any(|r| x >= r.x0 && x < r.x1) {
+                continue;
+            }
+            night(px);
+        }
+    }
+    Ok(())
+}
+
+// Moves all channels by the same amount so that the luminance is inverted
+// while the differences between channels, and so the hue, are kept
+fn night(px: &mut [u8]) {
+    let channels = px.len().min(3);
+    let luma = match channels {
+        3 => 0.299 * f32::from(px[0]) + 0.587 * f32::from(px[1]) + 0.114 * f32::from(px[2]),
+        _ => f32::from(px[0]),
+    };
+    let target = NIGHT_INK - (NIGHT_INK - NIGHT_PAPER) * luma / 255.0;
+    let shift = target - luma;
+    for c in &mut px[..channels] {
+        *c = (f32::from(*c) + shift).clamp(0.0, 255.0) as u8;
+    }
+}
@@ -0,0 +1,891 @@
+// Building new PDFs out of the pages of existing ones.
+//
+// Everything works at the object level with lopdf. Merged sources have their
+// page trees hung under a new root, so inherited page attributes survive, and
+// outlines, named destinations, page labels and form fields are carried over
+// with page offsets adjusted. Extracted pages get a fresh page tree and keep
+// only what still points at them. Image files become documents with one page
+// per image.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+use mupdf::pdf::{PdfDocument, PdfWriteOptions};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, State};
+
+use crate::content::{concat, Transform};
+use crate::labels::label_entries;
+use crate::loading::{linearization, HEADER_SIZE};
+use crate::objects::{self, MAX_DEPTH};
+use crate::outline::{lookup_named_destination, walk_name_tree};
+use crate::pdf::{blocking, DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scope;
+use crate::stamp::image_xobject;
+
+// Resolution assumed for images that don't record one
+const DEFAULT_IMAGE_DPI: f32 = 72.0;
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeProgress {
+    /// Sources merged so far; equal to `total` while the result is written
+    pub done: usize,
+    pub total: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WrittenDocument {
+    pub path: String,
+    pub page_count: i32,
+}
+
+/// Page size of documents made from images.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum PageSize {
+    /// Each page is the size of its image at the image's resolution
+    #[default]
+    Image,
+    A4,
+    Letter,
+    Legal,
+}
+
+impl PageSize {
+    // Portrait width and height in points
+    fn points(self) -> Option<(f32, f32)> {
+        match self {
+            PageSize::Image => None,
+            PageSize::A4 => Some((595.28, 841.89)),
+            PageSize::Letter => Some((612.0, 792.0)),
+            PageSize::Legal => Some((612.0, 1008.0)),
+        }
+    }
+}
+
+/// How an image is placed on a page of a fixed size.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum ImageFit {
+    /// Scaled to show the whole image
+    #[default]
+    Fit,
+    /// Scaled to cover the page, cutting off what sticks out
+    Fill,
+    /// At the image's resolution, centred
+    Actual,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImagePage {
+    pub path: String,
+    /// Degrees clockwise, in steps of 90
+    #[serde(default)]
+    pub rotation: i32,
+    #[serde(default)]
+    pub fit: ImageFit,
+}
+
+/// Loads a PDF for structural work, refusing files that need a password.
+pub fn load_document(path: &Path) -> Result<Document, String> {
+    let doc =
+        Document::load(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if doc.is_encrypted() {
+        return Err(format!("{} is password protected", path.display()));
+    }
+    Ok(doc)
+}
+
+// Where a file is written before it replaces `path`
+fn partial_path(path: &Path) -> PathBuf {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".partial");
+    PathBuf::from(temp)
+}
+
+// Moves a finished file into place, or cleans up after a failed write
+fn finish_write(temp: &Path, path: &Path, written: Result<(), String>) -> Result<(), String> {
+    let result = written.and_then(|_| std::fs::rename(temp, path).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(temp);
+        return Err(format!("Failed to write {}: {}", path.display(), e));
+    }
+    Ok(())
+}
+
+/// Saves a document, replacing `path` only once the new file is complete.
+pub fn write_document(doc: &mut Document, path: &Path) -> Result<(), String> {
+    let temp = partial_path(path);
+    doc.compress();
+    let written = doc.save(&temp).map(|_| ()).map_err(|e| e.to_string());
+    finish_write(&temp, path, written)
+}
+
+/// Writes finished file contents to `path`, replacing it only once they are
+/// all written.
+pub fn write_file(path: &Path, data: &[u8]) -> Result<(), String> {
+    let temp = partial_path(path);
+    let written = std::fs::write(&temp, data).map_err(|e| e.to_string());
+    finish_write(&temp, path, written)
+}
+
+/// Writes the PDF in `data` to `path` through MuPDF, for what lopdf can't
+/// write, such as encryption.
+///
+/// Uses MuPDF, so call this on a render worker.
+pub fn write_with_options(
+    data: &[u8],
+    path: &Path,
+    options: PdfWriteOptions,
+) -> Result<(), String> {
+    let temp = partial_path(path);
+    let written = PdfDocument::from_bytes(data)
+        .and_then(|doc| doc.save_with_options(&temp.to_string_lossy(), options))
+        .map_err(|e| e.to_string());
+    finish_write(&temp, path, written)
+}
+
+/// Rewrites the PDF at `path` in linearized ("fast web view") form, so that
+/// viewers can show the first page before the whole file has downloaded.
+///
+/// Uses MuPDF, so call this on a render worker. The file is left as it was
+/// if the result isn't actually linearized.
+pub fn linearize(path: &Path) -> Result<(), String> {
+    let temp = partial_path(path);
+    let written = (|| {
+        let doc = PdfDocument::open(&path.to_string_lossy()).map_err(|e| e.to_string())?;
+        let mut options = PdfWriteOptions::default();
+        options
+            .set_linear(true)
+            .set_garbage_level(1)
+            .set_compress(true);
+        doc.save_with_options(&temp.to_string_lossy(), options)
+            .map_err(|e| e.to_string())?;
+
+        let mut header = Vec::new();
+        File::open(&temp)
+            .and_then(|file| file.take(HEADER_SIZE as u64).read_to_end(&mut header))
+            .map_err(|e| e.to_string())?;
+        if linearization(&header).is_none() {
+            return Err("this build can't write linearized files".to_string());
+        }
+        Ok(())
+    })();
+    finish_write(&temp, path, written)
+}
+
+// All named destinations, from both the old /Dests dictionary and the
+// /Names /Dests tree
+fn named_destinations(doc: &Document) -> Vec<(Vec<u8>, Object)> {
+    let mut dests = Vec::new();
+    let Ok(catalog) = doc.catalog() else {
+        return dests;
+    };
+    if let Some(old) = objects::get_dict(doc, catalog, b"Dests") {
+        for (name, value) in old.iter() {
+            dests.push((name.clone(), value.clone()));
+        }
+    }
+    if let Some(tree) = objects::get_dict(doc, catalog, b"Names")
+        .and_then(|names| objects::get_dict(doc, names, b"Dests"))
+    {
+        walk_name_tree(doc, tree, 0, &mut |name, value| {
+            dests.push((name.to_vec(), value.clone()));
+        });
+    }
+    dests
+}
+
+// Builds a catalog /Names entry holding a single-leaf destination tree
+fn names_dictionary(dests: BTreeMap<Vec<u8>, Object>) -> Dictionary {
+    // Name tree leaves must be sorted, which the BTreeMap already is
+    let names: Vec<Object> = dests
+        .into_iter()
+        .flat_map(|(name, value)| [Object::string_literal(name), value])
+        .collect();
+    dictionary! { "Dests" => dictionary! { "Names" => names } }
+}
+
+// Everything carried over from one source besides its objects
+struct Parts {
+    pages: ObjectId,
+    page_count: i64,
+    outline: Option<(ObjectId, ObjectId, i64)>,
+    labels: Vec<(i64, Dictionary)>,
+    dests: Vec<(Vec<u8>, Object)>,
+    fields: Vec<Object>,
+}
+
+fn collect_parts(doc: &Document) -> Result<Parts, String> {
+    let catalog = doc.catalog().map_err(|e| e.to_string())?;
+    let pages = catalog
+        .get(b"Pages")
+        .and_then(Object::as_reference)
+        .map_err(|_| "Document has no page tree".to_string())?;
+
+    let outline = objects::get_dict(doc, catalog, b"Outlines").and_then(|outlines| {
+        let first = outlines.get(b"First").and_then(Object::as_reference).ok()?;
+        let last = outlines.get(b"Last").and_then(Object::as_reference).ok()?;
+        let count = objects::get(doc, outlines, b"Count")
+            .and_then(|c| c.as_i64().ok())
+            .unwrap_or(0);
+        Some((first, last, count.abs()))
+    });
+
+    let fields = objects::get_dict(doc, catalog, b"AcroForm")
+        .and_then(|form| objects::get_array(doc, form, b"Fields"))
+        .map(|fields| fields.to_vec())
+        .unwrap_or_default();
+
+    Ok(Parts {
+        pages,
+        page_count: doc.get_pages().len() as i64,
+        outline,
+        labels: label_entries(doc)
+            .into_iter()
+            .map(|(start, label)| (start, label.clone()))
+            .collect(),
+        dests: named_destinations(doc),
+        fields,
+    })
+}
+
+/// Concatenates documents, calling `progress` after each one.
+pub fn merge(sources: Vec<Document>, mut progress: impl FnMut(usize)) -> Result<Document, String> {
+    let version = sources
+        .iter()
+        .map(|doc| doc.version.clone())
+        .max()
+        .unwrap_or_else(|| "1.7".to_string());
+    let mut out = Document::with_version(version);
+    let pages_id = out.new_object_id();
+    let outlines_id = out.new_object_id();
+
+    let mut kids = Vec::new();
+    let mut offset = 0;
+    let mut outline: Option<(ObjectId, ObjectId)> = None;
+    let mut outline_count = 0;
+    let mut labels = Vec::new();
+    let mut any_labels = false;
+    let mut dests = BTreeMap::new();
+    let mut fields = Vec::new();
+
+    for (index, mut doc) in sources.into_iter().enumerate() {
+        doc.renumber_objects_with(out.max_id + 1);
+        let parts = collect_parts(&doc)?;
+        let catalog_id = doc.trailer.get(b"Root").and_then(Object::as_reference).ok();
+
+        out.max_id = doc.max_id;
+        for (id, object) in std::mem::take(&mut doc.objects) {
+            if Some(id) != catalog_id {
+                out.objects.insert(id, object);
+            }
+        }
+
+        if let Ok(pages) = out.get_dictionary_mut(parts.pages) {
+            pages.set("Parent", pages_id);
+        }
+        kids.push(Object::Reference(parts.pages));
+
+        // Chain this source's top-level outline items after the previous ones
+        if let Some((first, last, count)) = parts.outline {
+            let mut item = Some(first);
+            while let Some(id) = item {
+                let Ok(dict) = out.get_dictionary_mut(id) else {
+                    break;
+                };
+                dict.set("Parent", outlines_id);
+                item = dict.get(b"Next").and_then(Object::as_reference).ok();
+                if id == last {
+                    break;
+                }
+            }
+            if let Some((_, previous_last)) = outline {
+                if let Ok(dict) = out.get_dictionary_mut(previous_last) {
+                    dict.set("Next", first);
+                }
+                if let Ok(dict) = out.get_dictionary_mut(first) {
+                    dict.set("Prev", previous_last);
+                }
+            }
+            outline = Some((outline.map_or(first, |(first, _)| first), last));
+            outline_count += count;
+        }
+
+        // Sources without labels keep their own 1, 2, 3... numbering
+        any_labels |= !parts.labels.is_empty();
+        if parts.labels.first().is_none_or(|(start, _)| *start > 0) {
+            labels.push((offset, dictionary! { "S" => "D" }));
+        }
+        labels.extend(
+            parts
+                .labels
+                .into_iter()
+                .map(|(start, label)| (start + offset, label)),
+        );
+
+        // The first document wins when names clash
+        for (name, value) in parts.dests {
+            dests.entry(name).or_insert(value);
+        }
+        fields.extend(parts.fields);
+
+        offset += parts.page_count;
+        progress(index + 1);
+    }
+
+    out.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => offset,
+        }),
+    );
+
+    let mut catalog = dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    };
+    if let Some((first, last)) = outline {
+        out.objects.insert(
+            outlines_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Outlines",
+                "First" => first,
+                "Last" => last,
+                "Count" => outline_count,
+            }),
+        );
+        catalog.set("Outlines", outlines_id);
+    }
+    if any_labels {
+        let nums: Vec<Object> = labels
+            .into_iter()
+            .flat_map(|(start, label)| [Object::Integer(start), Object::Dictionary(label)])
+            .collect();
+        catalog.set("PageLabels", dictionary! { "Nums" => nums });
+    }
+    if !dests.is_empty() {
+        catalog.set("Names", names_dictionary(dests));
+    }
+    if !fields.is_empty() {
+        catalog.set("AcroForm", dictionary! { "Fields" => fields });
+    }
+
+    let catalog_id = out.add_object(catalog);
+    out.trailer.set("Root", catalog_id);
+    // Drop the old catalogs' leftovers, such as /Info dictionaries
+    out.prune_objects();
+    Ok(out)
+}
+
+// Builds a document with a page for each image
+fn images_document(
+    images: &[ImagePage],
+    page_size: PageSize,
+    margin: f32,
+) -> Result<Document, String> {
+    let mut doc = Document::with_version("1.7");
+    let pages_id = doc.new_object_id();
+    let mut kids = Vec::new();
+    for page in images {
+        let image = image_xobject(&mut doc, Path::new(&page.path))?;
+        let rotation = page.rotation.rem_euclid(360);
+        // Size in points at the image's own resolution
+        let scale = 72.0 / image.dpi.unwrap_or(DEFAULT_IMAGE_DPI);
+        let (width, height) = (image.width as f32 * scale, image.height as f32 * scale);
+        let (shown_width, shown_height) = match rotation {
+            90 | 270 => (height, width),
+            _ => (width, height),
+        };
+
+        let (page_width, page_height, zoom) = match page_size.points() {
+            None => (shown_width + 2.0 * margin, shown_height + 2.0 * margin, 1.0),
+            Some((short, long)) => {
+                // Landscape images get landscape pages
+                let (page_width, page_height) = if shown_width > shown_height {
+                    (long, short)
+                } else {
+                    (short, long)
+                };
+                let room_x = (page_width - 2.0 * margin).max(1.0) / shown_width.max(f32::EPSILON);
+                let room_y = (page_height - 2.0 * margin).max(1.0) / shown_height.max(f32::EPSILON);
+                let zoom = match page.fit {
+                    ImageFit::Fit => room_x.min(room_y),
+                    ImageFit::Fill => room_x.max(room_y),
+                    ImageFit::Actual => 1.0,
+                };
+                (page_width, page_height, zoom)
+            }
+        };
+
+        // Images fill the unit square: size it, turn it about its centre and
+        // centre it on the page
+        let (w, h) = (width * zoom, height * zoom);
+        let (sin, cos) = (-(rotation as f32)).to_radians().sin_cos();
+        let mut m: Transform = [w, 0.0, 0.0, h, -w / 2.0, -h / 2.0];
+        m = concat(m, [cos, sin, -sin, cos, 0.0, 0.0]);
+        m = concat(m, [1.0, 0.0, 0.0, 1.0, page_width / 2.0, page_height / 2.0]);
+        let operations = vec![
+            Operation::new("q", vec![]),
+            Operation::new("cm", m.iter().map(|&v| v.into()).collect()),
+            Operation::new("Do", vec![Object::Name(b"Im1".to_vec())]),
+            Operation::new("Q", vec![]),
+        ];
+        let content = Content { operations }
+            .encode()
+            .map_err(|e| format!("Failed to write page content: {}", e))?;
+        let contents = doc.add_object(Stream::new(Dictionary::new(), content));
+        kids.push(Object::Reference(doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), page_width.into(), page_height.into()],
+            "Resources" => dictionary! {
+                "XObject" => dictionary! { "Im1" => image.id },
+            },
+            "Contents" => contents,
+        })));
+    }
+
+    let count = kids.len() as i32;
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => count,
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    doc.trailer.set("Root", catalog_id);
+    Ok(doc)
+}
+
+// Page attributes a page can inherit from its ancestors in the page tree
+const INHERITABLE: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+// Catalog entries carried over into extracted documents. /Pages and
+// /AcroForm have been rebuilt by `set_pages` by then.
+const DOCUMENT_ENTRIES: [&[u8]; 6] = [
+    b"Pages",
+    b"AcroForm",
+    b"Lang",
+    b"Metadata",
+    b"ViewerPreferences",
+    b"PageLayout",
+];
+
+/// Parses one-based page ranges like "1-5,8,10-end" into zero-based page
+/// indices, in the order given and without repeats.
+pub fn parse_page_ranges(ranges: &str, page_count: i32) -> Result<Vec<i32>, String> {
+    let page = |text: &str| -> Result<i32, String> {
+        let text = text.trim();
+        let number = if text.eq_ignore_ascii_case("end") {
+            page_count
+        } else {
+            text.parse()
+                .map_err(|_| format!("\"{}\" is not a page number", text))?
+        };
+        if number < 1 || number > page_count {
+            return Err(format!(
+                "Page {} is out of range (the document has {} pages)",
+                number, page_count
+            ));
+        }
+        Ok(number - 1)
+    };
+
+    let mut pages = Vec::new();
+    for part in ranges.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (page(first)?, page(last)?),
+            None => (page(part)?, page(part)?),
+        };
+        // Backwards ranges like "5-1" select the pages in reverse
+        let range: Box<dyn Iterator<Item = i32>> = if first <= last {
+            Box::new(first..=last)
+        } else {
+            Box::new((last..=first).rev())
+        };
+        for index in range {
+            if !pages.contains(&index) {
+                pages.push(index);
+            }
+        }
+    }
+    if pages.is_empty() {
+        return Err("No pages selected".to_string());
+    }
+    Ok(pages)
+}
+
+// The page an internal link or named destination jumps to
+fn destination_page(doc: &Document, dest: &Object) -> Option<ObjectId> {
+    match objects::resolve(doc, dest) {
+        Object::Array(array) => array.first()?.as_reference().ok(),
+        Object::Name(name) | Object::String(name, _) => {
+            destination_page(doc, lookup_named_destination(doc, name)?)
+        }
+        Object::Dictionary(dict) => destination_page(doc, dict.get(b"D").ok()?),
+        _ => None,
+    }
+}
+
+// Whether an annotation may be kept when only `kept` pages remain. Links into
+// the removed pages are dropped rather than left pointing nowhere.
+fn keep_annotation(doc: &Document, annot: &Dictionary, kept: &HashSet<ObjectId>) -> bool {
+    if objects::get_name(doc, annot, b"Subtype") != Some(b"Link") {
+        return true;
+    }
+    let dest = objects::get(doc, annot, b"Dest").or_else(|| {
+        let action = objects::get_dict(doc, annot, b"A")?;
+        match objects::get_name(doc, action, b"S") {
+            Some(b"GoTo") => objects::get(doc, action, b"D"),
+            _ => None,
+        }
+    });
+    match dest {
+        Some(dest) => destination_page(doc, dest).is_some_and(|page| kept.contains(&page)),
+        None => true,
+    }
+}
+
+/// Whether a form field has a widget among `annots`.
+pub fn field_in(doc: &Document, field: &Object, annots: &HashSet<ObjectId>, depth: usize) -> bool {
+    if depth > MAX_DEPTH {
+        return false;
+    }
+    if field.as_reference().is_ok_and(|id| annots.contains(&id)) {
+        return true;
+    }
+    let Ok(dict) = objects::resolve(doc, field).as_dict() else {
+        return false;
+    };
+    objects::get_array(doc, dict, b"Kids")
+        .unwrap_or(&[])
+        .iter()
+        .any(|kid| field_in(doc, kid, annots, depth + 1))
+}
+
+/// Replaces the page tree with a flat one holding `pages`, in order.
+///
+/// Inherited page attributes are copied onto the pages. Pages left out are
+/// deleted, along with links into them and form fields that were on them.
+pub fn set_pages(doc: &mut Document, pages: &[ObjectId]) -> Result<(), String> {
+    let all: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    let kept: HashSet<ObjectId> = pages.iter().copied().collect();
+    let removing = kept.len() < all.len();
+    let pages_id = doc.new_object_id();
+
+    let mut updated = Vec::new();
+    let mut kept_annots = HashSet::new();
+    for &id in pages {
+        let mut page = doc.get_dictionary(id).map_err(|e| e.to_string())?.clone();
+        for key in INHERITABLE {
+            if !page.has(key) {
+                if let Some(value) = objects::inherited(doc, id, key) {
+                    page.set(key, value.clone());
+                }
+            }
+        }
+        page.set("Parent", pages_id);
+        if !removing {
+            updated.push((id, page));
+            continue;
+        }
+
+        // Article threads span pages that may be gone
+        page.remove(b"B");
+        if let Some(annots) = objects::get_array(doc, &page, b"Annots") {
+            let annots: Vec<Object> = annots
+                .iter()
+                .filter(|annot| {
+                    objects::resolve(doc, annot)
+                        .as_dict()
+                        .is_ok_and(|dict| keep_annotation(doc, dict, &kept))
+                })
+                .cloned()
+                .collect();
+            kept_annots.extend(annots.iter().filter_map(|annot| annot.as_reference().ok()));
+            page.set("Annots", annots);
+        }
+        updated.push((id, page));
+    }
+    for (id, page) in updated {
+        doc.objects.insert(id, Object::Dictionary(page));
+    }
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => pages.iter().map(|&id| Object::Reference(id)).collect::<Vec<_>>(),
+            "Count" => pages.len() as i64,
+        }),
+    );
+
+    let form = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| objects::get_dict(doc, catalog, b"AcroForm"))
+        .filter(|_| removing)
+        .map(|form| {
+            let fields: Vec<Object> = objects::get_array(doc, form, b"Fields")
+                .unwrap_or(&[])
+                .iter()
+                .filter(|field| field_in(doc, field, &kept_annots, 0))
+                .cloned()
+                .collect();
+            let mut form = form.clone();
+            form.set("Fields", fields);
+            form
+        });
+    let catalog = doc.catalog_mut().map_err(|e| e.to_string())?;
+    catalog.set("Pages", pages_id);
+    if let Some(form) = form {
+        catalog.set("AcroForm", form);
+    }
+
+    // Other objects may still refer to removed pages, e.g. through /P entries
+    // of their annotations; dangling references read as null
+    for id in all.iter().filter(|id| !kept.contains(id)) {
+        doc.objects.remove(id);
+    }
+    Ok(())
+}
+
+/// Copies `pages` (zero-based, in order) into a standalone document.
+///
+/// Named destinations are kept where they point at the selected pages. The
+/// outline is left out, since it describes the whole document.
+pub fn extract(source: &Document, pages: &[i32]) -> Result<Document, String> {
+    let mut doc = source.clone();
+    let all: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    let kept: Vec<ObjectId> = pages
+        .iter()
+        .map(|&page| {
+            all.get(page as usize)
+                .copied()
+                .ok_or_else(|| format!("Page {} is out of range", page + 1))
+        })
+        .collect::<Result<_, _>>()?;
+    let dests: BTreeMap<Vec<u8>, Object> = named_destinations(&doc)
+        .into_iter()
+        .filter(|(_, dest)| destination_page(&doc, dest).is_some_and(|page| kept.contains(&page)))
+        .collect();
+    set_pages(&mut doc, &kept)?;
+
+    let source_catalog = doc.catalog().map_err(|e| e.to_string())?;
+    let mut catalog = dictionary! { "Type" => "Catalog" };
+    for key in DOCUMENT_ENTRIES {
+        if let Ok(value) = source_catalog.get(key) {
+            catalog.set(key, value.clone());
+        }
+    }
+    if !dests.is_empty() {
+        catalog.set("Names", names_dictionary(dests));
+    }
+    let catalog_id = doc.add_object(catalog);
+    doc.trailer.set("Root", catalog_id);
+    doc.prune_objects();
+    Ok(doc)
+}
+
+/// Moves the objects of `other` into `doc` and returns its pages, in order,
+/// for the caller to place with `set_pages`. Form fields are added to the
+/// form of `doc`; the rest of the other catalog is dropped.
+pub fn import(doc: &mut Document, mut other: Document) -> Result<Vec<ObjectId>, String> {
+    other.renumber_objects_with(doc.max_id + 1);
+    let pages: Vec<ObjectId> = other.get_pages().into_values().collect();
+    let catalog = other.catalog().map_err(|e| e.to_string())?;
+    let fields = objects::get_dict(&other, catalog, b"AcroForm")
+        .and_then(|form| objects::get_array(&other, form, b"Fields"))
+        .map(|fields| fields.to_vec())
+        .unwrap_or_default();
+    let catalog_id = other
+        .trailer
+        .get(b"Root")
+        .and_then(Object::as_reference)
+        .ok();
+
+    doc.max_id = other.max_id;
+    for (id, object) in std::mem::take(&mut other.objects) {
+        if Some(id) != catalog_id {
+            doc.objects.insert(id, object);
+        }
+    }
+
+    if !fields.is_empty() {
+        let source_catalog = doc.catalog().map_err(|e| e.to_string())?;
+        let mut form = objects::get_dict(doc, source_catalog, b"AcroForm")
+            .cloned()
+            .unwrap_or_default();
+        let mut all = objects::get_array(doc, &form, b"Fields")
+            .map(|fields| fields.to_vec())
+            .unwrap_or_default();
+        all.extend(fields);
+        form.set("Fields", all);
+        doc.catalog_mut()
+            .map_err(|e| e.to_string())?
+            .set("AcroForm", form);
+    }
+    Ok(pages)
+}
+
+/// Concatenates the PDFs at `paths` into a new file at `output_path`.
+///
+/// Emits `merge-progress` events as sources are merged.
+#[tauri::command]
+pub async fn merge_documents(
+    app: AppHandle,
+    paths: Vec<String>,
+    output_path: String,
+) -> Result<WrittenDocument, String> {
+    if paths.len() < 2 {
+        return Err("Select at least two documents to merge".to_string());
+    }
+    for path in &paths {
+        scope::check(&app, Path::new(path))?;
+    }
+    scope::check_write(&app, Path::new(&output_path))?;
+    blocking(move || {
+        let total = paths.len();
+        let sources = paths
+            .iter()
+            .map(|path| load_document(Path::new(path)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut merged = merge(sources, |done| {
+            let _ = app.emit("merge-progress", MergeProgress { done, total });
+        })?;
+
+        let output = PathBuf::from(&output_path);
+        write_document(&mut merged, &output)?;
+        let page_count = merged.get_pages().len() as i32;
+        eprintln!(
+            "Merged {} documents ({} pages) into {}",
+            total,
+            page_count,
+            output.display()
+        );
+        Ok(WrittenDocument {
+            path: output_path,
+            page_count,
+        })
+    })
+    .await
+}
+
+/// Saves the pages selected by `ranges` (e.g. "1-5,8,10-end") as a new PDF.
+#[tauri::command]
+pub async fn extract_pages(
+    app: AppHandle,
+    doc_id: DocId,
+    ranges: String,
+    output_path: String,
+    store: State<'_, DocumentStore>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<WrittenDocument, String> {
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Copy).await?;
+    scope::check_write(&app, Path::new(&output_path))?;
+    let pages = parse_page_ranges(&ranges, doc.page_count)?;
+    blocking(move || {
+        let mut extracted = doc.with_objects(|objects| extract(objects, &pages))?;
+        write_document(&mut extracted, Path::new(&output_path))?;
+        eprintln!(
+            "Extracted {} pages of {} into {}",
+            pages.len(),
+            doc.path.display(),
+            output_path
+        );
+        Ok(WrittenDocument {
+            path: output_path,
+            page_count: pages.len() as i32,
+        })
+    })
+    .await
+}
+
+/// Combines JPEG and PNG files into a new PDF with one page per image.
+///
+/// `margin` is the space in points kept free around each image.
+#[tauri::command]
+pub async fn create_pdf_from_images(
+    app: AppHandle,
+    images: Vec<ImagePage>,
+    output_path: String,
+    page_size: Option<PageSize>,
+    margin: Option<f32>,
+) -> Result<WrittenDocument, String> {
+    if images.is_empty() {
+        return Err("Select at least one image".to_string());
+    }
+    if let Some(page) = images.iter().find(|page| page.rotation % 90 != 0) {
+        return Err(format!(
+            "Images can only be turned in steps of 90 degrees, not {}",
+            page.rotation
+        ));
+    }
+    let margin = margin.unwrap_or(0.0);
+    if !(0.0..=144.0).contains(&margin) {
+        return Err("Margins must be between 0 and 144 points".to_string());
+    }
+    for page in &images {
+        scope::check(&app, Path::new(&page.path))?;
+    }
+    scope::check_write(&app, Path::new(&output_path))?;
+    blocking(move || {
+        let mut doc = images_document(&images, page_size.unwrap_or_default(), margin)?;
+        write_document(&mut doc, Path::new(&output_path))?;
+        eprintln!("Created {} from {} images", output_path, images.len());
+        Ok(WrittenDocument {
+            path: output_path,
+            page_count: images.len() as i32,
+        })
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_are_read_in_order() {
+        assert_eq!(parse_page_ranges("1-3,5", 6), Ok(vec![0, 1, 2, 4]));
+        assert_eq!(parse_page_ranges("5-3", 6), Ok(vec![4, 3, 2]));
+        assert_eq!(parse_page_ranges("4-end", 5), Ok(vec![3, 4]));
+        assert_eq!(parse_page_ranges("END", 5), Ok(vec![4]));
+        // Pages already selected aren't repeated
+        assert_eq!(parse_page_ranges("1-3,2,1", 6), Ok(vec![0, 1, 2]));
+        assert_eq!(parse_page_ranges(" 1 , 3 - 4 ,, ", 6), Ok(vec![0, 2, 3]));
+    }
+
+    #[test]
+    fn bad_ranges_are_rejected() {
+        let out_of_range = "Page 0 is out of range (the document has 6 pages)";
+        assert_eq!(parse_page_ranges("0", 6), Err(out_of_range.to_string()));
+        assert!(parse_page_ranges("7", 6).is_err());
+        assert!(parse_page_ranges("2-9", 6).is_err());
+        assert!(parse_page_ranges("-2", 6).is_err());
+        assert_eq!(
+            parse_page_ranges("two", 6),
+            Err("\"two\" is not a page number".to_string())
+        );
+        assert_eq!(
+            parse_page_ranges(" , ", 6),
+            Err("No pages selected".to_string())
+        );
+    }
+}
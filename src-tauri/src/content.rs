@@ -0,0 +1,175 @@
+// Page content streams: transforms, and drawing on top of existing pages.
+
+use lopdf::content::{Content, Operation};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+
+use crate::objects;
+
+/// Affine transform [a b c d e f], as in PDF content streams.
+pub type Transform = [f32; 6];
+
+pub const IDENTITY: Transform = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+/// Combines two transforms, applying `m` first, then `n`.
+pub fn concat(m: Transform, n: Transform) -> Transform {
+    [
+        m[0] * n[0] + m[1] * n[2],
+        m[0] * n[1] + m[1] * n[3],
+        m[2] * n[0] + m[3] * n[2],
+        m[2] * n[1] + m[3] * n[3],
+        m[4] * n[0] + m[5] * n[2] + n[4],
+        m[4] * n[1] + m[5] * n[3] + n[5],
+    ]
+}
+
+/// Reads a transform from six numbers, such as the operands of `cm`.
+pub fn transform(doc: &Document, values: &[Object]) -> Option<Transform> {
+    let values: Vec<f32> = values
+        .iter()
+        .filter_map(|v| objects::number(objects::resolve(doc, v)))
+        .collect();
+    values.try_into().ok()
+}
+
+/// The smallest axis-aligned rectangle holding `rect` after `m`.
+pub fn transform_rect(rect: [f32; 4], m: Transform) -> [f32; 4] {
+    let [a, b, c, d, e, f] = m;
+    let corners = [
+        (rect[0], rect[1]),
+        (rect[2], rect[1]),
+        (rect[0], rect[3]),
+        (rect[2], rect[3]),
+    ]
+    .map(|(x, y)| (a * x + c * y + e, b * x + d * y + f));
+    let x0 = corners.iter().map(|p| p.0).fold(f32::INFINITY, f32::min);
+    let x1 = corners
+        .iter()
+        .map(|p| p.0)
+        .fold(f32::NEG_INFINITY, f32::max);
+    let y0 = corners.iter().map(|p| p.1).fold(f32::INFINITY, f32::min);
+    let y1 = corners
+        .iter()
+        .map(|p| p.1)
+        .fold(f32::NEG_INFINITY, f32::max);
+    [x0, y0, x1, y1]
+}
+
+/// The transform that undoes `m`, unless `m` squashes everything flat.
+pub fn invert(m: Transform) -> Option<Transform> {
+    let [a, b, c, d, e, f] = m;
+    let det = a * d - b * c;
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+    Some([
+        d / det,
+        -b / det,
+        -c / det,
+        a / det,
+        (c * f - d * e) / det,
+        (b * e - a * f) / det,
+    ])
+}
+
+/// The transform that fits `bbox`, after `matrix`, into `rect`. This is how
+/// viewers place annotation appearances (PDF 32000-1, 12.5.5).
+pub fn fit_rect(bbox: [f32; 4], matrix: Transform, rect: [f32; 4]) -> Transform {
+    let [x0, y0, x1, y1] = transform_rect(bbox, matrix);
+    let scale = |from: f32, to: f32| if from > 0.0 { to / from } else { 1.0 };
+    let sx = scale(x1 - x0, rect[2] - rect[0]);
+    let sy = scale(y1 - y0, rect[3] - rect[1]);
+    [sx, 0.0, 0.0, sy, rect[0] - x0 * sx, rect[1] - y0 * sy]
+}
+
+/// The transform from the page as it is shown, with the origin at the bottom
+/// left after /Rotate, to user space, along with the shown width and height.
+pub fn upright_transform(doc: &Document, page_id: ObjectId) -> (Transform, (f32, f32)) {
+    let [x0, y0, x1, y1] = objects::page_box(doc, page_id);
+    let rotate = objects::inherited(doc, page_id, b"Rotate")
+        .and_then(|r| r.as_i64().ok())
+        .unwrap_or(0)
+        .rem_euclid(360);
+    let (width, height) = (x1 - x0, y1 - y0);
+    match rotate {
+        90 => ([0.0, 1.0, -1.0, 0.0, x1, y0], (height, width)),
+        180 => ([-1.0, 0.0, 0.0, -1.0, x1, y1], (width, height)),
+        270 => ([0.0, -1.0, 1.0, 0.0, x0, y1], (height, width)),
+        _ => ([1.0, 0.0, 0.0, 1.0, x0, y0], (width, height)),
+    }
+}
+
+/// Adds `value` to a resource dictionary under a fresh name, and returns the
+/// name.
+pub fn insert_resource(
+    doc: &Document,
+    resources: &mut Dictionary,
+    category: &[u8],
+    prefix: &str,
+    value: Object,
+) -> String {
+    let mut entries = objects::get_dict(doc, resources, category)
+        .cloned()
+        .unwrap_or_default();
+    let name = (1..)
+        .map(|n| format!("{}{}", prefix, n))
+        .find(|name| !entries.has(name.as_bytes()))
+        .unwrap_or_default();
+    entries.set(name.clone(), value);
+    resources.set(category.to_vec(), entries);
+    name
+}
+
+/// Adds a resource such as an XObject or ExtGState to a page under a fresh
+/// name, and returns the name.
+pub fn add_resource(
+    doc: &mut Document,
+    page_id: ObjectId,
+    category: &[u8],
+    prefix: &str,
+    value: Object,
+) -> Result<String, String> {
+    // Resources may be inherited or shared with other pages, so the page gets
+    // its own copy
+    let mut resources = objects::inherited(doc, page_id, b"Resources")
+        .and_then(|r| r.as_dict().ok())
+        .cloned()
+        .unwrap_or_default();
+    let name = insert_resource(doc, &mut resources, category, prefix, value);
+    doc.get_dictionary_mut(page_id)
+        .map_err(|e| e.to_string())?
+        .set("Resources", resources);
+    Ok(name)
+}
+
+/// Draws `operations` on top of a page. The existing content is wrapped in
+/// q/Q, so graphics state it leaves behind doesn't affect the new drawing.
+pub fn append_content(
+    doc: &mut Document,
+    page_id: ObjectId,
+    operations: Vec<Operation>,
+) -> Result<(), String> {
+    let page = doc.get_dictionary(page_id).map_err(|e| e.to_string())?;
+    let mut contents = match page.get(b"Contents") {
+        Ok(Object::Array(parts)) => parts.clone(),
+        Ok(Object::Reference(id)) => match doc.get_object(*id) {
+            Ok(Object::Array(parts)) => parts.clone(),
+            _ => vec![Object::Reference(*id)],
+        },
+        _ => Vec::new(),
+    };
+
+    let mut after = b"Q\n".to_vec();
+    after.extend(
+        Content { operations }
+            .encode()
+            .map_err(|e| format!("Failed to write page content: {}", e))?,
+    );
+    let before = doc.add_object(Stream::new(Dictionary::new(), b"q\n".to_vec()));
+    let after = doc.add_object(Stream::new(Dictionary::new(), after));
+    contents.insert(0, Object::Reference(before));
+    contents.push(Object::Reference(after));
+    doc.get_dictionary_mut(page_id)
+        .map_err(|e| e.to_string())?
+        .set("Contents", contents);
+    Ok(())
+}
@@ -0,0 +1,190 @@
+// Native context menus for pages and tabs.
+//
+// The frontend asks for a menu with `show_context_menu` when the user right
+// clicks, saying what was clicked and where. The menu is the system's own, so
+// it looks native and can reach outside the window. "Show in Folder" is
+// done here (see `reveal.rs`); the other items are the frontend's to carry
+// out, and are sent back as a `context-menu-action` event naming the action
+// and what it applies to.
+
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tauri::{AppHandle, Emitter, LogicalPosition, Manager, State, Window};
+
+use crate::pdf::{DocId, DocumentStore};
+use crate::reveal;
+use crate::window_manager;
+
+// Item ids, all with this prefix so other menus' events are told apart
+const PREFIX: &str = "context:";
+const REVEAL: &str = "reveal";
+
+#[cfg(target_os = "macos")]
+pub const REVEAL_LABEL: &str = "Show in Finder";
+#[cfg(windows)]
+pub const REVEAL_LABEL: &str = "Show in Explorer";
+#[cfg(all(unix, not(target_os = "macos")))]
+pub const REVEAL_LABEL: &str = "Show in Folder";
+
+/// What was right clicked.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+pub enum MenuTarget {
+    #[serde(rename_all = "camelCase")]
+    Page {
+        doc_id: DocId,
+        /// Counted from 0
+        page: i32,
+        /// Whether there's selected text to copy
+        has_selection: bool,
+    },
+    #[serde(rename_all = "camelCase")]
+    Tab { doc_id: DocId },
+}
+
+impl MenuTarget {
+    fn doc_id(&self) -> DocId {
+        match *self {
+            MenuTarget::Page { doc_id, .. } | MenuTarget::Tab { doc_id } => doc_id,
+        }
+    }
+
+    fn page(&self) -> Option<i32> {
+        match *self {
+            MenuTarget::Page { page, .. } => Some(page),
+            MenuTarget::Tab { .. } => None,
+        }
+    }
+}
+
+/// The items the frontend carries out.
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum MenuAction {
+    Copy,
+    RotateLeft,
+    RotateRight,
+    ExtractPage,
+    CloseTab,
+}
+
+const ACTIONS: &[(&str, MenuAction)] = &[
+    ("copy", MenuAction::Copy),
+    ("rotate-left", MenuAction::RotateLeft),
+    ("rotate-right", MenuAction::RotateRight),
+    ("extract-page", MenuAction::ExtractPage),
+    ("close-tab", MenuAction::CloseTab),
+];
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ContextMenuAction {
+    pub action: MenuAction,
+    pub doc_id: DocId,
+    pub page: Option<i32>,
+}
+
+/// The target of the menu shown last, managed as Tauri state.
+#[derive(Default)]
+pub struct ContextMenus {
+    target: Mutex<Option<MenuTarget>>,
+}
+
+fn item(
+    app: &AppHandle,
+    id: &str,
+    text: &str,
+    enabled: bool,
+) -> tauri::Result<MenuItem<tauri::Wry>> {
+    MenuItem::with_id(
+        app,
+        format!("{}{}", PREFIX, id),
+        text,
+        enabled,
+        None::<&str>,
+    )
+}
+
+fn build_menu(app: &AppHandle, target: MenuTarget) -> tauri::Result<Menu<tauri::Wry>> {
+    let separator = PredefinedMenuItem::separator(app)?;
+    let reveal = item(app, REVEAL, REVEAL_LABEL, true)?;
+    match target {
+        MenuTarget::Page { has_selection, .. } => Menu::with_items(
+            app,
+            &[
+                &item(app, "copy", "Copy", has_selection)?,
+                &separator,
+                &item(app, "rotate-left", "Rotate Left", true)?,
+                &item(app, "rotate-right", "Rotate Right", true)?,
+                &item(app, "extract-page", "Extract Page…", true)?,
+                &PredefinedMenuItem::separator(app)?,
+                &reveal,
+            ],
+        ),
+        MenuTarget::Tab { .. } => Menu::with_items(
+            app,
+            &[
+                &reveal,
+                &separator,
+                &item(app, "close-tab", "Close Tab", true)?,
+            ],
+        ),
+    }
+}
+
+/// Shows the context menu for `target` at `x`, `y` in the window's CSS
+/// pixels, or at the mouse pointer.
+#[tauri::command]
+pub async fn show_context_menu(
+    window: Window,
+    target: MenuTarget,
+    x: Option<f64>,
+    y: Option<f64>,
+    store: State<'_, DocumentStore>,
+    menus: State<'_, ContextMenus>,
+) -> Result<(), String> {
+    store.get(target.doc_id())?;
+    let menu = build_menu(window.app_handle(), target).map_err(|e| e.to_string())?;
+    *menus.target.lock().unwrap() = Some(target);
+    match (x, y) {
+        (Some(x), Some(y)) => window.popup_menu_at(&menu, LogicalPosition::new(x, y)),
+        _ => window.popup_menu(&menu),
+    }
+    .map_err(|e| format!("Failed to show the context menu: {}", e))
+}
+
+/// Carries out a context menu item. The app's menu event handler.
+pub fn menu_event(app: &AppHandle, event: MenuEvent) {
+    let Some(id) = event.id().as_ref().strip_prefix(PREFIX) else {
+        return;
+    };
+    let Some(target) = *app.state::<ContextMenus>().target.lock().unwrap() else {
+        return;
+    };
+    if id == REVEAL {
+        // Closed since the menu was shown
+        let Ok(doc) = app.state::<DocumentStore>().get(target.doc_id()) else {
+            return;
+        };
+        let (app, path) = (app.clone(), doc.path.to_string_lossy().into_owned());
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = reveal::reveal_in_file_manager(app, path).await {
+                eprintln!("{}", e);
+            }
+        });
+        return;
+    }
+    if let Some((_, action)) = ACTIONS.iter().find(|(name, _)| *name == id) {
+        let _ = app.emit_to(
+            window_manager::owner(app, target.doc_id()),
+            "context-menu-action",
+            ContextMenuAction {
+                action: *action,
+                doc_id: target.doc_id(),
+                page: target.page(),
+            },
+        );
+    }
+}
@@ -0,0 +1,301 @@
+// Converting documents to Markdown, HTML or plain text.
+//
+// PDFs don't say which text is a heading or a list, so the structure is
+// inferred from the text blocks in reading order: blocks set noticeably larger
+// than the body text become headings, larger sizes ranking higher, and blocks
+// whose lines start with bullets or numbers become lists. Everything else is a
+// paragraph. Pages are separated by a rule, or a form feed in plain text.
+
+use std::path::Path;
+
+use serde::Deserialize;
+use tauri::{AppHandle, State};
+
+use crate::engine::Engine;
+use crate::pdf::{blocking, DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scope;
+use crate::text::{page_paragraphs, Paragraph};
+
+// How much larger than the body text a heading is
+const HEADING_RATIO: f32 = 1.15;
+// Blocks longer than this are never headings
+const MAX_HEADING_LINES: usize = 3;
+const MAX_HEADING_CHARS: usize = 200;
+
+const BULLETS: [char; 8] = ['•', '◦', '▪', '‣', '·', '–', '-', '*'];
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum ExportFormat {
+    Markdown,
+    Html,
+    Text,
+}
+
+enum Element {
+    Heading(usize, String),
+    List { ordered: bool, items: Vec<String> },
+    Paragraph(String),
+    PageBreak,
+}
+
+// Font sizes in half points, so that sizes differing by rounding compare equal
+fn size_key(size: f32) -> i32 {
+    (size * 2.0).round() as i32
+}
+
+// Length of the bullet or number starting `line`, with its trailing space,
+// and whether it is a number
+fn list_marker(line: &str) -> Option<(usize, bool)> {
+    let trimmed = line.trim_start();
+    let indent = line.len() - trimmed.len();
+    let mut chars = trimmed.char_indices();
+    let (_, first) = chars.next()?;
+    if BULLETS.contains(&first) {
+        let (at, next) = chars.next()?;
+        return next
+            .is_whitespace()
+            .then_some((indent + at + next.len_utf8(), false));
+    }
+    let label = trimmed
+        .find(['.', ')'])
+        .filter(|&end| end > 0 && end <= 3)?;
+    let counter = &trimmed[..label];
+    let numbered = counter.chars().all(|c| c.is_ascii_digit())
+        || (counter.len() == 1 && counter.chars().all(|c| c.is_ascii_lowercase()));
+    let after = trimmed[label + 1..].chars().next()?;
+    (numbered && after.is_whitespace()).then_some((indent + label + 1 + after.len_utf8(), true))
+}
+
+// Splits a block whose lines start with markers into items, joining the lines
+// that continue an item
+fn list_items(paragraph: &Paragraph) -> Vec<String> {
+    let mut items: Vec<Vec<String>> = Vec::new();
+    for line in &paragraph.lines {
+        match list_marker(line) {
+            Some((len, _)) => items.push(vec![line[len..].trim().to_string()]),
+            None => match items.last_mut() {
+                Some(item) => item.push(line.clone()),
+                None => items.push(vec![line.clone()]),
+            },
+        }
+    }
+    items
+        .into_iter()
+        .map(|lines| {
+            Paragraph {
+                lines,
+                font_size: paragraph.font_size,
+            }
+            .text()
+            .replace('\n', " ")
+        })
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn structure(pages: &[Vec<Paragraph>]) -> Vec<Element> {
+    // The body size is the one most of the text is set in
+    let mut sizes: Vec<(i32, usize)> = Vec::new();
+    for paragraph in pages.iter().flatten() {
+        let key = size_key(paragraph.font_size);
+        let chars = paragraph.lines.iter().map(|l| l.chars().count()).sum();
+        match sizes.iter_mut().find(|(size, _)| *size == key) {
+            Some((_, count)) => *count += chars,
+            None => sizes.push((key, chars)),
+        }
+    }
+    let body = sizes
+        .iter()
+        .max_by_key(|(_, count)| *count)
+        .map_or(0.0, |(size, _)| *size as f32 / 2.0);
+
+    let is_heading = |paragraph: &Paragraph| {
+        paragraph.font_size > body * HEADING_RATIO
+            && paragraph.lines.len() <= MAX_HEADING_LINES
+            && paragraph.text().chars().count() <= MAX_HEADING_CHARS
+    };
+    let mut heading_sizes: Vec<i32> = pages
+        .iter()
+        .flatten()
+        .filter(|p| is_heading(p))
+        .map(|p| size_key(p.font_size))
+        .collect();
+    heading_sizes.sort_unstable_by(|a, b| b.cmp(a));
+    heading_sizes.dedup();
+
+    let mut elements = Vec::new();
+    for (index, paragraphs) in pages.iter().enumerate() {
+        if index > 0 {
+            elements.push(Element::PageBreak);
+        }
+        for paragraph in paragraphs {
+            let text = paragraph.text().replace('\n', " ");
+            if is_heading(paragraph) {
+                let key = size_key(paragraph.font_size);
+                let rank = heading_sizes.iter().position(|&s| s == key).unwrap_or(0);
+                elements.push(Element::Heading((rank + 1).min(6), text));
+                continue;
+            }
+            let Some((_, numbered)) = paragraph.lines.first().and_then(|l| list_marker(l)) else {
+                elements.push(Element::Paragraph(text));
+                continue;
+            };
+            // Items are often separate blocks, so they join the list before
+            let new_items = list_items(paragraph);
+            match elements.last_mut() {
+                Some(Element::List { ordered, items }) if *ordered == numbered => {
+                    items.extend(new_items)
+                }
+                _ => elements.push(Element::List {
+                    ordered: numbered,
+                    items: new_items,
+                }),
+            }
+        }
+    }
+    elements
+}
+
+/// Escapes what Markdown would read as formatting.
+pub fn escape_markdown(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    if out.starts_with(['#', '>', '-', '+']) {
+        out.insert(0, '\\');
+    } else if let Some((_, true)) = list_marker(&out) {
+        if let Some(at) = out.find(['.', ')']) {
+            out.insert(at, '\\');
+        }
+    }
+    out
+}
+
+fn to_markdown(elements: &[Element]) -> String {
+    let blocks: Vec<String> = elements
+        .iter()
+        .map(|element| match element {
+            Element::Heading(level, text) => {
+                format!("{} {}", "#".repeat(*level), escape_markdown(text))
+            }
+            Element::List { ordered, items } => items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| match ordered {
+                    true => format!("{}. {}", i + 1, escape_markdown(item)),
+                    false => format!("- {}", escape_markdown(item)),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Element::Paragraph(text) => escape_markdown(text),
+            Element::PageBreak => "---".to_string(),
+        })
+        .collect();
+    blocks.join("\n\n") + "\n"
+}
+
+fn to_html(elements: &[Element], title: &str) -> String {
+    let escape = |text: &str| quick_xml::escape::escape(text).into_owned();
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+         <style>hr.page-break {{ break-after: page; }}</style>\n</head>\n<body>\n",
+        escape(title)
+    );
+    for element in elements {
+        match element {
+            Element::Heading(level, text) => {
+                out.push_str(&format!("<h{0}>{1}</h{0}>\n", level, escape(text)))
+            }
+            Element::List { ordered, items } => {
+                let tag = if *ordered { "ol" } else { "ul" };
+                out.push_str(&format!("<{}>\n", tag));
+                for item in items {
+                    out.push_str(&format!("<li>{}</li>\n", escape(item)));
+                }
+                out.push_str(&format!("</{}>\n", tag));
+            }
+            Element::Paragraph(text) => out.push_str(&format!("<p>{}</p>\n", escape(text))),
+            Element::PageBreak => out.push_str("<hr class=\"page-break\">\n"),
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn to_text(elements: &[Element]) -> String {
+    let blocks: Vec<String> = elements
+        .iter()
+        .map(|element| match element {
+            Element::Heading(_, text) | Element::Paragraph(text) => text.clone(),
+            Element::List { ordered, items } => items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| match ordered {
+                    true => format!("{}. {}", i + 1, item),
+                    false => format!("• {}", item),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Element::PageBreak => "\u{c}".to_string(),
+        })
+        .collect();
+    blocks.join("\n\n") + "\n"
+}
+
+/// Converts the text of a document to Markdown, HTML or plain text and saves
+/// it at `path`.
+#[tauri::command]
+pub async fn export_document(
+    app: AppHandle,
+    doc_id: DocId,
+    format: ExportFormat,
+    path: String,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<(), String> {
+    scope::check_write(&app, Path::new(&path))?;
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Copy).await?;
+    let title = doc.path.file_stem().map_or("Document".into(), |stem| {
+        stem.to_string_lossy().into_owned()
+    });
+    let page_count = doc.page_count;
+    let pages = engine
+        .run(move |worker| {
+            let handle = worker.document(&doc)?;
+            (0..page_count)
+                .map(|index| {
+                    handle
+                        .load_page(index)
+                        .and_then(|p| p.to_text_page(mupdf::TextPageOptions::empty()))
+                        .map(|text_page| page_paragraphs(&text_page))
+                        .map_err(|e| e.to_string())
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .await?;
+
+    blocking(move || {
+        let elements = structure(&pages);
+        let output = match format {
+            ExportFormat::Markdown => to_markdown(&elements),
+            ExportFormat::Html => to_html(&elements, &title),
+            ExportFormat::Text => to_text(&elements),
+        };
+        std::fs::write(Path::new(&path), output)
+            .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        eprintln!(
+            "Exported {} pages of document {} to {}",
+            page_count, doc_id, path
+        );
+        Ok(())
+    })
+    .await
+}
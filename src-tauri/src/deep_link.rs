@@ -0,0 +1,134 @@
+// nanodoc:// links, which open a document at a given page and zoom.
+//
+// `nanodoc://open?path=/Users/me/paper.pdf&page=12&zoom=150` opens the file,
+// goes to page 12 (counted from 1) and zooms to 150%. Links arrive the way
+// files do (see `launch.rs`): on the command line on Windows and Linux,
+// handed to the running instance if there is one, and as an Apple Event on
+// macOS. macOS learns of the scheme from `Info.plist`; on Windows and Linux
+// it's registered for the current user at every launch, so it follows the app
+// when it moves.
+//
+// Any web page can open a link, so links only open existing PDFs named by an
+// absolute path.
+
+use std::path::PathBuf;
+
+use tauri::Url;
+
+use crate::launch::Location;
+
+pub const SCHEME: &str = "nanodoc";
+
+/// Whether a command line argument is a link rather than a file.
+pub fn is_link(arg: &str) -> bool {
+    arg.get(..SCHEME.len() + 1)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(&format!("{}:", SCHEME)))
+}
+
+/// The file a link opens and where to go in it.
+pub fn parse(link: &str) -> Result<Location, String> {
+    let url = Url::parse(link).map_err(|e| format!("Invalid link {}: {}", link, e))?;
+    if url.scheme() != SCHEME || url.host_str() != Some("open") {
+        return Err(format!("Unsupported link {}", link));
+    }
+    let (mut path, mut page, mut zoom) = (None, None, None);
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "path" => path = Some(PathBuf::from(value.as_ref())),
+            "page" => page = value.parse::<u32>().ok().filter(|page| *page > 0),
+            "zoom" => {
+                zoom = value
+                    .trim_end_matches('%')
+                    .parse::<f32>()
+                    .ok()
+                    // The viewer keeps it within the zoom levels it has
+                    .filter(|zoom| zoom.is_finite() && *zoom > 0.0)
+            }
+            _ => {}
+        }
+    }
+    let path = path.ok_or_else(|| format!("Link {} names no file", link))?;
+    let named_pdf = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"));
+    if !path.is_absolute() || !named_pdf || !path.is_file() {
+        return Err(format!("Link {} doesn't name a PDF file", link));
+    }
+    Ok(Location { path, page, zoom })
+}
+
+/// Registers the scheme with the system for the current user.
+pub fn register() {
+    if let Err(e) = platform::register() {
+        eprintln!("Failed to register {}:// links: {}", SCHEME, e);
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::SCHEME;
+    use crate::registry;
+
+    pub fn register() -> Result<(), String> {
+        let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+        let key = format!("Software\\Classes\\{}", SCHEME);
+        registry::set_string(&key, "", "URL:Nanodoc document link")?;
+        registry::set_string(&key, "URL Protocol", "")?;
+        registry::set_string(
+            &format!("{}\\shell\\open\\command", key),
+            "",
+            &format!("\"{}\" \"%1\"", exe.display()),
+        )
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use std::process::Command;
+
+    use super::SCHEME;
+
+    const DESKTOP_FILE: &str = "nanodoc-url-handler.desktop";
+
+    pub fn register() -> Result<(), String> {
+        // An AppImage runs from a mount that changes at every launch
+        let exe = match std::env::var_os("APPIMAGE") {
+            Some(image) => image.into(),
+            None => std::env::current_exe().map_err(|e| e.to_string())?,
+        };
+        let dir = dirs::data_dir()
+            .ok_or("No data directory")?
+            .join("applications");
+        let file = dir.join(DESKTOP_FILE);
+        let entry = format!(
+            "[Desktop Entry]\nType=Application\nName=Nanodoc\nExec=\"{}\" %u\n\
+             NoDisplay=true\nMimeType=x-scheme-handler/{};\n",
+            exe.display(),
+            SCHEME
+        );
+        if std::fs::read_to_string(&file).is_ok_and(|current| current == entry) {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        std::fs::write(&file, entry)
+            .map_err(|e| format!("Failed to write {}: {}", file.display(), e))?;
+        let status = Command::new("xdg-mime")
+            .args(["default", DESKTOP_FILE])
+            .arg(format!("x-scheme-handler/{}", SCHEME))
+            .status()
+            .map_err(|e| format!("Failed to run xdg-mime: {}", e))?;
+        if !status.success() {
+            return Err(format!("xdg-mime failed ({})", status));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    // The scheme is declared in Info.plist
+    pub fn register() -> Result<(), String> {
+        Ok(())
+    }
+}
@@ -0,0 +1,323 @@
+// Making Nanodoc the default app for PDFs.
+//
+// On macOS Launch Services is told directly. Windows doesn't let apps make
+// themselves the default: Nanodoc registers as a PDF handler for the current
+// user and opens the Default apps page of Settings, where the user confirms.
+// On Linux the handler is set with `xdg-mime`, for the installed desktop
+// entry or, when running unpackaged, one of the user's own.
+
+use serde::Serialize;
+use tauri::AppHandle;
+
+use crate::pdf::blocking;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DefaultViewer {
+    /// Nanodoc opens PDFs now
+    pub is_default: bool,
+    /// The system still asks the user to choose Nanodoc, as Windows does
+    pub needs_confirmation: bool,
+}
+
+/// Whether Nanodoc is the default app for PDFs.
+#[tauri::command]
+pub async fn is_default_pdf_viewer(app: AppHandle) -> Result<bool, String> {
+    let identifier = app.config().identifier.clone();
+    blocking(move || Ok(platform::is_default(&identifier))).await
+}
+
+/// Makes Nanodoc the default app for PDFs, or asks the system to let the user
+/// do so.
+#[tauri::command]
+pub async fn set_default_pdf_viewer(app: AppHandle) -> Result<DefaultViewer, String> {
+    let identifier = app.config().identifier.clone();
+    blocking(move || {
+        let needs_confirmation = platform::set_default(&identifier)?;
+        let is_default = platform::is_default(&identifier);
+        eprintln!(
+            "Set as the default PDF viewer: {}",
+            match (is_default, needs_confirmation) {
+                (true, _) => "done",
+                (false, true) => "waiting for the user",
+                (false, false) => "ignored by the system",
+            }
+        );
+        Ok(DefaultViewer {
+            is_default,
+            needs_confirmation: needs_confirmation && !is_default,
+        })
+    })
+    .await
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::ffi::{c_char, c_void, CStr};
+
+    const PDF_TYPE: &str = "com.adobe.pdf";
+    const UTF8: u32 = 0x0800_0100;
+    const ROLES_ALL: u32 = 0xffff_ffff;
+
+    #[link(name = "CoreFoundation", kind = "framework")]
+    extern "C" {
+        fn CFStringCreateWithBytes(
+            allocator: *const c_void,
+            bytes: *const u8,
+            len: isize,
+            encoding: u32,
+            external: u8,
+        ) -> *const c_void;
+        fn CFStringGetCString(
+            string: *const c_void,
+            buffer: *mut c_char,
+            size: isize,
+            encoding: u32,
+        ) -> u8;
+        fn CFRelease(object: *const c_void);
+    }
+
+    #[link(name = "CoreServices", kind = "framework")]
+    extern "C" {
+        fn LSSetDefaultRoleHandlerForContentType(
+            content_type: *const c_void,
+            role: u32,
+            handler: *const c_void,
+        ) -> i32;
+        fn LSCopyDefaultRoleHandlerForContentType(
+            content_type: *const c_void,
+            role: u32,
+        ) -> *const c_void;
+    }
+
+    // A CFString, released when dropped
+    struct CfString(*const c_void);
+
+    impl CfString {
+        fn new(text: &str) -> Self {
+            // SAFETY: the bytes are copied into the new string
+            CfString(unsafe {
+                CFStringCreateWithBytes(
+                    std::ptr::null(),
+                    text.as_ptr(),
+                    text.len() as isize,
+                    UTF8,
+                    0,
+                )
+            })
+        }
+
+        fn read(&self) -> Option<String> {
+            let mut buffer = [0 as c_char; 512];
+            // SAFETY: the buffer's size is passed along
+            let copied = unsafe {
+                CFStringGetCString(self.0, buffer.as_mut_ptr(), buffer.len() as isize, UTF8)
+            };
+            (copied != 0).then(|| {
+                unsafe { CStr::from_ptr(buffer.as_ptr()) }
+                    .to_string_lossy()
+                    .into_owned()
+            })
+        }
+    }
+
+    impl Drop for CfString {
+        fn drop(&mut self) {
+            if !self.0.is_null() {
+                unsafe { CFRelease(self.0) };
+            }
+        }
+    }
+
+    pub fn is_default(identifier: &str) -> bool {
+        let content_type = CfString::new(PDF_TYPE);
+        let handler =
+            CfString(unsafe { LSCopyDefaultRoleHandlerForContentType(content_type.0, ROLES_ALL) });
+        !handler.0.is_null()
+            && handler
+                .read()
+                .is_some_and(|handler| handler.eq_ignore_ascii_case(identifier))
+    }
+
+    pub fn set_default(identifier: &str) -> Result<bool, String> {
+        let (content_type, handler) = (CfString::new(PDF_TYPE), CfString::new(identifier));
+        let status =
+            unsafe { LSSetDefaultRoleHandlerForContentType(content_type.0, ROLES_ALL, handler.0) };
+        if status != 0 {
+            return Err(format!(
+                "Launch Services refused to make {} the PDF viewer (error {})",
+                identifier, status
+            ));
+        }
+        // Recent versions of macOS have the user confirm the change
+        Ok(true)
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::ffi::c_void;
+    use std::ptr;
+
+    use crate::registry;
+
+    const PROG_ID: &str = "Nanodoc.Document";
+    const APP_NAME: &str = "Nanodoc";
+    const CAPABILITIES: &str = "Software\\Nanodoc\\Capabilities";
+    const DEFAULT_APPS: &str = "ms-settings:defaultapps?registeredAppUser=Nanodoc";
+    const SHCNE_ASSOCCHANGED: i32 = 0x0800_0000;
+    const SW_SHOWNORMAL: i32 = 1;
+
+    #[link(name = "shell32")]
+    extern "system" {
+        fn SHChangeNotify(event: i32, flags: u32, item1: *const c_void, item2: *const c_void);
+        fn ShellExecuteW(
+            window: *mut c_void,
+            operation: *const u16,
+            file: *const u16,
+            parameters: *const u16,
+            directory: *const u16,
+            show: i32,
+        ) -> isize;
+    }
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(Some(0)).collect()
+    }
+
+    pub fn is_default(_identifier: &str) -> bool {
+        registry::get_string(
+            "Software\\Microsoft\\Windows\\CurrentVersion\\Explorer\\FileExts\\.pdf\\UserChoice",
+            "ProgId",
+        )
+        .is_some_and(|prog_id| prog_id.eq_ignore_ascii_case(PROG_ID))
+    }
+
+    fn register() -> Result<(), String> {
+        let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+        let class = format!("Software\\Classes\\{}", PROG_ID);
+        registry::set_string(&class, "", "PDF Document")?;
+        registry::set_string(
+            &format!("{}\\DefaultIcon", class),
+            "",
+            &format!("\"{}\",0", exe.display()),
+        )?;
+        registry::set_string(
+            &format!("{}\\shell\\open\\command", class),
+            "",
+            &format!("\"{}\" \"%1\"", exe.display()),
+        )?;
+        registry::set_string("Software\\Classes\\.pdf\\OpenWithProgids", PROG_ID, "")?;
+        registry::set_string(CAPABILITIES, "ApplicationName", APP_NAME)?;
+        registry::set_string(CAPABILITIES, "ApplicationDescription", "View and edit PDFs")?;
+        registry::set_string(
+            &format!("{}\\FileAssociations", CAPABILITIES),
+            ".pdf",
+            PROG_ID,
+        )?;
+        registry::set_string("Software\\RegisteredApplications", APP_NAME, CAPABILITIES)?;
+        unsafe { SHChangeNotify(SHCNE_ASSOCCHANGED, 0, ptr::null(), ptr::null()) };
+        Ok(())
+    }
+
+    pub fn set_default(_identifier: &str) -> Result<bool, String> {
+        register()?;
+        let (operation, page) = (wide("open"), wide(DEFAULT_APPS));
+        let result = unsafe {
+            ShellExecuteW(
+                ptr::null_mut(),
+                operation.as_ptr(),
+                page.as_ptr(),
+                ptr::null(),
+                ptr::null(),
+                SW_SHOWNORMAL,
+            )
+        };
+        // Values up to 32 are errors
+        if result <= 32 {
+            return Err(format!(
+                "Failed to open the Default apps settings (error {})",
+                result
+            ));
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    const PDF_TYPE: &str = "application/pdf";
+    // The entries packages install, and the one written when there's none
+    const INSTALLED: &[&str] = &["Nanodoc.desktop", "nanodoc.desktop"];
+    const OWN: &str = "nanodoc-pdf.desktop";
+
+    // The directories desktop entries are in, the user's first
+    fn application_dirs() -> Vec<PathBuf> {
+        let system = std::env::var("XDG_DATA_DIRS")
+            .ok()
+            .filter(|dirs| !dirs.is_empty())
+            .unwrap_or_else(|| "/usr/local/share:/usr/share".into());
+        dirs::data_dir()
+            .into_iter()
+            .chain(system.split(':').map(PathBuf::from))
+            .map(|dir| dir.join("applications"))
+            .collect()
+    }
+
+    fn current_default() -> Option<String> {
+        let output = Command::new("xdg-mime")
+            .args(["query", "default", PDF_TYPE])
+            .output()
+            .ok()?;
+        let name = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+        (output.status.success() && !name.is_empty()).then_some(name)
+    }
+
+    pub fn is_default(_identifier: &str) -> bool {
+        current_default().is_some_and(|name| name == OWN || INSTALLED.contains(&name.as_str()))
+    }
+
+    // The installed entry, or one written for the running executable
+    fn desktop_entry() -> Result<String, String> {
+        for dir in application_dirs() {
+            if let Some(name) = INSTALLED.iter().find(|name| dir.join(name).is_file()) {
+                return Ok(name.to_string());
+            }
+        }
+        // An AppImage runs from a mount that changes at every launch
+        let exe = match std::env::var_os("APPIMAGE") {
+            Some(image) => image.into(),
+            None => std::env::current_exe().map_err(|e| e.to_string())?,
+        };
+        let dir = dirs::data_dir()
+            .ok_or("No data directory")?
+            .join("applications");
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let file = dir.join(OWN);
+        let entry = format!(
+            "[Desktop Entry]\nType=Application\nName=Nanodoc\nExec=\"{}\" %F\n\
+             NoDisplay=true\nMimeType={};\n",
+            exe.display(),
+            PDF_TYPE
+        );
+        std::fs::write(&file, entry)
+            .map_err(|e| format!("Failed to write {}: {}", file.display(), e))?;
+        Ok(OWN.into())
+    }
+
+    pub fn set_default(_identifier: &str) -> Result<bool, String> {
+        let entry = desktop_entry()?;
+        let status = Command::new("xdg-mime")
+            .args(["default", &entry, PDF_TYPE])
+            .status()
+            .map_err(|e| format!("Failed to run xdg-mime: {}", e))?;
+        if !status.success() {
+            return Err(format!("xdg-mime failed ({})", status));
+        }
+        Ok(false)
+    }
+}
@@ -0,0 +1,227 @@
+// Desktop integration for Nanodoc run from an AppImage or a tarball.
+//
+// Packages install a desktop entry and icons, which put Nanodoc in the
+// application menu and in "Open With" for PDFs. Run unpackaged, Nanodoc has
+// none, so on the first launch it installs its own for the current user:
+// `nanodoc.desktop`, declaring application/pdf, under
+// `~/.local/share/applications`, and its icon in the hicolor theme. Making it
+// the default PDF viewer is left to the user (see `default_viewer.rs`).
+// `uninstall_desktop_integration` removes them again, and they aren't put
+// back on later launches; `install_desktop_integration` does. Elsewhere the
+// system learns of the app from its bundle, and all of this does nothing.
+
+use std::path::PathBuf;
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+use crate::pdf::blocking;
+
+// Written once the first launch installed the integration, so an uninstall
+// sticks
+const MARKER_FILE: &str = "desktop-integration";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DesktopIntegration {
+    /// Nanodoc's own desktop entry is installed
+    pub installed: bool,
+    /// A package installed one, so Nanodoc needs none of its own
+    pub packaged: bool,
+}
+
+fn marker_file(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join(MARKER_FILE))
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))
+}
+
+fn status() -> DesktopIntegration {
+    DesktopIntegration {
+        installed: platform::installed(),
+        packaged: platform::packaged(),
+    }
+}
+
+/// Installs the integration on the first unpackaged launch.
+pub fn setup(app: &AppHandle) {
+    let Ok(marker) = marker_file(app) else {
+        return;
+    };
+    if marker.exists() || platform::packaged() {
+        return;
+    }
+    std::thread::spawn(move || {
+        if let Err(e) = platform::install() {
+            eprintln!("Failed to install the desktop entry: {}", e);
+            return;
+        }
+        eprintln!("Installed the desktop entry");
+        let written = marker
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&marker, b""));
+        if let Err(e) = written {
+            eprintln!("Failed to write {}: {}", marker.display(), e);
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn get_desktop_integration() -> Result<DesktopIntegration, String> {
+    blocking(|| Ok(status())).await
+}
+
+/// Installs Nanodoc's desktop entry and icon for the current user.
+#[tauri::command]
+pub async fn install_desktop_integration() -> Result<DesktopIntegration, String> {
+    blocking(|| {
+        platform::install()?;
+        Ok(status())
+    })
+    .await
+}
+
+/// Removes Nanodoc's desktop entry and icon, which stay removed.
+#[tauri::command]
+pub async fn uninstall_desktop_integration(app: AppHandle) -> Result<DesktopIntegration, String> {
+    let marker = marker_file(&app)?;
+    blocking(move || {
+        platform::uninstall()?;
+        if let Some(dir) = marker.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        std::fs::write(&marker, b"")
+            .map_err(|e| format!("Failed to write {}: {}", marker.display(), e))?;
+        Ok(status())
+    })
+    .await
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use std::path::{Path, PathBuf};
+    use std::process::{Command, Stdio};
+
+    const DESKTOP_FILE: &str = "nanodoc.desktop";
+    // The names packages install their entry under
+    const PACKAGED: &[&str] = &["Nanodoc.desktop", "nanodoc.desktop"];
+    const ICON_NAME: &str = "nanodoc";
+    const ICONS: &[(&str, &[u8])] = &[
+        ("32x32", include_bytes!("../icons/32x32.png")),
+        ("128x128", include_bytes!("../icons/128x128.png")),
+        ("256x256", include_bytes!("../icons/128x128@2x.png")),
+    ];
+
+    fn data_dir() -> Result<PathBuf, String> {
+        dirs::data_dir().ok_or_else(|| "No data directory".into())
+    }
+
+    fn desktop_file() -> Result<PathBuf, String> {
+        Ok(data_dir()?.join("applications").join(DESKTOP_FILE))
+    }
+
+    fn icon_file(size: &str) -> Result<PathBuf, String> {
+        Ok(data_dir()?
+            .join("icons/hicolor")
+            .join(size)
+            .join("apps")
+            .join(format!("{}.png", ICON_NAME)))
+    }
+
+    // Refreshes the caches desktops read entries and icons from. Not every
+    // system has the tools, and entries are found without them
+    fn refresh(data_dir: &Path) {
+        let _ = Command::new("update-desktop-database")
+            .arg(data_dir.join("applications"))
+            .stderr(Stdio::null())
+            .status();
+        let _ = Command::new("gtk-update-icon-cache")
+            .args(["--force", "--ignore-theme-index"])
+            .arg(data_dir.join("icons/hicolor"))
+            .stderr(Stdio::null())
+            .status();
+    }
+
+    fn write(file: &Path, data: &[u8]) -> Result<(), String> {
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        std::fs::write(file, data).map_err(|e| format!("Failed to write {}: {}", file.display(), e))
+    }
+
+    pub fn packaged() -> bool {
+        let system = std::env::var("XDG_DATA_DIRS")
+            .ok()
+            .filter(|dirs| !dirs.is_empty())
+            .unwrap_or_else(|| "/usr/local/share:/usr/share".into());
+        // An AppImage is never installed by a package, whatever else is
+        std::env::var_os("APPIMAGE").is_none()
+            && system.split(':').any(|dir| {
+                PACKAGED
+                    .iter()
+                    .any(|name| Path::new(dir).join("applications").join(name).is_file())
+            })
+    }
+
+    pub fn installed() -> bool {
+        desktop_file().is_ok_and(|file| file.is_file())
+    }
+
+    pub fn install() -> Result<(), String> {
+        // An AppImage runs from a mount that changes at every launch
+        let exe = match std::env::var_os("APPIMAGE") {
+            Some(image) => image.into(),
+            None => std::env::current_exe().map_err(|e| e.to_string())?,
+        };
+        for (size, data) in ICONS {
+            write(&icon_file(size)?, data)?;
+        }
+        let entry = format!(
+            "[Desktop Entry]\nType=Application\nName=Nanodoc\nGenericName=PDF Editor\n\
+             Comment=View and edit PDFs\nExec=\"{}\" %F\nIcon={}\nTerminal=false\n\
+             Categories=Office;Viewer;\nMimeType=application/pdf;\n",
+            exe.display(),
+            ICON_NAME
+        );
+        write(&desktop_file()?, entry.as_bytes())?;
+        refresh(&data_dir()?);
+        Ok(())
+    }
+
+    pub fn uninstall() -> Result<(), String> {
+        let files = std::iter::once(desktop_file()?)
+            .chain(ICONS.iter().filter_map(|(size, _)| icon_file(size).ok()));
+        for file in files {
+            match std::fs::remove_file(&file) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(format!("Failed to remove {}: {}", file.display(), e)),
+            }
+        }
+        refresh(&data_dir()?);
+        Ok(())
+    }
+}
+
+#[cfg(any(windows, target_os = "macos"))]
+mod platform {
+    pub fn packaged() -> bool {
+        true
+    }
+
+    pub fn installed() -> bool {
+        false
+    }
+
+    pub fn install() -> Result<(), String> {
+        Err("Only needed on Linux".into())
+    }
+
+    pub fn uninstall() -> Result<(), String> {
+        Err("Only needed on Linux".into())
+    }
+}
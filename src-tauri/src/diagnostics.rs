@@ -0,0 +1,89 @@
+// Memory diagnostics for troubleshooting big documents.
+
+use serde::Serialize;
+use tauri::State;
+
+use crate::pdf::{DocId, DocumentStore};
+use crate::scheduler::RenderScheduler;
+use crate::tiles::TileCache;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentMemory {
+    pub doc_id: DocId,
+    pub path: String,
+    pub size: u64,
+    /// Mapped files only use memory for the parts that have been accessed
+    pub mapped: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryUsage {
+    pub documents: Vec<DocumentMemory>,
+    pub page_cache_bytes: usize,
+    pub tile_cache_bytes: usize,
+    /// Current resident set size of the process, where the OS reports it
+    pub resident_bytes: Option<u64>,
+    /// Peak resident set size of the process, where the OS reports it
+    pub peak_resident_bytes: Option<u64>,
+}
+
+#[cfg(target_os = "linux")]
+fn resident_bytes() -> Option<u64> {
+    // Second field of statm is the resident size in pages
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * page_size.max(0) as u64)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn resident_bytes() -> Option<u64> {
+    None
+}
+
+#[cfg(unix)]
+fn peak_resident_bytes() -> Option<u64> {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let max_rss = unsafe { usage.assume_init() }.ru_maxrss.max(0) as u64;
+    // macOS reports bytes, other systems kilobytes
+    if cfg!(target_os = "macos") {
+        Some(max_rss)
+    } else {
+        Some(max_rss * 1024)
+    }
+}
+
+#[cfg(not(unix))]
+fn peak_resident_bytes() -> Option<u64> {
+    None
+}
+
+#[tauri::command]
+pub async fn get_memory_usage(
+    store: State<'_, DocumentStore>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+) -> Result<MemoryUsage, String> {
+    let documents = store
+        .all()
+        .iter()
+        .map(|doc| DocumentMemory {
+            doc_id: doc.id,
+            path: doc.path.to_string_lossy().into_owned(),
+            size: doc.data.len() as u64,
+            mapped: doc.data.is_mapped(),
+        })
+        .collect();
+    Ok(MemoryUsage {
+        documents,
+        page_cache_bytes: scheduler.memory_used(),
+        tile_cache_bytes: tiles.memory_used(),
+        resident_bytes: resident_bytes(),
+        peak_resident_bytes: peak_resident_bytes(),
+    })
+}
@@ -0,0 +1,47 @@
+// Display scale handling for sharp pages on HiDPI screens.
+//
+// Render commands take their scale in CSS pixels per point and multiply it by
+// the device pixel ratio, so bitmaps match the physical pixels of the screen.
+// When a window moves to a monitor with a different scale factor a
+// `scale-factor-changed` event tells the frontend to render again.
+
+use serde::Serialize;
+use tauri::{Emitter, Window, WindowEvent};
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ScaleFactorChanged {
+    pub window: String,
+    pub scale_factor: f64,
+}
+
+/// The device pixel ratio for a render: the one the frontend passed, or else
+/// the scale factor of the calling window.
+pub fn device_pixel_ratio(window: &Window, requested: Option<f32>) -> f32 {
+    requested
+        .filter(|ratio| ratio.is_finite() && *ratio > 0.0)
+        .or_else(|| window.scale_factor().ok().map(|factor| factor as f32))
+        .unwrap_or(1.0)
+}
+
+pub fn handle_window_event(window: &Window, event: &WindowEvent) {
+    if let WindowEvent::ScaleFactorChanged { scale_factor, .. } = event {
+        eprintln!(
+            "Window {} scale factor changed to {}",
+            window.label(),
+            scale_factor
+        );
+        let _ = window.emit(
+            "scale-factor-changed",
+            ScaleFactorChanged {
+                window: window.label().to_string(),
+                scale_factor: *scale_factor,
+            },
+        );
+    }
+}
+
+#[tauri::command]
+pub async fn get_scale_factor(window: Window) -> Result<f64, String> {
+    window.scale_factor().map_err(|e| e.to_string())
+}
@@ -0,0 +1,564 @@
+// Page edits on open documents.
+//
+// Edits are kept in memory until `save_document`. Each one is made on a copy
+// of the object tree, which is serialized and swapped in for the open document
+// under the same id. The workers then reopen it and the caches start over, so
+// the viewer shows the edited pages right away.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use lopdf::{dictionary, Document, Object, ObjectId};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+
+use crate::compose::{
+    extract, import, linearize, load_document, parse_page_ranges, set_pages, write_document,
+};
+use crate::engine::Engine;
+use crate::flatten::flatten_form;
+use crate::objects;
+use crate::pdf::{blocking, read_data, Bounds, DocId, DocumentStore, OpenDocument};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scheduler::RenderScheduler;
+use crate::scope;
+use crate::tiles::TileCache;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EditedDocument {
+    pub doc_id: DocId,
+    pub page_count: i32,
+    /// True while there are edits that haven't been saved
+    pub unsaved: bool,
+}
+
+/// Which pages of a range an edit applies to, counting from page 1.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum PageParity {
+    #[default]
+    All,
+    Odd,
+    Even,
+}
+
+// Largest page side allowed by the PDF specification, in points
+const MAX_PAGE_SIDE: f32 = 14400.0;
+
+/// Checks page indices from the frontend and drops repeats.
+pub fn selected_pages(doc: &OpenDocument, pages: &[i32]) -> Result<Vec<i32>, String> {
+    let mut selected = Vec::new();
+    for &page in pages {
+        doc.check_page(page)?;
+        if !selected.contains(&page) {
+            selected.push(page);
+        }
+    }
+    if selected.is_empty() {
+        return Err("No pages selected".to_string());
+    }
+    Ok(selected)
+}
+
+// Reorders the page tree, leaving out pages missing from `order`
+fn reorder(doc: &mut Document, order: &[i32]) -> Result<(), String> {
+    let ids: Vec<_> = doc.get_pages().into_values().collect();
+    let order: Vec<_> = order.iter().map(|&page| ids[page as usize]).collect();
+    set_pages(doc, &order)
+}
+
+// Turns the page rotation by `degrees` clockwise
+fn rotate(doc: &mut Document, pages: &[i32], degrees: i64) -> Result<(), String> {
+    let ids = doc.get_pages();
+    for &page in pages {
+        let id = *ids
+            .get(&(page as u32 + 1))
+            .ok_or_else(|| format!("Page {} is out of range", page))?;
+        // /Rotate may be inherited, so set the result on the page itself
+        let current = objects::inherited(doc, id, b"Rotate")
+            .and_then(|r| r.as_i64().ok())
+            .unwrap_or(0);
+        let page = doc.get_dictionary_mut(id).map_err(|e| e.to_string())?;
+        page.set(
+            "Rotate",
+            Object::Integer((current + degrees).rem_euclid(360)),
+        );
+    }
+    Ok(())
+}
+
+// Crops pages to `rect`, in page space, clamped to each page's media box. With
+// `media_box` the media box is cut down too, so the margins are gone for good.
+fn crop(doc: &mut Document, pages: &[i32], rect: Bounds, media_box: bool) -> Result<(), String> {
+    let ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    for &page in pages {
+        let id = ids[page as usize];
+        let [x0, y0, x1, y1] = objects::PageSpace::new(doc, id).user_rect(rect);
+        let [mx0, my0, mx1, my1] = objects::inherited(doc, id, b"MediaBox")
+            .and_then(|b| objects::rect(doc, b))
+            .unwrap_or_else(|| objects::page_box(doc, id));
+        let cropped = [x0.max(mx0), y0.max(my0), x1.min(mx1), y1.min(my1)];
+        if cropped[2] - cropped[0] < 1.0 || cropped[3] - cropped[1] < 1.0 {
+            return Err(format!("The crop area is outside page {}", page + 1));
+        }
+
+        // The other boxes must stay inside the media box
+        let clamped: Vec<(&[u8], Option<[f32; 4]>)> =
+            [b"BleedBox".as_slice(), b"TrimBox", b"ArtBox"]
+                .into_iter()
+                .map(|key| {
+                    let clamped = objects::inherited(doc, id, key)
+                        .and_then(|b| objects::rect(doc, b))
+                        .map(|b| {
+                            [
+                                b[0].max(cropped[0]),
+                                b[1].max(cropped[1]),
+                                b[2].min(cropped[2]),
+                                b[3].min(cropped[3]),
+                            ]
+                        })
+                        .filter(|b| b[0] < b[2] && b[1] < b[3]);
+                    (key, clamped)
+                })
+                .collect();
+        let as_array = |b: [f32; 4]| b.map(Object::from).to_vec();
+
+        let page = doc.get_dictionary_mut(id).map_err(|e| e.to_string())?;
+        page.set("CropBox", as_array(cropped));
+        if media_box {
+            page.set("MediaBox", as_array(cropped));
+            for (key, clamped) in clamped {
+                match clamped {
+                    Some(b) => page.set(key, as_array(b)),
+                    None => {
+                        page.remove(key);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Swaps in a new version of a document and drops everything cached for the
+/// old one.
+pub fn replace(
+    doc: OpenDocument,
+    store: &DocumentStore,
+    tiles: &TileCache,
+    scheduler: &RenderScheduler,
+) -> Result<EditedDocument, String> {
+    let id = doc.id;
+    let doc = store
+        .replace(doc)
+        .ok_or_else(|| format!("No open document with id {}", id))?;
+    tiles.clear_document(id);
+    scheduler.clear_document(id);
+    Ok(EditedDocument {
+        doc_id: id,
+        page_count: doc.page_count,
+        unsaved: doc.unsaved,
+    })
+}
+
+/// Applies `edit` to a copy of the document's objects and swaps in the
+/// result, passing on what `edit` returns.
+pub async fn apply_edit_with<T: Send + 'static>(
+    doc: Arc<OpenDocument>,
+    store: &DocumentStore,
+    tiles: &TileCache,
+    scheduler: &RenderScheduler,
+    edit: impl FnOnce(&mut Document) -> Result<T, String> + Send + 'static,
+) -> Result<(EditedDocument, T), String> {
+    let (id, path, password) = (doc.id, doc.path.clone(), doc.password.clone());
+    let original = doc.clone();
+    let (data, edited, security, result) = blocking(move || {
+        // Hashed here, off the IPC thread, for the edited version to keep
+        doc.fingerprint();
+        let security = doc.security()?.cloned();
+        let (mut edited, result) = doc.with_objects(|objects| {
+            let mut edited = objects.clone();
+            let result = edit(&mut edited)?;
+            Ok((edited, result))
+        })?;
+        let mut data = Vec::new();
+        edited
+            .save_to(&mut data)
+            .map_err(|e| format!("Failed to apply edit: {}", e))?;
+        Ok((data, edited, security, result))
+    })
+    .await?;
+
+    let page_count = edited.get_pages().len() as i32;
+    let mut doc = OpenDocument::new(id, path, data, page_count)
+        .edited(edited)
+        .made_from(&original)
+        .with_security(security);
+    // The edited copy is no longer encrypted, but saving must still know that
+    // the file was, and its permissions still apply
+    doc.password = password;
+    Ok((replace(doc, store, tiles, scheduler)?, result))
+}
+
+/// Applies `edit` like `apply_edit_with`, for edits that return nothing.
+pub async fn apply_edit(
+    doc: Arc<OpenDocument>,
+    store: &DocumentStore,
+    tiles: &TileCache,
+    scheduler: &RenderScheduler,
+    edit: impl FnOnce(&mut Document) -> Result<(), String> + Send + 'static,
+) -> Result<EditedDocument, String> {
+    let (edited, ()) = apply_edit_with(doc, store, tiles, scheduler, edit).await?;
+    Ok(edited)
+}
+
+/// Rotates pages clockwise by a multiple of 90 degrees.
+#[tauri::command]
+pub async fn rotate_pages(
+    doc_id: DocId,
+    pages: Vec<i32>,
+    degrees: i32,
+    store: State<'_, DocumentStore>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<EditedDocument, String> {
+    if degrees % 90 != 0 {
+        return Err(format!(
+            "Pages can only be rotated by multiples of 90 degrees, not {}",
+            degrees
+        ));
+    }
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Assemble).await?;
+    let pages = selected_pages(&doc, &pages)?;
+    apply_edit(doc, &store, &tiles, &scheduler, move |objects| {
+        rotate(objects, &pages, degrees as i64)
+    })
+    .await
+}
+
+/// Crops pages to `rect`, given in page space as the pages are shown now.
+/// Applies to the pages selected by `ranges` (e.g. "1-5,8,10-end"), or all of
+/// them, and of those only the odd or even ones if `parity` says so. Pass
+/// `media_box` to drop the margins from the file rather than only hide them.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn crop_pages(
+    doc_id: DocId,
+    rect: Bounds,
+    ranges: Option<String>,
+    parity: Option<PageParity>,
+    media_box: Option<bool>,
+    store: State<'_, DocumentStore>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<EditedDocument, String> {
+    if rect.width < 1.0 || rect.height < 1.0 {
+        return Err("The crop area must be at least 1 point on each side".to_string());
+    }
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Modify).await?;
+    let pages = match &ranges {
+        Some(ranges) => parse_page_ranges(ranges, doc.page_count)?,
+        None => (0..doc.page_count).collect(),
+    };
+    // Page 1 is odd, and has index 0
+    let pages: Vec<i32> = match parity.unwrap_or_default() {
+        PageParity::All => pages,
+        PageParity::Odd => pages.into_iter().filter(|page| page % 2 == 0).collect(),
+        PageParity::Even => pages.into_iter().filter(|page| page % 2 == 1).collect(),
+    };
+    let pages = selected_pages(&doc, &pages)?;
+    let media_box = media_box.unwrap_or(false);
+    apply_edit(doc, &store, &tiles, &scheduler, move |objects| {
+        crop(objects, &pages, rect, media_box)
+    })
+    .await
+}
+
+/// Moves pages, in the order given, in front of the page currently at `to`.
+/// Pass the page count as `to` to move them to the end.
+#[tauri::command]
+pub async fn move_pages(
+    doc_id: DocId,
+    pages: Vec<i32>,
+    to: i32,
+    store: State<'_, DocumentStore>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<EditedDocument, String> {
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Assemble).await?;
+    let pages = selected_pages(&doc, &pages)?;
+    if to < 0 || to > doc.page_count {
+        return Err(format!("Page {} is out of range", to));
+    }
+
+    let rest: Vec<i32> = (0..doc.page_count)
+        .filter(|page| !pages.contains(page))
+        .collect();
+    let at = rest.iter().filter(|&&page| page < to).count();
+    let mut order = rest[..at].to_vec();
+    order.extend(&pages);
+    order.extend(&rest[at..]);
+
+    apply_edit(doc, &store, &tiles, &scheduler, move |objects| {
+        reorder(objects, &order)
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn delete_pages(
+    doc_id: DocId,
+    pages: Vec<i32>,
+    store: State<'_, DocumentStore>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<EditedDocument, String> {
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Assemble).await?;
+    let pages = selected_pages(&doc, &pages)?;
+    let order: Vec<i32> = (0..doc.page_count)
+        .filter(|page| !pages.contains(page))
+        .collect();
+    if order.is_empty() {
+        return Err("A document needs at least one page".to_string());
+    }
+
+    apply_edit(doc, &store, &tiles, &scheduler, move |objects| {
+        reorder(objects, &order)
+    })
+    .await
+}
+
+// Checks an index pages can be inserted at, the page count meaning the end
+fn check_insert_index(doc: &OpenDocument, at: i32) -> Result<(), String> {
+    if at < 0 || at > doc.page_count {
+        return Err(format!(
+            "Pages can't be inserted at {} (document has {} pages)",
+            at, doc.page_count
+        ));
+    }
+    Ok(())
+}
+
+// The on-screen size of a page, in points
+fn visible_size(doc: &Document, page_id: ObjectId) -> (f32, f32) {
+    let [x0, y0, x1, y1] = objects::page_box(doc, page_id);
+    if objects::PageSpace::new(doc, page_id).is_sideways() {
+        (y1 - y0, x1 - x0)
+    } else {
+        (x1 - x0, y1 - y0)
+    }
+}
+
+/// Inserts `count` blank pages before the page at `at`. Without a size, they
+/// match the page they are inserted before, or the last page at the end.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn insert_blank_pages(
+    doc_id: DocId,
+    at: i32,
+    count: i32,
+    width: Option<f32>,
+    height: Option<f32>,
+    store: State<'_, DocumentStore>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<EditedDocument, String> {
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Assemble).await?;
+    check_insert_index(&doc, at)?;
+    if count < 1 {
+        return Err("Insert at least one page".to_string());
+    }
+    let size = width.zip(height);
+    if let Some((width, height)) = size {
+        if !(1.0..=MAX_PAGE_SIDE).contains(&width) || !(1.0..=MAX_PAGE_SIDE).contains(&height) {
+            return Err(format!(
+                "Pages must be between 1 and {} points on each side",
+                MAX_PAGE_SIDE
+            ));
+        }
+    }
+
+    apply_edit(doc, &store, &tiles, &scheduler, move |objects| {
+        let mut order: Vec<ObjectId> = objects.get_pages().into_values().collect();
+        let at = at as usize;
+        let (width, height) = match size {
+            Some(size) => size,
+            None => order
+                .get(at)
+                .or(order.last())
+                .map_or((612.0, 792.0), |&id| visible_size(objects, id)),
+        };
+        let blank: Vec<ObjectId> = (0..count)
+            .map(|_| {
+                objects.add_object(dictionary! {
+                    "Type" => "Page",
+                    "MediaBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+                    "Resources" => dictionary! {},
+                })
+            })
+            .collect();
+        order.splice(at..at, blank);
+        set_pages(objects, &order)
+    })
+    .await
+}
+
+/// Inserts pages of the PDF at `path` before the page at `at`: those selected
+/// by `ranges` (e.g. "1-5,8,10-end"), or all of them.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn insert_pages_from_file(
+    app: AppHandle,
+    doc_id: DocId,
+    at: i32,
+    path: String,
+    ranges: Option<String>,
+    store: State<'_, DocumentStore>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<EditedDocument, String> {
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Assemble).await?;
+    check_insert_index(&doc, at)?;
+    scope::check(&app, Path::new(&path))?;
+
+    apply_edit(doc, &store, &tiles, &scheduler, move |objects| {
+        let source = load_document(Path::new(&path))?;
+        let page_count = source.get_pages().len() as i32;
+        let pages = match &ranges {
+            Some(ranges) => parse_page_ranges(ranges, page_count)?,
+            None => (0..page_count).collect(),
+        };
+        // Extracting first leaves out everything the other pages need
+        let imported = import(objects, extract(&source, &pages)?)?;
+        eprintln!("Inserting {} pages from {}", imported.len(), path);
+
+        let mut order: Vec<ObjectId> = objects.get_pages().into_values().collect();
+        let at = at as usize;
+        order.splice(at..at, imported);
+        set_pages(objects, &order)
+    })
+    .await
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SavedDocument {
+    pub document: EditedDocument,
+    /// Whether the file was written for fast web view
+    pub linearized: bool,
+}
+
+/// Writes the document with its edits to `output_path`, or over its own file.
+/// The open document then refers to the saved file.
+///
+/// With `fast_web_view` the file is also linearized. Should that fail, the
+/// document is still saved and `linearized` is false. With `flatten_forms`
+/// the values of form fields are burned into the pages, so whoever gets the
+/// file can't change them (see `flatten.rs`).
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn save_document(
+    app: AppHandle,
+    doc_id: DocId,
+    output_path: Option<String>,
+    fast_web_view: Option<bool>,
+    flatten_forms: Option<bool>,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<SavedDocument, String> {
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Modify).await?;
+    let path = output_path.map_or_else(|| doc.path.clone(), PathBuf::from);
+    scope::check_write(&app, &path)?;
+    save(
+        doc,
+        path,
+        fast_web_view.unwrap_or(false),
+        flatten_forms.unwrap_or(false),
+        &store,
+        &engine,
+        &tiles,
+        &scheduler,
+    )
+    .await
+}
+
+/// Writes a document with its edits to `path`, as `save_document` does.
+#[allow(clippy::too_many_arguments)]
+pub async fn save(
+    doc: Arc<OpenDocument>,
+    path: PathBuf,
+    fast_web_view: bool,
+    flatten_forms: bool,
+    store: &DocumentStore,
+    engine: &Engine,
+    tiles: &TileCache,
+    scheduler: &RenderScheduler,
+) -> Result<SavedDocument, String> {
+    let doc_id = doc.id;
+    if doc.password.is_some() {
+        return Err(format!(
+            "{} is password protected. Save it with encryption to keep the protection",
+            doc.path.display()
+        ));
+    }
+    let page_count = {
+        let path = path.clone();
+        blocking(move || {
+            // Saving compresses the tree, so work on a copy
+            let mut objects = doc.with_objects(|objects| Ok(objects.clone()))?;
+            if flatten_forms {
+                let flattened = flatten_form(&mut objects)?;
+                eprintln!(
+                    "Flattened {} form widgets of document {}",
+                    flattened, doc_id
+                );
+            }
+            // Edits leave pages and page tree nodes behind that nothing refers to
+            objects.prune_objects();
+            write_document(&mut objects, &path)?;
+            Ok(objects.get_pages().len() as i32)
+        })
+        .await?
+    };
+
+    let linearized = if fast_web_view {
+        let path = path.clone();
+        match engine.run(move |_| linearize(&path)).await {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("Saved without fast web view: {}", e);
+                false
+            }
+        }
+    } else {
+        false
+    };
+    eprintln!("Saved document {} to {}", doc_id, path.display());
+
+    let data = {
+        let path = path.clone();
+        blocking(move || read_data(&path)).await?
+    };
+    let saved = OpenDocument::new(doc_id, path, data, page_count);
+    Ok(SavedDocument {
+        document: replace(saved, store, tiles, scheduler)?,
+        linearized,
+    })
+}
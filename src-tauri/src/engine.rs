@@ -0,0 +1,210 @@
+// Render engine worker pool.
+//
+// mupdf documents are tied to the thread that opened them, so every piece of
+// work that needs the engine is sent to one of a few long-lived worker threads.
+// Each worker keeps its own copy of every document it has touched, opened
+// lazily from the shared bytes held by the `DocumentStore`.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+
+use crate::pdf::{unlock, DocId, DocumentData, OpenDocument};
+
+type Job = Box<dyn FnOnce(&mut Worker) + Send>;
+
+// Number of parsed pages each worker keeps around for repeated tile renders
+const DISPLAY_LIST_CACHE: usize = 4;
+
+/// Per-thread engine state handed to every job.
+pub struct Worker {
+    docs: HashMap<DocId, (Weak<OpenDocument>, mupdf::Document)>,
+    // Recently used page display lists, most recent last
+    lists: Vec<((DocId, i32), mupdf::DisplayList)>,
+}
+
+impl Default for Worker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Worker {
+    /// A worker of its own, for threads that render outside the engine.
+    pub fn new() -> Self {
+        Worker {
+            docs: HashMap::new(),
+            lists: Vec::new(),
+        }
+    }
+
+    /// Returns this worker's engine handle for `doc`, opening it on first use.
+    pub fn document(&mut self, doc: &Arc<OpenDocument>) -> Result<&mupdf::Document, String> {
+        let current = self
+            .docs
+            .get(&doc.id)
+            .is_some_and(|(opened, _)| opened.as_ptr() == Arc::as_ptr(doc));
+        if !current {
+            let mut handle = match &doc.data {
+                DocumentData::Owned(bytes) => mupdf::Document::from_bytes(bytes, "application/pdf"),
+                // from_bytes copies its input, which would defeat the mapping;
+                // mupdf reads the file on demand instead
+                DocumentData::Mapped(_) => mupdf::Document::open(&doc.path.to_string_lossy()),
+            }
+            .map_err(|e| format!("Failed to open {}: {}", doc.path.display(), e))?;
+            if !unlock(&mut handle, doc.password.as_deref())? {
+                return Err(format!("{} is password protected", doc.path.display()));
+            }
+            // The store may have replaced the document under the same id, e.g.
+            // once a progressive load finished, so pages parsed before are stale
+            self.lists.retain(|((id, _), _)| *id != doc.id);
+            self.docs.insert(doc.id, (Arc::downgrade(doc), handle));
+        }
+        Ok(&self.docs[&doc.id].1)
+    }
+
+    /// Returns the parsed display list for a page, reusing a recent one when
+    /// the same page is rendered again (e.g. one tile after another).
+    pub fn display_list(
+        &mut self,
+        doc: &Arc<OpenDocument>,
+        page: i32,
+    ) -> Result<&mupdf::DisplayList, String> {
+        let key = (doc.id, page);
+        if let Some(index) = self.lists.iter().position(|(k, _)| *k == key) {
+            let list = self.lists.remove(index);
+            self.lists.push(list);
+        } else {
+            let list = self
+                .document(doc)?
+                .load_page(page)
+                .and_then(|p| p.to_display_list(true))
+                .map_err(|e| e.to_string())?;
+            if self.lists.len() >= DISPLAY_LIST_CACHE {
+                self.lists.remove(0);
+            }
+            self.lists.push((key, list));
+        }
+        Ok(&self.lists.last().unwrap().1)
+    }
+
+    // Drop handles for documents that have been closed since the last job.
+    fn prune(&mut self) {
+        self.docs.retain(|_, (doc, _)| doc.strong_count() > 0);
+        let docs = &self.docs;
+        self.lists.retain(|((id, _), _)| docs.contains_key(id));
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    urgent: VecDeque<Job>,
+    background: VecDeque<Job>,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    ready: Condvar,
+}
+
+/// Pool of engine worker threads, managed as Tauri state.
+///
+/// Jobs submitted with `run` always go ahead of speculative work queued with
+/// `run_background`, so prerendering never delays what the user is looking at.
+pub struct Engine {
+    shared: Arc<Shared>,
+    threads: usize,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        let threads = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(2)
+            .clamp(1, 4);
+        Self::with_threads(threads)
+    }
+
+    pub fn with_threads(threads: usize) -> Self {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue::default()),
+            ready: Condvar::new(),
+        });
+
+        for index in 0..threads {
+            let shared = shared.clone();
+            std::thread::Builder::new()
+                .name(format!("render-worker-{}", index))
+                .spawn(move || {
+                    let mut worker = Worker::new();
+                    loop {
+                        let job = shared.next_job();
+                        worker.prune();
+                        job(&mut worker);
+                    }
+                })
+                .expect("failed to spawn render worker");
+        }
+
+        Engine { shared, threads }
+    }
+
+    /// The number of worker threads, which is how many jobs run at once.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Runs `job` on a worker thread and waits for its result.
+    pub async fn run<T, F>(&self, job: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Worker) -> Result<T, String> + Send + 'static,
+    {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.shared.push(
+            Box::new(move |worker| {
+                let _ = tx.send(job(worker));
+            }),
+            true,
+        );
+        rx.await.map_err(|_| "Render job was dropped".to_string())?
+    }
+
+    /// Queues low-priority work that nobody is waiting on.
+    pub fn run_background<F>(&self, job: F)
+    where
+        F: FnOnce(&mut Worker) + Send + 'static,
+    {
+        self.shared.push(Box::new(job), false);
+    }
+}
+
+impl Shared {
+    fn push(&self, job: Job, urgent: bool) {
+        let mut queue = self.queue.lock().unwrap();
+        if urgent {
+            queue.urgent.push_back(job);
+        } else {
+            queue.background.push_back(job);
+        }
+        self.ready.notify_one();
+    }
+
+    fn next_job(&self) -> Job {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if let Some(job) = queue.urgent.pop_front() {
+                return job;
+            }
+            if let Some(job) = queue.background.pop_front() {
+                return job;
+            }
+            queue = self.ready.wait(queue).unwrap();
+        }
+    }
+}
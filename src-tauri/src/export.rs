@@ -0,0 +1,314 @@
+// Page exports for printing and sharing.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, State};
+
+use crate::colors::ColorMode;
+use crate::compose::parse_page_ranges;
+use crate::engine::Engine;
+use crate::jobs::{JobId, RenderJobs};
+use crate::pdf::{blocking, decode_bitmap, rasterize_page, DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scope;
+
+const MIN_EXPORT_DPI: f32 = 36.0;
+const MAX_EXPORT_DPI: f32 = 1200.0;
+const JPEG_QUALITY: u8 = 90;
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum RasterFormat {
+    Png,
+    Jpeg,
+    Tiff,
+}
+
+impl RasterFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            RasterFormat::Png => "png",
+            RasterFormat::Jpeg => "jpg",
+            RasterFormat::Tiff => "tif",
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportProgress {
+    pub job_id: Option<JobId>,
+    /// Pages written so far
+    pub done: usize,
+    pub total: usize,
+}
+
+/// Renders a page to an SVG document at its natural size (1 unit = 1 point).
+///
+/// Text is emitted as outlines, so the result looks the same without the
+/// document's fonts installed.
+#[tauri::command]
+pub async fn render_page_svg(
+    doc_id: DocId,
+    page: i32,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<String, String> {
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Copy).await?;
+    doc.check_page(page)?;
+    engine
+        .run(move |worker| {
+            worker
+                .document(&doc)?
+                .load_page(page)
+                .and_then(|page| page.to_svg(&mupdf::Matrix::IDENTITY))
+                .map_err(|e| e.to_string())
+        })
+        .await
+}
+
+fn encode_png(pixels: &[u8], width: u32, height: u32, dpi: f32) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let per_meter = (dpi / 0.0254).round() as u32;
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: per_meter,
+        yppu: per_meter,
+        unit: png::Unit::Meter,
+    }));
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(pixels))
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    Ok(out)
+}
+
+/// Encodes RGB pixels as a JPEG with the given resolution.
+pub fn encode_jpeg(pixels: &[u8], width: u32, height: u32, dpi: f32) -> Result<Vec<u8>, String> {
+    let (Ok(w), Ok(h)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err("The page is too large for JPEG at this resolution".to_string());
+    };
+    let mut out = Vec::new();
+    let mut encoder = jpeg_encoder::Encoder::new(&mut out, JPEG_QUALITY);
+    let dpi = dpi.round() as u16;
+    encoder.set_density(jpeg_encoder::Density::Inch { x: dpi, y: dpi });
+    encoder
+        .encode(pixels, w, h, jpeg_encoder::ColorType::Rgb)
+        .map_err(|e| format!("Failed to encode JPEG: {}", e))?;
+    Ok(out)
+}
+
+// Writes an uncompressed baseline TIFF: the pixels as a single strip,
+// followed by the values that don't fit in their tags and the directory
+fn encode_tiff(pixels: &[u8], width: u32, height: u32, dpi: f32) -> Result<Vec<u8>, String> {
+    let size = u32::try_from(pixels.len())
+        .ok()
+        .filter(|size| *size < u32::MAX - 1024)
+        .ok_or("The page is too large for TIFF at this resolution")?;
+    let mut out = Vec::with_capacity(pixels.len() + 256);
+    out.extend_from_slice(b"II*\0");
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(pixels);
+    if out.len() % 2 == 1 {
+        out.push(0);
+    }
+
+    let bits_offset = out.len() as u32;
+    for _ in 0..3 {
+        out.extend_from_slice(&8u16.to_le_bytes());
+    }
+    let resolution_offset = out.len() as u32;
+    let resolution = (dpi * 100.0).round() as u32;
+    out.extend_from_slice(&resolution.to_le_bytes());
+    out.extend_from_slice(&100u32.to_le_bytes());
+
+    const SHORT: u16 = 3;
+    const LONG: u16 = 4;
+    const RATIONAL: u16 = 5;
+    // Tag, type, count and value or offset, sorted by tag
+    let entries: [(u16, u16, u32, u32); 12] = [
+        (256, LONG, 1, width),
+        (257, LONG, 1, height),
+        (258, SHORT, 3, bits_offset),
+        (259, SHORT, 1, 1),
+        (262, SHORT, 1, 2),
+        (273, LONG, 1, 8),
+        (277, SHORT, 1, 3),
+        (278, LONG, 1, height),
+        (279, LONG, 1, size),
+        (282, RATIONAL, 1, resolution_offset),
+        (283, RATIONAL, 1, resolution_offset),
+        (296, SHORT, 1, 2),
+    ];
+    let directory = out.len() as u32;
+    out[4..8].copy_from_slice(&directory.to_le_bytes());
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for (tag, kind, count, value) in entries {
+        out.extend_from_slice(&tag.to_le_bytes());
+        out.extend_from_slice(&kind.to_le_bytes());
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    out.extend_from_slice(&0u32.to_le_bytes());
+    Ok(out)
+}
+
+// Encodes a bitmap from `rasterize_page`, dropping its alpha channel
+fn encode_raster(bitmap: &[u8], format: RasterFormat, dpi: f32) -> Result<Vec<u8>, String> {
+    let (width, height, rgba) = decode_bitmap(bitmap);
+    let pixels: Vec<u8> = rgba
+        .chunks_exact(4)
+        .flat_map(|rgba| [rgba[0], rgba[1], rgba[2]])
+        .collect();
+    match format {
+        RasterFormat::Png => encode_png(&pixels, width, height, dpi),
+        RasterFormat::Jpeg => encode_jpeg(&pixels, width, height, dpi),
+        RasterFormat::Tiff => encode_tiff(&pixels, width, height, dpi),
+    }
+}
+
+/// Renders the pages selected by `ranges` (e.g. "1-5,8,10-end"), or all
+/// pages, to image files in `output_dir` at `dpi` dots per inch, and returns
+/// their paths.
+///
+/// Emits `export-progress` events after each page. Passing a `job_id` allows
+/// the export to be aborted with `cancel_render`; pages written before that
+/// are kept.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn export_page_images(
+    app: AppHandle,
+    doc_id: DocId,
+    ranges: Option<String>,
+    format: RasterFormat,
+    dpi: f32,
+    output_dir: String,
+    job_id: Option<JobId>,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    jobs: State<'_, RenderJobs>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<Vec<String>, String> {
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Copy).await?;
+    if !(MIN_EXPORT_DPI..=MAX_EXPORT_DPI).contains(&dpi) {
+        return Err(format!(
+            "Choose a resolution between {} and {} dpi",
+            MIN_EXPORT_DPI, MAX_EXPORT_DPI
+        ));
+    }
+    let pages = match &ranges {
+        Some(ranges) => parse_page_ranges(ranges, doc.page_count)?,
+        None => (0..doc.page_count).collect(),
+    };
+    let dir = Path::new(&output_dir).to_path_buf();
+    scope::check_write(&app, &dir)?;
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let stem = doc.path.file_stem().map_or("document".into(), |stem| {
+        stem.to_string_lossy().into_owned()
+    });
+
+    let token = jobs.begin_batch(job_id);
+    let total = pages.len();
+    let mut files = Vec::with_capacity(total);
+    let mut result = Ok(());
+    for (done, page) in pages.into_iter().enumerate() {
+        let target = doc.clone();
+        let cancel = token.clone();
+        let bitmap = engine
+            .run(move |worker| {
+                cancel.check()?;
+                rasterize_page(
+                    worker,
+                    &target,
+                    page,
+                    dpi / 72.0,
+                    ColorMode::Normal,
+                    &cancel,
+                )
+            })
+            .await;
+        let path = dir.join(format!("{}-{:03}.{}", stem, page + 1, format.extension()));
+        let written = match bitmap {
+            Ok(bitmap) => {
+                let path = path.clone();
+                blocking(move || {
+                    let data = encode_raster(&bitmap, format, dpi)?;
+                    std::fs::write(&path, data)
+                        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+                })
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            result = Err(e);
+            break;
+        }
+        files.push(path.to_string_lossy().into_owned());
+        let _ = app.emit(
+            "export-progress",
+            ExportProgress {
+                job_id,
+                done: done + 1,
+                total,
+            },
+        );
+    }
+    jobs.finish_batch(job_id);
+    result?;
+
+    eprintln!(
+        "Exported {} pages of document {} to {} at {} dpi",
+        files.len(),
+        doc_id,
+        output_dir,
+        dpi
+    );
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The type, count and value or offset of `tag` in the first directory
+    fn tag(tiff: &[u8], tag: u16) -> Option<(u16, u32, u32)> {
+        let u16_at = |at: usize| u16::from_le_bytes(tiff[at..at + 2].try_into().unwrap());
+        let u32_at = |at: usize| u32::from_le_bytes(tiff[at..at + 4].try_into().unwrap());
+        let directory = u32_at(4) as usize;
+        (0..u16_at(directory) as usize)
+            .map(|i| directory + 2 + i * 12)
+            .find(|&entry| u16_at(entry) == tag)
+            .map(|entry| (u16_at(entry + 2), u32_at(entry + 4), u32_at(entry + 8)))
+    }
+
+    #[test]
+    fn tiff_holds_the_pixels_and_resolution() {
+        // One pixel, which leaves the strip an odd length to pad
+        let pixels = [255, 128, 0];
+        let tiff = encode_tiff(&pixels, 1, 1, 150.0).unwrap();
+        assert_eq!(&tiff[..4], b"II*\0");
+        assert_eq!(tag(&tiff, 256), Some((4, 1, 1)));
+        assert_eq!(tag(&tiff, 257), Some((4, 1, 1)));
+        assert_eq!(tag(&tiff, 277), Some((3, 1, 3)));
+        assert_eq!(tag(&tiff, 279), Some((4, 1, 3)));
+        let (_, _, strip) = tag(&tiff, 273).unwrap();
+        assert_eq!(&tiff[strip as usize..strip as usize + 3], &pixels);
+
+        let (kind, _, offset) = tag(&tiff, 282).unwrap();
+        assert_eq!(kind, 5);
+        let offset = offset as usize;
+        let numerator = u32::from_le_bytes(tiff[offset..offset + 4].try_into().unwrap());
+        let denominator = u32::from_le_bytes(tiff[offset + 4..offset + 8].try_into().unwrap());
+        assert_eq!(numerator as f32 / denominator as f32, 150.0);
+        // The directory starts on a word boundary
+        assert_eq!(u32::from_le_bytes(tiff[4..8].try_into().unwrap()) % 2, 0);
+    }
+}
@@ -0,0 +1,738 @@
+// Exchanging form data with FDF and XFDF files.
+//
+// Both hold the values of a form's fields, apart from the PDF: FDF in PDF
+// syntax, XFDF as XML. Acrobat and most other form tools read and write
+// them, which makes them the usual way to fill in the same form again and
+// again. Fields are nested by their partial names, as the form nests them.
+// Values are written as the document stores them, so buttons export the name
+// of their state rather than a label.
+//
+// Importing fills in the fields the data and the form have in common. Fields
+// missing from the form, read-only fields and buttons are skipped, so data
+// exported from an older version of a form still fills in the rest.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use lopdf::{Dictionary, Document, Object};
+use quick_xml::escape::{escape, resolve_predefined_entity};
+use quick_xml::events::Event;
+use quick_xml::{Reader, XmlVersion};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+
+use crate::edit::{apply_edit_with, EditedDocument};
+use crate::forms::{self, FieldType, FieldUpdate, FieldValue};
+use crate::objects::{self, MAX_DEPTH};
+use crate::pdf::{blocking, DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scheduler::RenderScheduler;
+use crate::scope;
+use crate::tiles::TileCache;
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum FormDataFormat {
+    Fdf,
+    Xfdf,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportedFormData {
+    pub document: EditedDocument,
+    pub filled: usize,
+    /// Fields of the data that weren't filled in
+    pub skipped: Vec<String>,
+}
+
+// A value read from form data, before it is matched with its field
+enum Imported {
+    Text(String),
+    List(Vec<String>),
+}
+
+// Field values nested by partial name, the way both formats write them
+#[derive(Default)]
+struct FieldTree {
+    value: Option<Object>,
+    kids: BTreeMap<String, FieldTree>,
+}
+
+impl FieldTree {
+    fn new(values: Vec<(String, Object)>) -> Self {
+        let mut tree = FieldTree::default();
+        for (name, value) in values {
+            let mut node = &mut tree;
+            for part in name.split('.') {
+                node = node.kids.entry(part.to_string()).or_default();
+            }
+            node.value = Some(value);
+        }
+        tree
+    }
+}
+
+// Writes an object in PDF syntax; form values hold no references
+fn write_object(out: &mut Vec<u8>, object: &Object) {
+    match object {
+        Object::Null | Object::Reference(_) | Object::Stream(_) => out.extend(b"null"),
+        Object::Boolean(value) => out.extend(value.to_string().as_bytes()),
+        Object::Integer(value) => out.extend(value.to_string().as_bytes()),
+        Object::Real(value) => out.extend(value.to_string().as_bytes()),
+        Object::Name(name) => {
+            out.push(b'/');
+            for &b in name {
+                if b.is_ascii_graphic() && !b"#()<>[]{}/%".contains(&b) {
+                    out.push(b);
+                } else {
+                    out.extend(format!("#{:02X}", b).as_bytes());
+                }
+            }
+        }
+        Object::String(bytes, _) => {
+            out.push(b'(');
+            for &b in bytes {
+                match b {
+                    b'(' | b')' | b'\\' => out.extend([b'\\', b]),
+                    b'\r' => out.extend(b"\\r"),
+                    b'\n' => out.extend(b"\\n"),
+                    _ => out.push(b),
+                }
+            }
+            out.push(b')');
+        }
+        Object::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b' ');
+                }
+                write_object(out, item);
+            }
+            out.push(b']');
+        }
+        Object::Dictionary(dict) => {
+            out.extend(b"<<");
+            for (key, value) in dict.iter() {
+                write_object(out, &Object::Name(key.clone()));
+                out.push(b' ');
+                write_object(out, value);
+            }
+            out.extend(b">>");
+        }
+    }
+}
+
+fn fdf_fields(tree: &FieldTree) -> Vec<Object> {
+    tree.kids
+        .iter()
+        .map(|(name, node)| {
+            let mut field = Dictionary::new();
+            field.set("T", objects::encode_text(name));
+            if let Some(value) = &node.value {
+                field.set("V", value.clone());
+            }
+            if !node.kids.is_empty() {
+                field.set("Kids", fdf_fields(node));
+            }
+            Object::Dictionary(field)
+        })
+        .collect()
+}
+
+fn to_fdf(values: Vec<(String, Object)>, pdf_name: &str) -> Vec<u8> {
+    let mut fdf = Dictionary::new();
+    fdf.set("F", objects::encode_text(pdf_name));
+    fdf.set("Fields", fdf_fields(&FieldTree::new(values)));
+    let mut root = Dictionary::new();
+    root.set("FDF", fdf);
+
+    // The binary comment marks the file as binary for transfer tools
+    let mut out = b"%FDF-1.2\n%\xe2\xe3\xcf\xd3\n1 0 obj\n".to_vec();
+    write_object(&mut out, &Object::Dictionary(root));
+    out.extend(b"\nendobj\ntrailer\n<</Root 1 0 R>>\n%%EOF\n");
+    out
+}
+
+// The text of a stored value for XFDF, one entry per selected option
+fn xfdf_values(value: &Object) -> Vec<String> {
+    match value {
+        Object::Name(name) => vec![String::from_utf8_lossy(name).into_owned()],
+        Object::Array(values) => values.iter().flat_map(xfdf_values).collect(),
+        other => objects::text_string(other).into_iter().collect(),
+    }
+}
+
+fn xfdf_fields(out: &mut String, tree: &FieldTree, indent: usize) {
+    let pad = "  ".repeat(indent);
+    for (name, node) in &tree.kids {
+        out.push_str(&format!(
+            "{}<field name=\"{}\">\n",
+            pad,
+            escape(name.as_str())
+        ));
+        for value in node.value.iter().flat_map(xfdf_values) {
+            out.push_str(&format!(
+                "{}  <value>{}</value>\n",
+                pad,
+                escape(value.as_str())
+            ));
+        }
+        xfdf_fields(out, node, indent + 1);
+        out.push_str(&format!("{}</field>\n", pad));
+    }
+}
+
+fn to_xfdf(values: Vec<(String, Object)>, pdf_name: &str) -> Vec<u8> {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <xfdf xmlns=\"http://ns.adobe.com/xfdf/\" xml:space=\"preserve\">\n",
+    );
+    out.push_str(&format!("  <f href=\"{}\"/>\n", escape(pdf_name)));
+    out.push_str("  <fields>\n");
+    xfdf_fields(&mut out, &FieldTree::new(values), 2);
+    out.push_str("  </fields>\n</xfdf>\n");
+    out.into_bytes()
+}
+
+// Reads the objects of PDF syntax, enough for FDF files: they are small and
+// usually without a cross-reference table, which lopdf insists on
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+fn is_delimiter(b: u8) -> bool {
+    b"()<>[]{}/%".contains(&b) || b.is_ascii_whitespace() || b == 0
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn skip_space(&mut self) {
+        while let Some(b) = self.peek() {
+            if b == b'%' {
+                while !matches!(self.peek(), None | Some(b'\r') | Some(b'\n')) {
+                    self.pos += 1;
+                }
+            } else if b.is_ascii_whitespace() || b == 0 {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn starts_with(&self, token: &[u8]) -> bool {
+        self.data[self.pos..].starts_with(token)
+    }
+
+    // A keyword or number
+    fn word(&mut self) -> &[u8] {
+        let start = self.pos;
+        while self.peek().is_some_and(|b| !is_delimiter(b)) {
+            self.pos += 1;
+        }
+        &self.data[start..self.pos]
+    }
+
+    fn literal_string(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut depth = 0;
+        self.pos += 1;
+        while let Some(b) = self.peek() {
+            self.pos += 1;
+            match b {
+                b'\\' => {
+                    let Some(escaped) = self.peek() else {
+                        break;
+                    };
+                    self.pos += 1;
+                    match escaped {
+                        b'n' => out.push(b'\n'),
+                        b'r' => out.push(b'\r'),
+                        b't' => out.push(b'\t'),
+                        b'b' => out.push(8),
+                        b'f' => out.push(12),
+                        b'0'..=b'7' => {
+                            let mut code = (escaped - b'0') as u32;
+                            for _ in 0..2 {
+                                match self.peek() {
+                                    Some(digit @ b'0'..=b'7') => {
+                                        code = code * 8 + (digit - b'0') as u32;
+                                        self.pos += 1;
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            out.push(code as u8);
+                        }
+                        // A line break continued on the next line
+                        b'\r' => {
+                            if self.peek() == Some(b'\n') {
+                                self.pos += 1;
+                            }
+                        }
+                        b'\n' => {}
+                        other => out.push(other),
+                    }
+                }
+                b'(' => {
+                    depth += 1;
+                    out.push(b);
+                }
+                b')' if depth == 0 => break,
+                b')' => {
+                    depth -= 1;
+                    out.push(b);
+                }
+                _ => out.push(b),
+            }
+        }
+        out
+    }
+
+    fn hex_string(&mut self) -> Vec<u8> {
+        self.pos += 1;
+        let mut digits = Vec::new();
+        while let Some(b) = self.peek() {
+            self.pos += 1;
+            match b {
+                b'>' => break,
+                b if b.is_ascii_hexdigit() => digits.push(b),
+                _ => {}
+            }
+        }
+        if digits.len() % 2 == 1 {
+            digits.push(b'0');
+        }
+        digits
+            .chunks(2)
+            .filter_map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+            .collect()
+    }
+
+    fn name(&mut self) -> Vec<u8> {
+        self.pos += 1;
+        let raw = self.word().to_vec();
+        let mut name = Vec::new();
+        let mut i = 0;
+        while i < raw.len() {
+            let code = raw
+                .get(i + 1..i + 3)
+                .filter(|_| raw[i] == b'#')
+                .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+            match code {
+                Some(code) => {
+                    name.push(code);
+                    i += 3;
+                }
+                None => {
+                    name.push(raw[i]);
+                    i += 1;
+                }
+            }
+        }
+        name
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Object, String> {
+        if depth > MAX_DEPTH {
+            return Err("The form data is nested too deeply".to_string());
+        }
+        self.skip_space();
+        match self.peek() {
+            None => Err("The form data ends early".to_string()),
+            Some(b'<') if self.starts_with(b"<<") => {
+                self.pos += 2;
+                let mut dict = Dictionary::new();
+                loop {
+                    self.skip_space();
+                    if self.starts_with(b">>") {
+                        self.pos += 2;
+                        return Ok(Object::Dictionary(dict));
+                    }
+                    let Object::Name(key) = self.object(depth + 1)? else {
+                        return Err("The form data has a malformed dictionary".to_string());
+                    };
+                    let value = self.object(depth + 1)?;
+                    dict.set(key, value);
+                }
+            }
+            Some(b'<') => Ok(Object::String(
+                self.hex_string(),
+                lopdf::StringFormat::Hexadecimal,
+            )),
+            Some(b'(') => Ok(Object::String(
+                self.literal_string(),
+                lopdf::StringFormat::Literal,
+            )),
+            Some(b'/') => Ok(Object::Name(self.name())),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_space();
+                    if self.peek() == Some(b']') {
+                        self.pos += 1;
+                        return Ok(Object::Array(items));
+                    }
+                    items.push(self.object(depth + 1)?);
+                }
+            }
+            Some(_) => {
+                let start = self.pos;
+                let word = String::from_utf8_lossy(self.word()).into_owned();
+                match word.as_str() {
+                    "true" => return Ok(Object::Boolean(true)),
+                    "false" => return Ok(Object::Boolean(false)),
+                    "null" => return Ok(Object::Null),
+                    _ => {}
+                }
+                if let Ok(number) = word.parse::<i64>() {
+                    // "12 0 R" refers to an object
+                    let after = self.pos;
+                    self.skip_space();
+                    let generation = self.word().to_vec();
+                    self.skip_space();
+                    if let (Ok(generation), true) = (
+                        String::from_utf8_lossy(&generation).parse::<u16>(),
+                        self.peek() == Some(b'R')
+                            && self.data.get(self.pos + 1).is_none_or(|&b| is_delimiter(b)),
+                    ) {
+                        self.pos += 1;
+                        return Ok(Object::Reference((number as u32, generation)));
+                    }
+                    self.pos = after;
+                    return Ok(Object::Integer(number));
+                }
+                match word.parse::<f32>() {
+                    Ok(number) => Ok(Object::Real(number)),
+                    Err(_) if word.is_empty() => {
+                        Err(format!("The form data has a stray character at {}", start))
+                    }
+                    Err(_) => Err(format!("The form data has an unknown keyword {}", word)),
+                }
+            }
+        }
+    }
+}
+
+// Reads the objects and trailer of an FDF file into a document to look
+// things up in
+fn parse_fdf(data: &[u8]) -> Result<Document, String> {
+    if !data.starts_with(b"%FDF-") {
+        return Err("The file isn't FDF form data".to_string());
+    }
+    let mut doc = Document::new();
+    let mut parser = Parser { data, pos: 0 };
+    loop {
+        parser.skip_space();
+        if parser.peek().is_none() {
+            break;
+        }
+        if parser.starts_with(b"trailer") {
+            parser.pos += b"trailer".len();
+            if let Object::Dictionary(trailer) = parser.object(0)? {
+                doc.trailer = trailer;
+            }
+            continue;
+        }
+        let start = parser.pos;
+        let word = parser.word().to_vec();
+        let Ok(number) = String::from_utf8_lossy(&word).parse::<u32>() else {
+            // Anything else, like an xref table or startxref, isn't needed
+            if parser.pos == start {
+                parser.pos += 1;
+            }
+            continue;
+        };
+        parser.skip_space();
+        let generation = String::from_utf8_lossy(parser.word())
+            .parse::<u16>()
+            .unwrap_or(0);
+        parser.skip_space();
+        if !parser.starts_with(b"obj") {
+            continue;
+        }
+        parser.pos += b"obj".len();
+        let object = parser.object(0)?;
+        parser.skip_space();
+        // Streams, like embedded files, carry nothing of the form's values
+        if parser.starts_with(b"stream") {
+            let rest = &data[parser.pos..];
+            let end = rest
+                .windows(b"endstream".len())
+                .position(|w| w == b"endstream")
+                .ok_or("The form data has an unfinished stream")?;
+            parser.pos += end + b"endstream".len();
+        }
+        doc.objects.insert((number, generation), object);
+    }
+    Ok(doc)
+}
+
+fn collect_fdf_values(
+    doc: &Document,
+    field: &Object,
+    parent: Option<&str>,
+    depth: usize,
+    found: &mut Vec<(String, Imported)>,
+) {
+    let Ok(field) = objects::resolve(doc, field).as_dict() else {
+        return;
+    };
+    if depth > MAX_DEPTH {
+        return;
+    }
+    let name = match (parent, objects::get_text(doc, field, b"T")) {
+        (Some(parent), Some(own)) => format!("{}.{}", parent, own),
+        (None, Some(own)) => own,
+        (parent, None) => parent.unwrap_or_default().to_string(),
+    };
+    let value = match objects::get(doc, field, b"V") {
+        Some(Object::Name(state)) => {
+            Some(Imported::Text(String::from_utf8_lossy(state).into_owned()))
+        }
+        Some(Object::Array(values)) => Some(Imported::List(
+            values
+                .iter()
+                .filter_map(|value| objects::text_string(objects::resolve(doc, value)))
+                .collect(),
+        )),
+        Some(other) => objects::text_string(other).map(Imported::Text),
+        None => None,
+    };
+    if let Some(value) = value {
+        found.push((name.clone(), value));
+    }
+    for kid in objects::get_array(doc, field, b"Kids").unwrap_or(&[]) {
+        collect_fdf_values(doc, kid, Some(&name), depth + 1, found);
+    }
+}
+
+fn read_fdf(data: &[u8]) -> Result<Vec<(String, Imported)>, String> {
+    let doc = parse_fdf(data)?;
+    let fdf = doc
+        .catalog()
+        .ok()
+        .and_then(|root| objects::get_dict(&doc, root, b"FDF"))
+        .ok_or("The FDF file has no form data")?;
+    let mut values = Vec::new();
+    for field in objects::get_array(&doc, fdf, b"Fields").unwrap_or(&[]) {
+        collect_fdf_values(&doc, field, None, 0, &mut values);
+    }
+    Ok(values)
+}
+
+fn read_xfdf(xml: &str) -> Result<Vec<(String, Imported)>, String> {
+    let mut reader = Reader::from_str(xml);
+    // Partial names of the fields the reader is in
+    let mut names: Vec<String> = Vec::new();
+    let mut values: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut text: Option<String> = None;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(tag)) => match tag.name().into_inner() {
+                "field" => {
+                    let name = tag
+                        .try_get_attribute("name")
+                        .ok()
+                        .flatten()
+                        .and_then(|name| name.normalized_value(XmlVersion::Implicit1_0).ok())
+                        .map(|name| name.into_owned())
+                        .unwrap_or_default();
+                    names.push(name);
+                }
+                "value" if !names.is_empty() => text = Some(String::new()),
+                _ => {}
+            },
+            Ok(Event::End(tag)) => match tag.name().into_inner() {
+                "field" => {
+                    names.pop();
+                }
+                "value" => {
+                    if let Some(value) = text.take() {
+                        values.entry(names.join(".")).or_default().push(value);
+                    }
+                }
+                _ => {}
+            },
+            Ok(Event::Empty(tag)) if tag.name().into_inner() == "value" && !names.is_empty() => {
+                values
+                    .entry(names.join("."))
+                    .or_default()
+                    .push(String::new());
+            }
+            Ok(Event::Text(content)) => {
+                if let Some(text) = text.as_mut() {
+                    text.push_str(&content.xml10_content());
+                }
+            }
+            Ok(Event::CData(content)) => {
+                if let Some(text) = text.as_mut() {
+                    text.push_str(&content.xml10_content());
+                }
+            }
+            Ok(Event::GeneralRef(entity)) => {
+                if let Some(text) = text.as_mut() {
+                    if let Ok(Some(c)) = entity.resolve_char_ref() {
+                        text.push(c);
+                    } else if let Some(s) = resolve_predefined_entity(&entity) {
+                        text.push_str(s);
+                    }
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(format!("The XFDF file is malformed: {}", e)),
+            _ => {}
+        }
+    }
+    Ok(values
+        .into_iter()
+        .map(|(name, mut values)| match values.len() {
+            1 => (name, Imported::Text(values.remove(0))),
+            _ => (name, Imported::List(values)),
+        })
+        .collect())
+}
+
+// The value to fill a field of `field_type` in with
+fn field_value(field_type: FieldType, value: Imported) -> Option<FieldValue> {
+    let first = |value: Imported| match value {
+        Imported::Text(text) => text,
+        Imported::List(values) => values.into_iter().next().unwrap_or_default(),
+    };
+    match field_type {
+        FieldType::Text | FieldType::ComboBox => Some(FieldValue::Text(first(value))),
+        FieldType::ListBox => Some(FieldValue::Selected(match value {
+            Imported::Text(text) if text.is_empty() => Vec::new(),
+            Imported::Text(text) => vec![text],
+            Imported::List(values) => values,
+        })),
+        FieldType::Checkbox => {
+            let state = first(value);
+            Some(FieldValue::Checked(!state.is_empty() && state != "Off"))
+        }
+        FieldType::Radio => {
+            let state = first(value);
+            Some(FieldValue::Text(match state.as_str() {
+                "Off" => String::new(),
+                _ => state,
+            }))
+        }
+        FieldType::PushButton | FieldType::Signature => None,
+    }
+}
+
+fn import(
+    doc: &mut Document,
+    values: Vec<(String, Imported)>,
+) -> Result<(usize, Vec<String>), String> {
+    let fields = forms::fillable_fields(doc);
+    let mut updates = Vec::new();
+    let mut skipped = Vec::new();
+    for (name, value) in values {
+        match fields
+            .get(&name)
+            .and_then(|&field_type| field_value(field_type, value))
+        {
+            Some(value) => updates.push(FieldUpdate { name, value }),
+            None => skipped.push(name),
+        }
+    }
+    if updates.is_empty() {
+        return Err("None of the fields in the form data are in this form".to_string());
+    }
+    forms::fill(doc, &updates)?;
+    Ok((updates.len(), skipped))
+}
+
+/// Saves the values of a document's form fields at `path`, as FDF or XFDF.
+#[tauri::command]
+pub async fn export_form_data(
+    app: AppHandle,
+    doc_id: DocId,
+    format: FormDataFormat,
+    path: String,
+    store: State<'_, DocumentStore>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<(), String> {
+    scope::check_write(&app, Path::new(&path))?;
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Copy).await?;
+    blocking(move || {
+        let values = doc.with_objects(|objects| Ok(forms::stored_values(objects)))?;
+        if values.is_empty() {
+            return Err("The document has no filled in form fields".to_string());
+        }
+        let count = values.len();
+        // Where the form came from, for tools that open it along with the data
+        let pdf_name = doc
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let output = match format {
+            FormDataFormat::Fdf => to_fdf(values, &pdf_name),
+            FormDataFormat::Xfdf => to_xfdf(values, &pdf_name),
+        };
+        std::fs::write(Path::new(&path), output)
+            .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        eprintln!(
+            "Exported {} form fields of document {} to {}",
+            count, doc_id, path
+        );
+        Ok(())
+    })
+    .await
+}
+
+/// Fills in a document's form from the FDF or XFDF file at `path`, told
+/// apart by their contents. Like other edits, the values are written when
+/// the document is saved.
+#[tauri::command]
+pub async fn import_form_data(
+    app: AppHandle,
+    doc_id: DocId,
+    path: String,
+    store: State<'_, DocumentStore>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<ImportedFormData, String> {
+    scope::check(&app, Path::new(&path))?;
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::FillForms).await?;
+    let data = {
+        let path = path.clone();
+        blocking(move || {
+            std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path, e))
+        })
+        .await?
+    };
+    let values = match data.starts_with(b"%FDF-") {
+        true => read_fdf(&data)?,
+        false => read_xfdf(&String::from_utf8_lossy(&data))?,
+    };
+    let (document, (filled, skipped)) =
+        apply_edit_with(doc, &store, &tiles, &scheduler, move |objects| {
+            import(objects, values)
+        })
+        .await?;
+    eprintln!(
+        "Filled in {} form fields of document {} from {} ({} skipped)",
+        filled,
+        doc_id,
+        path,
+        skipped.len()
+    );
+    Ok(ImportedFormData {
+        document,
+        filled,
+        skipped,
+    })
+}
@@ -0,0 +1,129 @@
+// Documents made up for the tests.
+
+use lopdf::{dictionary, Document, Object};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+
+use crate::cms::{encode, INTEGER, SEQUENCE};
+
+const BIT_STRING: u8 = 0x03;
+const OID: u8 = 0x06;
+const UTF8_STRING: u8 = 0x0C;
+const UTC_TIME: u8 = 0x17;
+const SET: u8 = 0x31;
+const EXPLICIT_0: u8 = 0xA0;
+
+const ID_EC_PUBLIC_KEY: &[u8] = &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01];
+const ID_ECDSA_WITH_SHA256: &[u8] = &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02];
+const ID_P256: &[u8] = &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07];
+const ID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+
+/// A document of `pages` empty Letter pages, with an Info dictionary.
+pub fn document(pages: usize) -> Document {
+    let mut doc = Document::with_version("1.7");
+    let pages_id = doc.new_object_id();
+    let kids: Vec<Object> = (0..pages)
+        .map(|_| {
+            doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            })
+            .into()
+        })
+        .collect();
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => pages as i64,
+            "Kids" => kids,
+        }),
+    );
+    let catalog = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    let info = doc.add_object(dictionary! {
+        "Title" => Object::string_literal("Untitled"),
+    });
+    doc.trailer.set("Root", catalog);
+    doc.trailer.set("Info", info);
+    doc
+}
+
+/// The bytes of `document(pages)`.
+pub fn pdf(pages: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    document(pages).save_to(&mut data).unwrap();
+    data
+}
+
+/// A P-256 key with a self-signed certificate for it, standing in for one
+/// from the keystore.
+pub struct Signer {
+    pub der: Vec<u8>,
+    key: EcdsaKeyPair,
+}
+
+impl Signer {
+    /// A new key whose certificate is issued to and by `name`.
+    pub fn new(name: &str) -> Self {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+
+        let algorithm = encode(SEQUENCE, &encode(OID, ID_ECDSA_WITH_SHA256));
+        let common_name = [
+            encode(OID, ID_COMMON_NAME),
+            encode(UTF8_STRING, name.as_bytes()),
+        ];
+        let name = encode(
+            SEQUENCE,
+            &encode(SET, &encode(SEQUENCE, &common_name.concat())),
+        );
+        let validity = [
+            encode(UTC_TIME, b"250101000000Z"),
+            encode(UTC_TIME, b"450101000000Z"),
+        ];
+        let key_algorithm = [encode(OID, ID_EC_PUBLIC_KEY), encode(OID, ID_P256)];
+        let public_key = [&[0][..], key.public_key().as_ref()].concat();
+        let public_key_info = [
+            encode(SEQUENCE, &key_algorithm.concat()),
+            encode(BIT_STRING, &public_key),
+        ];
+        let tbs = encode(
+            SEQUENCE,
+            &[
+                encode(EXPLICIT_0, &encode(INTEGER, &[2])),
+                encode(INTEGER, &[1]),
+                algorithm.clone(),
+                name.clone(),
+                encode(SEQUENCE, &validity.concat()),
+                name,
+                encode(SEQUENCE, &public_key_info.concat()),
+            ]
+            .concat(),
+        );
+        let mut signer = Signer {
+            der: Vec::new(),
+            key,
+        };
+        let signature = [&[0][..], &signer.sign(&tbs)].concat();
+        signer.der = encode(
+            SEQUENCE,
+            &[tbs, algorithm, encode(BIT_STRING, &signature)].concat(),
+        );
+        signer
+    }
+
+    /// Signs the SHA-256 digest of `message`, as a keystore would.
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.key
+            .sign(&SystemRandom::new(), message)
+            .unwrap()
+            .as_ref()
+            .to_vec()
+    }
+}
@@ -0,0 +1,258 @@
+// Flattening annotations into page content.
+//
+// The normal appearance of each annotation is drawn as a form XObject where
+// the annotation sits, and the annotation itself is removed, so every viewer
+// shows the same thing and nothing can be edited any more. Annotations without
+// an appearance stream can't be drawn this way and are kept.
+
+use std::collections::HashSet;
+
+use lopdf::content::Operation;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use serde::Serialize;
+use tauri::State;
+
+use crate::compose::field_in;
+use crate::content::{add_resource, append_content, fit_rect, transform, IDENTITY};
+use crate::edit::{apply_edit_with, selected_pages, EditedDocument};
+use crate::forms;
+use crate::objects;
+use crate::pdf::{DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scheduler::RenderScheduler;
+use crate::tiles::TileCache;
+
+// Annotation flags (PDF 32000-1, 12.5.3)
+const HIDDEN: i64 = 1 << 1;
+const NO_VIEW: i64 = 1 << 5;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlattenReport {
+    pub document: EditedDocument,
+    pub flattened: usize,
+    /// Visible annotations left in place because they have no appearance
+    pub kept: usize,
+}
+
+// The form XObject that shows an annotation normally
+fn normal_appearance(doc: &Document, annot: &Dictionary) -> Option<ObjectId> {
+    let appearances = objects::get_dict(doc, annot, b"AP")?;
+    let id = match objects::get(doc, appearances, b"N")? {
+        // Annotations with several states, like checkboxes, pick one with /AS
+        Object::Dictionary(states) => {
+            let state = objects::get_name(doc, annot, b"AS")?;
+            states.get(state).ok()?.as_reference().ok()?
+        }
+        _ => appearances.get(b"N").ok()?.as_reference().ok()?,
+    };
+    matches!(doc.get_object(id), Ok(Object::Stream(_))).then_some(id)
+}
+
+// Flattens the annotations of one page, or only its form widgets, noting
+// whether any of them were widgets. Returns the number flattened and the
+// number kept.
+fn flatten_page(
+    doc: &mut Document,
+    page_id: ObjectId,
+    widgets_only: bool,
+    widgets: &mut bool,
+) -> Result<(usize, usize), String> {
+    let Some(annots) = doc
+        .get_dictionary(page_id)
+        .ok()
+        .and_then(|page| objects::get_array(doc, page, b"Annots"))
+        .map(|annots| annots.to_vec())
+    else {
+        return Ok((0, 0));
+    };
+
+    let mut remaining = Vec::new();
+    let mut drawn = Vec::new();
+    let mut popups = HashSet::new();
+    let mut kept = 0;
+    for entry in annots {
+        let Ok(annot) = objects::resolve(doc, &entry).as_dict() else {
+            continue;
+        };
+        let subtype = objects::get_name(doc, annot, b"Subtype");
+        let flags = objects::get(doc, annot, b"F")
+            .and_then(|f| f.as_i64().ok())
+            .unwrap_or(0);
+        // Popups belong to the annotation they open from
+        let skipped = widgets_only && subtype != Some(b"Widget");
+        if skipped || subtype == Some(b"Popup") || flags & (HIDDEN | NO_VIEW) != 0 {
+            remaining.push(entry);
+            continue;
+        }
+
+        let appearance = normal_appearance(doc, annot);
+        let rect = annot.get(b"Rect").ok().and_then(|r| objects::rect(doc, r));
+        let (Some(appearance), Some(rect)) = (appearance, rect) else {
+            // Links have no appearance of their own and keep working as they are
+            if subtype != Some(b"Link") {
+                kept += 1;
+            }
+            remaining.push(entry);
+            continue;
+        };
+
+        let Ok(Object::Stream(form)) = doc.get_object(appearance) else {
+            continue;
+        };
+        let bbox = objects::get(doc, &form.dict, b"BBox")
+            .and_then(|b| objects::rect(doc, b))
+            .unwrap_or(rect);
+        let matrix = objects::get_array(doc, &form.dict, b"Matrix")
+            .and_then(|m| transform(doc, m))
+            .unwrap_or(IDENTITY);
+        drawn.push((appearance, fit_rect(bbox, matrix, rect)));
+
+        if let Ok(popup) = annot.get(b"Popup").and_then(Object::as_reference) {
+            popups.insert(popup);
+        }
+        *widgets |= subtype == Some(b"Widget");
+    }
+    remaining.retain(|entry| !entry.as_reference().is_ok_and(|id| popups.contains(&id)));
+
+    let mut operations = Vec::new();
+    for &(appearance, placement) in &drawn {
+        // Appearance streams sometimes leave out that they are forms
+        if let Ok(Object::Stream(form)) = doc.get_object_mut(appearance) {
+            form.dict.set("Type", "XObject");
+            form.dict.set("Subtype", "Form");
+        }
+        let name = add_resource(doc, page_id, b"XObject", "Flat", appearance.into())?;
+        operations.extend([
+            Operation::new("q", vec![]),
+            Operation::new("cm", placement.iter().map(|&v| v.into()).collect()),
+            Operation::new("Do", vec![Object::Name(name.into_bytes())]),
+            Operation::new("Q", vec![]),
+        ]);
+    }
+    if !operations.is_empty() {
+        append_content(doc, page_id, operations)?;
+    }
+
+    let page = doc.get_dictionary_mut(page_id).map_err(|e| e.to_string())?;
+    if remaining.is_empty() {
+        page.remove(b"Annots");
+    } else {
+        page.set("Annots", remaining);
+    }
+    Ok((drawn.len(), kept))
+}
+
+/// Drops widgets that are no longer on any page from the document's form, and
+/// the form itself once none of its fields are left.
+pub fn prune_form(doc: &mut Document) -> Result<(), String> {
+    let mut annots = HashSet::new();
+    for page_id in doc.get_pages().into_values() {
+        let Ok(page) = doc.get_dictionary(page_id) else {
+            continue;
+        };
+        for annot in objects::get_array(doc, page, b"Annots").unwrap_or(&[]) {
+            annots.extend(annot.as_reference().ok());
+        }
+    }
+
+    let catalog = doc.catalog().map_err(|e| e.to_string())?;
+    let Some(form) = objects::get_dict(doc, catalog, b"AcroForm") else {
+        return Ok(());
+    };
+    let fields: Vec<Object> = objects::get_array(doc, form, b"Fields")
+        .unwrap_or(&[])
+        .iter()
+        .filter(|field| field_in(doc, field, &annots, 0))
+        .cloned()
+        .collect();
+    let mut form = form.clone();
+    let catalog = doc.catalog_mut().map_err(|e| e.to_string())?;
+    if fields.is_empty() {
+        catalog.remove(b"AcroForm");
+    } else {
+        form.set("Fields", fields);
+        catalog.set("AcroForm", form);
+    }
+    Ok(())
+}
+
+fn flatten(doc: &mut Document, pages: Option<&[i32]>) -> Result<(usize, usize), String> {
+    let ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    let selected: Vec<ObjectId> = match pages {
+        Some(pages) => pages.iter().map(|&page| ids[page as usize]).collect(),
+        None => ids,
+    };
+
+    let mut widgets = false;
+    let (mut flattened, mut kept) = (0, 0);
+    for page_id in selected {
+        let (page_flattened, page_kept) = flatten_page(doc, page_id, false, &mut widgets)?;
+        flattened += page_flattened;
+        kept += page_kept;
+    }
+    if widgets {
+        prune_form(doc)?;
+    }
+    Ok((flattened, kept))
+}
+
+/// Burns the values of form fields into the pages and removes the form, so
+/// the fields can't be changed any more. Forms that leave drawing their
+/// fields to the viewer are drawn first. Returns the number of widgets
+/// flattened.
+pub fn flatten_form(doc: &mut Document) -> Result<usize, String> {
+    let needs_appearances = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| objects::get_dict(doc, catalog, b"AcroForm"))
+        .and_then(|form| objects::get(doc, form, b"NeedsAppearances"))
+        .and_then(|needs| needs.as_bool().ok())
+        .unwrap_or(false);
+    if needs_appearances {
+        forms::draw_appearances(doc)?;
+    }
+
+    let ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    let mut widgets = false;
+    let mut flattened = 0;
+    for page_id in ids {
+        flattened += flatten_page(doc, page_id, true, &mut widgets)?.0;
+    }
+    if widgets {
+        prune_form(doc)?;
+    }
+    Ok(flattened)
+}
+
+/// Burns annotations and form field values into the content of `pages`, or
+/// of every page.
+#[tauri::command]
+pub async fn flatten_annotations(
+    doc_id: DocId,
+    pages: Option<Vec<i32>>,
+    store: State<'_, DocumentStore>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<FlattenReport, String> {
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Modify).await?;
+    let pages = pages
+        .map(|pages| selected_pages(&doc, &pages))
+        .transpose()?;
+    let (document, (flattened, kept)) =
+        apply_edit_with(doc, &store, &tiles, &scheduler, move |objects| {
+            flatten(objects, pages.as_deref())
+        })
+        .await?;
+    eprintln!(
+        "Flattened {} annotations of document {} ({} kept)",
+        flattened, doc_id, kept
+    );
+    Ok(FlattenReport {
+        document,
+        flattened,
+        kept,
+    })
+}
@@ -0,0 +1,1299 @@
+// Reading and filling in interactive forms (AcroForm).
+//
+// Fields go by their full names, "parent.child", and are filled in with the
+// values shown to the user: the text of text fields, whether a checkbox is
+// checked, the export value of the chosen radio button or list option. Each
+// change also updates the widgets, so every viewer shows the new values
+// without generating appearances itself. Checkboxes and radio buttons switch
+// to one of the appearances they come with. Text and choice fields are drawn
+// again in the font and color their /DA asks for, as Acrobat would. Like page
+// edits, the values are written when the document is saved.
+//
+// Signature fields are listed but signed through `signing.rs`.
+//
+// XFA forms, which some files carry alongside or instead of the AcroForm,
+// aren't supported. Static ones come with AcroForm fields and pages drawn in
+// full, so those are filled in as usual, and the XFA is dropped so other
+// viewers don't show its stale values. Dynamic ones are laid out by the
+// viewer from the XFA alone; their pages only hold a placeholder telling to
+// open the file in Adobe Reader, so they can't be filled in at all.
+// `FormCapabilities` tells which kind a document has, for the frontend to
+// explain.
+
+use std::collections::HashMap;
+
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::edit::{apply_edit, EditedDocument};
+use crate::objects::{self, PageSpace, MAX_DEPTH};
+use crate::pdf::{blocking, Bounds, DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scheduler::RenderScheduler;
+use crate::stamp::{text_width, win_ansi, StandardFont};
+use crate::tiles::TileCache;
+
+// Field flags (PDF 32000-1, 12.7.3.1, 12.7.4)
+const READ_ONLY: i64 = 1 << 0;
+const REQUIRED: i64 = 1 << 1;
+const MULTILINE: i64 = 1 << 12;
+const PASSWORD: i64 = 1 << 13;
+const RADIO: i64 = 1 << 15;
+const PUSH_BUTTON: i64 = 1 << 16;
+const COMBO: i64 = 1 << 17;
+const EDIT: i64 = 1 << 18;
+const MULTI_SELECT: i64 = 1 << 21;
+const COMB: i64 = 1 << 24;
+
+// Font size of fields sized automatically (size 0 in /DA), at most
+const AUTO_FONT_SIZE: f32 = 12.0;
+const MIN_FONT_SIZE: f32 = 4.0;
+const LINE_HEIGHT: f32 = 1.15;
+// Background of selected list options, as Acrobat draws it
+const SELECTED_BACKGROUND: [f32; 3] = [0.6, 0.757, 0.855];
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum FieldType {
+    Text,
+    Checkbox,
+    Radio,
+    PushButton,
+    ComboBox,
+    ListBox,
+    Signature,
+}
+
+/// A field's value, in the shape its type calls for.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum FieldValue {
+    /// Text and combo box fields, and the export value of the chosen radio
+    /// button ("" for none)
+    Text(String),
+    /// Checkboxes
+    Checked(bool),
+    /// Export values of the selected list box options
+    Selected(Vec<String>),
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChoiceOption {
+    /// What the field's value is set to
+    pub value: String,
+    /// What the list shows
+    pub label: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldWidget {
+    pub page: i32,
+    pub bounds: Bounds,
+    /// The export value a checkbox or radio button stands for
+    pub on_value: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FormField {
+    pub name: String,
+    pub field_type: FieldType,
+    /// None for push buttons and signature fields
+    pub value: Option<FieldValue>,
+    /// The tooltip (/TU), which often says what to fill in
+    pub label: Option<String>,
+    pub read_only: bool,
+    pub required: bool,
+    /// Text fields taking several lines
+    pub multiline: bool,
+    /// Text fields showing their text hidden
+    pub password: bool,
+    pub max_length: Option<i64>,
+    /// Combo boxes that also take text of the user's own
+    pub editable: bool,
+    /// List boxes where more than one option can be selected
+    pub multi_select: bool,
+    pub options: Vec<ChoiceOption>,
+    pub widgets: Vec<FieldWidget>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum FormKind {
+    /// No form fields
+    None,
+    AcroForm,
+    /// XFA along with AcroForm fields and fully drawn pages
+    StaticXfa,
+    /// XFA alone, which Nanodoc can't lay out
+    DynamicXfa,
+}
+
+/// What Nanodoc can do with the form of a document.
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FormCapabilities {
+    pub doc_id: DocId,
+    pub kind: FormKind,
+    /// True if `fill_form_fields` works on it
+    pub fillable: bool,
+    pub field_count: usize,
+    /// The XFA packets, e.g. "template" and "datasets"
+    pub xfa_packets: Vec<String>,
+    /// What to tell the user, for XFA forms
+    pub message: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldUpdate {
+    pub name: String,
+    pub value: FieldValue,
+}
+
+// A terminal field, with what it inherits from its parents
+struct Field {
+    name: String,
+    id: Option<ObjectId>,
+    field_type: FieldType,
+    flags: i64,
+    value: Object,
+    appearance: Option<Vec<u8>>,
+    quadding: i64,
+    max_length: Option<i64>,
+    options: Vec<(String, String)>,
+    // Each with the appearance state it turns on, for buttons
+    widgets: Vec<(ObjectId, Option<Vec<u8>>)>,
+    // Export values of buttons, by widget, where names alone won't do
+    button_values: Vec<String>,
+}
+
+impl Field {
+    // The export value of a button's widget
+    fn on_value(&self, index: usize, state: &[u8]) -> String {
+        self.button_values
+            .get(index)
+            .cloned()
+            .unwrap_or_else(|| String::from_utf8_lossy(state).into_owned())
+    }
+
+    fn value(&self) -> Option<FieldValue> {
+        let texts = || match &self.value {
+            Object::Array(values) => values.iter().filter_map(objects::text_string).collect(),
+            value => objects::text_string(value).into_iter().collect::<Vec<_>>(),
+        };
+        let state = || self.value.as_name().ok().filter(|state| *state != b"Off");
+        match self.field_type {
+            FieldType::Text | FieldType::ComboBox => Some(FieldValue::Text(
+                texts().into_iter().next().unwrap_or_default(),
+            )),
+            FieldType::ListBox => Some(FieldValue::Selected(texts())),
+            FieldType::Checkbox => Some(FieldValue::Checked(state().is_some())),
+            FieldType::Radio => Some(FieldValue::Text(state().map_or_else(String::new, |on| {
+                self.widgets
+                    .iter()
+                    .enumerate()
+                    .find(|(_, (_, state))| state.as_deref() == Some(on))
+                    .map_or_else(
+                        || String::from_utf8_lossy(on).into_owned(),
+                        |(i, _)| self.on_value(i, on),
+                    )
+            }))),
+            FieldType::PushButton | FieldType::Signature => None,
+        }
+    }
+
+    // The label shown for an export value of a choice field
+    fn label_of<'a>(&'a self, value: &'a str) -> &'a str {
+        self.options
+            .iter()
+            .find(|(export, _)| export == value)
+            .map_or(value, |(_, label)| label)
+    }
+}
+
+#[derive(Clone, Default)]
+struct Inherited {
+    field_type: Option<Vec<u8>>,
+    flags: i64,
+    value: Option<Object>,
+    appearance: Option<Vec<u8>>,
+    quadding: i64,
+}
+
+// The state a button widget turns on: the name of its normal appearance that
+// isn't /Off
+fn on_state(doc: &Document, widget: &Dictionary) -> Option<Vec<u8>> {
+    let appearances = objects::get_dict(doc, widget, b"AP")?;
+    let states = objects::get(doc, appearances, b"N")
+        .or_else(|| objects::get(doc, appearances, b"D"))?
+        .as_dict()
+        .ok()?;
+    states
+        .iter()
+        .map(|(name, _)| name)
+        .find(|name| name.as_slice() != b"Off")
+        .cloned()
+}
+
+fn choice_options(doc: &Document, dict: &Dictionary) -> Vec<(String, String)> {
+    objects::get_array(doc, dict, b"Opt")
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|option| match objects::resolve(doc, option) {
+            Object::Array(pair) => {
+                let text = |i: usize| objects::text_string(objects::resolve(doc, pair.get(i)?));
+                Some((text(0)?, text(1)?))
+            }
+            other => objects::text_string(other).map(|text| (text.clone(), text)),
+        })
+        .collect()
+}
+
+fn collect_fields(
+    doc: &Document,
+    field: &Object,
+    parent: Option<&str>,
+    inherited: &Inherited,
+    depth: usize,
+    found: &mut Vec<Field>,
+) {
+    let Ok(dict) = objects::resolve(doc, field).as_dict() else {
+        return;
+    };
+    if depth > MAX_DEPTH {
+        return;
+    }
+    let name = match (parent, objects::get_text(doc, dict, b"T")) {
+        (Some(parent), Some(own)) => format!("{}.{}", parent, own),
+        (None, Some(own)) => own,
+        (parent, None) => parent.unwrap_or_default().to_string(),
+    };
+    let mut inherited = inherited.clone();
+    if let Some(field_type) = objects::get_name(doc, dict, b"FT") {
+        inherited.field_type = Some(field_type.to_vec());
+    }
+    if let Some(flags) = objects::get(doc, dict, b"Ff").and_then(|f| f.as_i64().ok()) {
+        inherited.flags = flags;
+    }
+    if let Some(value) = objects::get(doc, dict, b"V") {
+        inherited.value = Some(value.clone());
+    }
+    if let Ok(appearance) =
+        objects::get(doc, dict, b"DA").map_or(Err(()), |da| da.as_str().map_err(|_| ()))
+    {
+        inherited.appearance = Some(appearance.to_vec());
+    }
+    if let Some(quadding) = objects::get(doc, dict, b"Q").and_then(|q| q.as_i64().ok()) {
+        inherited.quadding = quadding;
+    }
+
+    let kids = objects::get_array(doc, dict, b"Kids").unwrap_or(&[]);
+    // Kids without names are the field's widgets
+    let (fields, widgets): (Vec<&Object>, Vec<&Object>) = kids.iter().partition(|kid| {
+        objects::resolve(doc, kid)
+            .as_dict()
+            .is_ok_and(|kid| kid.has(b"T"))
+    });
+    for kid in fields {
+        collect_fields(doc, kid, Some(&name), &inherited, depth + 1, found);
+    }
+    if !kids.is_empty() && widgets.is_empty() {
+        return;
+    }
+    let flags = inherited.flags;
+    let field_type = match inherited.field_type.as_deref() {
+        Some(b"Tx") => FieldType::Text,
+        Some(b"Btn") if flags & PUSH_BUTTON != 0 => FieldType::PushButton,
+        Some(b"Btn") if flags & RADIO != 0 => FieldType::Radio,
+        Some(b"Btn") => FieldType::Checkbox,
+        Some(b"Ch") if flags & COMBO != 0 => FieldType::ComboBox,
+        Some(b"Ch") => FieldType::ListBox,
+        Some(b"Sig") => FieldType::Signature,
+        _ => return,
+    };
+    let widgets: Vec<ObjectId> = match widgets.is_empty() {
+        // The field is its own widget
+        true => field.as_reference().into_iter().collect(),
+        false => widgets
+            .iter()
+            .filter_map(|widget| widget.as_reference().ok())
+            .collect(),
+    };
+    let widgets = widgets
+        .into_iter()
+        .map(|id| {
+            let state = doc
+                .get_dictionary(id)
+                .ok()
+                .and_then(|widget| on_state(doc, widget));
+            (id, state)
+        })
+        .collect();
+    let options = choice_options(doc, dict);
+    let button_values = match field_type {
+        FieldType::Checkbox | FieldType::Radio => options.iter().map(|(v, _)| v.clone()).collect(),
+        _ => Vec::new(),
+    };
+    found.push(Field {
+        name,
+        id: field.as_reference().ok(),
+        field_type,
+        flags,
+        value: inherited.value.unwrap_or(Object::Null),
+        appearance: inherited.appearance,
+        quadding: inherited.quadding,
+        max_length: objects::get(doc, dict, b"MaxLen").and_then(|m| m.as_i64().ok()),
+        options,
+        widgets,
+        button_values,
+    });
+}
+
+fn form_fields(doc: &Document) -> Vec<Field> {
+    let Some(form) = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| objects::get_dict(doc, catalog, b"AcroForm"))
+    else {
+        return Vec::new();
+    };
+    // The form's defaults for variable text
+    let inherited = Inherited {
+        appearance: objects::get(doc, form, b"DA")
+            .and_then(|da| da.as_str().ok())
+            .map(<[u8]>::to_vec),
+        quadding: objects::get(doc, form, b"Q")
+            .and_then(|q| q.as_i64().ok())
+            .unwrap_or(0),
+        ..Inherited::default()
+    };
+    let mut fields = Vec::new();
+    for field in objects::get_array(doc, form, b"Fields").unwrap_or(&[]) {
+        collect_fields(doc, field, None, &inherited, 0, &mut fields);
+    }
+    fields
+}
+
+fn list_fields(doc: &Document) -> Vec<FormField> {
+    let mut widget_pages = HashMap::new();
+    for (number, page_id) in doc.get_pages() {
+        let Ok(page) = doc.get_dictionary(page_id) else {
+            continue;
+        };
+        for annot in objects::get_array(doc, page, b"Annots").unwrap_or(&[]) {
+            if let Ok(id) = annot.as_reference() {
+                widget_pages.insert(id, (number as i32 - 1, page_id));
+            }
+        }
+    }
+
+    form_fields(doc)
+        .into_iter()
+        .map(|field| {
+            let widgets = field
+                .widgets
+                .iter()
+                .enumerate()
+                .filter_map(|(i, (id, state))| {
+                    let &(page, page_id) = widget_pages.get(id)?;
+                    let widget = doc.get_dictionary(*id).ok()?;
+                    let rect = objects::rect(doc, objects::get(doc, widget, b"Rect")?)?;
+                    let on_value = match field.field_type {
+                        FieldType::Checkbox | FieldType::Radio => {
+                            state.as_deref().map(|state| field.on_value(i, state))
+                        }
+                        _ => None,
+                    };
+                    Some(FieldWidget {
+                        page,
+                        bounds: PageSpace::new(doc, page_id).rect(rect),
+                        on_value,
+                    })
+                })
+                .collect();
+            let label = field
+                .id
+                .and_then(|id| doc.get_dictionary(id).ok())
+                .and_then(|dict| objects::get_text(doc, dict, b"TU"));
+            FormField {
+                value: field.value(),
+                label,
+                read_only: field.flags & READ_ONLY != 0,
+                required: field.flags & REQUIRED != 0,
+                multiline: field.field_type == FieldType::Text && field.flags & MULTILINE != 0,
+                password: field.field_type == FieldType::Text && field.flags & PASSWORD != 0,
+                max_length: field.max_length,
+                editable: field.field_type == FieldType::ComboBox && field.flags & EDIT != 0,
+                multi_select: field.field_type == FieldType::ListBox
+                    && field.flags & MULTI_SELECT != 0,
+                options: match field.field_type {
+                    FieldType::ComboBox | FieldType::ListBox => field
+                        .options
+                        .iter()
+                        .map(|(value, label)| ChoiceOption {
+                            value: value.clone(),
+                            label: label.clone(),
+                        })
+                        .collect(),
+                    _ => Vec::new(),
+                },
+                name: field.name,
+                field_type: field.field_type,
+                widgets,
+            }
+        })
+        .collect()
+}
+
+// Character widths of the font of a field
+enum Metrics {
+    Standard(StandardFont),
+    Widths {
+        first: i64,
+        widths: Vec<f32>,
+        missing: f32,
+    },
+}
+
+impl Metrics {
+    fn of(doc: &Document, font: &Dictionary) -> Self {
+        let base = objects::get_name(doc, font, b"BaseFont").unwrap_or_default();
+        let standard = |base: &[u8]| {
+            let base = String::from_utf8_lossy(base).to_lowercase();
+            if base.starts_with("helvetica-bold") || base.starts_with("arial,bold") {
+                Some(StandardFont::HelveticaBold)
+            } else if base.starts_with("helvetica") || base.starts_with("arial") {
+                Some(StandardFont::Helvetica)
+            } else if base.starts_with("times") {
+                Some(StandardFont::TimesRoman)
+            } else if base.starts_with("courier") {
+                Some(StandardFont::Courier)
+            } else {
+                None
+            }
+        };
+        let widths = objects::get_array(doc, font, b"Widths");
+        match (widths, standard(base)) {
+            (Some(widths), _) => Metrics::Widths {
+                first: objects::get(doc, font, b"FirstChar")
+                    .and_then(|f| f.as_i64().ok())
+                    .unwrap_or(0),
+                widths: widths
+                    .iter()
+                    .map(|w| objects::number(objects::resolve(doc, w)).unwrap_or(0.0))
+                    .collect(),
+                missing: objects::get_dict(doc, font, b"FontDescriptor")
+                    .and_then(|d| objects::get(doc, d, b"MissingWidth"))
+                    .and_then(objects::number)
+                    .unwrap_or(500.0),
+            },
+            (None, Some(font)) => Metrics::Standard(font),
+            (None, None) => Metrics::Standard(StandardFont::Helvetica),
+        }
+    }
+
+    fn width(&self, bytes: &[u8], font_size: f32) -> f32 {
+        match self {
+            Metrics::Standard(font) => text_width(*font, bytes, font_size),
+            Metrics::Widths {
+                first,
+                widths,
+                missing,
+            } => {
+                let units: f32 = bytes
+                    .iter()
+                    .map(|&b| {
+                        usize::try_from(b as i64 - first)
+                            .ok()
+                            .and_then(|i| widths.get(i).copied())
+                            .unwrap_or(*missing)
+                    })
+                    .sum();
+                units / 1000.0 * font_size
+            }
+        }
+    }
+
+    fn cap_height(&self) -> f32 {
+        match self {
+            Metrics::Standard(font) => font.cap_height(),
+            Metrics::Widths { .. } => 0.7,
+        }
+    }
+}
+
+// The font a field's text is drawn in: its resource name, the font itself,
+// and its size (0 for automatic) and color operators from /DA
+struct FieldFont {
+    name: Vec<u8>,
+    font: Object,
+    metrics: Metrics,
+    size: f32,
+    color: Vec<Operation>,
+}
+
+// Resolves a field's /DA against the form's resources. Fonts that don't take
+// WinAnsi text, or are missing, give way to Helvetica, which is added to the
+// form's resources as /Helv.
+fn field_font(doc: &mut Document, appearance: Option<&[u8]>) -> Result<FieldFont, String> {
+    let operations = appearance
+        .and_then(|da| Content::decode(da).ok())
+        .map(|content| content.operations)
+        .unwrap_or_default();
+    let mut name = None;
+    let mut size = 0.0;
+    let mut color = Vec::new();
+    for operation in operations {
+        match operation.operator.as_str() {
+            "Tf" => {
+                name = operation
+                    .operands
+                    .first()
+                    .and_then(|n| n.as_name().ok())
+                    .map(<[u8]>::to_vec);
+                size = operation
+                    .operands
+                    .get(1)
+                    .and_then(objects::number)
+                    .unwrap_or(0.0);
+            }
+            "g" | "rg" | "k" => color = vec![operation],
+            _ => {}
+        }
+    }
+
+    let form = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| objects::get_dict(doc, catalog, b"AcroForm"))
+        .ok_or("The document has no form")?;
+    let resources = objects::get_dict(doc, form, b"DR");
+    let fonts = resources.and_then(|resources| objects::get_dict(doc, resources, b"Font"));
+    let usable = |name: &[u8]| {
+        let entry = fonts?.get(name).ok()?;
+        let font = objects::resolve(doc, entry).as_dict().ok()?;
+        let simple = objects::get_name(doc, font, b"Subtype") != Some(b"Type0");
+        simple.then(|| (entry.clone(), Metrics::of(doc, font)))
+    };
+    let found = match name.as_deref().and_then(usable) {
+        Some(found) => Some((name.unwrap_or_default(), found)),
+        // Added for an earlier field
+        None => usable(b"Helv").map(|found| (b"Helv".to_vec(), found)),
+    };
+    if let Some((name, (font, metrics))) = found {
+        return Ok(FieldFont {
+            name,
+            font,
+            metrics,
+            size,
+            color,
+        });
+    }
+
+    // The resources are written back as direct dictionaries, which saves
+    // finding out which of them are objects of their own
+    let mut resources = resources.cloned().unwrap_or_default();
+    let mut fonts = fonts.cloned().unwrap_or_default();
+    let form_id = match doc.catalog().map_err(|e| e.to_string())?.get(b"AcroForm") {
+        Ok(Object::Reference(id)) => Some(*id),
+        _ => None,
+    };
+    let helvetica = Object::Reference(StandardFont::Helvetica.add_to(doc));
+    fonts.set("Helv", helvetica.clone());
+    resources.set("Font", fonts);
+    let form = match form_id {
+        Some(id) => doc.get_dictionary_mut(id).map_err(|e| e.to_string())?,
+        None => doc
+            .catalog_mut()
+            .map_err(|e| e.to_string())?
+            .get_mut(b"AcroForm")
+            .and_then(Object::as_dict_mut)
+            .map_err(|e| e.to_string())?,
+    };
+    form.set("DR", resources);
+    Ok(FieldFont {
+        name: b"Helv".to_vec(),
+        font: helvetica,
+        metrics: Metrics::Standard(StandardFont::Helvetica),
+        size,
+        color,
+    })
+}
+
+// Operators setting a color from an array of 1, 3 or 4 components
+fn color_operation(doc: &Document, color: Option<&Object>, stroke: bool) -> Option<Operation> {
+    let values: Vec<Object> = color?
+        .as_array()
+        .ok()?
+        .iter()
+        .filter_map(|c| objects::number(objects::resolve(doc, c)))
+        .map(Object::from)
+        .collect();
+    let operator = match (values.len(), stroke) {
+        (1, false) => "g",
+        (1, true) => "G",
+        (3, false) => "rg",
+        (3, true) => "RG",
+        (4, false) => "k",
+        (4, true) => "K",
+        _ => return None,
+    };
+    Some(Operation::new(operator, values))
+}
+
+// Breaks text into lines no wider than `width`, at spaces where it can
+fn wrap(text: &[u8], metrics: &Metrics, font_size: f32, width: f32) -> Vec<Vec<u8>> {
+    let mut lines = Vec::new();
+    for paragraph in text.split(|&b| b == b'\n') {
+        let mut line: Vec<u8> = Vec::new();
+        for word in paragraph.split(|&b| b == b' ') {
+            let candidate = match line.is_empty() {
+                true => word.to_vec(),
+                false => [line.as_slice(), b" ", word].concat(),
+            };
+            if line.is_empty() || metrics.width(&candidate, font_size) <= width {
+                line = candidate;
+            } else {
+                lines.push(std::mem::replace(&mut line, word.to_vec()));
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+// What a text or choice widget shows
+enum Shown<'a> {
+    Text(&'a str),
+    List {
+        labels: Vec<&'a str>,
+        selected: Vec<usize>,
+        top: usize,
+    },
+}
+
+// Draws a text or choice widget anew with `shown`
+fn draw_widget(
+    doc: &mut Document,
+    widget_id: ObjectId,
+    field: &Field,
+    shown: &Shown,
+) -> Result<(), String> {
+    let font = field_font(doc, field.appearance.as_deref())?;
+    let widget = doc.get_dictionary(widget_id).map_err(|e| e.to_string())?;
+    let Some(rect) = objects::get(doc, widget, b"Rect").and_then(|r| objects::rect(doc, r)) else {
+        return Ok(());
+    };
+    let characteristics = objects::get_dict(doc, widget, b"MK");
+    let rotation = characteristics
+        .and_then(|mk| objects::get(doc, mk, b"R"))
+        .and_then(|r| r.as_i64().ok())
+        .unwrap_or(0)
+        .rem_euclid(360);
+    let (mut width, mut height) = ((rect[2] - rect[0]).abs(), (rect[3] - rect[1]).abs());
+    if rotation == 90 || rotation == 270 {
+        (width, height) = (height, width);
+    }
+    let background = color_operation(
+        doc,
+        characteristics.and_then(|mk| objects::get(doc, mk, b"BG")),
+        false,
+    );
+    let border = color_operation(
+        doc,
+        characteristics.and_then(|mk| objects::get(doc, mk, b"BC")),
+        true,
+    );
+    let border_width = match border {
+        Some(_) => objects::get_dict(doc, widget, b"BS")
+            .and_then(|bs| objects::get(doc, bs, b"W"))
+            .and_then(objects::number)
+            .unwrap_or(1.0),
+        None => 0.0,
+    };
+
+    let mut operations = Vec::new();
+    let rect_operation = |x: f32, y: f32, w: f32, h: f32| {
+        Operation::new("re", vec![x.into(), y.into(), w.into(), h.into()])
+    };
+    if let Some(background) = background {
+        operations.push(background);
+        operations.push(rect_operation(0.0, 0.0, width, height));
+        operations.push(Operation::new("f", vec![]));
+    }
+    if let Some(border) = border.filter(|_| border_width > 0.0) {
+        let half = border_width / 2.0;
+        operations.push(border);
+        operations.push(Operation::new("w", vec![border_width.into()]));
+        operations.push(rect_operation(
+            half,
+            half,
+            width - border_width,
+            height - border_width,
+        ));
+        operations.push(Operation::new("S", vec![]));
+    }
+
+    let padding = (2.0 * border_width).max(2.0);
+    let inner = width - 2.0 * padding;
+    operations.push(Operation::new("BMC", vec!["Tx".into()]));
+    operations.push(Operation::new("q", vec![]));
+    operations.push(rect_operation(
+        border_width,
+        border_width,
+        width - 2.0 * border_width,
+        height - 2.0 * border_width,
+    ));
+    operations.push(Operation::new("W", vec![]));
+    operations.push(Operation::new("n", vec![]));
+
+    let metrics = &font.metrics;
+    let multiline = field.flags & MULTILINE != 0 && field.field_type == FieldType::Text;
+    let mut lines: Vec<(f32, Vec<u8>)> = Vec::new();
+    let font_size;
+    match shown {
+        Shown::Text(text) => {
+            let text = match field.flags & PASSWORD != 0 {
+                true => "*".repeat(text.chars().count()),
+                false => text.to_string(),
+            };
+            let bytes = win_ansi(&text.replace('\r', ""));
+            if multiline {
+                font_size = if font.size > 0.0 {
+                    font.size
+                } else {
+                    AUTO_FONT_SIZE
+                };
+                let top = height - padding - font_size;
+                for (i, line) in wrap(&bytes, metrics, font_size, inner)
+                    .into_iter()
+                    .enumerate()
+                {
+                    lines.push((top - i as f32 * font_size * LINE_HEIGHT, line));
+                }
+            } else {
+                font_size = match font.size {
+                    size if size > 0.0 => size,
+                    _ => {
+                        let fitting = (height - 2.0 * padding) / LINE_HEIGHT;
+                        let wide = metrics.width(&bytes, fitting).max(0.01);
+                        fitting
+                            .min(fitting * inner / wide)
+                            .clamp(MIN_FONT_SIZE, AUTO_FONT_SIZE)
+                    }
+                };
+                let baseline = (height - font_size * metrics.cap_height()) / 2.0;
+                lines.push((baseline, bytes));
+            }
+        }
+        Shown::List {
+            labels,
+            selected,
+            top,
+        } => {
+            font_size = if font.size > 0.0 {
+                font.size
+            } else {
+                AUTO_FONT_SIZE
+            };
+            let line_height = font_size * LINE_HEIGHT;
+            for (row, i) in (*top..labels.len()).enumerate() {
+                let y = height - padding - (row + 1) as f32 * line_height;
+                if y + line_height < 0.0 {
+                    break;
+                }
+                if selected.contains(&i) {
+                    operations.push(Operation::new(
+                        "rg",
+                        SELECTED_BACKGROUND.iter().map(|&c| c.into()).collect(),
+                    ));
+                    operations.push(rect_operation(
+                        border_width,
+                        y,
+                        width - 2.0 * border_width,
+                        line_height,
+                    ));
+                    operations.push(Operation::new("f", vec![]));
+                }
+                lines.push((
+                    y + (line_height - font_size * metrics.cap_height()) / 2.0,
+                    win_ansi(labels[i]),
+                ));
+            }
+        }
+    }
+
+    operations.push(Operation::new("BT", vec![]));
+    operations.extend(font.color.iter().cloned());
+    if font.color.is_empty() {
+        operations.push(Operation::new("g", vec![0.into()]));
+    }
+    operations.push(Operation::new(
+        "Tf",
+        vec![Object::Name(font.name.clone()), font_size.into()],
+    ));
+    let comb = field
+        .max_length
+        .filter(|&n| n > 0 && field.flags & COMB != 0 && !multiline);
+    for (y, line) in lines {
+        let glyphs: Vec<Vec<u8>> = match comb {
+            // One character to each cell
+            Some(_) => line.iter().map(|&b| vec![b]).collect(),
+            None => vec![line],
+        };
+        for (i, glyph) in glyphs.into_iter().enumerate() {
+            let text_width = metrics.width(&glyph, font_size);
+            let x = match comb {
+                Some(cells) => {
+                    let cell = width / cells as f32;
+                    cell * i as f32 + (cell - text_width) / 2.0
+                }
+                None => match field.quadding {
+                    1 => (width - text_width) / 2.0,
+                    2 => width - padding - text_width,
+                    _ => padding,
+                },
+            };
+            operations.push(Operation::new(
+                "Tm",
+                vec![1.into(), 0.into(), 0.into(), 1.into(), x.into(), y.into()],
+            ));
+            operations.push(Operation::new(
+                "Tj",
+                vec![Object::String(glyph, StringFormat::Literal)],
+            ));
+        }
+    }
+    operations.push(Operation::new("ET", vec![]));
+    operations.push(Operation::new("Q", vec![]));
+    operations.push(Operation::new("EMC", vec![]));
+    let content = Content { operations }
+        .encode()
+        .map_err(|e| format!("Failed to draw field {}: {}", field.name, e))?;
+
+    let mut fonts = Dictionary::new();
+    fonts.set(font.name, font.font);
+    let mut dict = dictionary! {
+        "Type" => "XObject",
+        "Subtype" => "Form",
+        "BBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+        "Resources" => dictionary! {
+            "Font" => fonts,
+        },
+    };
+    // The viewer fits the turned box to the widget
+    let matrix: Option<[i64; 4]> = match rotation {
+        90 => Some([0, 1, -1, 0]),
+        180 => Some([-1, 0, 0, -1]),
+        270 => Some([0, -1, 1, 0]),
+        _ => None,
+    };
+    if let Some(matrix) = matrix {
+        let mut values: Vec<Object> = matrix.iter().map(|&v| v.into()).collect();
+        values.extend([0.into(), 0.into()]);
+        dict.set("Matrix", values);
+    }
+    let appearance = doc.add_object(Stream::new(dict, content));
+    let widget = doc
+        .get_dictionary_mut(widget_id)
+        .map_err(|e| e.to_string())?;
+    widget.set("AP", dictionary! { "N" => Object::Reference(appearance) });
+    Ok(())
+}
+
+// Switches the widgets of a checkbox or radio button to `state`, or off
+fn set_button_state(doc: &mut Document, field: &Field, state: Option<&[u8]>) -> Result<(), String> {
+    for (id, on) in &field.widgets {
+        let shown = match (state, on) {
+            (Some(state), Some(on)) if state == on.as_slice() => state,
+            _ => b"Off".as_slice(),
+        };
+        doc.get_dictionary_mut(*id)
+            .map_err(|e| e.to_string())?
+            .set("AS", Object::Name(shown.to_vec()));
+    }
+    Ok(())
+}
+
+fn fill_field(doc: &mut Document, field: &Field, value: &FieldValue) -> Result<(), String> {
+    let name = &field.name;
+    if field.flags & READ_ONLY != 0 {
+        return Err(format!("Field {} is read-only", name));
+    }
+    let Some(field_id) = field.id else {
+        return Err(format!("Field {} can't be changed", name));
+    };
+    let set_value = |doc: &mut Document, value: Object| -> Result<(), String> {
+        doc.get_dictionary_mut(field_id)
+            .map_err(|e| e.to_string())?
+            .set("V", value);
+        Ok(())
+    };
+    let wrong_value = || format!("Field {} doesn't take a value like that", name);
+
+    match (field.field_type, value) {
+        (FieldType::Text, FieldValue::Text(text)) => {
+            if let Some(max) = field.max_length.filter(|&max| max > 0) {
+                if text.chars().count() as i64 > max {
+                    return Err(format!("Field {} takes at most {} characters", name, max));
+                }
+            }
+            set_value(doc, objects::encode_text(text))?;
+            for (widget, _) in &field.widgets {
+                draw_widget(doc, *widget, field, &Shown::Text(text))?;
+            }
+        }
+        (FieldType::ComboBox, FieldValue::Text(text)) => {
+            let listed = field.options.iter().any(|(value, _)| value == text);
+            if !listed && !text.is_empty() && field.flags & EDIT == 0 {
+                return Err(format!("Field {} has no option {}", name, text));
+            }
+            set_value(doc, objects::encode_text(text))?;
+            for (widget, _) in &field.widgets {
+                draw_widget(doc, *widget, field, &Shown::Text(field.label_of(text)))?;
+            }
+        }
+        (FieldType::ListBox, FieldValue::Selected(_) | FieldValue::Text(_)) => {
+            let values = match value {
+                FieldValue::Selected(values) => values.clone(),
+                FieldValue::Text(text) if text.is_empty() => Vec::new(),
+                FieldValue::Text(text) => vec![text.clone()],
+                FieldValue::Checked(_) => unreachable!(),
+            };
+            if values.len() > 1 && field.flags & MULTI_SELECT == 0 {
+                return Err(format!("Field {} takes only one option", name));
+            }
+            let mut selected = Vec::new();
+            for value in &values {
+                let index = field
+                    .options
+                    .iter()
+                    .position(|(option, _)| option == value)
+                    .ok_or_else(|| format!("Field {} has no option {}", name, value))?;
+                selected.push(index);
+            }
+            selected.sort_unstable();
+            let stored = match values.as_slice() {
+                [] => Object::Null,
+                [value] => objects::encode_text(value),
+                values => Object::Array(values.iter().map(|v| objects::encode_text(v)).collect()),
+            };
+            set_value(doc, stored)?;
+            let top = {
+                let field = doc
+                    .get_dictionary_mut(field_id)
+                    .map_err(|e| e.to_string())?;
+                field.set(
+                    "I",
+                    selected
+                        .iter()
+                        .map(|&i| Object::from(i as i64))
+                        .collect::<Vec<_>>(),
+                );
+                field
+                    .get(b"TI")
+                    .and_then(Object::as_i64)
+                    .unwrap_or(0)
+                    .max(0) as usize
+            };
+            let shown = Shown::List {
+                labels: field
+                    .options
+                    .iter()
+                    .map(|(_, label)| label.as_str())
+                    .collect(),
+                selected,
+                top,
+            };
+            for (widget, _) in &field.widgets {
+                draw_widget(doc, *widget, field, &shown)?;
+            }
+        }
+        (FieldType::Checkbox, FieldValue::Checked(checked)) => {
+            let state = match checked {
+                true => Some(
+                    field
+                        .widgets
+                        .iter()
+                        .find_map(|(_, on)| on.clone())
+                        .ok_or_else(|| format!("Checkbox {} has no checked appearance", name))?,
+                ),
+                false => None,
+            };
+            let stored = state.clone().unwrap_or_else(|| b"Off".to_vec());
+            set_value(doc, Object::Name(stored))?;
+            set_button_state(doc, field, state.as_deref())?;
+        }
+        (FieldType::Radio, FieldValue::Text(text)) => {
+            let state = match text.is_empty() {
+                true => None,
+                false => Some(
+                    field
+                        .widgets
+                        .iter()
+                        .enumerate()
+                        .find_map(|(i, (_, on))| {
+                            // Export values, or the states they stand for
+                            on.as_ref()
+                                .filter(|on| {
+                                    field.on_value(i, on) == *text
+                                        || on.as_slice() == text.as_bytes()
+                                })
+                                .cloned()
+                        })
+                        .ok_or_else(|| format!("Field {} has no option {}", name, text))?,
+                ),
+            };
+            let stored = state.clone().unwrap_or_else(|| b"Off".to_vec());
+            set_value(doc, Object::Name(stored))?;
+            set_button_state(doc, field, state.as_deref())?;
+        }
+        (FieldType::PushButton, _) => return Err(format!("{} is a button, without a value", name)),
+        (FieldType::Signature, _) => {
+            return Err(format!(
+                "{} is a signature field; sign the document instead",
+                name
+            ))
+        }
+        _ => return Err(wrong_value()),
+    }
+    Ok(())
+}
+
+// The names of the packets of the form's XFA, if it has any
+fn xfa_packets(doc: &Document) -> Option<Vec<String>> {
+    let form = objects::get_dict(doc, doc.catalog().ok()?, b"AcroForm")?;
+    match objects::get(doc, form, b"XFA")? {
+        // Names and streams taking turns
+        Object::Array(packets) => Some(
+            packets
+                .iter()
+                .step_by(2)
+                .filter_map(objects::text_string)
+                .collect(),
+        ),
+        // A single XDP document holding them all
+        Object::Stream(_) => Some(vec!["xdp".to_string()]),
+        _ => None,
+    }
+}
+
+// True if pages are left for the viewer to lay out from the XFA
+fn needs_rendering(doc: &Document) -> bool {
+    doc.catalog()
+        .ok()
+        .and_then(|catalog| objects::get(doc, catalog, b"NeedsRendering"))
+        .and_then(|needs| needs.as_bool().ok())
+        .unwrap_or(false)
+}
+
+/// Tells what kind of form a document has, and what Nanodoc can do with it.
+pub fn capabilities(doc: &Document, doc_id: DocId) -> FormCapabilities {
+    let field_count = form_fields(doc).len();
+    let xfa_packets = xfa_packets(doc);
+    let kind = match &xfa_packets {
+        Some(_) if needs_rendering(doc) || field_count == 0 => FormKind::DynamicXfa,
+        Some(_) => FormKind::StaticXfa,
+        None if field_count == 0 => FormKind::None,
+        None => FormKind::AcroForm,
+    };
+    let message = match kind {
+        FormKind::DynamicXfa => Some(
+            "This is a dynamic XFA form, which Nanodoc can't display or fill in. \
+             Open it in Adobe Acrobat or Reader to see its pages."
+                .to_string(),
+        ),
+        FormKind::StaticXfa => Some(
+            "This form was made with XFA. Nanodoc fills in its regular fields, \
+             but scripts and calculations of the form don't run."
+                .to_string(),
+        ),
+        FormKind::None | FormKind::AcroForm => None,
+    };
+    FormCapabilities {
+        doc_id,
+        kind,
+        fillable: matches!(kind, FormKind::AcroForm | FormKind::StaticXfa),
+        field_count,
+        xfa_packets: xfa_packets.unwrap_or_default(),
+        message,
+    }
+}
+
+// Drops the XFA of a static form, whose values no longer match the fields
+fn drop_xfa(doc: &mut Document) -> Result<(), String> {
+    let form_id = match doc.catalog().map_err(|e| e.to_string())?.get(b"AcroForm") {
+        Ok(Object::Reference(id)) => Some(*id),
+        _ => None,
+    };
+    let form = match form_id {
+        Some(id) => doc.get_dictionary_mut(id).map_err(|e| e.to_string())?,
+        None => doc
+            .catalog_mut()
+            .map_err(|e| e.to_string())?
+            .get_mut(b"AcroForm")
+            .and_then(Object::as_dict_mut)
+            .map_err(|e| e.to_string())?,
+    };
+    form.remove(b"XFA");
+    doc.catalog_mut()
+        .map_err(|e| e.to_string())?
+        .remove(b"NeedsRendering");
+    Ok(())
+}
+
+/// Draws the text and choice fields of forms that leave that to the viewer
+/// (/NeedsAppearances), with the values they have.
+pub fn draw_appearances(doc: &mut Document) -> Result<(), String> {
+    for field in form_fields(doc) {
+        let top = field
+            .id
+            .and_then(|id| doc.get_dictionary(id).ok())
+            .and_then(|dict| dict.get(b"TI").ok())
+            .and_then(|top| top.as_i64().ok())
+            .unwrap_or(0)
+            .max(0) as usize;
+        let value = field.value();
+        let shown = match (field.field_type, &value) {
+            (FieldType::Text, Some(FieldValue::Text(text))) => Shown::Text(text),
+            (FieldType::ComboBox, Some(FieldValue::Text(text))) => {
+                Shown::Text(field.label_of(text))
+            }
+            (FieldType::ListBox, Some(FieldValue::Selected(values))) => Shown::List {
+                labels: field
+                    .options
+                    .iter()
+                    .map(|(_, label)| label.as_str())
+                    .collect(),
+                selected: (0..field.options.len())
+                    .filter(|&i| values.contains(&field.options[i].0))
+                    .collect(),
+                top,
+            },
+            _ => continue,
+        };
+        for (widget, _) in &field.widgets {
+            draw_widget(doc, *widget, &field, &shown)?;
+        }
+    }
+    Ok(())
+}
+
+/// The fields that can be filled in, by full name.
+pub fn fillable_fields(doc: &Document) -> HashMap<String, FieldType> {
+    form_fields(doc)
+        .into_iter()
+        .filter(|field| field.flags & READ_ONLY == 0 && field.id.is_some())
+        .filter(|field| {
+            !matches!(
+                field.field_type,
+                FieldType::PushButton | FieldType::Signature
+            )
+        })
+        .map(|field| (field.name, field.field_type))
+        .collect()
+}
+
+/// The values of the fields that have one, as the document stores them: text
+/// strings, names of the states of buttons, and arrays of the options
+/// selected in lists.
+pub fn stored_values(doc: &Document) -> Vec<(String, Object)> {
+    form_fields(doc)
+        .into_iter()
+        .filter(|field| {
+            !matches!(
+                field.field_type,
+                FieldType::PushButton | FieldType::Signature
+            )
+        })
+        .filter(|field| !matches!(field.value, Object::Null))
+        .map(|field| {
+            let value = match field.value {
+                Object::Array(values) => Object::Array(
+                    values
+                        .iter()
+                        .map(|value| objects::resolve(doc, value).clone())
+                        .collect(),
+                ),
+                value => value,
+            };
+            (field.name, value)
+        })
+        .collect()
+}
+
+/// Fills in fields, failing on the first value refused.
+pub fn fill(doc: &mut Document, updates: &[FieldUpdate]) -> Result<(), String> {
+    let fields = form_fields(doc);
+    if fields.is_empty() {
+        return Err(match xfa_packets(doc) {
+            Some(_) => "The document is a dynamic XFA form, which can't be filled in".to_string(),
+            None => "The document has no form fields".to_string(),
+        });
+    }
+    if xfa_packets(doc).is_some() {
+        if needs_rendering(doc) {
+            return Err("The document is a dynamic XFA form, which can't be filled in".to_string());
+        }
+        drop_xfa(doc)?;
+    }
+    for update in updates {
+        let field = fields
+            .iter()
+            .find(|field| field.name == update.name)
+            .ok_or_else(|| format!("No form field named {}", update.name))?;
+        fill_field(doc, field, &update.value)?;
+    }
+    Ok(())
+}
+
+/// Lists the form fields of a document, in the order of the form.
+#[tauri::command]
+pub async fn get_form_fields(
+    doc_id: DocId,
+    store: State<'_, DocumentStore>,
+) -> Result<Vec<FormField>, String> {
+    let doc = store.get(doc_id)?;
+    blocking(move || doc.with_objects(|objects| Ok(list_fields(objects)))).await
+}
+
+/// Tells what kind of form a document has. Also emitted as
+/// `document-form-capabilities` on opening documents with XFA forms.
+#[tauri::command]
+pub async fn get_form_capabilities(
+    doc_id: DocId,
+    store: State<'_, DocumentStore>,
+) -> Result<FormCapabilities, String> {
+    let doc = store.get(doc_id)?;
+    blocking(move || doc.with_objects(|objects| Ok(capabilities(objects, doc_id)))).await
+}
+
+/// Fills in form fields, by full name. Nothing is changed if any value is
+/// refused. The values are written with the document by `save_document`.
+#[tauri::command]
+pub async fn fill_form_fields(
+    doc_id: DocId,
+    fields: Vec<FieldUpdate>,
+    store: State<'_, DocumentStore>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<EditedDocument, String> {
+    if fields.is_empty() {
+        return Err("No fields to fill in".to_string());
+    }
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::FillForms).await?;
+    let count = fields.len();
+    let edited = apply_edit(doc, &store, &tiles, &scheduler, move |objects| {
+        fill(objects, &fields)
+    })
+    .await?;
+    eprintln!("Filled in {} fields of document {}", count, doc_id);
+    Ok(edited)
+}
@@ -0,0 +1,404 @@
+// Extracting the images embedded in pages.
+//
+// JPEG and JPEG 2000 images are written out exactly as they are stored. Other
+// images are decoded from their samples and written as PNG, with their soft
+// mask as the alpha channel, so what comes out is the original resolution
+// rather than what the page shows. Images in CCITT or JBIG2 encoding, and
+// colour spaces like Lab or DeviceN, are skipped; so are inline images, which
+// are part of the page content rather than objects of their own.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use serde::Serialize;
+use tauri::{AppHandle, State};
+
+use crate::compose::parse_page_ranges;
+use crate::objects::{self, MAX_DEPTH};
+use crate::pdf::{blocking, DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scope;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtractedImage {
+    /// First page the image appears on
+    pub page: i32,
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageExtraction {
+    pub images: Vec<ExtractedImage>,
+    /// Images in encodings or colour spaces that can't be converted
+    pub skipped: usize,
+}
+
+enum ColorSpace {
+    Gray,
+    Rgb,
+    Cmyk,
+    /// Base space and its colours, one after another
+    Indexed(Box<ColorSpace>, Vec<u8>),
+}
+
+impl ColorSpace {
+    fn components(&self) -> usize {
+        match self {
+            ColorSpace::Gray | ColorSpace::Indexed(..) => 1,
+            ColorSpace::Rgb => 3,
+            ColorSpace::Cmyk => 4,
+        }
+    }
+}
+
+fn color_space(doc: &Document, space: &Object, depth: usize) -> Option<ColorSpace> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    match objects::resolve(doc, space) {
+        Object::Name(name) => match name.as_slice() {
+            b"DeviceGray" | b"CalGray" | b"G" => Some(ColorSpace::Gray),
+            b"DeviceRGB" | b"CalRGB" | b"RGB" => Some(ColorSpace::Rgb),
+            b"DeviceCMYK" | b"CMYK" => Some(ColorSpace::Cmyk),
+            _ => None,
+        },
+        Object::Array(array) => {
+            let kind = array.first()?.as_name().ok()?;
+            match kind {
+                b"CalGray" => Some(ColorSpace::Gray),
+                b"CalRGB" => Some(ColorSpace::Rgb),
+                b"ICCBased" => {
+                    let Object::Stream(profile) = objects::resolve(doc, array.get(1)?) else {
+                        return None;
+                    };
+                    match objects::get(doc, &profile.dict, b"N")?.as_i64().ok()? {
+                        1 => Some(ColorSpace::Gray),
+                        3 => Some(ColorSpace::Rgb),
+                        4 => Some(ColorSpace::Cmyk),
+                        _ => None,
+                    }
+                }
+                b"Indexed" | b"I" => {
+                    let base = color_space(doc, array.get(1)?, depth + 1)?;
+                    if matches!(base, ColorSpace::Indexed(..)) {
+                        return None;
+                    }
+                    let palette = match objects::resolve(doc, array.get(3)?) {
+                        Object::String(bytes, _) => bytes.clone(),
+                        Object::Stream(stream) => stream
+                            .decompressed_content()
+                            .unwrap_or_else(|_| stream.content.clone()),
+                        _ => return None,
+                    };
+                    Some(ColorSpace::Indexed(Box::new(base), palette))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// Unpacks samples of any bit depth into one byte each. Rows start on a byte
+// boundary.
+fn unpack(data: &[u8], width: usize, height: usize, components: usize, bits: usize) -> Vec<u8> {
+    let per_row = width * components;
+    if bits == 8 {
+        return data.iter().copied().take(per_row * height).collect();
+    }
+    let row_bytes = (per_row * bits).div_ceil(8);
+    let mut samples = Vec::with_capacity(per_row * height);
+    for row in data.chunks(row_bytes).take(height) {
+        for i in 0..per_row {
+            let sample = match bits {
+                16 => row.get(i * 2).copied().unwrap_or(0),
+                _ => {
+                    let bit = i * bits;
+                    let byte = row.get(bit / 8).copied().unwrap_or(0);
+                    (byte >> (8 - bits - bit % 8)) & ((1 << bits) - 1) as u8
+                }
+            };
+            samples.push(sample);
+        }
+    }
+    samples
+}
+
+// Converts samples to gray or RGB, scaling them to 8 bits
+fn to_color(
+    samples: &[u8],
+    space: &ColorSpace,
+    bits: usize,
+    inverted: bool,
+) -> (Vec<u8>, png::ColorType) {
+    let max = if bits >= 8 { 255 } else { (1u32 << bits) - 1 };
+    let scale = |v: u8| {
+        let v = (v as u32 * 255 / max) as u8;
+        if inverted {
+            255 - v
+        } else {
+            v
+        }
+    };
+    match space {
+        ColorSpace::Gray => (
+            samples.iter().map(|&v| scale(v)).collect(),
+            png::ColorType::Grayscale,
+        ),
+        ColorSpace::Rgb => (
+            samples.iter().map(|&v| scale(v)).collect(),
+            png::ColorType::Rgb,
+        ),
+        ColorSpace::Cmyk => {
+            let pixels = samples
+                .chunks_exact(4)
+                .flat_map(|p| {
+                    let [c, m, y, k] = [p[0], p[1], p[2], p[3]].map(|v| 255 - scale(v) as u32);
+                    [c * k / 255, m * k / 255, y * k / 255].map(|v| v as u8)
+                })
+                .collect();
+            (pixels, png::ColorType::Rgb)
+        }
+        ColorSpace::Indexed(base, palette) => {
+            let n = base.components();
+            let colors: Vec<u8> = samples
+                .iter()
+                .flat_map(|&index| {
+                    let start = index as usize * n;
+                    (0..n).map(move |i| palette.get(start + i).copied().unwrap_or(0))
+                })
+                .collect();
+            to_color(&colors, base, 8, false)
+        }
+    }
+}
+
+fn number_in(doc: &Document, dict: &Dictionary, key: &[u8]) -> Option<usize> {
+    objects::get(doc, dict, key)?
+        .as_i64()
+        .ok()
+        .and_then(|n| usize::try_from(n).ok())
+}
+
+// Decodes an 8-bit gray soft mask of the given size
+fn soft_mask(doc: &Document, dict: &Dictionary, width: usize, height: usize) -> Option<Vec<u8>> {
+    let Object::Stream(mask) = objects::get(doc, dict, b"SMask")? else {
+        return None;
+    };
+    if number_in(doc, &mask.dict, b"Width")? != width
+        || number_in(doc, &mask.dict, b"Height")? != height
+    {
+        return None;
+    }
+    let bits = number_in(doc, &mask.dict, b"BitsPerComponent").unwrap_or(8);
+    let data = mask.decompressed_content().ok()?;
+    let samples = unpack(&data, width, height, 1, bits);
+    let (alpha, _) = to_color(&samples, &ColorSpace::Gray, bits, false);
+    (alpha.len() == width * height).then_some(alpha)
+}
+
+// Decodes an image into PNG data
+fn to_png(doc: &Document, stream: &Stream) -> Option<(Vec<u8>, u32, u32)> {
+    let dict = &stream.dict;
+    let width = number_in(doc, dict, b"Width")?;
+    let height = number_in(doc, dict, b"Height")?;
+    let filters = stream.filters().unwrap_or_default();
+    let data = if filters.is_empty() {
+        stream.content.clone()
+    } else {
+        stream.decompressed_content().ok()?
+    };
+
+    // A Decode array starting high means the samples are inverted
+    let inverted = objects::get_array(doc, dict, b"Decode")
+        .and_then(|d| Some(objects::number(d.first()?)? > objects::number(d.get(1)?)?))
+        .unwrap_or(false);
+    let stencil = objects::get(doc, dict, b"ImageMask").and_then(|m| m.as_bool().ok());
+    let (pixels, mut color) = if stencil == Some(true) {
+        // Stencil masks paint where samples are 0, shown here as black on white
+        let samples = unpack(&data, width, height, 1, 1);
+        to_color(&samples, &ColorSpace::Gray, 1, inverted)
+    } else {
+        let space = color_space(doc, objects::get(doc, dict, b"ColorSpace")?, 0)?;
+        let bits = number_in(doc, dict, b"BitsPerComponent")?;
+        if !matches!(bits, 1 | 2 | 4 | 8 | 16) {
+            return None;
+        }
+        let samples = unpack(&data, width, height, space.components(), bits);
+        if samples.len() != width * height * space.components() {
+            return None;
+        }
+        // Decode arrays don't apply to palette indices the same way
+        let inverted = inverted && !matches!(space, ColorSpace::Indexed(..));
+        to_color(&samples, &space, bits, inverted)
+    };
+
+    let mut pixels = pixels;
+    if let Some(alpha) = soft_mask(doc, dict, width, height) {
+        let n = if color == png::ColorType::Grayscale {
+            1
+        } else {
+            3
+        };
+        pixels = pixels
+            .chunks_exact(n)
+            .zip(alpha)
+            .flat_map(|(pixel, a)| pixel.iter().copied().chain([a]).collect::<Vec<_>>())
+            .collect();
+        color = if n == 1 {
+            png::ColorType::GrayscaleAlpha
+        } else {
+            png::ColorType::Rgba
+        };
+    }
+
+    let (width, height) = (width as u32, height as u32);
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width, height);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .ok()?;
+    Some((out, width, height))
+}
+
+// Collects the images a resource dictionary makes available, including those
+// of the forms in it
+fn collect_images(
+    doc: &Document,
+    resources: &Dictionary,
+    depth: usize,
+    seen: &mut HashSet<ObjectId>,
+    images: &mut Vec<ObjectId>,
+) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+    let Some(xobjects) = objects::get_dict(doc, resources, b"XObject") else {
+        return;
+    };
+    for (_, xobject) in xobjects.iter() {
+        let Ok(id) = xobject.as_reference() else {
+            continue;
+        };
+        if !seen.insert(id) {
+            continue;
+        }
+        let Ok(Object::Stream(stream)) = doc.get_object(id) else {
+            continue;
+        };
+        match objects::get_name(doc, &stream.dict, b"Subtype") {
+            Some(b"Image") => images.push(id),
+            Some(b"Form") => {
+                if let Some(form_resources) = objects::get_dict(doc, &stream.dict, b"Resources") {
+                    collect_images(doc, form_resources, depth + 1, seen, images);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn extract(
+    doc: &Document,
+    pages: &[i32],
+    output_dir: &Path,
+    stem: &str,
+) -> Result<ImageExtraction, String> {
+    std::fs::create_dir_all(output_dir)
+        .map_err(|e| format!("Failed to create {}: {}", output_dir.display(), e))?;
+    let ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    let mut seen = HashSet::new();
+    let mut images = Vec::new();
+    let mut skipped = 0;
+    for &page in pages {
+        let Some(resources) = objects::inherited(doc, ids[page as usize], b"Resources")
+            .and_then(|r| objects::resolve(doc, r).as_dict().ok())
+        else {
+            continue;
+        };
+        let mut found = Vec::new();
+        collect_images(doc, resources, 0, &mut seen, &mut found);
+
+        for (index, id) in found.into_iter().enumerate() {
+            let Ok(Object::Stream(stream)) = doc.get_object(id) else {
+                continue;
+            };
+            let width = number_in(doc, &stream.dict, b"Width").unwrap_or(0) as u32;
+            let height = number_in(doc, &stream.dict, b"Height").unwrap_or(0) as u32;
+            let filters = stream.filters().unwrap_or_default();
+            let filters: Vec<&str> = filters.iter().map(String::as_str).collect();
+            let (data, extension, width, height) = match filters.as_slice() {
+                ["DCTDecode"] => (stream.content.clone(), "jpg", width, height),
+                ["JPXDecode"] => (stream.content.clone(), "jp2", width, height),
+                _ => match to_png(doc, stream) {
+                    Some((data, width, height)) => (data, "png", width, height),
+                    None => {
+                        skipped += 1;
+                        continue;
+                    }
+                },
+            };
+            let path = output_dir.join(format!(
+                "{}-page{:03}-{}.{}",
+                stem,
+                page + 1,
+                index + 1,
+                extension
+            ));
+            std::fs::write(&path, data)
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+            images.push(ExtractedImage {
+                page,
+                path: path.to_string_lossy().into_owned(),
+                width,
+                height,
+            });
+        }
+    }
+    Ok(ImageExtraction { images, skipped })
+}
+
+/// Writes the images embedded in the pages selected by `page_range` (e.g.
+/// "1-5,8,10-end"), or in all pages, to `output_dir`. Images used on several
+/// pages are written once.
+#[tauri::command]
+pub async fn extract_images(
+    app: AppHandle,
+    doc_id: DocId,
+    page_range: Option<String>,
+    output_dir: String,
+    store: State<'_, DocumentStore>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<ImageExtraction, String> {
+    scope::check_write(&app, Path::new(&output_dir))?;
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Copy).await?;
+    let pages = match &page_range {
+        Some(ranges) => parse_page_ranges(ranges, doc.page_count)?,
+        None => (0..doc.page_count).collect(),
+    };
+    let stem = doc.path.file_stem().map_or("document".into(), |stem| {
+        stem.to_string_lossy().into_owned()
+    });
+    blocking(move || {
+        let extraction =
+            doc.with_objects(|objects| extract(objects, &pages, Path::new(&output_dir), &stem))?;
+        eprintln!(
+            "Extracted {} images of document {} to {} ({} skipped)",
+            extraction.images.len(),
+            doc_id,
+            output_dir,
+            extraction.skipped
+        );
+        Ok(extraction)
+    })
+    .await
+}
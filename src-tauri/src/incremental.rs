@@ -0,0 +1,169 @@
+// Incremental updates: changed and new objects appended to a file.
+//
+// Rewriting a signed file invalidates its signatures, as they cover the exact
+// bytes. An incremental update leaves those bytes alone and adds the new
+// versions of objects after them, with a cross-reference section of its own
+// that points back to the previous one. Objects are serialized here rather
+// than by lopdf, so that callers know where every byte lands.
+
+use lopdf::{Dictionary, Object, ObjectId, StringFormat};
+
+// Characters that end a name or must be escaped in it
+const DELIMITERS: &[u8] = b"()<>[]{}/%#";
+
+/// Offset of the last cross-reference section, read from the final startxref.
+pub fn last_startxref(data: &[u8]) -> Option<usize> {
+    let tail = &data[data.len().saturating_sub(1024)..];
+    let at = tail.windows(9).rposition(|w| w == b"startxref")?;
+    let digits: String = tail[at + 9..]
+        .iter()
+        .map(|&b| b as char)
+        .skip_while(|c| c.is_ascii_whitespace())
+        .take_while(char::is_ascii_digit)
+        .collect();
+    digits.parse().ok()
+}
+
+/// Serializes an object as it appears in a file.
+pub fn serialize(object: &Object, out: &mut Vec<u8>) {
+    match object {
+        Object::Null => out.extend_from_slice(b"null"),
+        Object::Boolean(value) => out.extend_from_slice(if *value { b"true" } else { b"false" }),
+        Object::Integer(value) => out.extend_from_slice(value.to_string().as_bytes()),
+        Object::Real(value) => out.extend_from_slice(value.to_string().as_bytes()),
+        Object::Name(name) => {
+            out.push(b'/');
+            for &b in name {
+                if (b'!'..=b'~').contains(&b) && !DELIMITERS.contains(&b) {
+                    out.push(b);
+                } else {
+                    out.extend_from_slice(format!("#{:02X}", b).as_bytes());
+                }
+            }
+        }
+        Object::String(bytes, StringFormat::Hexadecimal) => {
+            out.push(b'<');
+            out.extend_from_slice(hex::encode_upper(bytes).as_bytes());
+            out.push(b'>');
+        }
+        Object::String(bytes, StringFormat::Literal) => {
+            out.push(b'(');
+            for &b in bytes {
+                match b {
+                    b'(' | b')' | b'\\' => out.extend_from_slice(&[b'\\', b]),
+                    b'\r' => out.extend_from_slice(b"\\r"),
+                    b'\n' => out.extend_from_slice(b"\\n"),
+                    _ => out.push(b),
+                }
+            }
+            out.push(b')');
+        }
+        Object::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b' ');
+                }
+                serialize(item, out);
+            }
+            out.push(b']');
+        }
+        Object::Dictionary(dict) => serialize_dict(dict, out),
+        Object::Stream(stream) => {
+            let mut dict = stream.dict.clone();
+            dict.set("Length", stream.content.len() as i64);
+            serialize_dict(&dict, out);
+            out.extend_from_slice(b"\nstream\n");
+            out.extend_from_slice(&stream.content);
+            out.extend_from_slice(b"\nendstream");
+        }
+        Object::Reference((number, generation)) => {
+            out.extend_from_slice(format!("{} {} R", number, generation).as_bytes())
+        }
+    }
+}
+
+fn serialize_dict(dict: &Dictionary, out: &mut Vec<u8>) {
+    out.extend_from_slice(b"<<");
+    for (key, value) in dict.iter() {
+        serialize(&Object::Name(key.clone()), out);
+        out.push(b' ');
+        serialize(value, out);
+    }
+    out.extend_from_slice(b">>");
+}
+
+/// An incremental update being written after the bytes of a file.
+pub struct Update<'a> {
+    previous: &'a [u8],
+    body: Vec<u8>,
+    offsets: Vec<(ObjectId, usize)>,
+}
+
+impl<'a> Update<'a> {
+    pub fn new(previous: &'a [u8]) -> Self {
+        let mut body = Vec::new();
+        if !previous.ends_with(b"\n") && !previous.ends_with(b"\r") {
+            body.push(b'\n');
+        }
+        Update {
+            previous,
+            body,
+            offsets: Vec::new(),
+        }
+    }
+
+    /// Appends an object, already serialized. Returns the offset in the
+    /// finished file at which `serialized` starts.
+    pub fn write_raw(&mut self, id: ObjectId, serialized: &[u8]) -> usize {
+        self.offsets
+            .push((id, self.previous.len() + self.body.len()));
+        self.body
+            .extend_from_slice(format!("{} {} obj\n", id.0, id.1).as_bytes());
+        let start = self.previous.len() + self.body.len();
+        self.body.extend_from_slice(serialized);
+        self.body.extend_from_slice(b"\nendobj\n");
+        start
+    }
+
+    pub fn write(&mut self, id: ObjectId, object: &Object) {
+        let mut serialized = Vec::new();
+        serialize(object, &mut serialized);
+        self.write_raw(id, &serialized);
+    }
+
+    /// Finishes the update with a cross-reference section and `trailer`, to
+    /// which the caller adds /Size and /Prev, and returns the whole file.
+    pub fn finish(mut self, trailer: &Dictionary) -> Vec<u8> {
+        let xref_start = self.previous.len() + self.body.len();
+        self.offsets.sort_unstable();
+        self.body.extend_from_slice(b"xref\n");
+        // Consecutive object numbers share a subsection
+        let mut at = 0;
+        while at < self.offsets.len() {
+            let first = self.offsets[at].0 .0;
+            let count = self.offsets[at..]
+                .iter()
+                .enumerate()
+                .take_while(|(i, ((number, _), _))| *number == first + *i as u32)
+                .count();
+            self.body
+                .extend_from_slice(format!("{} {}\n", first, count).as_bytes());
+            for ((_, generation), offset) in &self.offsets[at..at + count] {
+                self.body.extend_from_slice(
+                    format!("{:010} {:05} n\r\n", offset, generation).as_bytes(),
+                );
+            }
+            at += count;
+        }
+        self.body.extend_from_slice(b"trailer\n");
+        serialize_dict(trailer, &mut self.body);
+        self.body
+            .extend_from_slice(format!("\nstartxref\n{}\n%%EOF\n", xref_start).as_bytes());
+
+        let mut file = Vec::with_capacity(self.previous.len() + self.body.len());
+        file.extend_from_slice(self.previous);
+        file.extend_from_slice(&self.body);
+        file
+    }
+}
@@ -0,0 +1,117 @@
+// Cancellation of in-flight render jobs.
+//
+// The frontend may tag a render request with a job id and later cancel it with
+// `cancel_render`. A new render of the same page also cancels the previous
+// one, which covers the common case of zooming in several steps. Batch jobs
+// such as exports are cancelled the same way, by their job id.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tauri::State;
+
+use crate::pdf::DocId;
+
+pub type JobId = u64;
+
+pub const CANCELLED: &str = "Render cancelled";
+
+/// Shared flag checked by rasterization between bands.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn is_same(&self, other: &CancelToken) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Returns `Err(CANCELLED)` once the token has been cancelled.
+    pub fn check(&self) -> Result<(), String> {
+        if self.is_cancelled() {
+            Err(CANCELLED.to_string())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RenderJobs {
+    jobs: Mutex<HashMap<JobId, CancelToken>>,
+    // Most recent on-demand render of each page
+    latest: Mutex<HashMap<(DocId, i32), CancelToken>>,
+}
+
+impl RenderJobs {
+    /// Registers a render of `page`, cancelling any earlier render of the same
+    /// page that is still running.
+    pub fn begin(&self, job_id: Option<JobId>, doc_id: DocId, page: i32) -> CancelToken {
+        let token = CancelToken::default();
+        if let Some(previous) = self
+            .latest
+            .lock()
+            .unwrap()
+            .insert((doc_id, page), token.clone())
+        {
+            previous.cancel();
+        }
+        if let Some(job_id) = job_id {
+            self.jobs.lock().unwrap().insert(job_id, token.clone());
+        }
+        token
+    }
+
+    pub fn finish(&self, job_id: Option<JobId>, doc_id: DocId, page: i32, token: &CancelToken) {
+        if let Some(job_id) = job_id {
+            self.jobs.lock().unwrap().remove(&job_id);
+        }
+        let mut latest = self.latest.lock().unwrap();
+        if latest
+            .get(&(doc_id, page))
+            .is_some_and(|t| t.is_same(token))
+        {
+            latest.remove(&(doc_id, page));
+        }
+    }
+
+    /// Registers a job that renders many pages, such as an export.
+    pub fn begin_batch(&self, job_id: Option<JobId>) -> CancelToken {
+        let token = CancelToken::default();
+        if let Some(job_id) = job_id {
+            self.jobs.lock().unwrap().insert(job_id, token.clone());
+        }
+        token
+    }
+
+    pub fn finish_batch(&self, job_id: Option<JobId>) {
+        if let Some(job_id) = job_id {
+            self.jobs.lock().unwrap().remove(&job_id);
+        }
+    }
+
+    pub fn cancel(&self, job_id: JobId) -> bool {
+        match self.jobs.lock().unwrap().remove(&job_id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Aborts a render started with the given job id. Returns false if the job
+/// had already finished.
+#[tauri::command]
+pub async fn cancel_render(job_id: JobId, jobs: State<'_, RenderJobs>) -> Result<bool, String> {
+    Ok(jobs.cancel(job_id))
+}
@@ -0,0 +1,144 @@
+#import <Foundation/Foundation.h>
+#import <Security/Security.h>
+
+// Keychain access for signing documents and for saved document passwords.
+// Certificates cross over as DER bytes; private keys never leave the
+// keychain.
+
+static NSArray *copy_identities(OSStatus *status) {
+    NSDictionary *query = @{
+        (__bridge id)kSecClass: (__bridge id)kSecClassIdentity,
+        (__bridge id)kSecReturnRef: @YES,
+        (__bridge id)kSecMatchLimit: (__bridge id)kSecMatchLimitAll,
+    };
+    CFTypeRef result = NULL;
+    *status = SecItemCopyMatching((__bridge CFDictionaryRef)query, &result);
+    if (*status != errSecSuccess) {
+        return nil;
+    }
+    return (__bridge_transfer NSArray *)result;
+}
+
+static NSData *copy_certificate(SecIdentityRef identity) {
+    SecCertificateRef certificate = NULL;
+    if (SecIdentityCopyCertificate(identity, &certificate) != errSecSuccess) {
+        return nil;
+    }
+    NSData *der = (__bridge_transfer NSData *)SecCertificateCopyData(certificate);
+    CFRelease(certificate);
+    return der;
+}
+
+// Calls `found` with the certificate of every identity, a certificate with
+// its private key. Returns an OSStatus.
+int keychain_identities(void (*found)(void *, const uint8_t *, size_t), void *context) {
+    OSStatus status = errSecSuccess;
+    NSArray *identities = copy_identities(&status);
+    if (status == errSecItemNotFound) {
+        return errSecSuccess;
+    }
+    for (id identity in identities) {
+        NSData *der = copy_certificate((__bridge SecIdentityRef)identity);
+        if (der) {
+            found(context, der.bytes, der.length);
+        }
+    }
+    return status;
+}
+
+// Signs `message` with SHA-256 and the private key belonging to the
+// certificate `der`: PKCS #1 v1.5 for RSA keys, X9.62 (DER) for elliptic
+// curve keys. The signature is released with keychain_free. Returns an
+// OSStatus.
+int keychain_sign(const uint8_t *der, size_t der_len, const uint8_t *message, size_t message_len,
+                  uint8_t **signature, size_t *signature_len) {
+    OSStatus status = errSecSuccess;
+    NSArray *identities = copy_identities(&status);
+    if (!identities) {
+        return status;
+    }
+    NSData *wanted = [NSData dataWithBytes:der length:der_len];
+    for (id identity in identities) {
+        if (![copy_certificate((__bridge SecIdentityRef)identity) isEqualToData:wanted]) {
+            continue;
+        }
+        SecKeyRef key = NULL;
+        status = SecIdentityCopyPrivateKey((__bridge SecIdentityRef)identity, &key);
+        if (status != errSecSuccess) {
+            return status;
+        }
+        NSDictionary *attributes = (__bridge_transfer NSDictionary *)SecKeyCopyAttributes(key);
+        BOOL rsa = [attributes[(__bridge id)kSecAttrKeyType] isEqual:(__bridge id)kSecAttrKeyTypeRSA];
+        SecKeyAlgorithm algorithm = rsa ? kSecKeyAlgorithmRSASignatureMessagePKCS1v15SHA256
+                                        : kSecKeyAlgorithmECDSASignatureMessageX962SHA256;
+
+        CFErrorRef error = NULL;
+        NSData *data = [NSData dataWithBytes:message length:message_len];
+        NSData *signed_data = (__bridge_transfer NSData *)SecKeyCreateSignature(
+            key, algorithm, (__bridge CFDataRef)data, &error);
+        CFRelease(key);
+        if (!signed_data) {
+            status = error ? (OSStatus)CFErrorGetCode(error) : errSecInternalError;
+            if (error) {
+                CFRelease(error);
+            }
+            return status;
+        }
+        *signature = malloc(signed_data.length);
+        memcpy(*signature, signed_data.bytes, signed_data.length);
+        *signature_len = signed_data.length;
+        return errSecSuccess;
+    }
+    return errSecItemNotFound;
+}
+
+void keychain_free(void *buffer) {
+    free(buffer);
+}
+
+// Passwords of encrypted documents, kept as generic passwords of the service
+// below under the document's fingerprint
+
+static NSString *const PASSWORD_SERVICE = @"Nanodoc document password";
+
+static NSMutableDictionary *password_query(const char *account) {
+    return [@{
+        (__bridge id)kSecClass: (__bridge id)kSecClassGenericPassword,
+        (__bridge id)kSecAttrService: PASSWORD_SERVICE,
+        (__bridge id)kSecAttrAccount: [NSString stringWithUTF8String:account],
+    } mutableCopy];
+}
+
+// Finds the password saved for `account`, released with keychain_free.
+// Returns an OSStatus: errSecItemNotFound if there is none.
+int keychain_password_find(const char *account, uint8_t **password, size_t *password_len) {
+    NSMutableDictionary *query = password_query(account);
+    query[(__bridge id)kSecReturnData] = @YES;
+    query[(__bridge id)kSecMatchLimit] = (__bridge id)kSecMatchLimitOne;
+    CFTypeRef result = NULL;
+    OSStatus status = SecItemCopyMatching((__bridge CFDictionaryRef)query, &result);
+    if (status != errSecSuccess) {
+        return status;
+    }
+    NSData *data = (__bridge_transfer NSData *)result;
+    *password = malloc(data.length);
+    memcpy(*password, data.bytes, data.length);
+    *password_len = data.length;
+    return errSecSuccess;
+}
+
+// Saves `password` for `account`, replacing any saved before. `label` is
+// what Keychain Access shows. Returns an OSStatus.
+int keychain_password_save(const char *account, const char *label, const uint8_t *password,
+                           size_t password_len) {
+    NSMutableDictionary *query = password_query(account);
+    SecItemDelete((__bridge CFDictionaryRef)query);
+    query[(__bridge id)kSecAttrLabel] = [NSString stringWithUTF8String:label];
+    query[(__bridge id)kSecValueData] = [NSData dataWithBytes:password length:password_len];
+    return SecItemAdd((__bridge CFDictionaryRef)query, NULL);
+}
+
+// Returns an OSStatus: errSecItemNotFound if there was nothing to delete.
+int keychain_password_delete(const char *account) {
+    return SecItemDelete((__bridge CFDictionaryRef)password_query(account));
+}
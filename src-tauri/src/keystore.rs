@@ -0,0 +1,344 @@
+// Signing certificates from the operating system's keystore.
+//
+// The private keys stay in the keystore: the macOS Keychain (through
+// keychain.m) or the Windows certificate store with its CNG key providers.
+// Either may ask the user to allow the use of a key, or for the PIN of a smart
+// card, while signing. Elsewhere there is no system keystore to use.
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::ffi::c_void;
+
+    // errSecItemNotFound and errSecUserCanceled
+    const ITEM_NOT_FOUND: i32 = -25300;
+    const USER_CANCELED: i32 = -128;
+
+    extern "C" {
+        fn keychain_identities(
+            found: extern "C" fn(*mut c_void, *const u8, usize),
+            context: *mut c_void,
+        ) -> i32;
+        fn keychain_sign(
+            certificate: *const u8,
+            certificate_len: usize,
+            message: *const u8,
+            message_len: usize,
+            signature: *mut *mut u8,
+            signature_len: *mut usize,
+        ) -> i32;
+        fn keychain_free(buffer: *mut c_void);
+    }
+
+    extern "C" fn collect(context: *mut c_void, der: *const u8, len: usize) {
+        let certificates = unsafe { &mut *(context as *mut Vec<Vec<u8>>) };
+        certificates.push(unsafe { std::slice::from_raw_parts(der, len) }.to_vec());
+    }
+
+    pub fn identities() -> Result<Vec<Vec<u8>>, String> {
+        let mut certificates: Vec<Vec<u8>> = Vec::new();
+        let status =
+            unsafe { keychain_identities(collect, &mut certificates as *mut _ as *mut c_void) };
+        if status != 0 {
+            return Err(format!("Failed to read the keychain (error {})", status));
+        }
+        Ok(certificates)
+    }
+
+    pub fn sign(certificate: &[u8], message: &[u8]) -> Result<Vec<u8>, String> {
+        let mut signature = std::ptr::null_mut();
+        let mut signature_len = 0;
+        let status = unsafe {
+            keychain_sign(
+                certificate.as_ptr(),
+                certificate.len(),
+                message.as_ptr(),
+                message.len(),
+                &mut signature,
+                &mut signature_len,
+            )
+        };
+        match status {
+            0 => {}
+            USER_CANCELED => return Err("Signing was cancelled".to_string()),
+            ITEM_NOT_FOUND => {
+                return Err("The certificate is no longer in the keychain".to_string())
+            }
+            status => return Err(format!("The keychain failed to sign (error {})", status)),
+        }
+        let signed = unsafe { std::slice::from_raw_parts(signature, signature_len) }.to_vec();
+        unsafe { keychain_free(signature as *mut c_void) };
+        Ok(signed)
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::ffi::c_void;
+    use std::ptr;
+
+    use crate::cms::{encode, Digest, INTEGER, SEQUENCE};
+
+    #[repr(C)]
+    struct CertContext {
+        encoding_type: u32,
+        encoded: *const u8,
+        encoded_len: u32,
+        info: *mut c_void,
+        store: *mut c_void,
+    }
+
+    #[repr(C)]
+    struct Pkcs1PaddingInfo {
+        algorithm: *const u16,
+    }
+
+    const CERT_KEY_PROV_INFO_PROP_ID: u32 = 2;
+    const CRYPT_ACQUIRE_ONLY_NCRYPT_KEY_FLAG: u32 = 0x0004_0000;
+    const BCRYPT_PAD_PKCS1: u32 = 0x2;
+    const NTE_USER_CANCELLED: u32 = 0x8009_0036;
+    const SCARD_W_CANCELLED_BY_USER: u32 = 0x8010_006E;
+
+    #[link(name = "crypt32")]
+    extern "system" {
+        fn CertOpenSystemStoreW(provider: usize, name: *const u16) -> *mut c_void;
+        fn CertEnumCertificatesInStore(
+            store: *mut c_void,
+            previous: *const CertContext,
+        ) -> *const CertContext;
+        fn CertGetCertificateContextProperty(
+            context: *const CertContext,
+            property: u32,
+            data: *mut c_void,
+            len: *mut u32,
+        ) -> i32;
+        fn CryptAcquireCertificatePrivateKey(
+            context: *const CertContext,
+            flags: u32,
+            parameters: *const c_void,
+            key: *mut usize,
+            key_spec: *mut u32,
+            caller_free: *mut i32,
+        ) -> i32;
+        fn CertFreeCertificateContext(context: *const CertContext) -> i32;
+        fn CertCloseStore(store: *mut c_void, flags: u32) -> i32;
+    }
+
+    #[link(name = "ncrypt")]
+    extern "system" {
+        fn NCryptGetProperty(
+            object: usize,
+            property: *const u16,
+            output: *mut u8,
+            output_len: u32,
+            result: *mut u32,
+            flags: u32,
+        ) -> i32;
+        fn NCryptSignHash(
+            key: usize,
+            padding: *const c_void,
+            hash: *const u8,
+            hash_len: u32,
+            signature: *mut u8,
+            signature_len: u32,
+            result: *mut u32,
+            flags: u32,
+        ) -> i32;
+        fn NCryptFreeObject(object: usize) -> i32;
+    }
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(Some(0)).collect()
+    }
+
+    fn describe(status: i32) -> String {
+        match status as u32 {
+            NTE_USER_CANCELLED | SCARD_W_CANCELLED_BY_USER => "Signing was cancelled".to_string(),
+            status => format!(
+                "The certificate store failed to sign (error 0x{:08X})",
+                status
+            ),
+        }
+    }
+
+    // Calls `f` with each certificate in the personal store that has a private
+    // key, until it returns true; that certificate is then left to the caller
+    // to free
+    fn find(mut f: impl FnMut(*const CertContext, &[u8]) -> bool) -> Result<(), String> {
+        let name = wide("MY");
+        let store = unsafe { CertOpenSystemStoreW(0, name.as_ptr()) };
+        if store.is_null() {
+            return Err("Failed to open the certificate store".to_string());
+        }
+        let mut context = ptr::null();
+        loop {
+            // Frees the previous certificate
+            context = unsafe { CertEnumCertificatesInStore(store, context) };
+            if context.is_null() {
+                break;
+            }
+            let mut len = 0;
+            let has_key = unsafe {
+                CertGetCertificateContextProperty(
+                    context,
+                    CERT_KEY_PROV_INFO_PROP_ID,
+                    ptr::null_mut(),
+                    &mut len,
+                )
+            } != 0;
+            if !has_key {
+                continue;
+            }
+            let der = unsafe {
+                std::slice::from_raw_parts((*context).encoded, (*context).encoded_len as usize)
+            };
+            if f(context, der) {
+                break;
+            }
+        }
+        unsafe { CertCloseStore(store, 0) };
+        Ok(())
+    }
+
+    // CNG signs ECDSA as r and s side by side; CMS wants them DER encoded
+    fn ecdsa_der(raw: &[u8]) -> Vec<u8> {
+        let integer = |half: &[u8]| {
+            let trimmed: Vec<u8> = half.iter().copied().skip_while(|&b| b == 0).collect();
+            let mut value = Vec::with_capacity(trimmed.len() + 1);
+            if trimmed.first().is_none_or(|&b| b & 0x80 != 0) {
+                value.push(0);
+            }
+            value.extend_from_slice(&trimmed);
+            encode(INTEGER, &value)
+        };
+        let (r, s) = raw.split_at(raw.len() / 2);
+        encode(SEQUENCE, &[integer(r), integer(s)].concat())
+    }
+
+    fn sign_with(key: usize, message: &[u8]) -> Result<Vec<u8>, String> {
+        let property = wide("Algorithm Group");
+        let mut group = [0u8; 64];
+        let mut group_len = 0;
+        let status = unsafe {
+            NCryptGetProperty(
+                key,
+                property.as_ptr(),
+                group.as_mut_ptr(),
+                group.len() as u32,
+                &mut group_len,
+                0,
+            )
+        };
+        if status != 0 {
+            return Err(describe(status));
+        }
+        let units: Vec<u16> = group[..group_len as usize]
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .take_while(|&unit| unit != 0)
+            .collect();
+        let rsa = String::from_utf16_lossy(&units) == "RSA";
+
+        let hash = Digest::Sha256.hash(&[message]);
+        let algorithm = wide("SHA256");
+        let padding = Pkcs1PaddingInfo {
+            algorithm: algorithm.as_ptr(),
+        };
+        let (padding, flags) = match rsa {
+            true => (
+                &padding as *const Pkcs1PaddingInfo as *const c_void,
+                BCRYPT_PAD_PKCS1,
+            ),
+            false => (ptr::null(), 0),
+        };
+        let mut len = 0;
+        let sign_hash = |signature: *mut u8, capacity: u32, len: &mut u32| unsafe {
+            NCryptSignHash(
+                key,
+                padding,
+                hash.as_ptr(),
+                hash.len() as u32,
+                signature,
+                capacity,
+                len,
+                flags,
+            )
+        };
+        let status = sign_hash(ptr::null_mut(), 0, &mut len);
+        if status != 0 {
+            return Err(describe(status));
+        }
+        let mut signature = vec![0u8; len as usize];
+        let status = sign_hash(signature.as_mut_ptr(), len, &mut len);
+        if status != 0 {
+            return Err(describe(status));
+        }
+        signature.truncate(len as usize);
+        Ok(match rsa {
+            true => signature,
+            false => ecdsa_der(&signature),
+        })
+    }
+
+    pub fn identities() -> Result<Vec<Vec<u8>>, String> {
+        let mut certificates = Vec::new();
+        find(|_, der| {
+            certificates.push(der.to_vec());
+            false
+        })?;
+        Ok(certificates)
+    }
+
+    pub fn sign(certificate: &[u8], message: &[u8]) -> Result<Vec<u8>, String> {
+        let mut result = None;
+        find(|context, der| {
+            if der != certificate {
+                return false;
+            }
+            let mut key = 0;
+            let mut key_spec = 0;
+            let mut caller_free = 0;
+            let acquired = unsafe {
+                CryptAcquireCertificatePrivateKey(
+                    context,
+                    CRYPT_ACQUIRE_ONLY_NCRYPT_KEY_FLAG,
+                    ptr::null(),
+                    &mut key,
+                    &mut key_spec,
+                    &mut caller_free,
+                )
+            } != 0;
+            result = Some(match acquired {
+                true => {
+                    let signed = sign_with(key, message);
+                    if caller_free != 0 {
+                        unsafe { NCryptFreeObject(key) };
+                    }
+                    signed
+                }
+                false => Err("The certificate's private key can't be used".to_string()),
+            });
+            unsafe { CertFreeCertificateContext(context) };
+            true
+        })?;
+        result.unwrap_or_else(|| {
+            Err("The certificate is no longer in the certificate store".to_string())
+        })
+    }
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+mod platform {
+    pub fn identities() -> Result<Vec<Vec<u8>>, String> {
+        Ok(Vec::new())
+    }
+
+    pub fn sign(_certificate: &[u8], _message: &[u8]) -> Result<Vec<u8>, String> {
+        Err("Signing with system certificates isn't supported on this system".to_string())
+    }
+}
+
+/// Certificates with a private key the keystore can sign with, DER encoded.
+pub use platform::identities;
+/// Signs `message` with SHA-256 and the private key of `certificate`:
+/// PKCS #1 v1.5 for RSA keys, DER encoded ECDSA for elliptic curve keys.
+pub use platform::sign;
@@ -0,0 +1,186 @@
+// Page labels ("xii", "A-3", ...) from the catalog's /PageLabels number tree.
+
+use lopdf::{Dictionary, Document};
+use tauri::State;
+
+use crate::objects::{self, MAX_DEPTH};
+use crate::pdf::{blocking, DocId, DocumentStore};
+
+// The largest /St taken; those beyond count from 1
+const MAX_FIRST: i32 = 1_000_000;
+// Roman and letter labels past this are written in digits, as they'd grow
+// with the number
+const MAX_NUMERAL: i32 = 3999;
+
+struct LabelRange {
+    start: i32,
+    style: Option<u8>,
+    prefix: String,
+    first: i32,
+}
+
+fn collect_entries<'a>(
+    doc: &'a Document,
+    node: &'a Dictionary,
+    depth: usize,
+    out: &mut Vec<(i64, &'a Dictionary)>,
+) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+    if let Some(nums) = objects::get_array(doc, node, b"Nums") {
+        for pair in nums.chunks(2) {
+            let [index, label] = pair else {
+                continue;
+            };
+            if let (Ok(start), Ok(label)) = (
+                objects::resolve(doc, index).as_i64(),
+                objects::resolve(doc, label).as_dict(),
+            ) {
+                out.push((start, label));
+            }
+        }
+    }
+    for kid in objects::get_array(doc, node, b"Kids").unwrap_or(&[]) {
+        if let Ok(kid) = objects::resolve(doc, kid).as_dict() {
+            collect_entries(doc, kid, depth + 1, out);
+        }
+    }
+}
+
+/// Flattens the /PageLabels number tree into (first page, label dictionary)
+/// pairs, sorted by page.
+pub fn label_entries(doc: &Document) -> Vec<(i64, &Dictionary)> {
+    let mut entries = Vec::new();
+    if let Some(tree) = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| objects::get_dict(doc, catalog, b"PageLabels"))
+    {
+        collect_entries(doc, tree, 0, &mut entries);
+    }
+    entries.sort_by_key(|(start, _)| *start);
+    entries
+}
+
+fn roman(mut n: i32) -> String {
+    const NUMERALS: [(i32, &str); 13] = [
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+    let mut out = String::new();
+    for (value, numeral) in NUMERALS {
+        while n >= value {
+            out.push_str(numeral);
+            n -= value;
+        }
+    }
+    out
+}
+
+// 1 = a, 26 = z, 27 = aa, 28 = bb, ...
+fn letters(n: i32) -> String {
+    if n < 1 {
+        return String::new();
+    }
+    let letter = (b'a' + ((n - 1) % 26) as u8) as char;
+    std::iter::repeat_n(letter, ((n - 1) / 26 + 1) as usize).collect()
+}
+
+fn format_label(range: &LabelRange, page: i32) -> String {
+    let Some(n) = page
+        .checked_sub(range.start)
+        .and_then(|offset| range.first.checked_add(offset))
+    else {
+        return (page + 1).to_string();
+    };
+    let number = match range.style {
+        Some(b'R' | b'r' | b'A' | b'a') if n > MAX_NUMERAL => n.to_string(),
+        Some(b'D') => n.to_string(),
+        Some(b'R') => roman(n).to_uppercase(),
+        Some(b'r') => roman(n),
+        Some(b'A') => letters(n).to_uppercase(),
+        Some(b'a') => letters(n),
+        _ => String::new(),
+    };
+    format!("{}{}", range.prefix, number)
+}
+
+/// Returns one label per page. Documents without /PageLabels are labelled
+/// `1`, `2`, `3`...
+pub fn page_labels(doc: &Document, page_count: i32) -> Vec<String> {
+    let ranges: Vec<LabelRange> = label_entries(doc)
+        .into_iter()
+        .filter_map(|(start, label)| {
+            Some(LabelRange {
+                start: i32::try_from(start).ok().filter(|start| *start >= 0)?,
+                style: objects::get_name(doc, label, b"S").and_then(|s| s.first().copied()),
+                prefix: objects::get_text(doc, label, b"P").unwrap_or_default(),
+                first: objects::get(doc, label, b"St")
+                    .and_then(|st| st.as_i64().ok())
+                    .and_then(|st| i32::try_from(st).ok())
+                    .filter(|st| (1..=MAX_FIRST).contains(st))
+                    .unwrap_or(1),
+            })
+        })
+        .collect();
+
+    (0..page_count)
+        .map(|page| match ranges.iter().rev().find(|r| r.start <= page) {
+            Some(range) => format_label(range, page),
+            None => (page + 1).to_string(),
+        })
+        .collect()
+}
+
+#[tauri::command]
+pub async fn get_page_labels(
+    doc_id: DocId,
+    store: State<'_, DocumentStore>,
+) -> Result<Vec<String>, String> {
+    let doc = store.get(doc_id)?;
+    let page_count = doc.page_count;
+    blocking(move || doc.with_objects(|objects| Ok(page_labels(objects, page_count)))).await
+}
+
+/// Finds the zero-based page for a label typed by the user, e.g. "xii".
+///
+/// Exact matches win over case-insensitive ones; a plain number that is not a
+/// label is treated as a physical page number.
+#[tauri::command]
+pub async fn resolve_page_label(
+    doc_id: DocId,
+    label: String,
+    store: State<'_, DocumentStore>,
+) -> Result<Option<i32>, String> {
+    let doc = store.get(doc_id)?;
+    let page_count = doc.page_count;
+    let labels =
+        blocking(move || doc.with_objects(|objects| Ok(page_labels(objects, page_count)))).await?;
+
+    let label = label.trim();
+    let found = labels
+        .iter()
+        .position(|l| l == label)
+        .or_else(|| labels.iter().position(|l| l.eq_ignore_ascii_case(label)))
+        .map(|i| i as i32)
+        .or_else(|| {
+            label
+                .parse::<i32>()
+                .ok()
+                .filter(|n| *n >= 1 && *n <= page_count)
+                .map(|n| n - 1)
+        });
+    Ok(found)
+}
@@ -0,0 +1,473 @@
+// Files handed to the app by the system.
+//
+// On Windows and Linux files opened with the app arrive on the command line,
+// any number of them; on macOS each arrives as an Apple Event (see
+// `app_delegate.m`), at launch or at any time after. Files dropped on a
+// window come as a window event: the webview's own drop events don't see them
+// while Tauri handles drops, and never see their real paths. A `-` on the
+// command line reads a PDF from standard input, as piped from a shell or a
+// mail client; it's saved to a file of its own in the app's cache directory,
+// readable only by the user, opened from there and deleted on exit. Another
+// instance couldn't read this one's input, so such launches always run on
+// their own. Either way the frontend opens each in a tab of its own on an
+// `open-pdf-file` event, which is only heard once its listener is in place,
+// so files are held back until the frontend reports that with
+// `frontend_ready`, and again whenever the page reloads. nanodoc:// links
+// (see `deep_link.rs`) come the same ways, and open the file with an
+// `open-pdf-location` event that says where to go in it. Apple Events may
+// come before the app is even set up; those wait in a static queue that setup
+// takes over. Files go to the main window, or to a window of their own once
+// it's closed (see `window_manager.rs`); document windows report ready the
+// same way.
+//
+// Only one instance runs at a time: a second launch hands its files to the
+// first, which opens them and comes to the front, and quits. On macOS the
+// system does that by itself. Users who want separate processes turn it off
+// with `set_launch_settings`, which takes effect on the next launch, or start
+// the app with `--new-instance`. The setting is a section of the preferences,
+// read from their file before the app is built (see `preferences.rs`).
+// `--open-file`, the Jump List's "Open File…" task, shows the open dialog.
+
+use std::fs::{File, OpenOptions};
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::webview::{PageLoadEvent, PageLoadPayload};
+use tauri::{
+    AppHandle, DragDropEvent, Emitter, Manager, RunEvent, State, Webview, Window, WindowEvent,
+};
+use tauri_plugin_dialog::DialogExt;
+
+use crate::window_manager::{self, MAIN_WINDOW};
+use crate::{deep_link, preferences, recent_files, scope};
+
+/// Starts a process of its own even while another instance runs.
+pub const NEW_INSTANCE_FLAG: &str = "--new-instance";
+/// Shows the open dialog, in the running instance if there is one.
+pub const OPEN_FILE_FLAG: &str = "--open-file";
+// Names standard input as the file to open
+const STDIN_ARG: &str = "-";
+// The size of the start of a file searched for the PDF header
+const HEADER_SEARCH: usize = 1024;
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LaunchSettings {
+    /// Launches start a process of their own instead of handing their files
+    /// to the running instance
+    pub separate_processes: bool,
+}
+
+/// A file to open, and where to go in it.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Location {
+    pub path: PathBuf,
+    /// Counted from 1
+    pub page: Option<u32>,
+    /// In percent
+    pub zoom: Option<f32>,
+}
+
+impl Location {
+    pub fn file(path: PathBuf) -> Self {
+        Location {
+            path,
+            page: None,
+            zoom: None,
+        }
+    }
+}
+
+// Whether the frontend listens yet, and the files waiting until it does
+#[derive(Default)]
+struct Requests {
+    ready: bool,
+    pending: Vec<Location>,
+}
+
+/// Files waiting for the frontend, managed as Tauri state.
+#[derive(Default)]
+pub struct OpenRequests {
+    requests: Mutex<Requests>,
+    // The file standard input was saved to, deleted on exit
+    stdin_file: Mutex<Option<PathBuf>>,
+}
+
+/// Allows the files and has the frontend open them, now or once it's ready.
+pub fn open_files(app: &AppHandle, paths: Vec<PathBuf>) {
+    open_locations(app, paths.into_iter().map(Location::file).collect());
+}
+
+/// Like `open_files`, going to a place in each file.
+pub fn open_locations(app: &AppHandle, locations: Vec<Location>) {
+    for location in &locations {
+        scope::allow(app, &location.path);
+        recent_files::add(app, &location.path);
+    }
+    if app.get_webview_window(MAIN_WINDOW).is_none() {
+        if !locations.is_empty() {
+            if let Err(e) = window_manager::open_window(app, None, locations) {
+                eprintln!("{}", e);
+            }
+        }
+        return;
+    }
+    let state = app.state::<OpenRequests>();
+    let mut requests = state.requests.lock().unwrap();
+    for location in locations {
+        if requests.ready {
+            send(app, MAIN_WINDOW, location);
+        } else {
+            eprintln!(
+                "Opening {} once the window is ready",
+                location.path.display()
+            );
+            requests.pending.push(location);
+        }
+    }
+}
+
+/// Has a window open a file. Plain files go by path alone.
+pub fn send(app: &AppHandle, label: &str, location: Location) {
+    eprintln!("Opening {} in {}", location.path.display(), label);
+    if location.page.is_none() && location.zoom.is_none() {
+        let _ = app.emit_to(label, "open-pdf-file", location.path.to_string_lossy());
+    } else {
+        let _ = app.emit_to(label, "open-pdf-location", location);
+    }
+}
+
+// The files and links on a command line run in `cwd`, each file once and in
+// order. Flags are skipped up to a `--`, after which everything is a file
+// name, and so are arguments that are neither a file nor named like a PDF
+fn launch_arguments(args: impl Iterator<Item = String>, cwd: &Path) -> Vec<Location> {
+    let mut locations: Vec<Location> = Vec::new();
+    let mut flags = true;
+    for arg in args.skip(1) {
+        if flags && arg == "--" {
+            flags = false;
+            continue;
+        }
+        if flags && arg.starts_with('-') {
+            continue;
+        }
+        let arg = arg.trim_matches('"').trim_matches('\'');
+        if deep_link::is_link(arg) {
+            match deep_link::parse(arg) {
+                Ok(location) => locations.push(location),
+                Err(e) => eprintln!("{}", e),
+            }
+            continue;
+        }
+        let path = cwd.join(arg);
+        let named_pdf = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"));
+        if (path.is_file() || named_pdf) && !locations.iter().any(|other| other.path == path) {
+            locations.push(Location::file(path));
+        }
+    }
+    locations
+}
+
+/// Has the user pick PDFs to open.
+pub fn choose_files(app: &AppHandle) {
+    let handle = app.clone();
+    app.dialog()
+        .file()
+        .set_title("Open File")
+        .add_filter("PDF", &["pdf"])
+        .pick_files(move |files| {
+            let paths = files
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|file| file.into_path().ok())
+                .collect();
+            open_files(&handle, paths);
+        });
+}
+
+/// Starts listening for files opened from the Finder. Must be called before
+/// the app runs, so the files it's launched with aren't missed.
+pub fn listen() {
+    #[cfg(target_os = "macos")]
+    apple_events::register();
+}
+
+/// Whether this launch should hand its files to a running instance. Called
+/// before the app is built, so the preferences are found the way Tauri finds
+/// the app data directory.
+pub fn single_instance(identifier: &str) -> bool {
+    if std::env::args().any(|arg| arg == NEW_INSTANCE_FLAG) || reads_stdin() {
+        return false;
+    }
+    let settings = dirs::data_dir()
+        .map(|dir| preferences::launch_settings(&dir.join(identifier)))
+        .unwrap_or_default();
+    !settings.separate_processes
+}
+
+/// Opens the files of a later launch, which has quit since, and brings the
+/// window to the front.
+pub fn forwarded(app: &AppHandle, args: Vec<String>, cwd: String) {
+    eprintln!("Another launch handed over {:?}", args);
+    if args.iter().any(|arg| arg == OPEN_FILE_FLAG) {
+        choose_files(app);
+    }
+    open_locations(app, launch_arguments(args.into_iter(), Path::new(&cwd)));
+    show_window(app);
+}
+
+/// Brings the window to the front, showing it if it was hidden.
+pub fn show_window(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window(MAIN_WINDOW) {
+        let _ = window.unminimize();
+        let _ = window.show();
+        let _ = window.set_focus();
+    }
+}
+
+/// Takes over the files handed to the app so far.
+pub fn setup(app: &AppHandle) {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    if std::env::args().any(|arg| arg == OPEN_FILE_FLAG) {
+        choose_files(app);
+    }
+    open_locations(app, launch_arguments(std::env::args(), &cwd));
+    if reads_stdin() {
+        open_stdin(app);
+    }
+    #[cfg(target_os = "macos")]
+    apple_events::attach(app);
+}
+
+// Whether the command line names standard input, before any `--`
+fn reads_stdin() -> bool {
+    std::env::args()
+        .skip(1)
+        .take_while(|arg| arg != "--")
+        .any(|arg| arg == STDIN_ARG)
+}
+
+// The header may come after some junk, which readers accept
+fn has_pdf_header(data: &[u8]) -> bool {
+    data[..data.len().min(HEADER_SEARCH)]
+        .windows(5)
+        .any(|window| window == b"%PDF-")
+}
+
+// Saves the PDF piped to the app to a file of its own
+fn read_stdin(app: &AppHandle) -> Result<PathBuf, String> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        return Err("Nothing is piped to the standard input".into());
+    }
+    let mut data = Vec::new();
+    stdin
+        .lock()
+        .read_to_end(&mut data)
+        .map_err(|e| format!("Failed to read the standard input: {}", e))?;
+    if !has_pdf_header(&data) {
+        return Err("The standard input isn't a PDF".into());
+    }
+    let dir = app
+        .path()
+        .app_cache_dir()
+        .map_err(|e| format!("Failed to resolve app cache directory: {}", e))?
+        .join("stdin");
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let file = dir.join(format!("{}-{}.pdf", std::process::id(), nanos));
+    // A new file, so nothing already there is written through
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&file)
+        .and_then(|mut out| out.write_all(&data))
+        .map_err(|e| format!("Failed to write {}: {}", file.display(), e))?;
+    *app.state::<OpenRequests>().stdin_file.lock().unwrap() = Some(file.clone());
+    Ok(file)
+}
+
+/// Deletes the file standard input was saved to once the app exits.
+pub fn handle_run_event(app: &AppHandle, event: &RunEvent) {
+    if !matches!(event, RunEvent::Exit) {
+        return;
+    }
+    if let Some(file) = app
+        .state::<OpenRequests>()
+        .stdin_file
+        .lock()
+        .unwrap()
+        .take()
+    {
+        if let Err(e) = std::fs::remove_file(&file) {
+            eprintln!("Failed to delete {}: {}", file.display(), e);
+        }
+    }
+}
+
+// Reads standard input off the main thread, as the pipe may be slow to fill
+fn open_stdin(app: &AppHandle) {
+    let app = app.clone();
+    let spawned = std::thread::Builder::new()
+        .name("stdin".into())
+        .spawn(move || match read_stdin(&app) {
+            Ok(file) => open_files(&app, vec![file]),
+            Err(e) => eprintln!("{}", e),
+        });
+    if let Err(e) = spawned {
+        eprintln!("Failed to read the standard input: {}", e);
+    }
+}
+
+// Whether a dropped path is a PDF, by its name or else by its header
+fn is_pdf(path: &Path) -> bool {
+    if !path.is_file() {
+        return false;
+    }
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+    {
+        return true;
+    }
+    let mut head = Vec::with_capacity(HEADER_SEARCH);
+    let read =
+        File::open(path).and_then(|file| file.take(HEADER_SEARCH as u64).read_to_end(&mut head));
+    read.is_ok() && has_pdf_header(&head)
+}
+
+/// Opens the PDFs dropped on a window. Other files are left to the webview.
+pub fn handle_window_event(window: &Window, event: &WindowEvent) {
+    if let WindowEvent::DragDrop(DragDropEvent::Drop { paths, .. }) = event {
+        let (pdfs, others): (Vec<PathBuf>, Vec<PathBuf>) =
+            paths.iter().cloned().partition(|path| is_pdf(path));
+        if !others.is_empty() {
+            eprintln!("Not opening dropped files that aren't PDFs: {:?}", others);
+        }
+        open_files(window.app_handle(), pdfs);
+    }
+}
+
+/// Holds files back again while the window (re)loads, as the page that
+/// listened is gone until the new one reports ready.
+pub fn page_loading(webview: &Webview, payload: &PageLoadPayload<'_>) {
+    if payload.event() != PageLoadEvent::Started {
+        return;
+    }
+    if webview.label() != MAIN_WINDOW {
+        window_manager::page_loading(webview.app_handle(), webview.label());
+        return;
+    }
+    let state = webview.state::<OpenRequests>();
+    state.requests.lock().unwrap().ready = false;
+}
+
+/// Reports that the frontend listens for `open-pdf-file` events, and sends
+/// it the files that were waiting for that.
+#[tauri::command]
+pub fn frontend_ready(app: AppHandle, webview: Webview, state: State<'_, OpenRequests>) {
+    if webview.label() != MAIN_WINDOW {
+        window_manager::window_ready(&app, webview.label());
+        return;
+    }
+    let pending = {
+        let mut requests = state.requests.lock().unwrap();
+        requests.ready = true;
+        std::mem::take(&mut requests.pending)
+    };
+    for location in pending {
+        send(&app, MAIN_WINDOW, location);
+    }
+}
+
+#[tauri::command]
+pub async fn get_launch_settings(app: AppHandle) -> Result<LaunchSettings, String> {
+    Ok(preferences::current(&app).launch)
+}
+
+/// Saves the launch settings, which apply from the next launch on.
+#[tauri::command]
+pub async fn set_launch_settings(app: AppHandle, settings: LaunchSettings) -> Result<(), String> {
+    preferences::update(&app, |preferences| preferences.launch = settings).map(|_| ())
+}
+
+#[cfg(target_os = "macos")]
+mod apple_events {
+    use std::ffi::{c_char, CStr, OsStr};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::PathBuf;
+    use std::sync::{Mutex, OnceLock};
+
+    use tauri::AppHandle;
+
+    use super::Location;
+    use crate::deep_link;
+
+    extern "C" {
+        fn register_open_handlers(
+            file_callback: extern "C" fn(*const c_char),
+            link_callback: extern "C" fn(*const c_char),
+        );
+    }
+
+    // The app once it's set up, and the files that came before
+    static APP: OnceLock<AppHandle> = OnceLock::new();
+    static EARLY: Mutex<Vec<Location>> = Mutex::new(Vec::new());
+
+    fn opened(location: Location) {
+        let mut early = EARLY.lock().unwrap();
+        match APP.get() {
+            Some(app) => {
+                drop(early);
+                super::open_locations(app, vec![location]);
+            }
+            None => early.push(location),
+        }
+    }
+
+    // SAFETY: the handlers pass NUL-terminated strings that live for the
+    // duration of the call
+    extern "C" fn file_opened(path: *const c_char) {
+        if path.is_null() {
+            return;
+        }
+        let bytes = unsafe { CStr::from_ptr(path) }.to_bytes();
+        opened(Location::file(PathBuf::from(OsStr::from_bytes(bytes))));
+    }
+
+    extern "C" fn link_opened(link: *const c_char) {
+        if link.is_null() {
+            return;
+        }
+        let link = unsafe { CStr::from_ptr(link) }.to_string_lossy();
+        match deep_link::parse(&link) {
+            Ok(location) => opened(location),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    pub fn register() {
+        // SAFETY: called once on the main thread before the event loop starts
+        unsafe { register_open_handlers(file_opened, link_opened) };
+    }
+
+    pub fn attach(app: &AppHandle) {
+        let early = {
+            let mut early = EARLY.lock().unwrap();
+            let _ = APP.set(app.clone());
+            std::mem::take(&mut *early)
+        };
+        super::open_locations(app, early);
+    }
+}
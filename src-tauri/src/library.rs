@@ -0,0 +1,1077 @@
+// The user's PDFs, to browse and to search in full.
+//
+// The user names folders to make up the library. A background thread walks
+// them, extracts the text of every PDF in them page by page, and keeps it in
+// an SQLite FTS5 index at `<app data>/library.sqlite3`, which `search_library`
+// ranks with BM25. Fuzzy searches also find the words of the index a few
+// typos from those searched for (see `search.rs`). Along with the text go the
+// document's metadata and a cover, its first page rendered small, which
+// `list_library` and `get_library_cover` serve to the library's grid.
+//
+// Files are read again only when their size or modification time changed,
+// and dropped from the index once they're gone. Indexing runs on a thread of
+// its own rather than the engine's workers, so the open documents never wait
+// for it; it's started on launch and whenever the folders change, and emits
+// `library-indexing` events as it goes and `library-changed` once documents
+// were added, changed or removed. Tags and collections organize the library
+// further (see `tags.rs`). The folders are watched by looking them
+// over again every `WATCH_INTERVAL`, which only reads the files that changed.
+//
+// Library folders are added to the file scope on each start, so documents
+// found in them can be opened like any other. Folders imported from another
+// computer wait apart until the user picks each in a folder dialog, so an
+// archive can't open up a folder the user never chose.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use tauri::ipc::Response;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::metadata::parse_pdf_date;
+use crate::pdf::{blocking, encode_png, unlock};
+use crate::scope;
+use crate::search::{allowed_edits, edit_distance};
+use crate::tags::{self, CollectionRule};
+use crate::text::page_text;
+
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    CREATE TABLE IF NOT EXISTS folders (path TEXT PRIMARY KEY);
+    CREATE TABLE IF NOT EXISTS pending_folders (path TEXT PRIMARY KEY);
+    CREATE TABLE IF NOT EXISTS documents (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL UNIQUE,
+        size INTEGER NOT NULL,
+        modified INTEGER NOT NULL,
+        title TEXT NOT NULL,
+        page_count INTEGER NOT NULL
+    );
+    CREATE VIRTUAL TABLE IF NOT EXISTS pages USING fts5(
+        text,
+        document UNINDEXED,
+        page UNINDEXED,
+        tokenize = 'unicode61 remove_diacritics 2'
+    );
+    CREATE VIRTUAL TABLE IF NOT EXISTS terms USING fts5vocab(pages, 'row');
+    CREATE TABLE IF NOT EXISTS details (
+        document INTEGER PRIMARY KEY,
+        author TEXT NOT NULL,
+        subject TEXT NOT NULL,
+        keywords TEXT NOT NULL,
+        created TEXT,
+        cover BLOB
+    );
+";
+
+// How long a search waits for the indexer to finish writing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+// Pages of a document listed with each search result
+const MATCHES_PER_DOCUMENT: usize = 3;
+const DEFAULT_RESULTS: usize = 20;
+// Pages ranked before they are grouped by document
+const MAX_RANKED_PAGES: usize = 500;
+// Words of the snippet around the matching words
+const SNIPPET_WORDS: i32 = 16;
+// Marks around the matching words in snippets, as FTS5 writes them
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+// Words of the index a word of a fuzzy search may stand for
+const MAX_VARIANTS: usize = 20;
+// How often the folders are looked over for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(60);
+// The longest side of covers, in pixels
+const COVER_PX: f32 = 256.0;
+const DEFAULT_LISTED: usize = 100;
+// Between the tags of a document as they're read together
+const TAG_SEPARATOR: char = '\u{1f}';
+
+// Whether the indexer is running, and whether it should run again once done
+#[derive(Default)]
+struct Indexing {
+    running: bool,
+    again: bool,
+}
+
+/// The indexer's state, managed as Tauri state.
+#[derive(Default)]
+pub struct Library {
+    indexing: Mutex<Indexing>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryStatus {
+    pub folders: Vec<String>,
+    /// Imported folders that join the library once the user picks them again
+    pub pending: Vec<String>,
+    pub documents: usize,
+    pub pages: usize,
+    pub indexing: bool,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexingProgress {
+    /// Files read so far
+    pub done: usize,
+    /// Files that were new or had changed
+    pub total: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryMatch {
+    pub page: i32,
+    pub snippet: String,
+    /// Ranges of characters of `snippet` that match, end exclusive
+    pub highlights: Vec<[usize; 2]>,
+    /// How close the words found are to those searched for, from 0 to 1
+    pub quality: f32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryDocument {
+    pub path: String,
+    pub title: String,
+    pub page_count: i32,
+    /// Higher is better; fuzzy matches count for less
+    pub score: f64,
+    /// That of the best match
+    pub quality: f32,
+    /// The best matching pages, best first
+    pub matches: Vec<LibraryMatch>,
+}
+
+/// How `list_library` orders documents.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum LibrarySort {
+    #[default]
+    Title,
+    Author,
+    /// When the file was last changed
+    Modified,
+    /// When the document was made, from its metadata
+    Created,
+    PageCount,
+}
+
+impl LibrarySort {
+    fn column(self) -> &'static str {
+        match self {
+            LibrarySort::Title => "d.title COLLATE NOCASE",
+            LibrarySort::Author => "t.author COLLATE NOCASE",
+            LibrarySort::Modified => "d.modified",
+            LibrarySort::Created => "t.created",
+            LibrarySort::PageCount => "d.page_count",
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LibraryQuery {
+    /// Only documents in this library folder
+    pub folder: Option<String>,
+    /// Only documents with this in their title, author, subject, keywords or
+    /// file name, ignoring case
+    pub filter: Option<String>,
+    /// Only documents by an author with this in their name, ignoring case
+    pub author: Option<String>,
+    /// Only documents with all these tags
+    pub tags: Vec<String>,
+    /// Only documents of this collection. The folder and author of the query
+    /// take the place of the collection's
+    pub collection: Option<i64>,
+    pub sort: LibrarySort,
+    pub descending: bool,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryEntry {
+    pub id: i64,
+    pub path: String,
+    pub title: String,
+    pub author: String,
+    pub subject: String,
+    pub keywords: String,
+    /// ISO 8601, from the document's metadata
+    pub created: Option<String>,
+    /// Seconds since the Unix epoch
+    pub modified: i64,
+    pub size: i64,
+    pub page_count: i32,
+    pub has_cover: bool,
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryListing {
+    pub documents: Vec<LibraryEntry>,
+    /// Documents matching, of which `documents` is a page
+    pub total: usize,
+}
+
+// What's read from a PDF for the library
+#[derive(Default)]
+struct ReadDocument {
+    title: String,
+    author: String,
+    subject: String,
+    keywords: String,
+    created: Option<String>,
+    cover: Option<Vec<u8>>,
+    pages: Vec<String>,
+}
+
+fn database_file(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("library.sqlite3"))
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))
+}
+
+fn open(file: &Path) -> Result<Connection, String> {
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let db =
+        Connection::open(file).map_err(|e| format!("Failed to open {}: {}", file.display(), e))?;
+    db.busy_timeout(BUSY_TIMEOUT).map_err(|e| e.to_string())?;
+    db.execute_batch(SCHEMA)
+        .and_then(|_| db.execute_batch(tags::SCHEMA))
+        .map_err(|e| format!("Failed to set up {}: {}", file.display(), e))?;
+    Ok(db)
+}
+
+/// Opens the library's database, for the modules that keep more in it.
+pub fn open_database(app: &AppHandle) -> Result<Connection, String> {
+    open(&database_file(app)?)
+}
+
+/// The library folders, by path.
+pub fn folders(db: &Connection) -> Result<Vec<String>, String> {
+    paths(db, "SELECT path FROM folders ORDER BY path")
+}
+
+fn paths(db: &Connection, sql: &str) -> Result<Vec<String>, String> {
+    let mut query = db.prepare(sql).map_err(|e| e.to_string())?;
+    let rows = query
+        .query_map(params![], |row| row.get::<_, String>(0))
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+}
+
+fn status(app: &AppHandle, db: &Connection) -> Result<LibraryStatus, String> {
+    let count = |sql: &str| {
+        db.query_row(sql, params![], |row| row.get::<_, i64>(0))
+            .map(|n| n as usize)
+            .map_err(|e| e.to_string())
+    };
+    Ok(LibraryStatus {
+        folders: folders(db)?,
+        pending: paths(db, "SELECT path FROM pending_folders ORDER BY path")?,
+        documents: count("SELECT COUNT(*) FROM documents")?,
+        pages: count("SELECT COUNT(*) FROM pages")?,
+        indexing: app.state::<Library>().indexing.lock().unwrap().running,
+    })
+}
+
+// The PDFs in a folder and the folders in it, leaving out hidden ones
+fn find_pdfs(dir: &Path, found: &mut BTreeSet<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        eprintln!("Failed to list {}", dir.display());
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        // Links are left alone, as they may lead in circles
+        let Ok(kind) = entry.file_type() else {
+            continue;
+        };
+        if kind.is_dir() {
+            find_pdfs(&path, found);
+        } else if kind.is_file()
+            && path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+        {
+            found.insert(path);
+        }
+    }
+}
+
+// What identifies a version of a file: its size and modification time
+fn version(path: &Path) -> Option<(i64, i64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    Some((metadata.len() as i64, modified))
+}
+
+// The first page, its longest side `COVER_PX` pixels long, as a PNG
+fn render_cover(doc: &mupdf::Document) -> Result<Vec<u8>, String> {
+    let page = doc.load_page(0).map_err(|e| e.to_string())?;
+    let bounds = page.bounds().map_err(|e| e.to_string())?;
+    let scale = COVER_PX / bounds.width().max(bounds.height()).max(1.0);
+    let pixmap = page
+        .to_pixmap(
+            &mupdf::Matrix::new_scale(scale, scale),
+            &mupdf::Colorspace::device_rgb(),
+            0.0,
+            true,
+        )
+        .map_err(|e| e.to_string())?;
+    encode_png(&pixmap)
+}
+
+// The metadata, the cover and the text of each page of a PDF
+fn read_pdf(path: &Path) -> Result<ReadDocument, String> {
+    let mut doc = mupdf::Document::open(&path.to_string_lossy())
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    // Files that need a password are listed by name, without their text
+    if !unlock(&mut doc, None)? {
+        return Ok(ReadDocument::default());
+    }
+    let field = |name| doc.metadata(name).unwrap_or_default().trim().to_string();
+    let mut read = ReadDocument {
+        title: field(mupdf::MetadataName::Title),
+        author: field(mupdf::MetadataName::Author),
+        subject: field(mupdf::MetadataName::Subject),
+        keywords: field(mupdf::MetadataName::Keywords),
+        created: parse_pdf_date(&field(mupdf::MetadataName::CreationDate)),
+        ..Default::default()
+    };
+    let page_count = doc.page_count().map_err(|e| e.to_string())?;
+    if page_count > 0 {
+        read.cover = render_cover(&doc)
+            .map_err(|e| eprintln!("Failed to render the cover of {}: {}", path.display(), e))
+            .ok();
+    }
+    let mut pages = Vec::with_capacity(page_count as usize);
+    for index in 0..page_count {
+        let text = doc
+            .load_page(index)
+            .and_then(|p| p.to_text_page(mupdf::TextPageOptions::empty()))
+            .map(|text_page| page_text(&text_page))
+            .unwrap_or_default();
+        pages.push(text);
+    }
+    read.pages = pages;
+    Ok(read)
+}
+
+fn forget(db: &Connection, id: i64) -> Result<(), String> {
+    db.execute("DELETE FROM pages WHERE document = ?1", params![id])
+        .and_then(|_| db.execute("DELETE FROM details WHERE document = ?1", params![id]))
+        .and_then(|_| db.execute("DELETE FROM documents WHERE id = ?1", params![id]))
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+fn index_file(
+    db: &mut Connection,
+    path: &Path,
+    (size, modified): (i64, i64),
+) -> Result<(), String> {
+    let read = read_pdf(path)?;
+    let title = match read.title.is_empty() {
+        true => path
+            .file_stem()
+            .map_or(String::new(), |stem| stem.to_string_lossy().into_owned()),
+        false => read.title,
+    };
+    let pages = read.pages;
+    let name = path.to_string_lossy().into_owned();
+    let tx = db.transaction().map_err(|e| e.to_string())?;
+    let known = tx
+        .query_row(
+            "SELECT id FROM documents WHERE path = ?1",
+            params![name],
+            |row| row.get::<_, i64>(0),
+        )
+        .ok();
+    if let Some(id) = known {
+        forget(&tx, id)?;
+    }
+    tx.execute(
+        "INSERT INTO documents (path, size, modified, title, page_count)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![name, size, modified, title, pages.len() as i64],
+    )
+    .map_err(|e| e.to_string())?;
+    let id = tx.last_insert_rowid();
+    tx.execute(
+        "INSERT INTO details (document, author, subject, keywords, created, cover)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            id,
+            read.author,
+            read.subject,
+            read.keywords,
+            read.created,
+            read.cover
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    for (page, text) in pages.iter().enumerate() {
+        if text.trim().is_empty() {
+            continue;
+        }
+        tx.execute(
+            "INSERT INTO pages (text, document, page) VALUES (?1, ?2, ?3)",
+            params![text, id, page as i64],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    tx.commit().map_err(|e| e.to_string())
+}
+
+// Brings the index up to date with the folders, telling whether anything
+// changed
+fn crawl(app: &AppHandle, db: &mut Connection) -> Result<bool, String> {
+    let mut files = BTreeSet::new();
+    for folder in folders(db)? {
+        find_pdfs(Path::new(&folder), &mut files);
+    }
+
+    // Documents indexed before their details were kept are read again
+    let known: HashMap<String, (i64, Option<(i64, i64)>)> = {
+        let mut query = db
+            .prepare(
+                "SELECT d.id, d.path, d.size, d.modified, t.document IS NOT NULL
+                 FROM documents d LEFT JOIN details t ON t.document = d.id",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = query
+            .query_map(params![], |row| {
+                let version = (row.get(2)?, row.get(3)?);
+                Ok((
+                    row.get::<_, String>(1)?,
+                    (
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, bool>(4)?.then_some(version),
+                    ),
+                ))
+            })
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+    };
+    let mut removed = 0;
+    for (path, (id, _)) in &known {
+        if !files.contains(Path::new(path)) {
+            forget(db, *id)?;
+            removed += 1;
+        }
+    }
+
+    let changed: Vec<(PathBuf, (i64, i64))> = files
+        .into_iter()
+        .filter_map(|path| {
+            let version = version(&path)?;
+            let known = known.get(path.to_string_lossy().as_ref());
+            match known.is_some_and(|(_, indexed)| *indexed == Some(version)) {
+                true => None,
+                false => Some((path, version)),
+            }
+        })
+        .collect();
+    let total = changed.len();
+    for (done, (path, version)) in changed.into_iter().enumerate() {
+        // A damaged file shouldn't keep the rest out of the index
+        if let Err(e) = index_file(db, &path, version) {
+            eprintln!("Failed to index {}: {}", path.display(), e);
+        }
+        let _ = app.emit(
+            "library-indexing",
+            IndexingProgress {
+                done: done + 1,
+                total,
+            },
+        );
+    }
+    if total > 0 {
+        eprintln!("Indexed {} documents of the library", total);
+    }
+    // Once the files are indexed, as tags may have been given to documents of
+    // folders just added (see `transfer.rs`)
+    if removed > 0 {
+        tags::prune(db)?;
+    }
+    Ok(total + removed > 0)
+}
+
+/// Brings the index up to date on a thread of its own. While that runs,
+/// further calls have it run once more when it's done.
+pub fn index_in_background(app: &AppHandle) {
+    {
+        let library = app.state::<Library>();
+        let mut indexing = library.indexing.lock().unwrap();
+        if indexing.running {
+            indexing.again = true;
+            return;
+        }
+        indexing.running = true;
+    }
+    let app = app.clone();
+    let spawned = std::thread::Builder::new()
+        .name("library-indexer".into())
+        .spawn({
+            let app = app.clone();
+            move || loop {
+                let crawled = database_file(&app)
+                    .and_then(|file| open(&file))
+                    .and_then(|mut db| crawl(&app, &mut db));
+                match crawled {
+                    Ok(true) => {
+                        let _ = app.emit("library-changed", ());
+                    }
+                    Ok(false) => {}
+                    Err(e) => eprintln!("Failed to index the library: {}", e),
+                }
+                let library = app.state::<Library>();
+                let mut indexing = library.indexing.lock().unwrap();
+                if !std::mem::take(&mut indexing.again) {
+                    indexing.running = false;
+                    break;
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        eprintln!("Failed to start indexing the library: {}", e);
+        app.state::<Library>().indexing.lock().unwrap().running = false;
+    }
+}
+
+// Looks the folders over for changes every `WATCH_INTERVAL`
+fn watch(app: &AppHandle) {
+    let app = app.clone();
+    let spawned = std::thread::Builder::new()
+        .name("library-watcher".into())
+        .spawn(move || loop {
+            std::thread::sleep(WATCH_INTERVAL);
+            let has_folders = database_file(&app)
+                .and_then(|file| open(&file))
+                .and_then(|db| folders(&db))
+                .is_ok_and(|folders| !folders.is_empty());
+            if has_folders {
+                index_in_background(&app);
+            }
+        });
+    if let Err(e) = spawned {
+        eprintln!("Failed to start watching the library: {}", e);
+    }
+}
+
+/// Keeps folders to add to the library once the user picks each of them
+/// with `add_library_folder`. Returns the folders that aren't there, which
+/// are left out.
+pub fn add_pending_folders(db: &Connection, folders: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut missing = Vec::new();
+    for folder in folders {
+        if !folder.is_dir() {
+            missing.push(folder.clone());
+            continue;
+        }
+        // As `add_library_folder` keeps them, for picking it to match
+        let folder = std::fs::canonicalize(folder).unwrap_or_else(|_| folder.clone());
+        db.execute(
+            "INSERT OR IGNORE INTO pending_folders (path)
+             SELECT ?1 WHERE NOT EXISTS (SELECT 1 FROM folders WHERE path = ?1)",
+            params![folder.to_string_lossy().into_owned()],
+        )
+        .map_err(|e| format!("Failed to add {}: {}", folder.display(), e))?;
+    }
+    Ok(missing)
+}
+
+/// Allows the library folders again, starts indexing them and watches them
+/// for changes.
+pub fn restore(app: &AppHandle) {
+    let stored = database_file(app)
+        .and_then(|file| open(&file))
+        .and_then(|db| folders(&db));
+    match stored {
+        Ok(folders) => {
+            for folder in &folders {
+                scope::allow_folder(app, Path::new(folder));
+            }
+            if !folders.is_empty() {
+                index_in_background(app);
+            }
+        }
+        Err(e) => eprintln!("Failed to read the library: {}", e),
+    }
+    watch(app);
+}
+
+// Text for a `LIKE` pattern to match as it is
+fn escape_like(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| match c {
+            '%' | '_' | '\\' => vec!['\\', c],
+            c => vec![c],
+        })
+        .collect()
+}
+
+fn entry(row: &Row<'_>) -> rusqlite::Result<LibraryEntry> {
+    Ok(LibraryEntry {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        title: row.get(2)?,
+        author: row.get(3)?,
+        subject: row.get(4)?,
+        keywords: row.get(5)?,
+        created: row.get(6)?,
+        modified: row.get(7)?,
+        size: row.get(8)?,
+        page_count: row.get(9)?,
+        has_cover: row.get(10)?,
+        tags: row
+            .get::<_, Option<String>>(11)?
+            .map(|tags| tags.split(TAG_SEPARATOR).map(str::to_string).collect())
+            .unwrap_or_default(),
+    })
+}
+
+// Tag names as a JSON array, without repeats
+fn tag_list(names: &[String]) -> String {
+    let mut names: Vec<String> = names
+        .iter()
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.sort();
+    names.dedup();
+    serde_json::to_string(&names).unwrap_or_else(|_| "[]".into())
+}
+
+/// A page of the documents a query matches, and how many it matches in all.
+pub fn list(db: &Connection, query: &LibraryQuery) -> Result<LibraryListing, String> {
+    let rule = match query.collection {
+        Some(id) => tags::collection_rule(db, id)?,
+        None => CollectionRule::default(),
+    };
+    // The tags a document needs all of, and those it needs one of
+    let mut all_tags = query.tags.clone();
+    let mut any_tags = Vec::new();
+    match rule.any_tag {
+        true => any_tags = rule.tags,
+        false => all_tags.extend(rule.tags),
+    }
+    let author = query
+        .author
+        .as_deref()
+        .or(rule.author.as_deref())
+        .map(str::trim)
+        .filter(|author| !author.is_empty())
+        .map(|author| format!("%{}%", escape_like(author)));
+    let folder = query
+        .folder
+        .as_ref()
+        .or(rule.folder.as_ref())
+        .map(|folder| {
+            let folder = folder.trim_end_matches(std::path::MAIN_SEPARATOR);
+            // The separator is a backslash on Windows, escaped too
+            let folder = format!("{}{}", folder, std::path::MAIN_SEPARATOR);
+            format!("{}%", escape_like(&folder))
+        });
+    let filter = query
+        .filter
+        .as_deref()
+        .map(str::trim)
+        .filter(|filter| !filter.is_empty())
+        .map(|filter| format!("%{}%", escape_like(filter)));
+    let conditions = "(?1 IS NULL OR d.path LIKE ?1 ESCAPE '\\')
+         AND (?2 IS NULL OR d.title LIKE ?2 ESCAPE '\\' OR t.author LIKE ?2 ESCAPE '\\'
+              OR t.subject LIKE ?2 ESCAPE '\\' OR t.keywords LIKE ?2 ESCAPE '\\'
+              OR d.path LIKE ?2 ESCAPE '\\')
+         AND (?3 IS NULL OR t.author LIKE ?3 ESCAPE '\\')
+         AND (json_array_length(?4) = 0 OR json_array_length(?4) =
+              (SELECT COUNT(*) FROM document_tags x JOIN tags g ON g.id = x.tag
+               WHERE x.path = d.path AND g.name IN (SELECT value FROM json_each(?4))))
+         AND (json_array_length(?5) = 0 OR EXISTS
+              (SELECT 1 FROM document_tags x JOIN tags g ON g.id = x.tag
+               WHERE x.path = d.path AND g.name IN (SELECT value FROM json_each(?5))))";
+    let (all_tags, any_tags) = (tag_list(&all_tags), tag_list(&any_tags));
+    let total = db
+        .query_row(
+            &format!(
+                "SELECT COUNT(*) FROM documents d LEFT JOIN details t ON t.document = d.id
+                 WHERE {}",
+                conditions
+            ),
+            params![folder, filter, author, all_tags, any_tags],
+            |row| row.get::<_, i64>(0),
+        )
+        .map_err(|e| e.to_string())? as usize;
+    let mut statement = db
+        .prepare(&format!(
+            "SELECT d.id, d.path, d.title, IFNULL(t.author, ''), IFNULL(t.subject, ''),
+                 IFNULL(t.keywords, ''), t.created, d.modified, d.size, d.page_count,
+                 t.cover IS NOT NULL,
+                 (SELECT group_concat(g.name, char({})) FROM document_tags x
+                  JOIN tags g ON g.id = x.tag WHERE x.path = d.path)
+             FROM documents d LEFT JOIN details t ON t.document = d.id
+             WHERE {}
+             ORDER BY {} {}, d.title COLLATE NOCASE, d.id
+             LIMIT ?6 OFFSET ?7",
+            TAG_SEPARATOR as u32,
+            conditions,
+            query.sort.column(),
+            if query.descending { "DESC" } else { "ASC" }
+        ))
+        .map_err(|e| e.to_string())?;
+    let limit = query.limit.unwrap_or(DEFAULT_LISTED).max(1);
+    let rows = statement
+        .query_map(
+            params![
+                folder,
+                filter,
+                author,
+                all_tags,
+                any_tags,
+                limit as i64,
+                query.offset as i64
+            ],
+            entry,
+        )
+        .map_err(|e| e.to_string())?;
+    Ok(LibraryListing {
+        documents: rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?,
+        total,
+    })
+}
+
+// The words of a query, as the index splits text into words
+fn query_words(query: &str) -> Vec<String> {
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn phrase(word: &str) -> String {
+    format!("\"{}\"", word.replace('"', "\"\""))
+}
+
+// The words of the index a few typos from `word` at most, closest first
+fn variants(db: &Connection, word: &str) -> Result<Vec<String>, String> {
+    let chars: Vec<char> = word.chars().collect();
+    let allowed = allowed_edits(chars.len());
+    if allowed == 0 {
+        return Ok(vec![word.to_string()]);
+    }
+    let mut query = db
+        .prepare("SELECT term FROM terms WHERE length(term) BETWEEN ?1 AND ?2")
+        .map_err(|e| e.to_string())?;
+    let rows = query
+        .query_map(
+            params![
+                chars.len().saturating_sub(allowed) as i64,
+                (chars.len() + allowed) as i64
+            ],
+            |row| row.get::<_, String>(0),
+        )
+        .map_err(|e| e.to_string())?;
+    let mut close = vec![(0, word.to_string())];
+    for term in rows {
+        let term = term.map_err(|e| e.to_string())?;
+        let edits = edit_distance(&chars, &term.chars().collect::<Vec<_>>());
+        if edits > 0 && edits <= allowed {
+            close.push((edits, term));
+        }
+    }
+    close.sort();
+    close.truncate(MAX_VARIANTS);
+    Ok(close.into_iter().map(|(_, term)| term).collect())
+}
+
+// The query as FTS5 phrases, so what the user types is never taken for query
+// syntax; with `fuzzy`, each word may be any of its variants
+fn match_query(db: &Connection, words: &[String], fuzzy: bool) -> Result<String, String> {
+    let mut groups = Vec::new();
+    for word in words {
+        let group = match fuzzy {
+            true => variants(db, word)?
+                .iter()
+                .map(|term| phrase(term))
+                .collect::<Vec<_>>()
+                .join(" OR "),
+            false => phrase(word),
+        };
+        groups.push(format!("({})", group));
+    }
+    Ok(groups.join(" AND "))
+}
+
+// Takes the marks out of a snippet, noting where they were
+fn highlights(marked: &str) -> (String, Vec<[usize; 2]>) {
+    let mut snippet = String::new();
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut count = 0;
+    for c in marked.chars() {
+        match c {
+            MATCH_START => start = count,
+            MATCH_END => ranges.push([start, count]),
+            _ => {
+                snippet.push(c);
+                count += 1;
+            }
+        }
+    }
+    (snippet, ranges)
+}
+
+// How close the words marked in a snippet are to those searched for, on
+// average. Words the snippet doesn't show are left out
+fn quality(words: &[String], snippet: &str, highlights: &[[usize; 2]]) -> f32 {
+    let chars: Vec<char> = snippet.chars().collect();
+    let found: Vec<Vec<char>> = highlights
+        .iter()
+        .map(|&[start, end]| {
+            chars[start..end]
+                .iter()
+                .flat_map(|c| c.to_lowercase())
+                .collect()
+        })
+        .collect();
+    let closeness: Vec<f32> = words
+        .iter()
+        .filter_map(|word| {
+            let word: Vec<char> = word.chars().collect();
+            found
+                .iter()
+                .map(|term| {
+                    let edits = edit_distance(&word, term);
+                    1.0 - edits as f32 / word.len().max(term.len()).max(1) as f32
+                })
+                .reduce(f32::max)
+        })
+        .collect();
+    match closeness.is_empty() {
+        true => 1.0,
+        false => closeness.iter().sum::<f32>() / closeness.len() as f32,
+    }
+}
+
+fn search(
+    db: &Connection,
+    query: &str,
+    fuzzy: bool,
+    limit: usize,
+) -> Result<Vec<LibraryDocument>, String> {
+    let words = query_words(query);
+    if words.is_empty() {
+        return Err("Enter the words to search for".to_string());
+    }
+    let mut ranked = db
+        .prepare(&format!(
+            "SELECT documents.path, documents.title, documents.page_count, pages.page,
+                    snippet(pages, 0, '{}', '{}', '…', {}), bm25(pages)
+             FROM pages JOIN documents ON documents.id = pages.document
+             WHERE pages MATCH ?1
+             ORDER BY bm25(pages)
+             LIMIT {}",
+            MATCH_START, MATCH_END, SNIPPET_WORDS, MAX_RANKED_PAGES
+        ))
+        .map_err(|e| e.to_string())?;
+    let rows = ranked
+        .query_map(params![match_query(db, &words, fuzzy)?], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i32>(2)?,
+                row.get::<_, i32>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, f64>(5)?,
+            ))
+        })
+        .map_err(|e| format!("Failed to search the library: {}", e))?;
+
+    // A document ranks by its best page, and its pages by their rank
+    // weighed by how close they came
+    let mut documents: Vec<LibraryDocument> = Vec::new();
+    let mut scores: Vec<Vec<f64>> = Vec::new();
+    for row in rows {
+        let (path, title, page_count, page, snippet, rank) =
+            row.map_err(|e| format!("Failed to search the library: {}", e))?;
+        let (snippet, highlights) = highlights(&snippet);
+        let quality = quality(&words, &snippet, &highlights);
+        // BM25 is lower for better matches
+        let score = -rank * quality as f64;
+        let found = LibraryMatch {
+            page,
+            snippet,
+            highlights,
+            quality,
+        };
+        match documents.iter().position(|doc| doc.path == path) {
+            Some(index) => {
+                documents[index].matches.push(found);
+                scores[index].push(score);
+            }
+            None => {
+                documents.push(LibraryDocument {
+                    path,
+                    title,
+                    page_count,
+                    score,
+                    quality,
+                    matches: vec![found],
+                });
+                scores.push(vec![score]);
+            }
+        }
+    }
+    for (doc, scores) in documents.iter_mut().zip(scores) {
+        let mut ranked: Vec<(f64, LibraryMatch)> =
+            scores.into_iter().zip(doc.matches.drain(..)).collect();
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+        ranked.truncate(MATCHES_PER_DOCUMENT);
+        doc.score = ranked[0].0;
+        doc.quality = ranked[0].1.quality;
+        doc.matches = ranked.into_iter().map(|(_, found)| found).collect();
+    }
+    documents.sort_by(|a, b| b.score.total_cmp(&a.score));
+    documents.truncate(limit);
+    Ok(documents)
+}
+
+/// Lists the library folders and how much of them is indexed.
+#[tauri::command]
+pub async fn get_library_status(app: AppHandle) -> Result<LibraryStatus, String> {
+    let file = database_file(&app)?;
+    blocking(move || status(&app, &open(&file)?)).await
+}
+
+/// Adds a folder the user picked to the library and indexes the PDFs in it.
+/// Picking an imported folder this way is what adds it.
+#[tauri::command]
+pub async fn add_library_folder(app: AppHandle, path: String) -> Result<LibraryStatus, String> {
+    let folder = Path::new(&path);
+    scope::check(&app, folder)?;
+    if !folder.is_dir() {
+        return Err(format!("{} isn't a folder", path));
+    }
+    let folder = std::fs::canonicalize(folder).unwrap_or_else(|_| folder.to_path_buf());
+    let file = database_file(&app)?;
+    blocking(move || {
+        let db = open(&file)?;
+        db.execute(
+            "INSERT OR IGNORE INTO folders (path) VALUES (?1)",
+            params![folder.to_string_lossy().into_owned()],
+        )
+        .map_err(|e| format!("Failed to add {}: {}", folder.display(), e))?;
+        db.execute(
+            "DELETE FROM pending_folders WHERE path = ?1",
+            params![folder.to_string_lossy().into_owned()],
+        )
+        .map_err(|e| e.to_string())?;
+        eprintln!("Added {} to the library", folder.display());
+        index_in_background(&app);
+        status(&app, &db)
+    })
+    .await
+}
+
+/// Takes a folder out of the library, along with the documents indexed from
+/// it, or drops an imported one that's waiting to be picked.
+#[tauri::command]
+pub async fn remove_library_folder(app: AppHandle, path: String) -> Result<LibraryStatus, String> {
+    let file = database_file(&app)?;
+    blocking(move || {
+        let db = open(&file)?;
+        let mut removed = 0;
+        for sql in [
+            "DELETE FROM folders WHERE path = ?1",
+            "DELETE FROM pending_folders WHERE path = ?1",
+        ] {
+            removed += db
+                .execute(sql, params![path])
+                .map_err(|e| format!("Failed to remove {}: {}", path, e))?;
+        }
+        if removed == 0 {
+            return Err(format!("{} isn't in the library", path));
+        }
+        eprintln!("Removed {} from the library", path);
+        index_in_background(&app);
+        status(&app, &db)
+    })
+    .await
+}
+
+/// Indexes the library folders again, e.g. after files were added to them.
+#[tauri::command]
+pub async fn reindex_library(app: AppHandle) -> Result<(), String> {
+    index_in_background(&app);
+    Ok(())
+}
+
+/// Searches the text of every document in the library, and returns the
+/// documents that match best, best first, each with its best pages. With
+/// `fuzzy`, words a few typos apart from those of the query match too.
+#[tauri::command]
+pub async fn search_library(
+    app: AppHandle,
+    query: String,
+    fuzzy: Option<bool>,
+    limit: Option<usize>,
+) -> Result<Vec<LibraryDocument>, String> {
+    let fuzzy = fuzzy.unwrap_or(false);
+    let limit = limit.unwrap_or(DEFAULT_RESULTS).max(1);
+    let file = database_file(&app)?;
+    blocking(move || search(&open(&file)?, &query, fuzzy, limit)).await
+}
+
+/// The documents of the library with their metadata, for the library's grid:
+/// a page of them, in the order asked for.
+#[tauri::command]
+pub async fn list_library(
+    app: AppHandle,
+    query: Option<LibraryQuery>,
+) -> Result<LibraryListing, String> {
+    let query = query.unwrap_or_default();
+    let file = database_file(&app)?;
+    blocking(move || list(&open(&file)?, &query)).await
+}
+
+/// The cover of a document of the library, as a PNG.
+#[tauri::command]
+pub async fn get_library_cover(app: AppHandle, id: i64) -> Result<Response, String> {
+    let file = database_file(&app)?;
+    let cover = blocking(move || {
+        open(&file)?
+            .query_row(
+                "SELECT cover FROM details WHERE document = ?1",
+                params![id],
+                |row| row.get::<_, Option<Vec<u8>>>(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?
+            .flatten()
+            .ok_or_else(|| format!("Document {} of the library has no cover", id))
+    })
+    .await?;
+    Ok(Response::new(cover))
+}
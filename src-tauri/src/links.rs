@@ -0,0 +1,227 @@
+// Link annotations and their targets.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use serde::Serialize;
+use tauri::{AppHandle, State};
+
+use crate::objects::{self, PageSpace};
+use crate::outline::{lookup_named_destination, resolve_destination, Destination};
+use crate::pdf::{blocking, Bounds, DocId, DocumentStore};
+use crate::scope;
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum LinkTarget {
+    /// Web or mail link
+    Uri { uri: String },
+    /// Jump inside this document
+    #[serde(rename_all = "camelCase")]
+    Page {
+        destination: Destination,
+        /// Set when the link used a named destination
+        name: Option<String>,
+    },
+    /// Jump into another PDF (GoToR)
+    #[serde(rename_all = "camelCase")]
+    Remote {
+        file: String,
+        /// Zero-based page, for explicit destinations
+        page: Option<i32>,
+        /// For named destinations, resolve with `resolve_named_destination`
+        name: Option<String>,
+        new_window: bool,
+    },
+    /// Request to open a file or program; never followed automatically
+    Launch { file: String },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageLink {
+    pub bounds: Bounds,
+    pub target: LinkTarget,
+}
+
+/// Reads a file specification (string or dictionary) and resolves it relative
+/// to the directory of the current document.
+pub fn file_spec(doc: &Document, spec: &Object, base: &Path) -> Option<String> {
+    let name = match objects::resolve(doc, spec) {
+        Object::Dictionary(dict) => {
+            objects::get_text(doc, dict, b"UF").or_else(|| objects::get_text(doc, dict, b"F"))?
+        }
+        other => objects::text_string(other)?,
+    };
+    let path = Path::new(&name);
+    if path.is_absolute() {
+        return Some(name);
+    }
+    let dir = base.parent().unwrap_or(Path::new(""));
+    Some(dir.join(path).to_string_lossy().into_owned())
+}
+
+fn dest_name(doc: &Document, dest: &Object) -> Option<String> {
+    match objects::resolve(doc, dest) {
+        obj @ (Object::Name(_) | Object::String(..)) => objects::text_string(obj),
+        _ => None,
+    }
+}
+
+fn link_target(
+    doc: &Document,
+    pages: &HashMap<ObjectId, i32>,
+    annot: &Dictionary,
+    base: &Path,
+) -> Option<LinkTarget> {
+    if let Ok(dest) = annot.get(b"Dest") {
+        return Some(LinkTarget::Page {
+            destination: resolve_destination(doc, pages, dest)?,
+            name: dest_name(doc, dest),
+        });
+    }
+
+    let action = objects::get_dict(doc, annot, b"A")?;
+    match objects::get_name(doc, action, b"S")? {
+        b"URI" => Some(LinkTarget::Uri {
+            uri: objects::get_text(doc, action, b"URI")?,
+        }),
+        b"GoTo" => {
+            let dest = action.get(b"D").ok()?;
+            Some(LinkTarget::Page {
+                destination: resolve_destination(doc, pages, dest)?,
+                name: dest_name(doc, dest),
+            })
+        }
+        b"GoToR" => {
+            let dest = action.get(b"D").ok().map(|d| objects::resolve(doc, d));
+            let page = dest
+                .and_then(|d| d.as_array().ok())
+                .and_then(|a| a.first())
+                .and_then(|p| p.as_i64().ok())
+                .map(|p| p as i32);
+            Some(LinkTarget::Remote {
+                file: file_spec(doc, action.get(b"F").ok()?, base)?,
+                page,
+                name: dest.and_then(|d| dest_name(doc, d)),
+                new_window: objects::get(doc, action, b"NewWindow")
+                    .and_then(|v| v.as_bool().ok())
+                    .unwrap_or(false),
+            })
+        }
+        b"Launch" => Some(LinkTarget::Launch {
+            file: file_spec(doc, action.get(b"F").ok()?, base)?,
+        }),
+        _ => None,
+    }
+}
+
+/// Returns the link annotations of one page, in page space.
+pub fn page_links(doc: &Document, page: i32, base: &Path) -> Vec<PageLink> {
+    let pages = objects::page_indices(doc);
+    let Some(page_id) = doc.get_pages().get(&(page as u32 + 1)).copied() else {
+        return Vec::new();
+    };
+    let Ok(page_dict) = doc.get_dictionary(page_id) else {
+        return Vec::new();
+    };
+    let space = PageSpace::new(doc, page_id);
+
+    let mut links = Vec::new();
+    for annot in objects::get_array(doc, page_dict, b"Annots").unwrap_or(&[]) {
+        let Ok(annot) = objects::resolve(doc, annot).as_dict() else {
+            continue;
+        };
+        if objects::get_name(doc, annot, b"Subtype") != Some(b"Link") {
+            continue;
+        }
+        let Some(rect) = annot.get(b"Rect").ok().and_then(|r| objects::rect(doc, r)) else {
+            continue;
+        };
+        if let Some(target) = link_target(doc, &pages, annot, base) {
+            links.push(PageLink {
+                bounds: space.rect(rect),
+                target,
+            });
+        }
+    }
+    links
+}
+
+#[tauri::command]
+pub async fn get_page_links(
+    doc_id: DocId,
+    page: i32,
+    store: State<'_, DocumentStore>,
+) -> Result<Vec<PageLink>, String> {
+    let doc = store.get(doc_id)?;
+    doc.check_page(page)?;
+    blocking(move || doc.with_objects(|objects| Ok(page_links(objects, page, &doc.path)))).await
+}
+
+// Resolves a link fragment: a bare destination name, or PDF open parameters
+// such as `nameddest=intro` or `page=3&zoom=150`
+fn fragment_destination(doc: &Document, fragment: &str) -> Option<Destination> {
+    let pages = objects::page_indices(doc);
+    let mut name = fragment.trim_start_matches('#');
+    for param in name.split('&') {
+        match param.split_once('=') {
+            Some(("nameddest", value)) => name = value,
+            Some(("page", value)) => {
+                let page = value.parse::<i32>().ok()? - 1;
+                return (0..pages.len() as i32)
+                    .contains(&page)
+                    .then_some(Destination {
+                        page,
+                        left: None,
+                        top: None,
+                        zoom: None,
+                    });
+            }
+            _ => {}
+        }
+    }
+    let target = lookup_named_destination(doc, name.as_bytes())?;
+    resolve_destination(doc, &pages, target)
+}
+
+/// Resolves a named destination, e.g. the `section3` of `file.pdf#section3`.
+///
+/// With `file` (from a `remote` link), the name is looked up in that PDF
+/// instead; it's parsed from disk unless it's open already. Returns `None` if
+/// the name doesn't exist.
+#[tauri::command]
+pub async fn resolve_named_destination(
+    app: AppHandle,
+    doc_id: DocId,
+    name: String,
+    file: Option<String>,
+    store: State<'_, DocumentStore>,
+) -> Result<Option<Destination>, String> {
+    let doc = store.get(doc_id)?;
+    let Some(file) = file else {
+        return blocking(move || {
+            doc.with_objects(|objects| Ok(fragment_destination(objects, &name)))
+        })
+        .await;
+    };
+
+    let path = doc.path.parent().unwrap_or(Path::new("")).join(file);
+    if let Some(other) = store.find_by_path(&path) {
+        return blocking(move || {
+            other.with_objects(|objects| Ok(fragment_destination(objects, &name)))
+        })
+        .await;
+    }
+    // The file is named by the document, which may send it anywhere
+    let path = std::fs::canonicalize(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    scope::check(&app, &path)?;
+    blocking(move || {
+        let other = Document::load(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Ok(fragment_destination(&other, &name))
+    })
+    .await
+}
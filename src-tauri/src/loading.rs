@@ -0,0 +1,164 @@
+// Reading documents from disk with progress reporting.
+//
+// Files are read in chunks and every chunk is announced with a
+// `document-loading-progress` event. Linearized ("fast web view") files keep
+// everything needed for the first page at the start of the file, so big ones
+// are registered as soon as that part has been read: page 1 can be shown while
+// the rest is loading, and `document-loaded` follows once the full file has
+// replaced the partial one. Files big enough to be mapped (see `pdf.rs`) aren't
+// read here; their first page is copied out of the map the same way, and the
+// map replaces it once MuPDF has opened the whole file.
+//
+// Only the first page's end (/E) is taken from the linearization dictionary.
+// The hint tables locate the objects of every other page, for readers that
+// fetch pages out of order over a network; here the file is read from start
+// to end and no page but the first is shown before all of it is there, so
+// they'd tell nothing the first page's end doesn't.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+
+use crate::pdf::DocId;
+
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+// The linearization dictionary must be the first object in the file
+pub const HEADER_SIZE: usize = 1024;
+// Smaller files are read in one go; opening them twice isn't worth it
+pub const PROGRESSIVE_MIN_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Parameters from a linearization dictionary.
+pub struct Linearization {
+    /// File length the dictionary was written for
+    pub length: u64,
+    /// Offset of the end of the first page's objects
+    pub first_page_end: u64,
+    pub page_count: i32,
+}
+
+// Reads an unsigned integer following `key` in a dictionary's source text
+fn dict_number(dict: &[u8], key: &[u8]) -> Option<u64> {
+    let mut rest = dict;
+    while let Some(pos) = rest.windows(key.len()).position(|w| w == key) {
+        rest = &rest[pos + key.len()..];
+        // Skip longer keys that merely start with `key`, like /Linearized
+        if rest.first().is_some_and(|c| c.is_ascii_alphanumeric()) {
+            continue;
+        }
+        let start = rest.iter().position(|c| !c.is_ascii_whitespace())?;
+        let digits: Vec<u8> = rest[start..]
+            .iter()
+            .copied()
+            .take_while(u8::is_ascii_digit)
+            .collect();
+        return std::str::from_utf8(&digits).ok()?.parse().ok();
+    }
+    None
+}
+
+/// Finds the linearization parameters at the start of a file.
+pub fn linearization(header: &[u8]) -> Option<Linearization> {
+    let start = header.windows(11).position(|w| w == b"/Linearized")?;
+    let end = header[start..].windows(2).position(|w| w == b">>")? + start;
+    let dict = &header[start..end];
+    Some(Linearization {
+        length: dict_number(dict, b"/L")?,
+        first_page_end: dict_number(dict, b"/E")?,
+        page_count: dict_number(dict, b"/N")? as i32,
+    })
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadingProgress {
+    pub path: String,
+    /// Set once a partially loaded document has been registered
+    pub doc_id: Option<DocId>,
+    pub loaded: u64,
+    pub total: u64,
+    pub linearized: bool,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentLoaded {
+    pub doc_id: DocId,
+    pub page_count: i32,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadFailed {
+    pub doc_id: DocId,
+    pub error: String,
+}
+
+/// Reads a file in chunks, emitting progress events.
+pub struct ProgressReader {
+    app: AppHandle,
+    pub path: PathBuf,
+    file: File,
+    pub total: u64,
+    pub data: Vec<u8>,
+    pub linearization: Option<Linearization>,
+}
+
+impl ProgressReader {
+    /// Opens `path` and reads its first chunk.
+    pub fn open(app: AppHandle, path: PathBuf) -> Result<Self, String> {
+        let error = |e: std::io::Error| format!("Failed to read {}: {}", path.display(), e);
+        let file = File::open(&path).map_err(error)?;
+        let total = file.metadata().map_err(error)?.len();
+        let mut reader = ProgressReader {
+            app,
+            path: path.clone(),
+            file,
+            total,
+            data: Vec::with_capacity(total as usize),
+            linearization: None,
+        };
+        reader.read_to(HEADER_SIZE as u64, None)?;
+        // Files changed by incremental updates no longer match their
+        // linearization, and their first page may use the appended objects
+        reader.linearization = linearization(&reader.data).filter(|l| l.length == total);
+        Ok(reader)
+    }
+
+    /// Reads until at least `end` bytes (or the whole file) are loaded.
+    pub fn read_to(&mut self, end: u64, doc_id: Option<DocId>) -> Result<(), String> {
+        let end = end.min(self.total) as usize;
+        let mut chunk = vec![0; CHUNK_SIZE];
+        while self.data.len() < end {
+            let want = (end - self.data.len()).min(CHUNK_SIZE);
+            let read = self
+                .file
+                .read(&mut chunk[..want])
+                .map_err(|e| format!("Failed to read {}: {}", self.path.display(), e))?;
+            if read == 0 {
+                return Err(format!(
+                    "{} was truncated while reading",
+                    self.path.display()
+                ));
+            }
+            self.data.extend_from_slice(&chunk[..read]);
+            let _ = self.app.emit(
+                "document-loading-progress",
+                LoadingProgress {
+                    path: self.path.to_string_lossy().into_owned(),
+                    doc_id,
+                    loaded: self.data.len() as u64,
+                    total: self.total,
+                    linearized: self.linearization.is_some(),
+                },
+            );
+        }
+        Ok(())
+    }
+
+    pub fn read_all(&mut self, doc_id: Option<DocId>) -> Result<(), String> {
+        self.read_to(self.total, doc_id)
+    }
+}
@@ -0,0 +1,195 @@
+// Small least-recently-used cache with a byte budget.
+//
+// Entries carry their own size so the cache can be bounded by memory rather
+// than by entry count, which matters when the values are bitmaps of very
+// different dimensions.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+use serde::Serialize;
+
+pub struct LruCache<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    // Access tick -> key, oldest first
+    order: BTreeMap<u64, K>,
+    tick: u64,
+    used: usize,
+    budget: usize,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub entries: usize,
+    pub used_bytes: usize,
+    pub budget_bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Share of lookups that were hits, 0 before the first lookup
+    pub hit_rate: f64,
+}
+
+struct Entry<V> {
+    value: V,
+    size: usize,
+    tick: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    pub fn new(budget: usize) -> Self {
+        LruCache {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            used: 0,
+            budget,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Looks up `key` and marks it as most recently used.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.tick += 1;
+        let Some(entry) = self.entries.get_mut(key) else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        self.order.remove(&entry.tick);
+        entry.tick = self.tick;
+        self.order.insert(self.tick, key.clone());
+        Some(&entry.value)
+    }
+
+    /// Inserts `value`, evicting the oldest entries until it fits the budget.
+    /// Values larger than the whole budget are not cached.
+    pub fn insert(&mut self, key: K, value: V, size: usize) {
+        self.remove(&key);
+        if size > self.budget {
+            return;
+        }
+
+        self.tick += 1;
+        self.used += size;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                size,
+                tick: self.tick,
+            },
+        );
+        self.evict();
+    }
+
+    /// Checks for `key` without counting a lookup or touching the order.
+    pub fn contains(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Bytes currently held.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Changes the budget, evicting the oldest entries if it shrank.
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let lookups = self.hits + self.misses;
+        CacheStats {
+            entries: self.entries.len(),
+            used_bytes: self.used,
+            budget_bytes: self.budget,
+            hits: self.hits,
+            misses: self.misses,
+            hit_rate: if lookups == 0 {
+                0.0
+            } else {
+                self.hits as f64 / lookups as f64
+            },
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&entry.tick);
+        self.used -= entry.size;
+        Some(entry.value)
+    }
+
+    /// Drops every entry whose key does not satisfy `keep`.
+    pub fn retain(&mut self, mut keep: impl FnMut(&K) -> bool) {
+        let stale: Vec<K> = self.entries.keys().filter(|k| !keep(k)).cloned().collect();
+        for key in stale {
+            self.remove(&key);
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.used > self.budget {
+            let Some((_, key)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&key) {
+                self.used -= entry.size;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used_goes_first() {
+        let mut cache = LruCache::new(10);
+        cache.insert("a", 1, 4);
+        cache.insert("b", 2, 4);
+        assert_eq!(cache.get(&"a"), Some(&1));
+        cache.insert("c", 3, 4);
+        assert!(cache.contains(&"a") && cache.contains(&"c"));
+        assert!(!cache.contains(&"b"));
+        assert_eq!(cache.used(), 8);
+
+        // Replacing an entry counts only its new size
+        cache.insert("a", 4, 2);
+        assert_eq!(cache.used(), 6);
+        assert_eq!(cache.get(&"a"), Some(&4));
+    }
+
+    #[test]
+    fn budget_bounds_the_entries() {
+        let mut cache = LruCache::new(10);
+        cache.insert("big", 1, 11);
+        assert!(!cache.contains(&"big"));
+        assert_eq!(cache.used(), 0);
+
+        cache.insert("a", 1, 4);
+        cache.insert("b", 2, 4);
+        cache.set_budget(5);
+        assert!(!cache.contains(&"a") && cache.contains(&"b"));
+        assert_eq!(cache.used(), 4);
+    }
+
+    #[test]
+    fn lookups_are_counted() {
+        let mut cache = LruCache::new(10);
+        cache.insert("a", 1, 1);
+        cache.get(&"a");
+        cache.get(&"b");
+        cache.get(&"a");
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (2, 1, 1));
+        assert!((stats.hit_rate - 2.0 / 3.0).abs() < 1e-9);
+    }
+}
@@ -1,7 +1,83 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use tauri::{Emitter, Manager};
+mod annotations;
+mod attachments;
+mod bookmarks;
+mod cms;
+mod colors;
+mod compose;
+mod content;
+mod context_menu;
+mod convert;
+mod deep_link;
+mod default_viewer;
+mod desktop;
+mod diagnostics;
+mod display;
+mod edit;
+mod engine;
+mod export;
+mod fdf;
+#[cfg(test)]
+mod fixtures;
+mod flatten;
+mod forms;
+mod images;
+mod incremental;
+mod jobs;
+mod keystore;
+mod labels;
+mod launch;
+mod library;
+mod links;
+mod loading;
+mod lru;
+mod menu_bar;
+mod metadata;
+mod numbering;
+mod objects;
+mod ocr;
+mod ocr_queue;
+mod optimize;
+mod outline;
+mod passwords;
+mod pdf;
+mod pdfa;
+mod permissions;
+mod preferences;
+mod print;
+mod reading;
+mod reading_stats;
+mod recent;
+mod recent_files;
+mod redact;
+#[cfg(windows)]
+mod registry;
+mod repair;
+mod reveal;
+mod scan;
+mod scheduler;
+mod scope;
+mod search;
+mod security;
+mod session;
+mod share;
+mod shortcut;
+mod sidecar;
+mod signatures;
+mod signing;
+mod stamp;
+mod tags;
+mod text;
+mod theme;
+mod thumbnails;
+mod tiles;
+mod transfer;
+mod tray;
+mod window_manager;
+mod window_state;
+mod xfdf;
 
 #[tauri::command]
 async fn open_file_path(_file_path: String) -> Result<(), String> {
@@ -10,59 +86,232 @@ async fn open_file_path(_file_path: String) -> Result<(), String> {
 }
 
 fn main() {
-    tauri::Builder::default()
+    launch::listen();
+    let context = tauri::generate_context!();
+    let mut builder = tauri::Builder::default();
+    // First, so a second instance quits before setting anything else up
+    if launch::single_instance(&context.config().identifier) {
+        builder = builder.plugin(tauri_plugin_single_instance::init(|app, args, cwd| {
+            launch::forwarded(app, args, cwd)
+        }));
+    }
+    builder
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_os::init())
         .plugin(tauri_plugin_updater::Builder::new().build())
         .plugin(tauri_plugin_process::init())
+        .plugin(
+            tauri_plugin_global_shortcut::Builder::new()
+                .with_handler(shortcut::handle)
+                .build(),
+        )
+        .manage(pdf::DocumentStore::default())
+        .manage(engine::Engine::new())
+        .manage(tiles::TileCache::default())
+        .manage(scheduler::RenderScheduler::default())
+        .manage(jobs::RenderJobs::default())
+        .manage(security::LockedDocuments::default())
+        .manage(permissions::PermissionPolicy::default())
+        .manage(recent_files::RecentFiles::default())
+        .manage(library::Library::default())
+        .manage(ocr_queue::OcrQueue::default())
+        .manage(launch::OpenRequests::default())
+        .manage(context_menu::ContextMenus::default())
+        .manage(session::Session::default())
+        .manage(preferences::PreferencesStore::default())
+        .manage(window_state::WindowStates::default())
+        .manage(window_manager::WindowManager::default())
+        .on_page_load(launch::page_loading)
+        .on_menu_event(context_menu::menu_event)
+        .on_menu_event(menu_bar::menu_event)
+        .on_window_event(|window, event| {
+            display::handle_window_event(window, event);
+            theme::handle_window_event(window, event);
+            scope::handle_window_event(window, event);
+            launch::handle_window_event(window, event);
+            tray::handle_window_event(window, event);
+            window_state::handle_window_event(window, event);
+            window_manager::handle_window_event(window, event);
+        })
         .setup(|app| {
-            // Handle file opening from command line arguments
-            // When a file is opened via file association, Tauri passes it as a command-line argument
-            let args: Vec<String> = std::env::args().collect();
-            eprintln!("Command line args: {:?}", args);
-            
-            if args.len() > 1 {
-                let raw_file_path = &args[1];
-                eprintln!("Processing file path: {}", raw_file_path);
-
-                // Only process if it looks like a file path (not a flag)
-                if !raw_file_path.starts_with('-') {
-                    // Clean the path - remove surrounding quotes if present
-                    let file_path = raw_file_path.trim_matches('"').trim_matches('\'');
-
-                    // Check if it ends with .pdf (case insensitive) or if the path exists
-                    let is_pdf = file_path.to_lowercase().ends_with(".pdf");
-                    let path_exists = std::path::Path::new(file_path).exists();
-
-                    eprintln!("Cleaned path: {}, Is PDF: {}, Path exists: {}", file_path, is_pdf, path_exists);
+            preferences::restore(app.handle());
+            recent_files::restore(app.handle());
+            library::restore(app.handle());
+            ocr_queue::restore(app.handle());
 
-                    if is_pdf || path_exists {
-                        // Emit event to frontend after a delay to ensure window is ready
-                        let app_handle = app.handle().clone();
-                        let file_path_clone = file_path.to_string();
-                        std::thread::spawn(move || {
-                            // Wait longer to ensure window is fully ready
-                            std::thread::sleep(std::time::Duration::from_millis(1500));
-                            eprintln!("Attempting to emit event for file: {}", file_path_clone);
-                            if let Some(window) = app_handle.get_webview_window("main") {
-                                match window.emit("open-pdf-file", &file_path_clone) {
-                                    Ok(_) => eprintln!("Successfully emitted open-pdf-file event"),
-                                    Err(e) => eprintln!("Error emitting event: {:?}", e),
-                                }
-                            } else {
-                                eprintln!("Window 'main' not found");
-                            }
-                        });
-                    }
-                }
-            }
+            window_state::setup(app.handle());
+            launch::setup(app.handle());
+            menu_bar::setup(app.handle());
+            recent::setup(app.handle());
+            deep_link::register();
+            desktop::setup(app.handle());
+            tray::setup(app.handle());
+            shortcut::setup(app.handle());
+            session::setup(app.handle());
 
             Ok(())
         })
-        .invoke_handler(tauri::generate_handler![open_file_path])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .invoke_handler(tauri::generate_handler![
+            open_file_path,
+            launch::frontend_ready,
+            recent_files::add_recent,
+            recent_files::get_recents,
+            recent_files::pin_recent,
+            recent_files::clear_recents,
+            launch::get_launch_settings,
+            launch::set_launch_settings,
+            reveal::reveal_in_file_manager,
+            share::share_document,
+            context_menu::show_context_menu,
+            default_viewer::is_default_pdf_viewer,
+            default_viewer::set_default_pdf_viewer,
+            desktop::get_desktop_integration,
+            desktop::install_desktop_integration,
+            desktop::uninstall_desktop_integration,
+            tray::get_tray_settings,
+            tray::set_tray_settings,
+            shortcut::get_shortcut_settings,
+            shortcut::set_shortcut_settings,
+            print::print_document,
+            print::render_print_preview,
+            pdf::open_document,
+            pdf::close_document,
+            pdf::get_page_size,
+            pdf::render_page,
+            tiles::render_tile,
+            scheduler::set_prerender_radius,
+            scheduler::set_page_cache_budget,
+            scheduler::get_cache_stats,
+            jobs::cancel_render,
+            thumbnails::get_thumbnail,
+            text::get_page_text_layout,
+            text::extract_text,
+            outline::get_outline,
+            labels::get_page_labels,
+            labels::resolve_page_label,
+            links::get_page_links,
+            links::resolve_named_destination,
+            metadata::get_document_info,
+            attachments::list_attachments,
+            attachments::save_attachment,
+            export::render_page_svg,
+            display::get_scale_factor,
+            theme::get_system_theme,
+            diagnostics::get_memory_usage,
+            compose::merge_documents,
+            compose::extract_pages,
+            edit::rotate_pages,
+            edit::crop_pages,
+            edit::move_pages,
+            edit::delete_pages,
+            edit::insert_blank_pages,
+            edit::insert_pages_from_file,
+            edit::save_document,
+            optimize::optimize_document,
+            flatten::flatten_annotations,
+            stamp::stamp_pages,
+            redact::redact_pages,
+            pdfa::preflight_pdfa,
+            pdfa::convert_to_pdfa,
+            images::extract_images,
+            export::export_page_images,
+            convert::export_document,
+            compose::create_pdf_from_images,
+            numbering::number_pages,
+            attachments::add_attachments,
+            metadata::set_document_info,
+            security::unlock_document,
+            security::forget_password,
+            security::save_encrypted,
+            security::decrypt_and_save,
+            permissions::get_permissions,
+            permissions::unlock_permissions,
+            permissions::set_enforce_permissions,
+            signatures::get_signatures,
+            signing::list_signing_certificates,
+            signing::sign_document,
+            forms::get_form_fields,
+            forms::get_form_capabilities,
+            forms::fill_form_fields,
+            fdf::export_form_data,
+            fdf::import_form_data,
+            annotations::get_annotations,
+            annotations::add_annotation,
+            annotations::update_annotation,
+            annotations::delete_annotation,
+            annotations::export_annotation_summary,
+            annotations::merge_annotations,
+            xfdf::export_annotations_xfdf,
+            xfdf::import_annotations_xfdf,
+            reading::get_document_state,
+            reading::save_document_state,
+            reading_stats::record_reading,
+            reading_stats::get_reading_stats,
+            reading_stats::clear_reading_stats,
+            preferences::get_preferences,
+            preferences::set_preferences,
+            preferences::update_preferences,
+            preferences::reset_preferences,
+            preferences::import_legacy_preferences,
+            bookmarks::get_bookmarks,
+            bookmarks::add_bookmark,
+            bookmarks::update_bookmark,
+            bookmarks::delete_bookmark,
+            bookmarks::export_bookmarks,
+            bookmarks::import_bookmarks,
+            session::update_session,
+            session::get_previous_session,
+            session::restore_session,
+            session::discard_session,
+            window_manager::open_in_new_window,
+            window_manager::move_document_to_window,
+            window_manager::get_windows,
+            window_manager::set_window_document,
+            session::get_session_settings,
+            session::set_session_settings,
+            sidecar::get_sidecar_annotations,
+            sidecar::add_sidecar_annotation,
+            sidecar::delete_sidecar_annotation,
+            sidecar::bake_sidecar_annotations,
+            search::search_document,
+            search::search_open_documents,
+            ocr::ocr_pages,
+            ocr::embed_ocr_text,
+            ocr::list_ocr_languages,
+            ocr::download_ocr_language,
+            ocr::remove_ocr_language,
+            ocr_queue::get_ocr_queue,
+            ocr_queue::queue_ocr_documents,
+            ocr_queue::pause_ocr_job,
+            ocr_queue::resume_ocr_job,
+            ocr_queue::cancel_ocr_job,
+            library::get_library_status,
+            library::add_library_folder,
+            library::remove_library_folder,
+            library::reindex_library,
+            library::search_library,
+            library::list_library,
+            library::get_library_cover,
+            tags::get_tags,
+            tags::get_document_tags,
+            tags::tag_documents,
+            tags::untag_documents,
+            tags::rename_tag,
+            tags::delete_tag,
+            tags::get_collections,
+            tags::save_collection,
+            tags::delete_collection,
+            transfer::export_settings,
+            transfer::import_settings,
+        ])
+        .build(context)
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            launch::handle_run_event(app, &event);
+            session::handle_run_event(app, &event);
+            window_state::handle_run_event(app, &event);
+        });
 }
 
 
@@ -0,0 +1,268 @@
+// The application's menu bar.
+//
+// The menus are built here, with each system's own layout and shortcuts:
+// on macOS the app menu comes first and Quit is in it, Windows says Exit,
+// and full screen is ⌃⌘F on macOS and F11 elsewhere. Clipboard, window and
+// app items are the system's own. "Open…" and Open Recent are handled here,
+// like files opened anywhere else (see `launch.rs`), and so is Full Screen;
+// every other item is the frontend's to carry out, and is sent to it as a
+// `menu-action` event with the item's id (see `Editor.tsx`). Open Recent
+// lists the recently opened files (see `recent_files.rs`) and is rebuilt as
+// they change. On macOS it's the system's Open Recent menu instead (see
+// `recent.rs`).
+
+#[cfg(not(target_os = "macos"))]
+use std::path::PathBuf;
+
+use tauri::menu::{AboutMetadata, Menu, MenuEvent, MenuItem, PredefinedMenuItem, Submenu};
+use tauri::{AppHandle, Emitter, Manager, Wry};
+
+use crate::context_menu::REVEAL_LABEL;
+use crate::launch;
+#[cfg(not(target_os = "macos"))]
+use crate::recent_files;
+use crate::window_manager;
+
+// Item ids, all with this prefix so other menus' events are told apart
+const PREFIX: &str = "menu:";
+const OPEN_ITEM: &str = "open-file";
+const FULL_SCREEN_ITEM: &str = "full-screen";
+#[cfg(not(target_os = "macos"))]
+const RECENT_PREFIX: &str = "recent:";
+// As many recent files as fit a menu comfortably
+#[cfg(not(target_os = "macos"))]
+const MENU_FILES: usize = 10;
+
+#[cfg(target_os = "macos")]
+const FULL_SCREEN: &str = "Ctrl+Cmd+F";
+#[cfg(not(target_os = "macos"))]
+const FULL_SCREEN: &str = "F11";
+
+type Item = (&'static str, &'static str, Option<&'static str>);
+
+// The items after Open Recent, by id, label and shortcut; separators have
+// no id
+const FILE_ITEMS: &[Item] = &[
+    ("", "", None),
+    ("save", "Save", Some("CmdOrCtrl+S")),
+    ("save-as", "Save As…", Some("CmdOrCtrl+Shift+S")),
+    ("export", "Export…", Some("CmdOrCtrl+E")),
+    ("combine-files", "Combine Files…", None),
+    ("", "", None),
+    ("print", "Print…", Some("CmdOrCtrl+P")),
+    ("", "", None),
+    ("reveal", REVEAL_LABEL, None),
+    ("", "", None),
+    ("close-tab", "Close Tab", Some("CmdOrCtrl+W")),
+];
+const FIND_ITEMS: &[Item] = &[
+    ("", "", None),
+    ("find", "Find…", Some("CmdOrCtrl+F")),
+    ("find-next", "Find Next", Some("CmdOrCtrl+G")),
+    ("find-previous", "Find Previous", Some("CmdOrCtrl+Shift+G")),
+];
+const VIEW_ITEMS: &[Item] = &[
+    ("zoom-in", "Zoom In", Some("CmdOrCtrl+=")),
+    ("zoom-out", "Zoom Out", Some("CmdOrCtrl+-")),
+    ("actual-size", "Actual Size", Some("CmdOrCtrl+0")),
+    ("fit-page", "Fit Page", Some("CmdOrCtrl+1")),
+    ("fit-width", "Fit Width", Some("CmdOrCtrl+2")),
+    ("", "", None),
+    ("rotate-left", "Rotate Left", Some("CmdOrCtrl+Shift+L")),
+    ("rotate-right", "Rotate Right", Some("CmdOrCtrl+Shift+R")),
+    ("", "", None),
+    ("toggle-sidebar", "Sidebar", Some("CmdOrCtrl+Alt+S")),
+    ("", "", None),
+    (FULL_SCREEN_ITEM, "Full Screen", Some(FULL_SCREEN)),
+];
+const TAB_ITEMS: &[Item] = &[
+    ("", "", None),
+    ("next-tab", "Next Tab", Some("Ctrl+Tab")),
+    ("previous-tab", "Previous Tab", Some("Ctrl+Shift+Tab")),
+];
+const HELP_ITEMS: &[Item] = &[("shortcuts", "Keyboard Shortcuts", None)];
+
+fn add_items(app: &AppHandle, menu: &Submenu<Wry>, items: &[Item]) -> tauri::Result<()> {
+    for &(id, text, accelerator) in items {
+        if id.is_empty() {
+            menu.append(&PredefinedMenuItem::separator(app)?)?;
+        } else {
+            menu.append(&MenuItem::with_id(
+                app,
+                format!("{}{}", PREFIX, id),
+                text,
+                true,
+                accelerator,
+            )?)?;
+        }
+    }
+    Ok(())
+}
+
+fn separator(app: &AppHandle) -> tauri::Result<PredefinedMenuItem<Wry>> {
+    PredefinedMenuItem::separator(app)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn recent_menu(app: &AppHandle) -> tauri::Result<Submenu<Wry>> {
+    let files: Vec<PathBuf> = recent_files::paths(app)
+        .into_iter()
+        .take(MENU_FILES)
+        .collect();
+    let recent = Submenu::new(app, "Open Recent", !files.is_empty())?;
+    for path in &files {
+        let name = path.file_name().map_or_else(
+            || path.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        recent.append(&MenuItem::with_id(
+            app,
+            format!("{}{}{}", PREFIX, RECENT_PREFIX, path.display()),
+            name,
+            true,
+            None::<&str>,
+        )?)?;
+    }
+    Ok(recent)
+}
+
+fn about(app: &AppHandle) -> tauri::Result<PredefinedMenuItem<Wry>> {
+    let info = app.package_info();
+    let metadata = AboutMetadata {
+        name: Some(info.name.clone()),
+        version: Some(info.version.to_string()),
+        ..Default::default()
+    };
+    PredefinedMenuItem::about(app, Some("About Nanodoc"), Some(metadata))
+}
+
+fn build(app: &AppHandle) -> tauri::Result<Menu<Wry>> {
+    let menu = Menu::new(app)?;
+
+    #[cfg(target_os = "macos")]
+    menu.append(&Submenu::with_items(
+        app,
+        "Nanodoc",
+        true,
+        &[
+            &about(app)?,
+            &separator(app)?,
+            &PredefinedMenuItem::services(app, None)?,
+            &separator(app)?,
+            &PredefinedMenuItem::hide(app, None)?,
+            &PredefinedMenuItem::hide_others(app, None)?,
+            &PredefinedMenuItem::show_all(app, None)?,
+            &separator(app)?,
+            &PredefinedMenuItem::quit(app, None)?,
+        ],
+    )?)?;
+
+    let file = Submenu::new(app, "File", true)?;
+    file.append(&MenuItem::with_id(
+        app,
+        format!("{}{}", PREFIX, OPEN_ITEM),
+        "Open…",
+        true,
+        Some("CmdOrCtrl+O"),
+    )?)?;
+    #[cfg(not(target_os = "macos"))]
+    file.append(&recent_menu(app)?)?;
+    add_items(app, &file, FILE_ITEMS)?;
+    #[cfg(not(target_os = "macos"))]
+    file.append_items(&[
+        &separator(app)?,
+        &PredefinedMenuItem::quit(app, cfg!(windows).then_some("Exit"))?,
+    ])?;
+    menu.append(&file)?;
+
+    let edit = Submenu::with_items(
+        app,
+        "Edit",
+        true,
+        &[
+            &PredefinedMenuItem::undo(app, None)?,
+            &PredefinedMenuItem::redo(app, None)?,
+            &separator(app)?,
+            &PredefinedMenuItem::cut(app, None)?,
+            &PredefinedMenuItem::copy(app, None)?,
+            &PredefinedMenuItem::paste(app, None)?,
+            &PredefinedMenuItem::select_all(app, None)?,
+        ],
+    )?;
+    add_items(app, &edit, FIND_ITEMS)?;
+    menu.append(&edit)?;
+
+    let view = Submenu::new(app, "View", true)?;
+    add_items(app, &view, VIEW_ITEMS)?;
+    menu.append(&view)?;
+
+    let window = Submenu::with_items(
+        app,
+        "Window",
+        true,
+        &[
+            &PredefinedMenuItem::minimize(app, None)?,
+            &PredefinedMenuItem::maximize(app, None)?,
+        ],
+    )?;
+    add_items(app, &window, TAB_ITEMS)?;
+    #[cfg(target_os = "macos")]
+    window.append_items(&[
+        &separator(app)?,
+        &PredefinedMenuItem::bring_all_to_front(app, None)?,
+    ])?;
+    menu.append(&window)?;
+
+    let help = Submenu::new(app, "Help", true)?;
+    add_items(app, &help, HELP_ITEMS)?;
+    #[cfg(not(target_os = "macos"))]
+    help.append_items(&[&separator(app)?, &about(app)?])?;
+    menu.append(&help)?;
+
+    Ok(menu)
+}
+
+/// Puts the menu bar in place.
+pub fn setup(app: &AppHandle) {
+    if let Err(e) = build(app).and_then(|menu| app.set_menu(menu)) {
+        eprintln!("Failed to set up the menu bar: {}", e);
+    }
+}
+
+/// Rebuilds Open Recent with the recent files.
+pub fn refresh(app: &AppHandle) {
+    // macOS keeps its own list
+    if cfg!(not(target_os = "macos")) {
+        setup(app);
+    }
+}
+
+// Takes the window in front to full screen, or back
+fn toggle_full_screen(app: &AppHandle) {
+    let Some(window) = app.get_webview_window(&window_manager::focused(app)) else {
+        return;
+    };
+    let full_screen = window.is_fullscreen().unwrap_or(false);
+    if let Err(e) = window.set_fullscreen(!full_screen) {
+        eprintln!("Failed to change full screen: {}", e);
+    }
+}
+
+/// Carries out a menu bar item. The app's menu event handler.
+pub fn menu_event(app: &AppHandle, event: MenuEvent) {
+    let Some(id) = event.id().as_ref().strip_prefix(PREFIX) else {
+        return;
+    };
+    match id {
+        OPEN_ITEM => launch::choose_files(app),
+        FULL_SCREEN_ITEM => toggle_full_screen(app),
+        _ => {
+            #[cfg(not(target_os = "macos"))]
+            if let Some(path) = id.strip_prefix(RECENT_PREFIX) {
+                launch::open_files(app, vec![PathBuf::from(path)]);
+                return;
+            }
+            let _ = app.emit_to(window_manager::focused(app), "menu-action", id);
+        }
+    }
+}
@@ -0,0 +1,718 @@
+// Document information: the trailer's /Info dictionary and XMP metadata.
+//
+// Edited information is written to both, since some readers only look at one
+// of them. Only the edited properties and the dates change in the XMP packet,
+// so whatever else other tools keep there stays; a document without a packet
+// gets one built from /Info.
+
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lopdf::{dictionary, Dictionary, Document, Object, Stream};
+use quick_xml::escape::{escape, resolve_predefined_entity};
+use quick_xml::events::{BytesEnd, BytesStart, Event};
+use quick_xml::{Reader, Writer, XmlVersion};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::edit::{apply_edit, save, SavedDocument};
+use crate::engine::Engine;
+use crate::objects;
+use crate::pdf::{blocking, DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scheduler::RenderScheduler;
+use crate::tiles::TileCache;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+    /// Application that created the original document
+    pub creator: Option<String>,
+    /// Application that produced the PDF
+    pub producer: Option<String>,
+    /// ISO 8601
+    pub created: Option<String>,
+    /// ISO 8601
+    pub modified: Option<String>,
+    pub pdf_version: String,
+    pub page_count: i32,
+    pub file_size: u64,
+    /// XMP properties by qualified name, e.g. `dc:title`. List values are
+    /// joined with "; "
+    pub xmp: BTreeMap<String, String>,
+}
+
+/// Formats a time as a PDF date in UTC, e.g. `D:20240131110000Z`.
+pub fn format_pdf_date(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+    let (days, rest) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+    // Days since 1970 to a civil date (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "D:{:04}{:02}{:02}{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest / 60 % 60,
+        rest % 60
+    )
+}
+
+/// Converts a PDF date (`D:20240131120000+01'00'`) to ISO 8601. Missing
+/// trailing fields default to the start of the period.
+pub fn parse_pdf_date(raw: &str) -> Option<String> {
+    let s = raw.trim();
+    let s = s.strip_prefix("D:").unwrap_or(s);
+    let digits: String = s.chars().take_while(|c| c.is_ascii_digit()).collect();
+    if digits.len() < 4 {
+        return None;
+    }
+    let part = |i: usize, default: &'static str| digits.get(i..i + 2).unwrap_or(default);
+    let mut out = format!(
+        "{}-{}-{}T{}:{}:{}",
+        &digits[..4],
+        part(4, "01"),
+        part(6, "01"),
+        part(8, "00"),
+        part(10, "00"),
+        part(12, "00")
+    );
+
+    let zone = &s[digits.len()..];
+    match zone.chars().next() {
+        Some('Z') => out.push('Z'),
+        Some(sign @ ('+' | '-')) => {
+            let offset: String = zone[1..].chars().filter(char::is_ascii_digit).collect();
+            out.push(sign);
+            out.push_str(offset.get(0..2).unwrap_or("00"));
+            out.push(':');
+            out.push_str(offset.get(2..4).unwrap_or("00"));
+        }
+        _ => {}
+    }
+    Some(out)
+}
+
+// Collects the properties of every rdf:Description, whether they are written
+// as attributes or as child elements
+#[derive(Default)]
+struct XmpReader {
+    props: BTreeMap<String, String>,
+    stack: Vec<String>,
+    // Property being read and its depth in `stack`
+    property: Option<(String, usize)>,
+    // rdf:Alt holds alternatives (usually translations); only the first is kept
+    alternative: bool,
+    values: Vec<String>,
+    text: String,
+}
+
+impl XmpReader {
+    fn open(&mut self, tag: &BytesStart) {
+        let name = tag.name().into_inner().to_string();
+        match &self.property {
+            None if name == "rdf:Description" => {
+                for attr in tag.attributes().flatten() {
+                    let key = attr.key.into_inner();
+                    if key.starts_with("xmlns") || key.starts_with("rdf:") {
+                        continue;
+                    }
+                    if let Ok(value) = attr.normalized_value(XmlVersion::Implicit1_0) {
+                        self.props.insert(key.to_string(), value.trim().to_string());
+                    }
+                }
+            }
+            None if self.stack.last().map(String::as_str) == Some("rdf:Description") => {
+                if let Ok(Some(resource)) = tag.try_get_attribute("rdf:resource") {
+                    self.values.push(resource.value.into_owned());
+                }
+                self.property = Some((name.clone(), self.stack.len()));
+            }
+            Some(_) if name == "rdf:Alt" => self.alternative = true,
+            _ => {}
+        }
+        self.stack.push(name);
+    }
+
+    fn close(&mut self) {
+        let Some(name) = self.stack.pop() else {
+            return;
+        };
+        let Some((property, depth)) = &self.property else {
+            return;
+        };
+        let finished = *depth == self.stack.len();
+        if name == "rdf:li" || finished {
+            let text = std::mem::take(&mut self.text);
+            if !text.trim().is_empty() {
+                self.values.push(text.trim().to_string());
+            }
+        }
+        if finished {
+            let values = std::mem::take(&mut self.values);
+            let value = if self.alternative {
+                values.into_iter().next().unwrap_or_default()
+            } else {
+                values.join("; ")
+            };
+            self.props.insert(property.clone(), value);
+            self.property = None;
+            self.alternative = false;
+        }
+    }
+}
+
+/// Parses an XMP packet into a flat map of properties.
+pub fn parse_xmp(xml: &str) -> BTreeMap<String, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut xmp = XmpReader::default();
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(tag)) => xmp.open(&tag),
+            Ok(Event::Empty(tag)) => {
+                xmp.open(&tag);
+                xmp.close();
+            }
+            Ok(Event::End(_)) => xmp.close(),
+            Ok(Event::Text(text)) if xmp.property.is_some() => {
+                xmp.text.push_str(&text.xml10_content());
+            }
+            Ok(Event::CData(text)) if xmp.property.is_some() => {
+                xmp.text.push_str(&text.xml10_content());
+            }
+            Ok(Event::GeneralRef(entity)) if xmp.property.is_some() => {
+                if let Ok(Some(c)) = entity.resolve_char_ref() {
+                    xmp.text.push(c);
+                } else if let Some(s) = resolve_predefined_entity(&entity) {
+                    xmp.text.push_str(s);
+                }
+            }
+            // Metadata is informational, so keep whatever was read before an error
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+    xmp.props
+}
+
+// The catalog's XMP packet, if any
+fn xmp_text(doc: &Document) -> Option<String> {
+    let stream = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Metadata").ok())
+        .and_then(|obj| objects::resolve(doc, obj).as_stream().ok())?;
+    // Metadata streams are usually left uncompressed so other tools can read them
+    let data = stream
+        .decompressed_content()
+        .unwrap_or_else(|_| stream.content.clone());
+    Some(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// Reads the catalog's XMP metadata, if any.
+pub fn read_xmp(doc: &Document) -> BTreeMap<String, String> {
+    xmp_text(doc).map(|xml| parse_xmp(&xml)).unwrap_or_default()
+}
+
+/// Reads the document information, preferring /Info entries and falling back
+/// to the equivalent XMP properties.
+pub fn document_info(doc: &Document, page_count: i32, file_size: u64) -> DocumentInfo {
+    let info = doc
+        .trailer
+        .get(b"Info")
+        .ok()
+        .and_then(|obj| objects::resolve(doc, obj).as_dict().ok());
+    let xmp = read_xmp(doc);
+
+    let field = |key: &[u8], xmp_key: &str| {
+        info.and_then(|info| objects::get_text(doc, info, key))
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .or_else(|| xmp.get(xmp_key).filter(|v| !v.is_empty()).cloned())
+    };
+    let date = |key: &[u8], xmp_key: &str| {
+        info.and_then(|info| objects::get_text(doc, info, key))
+            .and_then(|value| parse_pdf_date(&value))
+            .or_else(|| xmp.get(xmp_key).cloned())
+    };
+
+    DocumentInfo {
+        title: field(b"Title", "dc:title"),
+        author: field(b"Author", "dc:creator"),
+        subject: field(b"Subject", "dc:description"),
+        keywords: field(b"Keywords", "pdf:Keywords"),
+        creator: field(b"Creator", "xmp:CreatorTool"),
+        producer: field(b"Producer", "pdf:Producer"),
+        created: date(b"CreationDate", "xmp:CreateDate"),
+        modified: date(b"ModDate", "xmp:ModifyDate"),
+        pdf_version: doc.version.clone(),
+        page_count,
+        file_size,
+        xmp,
+    }
+}
+
+#[tauri::command]
+pub async fn get_document_info(
+    doc_id: DocId,
+    store: State<'_, DocumentStore>,
+) -> Result<DocumentInfo, String> {
+    let doc = store.get(doc_id)?;
+    let (page_count, file_size) = (doc.page_count, doc.data.len() as u64);
+    blocking(move || doc.with_objects(|objects| Ok(document_info(objects, page_count, file_size))))
+        .await
+}
+
+// The namespaces of the properties the document information is kept in
+const XMP_NAMESPACES: [(&str, &str); 3] = [
+    ("dc", "http://purl.org/dc/elements/1.1/"),
+    ("pdf", "http://ns.adobe.com/pdf/1.3/"),
+    ("xmp", "http://ns.adobe.com/xap/1.0/"),
+];
+
+// One property as an element, with the list Dublin Core wants for it
+fn xmp_property(name: &str, value: &str) -> String {
+    let value = escape(value);
+    match name {
+        "dc:title" | "dc:description" => format!(
+            "<{0}><rdf:Alt><rdf:li xml:lang=\"x-default\">{1}</rdf:li></rdf:Alt></{0}>",
+            name, value
+        ),
+        "dc:creator" => format!(
+            "<{0}><rdf:Seq><rdf:li>{1}</rdf:li></rdf:Seq></{0}>",
+            name, value
+        ),
+        _ => format!("<{0}>{1}</{0}>", name, value),
+    }
+}
+
+/// Sets properties in an existing XMP packet, leaving the rest of it as it
+/// is. Each is written as an element of the first rdf:Description, wherever
+/// it was before, or removed if its value is `None`. Returns `None` if the
+/// packet can't be read or has no rdf:Description.
+pub fn update_xmp(xml: &str, changes: &[(&str, Option<String>)]) -> Option<String> {
+    let changed = |name: &str| changes.iter().any(|(changed, _)| *changed == name);
+    let mut properties = String::new();
+    for (name, value) in changes {
+        if let Some(value) = value {
+            properties.push_str(&xmp_property(name, value));
+        }
+    }
+
+    let mut reader = Reader::from_str(xml);
+    let mut writer = Writer::new(Vec::new());
+    let mut depth = 0;
+    // Depth of the rdf:Description being copied, and of a property skipped
+    let mut description: Option<usize> = None;
+    let mut skipped: Option<usize> = None;
+    let mut written = false;
+    // An rdf:Description without the changed properties, declaring their
+    // namespaces if it's the one they go in
+    let description_tag = |tag: &BytesStart, first: bool| {
+        let mut copy = BytesStart::new("rdf:Description");
+        let attributes: Vec<_> = tag.attributes().flatten().collect();
+        for attr in &attributes {
+            if !changed(attr.key.into_inner()) {
+                copy.push_attribute(attr.clone());
+            }
+        }
+        for (prefix, uri) in XMP_NAMESPACES {
+            let key = format!("xmlns:{}", prefix);
+            let used = changes
+                .iter()
+                .any(|(name, value)| value.is_some() && name.starts_with(&format!("{}:", prefix)));
+            let declared = attributes.iter().any(|attr| attr.key.into_inner() == key);
+            if first && used && !declared {
+                copy.push_attribute((key.as_str(), uri));
+            }
+        }
+        copy
+    };
+    loop {
+        let event = reader.read_event().ok()?;
+        match &event {
+            Event::Eof => break,
+            _ if skipped.is_some() => {
+                match event {
+                    Event::Start(_) => depth += 1,
+                    Event::End(_) => {
+                        depth -= 1;
+                        if skipped == Some(depth) {
+                            skipped = None;
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            Event::Start(tag) | Event::Empty(tag) => {
+                let empty = matches!(event, Event::Empty(_));
+                let name = tag.name().into_inner();
+                if description.is_some_and(|at| depth == at + 1) && changed(name) {
+                    if !empty {
+                        skipped = Some(depth);
+                        depth += 1;
+                    }
+                    continue;
+                }
+                if description.is_none() && name == "rdf:Description" {
+                    let copy = description_tag(tag, !written);
+                    if empty {
+                        if written {
+                            writer.write_event(Event::Empty(copy)).ok()?;
+                        } else {
+                            writer.write_event(Event::Start(copy)).ok()?;
+                            writer.get_mut().extend_from_slice(properties.as_bytes());
+                            writer
+                                .write_event(Event::End(BytesEnd::new("rdf:Description")))
+                                .ok()?;
+                            written = true;
+                        }
+                        continue;
+                    }
+                    writer.write_event(Event::Start(copy)).ok()?;
+                    description = Some(depth);
+                    depth += 1;
+                    continue;
+                }
+                if !empty {
+                    depth += 1;
+                }
+            }
+            Event::End(_) => {
+                depth -= 1;
+                if description == Some(depth) {
+                    if !written {
+                        writer.get_mut().extend_from_slice(properties.as_bytes());
+                        written = true;
+                    }
+                    description = None;
+                }
+            }
+            _ => {}
+        }
+        writer.write_event(event).ok()?;
+    }
+    if !written {
+        return None;
+    }
+    String::from_utf8(writer.into_inner()).ok()
+}
+
+/// Builds an XMP packet holding the document information, claiming PDF/A
+/// conformance with a part and level such as ("2", "B") if given.
+pub fn xmp_packet(info: &DocumentInfo, pdfa: Option<(&str, &str)>) -> Vec<u8> {
+    let mut properties = String::new();
+    if let Some((part, conformance)) = pdfa {
+        properties.push_str(&format!(
+            "<pdfaid:part>{}</pdfaid:part><pdfaid:conformance>{}</pdfaid:conformance>",
+            escape(part),
+            escape(conformance)
+        ));
+    }
+    let values = [
+        ("dc:title", &info.title),
+        ("dc:creator", &info.author),
+        ("dc:description", &info.subject),
+        ("pdf:Keywords", &info.keywords),
+        ("pdf:Producer", &info.producer),
+        ("xmp:CreatorTool", &info.creator),
+        ("xmp:CreateDate", &info.created),
+        ("xmp:ModifyDate", &info.modified),
+    ];
+    for (name, value) in values {
+        if let Some(value) = value {
+            properties.push_str(&xmp_property(name, value));
+        }
+    }
+
+    format!(
+        concat!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n",
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n",
+            "<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n",
+            "<rdf:Description rdf:about=\"\"",
+            " xmlns:pdfaid=\"http://www.aiim.org/pdfa/ns/id/\"",
+            " xmlns:dc=\"http://purl.org/dc/elements/1.1/\"",
+            " xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\"",
+            " xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\">\n",
+            "{}\n",
+            "</rdf:Description>\n",
+            "</rdf:RDF>\n",
+            "</x:xmpmeta>\n",
+            "<?xpacket end=\"w\"?>"
+        ),
+        properties
+    )
+    .into_bytes()
+}
+
+/// Document information fields to change. Missing fields are left as they
+/// are; empty ones are removed.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InfoFields {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+}
+
+// A packet from /Info for documents whose own can't be updated, keeping a
+// PDF/A claim from it
+fn rebuilt_packet(doc: &Document, changes: &[(&str, Option<String>)]) -> Vec<u8> {
+    let xmp = read_xmp(doc);
+    let pdfa = xmp.get("pdfaid:part").map(|part| {
+        (
+            part.clone(),
+            xmp.get("pdfaid:conformance").cloned().unwrap_or_default(),
+        )
+    });
+    // Removed fields would otherwise come back from the old packet
+    let mut info = document_info(doc, 0, 0);
+    for (name, value) in changes {
+        let field = match *name {
+            "dc:title" => &mut info.title,
+            "dc:creator" => &mut info.author,
+            "dc:description" => &mut info.subject,
+            "pdf:Keywords" => &mut info.keywords,
+            "xmp:ModifyDate" => &mut info.modified,
+            _ => continue,
+        };
+        *field = value.clone();
+    }
+    xmp_packet(&info, pdfa.as_ref().map(|(p, c)| (p.as_str(), c.as_str())))
+}
+
+// Updates /Info and the XMP packet, and stamps the modification date
+fn set_info(doc: &mut Document, fields: &InfoFields) -> Result<(), String> {
+    let info_ref = doc
+        .trailer
+        .get(b"Info")
+        .ok()
+        .and_then(|obj| obj.as_reference().ok());
+    let mut info = doc
+        .trailer
+        .get(b"Info")
+        .ok()
+        .and_then(|obj| objects::resolve(doc, obj).as_dict().ok())
+        .cloned()
+        .unwrap_or_else(Dictionary::new);
+
+    let updates = [
+        ("Title", &fields.title),
+        ("Author", &fields.author),
+        ("Subject", &fields.subject),
+        ("Keywords", &fields.keywords),
+    ];
+    for (key, value) in updates {
+        match value.as_deref().map(str::trim) {
+            Some("") => {
+                info.remove(key.as_bytes());
+            }
+            Some(value) => info.set(key, objects::encode_text(value)),
+            None => {}
+        }
+    }
+    let modified = format_pdf_date(SystemTime::now());
+    info.set("ModDate", Object::string_literal(modified.as_str()));
+    match info_ref {
+        Some(id) => {
+            doc.objects.insert(id, Object::Dictionary(info));
+        }
+        None => {
+            let id = doc.add_object(info);
+            doc.trailer.set("Info", id);
+        }
+    }
+
+    // Only what changed is set in an existing packet, so what other tools
+    // keep there, such as a PDF/A claim, stays
+    let mut changes: Vec<(&str, Option<String>)> = [
+        ("dc:title", &fields.title),
+        ("dc:creator", &fields.author),
+        ("dc:description", &fields.subject),
+        ("pdf:Keywords", &fields.keywords),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| {
+        let value = value.as_deref()?.trim();
+        Some((name, Some(value.to_string()).filter(|v| !v.is_empty())))
+    })
+    .collect();
+    changes.push(("xmp:ModifyDate", parse_pdf_date(&modified)));
+    changes.push(("xmp:MetadataDate", parse_pdf_date(&modified)));
+    let packet = match xmp_text(doc).and_then(|xml| update_xmp(&xml, &changes)) {
+        Some(xml) => xml.into_bytes(),
+        None => rebuilt_packet(doc, &changes),
+    };
+    let metadata = doc.add_object(
+        Stream::new(
+            dictionary! { "Type" => "Metadata", "Subtype" => "XML" },
+            packet,
+        )
+        .with_compression(false),
+    );
+    doc.catalog_mut()
+        .map_err(|e| e.to_string())?
+        .set("Metadata", metadata);
+    Ok(())
+}
+
+/// Changes the title, author, subject or keywords of a document and saves it
+/// to its file, along with any other edits.
+#[tauri::command]
+pub async fn set_document_info(
+    doc_id: DocId,
+    fields: InfoFields,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<SavedDocument, String> {
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Modify).await?;
+    apply_edit(doc, &store, &tiles, &scheduler, move |objects| {
+        set_info(objects, &fields)
+    })
+    .await?;
+    let doc = store.get(doc_id)?;
+    let path = doc.path.clone();
+    eprintln!("Updated the information of document {}", doc_id);
+    save(doc, path, false, false, &store, &engine, &tiles, &scheduler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    // Written by another tool: some properties as attributes of one
+    // rdf:Description, others in a second one, with some this app doesn't know
+    const PACKET: &str = concat!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n",
+        "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n",
+        "<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n",
+        "<rdf:Description rdf:about=\"\" xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\"",
+        " pdf:Keywords=\"old, words\" pdf:Producer=\"Some Producer\"/>\n",
+        "<rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\"",
+        " xmlns:pdfaid=\"http://www.aiim.org/pdfa/ns/id/\"",
+        " xmlns:xmpMM=\"http://ns.adobe.com/xap/1.0/mm/\">\n",
+        "<pdfaid:part>2</pdfaid:part><pdfaid:conformance>B</pdfaid:conformance>\n",
+        "<dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">Old</rdf:li></rdf:Alt></dc:title>\n",
+        "<dc:creator><rdf:Seq><rdf:li>An Author</rdf:li></rdf:Seq></dc:creator>\n",
+        "<xmpMM:DocumentID>uuid:1234</xmpMM:DocumentID>\n",
+        "</rdf:Description>\n",
+        "</rdf:RDF>\n",
+        "</x:xmpmeta>\n",
+        "<?xpacket end=\"w\"?>"
+    );
+
+    #[test]
+    fn pdf_dates_become_iso_8601() {
+        let parsed = |raw: &str| parse_pdf_date(raw);
+        assert_eq!(
+            parsed("D:20240131120000+01'00'").as_deref(),
+            Some("2024-01-31T12:00:00+01:00")
+        );
+        assert_eq!(
+            parsed("D:19991231235959-08'00").as_deref(),
+            Some("1999-12-31T23:59:59-08:00")
+        );
+        assert_eq!(
+            parsed("D:20240131110000Z").as_deref(),
+            Some("2024-01-31T11:00:00Z")
+        );
+        // Missing fields are the start of the period, with no zone
+        assert_eq!(parsed("D:2024").as_deref(), Some("2024-01-01T00:00:00"));
+        assert_eq!(parsed(" 202401 ").as_deref(), Some("2024-01-01T00:00:00"));
+        assert_eq!(parsed("D:20"), None);
+        assert_eq!(parsed("yesterday"), None);
+    }
+
+    #[test]
+    fn formatted_dates_parse_back() {
+        let time = UNIX_EPOCH + std::time::Duration::from_secs(1_706_698_800);
+        assert_eq!(format_pdf_date(time), "D:20240131110000Z");
+        assert_eq!(
+            parse_pdf_date(&format_pdf_date(time)).as_deref(),
+            Some("2024-01-31T11:00:00Z")
+        );
+    }
+
+    fn edit(mut doc: Document, fields: InfoFields) -> Document {
+        set_info(&mut doc, &fields).unwrap();
+        let mut data = Vec::new();
+        doc.save_to(&mut data).unwrap();
+        Document::load_mem(&data).unwrap()
+    }
+
+    #[test]
+    fn edits_keep_the_rest_of_the_packet() {
+        let mut doc = fixtures::document(1);
+        let metadata = doc.add_object(Stream::new(
+            dictionary! { "Type" => "Metadata", "Subtype" => "XML" },
+            PACKET.as_bytes().to_vec(),
+        ));
+        doc.catalog_mut().unwrap().set("Metadata", metadata);
+        let fields = InfoFields {
+            title: Some("New & Improved".into()),
+            author: None,
+            subject: None,
+            keywords: Some(String::new()),
+        };
+        let doc = edit(doc, fields);
+
+        let xml = xmp_text(&doc).unwrap();
+        assert_eq!(xml.matches("<dc:title>").count(), 1);
+        assert!(xml.ends_with("<?xpacket end=\"w\"?>"));
+        let xmp = parse_xmp(&xml);
+        assert_eq!(xmp["dc:title"], "New & Improved");
+        assert!(!xmp.contains_key("pdf:Keywords"));
+        assert!(xmp.contains_key("xmp:ModifyDate"));
+        for (name, value) in [
+            ("dc:creator", "An Author"),
+            ("pdf:Producer", "Some Producer"),
+            ("pdfaid:part", "2"),
+            ("pdfaid:conformance", "B"),
+            ("xmpMM:DocumentID", "uuid:1234"),
+        ] {
+            assert_eq!(xmp.get(name).map(String::as_str), Some(value), "{}", name);
+        }
+        let info = document_info(&doc, 1, 0);
+        assert_eq!(info.title.as_deref(), Some("New & Improved"));
+        assert_eq!(info.keywords, None);
+    }
+
+    #[test]
+    fn documents_without_a_packet_get_one() {
+        let fields = InfoFields {
+            title: None,
+            author: Some("Someone".into()),
+            subject: None,
+            keywords: None,
+        };
+        let doc = edit(fixtures::document(1), fields);
+        let xmp = read_xmp(&doc);
+        assert_eq!(xmp["dc:title"], "Untitled");
+        assert_eq!(xmp["dc:creator"], "Someone");
+        assert!(xmp.contains_key("xmp:ModifyDate"));
+    }
+}
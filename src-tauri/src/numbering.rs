@@ -0,0 +1,204 @@
+// Bates numbers and page numbers stamped on pages.
+//
+// Bates numbering gives every page of a set of documents a unique, sequential
+// number, continuing from one document to the next, as court filings and
+// discovery productions require. Page numbers start again in each document.
+// Numbers are drawn like text stamps, upright in the page as the reader sees
+// it.
+
+use std::path::Path;
+
+use lopdf::content::Operation;
+use lopdf::{Document, Object, ObjectId, StringFormat};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+
+use crate::compose::{parse_page_ranges, write_document};
+use crate::content::{add_resource, append_content, concat, upright_transform};
+use crate::pdf::{blocking, DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scope;
+use crate::stamp::{anchor, text_width, win_ansi, StampPosition, StandardFont};
+
+// Longest zero-padded number
+const MAX_DIGITS: usize = 12;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum NumberStyle {
+    /// Continues across documents
+    Bates,
+    /// Restarts in each document
+    Page,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NumberingOptions {
+    pub style: NumberStyle,
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub suffix: String,
+    /// Number of the first page
+    pub start: u64,
+    /// Numbers are padded with zeros to this many digits
+    #[serde(default)]
+    pub digits: usize,
+    #[serde(default)]
+    pub position: StampPosition,
+    /// Distance from the page edges, in points
+    #[serde(default)]
+    pub margin: f32,
+    #[serde(default)]
+    pub font: StandardFont,
+    pub font_size: f32,
+    /// RGB, each from 0 to 1
+    #[serde(default)]
+    pub color: [f32; 3],
+    /// One-based ranges like "1-5,8,10-end", applied to each document; all
+    /// pages if missing. Pages left out don't use up a number.
+    pub ranges: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NumberedDocument {
+    pub doc_id: DocId,
+    pub output_path: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NumberedOutput {
+    pub path: String,
+    pub page_count: i32,
+    /// First and last number stamped, if any pages were numbered
+    pub first: Option<String>,
+    pub last: Option<String>,
+}
+
+fn label(options: &NumberingOptions, number: u64) -> String {
+    format!(
+        "{}{:0width$}{}",
+        options.prefix,
+        number,
+        options.suffix,
+        width = options.digits
+    )
+}
+
+// Stamps `labels` on `pages` in order
+fn number(
+    doc: &mut Document,
+    pages: &[i32],
+    labels: &[String],
+    options: &NumberingOptions,
+) -> Result<(), String> {
+    let font = options.font.add_to(doc);
+    let [r, g, b] = options.color.map(|c| c.clamp(0.0, 1.0));
+    let height = options.font_size * options.font.cap_height();
+    let ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    for (&page, label) in pages.iter().zip(labels) {
+        let page_id = ids[page as usize];
+        let bytes = win_ansi(label);
+        let width = text_width(options.font, &bytes, options.font_size);
+        let (upright, page_size) = upright_transform(doc, page_id);
+        let (x, y) = anchor(options.position, options.margin, (width, height), page_size);
+        let placement = concat(
+            [1.0, 0.0, 0.0, 1.0, x - width / 2.0, y - height / 2.0],
+            upright,
+        );
+
+        let name = add_resource(doc, page_id, b"Font", "NumberF", font.into())?;
+        let operations = vec![
+            Operation::new("q", vec![]),
+            Operation::new("cm", placement.iter().map(|&v| v.into()).collect()),
+            Operation::new("BT", vec![]),
+            Operation::new(
+                "Tf",
+                vec![Object::Name(name.into_bytes()), options.font_size.into()],
+            ),
+            Operation::new("rg", vec![r.into(), g.into(), b.into()]),
+            Operation::new("Tj", vec![Object::String(bytes, StringFormat::Literal)]),
+            Operation::new("ET", vec![]),
+            Operation::new("Q", vec![]),
+        ];
+        append_content(doc, page_id, operations)?;
+    }
+    Ok(())
+}
+
+/// Stamps Bates numbers or page numbers on the pages of one or more
+/// documents, saving each as a new file. Bates numbers continue from one
+/// document to the next in the order given. The open documents are left as
+/// they are.
+#[tauri::command]
+pub async fn number_pages(
+    app: AppHandle,
+    documents: Vec<NumberedDocument>,
+    options: NumberingOptions,
+    store: State<'_, DocumentStore>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<Vec<NumberedOutput>, String> {
+    if documents.is_empty() {
+        return Err("Select at least one document to number".to_string());
+    }
+    if options.font_size <= 0.0 {
+        return Err("Page numbers need a positive font size".to_string());
+    }
+    if options.digits > MAX_DIGITS {
+        return Err(format!("Numbers can have at most {} digits", MAX_DIGITS));
+    }
+    let targets = documents
+        .into_iter()
+        .map(|target| {
+            let doc = store.get(target.doc_id)?;
+            let pages = match &options.ranges {
+                Some(ranges) => parse_page_ranges(ranges, doc.page_count)?,
+                None => (0..doc.page_count).collect(),
+            };
+            Ok((doc, pages, target.output_path))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    for (doc, _, output_path) in &targets {
+        policy.check(doc, Action::Modify).await?;
+        scope::check_write(&app, Path::new(output_path))?;
+    }
+
+    blocking(move || {
+        let mut next = options.start;
+        let mut outputs = Vec::with_capacity(targets.len());
+        for (doc, pages, output_path) in targets {
+            if options.style == NumberStyle::Page {
+                next = options.start;
+            }
+            let labels: Vec<String> = (next..)
+                .take(pages.len())
+                .map(|n| label(&options, n))
+                .collect();
+            next += pages.len() as u64;
+
+            let mut numbered = doc.with_objects(|objects| {
+                let mut numbered = objects.clone();
+                number(&mut numbered, &pages, &labels, &options)?;
+                Ok(numbered)
+            })?;
+            write_document(&mut numbered, Path::new(&output_path))?;
+            eprintln!(
+                "Numbered {} pages of {} into {}",
+                pages.len(),
+                doc.path.display(),
+                output_path
+            );
+            outputs.push(NumberedOutput {
+                path: output_path,
+                page_count: doc.page_count,
+                first: labels.first().cloned(),
+                last: labels.last().cloned(),
+            });
+        }
+        Ok(outputs)
+    })
+    .await
+}
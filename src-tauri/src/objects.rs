@@ -0,0 +1,210 @@
+// Helpers for reading the PDF object tree with lopdf.
+//
+// lopdf leaves references unresolved and strings undecoded, so these small
+// functions keep the structure-reading modules (outline, labels, metadata...)
+// readable.
+
+use std::collections::HashMap;
+
+use lopdf::{Dictionary, Document, Object, ObjectId};
+
+use crate::pdf::Bounds;
+
+// Guards against reference cycles in malformed files
+pub const MAX_DEPTH: usize = 64;
+
+/// Follows references until a direct object is reached.
+pub fn resolve<'a>(doc: &'a Document, mut obj: &'a Object) -> &'a Object {
+    for _ in 0..MAX_DEPTH {
+        match obj {
+            Object::Reference(id) => match doc.get_object(*id) {
+                Ok(target) => obj = target,
+                Err(_) => return &Object::Null,
+            },
+            _ => return obj,
+        }
+    }
+    &Object::Null
+}
+
+/// Looks up `key` in `dict` and resolves the value.
+pub fn get<'a>(doc: &'a Document, dict: &'a Dictionary, key: &[u8]) -> Option<&'a Object> {
+    match dict.get(key).map(|obj| resolve(doc, obj)) {
+        Ok(Object::Null) | Err(_) => None,
+        Ok(obj) => Some(obj),
+    }
+}
+
+pub fn get_dict<'a>(doc: &'a Document, dict: &'a Dictionary, key: &[u8]) -> Option<&'a Dictionary> {
+    match get(doc, dict, key)? {
+        Object::Dictionary(dict) => Some(dict),
+        Object::Stream(stream) => Some(&stream.dict),
+        _ => None,
+    }
+}
+
+pub fn get_array<'a>(doc: &'a Document, dict: &'a Dictionary, key: &[u8]) -> Option<&'a [Object]> {
+    get(doc, dict, key)?.as_array().ok().map(|a| a.as_slice())
+}
+
+pub fn get_name<'a>(doc: &'a Document, dict: &'a Dictionary, key: &[u8]) -> Option<&'a [u8]> {
+    get(doc, dict, key)?.as_name().ok()
+}
+
+pub fn get_text(doc: &Document, dict: &Dictionary, key: &[u8]) -> Option<String> {
+    get(doc, dict, key).and_then(text_string)
+}
+
+/// Reads an integer or real as `f32`.
+pub fn number(obj: &Object) -> Option<f32> {
+    match obj {
+        Object::Integer(i) => Some(*i as f32),
+        Object::Real(r) => Some(*r),
+        _ => None,
+    }
+}
+
+/// Decodes a PDF text string (UTF-16BE or UTF-8 with BOM, else PDFDocEncoding).
+pub fn text_string(obj: &Object) -> Option<String> {
+    let bytes = match obj {
+        Object::String(bytes, _) => bytes.as_slice(),
+        Object::Name(bytes) => bytes.as_slice(),
+        _ => return None,
+    };
+    Some(decode_text(bytes))
+}
+
+/// Encodes a text string, using UTF-16BE when it isn't plain ASCII.
+pub fn encode_text(text: &str) -> Object {
+    if text.is_ascii() {
+        return Object::string_literal(text);
+    }
+    let mut bytes = vec![0xFE, 0xFF];
+    for unit in text.encode_utf16() {
+        bytes.extend_from_slice(&unit.to_be_bytes());
+    }
+    Object::String(bytes, lopdf::StringFormat::Hexadecimal)
+}
+
+pub fn decode_text(bytes: &[u8]) -> String {
+    if let Some(utf16) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        let units: Vec<u16> = utf16
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        return String::from_utf16_lossy(&units);
+    }
+    if let Some(utf8) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        return String::from_utf8_lossy(utf8).into_owned();
+    }
+    // PDFDocEncoding matches Latin-1 for everything that matters in practice
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+/// Maps page object ids to zero-based page indices.
+pub fn page_indices(doc: &Document) -> HashMap<ObjectId, i32> {
+    doc.get_pages()
+        .into_iter()
+        .map(|(number, id)| (id, number as i32 - 1))
+        .collect()
+}
+
+/// Looks up a page attribute, walking up the page tree for inherited keys.
+pub fn inherited<'a>(doc: &'a Document, page_id: ObjectId, key: &[u8]) -> Option<&'a Object> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+    for _ in 0..MAX_DEPTH {
+        if let Some(value) = get(doc, node, key) {
+            return Some(value);
+        }
+        node = get_dict(doc, node, b"Parent")?;
+    }
+    None
+}
+
+/// Reads a rectangle as `[x0, y0, x1, y1]` with normalised corners.
+pub fn rect(doc: &Document, obj: &Object) -> Option<[f32; 4]> {
+    let values: Vec<f32> = resolve(doc, obj)
+        .as_array()
+        .ok()?
+        .iter()
+        .filter_map(|v| number(resolve(doc, v)))
+        .collect();
+    if values.len() != 4 {
+        return None;
+    }
+    Some([
+        values[0].min(values[2]),
+        values[1].min(values[3]),
+        values[0].max(values[2]),
+        values[1].max(values[3]),
+    ])
+}
+
+/// The visible area of a page: its CropBox, or MediaBox if there is none.
+pub fn page_box(doc: &Document, page_id: ObjectId) -> [f32; 4] {
+    inherited(doc, page_id, b"CropBox")
+        .or_else(|| inherited(doc, page_id, b"MediaBox"))
+        .and_then(|obj| rect(doc, obj))
+        .unwrap_or([0.0, 0.0, 612.0, 792.0])
+}
+
+/// Maps PDF user space (origin bottom-left) to the top-left, rotated page
+/// space used by the renderer.
+pub struct PageSpace {
+    bounds: [f32; 4],
+    rotate: i64,
+}
+
+impl PageSpace {
+    pub fn new(doc: &Document, page_id: ObjectId) -> Self {
+        let rotate = inherited(doc, page_id, b"Rotate")
+            .and_then(|r| r.as_i64().ok())
+            .unwrap_or(0)
+            .rem_euclid(360);
+        PageSpace {
+            bounds: page_box(doc, page_id),
+            rotate,
+        }
+    }
+
+    pub fn point(&self, x: f32, y: f32) -> (f32, f32) {
+        let [x0, y0, x1, y1] = self.bounds;
+        match self.rotate {
+            90 => (y - y0, x - x0),
+            180 => (x1 - x, y - y0),
+            270 => (y1 - y, x1 - x),
+            _ => (x - x0, y1 - y),
+        }
+    }
+
+    pub fn rect(&self, r: [f32; 4]) -> Bounds {
+        let (ax, ay) = self.point(r[0], r[1]);
+        let (bx, by) = self.point(r[2], r[3]);
+        Bounds {
+            x: ax.min(bx),
+            y: ay.min(by),
+            width: (ax - bx).abs(),
+            height: (ay - by).abs(),
+        }
+    }
+
+    /// The inverse of `rect`: a rectangle in page space, in user space.
+    pub fn user_rect(&self, b: Bounds) -> [f32; 4] {
+        let [x0, y0, x1, y1] = self.bounds;
+        let point = |x: f32, y: f32| match self.rotate {
+            90 => (y + x0, x + y0),
+            180 => (x1 - x, y + y0),
+            270 => (x1 - y, y1 - x),
+            _ => (x + x0, y1 - y),
+        };
+        let (ax, ay) = point(b.x, b.y);
+        let (bx, by) = point(b.x + b.width, b.y + b.height);
+        [ax.min(bx), ay.min(by), ax.max(bx), ay.max(by)]
+    }
+
+    /// True when the page is shown rotated by 90 or 270 degrees, i.e. user
+    /// space x runs vertically on screen.
+    pub fn is_sideways(&self) -> bool {
+        self.rotate == 90 || self.rotate == 270
+    }
+}
@@ -0,0 +1,1000 @@
+// Text recognition for scanned pages.
+//
+// Pages without a text layer are rendered at OCR_DPI on the worker threads
+// and read by the Tesseract command line tool, which has to be installed
+// separately. The recognized lines are kept in a SQLite database at
+// `<app data>/ocr.sqlite3`, keyed by the fingerprint of the file like the
+// annotations in `sidecar.rs`, so a scan is only read once. The text layer
+// (`text.rs`) and search (`search.rs`) fall back to them on pages that have
+// no text of their own.
+//
+// Embedding writes the recognized lines into the pages as invisible text, so
+// the document is searchable in other viewers too. The text uses a font
+// without glyphs whose codes are the characters themselves, with a
+// ToUnicode map saying so, so any script survives copying; every character
+// is half an em wide and each word is stretched over its box.
+//
+// Tesseract needs trained data for each language it reads. Languages beyond
+// those installed with it can be downloaded from the tessdata_fast
+// repository into `<app data>/tessdata`. Tesseract reads from one data
+// directory at a time, so that one is used when it has every language asked
+// for, and the installed ones otherwise.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use lopdf::content::Operation;
+use lopdf::{dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::colors::ColorMode;
+use crate::compose::parse_page_ranges;
+use crate::content::{add_resource, append_content, upright_transform};
+use crate::edit::{apply_edit_with, EditedDocument};
+use crate::engine::{Engine, Worker};
+use crate::jobs::{CancelToken, JobId, RenderJobs};
+use crate::pdf::{
+    blocking, decode_bitmap, rasterize_page, Bounds, DocId, DocumentStore, OpenDocument,
+};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scheduler::RenderScheduler;
+use crate::search::union;
+use crate::text::{page_runs, TextRun};
+use crate::tiles::TileCache;
+
+// Resolution pages are read at; Tesseract is trained on 300 dpi scans
+const OCR_DPI: f32 = 300.0;
+
+/// The language read when none is chosen.
+pub const DEFAULT_LANGUAGE: &str = "eng";
+
+const TESSDATA_URL: &str = "https://github.com/tesseract-ocr/tessdata_fast/raw/main";
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(600);
+// Bytes between download progress events
+const PROGRESS_STEP: u64 = 1 << 20;
+
+// Where installers put Tesseract when it isn't on the PATH of the app, which
+// on macOS doesn't include Homebrew
+const TESSERACT_LOCATIONS: &[&str] = &[
+    "/opt/homebrew/bin/tesseract",
+    "/usr/local/bin/tesseract",
+    "C:\\Program Files\\Tesseract-OCR\\tesseract.exe",
+];
+
+// Metrics of the embedded text's font, in 1/1000 em
+const GLYPH_WIDTH: i64 = 500;
+const ASCENT: i64 = 800;
+const DESCENT: i64 = -200;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS pages (
+        fingerprint TEXT NOT NULL,
+        page INTEGER NOT NULL,
+        language TEXT NOT NULL,
+        runs TEXT NOT NULL,
+        PRIMARY KEY (fingerprint, page)
+    );
+";
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OcrProgress {
+    pub job_id: Option<JobId>,
+    pub doc_id: DocId,
+    pub done: usize,
+    pub total: usize,
+}
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct OcrSummary {
+    /// Pages read
+    pub pages: usize,
+    pub words: usize,
+    /// Pages left alone because they have a text layer
+    pub skipped: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbeddedText {
+    pub document: EditedDocument,
+    pub pages: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OcrLanguage {
+    /// Tesseract's name, such as "deu"
+    pub name: String,
+    /// True if it was downloaded into the app data directory, and so can be
+    /// removed
+    pub downloaded: bool,
+    /// Size of the trained data in bytes, for downloaded languages
+    pub size: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OcrLanguages {
+    /// False if Tesseract isn't installed, as nothing can be read then
+    pub tesseract: bool,
+    pub languages: Vec<OcrLanguage>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LanguageDownload {
+    pub language: String,
+    pub received: u64,
+    pub total: Option<u64>,
+}
+
+/// The database of recognized text.
+pub fn database_file(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("ocr.sqlite3"))
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))
+}
+
+/// Opens the database of recognized text, creating it if need be.
+pub fn open(file: &Path) -> Result<Connection, String> {
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let db =
+        Connection::open(file).map_err(|e| format!("Failed to open {}: {}", file.display(), e))?;
+    db.execute_batch(SCHEMA)
+        .map_err(|e| format!("Failed to set up {}: {}", file.display(), e))?;
+    Ok(db)
+}
+
+/// The recognized lines of one page, if it has been read.
+pub fn recognized_page(file: &Path, fingerprint: &str, page: i32) -> Option<Vec<TextRun>> {
+    // A missing database just means nothing was read yet
+    if !file.exists() {
+        return None;
+    }
+    let found = open(file).and_then(|db| {
+        db.query_row(
+            "SELECT runs FROM pages WHERE fingerprint = ?1 AND page = ?2",
+            params![fingerprint, page],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())
+    });
+    match found {
+        Ok(runs) => runs.and_then(|runs| serde_json::from_str(&runs).ok()),
+        Err(e) => {
+            eprintln!("Failed to look up recognized text: {}", e);
+            None
+        }
+    }
+}
+
+/// The recognized lines of every page of a file that has been read.
+pub fn recognized_pages(file: &Path, fingerprint: &str) -> HashMap<i32, Vec<TextRun>> {
+    let mut pages = HashMap::new();
+    if !file.exists() {
+        return pages;
+    }
+    let found = open(file).and_then(|db| {
+        let mut query = db
+            .prepare("SELECT page, runs FROM pages WHERE fingerprint = ?1")
+            .map_err(|e| e.to_string())?;
+        let rows = query
+            .query_map(params![fingerprint], |row| {
+                Ok((row.get::<_, i32>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())
+    });
+    match found {
+        Ok(rows) => {
+            for (page, runs) in rows {
+                if let Ok(runs) = serde_json::from_str(&runs) {
+                    pages.insert(page, runs);
+                }
+            }
+        }
+        Err(e) => eprintln!("Failed to look up recognized text: {}", e),
+    }
+    pages
+}
+
+fn tesseract() -> PathBuf {
+    TESSERACT_LOCATIONS
+        .iter()
+        .map(PathBuf::from)
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from("tesseract"))
+}
+
+/// The directory downloaded languages are kept in.
+pub fn tessdata_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("tessdata"))
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))
+}
+
+// The languages installed with Tesseract, or None if it isn't
+fn installed_languages() -> Option<Vec<String>> {
+    let output = Command::new(tesseract())
+        .arg("--list-langs")
+        .stdin(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    // A header line, then a name per line
+    let names = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1)
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+    Some(names)
+}
+
+// The languages in the app's data directory, with the sizes of their files
+fn downloaded_languages(dir: &Path) -> Vec<(String, u64)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut languages: Vec<(String, u64)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.extension()? != "traineddata" {
+                return None;
+            }
+            let name = path.file_stem()?.to_str()?.to_string();
+            Some((name, entry.metadata().ok()?.len()))
+        })
+        .collect();
+    languages.sort();
+    languages
+}
+
+/// The data directory to pass to Tesseract for `lang`, None for the one it
+/// was installed with.
+pub fn data_dir_for(dir: &Path, lang: &str) -> Result<Option<PathBuf>, String> {
+    let downloaded: Vec<String> = downloaded_languages(dir)
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    let names: Vec<&str> = lang.split('+').collect();
+    if names
+        .iter()
+        .all(|name| downloaded.iter().any(|d| d == name))
+    {
+        return Ok(Some(dir.to_path_buf()));
+    }
+    // Tesseract reports the languages it lacks itself if it can't be asked
+    let Some(installed) = installed_languages() else {
+        return Ok(None);
+    };
+    if names.iter().all(|name| installed.iter().any(|i| i == name)) {
+        return Ok(None);
+    }
+    let mut missing: Vec<&str> = names
+        .into_iter()
+        .filter(|name| !installed.iter().any(|i| i == name))
+        .collect();
+    missing.dedup();
+    // Mixing the two directories isn't possible
+    let hint = if missing
+        .iter()
+        .all(|name| downloaded.iter().any(|d| d == name))
+    {
+        "Download the other languages too, so they're all in one place"
+    } else {
+        "Download them first"
+    };
+    Err(format!(
+        "Tesseract has no data for {}. {}",
+        missing.join(", "),
+        hint
+    ))
+}
+
+/// Checks a language for Tesseract: trained data names joined by `+`.
+pub fn check_language(lang: &str) -> Result<(), String> {
+    let valid = !lang.is_empty()
+        && lang.split('+').all(|name| {
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "'{}' isn't a Tesseract language, such as 'eng' or 'eng+deu'",
+            lang
+        ))
+    }
+}
+
+// Converts a `[width][height][rgba...]` bitmap to a binary PGM, which
+// Tesseract reads from standard input without an image library
+fn to_pgm(bitmap: &[u8]) -> Vec<u8> {
+    let (width, height, pixels) = decode_bitmap(bitmap);
+    let mut out = format!("P5\n{} {}\n255\n", width, height).into_bytes();
+    out.extend(
+        pixels.chunks_exact(4).map(|px| {
+            ((px[0] as u32 * 299 + px[1] as u32 * 587 + px[2] as u32 * 114) / 1000) as u8
+        }),
+    );
+    out
+}
+
+fn recognize(image: &[u8], lang: &str, data_dir: Option<&Path>) -> Result<String, String> {
+    let mut command = Command::new(tesseract());
+    if let Some(dir) = data_dir {
+        command.arg("--tessdata-dir").arg(dir);
+    }
+    let mut child = command
+        .args(["stdin", "stdout", "-l", lang, "--dpi"])
+        .arg(OCR_DPI.to_string())
+        .arg("tsv")
+        // Pages are read in parallel already
+        .env("OMP_THREAD_LIMIT", "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                "Recognizing text needs Tesseract, which isn't installed".to_string()
+            }
+            _ => format!("Failed to start Tesseract: {}", e),
+        })?;
+    let written = child
+        .stdin
+        .take()
+        .map(|mut stdin| stdin.write_all(image))
+        .transpose();
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Tesseract failed: {}", e))?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Tesseract failed: {}", message.trim()));
+    }
+    written.map_err(|e| format!("Failed to pass the page to Tesseract: {}", e))?;
+    String::from_utf8(output.stdout).map_err(|e| format!("Tesseract wrote invalid text: {}", e))
+}
+
+// Builds lines from Tesseract's TSV output, whose rows are the pages,
+// blocks, paragraphs, lines and words it found, in reading order, with
+// boxes in pixels. Characters share their word's box evenly, and the spaces
+// between words cover the gaps.
+fn read_tsv(tsv: &str, scale: f32) -> Vec<TextRun> {
+    let mut runs = Vec::new();
+    let mut line = None;
+    let mut words: Vec<(Bounds, &str)> = Vec::new();
+    for row in tsv.lines().skip(1) {
+        let fields: Vec<&str> = row.split('\t').collect();
+        if fields.len() < 12 || fields[0] != "5" {
+            continue;
+        }
+        let text = fields[11].trim();
+        if text.is_empty() {
+            continue;
+        }
+        let number = |i: usize| fields[i].parse::<f32>().unwrap_or(0.0) / scale;
+        let bounds = Bounds {
+            x: number(6),
+            y: number(7),
+            width: number(8),
+            height: number(9),
+        };
+        let key = (fields[2], fields[3], fields[4]);
+        if line != Some(key) {
+            runs.extend(to_run(&words));
+            words.clear();
+            line = Some(key);
+        }
+        words.push((bounds, text));
+    }
+    runs.extend(to_run(&words));
+    runs
+}
+
+fn to_run(words: &[(Bounds, &str)]) -> Option<TextRun> {
+    let boxes: Vec<Bounds> = words.iter().map(|(bounds, _)| *bounds).collect();
+    if boxes.is_empty() {
+        return None;
+    }
+    let bounds = union(&boxes);
+    let mut text = String::new();
+    let mut glyphs = Vec::new();
+    let mut end = None;
+    for (word, word_text) in words {
+        if let Some(end) = end {
+            text.push(' ');
+            glyphs.push(Bounds {
+                x: end,
+                y: bounds.y,
+                width: (word.x - end).max(0.0),
+                height: bounds.height,
+            });
+        }
+        let count = word_text.chars().count() as f32;
+        for (i, c) in word_text.chars().enumerate() {
+            text.push(c);
+            glyphs.push(Bounds {
+                x: word.x + word.width * i as f32 / count,
+                y: word.y,
+                width: word.width / count,
+                height: word.height,
+            });
+        }
+        end = Some(word.x + word.width);
+    }
+    Some(TextRun {
+        text,
+        bounds,
+        font_size: boxes.iter().map(|b| b.height).fold(0.0, f32::max),
+        vertical: false,
+        glyphs,
+    })
+}
+
+/// Renders a page for recognition, or returns None if it has a text layer
+/// and needn't be read.
+pub fn scan_page(
+    worker: &mut Worker,
+    doc: &Arc<OpenDocument>,
+    page: i32,
+    cancel: &CancelToken,
+) -> Result<Option<Vec<u8>>, String> {
+    let text_page = worker
+        .document(doc)?
+        .load_page(page)
+        .and_then(|p| p.to_text_page(mupdf::TextPageOptions::empty()))
+        .map_err(|e| e.to_string())?;
+    if !page_runs(&text_page).is_empty() {
+        return Ok(None);
+    }
+    rasterize_page(worker, doc, page, OCR_DPI / 72.0, ColorMode::Normal, cancel).map(Some)
+}
+
+/// Reads a page rendered by `scan_page` with Tesseract and stores the lines
+/// found in `file`. Returns the number of words.
+pub fn read_page(
+    bitmap: &[u8],
+    doc: &OpenDocument,
+    page: i32,
+    lang: &str,
+    data_dir: Option<&Path>,
+    file: &Path,
+) -> Result<usize, String> {
+    let tsv = recognize(&to_pgm(bitmap), lang, data_dir)?;
+    let runs = read_tsv(&tsv, OCR_DPI / 72.0);
+    let words = runs
+        .iter()
+        .map(|run| run.text.split(' ').count())
+        .sum::<usize>();
+    let json = serde_json::to_string(&runs).map_err(|e| e.to_string())?;
+    open(file)?
+        .execute(
+            "INSERT OR REPLACE INTO pages (fingerprint, page, language, runs)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![doc.fingerprint(), page, lang, json],
+        )
+        .map_err(|e| format!("Failed to store the recognized text: {}", e))?;
+    Ok(words)
+}
+
+// A recognition run, as the lanes it runs in share it
+struct Recognition {
+    app: AppHandle,
+    doc: Arc<OpenDocument>,
+    pages: Vec<i32>,
+    lang: String,
+    data_dir: Option<PathBuf>,
+    file: PathBuf,
+    job_id: Option<JobId>,
+    token: CancelToken,
+    done: AtomicUsize,
+}
+
+// Reads every `lanes`th page from `first`, as one of the lanes a recognition
+// run works in
+async fn recognize_lane(
+    run: Arc<Recognition>,
+    first: usize,
+    lanes: usize,
+) -> Result<OcrSummary, String> {
+    let engine = run.app.state::<Engine>();
+    let mut summary = OcrSummary::default();
+    for &page in run.pages.iter().skip(first).step_by(lanes) {
+        run.token.check()?;
+        let job = run.clone();
+        let bitmap = engine
+            .run(move |worker| {
+                job.token.check()?;
+                scan_page(worker, &job.doc, page, &job.token)
+            })
+            .await?;
+
+        match bitmap {
+            Some(bitmap) => {
+                let job = run.clone();
+                let words = blocking(move || {
+                    read_page(
+                        &bitmap,
+                        &job.doc,
+                        page,
+                        &job.lang,
+                        job.data_dir.as_deref(),
+                        &job.file,
+                    )
+                })
+                .await?;
+                summary.pages += 1;
+                summary.words += words;
+            }
+            None => summary.skipped += 1,
+        }
+
+        let done = run.done.fetch_add(1, Ordering::Relaxed) + 1;
+        let _ = run.app.emit(
+            "ocr-progress",
+            OcrProgress {
+                job_id: run.job_id,
+                doc_id: run.doc.id,
+                done,
+                total: run.pages.len(),
+            },
+        );
+    }
+    Ok(summary)
+}
+
+/// Recognizes the text of the pages selected by `ranges` (e.g.
+/// "1-5,8,10-end"), or all pages, in `lang` (Tesseract language names such
+/// as "eng" or "eng+deu", English by default). Pages that have a text layer
+/// are skipped.
+///
+/// Emits `ocr-progress` events after each page. Passing a `job_id` allows the
+/// run to be aborted with `cancel_render`; pages read before that are kept.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn ocr_pages(
+    app: AppHandle,
+    doc_id: DocId,
+    ranges: Option<String>,
+    lang: Option<String>,
+    job_id: Option<JobId>,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    jobs: State<'_, RenderJobs>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<OcrSummary, String> {
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Copy).await?;
+    let lang = lang.unwrap_or_else(|| DEFAULT_LANGUAGE.to_string());
+    check_language(&lang)?;
+    let pages = match &ranges {
+        Some(ranges) => parse_page_ranges(ranges, doc.page_count)?,
+        None => (0..doc.page_count).collect(),
+    };
+    let file = database_file(&app)?;
+    let tessdata = tessdata_dir(&app)?;
+    let languages = lang.clone();
+    let data_dir = blocking(move || data_dir_for(&tessdata, &languages)).await?;
+
+    // Tesseract takes a while per page, so a lane per worker thread is
+    // enough to keep them all busy
+    let token = jobs.begin_batch(job_id);
+    let lanes = engine.threads().min(pages.len().max(1));
+    let run = Arc::new(Recognition {
+        app,
+        doc,
+        pages,
+        lang,
+        data_dir,
+        file,
+        job_id,
+        token,
+        done: AtomicUsize::new(0),
+    });
+    let tasks: Vec<_> = (0..lanes)
+        .map(|first| tauri::async_runtime::spawn(recognize_lane(run.clone(), first, lanes)))
+        .collect();
+    let mut result = Ok(OcrSummary::default());
+    for task in tasks {
+        let lane = task.await.map_err(|e| e.to_string()).and_then(|lane| lane);
+        match (lane, &mut result) {
+            (Ok(lane), Ok(total)) => {
+                total.pages += lane.pages;
+                total.words += lane.words;
+                total.skipped += lane.skipped;
+            }
+            (Err(e), Ok(_)) => {
+                // The other lanes needn't go on
+                run.token.cancel();
+                result = Err(e);
+            }
+            _ => {}
+        }
+    }
+    jobs.finish_batch(job_id);
+    let summary = result?;
+
+    eprintln!(
+        "Recognized {} words on {} pages of document {} ({} had text)",
+        summary.words, summary.pages, doc_id, summary.skipped
+    );
+    Ok(summary)
+}
+
+// A ToUnicode map from every two-byte code to the same character
+fn identity_to_unicode() -> String {
+    let ranges: Vec<String> = (0..=0xffu32)
+        .filter(|high| !(0xd8..=0xdf).contains(high))
+        .map(|high| format!("<{:02X}00> <{:02X}FF> <{:02X}00>", high, high, high))
+        .collect();
+    let mut cmap = String::from(
+        "/CIDInit /ProcSet findresource begin\n\
+         12 dict begin\n\
+         begincmap\n\
+         /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+         /CMapName /Adobe-Identity-UCS def\n\
+         /CMapType 2 def\n\
+         1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n",
+    );
+    // A section may hold at most 100 ranges
+    for chunk in ranges.chunks(100) {
+        cmap.push_str(&format!("{} beginbfrange\n", chunk.len()));
+        for range in chunk {
+            cmap.push_str(range);
+            cmap.push('\n');
+        }
+        cmap.push_str("endbfrange\n");
+    }
+    cmap.push_str("endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend\n");
+    cmap
+}
+
+fn add_text_layer_font(doc: &mut Document) -> ObjectId {
+    let descriptor = doc.add_object(dictionary! {
+        "Type" => "FontDescriptor",
+        "FontName" => "GlyphLessFont",
+        "Flags" => 5,
+        "FontBBox" => vec![0.into(), DESCENT.into(), GLYPH_WIDTH.into(), ASCENT.into()],
+        "ItalicAngle" => 0,
+        "Ascent" => ASCENT,
+        "Descent" => DESCENT,
+        "CapHeight" => ASCENT,
+        "StemV" => 80,
+    });
+    let to_unicode = doc.add_object(Stream::new(
+        dictionary! {},
+        identity_to_unicode().into_bytes(),
+    ));
+    let cid_font = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "CIDFontType2",
+        "BaseFont" => "GlyphLessFont",
+        "CIDSystemInfo" => dictionary! {
+            "Registry" => Object::string_literal("Adobe"),
+            "Ordering" => Object::string_literal("Identity"),
+            "Supplement" => 0,
+        },
+        "FontDescriptor" => descriptor,
+        "DW" => GLYPH_WIDTH,
+        "CIDToGIDMap" => "Identity",
+    });
+    doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type0",
+        "BaseFont" => "GlyphLessFont",
+        "Encoding" => "Identity-H",
+        "DescendantFonts" => vec![cid_font.into()],
+        "ToUnicode" => to_unicode,
+    })
+}
+
+// Two bytes per character; those outside the Basic Multilingual Plane have
+// no code of their own and become "?"
+fn encode(text: &str) -> Vec<u8> {
+    text.chars()
+        .flat_map(|c| u16::try_from(c as u32).unwrap_or(b'?' as u16).to_be_bytes())
+        .collect()
+}
+
+// The operations writing a page's lines as invisible text, word by word.
+// Each word and the space after it span up to the start of the next word.
+// `height` is the height of the page as it's shown
+fn text_layer(runs: &[TextRun], font: &str, height: f32) -> Vec<Operation> {
+    let mut operations = vec![
+        Operation::new("BT", vec![]),
+        // Neither filled nor stroked
+        Operation::new("Tr", vec![3.into()]),
+    ];
+    for run in runs {
+        if run.vertical || run.bounds.height <= 0.0 {
+            continue;
+        }
+        let size = run.bounds.height;
+        let chars: Vec<char> = run.text.chars().collect();
+        // Where each word starts, as a character index and a position
+        let starts: Vec<(usize, f32)> = (0..chars.len().min(run.glyphs.len()))
+            .filter(|&i| chars[i] != ' ' && (i == 0 || chars[i - 1] == ' '))
+            .map(|i| (i, run.glyphs[i].x))
+            .collect();
+        let right = run.bounds.x + run.bounds.width;
+        operations.push(Operation::new(
+            "Tf",
+            vec![Object::Name(font.as_bytes().to_vec()), size.into()],
+        ));
+        // The baseline sits as far above the bottom of the line as the
+        // font descends below it
+        let baseline = height - (run.bounds.y + run.bounds.height) - DESCENT as f32 / 1000.0 * size;
+        for (n, &(first, x)) in starts.iter().enumerate() {
+            let (last, end) = match starts.get(n + 1) {
+                Some(&(next, next_x)) => (next, next_x),
+                None => (chars.len(), right),
+            };
+            let word: String = chars[first..last].iter().collect();
+            let natural = word.chars().count() as f32 * GLYPH_WIDTH as f32 / 1000.0 * size;
+            if natural <= 0.0 || end <= x {
+                continue;
+            }
+            operations.push(Operation::new(
+                "Tz",
+                vec![(100.0 * (end - x) / natural).into()],
+            ));
+            operations.push(Operation::new(
+                "Tm",
+                vec![
+                    1.into(),
+                    0.into(),
+                    0.into(),
+                    1.into(),
+                    x.into(),
+                    baseline.into(),
+                ],
+            ));
+            operations.push(Operation::new(
+                "Tj",
+                vec![Object::String(encode(&word), StringFormat::Hexadecimal)],
+            ));
+        }
+    }
+    operations.push(Operation::new("ET", vec![]));
+    operations
+}
+
+/// Writes the text recognized in the document's scanned pages into them as
+/// invisible text, so the file is searchable in other viewers once saved.
+/// Pages that have a text layer by now are left alone.
+#[tauri::command]
+pub async fn embed_ocr_text(
+    app: AppHandle,
+    doc_id: DocId,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<EmbeddedText, String> {
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Modify).await?;
+    let file = database_file(&app)?;
+    let lookup = doc.clone();
+    let recognized = blocking(move || Ok(recognized_pages(&file, lookup.fingerprint()))).await?;
+    if recognized.is_empty() {
+        return Err(format!(
+            "No text has been recognized in {} yet",
+            doc.path.display()
+        ));
+    }
+
+    let target = doc.clone();
+    let mut pages = engine
+        .run(move |worker| {
+            let handle = worker.document(&target)?;
+            let mut pages = Vec::new();
+            for (page, runs) in recognized {
+                let text_page = handle
+                    .load_page(page)
+                    .and_then(|p| p.to_text_page(mupdf::TextPageOptions::empty()))
+                    .map_err(|e| e.to_string())?;
+                if page_runs(&text_page).is_empty() {
+                    pages.push((page, runs));
+                }
+            }
+            Ok(pages)
+        })
+        .await?;
+    if pages.is_empty() {
+        return Err("The pages read have a text layer already".into());
+    }
+    pages.sort_by_key(|(page, _)| *page);
+
+    let (document, count) = apply_edit_with(doc, &store, &tiles, &scheduler, move |objects| {
+        let font = add_text_layer_font(objects);
+        let ids: Vec<ObjectId> = objects.get_pages().into_values().collect();
+        for (page, runs) in &pages {
+            let page_id = ids[*page as usize];
+            let (upright, (_, height)) = upright_transform(objects, page_id);
+            let name = add_resource(objects, page_id, b"Font", "OcrF", font.into())?;
+            let mut operations = vec![
+                Operation::new("q", vec![]),
+                Operation::new("cm", upright.iter().map(|&v| v.into()).collect()),
+            ];
+            operations.extend(text_layer(runs, &name, height));
+            operations.push(Operation::new("Q", vec![]));
+            append_content(objects, page_id, operations)?;
+        }
+        Ok(pages.len())
+    })
+    .await?;
+    eprintln!(
+        "Embedded recognized text in {} pages of document {}",
+        count, doc_id
+    );
+    Ok(EmbeddedText {
+        document,
+        pages: count,
+    })
+}
+
+/// Lists the languages text can be recognized in.
+#[tauri::command]
+pub async fn list_ocr_languages(app: AppHandle) -> Result<OcrLanguages, String> {
+    let dir = tessdata_dir(&app)?;
+    blocking(move || {
+        let installed = installed_languages();
+        let mut languages: Vec<OcrLanguage> = downloaded_languages(&dir)
+            .into_iter()
+            .map(|(name, size)| OcrLanguage {
+                name,
+                downloaded: true,
+                size: Some(size),
+            })
+            .collect();
+        for name in installed.iter().flatten() {
+            if !languages.iter().any(|language| &language.name == name) {
+                languages.push(OcrLanguage {
+                    name: name.clone(),
+                    downloaded: false,
+                    size: None,
+                });
+            }
+        }
+        languages.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(OcrLanguages {
+            tesseract: installed.is_some(),
+            languages,
+        })
+    })
+    .await
+}
+
+/// Downloads the trained data for `language` (a Tesseract name such as
+/// "deu" or "ces") into the app data directory.
+///
+/// Emits `ocr-language-download` events as the file comes in.
+#[tauri::command]
+pub async fn download_ocr_language(
+    app: AppHandle,
+    language: String,
+) -> Result<OcrLanguage, String> {
+    if language.contains('+') {
+        return Err("Download one language at a time".into());
+    }
+    check_language(&language)?;
+    let dir = tessdata_dir(&app)?;
+    blocking(move || {
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        // The GitHub download redirects to its content server
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let url = format!("{}/{}.traineddata", TESSDATA_URL, language);
+        let mut response = reqwest::blocking::Client::new()
+            .get(&url)
+            .timeout(DOWNLOAD_TIMEOUT)
+            .send()
+            .map_err(|e| format!("Failed to download {}: {}", language, e))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(format!("Tesseract has no language called '{}'", language));
+        }
+        response = response
+            .error_for_status()
+            .map_err(|e| format!("Failed to download {}: {}", language, e))?;
+        let total = response.content_length();
+
+        // Written beside the final file, so a broken download never looks like
+        // a language
+        let target = dir.join(format!("{}.traineddata", language));
+        let partial = dir.join(format!("{}.traineddata.part", language));
+        let write_error =
+            |e: std::io::Error| format!("Failed to write {}: {}", partial.display(), e);
+        let mut file = std::fs::File::create(&partial).map_err(write_error)?;
+        let mut buffer = vec![0; 64 * 1024];
+        let mut received = 0;
+        let mut reported = 0;
+        let copied = loop {
+            let read = match response.read(&mut buffer) {
+                Ok(0) => break Ok(()),
+                Ok(read) => read,
+                Err(e) => break Err(format!("Failed to download {}: {}", language, e)),
+            };
+            if let Err(e) = file.write_all(&buffer[..read]) {
+                break Err(write_error(e));
+            }
+            received += read as u64;
+            if received - reported >= PROGRESS_STEP {
+                reported = received;
+                let _ = app.emit(
+                    "ocr-language-download",
+                    LanguageDownload {
+                        language: language.clone(),
+                        received,
+                        total,
+                    },
+                );
+            }
+        };
+        let finished = copied.and_then(|()| {
+            if total.is_some_and(|total| total != received) {
+                return Err(format!("The download of {} was cut short", language));
+            }
+            file.sync_all().map_err(write_error)?;
+            drop(file);
+            std::fs::rename(&partial, &target)
+                .map_err(|e| format!("Failed to write {}: {}", target.display(), e))
+        });
+        if let Err(e) = finished {
+            let _ = std::fs::remove_file(&partial);
+            return Err(e);
+        }
+        let _ = app.emit(
+            "ocr-language-download",
+            LanguageDownload {
+                language: language.clone(),
+                received,
+                total,
+            },
+        );
+        eprintln!("Downloaded OCR language {} ({} bytes)", language, received);
+        Ok(OcrLanguage {
+            name: language,
+            downloaded: true,
+            size: Some(received),
+        })
+    })
+    .await
+}
+
+/// Removes a downloaded language. Those installed with Tesseract stay.
+#[tauri::command]
+pub async fn remove_ocr_language(app: AppHandle, language: String) -> Result<(), String> {
+    check_language(&language)?;
+    let file = tessdata_dir(&app)?.join(format!("{}.traineddata", language));
+    blocking(move || {
+        if !file.is_file() {
+            return Err(format!("{} wasn't downloaded by Nanodoc", language));
+        }
+        std::fs::remove_file(&file)
+            .map_err(|e| format!("Failed to remove {}: {}", file.display(), e))?;
+        eprintln!("Removed OCR language {}", language);
+        Ok(())
+    })
+    .await
+}
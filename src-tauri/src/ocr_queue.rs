@@ -0,0 +1,457 @@
+// A queue of documents waiting for text recognition.
+//
+// Whole folders of scans take hours to read, so documents can be queued by
+// path. They're read one after another on a thread of their own, with a
+// renderer of its own, rather than on the engine's workers that the open
+// documents need. Tesseract runs on one core, and the thread rests between
+// pages for as long as the page took, so the rest of the machine stays
+// responsive.
+//
+// The queue is kept in `<app data>/ocr.sqlite3` beside the recognized text
+// (see `ocr.rs`), so it survives restarts. Pausing or cancelling a job stops
+// it within a page; as each page is stored once read, a paused job resumes
+// where it stopped. Every change of a job is sent as an `ocr-queue` event.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::compose::parse_page_ranges;
+use crate::engine::Worker;
+use crate::jobs::CancelToken;
+use crate::ocr;
+use crate::pdf::{read_data, DocumentStore, OpenDocument};
+use crate::scope;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS queue (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL,
+        ranges TEXT,
+        language TEXT NOT NULL,
+        state TEXT NOT NULL,
+        done INTEGER NOT NULL DEFAULT 0,
+        total INTEGER NOT NULL DEFAULT 0,
+        error TEXT
+    );
+";
+
+// How long the thread rests after a page, as a share of the time the page took
+const REST: f32 = 1.0;
+// Steps the rest is taken in, so pausing doesn't wait for it
+const REST_STEP: Duration = Duration::from_millis(100);
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Paused,
+    Done,
+    Failed,
+}
+
+impl JobState {
+    fn as_str(self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Paused => "paused",
+            JobState::Done => "done",
+            JobState::Failed => "failed",
+        }
+    }
+
+    fn parse(state: &str) -> JobState {
+        match state {
+            "running" => JobState::Running,
+            "paused" => JobState::Paused,
+            "done" => JobState::Done,
+            "failed" => JobState::Failed,
+            _ => JobState::Queued,
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OcrJob {
+    pub id: i64,
+    pub path: String,
+    /// One-based ranges like "1-5,8,10-end"; all pages if missing
+    pub ranges: Option<String>,
+    pub language: String,
+    pub state: JobState,
+    /// Pages read, or found to have text, out of `total`
+    pub done: usize,
+    pub total: usize,
+    /// Why a failed job failed
+    pub error: Option<String>,
+}
+
+// Whether the queue thread is running and which job it's on
+#[derive(Default)]
+struct Processing {
+    running: bool,
+    again: bool,
+    current: Option<(i64, CancelToken)>,
+}
+
+/// The queue thread's state, managed as Tauri state.
+#[derive(Default)]
+pub struct OcrQueue {
+    processing: Mutex<Processing>,
+}
+
+fn open(file: &Path) -> Result<Connection, String> {
+    let db = ocr::open(file)?;
+    db.execute_batch(SCHEMA)
+        .map_err(|e| format!("Failed to set up {}: {}", file.display(), e))?;
+    Ok(db)
+}
+
+fn open_queue(app: &AppHandle) -> Result<Connection, String> {
+    open(&ocr::database_file(app)?)
+}
+
+const COLUMNS: &str = "id, path, ranges, language, state, done, total, error";
+
+fn read_job(row: &rusqlite::Row) -> rusqlite::Result<OcrJob> {
+    Ok(OcrJob {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        ranges: row.get(2)?,
+        language: row.get(3)?,
+        state: JobState::parse(&row.get::<_, String>(4)?),
+        done: row.get::<_, i64>(5)? as usize,
+        total: row.get::<_, i64>(6)? as usize,
+        error: row.get(7)?,
+    })
+}
+
+fn job(db: &Connection, id: i64) -> Result<OcrJob, String> {
+    db.query_row(
+        &format!("SELECT {} FROM queue WHERE id = ?1", COLUMNS),
+        params![id],
+        read_job,
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("There is no OCR job {}", id))
+}
+
+fn jobs(db: &Connection) -> Result<Vec<OcrJob>, String> {
+    let mut query = db
+        .prepare(&format!("SELECT {} FROM queue ORDER BY id", COLUMNS))
+        .map_err(|e| e.to_string())?;
+    let rows = query
+        .query_map(params![], read_job)
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())
+}
+
+fn next_job(db: &Connection) -> Result<Option<OcrJob>, String> {
+    db.query_row(
+        &format!(
+            "SELECT {} FROM queue WHERE state = 'queued' ORDER BY id LIMIT 1",
+            COLUMNS
+        ),
+        params![],
+        read_job,
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+// Sets the state of a job if it's still in `from`, which a pause or cancel
+// in the meantime will have changed, and reports it. Returns whether it did
+fn settle(
+    app: &AppHandle,
+    db: &Connection,
+    id: i64,
+    (from, to): (JobState, JobState),
+    error: Option<&str>,
+) -> bool {
+    let updated = db.execute(
+        "UPDATE queue SET state = ?1, error = ?2 WHERE id = ?3 AND state = ?4",
+        params![to.as_str(), error, id, from.as_str()],
+    );
+    match updated {
+        Ok(0) => false,
+        Ok(_) => {
+            announce(app, db, id);
+            true
+        }
+        Err(e) => {
+            eprintln!("Failed to update OCR job {}: {}", id, e);
+            false
+        }
+    }
+}
+
+fn announce(app: &AppHandle, db: &Connection, id: i64) {
+    if let Ok(job) = job(db, id) {
+        let _ = app.emit("ocr-queue", job);
+    }
+}
+
+// Waits after a page so the thread uses about 1 / (1 + REST) of a core
+fn rest(took: Duration, cancel: &CancelToken) {
+    let until = Instant::now() + took.mul_f32(REST);
+    while Instant::now() < until && !cancel.is_cancelled() {
+        std::thread::sleep(REST_STEP);
+    }
+}
+
+// Reads the pages of a job that haven't been read yet
+fn process(
+    app: &AppHandle,
+    db: &Connection,
+    job: &OcrJob,
+    cancel: &CancelToken,
+) -> Result<(), String> {
+    let path = PathBuf::from(&job.path);
+    let file = ocr::database_file(app)?;
+    let data_dir = ocr::data_dir_for(&ocr::tessdata_dir(app)?, &job.language)?;
+
+    // The document isn't in the store, but its id mustn't clash with those
+    // that are. The page count comes from the renderer
+    let id = app.state::<DocumentStore>().next_id();
+    let doc = Arc::new(OpenDocument::new(id, path.clone(), read_data(&path)?, 0));
+    let mut worker = Worker::new();
+    let page_count = worker
+        .document(&doc)?
+        .page_count()
+        .map_err(|e| e.to_string())?;
+    let pages = match &job.ranges {
+        Some(ranges) => parse_page_ranges(ranges, page_count)?,
+        None => (0..page_count).collect(),
+    };
+    let recognized = ocr::recognized_pages(&file, doc.fingerprint());
+    let remaining: Vec<i32> = pages
+        .iter()
+        .copied()
+        .filter(|page| !recognized.contains_key(page))
+        .collect();
+
+    let progress = |done: usize| {
+        let updated = db.execute(
+            "UPDATE queue SET done = ?1, total = ?2 WHERE id = ?3",
+            params![done as i64, pages.len() as i64, job.id],
+        );
+        match updated {
+            Ok(_) => announce(app, db, job.id),
+            Err(e) => eprintln!("Failed to update OCR job {}: {}", job.id, e),
+        }
+    };
+    let mut done = pages.len() - remaining.len();
+    progress(done);
+    for page in remaining {
+        cancel.check()?;
+        let started = Instant::now();
+        if let Some(bitmap) = ocr::scan_page(&mut worker, &doc, page, cancel)? {
+            ocr::read_page(
+                &bitmap,
+                &doc,
+                page,
+                &job.language,
+                data_dir.as_deref(),
+                &file,
+            )?;
+        }
+        done += 1;
+        progress(done);
+        rest(started.elapsed(), cancel);
+    }
+    Ok(())
+}
+
+// Works through the queued jobs, then returns
+fn process_queue(app: &AppHandle) -> Result<(), String> {
+    let db = open_queue(app)?;
+    while let Some(job) = next_job(&db)? {
+        let cancel = CancelToken::default();
+        {
+            let queue = app.state::<OcrQueue>();
+            queue.processing.lock().unwrap().current = Some((job.id, cancel.clone()));
+        }
+        let transition = (JobState::Queued, JobState::Running);
+        if !settle(app, &db, job.id, transition, None) {
+            continue;
+        }
+        let result = process(app, &db, &job, &cancel);
+        app.state::<OcrQueue>().processing.lock().unwrap().current = None;
+        match result {
+            Ok(()) => {
+                eprintln!("Recognized the text of {}", job.path);
+                settle(app, &db, job.id, (JobState::Running, JobState::Done), None);
+            }
+            // Paused or cancelled, which has already been recorded
+            Err(_) if cancel.is_cancelled() => {}
+            Err(e) => {
+                eprintln!("Failed to recognize the text of {}: {}", job.path, e);
+                let transition = (JobState::Running, JobState::Failed);
+                settle(app, &db, job.id, transition, Some(&e));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Works through the queue on a thread of its own. While that runs, further
+/// calls have it look for queued jobs once more when it's done.
+pub fn process_in_background(app: &AppHandle) {
+    {
+        let queue = app.state::<OcrQueue>();
+        let mut processing = queue.processing.lock().unwrap();
+        if processing.running {
+            processing.again = true;
+            return;
+        }
+        processing.running = true;
+    }
+    let spawned = std::thread::Builder::new().name("ocr-queue".into()).spawn({
+        let app = app.clone();
+        move || loop {
+            if let Err(e) = process_queue(&app) {
+                eprintln!("Failed to work through the OCR queue: {}", e);
+            }
+            let queue = app.state::<OcrQueue>();
+            let mut processing = queue.processing.lock().unwrap();
+            if !std::mem::take(&mut processing.again) {
+                processing.running = false;
+                break;
+            }
+        }
+    });
+    if let Err(e) = spawned {
+        eprintln!("Failed to start the OCR queue: {}", e);
+        app.state::<OcrQueue>().processing.lock().unwrap().running = false;
+    }
+}
+
+/// Puts jobs that were running when the app quit back in the queue and starts
+/// working through it.
+pub fn restore(app: &AppHandle) {
+    // Nothing was ever queued without the database
+    let Ok(file) = ocr::database_file(app) else {
+        return;
+    };
+    if !file.exists() {
+        return;
+    }
+    let restored = open(&file).and_then(|db| {
+        db.execute(
+            "UPDATE queue SET state = 'queued' WHERE state = 'running'",
+            params![],
+        )
+        .map_err(|e| e.to_string())?;
+        next_job(&db)
+    });
+    match restored {
+        Ok(Some(_)) => process_in_background(app),
+        Ok(None) => {}
+        Err(e) => eprintln!("Failed to read the OCR queue: {}", e),
+    }
+}
+
+// Stops the job if it's the one being read
+fn stop(queue: &OcrQueue, id: i64) {
+    if let Some((current, cancel)) = &queue.processing.lock().unwrap().current {
+        if *current == id {
+            cancel.cancel();
+        }
+    }
+}
+
+/// Lists the jobs of the OCR queue, finished ones included.
+#[tauri::command]
+pub async fn get_ocr_queue(app: AppHandle) -> Result<Vec<OcrJob>, String> {
+    jobs(&open_queue(&app)?)
+}
+
+/// Queues documents for text recognition of the pages selected by `ranges`
+/// (all pages if missing) in `lang` (English by default; see `ocr_pages`).
+#[tauri::command]
+pub async fn queue_ocr_documents(
+    app: AppHandle,
+    paths: Vec<String>,
+    ranges: Option<String>,
+    lang: Option<String>,
+) -> Result<Vec<OcrJob>, String> {
+    let lang = lang.unwrap_or_else(|| ocr::DEFAULT_LANGUAGE.to_string());
+    ocr::check_language(&lang)?;
+    for path in &paths {
+        scope::check(&app, Path::new(path))?;
+    }
+    let db = open_queue(&app)?;
+    let mut queued = Vec::with_capacity(paths.len());
+    for path in paths {
+        db.execute(
+            "INSERT INTO queue (path, ranges, language, state) VALUES (?1, ?2, ?3, 'queued')",
+            params![path, ranges, lang],
+        )
+        .map_err(|e| format!("Failed to queue {}: {}", path, e))?;
+        let job = job(&db, db.last_insert_rowid())?;
+        let _ = app.emit("ocr-queue", job.clone());
+        queued.push(job);
+    }
+    eprintln!("Queued {} documents for OCR", queued.len());
+    process_in_background(&app);
+    Ok(queued)
+}
+
+/// Pauses a queued or running job. A running one stops after the page it's
+/// reading.
+#[tauri::command]
+pub async fn pause_ocr_job(
+    app: AppHandle,
+    id: i64,
+    queue: State<'_, OcrQueue>,
+) -> Result<OcrJob, String> {
+    let db = open_queue(&app)?;
+    let current = job(&db, id)?;
+    if !matches!(current.state, JobState::Queued | JobState::Running) {
+        return Err(format!("OCR job {} isn't waiting or running", id));
+    }
+    settle(&app, &db, id, (current.state, JobState::Paused), None);
+    stop(&queue, id);
+    job(&db, id)
+}
+
+/// Puts a paused or failed job back in the queue.
+#[tauri::command]
+pub async fn resume_ocr_job(app: AppHandle, id: i64) -> Result<OcrJob, String> {
+    let db = open_queue(&app)?;
+    let current = job(&db, id)?;
+    if !matches!(current.state, JobState::Paused | JobState::Failed) {
+        return Err(format!("OCR job {} isn't paused", id));
+    }
+    settle(&app, &db, id, (current.state, JobState::Queued), None);
+    process_in_background(&app);
+    job(&db, id)
+}
+
+/// Removes a job from the queue, stopping it if it's running. The text of the
+/// pages read so far is kept.
+#[tauri::command]
+pub async fn cancel_ocr_job(
+    app: AppHandle,
+    id: i64,
+    queue: State<'_, OcrQueue>,
+) -> Result<(), String> {
+    let db = open_queue(&app)?;
+    let deleted = db
+        .execute("DELETE FROM queue WHERE id = ?1", params![id])
+        .map_err(|e| format!("Failed to remove OCR job {}: {}", id, e))?;
+    if deleted == 0 {
+        return Err(format!("There is no OCR job {}", id));
+    }
+    stop(&queue, id);
+    Ok(())
+}
@@ -0,0 +1,380 @@
+// Making documents smaller: downsampling and recompressing images, then
+// dropping objects nothing refers to.
+//
+// Images are measured where page content draws them, so a scan shown at full
+// page size gets its resolution from the page size. Only 8-bit gray and RGB
+// images without colour key masks or decode arrays are touched; these can be
+// re-encoded as JPEG with the same colour space. Anything else, and images
+// that are only used by annotations or as soft masks, stays as it is.
+
+use std::collections::HashMap;
+
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::content::{concat, transform, Transform, IDENTITY};
+use crate::edit::{apply_edit_with, EditedDocument};
+use crate::objects::{self, MAX_DEPTH};
+use crate::pdf::{DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scheduler::RenderScheduler;
+use crate::tiles::TileCache;
+
+// Images are only downsampled when this much sharper than the target, since
+// every pass through JPEG costs quality
+const DOWNSAMPLE_MARGIN: f32 = 1.25;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OptimizeOptions {
+    /// Images sharper than this are downsampled to it; `None` keeps their size
+    pub target_dpi: Option<f32>,
+    /// JPEG quality for re-encoded images, 1 to 100
+    pub jpeg_quality: u8,
+    /// Also re-encode JPEG images that keep their size, where that makes them
+    /// smaller
+    pub recompress: bool,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        OptimizeOptions {
+            target_dpi: Some(150.0),
+            jpeg_quality: 75,
+            recompress: true,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OptimizeReport {
+    pub document: EditedDocument,
+    /// Size of the file on disk before optimizing
+    pub original_size: u64,
+    /// Size the optimized document will have when saved
+    pub optimized_size: u64,
+    pub images_downsampled: usize,
+    pub images_recompressed: usize,
+}
+
+// Records the largest size, in points, each image is drawn at by `content`
+fn scan_content(
+    doc: &Document,
+    content: &[u8],
+    resources: Option<&Dictionary>,
+    mut ctm: Transform,
+    depth: usize,
+    sizes: &mut HashMap<ObjectId, (f32, f32)>,
+) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+    let Ok(content) = Content::decode(content) else {
+        return;
+    };
+    let xobjects = resources.and_then(|r| objects::get_dict(doc, r, b"XObject"));
+    let mut saved = Vec::new();
+    for op in content.operations {
+        match op.operator.as_str() {
+            "q" => saved.push(ctm),
+            "Q" => ctm = saved.pop().unwrap_or(ctm),
+            "cm" => {
+                if let Some(m) = transform(doc, &op.operands) {
+                    ctm = concat(m, ctm);
+                }
+            }
+            "Do" => {
+                let Some(id) = op
+                    .operands
+                    .first()
+                    .and_then(|name| name.as_name().ok())
+                    .and_then(|name| xobjects?.get(name).ok())
+                    .and_then(|xobject| xobject.as_reference().ok())
+                else {
+                    continue;
+                };
+                let Ok(Object::Stream(stream)) = doc.get_object(id) else {
+                    continue;
+                };
+                match objects::get_name(doc, &stream.dict, b"Subtype") {
+                    // Images fill the unit square of the current transform
+                    Some(b"Image") => {
+                        let width = ctm[0].hypot(ctm[1]);
+                        let height = ctm[2].hypot(ctm[3]);
+                        let size = sizes.entry(id).or_insert((0.0, 0.0));
+                        *size = (size.0.max(width), size.1.max(height));
+                    }
+                    Some(b"Form") => {
+                        let matrix = objects::get_array(doc, &stream.dict, b"Matrix")
+                            .and_then(|m| transform(doc, m))
+                            .unwrap_or(IDENTITY);
+                        let form_resources =
+                            objects::get_dict(doc, &stream.dict, b"Resources").or(resources);
+                        if let Ok(form) = stream.decompressed_content() {
+                            scan_content(
+                                doc,
+                                &form,
+                                form_resources,
+                                concat(matrix, ctm),
+                                depth + 1,
+                                sizes,
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn image_sizes(doc: &Document) -> HashMap<ObjectId, (f32, f32)> {
+    let mut sizes = HashMap::new();
+    for page_id in doc.get_pages().into_values() {
+        let resources =
+            objects::inherited(doc, page_id, b"Resources").and_then(|r| r.as_dict().ok());
+        if let Ok(content) = doc.get_page_content(page_id) {
+            scan_content(doc, &content, resources, IDENTITY, 0, &mut sizes);
+        }
+    }
+    sizes
+}
+
+/// Decoded 8-bit samples of an image.
+pub struct Samples {
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    pub pixels: Vec<u8>,
+}
+
+fn color_channels(doc: &Document, space: &Object) -> Option<usize> {
+    match objects::resolve(doc, space) {
+        Object::Name(name) => match name.as_slice() {
+            b"DeviceGray" => Some(1),
+            b"DeviceRGB" => Some(3),
+            _ => None,
+        },
+        Object::Array(array) => {
+            let [kind, profile] = array.as_slice() else {
+                return None;
+            };
+            if kind.as_name().ok()? != b"ICCBased" {
+                return None;
+            }
+            let Object::Stream(profile) = objects::resolve(doc, profile) else {
+                return None;
+            };
+            match objects::get(doc, &profile.dict, b"N")?.as_i64().ok()? {
+                n @ (1 | 3) => Some(n as usize),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Decodes an image that can be re-encoded as JPEG, with whether it already
+/// is one.
+pub fn decode_image(doc: &Document, stream: &Stream) -> Option<(Samples, bool)> {
+    let dict = &stream.dict;
+    let image_mask = objects::get(doc, dict, b"ImageMask").and_then(|m| m.as_bool().ok());
+    if dict.has(b"Mask") || dict.has(b"Decode") || image_mask == Some(true) {
+        return None;
+    }
+    if objects::get(doc, dict, b"BitsPerComponent")?
+        .as_i64()
+        .ok()?
+        != 8
+    {
+        return None;
+    }
+    let width = objects::get(doc, dict, b"Width")?.as_i64().ok()? as usize;
+    let height = objects::get(doc, dict, b"Height")?.as_i64().ok()? as usize;
+    let channels = color_channels(doc, objects::get(doc, dict, b"ColorSpace")?)?;
+
+    let filters = stream.filters().unwrap_or_default();
+    let filters: Vec<&str> = filters.iter().map(String::as_str).collect();
+    let (pixels, jpeg) = match filters.as_slice() {
+        [] => (stream.content.clone(), false),
+        ["FlateDecode"] | ["LZWDecode"] => (stream.decompressed_content().ok()?, false),
+        ["DCTDecode"] => {
+            let mut decoder = jpeg_decoder::Decoder::new(stream.content.as_slice());
+            let pixels = decoder.decode().ok()?;
+            let format = decoder.info()?.pixel_format;
+            // CMYK JPEGs and the like would need their colour space changed
+            match (format, channels) {
+                (jpeg_decoder::PixelFormat::L8, 1) | (jpeg_decoder::PixelFormat::RGB24, 3) => {}
+                _ => return None,
+            }
+            (pixels, true)
+        }
+        _ => return None,
+    };
+    if pixels.len() != width * height * channels {
+        return None;
+    }
+    Some((
+        Samples {
+            width,
+            height,
+            channels,
+            pixels,
+        },
+        jpeg,
+    ))
+}
+
+// Box-filters an image down to `width` x `height`
+fn downsample(image: &Samples, width: usize, height: usize) -> Samples {
+    let n = image.channels;
+    let mut pixels = Vec::with_capacity(width * height * n);
+    for y in 0..height {
+        let y0 = y * image.height / height;
+        let y1 = ((y + 1) * image.height / height).max(y0 + 1);
+        for x in 0..width {
+            let x0 = x * image.width / width;
+            let x1 = ((x + 1) * image.width / width).max(x0 + 1);
+            let mut sum = [0u64; 3];
+            for row in y0..y1 {
+                let start = (row * image.width + x0) * n;
+                let end = (row * image.width + x1) * n;
+                for pixel in image.pixels[start..end].chunks_exact(n) {
+                    for (total, &value) in sum.iter_mut().zip(pixel) {
+                        *total += value as u64;
+                    }
+                }
+            }
+            let count = ((y1 - y0) * (x1 - x0)) as u64;
+            pixels.extend(
+                sum[..n]
+                    .iter()
+                    .map(|total| ((total + count / 2) / count) as u8),
+            );
+        }
+    }
+    Samples {
+        width,
+        height,
+        channels: n,
+        pixels,
+    }
+}
+
+fn encode_jpeg(image: &Samples, quality: u8) -> Option<Vec<u8>> {
+    let color = match image.channels {
+        1 => jpeg_encoder::ColorType::Luma,
+        _ => jpeg_encoder::ColorType::Rgb,
+    };
+    let mut data = Vec::new();
+    jpeg_encoder::Encoder::new(&mut data, quality)
+        .encode(
+            &image.pixels,
+            image.width.try_into().ok()?,
+            image.height.try_into().ok()?,
+            color,
+        )
+        .ok()?;
+    Some(data)
+}
+
+// Re-encodes the document's images, returning how many were downsampled and
+// how many only recompressed
+fn optimize_images(doc: &mut Document, options: &OptimizeOptions) -> (usize, usize) {
+    let quality = options.jpeg_quality.clamp(1, 100);
+    let mut downsampled = 0;
+    let mut recompressed = 0;
+    for (id, (width_pt, height_pt)) in image_sizes(doc) {
+        let Ok(Object::Stream(stream)) = doc.get_object(id) else {
+            continue;
+        };
+        let Some((image, jpeg)) = decode_image(doc, stream) else {
+            continue;
+        };
+
+        // The pixels needed to show the image at the target resolution
+        let scale = options.target_dpi.map_or(1.0, |dpi| {
+            let wanted_width = width_pt / 72.0 * dpi;
+            let wanted_height = height_pt / 72.0 * dpi;
+            (wanted_width / image.width as f32).max(wanted_height / image.height as f32)
+        });
+        let (image, resized) = if scale * DOWNSAMPLE_MARGIN < 1.0 {
+            let width = ((image.width as f32 * scale).ceil() as usize).max(1);
+            let height = ((image.height as f32 * scale).ceil() as usize).max(1);
+            (downsample(&image, width, height), true)
+        } else if jpeg && options.recompress {
+            (image, false)
+        } else {
+            continue;
+        };
+
+        let Some(data) = encode_jpeg(&image, quality) else {
+            continue;
+        };
+        if !resized && data.len() >= stream.content.len() {
+            continue;
+        }
+
+        let mut dict = stream.dict.clone();
+        dict.set("Filter", "DCTDecode");
+        dict.remove(b"DecodeParms");
+        dict.set("Width", image.width as i64);
+        dict.set("Height", image.height as i64);
+        // Already compressed; deflating JPEG data gains nothing
+        let stream = Stream::new(dict, data).with_compression(false);
+        doc.objects.insert(id, Object::Stream(stream));
+        if resized {
+            downsampled += 1;
+        } else {
+            recompressed += 1;
+        }
+    }
+    (downsampled, recompressed)
+}
+
+/// Shrinks a document by downsampling and recompressing its images and
+/// dropping unused objects. The result is kept as an unsaved edit; the report
+/// compares the file on disk with what saving will write.
+#[tauri::command]
+pub async fn optimize_document(
+    doc_id: DocId,
+    options: Option<OptimizeOptions>,
+    store: State<'_, DocumentStore>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<OptimizeReport, String> {
+    let options = options.unwrap_or_default();
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Modify).await?;
+    let original_size = std::fs::metadata(&doc.path)
+        .map(|m| m.len())
+        .unwrap_or(doc.data.len() as u64);
+
+    let (document, (images_downsampled, images_recompressed)) =
+        apply_edit_with(doc, &store, &tiles, &scheduler, move |objects| {
+            let counts = optimize_images(objects, &options);
+            objects.prune_objects();
+            objects.compress();
+            Ok(counts)
+        })
+        .await?;
+
+    // The edited version is what saving writes, as it's already compressed
+    let optimized_size = store.get(doc_id)?.data.len() as u64;
+    eprintln!(
+        "Optimized document {}: {} -> {} bytes ({} images downsampled, {} recompressed)",
+        doc_id, original_size, optimized_size, images_downsampled, images_recompressed
+    );
+    Ok(OptimizeReport {
+        document,
+        original_size,
+        optimized_size,
+        images_downsampled,
+        images_recompressed,
+    })
+}
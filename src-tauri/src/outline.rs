@@ -0,0 +1,257 @@
+// Document outline (bookmarks) and destination resolution.
+
+use std::collections::{HashMap, HashSet};
+
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use serde::Serialize;
+use tauri::State;
+
+use crate::objects::{self, MAX_DEPTH};
+use crate::pdf::{blocking, DocId, DocumentStore};
+
+/// A resolved jump target. Coordinates are in page space with the origin at
+/// the top-left corner, matching the renderer.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Destination {
+    pub page: i32,
+    pub left: Option<f32>,
+    pub top: Option<f32>,
+    pub zoom: Option<f32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutlineItem {
+    pub title: String,
+    pub destination: Option<Destination>,
+    /// Set for items that open a web link instead of jumping in the document
+    pub uri: Option<String>,
+    pub open: bool,
+    pub children: Vec<OutlineItem>,
+}
+
+/// Resolves an explicit destination array or a destination name.
+pub fn resolve_destination(
+    doc: &Document,
+    pages: &HashMap<ObjectId, i32>,
+    dest: &Object,
+) -> Option<Destination> {
+    match objects::resolve(doc, dest) {
+        Object::Array(array) => explicit_destination(doc, pages, array),
+        Object::Name(name) | Object::String(name, _) => {
+            let target = lookup_named_destination(doc, name)?;
+            resolve_destination(doc, pages, target)
+        }
+        // Named destinations may be wrapped in a dictionary with a /D entry
+        Object::Dictionary(dict) => resolve_destination(doc, pages, dict.get(b"D").ok()?),
+        _ => None,
+    }
+}
+
+fn explicit_destination(
+    doc: &Document,
+    pages: &HashMap<ObjectId, i32>,
+    array: &[Object],
+) -> Option<Destination> {
+    let page_id = array.first()?.as_reference().ok();
+    let page = match page_id {
+        Some(id) => *pages.get(&id)?,
+        // Remote destinations use a page number instead of a reference
+        None => array.first()?.as_i64().ok()? as i32,
+    };
+
+    let arg = |i: usize| {
+        array
+            .get(i)
+            .and_then(|v| objects::number(objects::resolve(doc, v)))
+    };
+    let kind = array
+        .get(1)
+        .and_then(|v| v.as_name().ok())
+        .unwrap_or(b"Fit");
+    let (left, top, zoom) = match kind {
+        b"XYZ" => (arg(2), arg(3), arg(4).filter(|z| *z > 0.0)),
+        b"FitH" | b"FitBH" => (None, arg(2), None),
+        b"FitV" | b"FitBV" => (arg(2), None, None),
+        b"FitR" => (arg(2), arg(5), None),
+        _ => (None, None, None),
+    };
+
+    let Some(page_id) = page_id else {
+        // Coordinates of remote pages can't be converted without the other file
+        return Some(Destination {
+            page,
+            left,
+            top,
+            zoom,
+        });
+    };
+
+    // Convert from PDF user space to the page space used by the renderer. On
+    // sideways pages the on-screen top comes from the user space x coordinate.
+    let space = objects::PageSpace::new(doc, page_id);
+    let [x0, _, _, y1] = objects::page_box(doc, page_id);
+    let (x, y) = space.point(left.unwrap_or(x0), top.unwrap_or(y1));
+    let (left, top) = if space.is_sideways() {
+        (top.map(|_| x), left.map(|_| y))
+    } else {
+        (left.map(|_| x), top.map(|_| y))
+    };
+    Some(Destination {
+        page,
+        left,
+        top,
+        zoom,
+    })
+}
+
+/// Finds a named destination in the catalog's /Dests dictionary or the
+/// /Names /Dests name tree.
+pub fn lookup_named_destination<'a>(doc: &'a Document, name: &[u8]) -> Option<&'a Object> {
+    let catalog = doc.catalog().ok()?;
+    if let Some(dests) = objects::get_dict(doc, catalog, b"Dests") {
+        if let Some(dest) = objects::get(doc, dests, name) {
+            return Some(dest);
+        }
+    }
+    let tree = objects::get_dict(doc, catalog, b"Names")
+        .and_then(|names| objects::get_dict(doc, names, b"Dests"))?;
+    lookup_name_tree(doc, tree, name, 0)
+}
+
+/// Looks up `key` in a name tree (used for destinations and embedded files).
+pub fn lookup_name_tree<'a>(
+    doc: &'a Document,
+    node: &'a Dictionary,
+    key: &[u8],
+    depth: usize,
+) -> Option<&'a Object> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    if let Some(names) = objects::get_array(doc, node, b"Names") {
+        for pair in names.chunks(2) {
+            if let [name, value] = pair {
+                if objects::resolve(doc, name).as_str().ok() == Some(key) {
+                    return Some(objects::resolve(doc, value));
+                }
+            }
+        }
+    }
+    for kid in objects::get_array(doc, node, b"Kids").unwrap_or(&[]) {
+        let Ok(kid) = objects::resolve(doc, kid).as_dict() else {
+            continue;
+        };
+        // Skip subtrees whose /Limits rule the key out
+        if let Some([low, high]) = objects::get_array(doc, kid, b"Limits") {
+            let low = objects::resolve(doc, low).as_str().unwrap_or(b"");
+            let high = objects::resolve(doc, high).as_str().unwrap_or(b"");
+            if key < low || key > high {
+                continue;
+            }
+        }
+        if let Some(found) = lookup_name_tree(doc, kid, key, depth + 1) {
+            return Some(found);
+        }
+    }
+    None
+}
+
+/// Calls `f` with every entry of a name tree, in key order.
+pub fn walk_name_tree<'a>(
+    doc: &'a Document,
+    node: &'a Dictionary,
+    depth: usize,
+    f: &mut impl FnMut(&'a [u8], &'a Object),
+) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+    if let Some(names) = objects::get_array(doc, node, b"Names") {
+        for pair in names.chunks(2) {
+            if let [name, value] = pair {
+                if let Ok(name) = objects::resolve(doc, name).as_str() {
+                    f(name, objects::resolve(doc, value));
+                }
+            }
+        }
+    }
+    for kid in objects::get_array(doc, node, b"Kids").unwrap_or(&[]) {
+        if let Ok(kid) = objects::resolve(doc, kid).as_dict() {
+            walk_name_tree(doc, kid, depth + 1, f);
+        }
+    }
+}
+
+fn read_items(
+    doc: &Document,
+    pages: &HashMap<ObjectId, i32>,
+    first: Option<ObjectId>,
+    visited: &mut HashSet<ObjectId>,
+    depth: usize,
+) -> Vec<OutlineItem> {
+    let mut items = Vec::new();
+    let mut next = first;
+    while let Some(id) = next {
+        if depth > MAX_DEPTH || !visited.insert(id) {
+            break;
+        }
+        let Ok(item) = doc.get_dictionary(id) else {
+            break;
+        };
+
+        let mut destination = item
+            .get(b"Dest")
+            .ok()
+            .and_then(|dest| resolve_destination(doc, pages, dest));
+        let mut uri = None;
+        if let Some(action) = objects::get_dict(doc, item, b"A") {
+            match objects::get_name(doc, action, b"S") {
+                Some(b"GoTo") => {
+                    destination = action
+                        .get(b"D")
+                        .ok()
+                        .and_then(|dest| resolve_destination(doc, pages, dest));
+                }
+                Some(b"URI") => uri = objects::get_text(doc, action, b"URI"),
+                _ => {}
+            }
+        }
+
+        let children_first = item.get(b"First").and_then(Object::as_reference).ok();
+        items.push(OutlineItem {
+            title: objects::get_text(doc, item, b"Title").unwrap_or_default(),
+            destination,
+            uri,
+            // A positive /Count means the item is shown expanded
+            open: objects::get(doc, item, b"Count")
+                .and_then(|c| c.as_i64().ok())
+                .is_some_and(|c| c > 0),
+            children: read_items(doc, pages, children_first, visited, depth + 1),
+        });
+        next = item.get(b"Next").and_then(Object::as_reference).ok();
+    }
+    items
+}
+
+/// Reads the outline tree from a parsed document.
+pub fn read_outline(doc: &Document) -> Vec<OutlineItem> {
+    let first = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| objects::get_dict(doc, catalog, b"Outlines"))
+        .and_then(|outlines| outlines.get(b"First").and_then(Object::as_reference).ok());
+    let pages = objects::page_indices(doc);
+    read_items(doc, &pages, first, &mut HashSet::new(), 0)
+}
+
+/// Returns the document outline as a nested list. Empty if there is none.
+#[tauri::command]
+pub async fn get_outline(
+    doc_id: DocId,
+    store: State<'_, DocumentStore>,
+) -> Result<Vec<OutlineItem>, String> {
+    let doc = store.get(doc_id)?;
+    blocking(move || doc.with_objects(|objects| Ok(read_outline(objects)))).await
+}
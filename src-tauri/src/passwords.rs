@@ -0,0 +1,396 @@
+// Document passwords remembered in the operating system's password store.
+//
+// Saving a password is up to the user, when unlocking a document. It is kept
+// under the document's fingerprint, so a renamed or moved file still finds
+// it, and a changed one doesn't. The stores are the macOS Keychain (through
+// keychain.m), the Windows Credential Manager and, elsewhere, the Secret
+// Service of the desktop (GNOME Keyring, KWallet) over D-Bus.
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::ffi::{c_char, c_void, CString};
+
+    // errSecItemNotFound
+    const ITEM_NOT_FOUND: i32 = -25300;
+
+    extern "C" {
+        fn keychain_password_find(
+            account: *const c_char,
+            password: *mut *mut u8,
+            password_len: *mut usize,
+        ) -> i32;
+        fn keychain_password_save(
+            account: *const c_char,
+            label: *const c_char,
+            password: *const u8,
+            password_len: usize,
+        ) -> i32;
+        fn keychain_password_delete(account: *const c_char) -> i32;
+        fn keychain_free(buffer: *mut c_void);
+    }
+
+    fn c_string(text: &str) -> CString {
+        CString::new(text.replace('\0', "")).unwrap_or_default()
+    }
+
+    pub fn load(fingerprint: &str) -> Result<Option<String>, String> {
+        let account = c_string(fingerprint);
+        let mut password = std::ptr::null_mut();
+        let mut password_len = 0;
+        let status =
+            unsafe { keychain_password_find(account.as_ptr(), &mut password, &mut password_len) };
+        match status {
+            0 => {}
+            ITEM_NOT_FOUND => return Ok(None),
+            status => return Err(format!("Failed to read the keychain (error {})", status)),
+        }
+        let bytes = unsafe { std::slice::from_raw_parts(password, password_len) }.to_vec();
+        unsafe { keychain_free(password as *mut c_void) };
+        Ok(Some(String::from_utf8_lossy(&bytes).into_owned()))
+    }
+
+    pub fn save(fingerprint: &str, label: &str, password: &str) -> Result<(), String> {
+        let (account, label) = (c_string(fingerprint), c_string(label));
+        let status = unsafe {
+            keychain_password_save(
+                account.as_ptr(),
+                label.as_ptr(),
+                password.as_ptr(),
+                password.len(),
+            )
+        };
+        match status {
+            0 => Ok(()),
+            status => Err(format!("Failed to save to the keychain (error {})", status)),
+        }
+    }
+
+    pub fn delete(fingerprint: &str) -> Result<(), String> {
+        let account = c_string(fingerprint);
+        match unsafe { keychain_password_delete(account.as_ptr()) } {
+            0 | ITEM_NOT_FOUND => Ok(()),
+            status => Err(format!("Failed to change the keychain (error {})", status)),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::ffi::c_void;
+    use std::ptr;
+
+    #[repr(C)]
+    struct Credential {
+        flags: u32,
+        kind: u32,
+        target_name: *const u16,
+        comment: *const u16,
+        last_written: u64,
+        blob_len: u32,
+        blob: *const u8,
+        persist: u32,
+        attribute_count: u32,
+        attributes: *mut c_void,
+        target_alias: *const u16,
+        user_name: *const u16,
+    }
+
+    const CRED_TYPE_GENERIC: u32 = 1;
+    const CRED_PERSIST_LOCAL_MACHINE: u32 = 2;
+    const ERROR_NOT_FOUND: i32 = 1168;
+
+    #[link(name = "advapi32")]
+    extern "system" {
+        fn CredReadW(
+            target: *const u16,
+            kind: u32,
+            flags: u32,
+            credential: *mut *mut Credential,
+        ) -> i32;
+        fn CredWriteW(credential: *const Credential, flags: u32) -> i32;
+        fn CredDeleteW(target: *const u16, kind: u32, flags: u32) -> i32;
+        fn CredFree(buffer: *mut c_void);
+    }
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(Some(0)).collect()
+    }
+
+    fn target(fingerprint: &str) -> Vec<u16> {
+        wide(&format!("Nanodoc/{}", fingerprint))
+    }
+
+    fn last_error() -> i32 {
+        std::io::Error::last_os_error().raw_os_error().unwrap_or(0)
+    }
+
+    pub fn load(fingerprint: &str) -> Result<Option<String>, String> {
+        let target = target(fingerprint);
+        let mut credential = ptr::null_mut();
+        if unsafe { CredReadW(target.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential) } == 0 {
+            return match last_error() {
+                ERROR_NOT_FOUND => Ok(None),
+                error => Err(format!(
+                    "Failed to read the Credential Manager (error {})",
+                    error
+                )),
+            };
+        }
+        // Saved as UTF-16, as Windows does for passwords
+        let units: Vec<u16> = unsafe {
+            std::slice::from_raw_parts((*credential).blob, (*credential).blob_len as usize)
+        }
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+        unsafe { CredFree(credential as *mut c_void) };
+        Ok(Some(String::from_utf16_lossy(&units)))
+    }
+
+    pub fn save(fingerprint: &str, label: &str, password: &str) -> Result<(), String> {
+        let target = target(fingerprint);
+        let label = wide(label);
+        let blob: Vec<u8> = password
+            .encode_utf16()
+            .flat_map(|unit| unit.to_le_bytes())
+            .collect();
+        let credential = Credential {
+            flags: 0,
+            kind: CRED_TYPE_GENERIC,
+            target_name: target.as_ptr(),
+            comment: ptr::null(),
+            last_written: 0,
+            blob_len: blob.len() as u32,
+            blob: blob.as_ptr(),
+            persist: CRED_PERSIST_LOCAL_MACHINE,
+            attribute_count: 0,
+            attributes: ptr::null_mut(),
+            target_alias: ptr::null(),
+            user_name: label.as_ptr(),
+        };
+        if unsafe { CredWriteW(&credential, 0) } == 0 {
+            return Err(format!(
+                "Failed to save to the Credential Manager (error {})",
+                last_error()
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn delete(fingerprint: &str) -> Result<(), String> {
+        let target = target(fingerprint);
+        if unsafe { CredDeleteW(target.as_ptr(), CRED_TYPE_GENERIC, 0) } == 0 {
+            return match last_error() {
+                ERROR_NOT_FOUND => Ok(()),
+                error => Err(format!(
+                    "Failed to change the Credential Manager (error {})",
+                    error
+                )),
+            };
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use dbus::arg::{PropMap, RefArg, Variant};
+    use dbus::blocking::{Connection, Proxy};
+    use dbus::message::MatchRule;
+    use dbus::Path;
+
+    const SERVICE: &str = "org.freedesktop.secrets";
+    const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+    const SERVICE_INTERFACE: &str = "org.freedesktop.Secret.Service";
+    const COLLECTION_INTERFACE: &str = "org.freedesktop.Secret.Collection";
+    const ITEM_INTERFACE: &str = "org.freedesktop.Secret.Item";
+    const PROMPT_INTERFACE: &str = "org.freedesktop.Secret.Prompt";
+    const TIMEOUT: Duration = Duration::from_secs(10);
+    // How long the user has to answer a prompt, such as to unlock the keyring
+    const PROMPT_TIMEOUT: Duration = Duration::from_secs(120);
+
+    // A secret as the service sends it: session, parameters, value, type
+    type Secret = (Path<'static>, Vec<u8>, Vec<u8>, String);
+
+    fn describe(error: dbus::Error) -> String {
+        format!(
+            "Failed to reach the desktop's password store: {}",
+            error.message().unwrap_or("unknown error")
+        )
+    }
+
+    struct SecretService {
+        connection: Connection,
+        session: Path<'static>,
+    }
+
+    impl SecretService {
+        fn connect() -> Result<Self, String> {
+            let connection = Connection::new_session().map_err(describe)?;
+            // Secrets travel unencrypted, but only over the local session bus
+            let (_, session): (Variant<Box<dyn RefArg>>, Path<'static>) = connection
+                .with_proxy(SERVICE, SERVICE_PATH, TIMEOUT)
+                .method_call(
+                    SERVICE_INTERFACE,
+                    "OpenSession",
+                    ("plain", Variant(String::new())),
+                )
+                .map_err(describe)?;
+            Ok(SecretService {
+                connection,
+                session,
+            })
+        }
+
+        fn proxy<'a>(&'a self, path: &'a Path<'static>) -> Proxy<'a, &'a Connection> {
+            self.connection.with_proxy(SERVICE, path.clone(), TIMEOUT)
+        }
+
+        // Shows a prompt the service asked for and waits for the user's answer.
+        // Returns false if the prompt was dismissed.
+        fn prompt(&self, prompt: Path<'static>) -> Result<bool, String> {
+            if &*prompt == "/" {
+                return Ok(true);
+            }
+            let answer = Arc::new(Mutex::new(None));
+            let seen = answer.clone();
+            let rule =
+                MatchRule::new_signal(PROMPT_INTERFACE, "Completed").with_path(prompt.clone());
+            let token = self
+                .connection
+                .add_match(
+                    rule,
+                    move |(dismissed, _): (bool, Variant<Box<dyn RefArg>>), _, _| {
+                        *seen.lock().unwrap() = Some(!dismissed);
+                        false
+                    },
+                )
+                .map_err(describe)?;
+            self.proxy(&prompt)
+                .method_call::<(), _, _, _>(PROMPT_INTERFACE, "Prompt", ("",))
+                .map_err(describe)?;
+            let deadline = Instant::now() + PROMPT_TIMEOUT;
+            while answer.lock().unwrap().is_none() && Instant::now() < deadline {
+                self.connection
+                    .process(Duration::from_millis(200))
+                    .map_err(describe)?;
+            }
+            let _ = self.connection.remove_match(token);
+            let answer = answer.lock().unwrap().unwrap_or(false);
+            Ok(answer)
+        }
+
+        // Unlocks `objects`, asking the user if need be
+        fn unlock(&self, objects: Vec<Path<'static>>) -> Result<(), String> {
+            let service = Path::from(SERVICE_PATH);
+            let (_, prompt): (Vec<Path<'static>>, Path<'static>) = self
+                .proxy(&service)
+                .method_call(SERVICE_INTERFACE, "Unlock", (objects,))
+                .map_err(describe)?;
+            match self.prompt(prompt)? {
+                true => Ok(()),
+                false => Err("The password store wasn't unlocked".to_string()),
+            }
+        }
+
+        fn find(&self, fingerprint: &str) -> Result<Option<Path<'static>>, String> {
+            let service = Path::from(SERVICE_PATH);
+            let (unlocked, locked): (Vec<Path<'static>>, Vec<Path<'static>>) = self
+                .proxy(&service)
+                .method_call(SERVICE_INTERFACE, "SearchItems", (attributes(fingerprint),))
+                .map_err(describe)?;
+            if let Some(item) = unlocked.into_iter().next() {
+                return Ok(Some(item));
+            }
+            let Some(item) = locked.into_iter().next() else {
+                return Ok(None);
+            };
+            self.unlock(vec![item.clone()])?;
+            Ok(Some(item))
+        }
+    }
+
+    fn attributes(fingerprint: &str) -> HashMap<String, String> {
+        HashMap::from([
+            ("application".to_string(), "nanodoc".to_string()),
+            ("fingerprint".to_string(), fingerprint.to_string()),
+        ])
+    }
+
+    pub fn load(fingerprint: &str) -> Result<Option<String>, String> {
+        let service = SecretService::connect()?;
+        let Some(item) = service.find(fingerprint)? else {
+            return Ok(None);
+        };
+        let (secret,): (Secret,) = service
+            .proxy(&item)
+            .method_call(ITEM_INTERFACE, "GetSecret", (service.session.clone(),))
+            .map_err(describe)?;
+        Ok(Some(String::from_utf8_lossy(&secret.2).into_owned()))
+    }
+
+    pub fn save(fingerprint: &str, label: &str, password: &str) -> Result<(), String> {
+        let service = SecretService::connect()?;
+        let (collection,): (Path<'static>,) = service
+            .proxy(&Path::from(SERVICE_PATH))
+            .method_call(SERVICE_INTERFACE, "ReadAlias", ("default",))
+            .map_err(describe)?;
+        if &*collection == "/" {
+            return Err("The desktop has no password store set up".to_string());
+        }
+        service.unlock(vec![collection.clone()])?;
+
+        let mut properties = PropMap::new();
+        properties.insert(
+            format!("{}.Label", ITEM_INTERFACE),
+            Variant(Box::new(label.to_string())),
+        );
+        properties.insert(
+            format!("{}.Attributes", ITEM_INTERFACE),
+            Variant(Box::new(attributes(fingerprint))),
+        );
+        let secret: Secret = (
+            service.session.clone(),
+            Vec::new(),
+            password.as_bytes().to_vec(),
+            "text/plain".to_string(),
+        );
+        let (_, prompt): (Path<'static>, Path<'static>) = service
+            .proxy(&collection)
+            .method_call(
+                COLLECTION_INTERFACE,
+                "CreateItem",
+                (properties, secret, true),
+            )
+            .map_err(describe)?;
+        match service.prompt(prompt)? {
+            true => Ok(()),
+            false => Err("The password wasn't saved".to_string()),
+        }
+    }
+
+    pub fn delete(fingerprint: &str) -> Result<(), String> {
+        let service = SecretService::connect()?;
+        let Some(item) = service.find(fingerprint)? else {
+            return Ok(());
+        };
+        let (prompt,): (Path<'static>,) = service
+            .proxy(&item)
+            .method_call(ITEM_INTERFACE, "Delete", ())
+            .map_err(describe)?;
+        service.prompt(prompt).map(|_| ())
+    }
+}
+
+/// Forgets the password of the document with `fingerprint`, if one is saved.
+pub use platform::delete;
+/// The password saved for the document with `fingerprint`, if any.
+pub use platform::load;
+/// Saves the password of the document with `fingerprint`, replacing any
+/// saved before. `label` names the document for the user, who may come
+/// across the entry in the password store's own app.
+pub use platform::save;
@@ -0,0 +1,941 @@
+// Native PDF document handling.
+//
+// Documents opened through `open_document` are registered in the
+// `DocumentStore` and referred to by a numeric id from the frontend. The actual
+// engine work happens on the render workers (see `engine.rs`).
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::ipc::Response;
+use tauri::{AppHandle, Emitter, Manager, State, Window};
+
+use crate::colors::{self, ColorMode};
+use crate::display;
+use crate::engine::{Engine, Worker};
+use crate::jobs::{CancelToken, JobId, RenderJobs};
+use crate::loading::{
+    self, DocumentLoaded, LoadFailed, ProgressReader, HEADER_SIZE, PROGRESSIVE_MIN_SIZE,
+};
+use crate::permissions::StandardSecurity;
+use crate::recent_files;
+use crate::repair::{repair, xref_damaged};
+use crate::scan;
+use crate::scheduler::{PageKey, RenderScheduler};
+use crate::scope;
+use crate::security::{open_locked, LockedDocuments};
+use crate::tiles::TileCache;
+use crate::window_manager;
+
+pub type DocId = u32;
+
+// Files at least this big are memory-mapped instead of read
+const MMAP_MIN_SIZE: u64 = 64 * 1024 * 1024;
+// Bytes hashed from each end of a mapped file for its fingerprint
+const FINGERPRINT_SAMPLE: usize = 1024 * 1024;
+
+/// The bytes of a document: read into memory, or mapped for big files so that
+/// only the parts in use are paged in.
+pub enum DocumentData {
+    Owned(Vec<u8>),
+    Mapped(memmap2::Mmap),
+}
+
+impl DocumentData {
+    pub fn is_mapped(&self) -> bool {
+        matches!(self, DocumentData::Mapped(_))
+    }
+
+    /// SHA-256 of the contents as hex. Hashing all of a mapped file would
+    /// page it in, so big files are identified by their length and the bytes
+    /// at both ends.
+    pub fn fingerprint(&self) -> String {
+        let digest = match self {
+            DocumentData::Owned(bytes) => Sha256::digest(bytes),
+            DocumentData::Mapped(map) => {
+                let sample = FINGERPRINT_SAMPLE.min(map.len());
+                let mut hasher = Sha256::new();
+                hasher.update((map.len() as u64).to_le_bytes());
+                hasher.update(&map[..sample]);
+                hasher.update(&map[map.len() - sample..]);
+                hasher.finalize()
+            }
+        };
+        hex::encode(digest)
+    }
+}
+
+impl Deref for DocumentData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            DocumentData::Owned(bytes) => bytes,
+            DocumentData::Mapped(map) => map,
+        }
+    }
+}
+
+impl AsRef<[u8]> for DocumentData {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<Vec<u8>> for DocumentData {
+    fn from(bytes: Vec<u8>) -> Self {
+        DocumentData::Owned(bytes)
+    }
+}
+
+// Maps a big file into memory. Returns `None` for small files and where
+// mapping isn't supported, so the caller reads the file instead.
+fn map_file(path: &Path) -> Option<memmap2::Mmap> {
+    let file = File::open(path).ok()?;
+    if file.metadata().ok()?.len() < MMAP_MIN_SIZE {
+        return None;
+    }
+    // SAFETY: the map is only read. Another process truncating the file while
+    // it is open would fault, which every viewer that maps files accepts
+    unsafe { memmap2::Mmap::map(&file) }.ok()
+}
+
+/// Reads a document's bytes from disk, mapping big files.
+pub fn read_data(path: &Path) -> Result<DocumentData, String> {
+    if let Some(map) = map_file(path) {
+        return Ok(DocumentData::Mapped(map));
+    }
+    std::fs::read(path)
+        .map(DocumentData::Owned)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+}
+
+/// A document opened by the user, shared between the store and the workers.
+pub struct OpenDocument {
+    pub id: DocId,
+    pub path: PathBuf,
+    pub data: DocumentData,
+    pub page_count: i32,
+    pub modified: Option<SystemTime>,
+    /// True while only the first page of a linearized file has been read
+    pub partial: bool,
+    /// True for edited versions that haven't been saved yet
+    pub unsaved: bool,
+    /// Password the file was unlocked with, for files that need one to open
+    pub password: Option<String>,
+    fingerprint: OnceLock<String>,
+    // Encryption dictionary of the file, read along with the object tree
+    security: OnceLock<Option<StandardSecurity>>,
+    // Object tree for structural reads and edits, parsed on first use
+    objects: Mutex<Option<lopdf::Document>>,
+}
+
+impl OpenDocument {
+    pub fn new(id: DocId, path: PathBuf, data: impl Into<DocumentData>, page_count: i32) -> Self {
+        let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+        OpenDocument {
+            id,
+            path,
+            data: data.into(),
+            page_count,
+            modified,
+            partial: false,
+            unsaved: false,
+            password: None,
+            fingerprint: OnceLock::new(),
+            security: OnceLock::new(),
+            objects: Mutex::new(None),
+        }
+    }
+
+    /// Marks this as an unsaved edited version whose object tree is already
+    /// at hand.
+    pub fn edited(mut self, objects: lopdf::Document) -> Self {
+        self.unsaved = true;
+        self.parsed(objects)
+    }
+
+    /// Hands over the object tree of `data`, already parsed (and decrypted).
+    pub fn parsed(mut self, objects: lopdf::Document) -> Self {
+        self.objects = Mutex::new(Some(objects));
+        self
+    }
+
+    /// Keeps the fingerprint and modification time of the file an edited
+    /// version was made from, so what's kept for the file by its fingerprint,
+    /// like bookmarks and the reading position, still finds it.
+    pub fn made_from(mut self, original: &OpenDocument) -> Self {
+        self.fingerprint = OnceLock::from(original.fingerprint().to_string());
+        self.modified = original.modified;
+        self
+    }
+
+    /// Hands over the encryption of the file an edited version was made from,
+    /// as its own data is no longer encrypted.
+    pub fn with_security(mut self, security: Option<StandardSecurity>) -> Self {
+        self.security = OnceLock::from(security);
+        self
+    }
+
+    /// Runs `f` against the document's object tree, parsing it on first use.
+    ///
+    /// Parsing is slow for big files, so call this from `blocking`.
+    pub fn with_objects<T>(
+        &self,
+        f: impl FnOnce(&mut lopdf::Document) -> Result<T, String>,
+    ) -> Result<T, String> {
+        if self.partial {
+            return Err(format!("{} is still loading", self.path.display()));
+        }
+        let mut objects = self.objects.lock().unwrap();
+        if objects.is_none() {
+            let mut parsed = lopdf::Document::load_mem(&self.data)
+                .map_err(|e| format!("Failed to parse {}: {}", self.path.display(), e))?;
+            let _ = self.security.set(StandardSecurity::read(&parsed));
+            // Work on the plain objects; encryption is applied again on saving
+            if parsed.is_encrypted() {
+                parsed
+                    .decrypt(self.password.as_deref().unwrap_or(""))
+                    .map_err(|e| format!("Failed to decrypt {}: {}", self.path.display(), e))?;
+                parsed.trailer.remove(b"Encrypt");
+            }
+            *objects = Some(parsed);
+        }
+        f(objects.as_mut().unwrap())
+    }
+
+    /// The standard security handler's entries, for encrypted files. Reads
+    /// the object tree on first use, so call this from `blocking`.
+    pub fn security(&self) -> Result<Option<&StandardSecurity>, String> {
+        if self.security.get().is_none() {
+            self.with_objects(|_| Ok(()))?;
+        }
+        if self.security.get().is_none() {
+            // The object tree was handed over already decrypted
+            let parsed = lopdf::Document::load_mem(&self.data)
+                .map_err(|e| format!("Failed to parse {}: {}", self.path.display(), e))?;
+            let _ = self.security.set(StandardSecurity::read(&parsed));
+        }
+        Ok(self.security.get().unwrap().as_ref())
+    }
+
+    /// The fingerprint of the file contents, computed on first use.
+    pub fn fingerprint(&self) -> &str {
+        self.fingerprint.get_or_init(|| self.data.fingerprint())
+    }
+
+    /// Key for on-disk caches that must be invalidated when the file changes.
+    /// None for unsaved edited versions, which share the file's fingerprint
+    /// but not its contents.
+    pub fn cache_key(&self) -> Option<String> {
+        if self.unsaved {
+            return None;
+        }
+        let mtime = self
+            .modified
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Some(format!("{}-{}", self.fingerprint(), mtime))
+    }
+
+    /// Checks a zero-based page index against the page count.
+    pub fn check_page(&self, page: i32) -> Result<(), String> {
+        if page < 0 || page >= self.page_count {
+            return Err(format!(
+                "Page {} is out of range (document has {} pages)",
+                page, self.page_count
+            ));
+        }
+        if self.partial && page > 0 {
+            return Err(format!("Page {} is still loading", page));
+        }
+        Ok(())
+    }
+}
+
+/// All documents currently open in the backend.
+#[derive(Default)]
+pub struct DocumentStore {
+    next_id: AtomicU32,
+    docs: Mutex<HashMap<DocId, Arc<OpenDocument>>>,
+}
+
+impl DocumentStore {
+    pub fn next_id(&self) -> DocId {
+        self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn insert(&self, doc: OpenDocument) -> Arc<OpenDocument> {
+        let doc = Arc::new(doc);
+        self.docs.lock().unwrap().insert(doc.id, doc.clone());
+        doc
+    }
+
+    pub fn get(&self, id: DocId) -> Result<Arc<OpenDocument>, String> {
+        self.docs
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| format!("No open document with id {}", id))
+    }
+
+    pub fn remove(&self, id: DocId) -> Option<Arc<OpenDocument>> {
+        self.docs.lock().unwrap().remove(&id)
+    }
+
+    /// Swaps in a new version of an open document under the same id. Does
+    /// nothing if the document was closed in the meantime.
+    pub fn replace(&self, doc: OpenDocument) -> Option<Arc<OpenDocument>> {
+        let mut docs = self.docs.lock().unwrap();
+        let slot = docs.get_mut(&doc.id)?;
+        *slot = Arc::new(doc);
+        Some(slot.clone())
+    }
+
+    pub fn all(&self) -> Vec<Arc<OpenDocument>> {
+        let mut docs: Vec<_> = self.docs.lock().unwrap().values().cloned().collect();
+        docs.sort_by_key(|doc| doc.id);
+        docs
+    }
+
+    /// Finds an open document by its file path.
+    pub fn find_by_path(&self, path: &Path) -> Option<Arc<OpenDocument>> {
+        self.docs
+            .lock()
+            .unwrap()
+            .values()
+            .find(|doc| doc.path == path)
+            .cloned()
+    }
+}
+
+/// Why a document couldn't be opened.
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum OpenError {
+    /// The file is encrypted; `unlock_document` opens it with its password
+    #[serde(rename_all = "camelCase")]
+    PasswordRequired { doc_id: DocId },
+    /// The password given to `unlock_document` doesn't open the file
+    WrongPassword,
+    /// The file is missing, damaged or not a PDF
+    Failed { message: String },
+}
+
+impl From<String> for OpenError {
+    fn from(message: String) -> Self {
+        OpenError::Failed { message }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenedDocument {
+    pub doc_id: DocId,
+    pub page_count: i32,
+    /// True if only the first page is available yet; `document-loaded` is
+    /// emitted when the rest is
+    pub loading: bool,
+    /// True if the file was damaged and has been repaired in memory; saving
+    /// it with `save_document` writes the repaired copy
+    pub repaired: bool,
+}
+
+/// Axis-aligned rectangle in page space (points, origin top-left).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Bounds {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl From<mupdf::Rect> for Bounds {
+    fn from(r: mupdf::Rect) -> Self {
+        Bounds {
+            x: r.x0,
+            y: r.y0,
+            width: r.x1 - r.x0,
+            height: r.y1 - r.y0,
+        }
+    }
+}
+
+impl From<mupdf::Quad> for Bounds {
+    fn from(q: mupdf::Quad) -> Self {
+        let xs = [q.ul.x, q.ur.x, q.ll.x, q.lr.x];
+        let ys = [q.ul.y, q.ur.y, q.ll.y, q.lr.y];
+        let x0 = xs.iter().copied().fold(f32::INFINITY, f32::min);
+        let x1 = xs.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let y0 = ys.iter().copied().fold(f32::INFINITY, f32::min);
+        let y1 = ys.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        Bounds {
+            x: x0,
+            y: y0,
+            width: x1 - x0,
+            height: y1 - y0,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageSize {
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Opens a PDF and registers it in the store.
+///
+/// Big linearized files are registered as soon as their first page has been
+/// read, with `loading` set; the rest is read, or for mapped files checked,
+/// in the background (see `loading.rs`). Files with a damaged cross-reference table are read in
+/// full and repaired (see `repair.rs`). Files that need a password open with
+/// the one saved for them, if any, and otherwise fail with `PasswordRequired`
+/// and are opened with `unlock_document`.
+///
+/// Once open, each document is scanned for active content (see `scan.rs`).
+#[tauri::command]
+pub async fn open_document(
+    app: AppHandle,
+    window: Window,
+    path: String,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    locked: State<'_, LockedDocuments>,
+) -> Result<OpenedDocument, OpenError> {
+    let path = PathBuf::from(path);
+    let opened = open(&app, path.clone(), &store, &engine, &locked).await;
+    // Documents waiting for a password are the window's too
+    match &opened {
+        Ok(OpenedDocument { doc_id, .. }) | Err(OpenError::PasswordRequired { doc_id }) => {
+            window_manager::adopt(&app, window.label(), *doc_id)
+        }
+        Err(_) => {}
+    }
+    let opened = opened?;
+    recent_files::add(&app, &path);
+    // Partly loaded documents are scanned once they are complete
+    if !opened.loading {
+        scan::scan_in_background(&app, opened.doc_id);
+    }
+    Ok(opened)
+}
+
+async fn open(
+    app: &AppHandle,
+    path: PathBuf,
+    store: &DocumentStore,
+    engine: &Engine,
+    locked: &LockedDocuments,
+) -> Result<OpenedDocument, OpenError> {
+    scope::check(app, &path)?;
+    let map = map_file(&path);
+    let damaged = map.as_ref().is_some_and(|map| xref_damaged(map));
+    if let Some(map) = map.filter(|_| !damaged) {
+        // Big linearized files show their first page while the rest is checked
+        let first_page = loading::linearization(&map[..HEADER_SIZE.min(map.len())])
+            .filter(|l| l.length == map.len() as u64)
+            .map(|l| {
+                (
+                    map[..(l.first_page_end as usize).min(map.len())].to_vec(),
+                    l.page_count,
+                )
+            });
+        if let Some((data, page_count)) = first_page {
+            if let Ok(Some(_)) = validate(engine, data.clone(), None).await {
+                let mut doc = OpenDocument::new(store.next_id(), path, data, page_count);
+                doc.partial = true;
+                let doc = store.insert(doc);
+                eprintln!(
+                    "Opened first page of mapped document {} ({} pages): {}",
+                    doc.id,
+                    doc.page_count,
+                    doc.path.display()
+                );
+                let (app, doc_id, path) = (app.clone(), doc.id, doc.path.clone());
+                tauri::async_runtime::spawn(async move {
+                    if let Err(error) = finish_mapping(&app, path, map, doc_id).await {
+                        eprintln!("Failed to load document {}: {}", doc_id, error);
+                        let _ = app.emit("document-load-failed", LoadFailed { doc_id, error });
+                    }
+                });
+                return Ok(OpenedDocument {
+                    doc_id,
+                    page_count,
+                    loading: true,
+                    repaired: false,
+                });
+            }
+        }
+        let Some(page_count) = validate_file(engine, path.clone(), None)
+            .await
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?
+        else {
+            return open_locked(path, DocumentData::Mapped(map), store, engine, locked).await;
+        };
+        let doc = store.insert(OpenDocument::new(
+            store.next_id(),
+            path,
+            DocumentData::Mapped(map),
+            page_count,
+        ));
+        eprintln!(
+            "Mapped document {} ({} pages): {}",
+            doc.id,
+            doc.page_count,
+            doc.path.display()
+        );
+        return Ok(OpenedDocument {
+            doc_id: doc.id,
+            page_count,
+            loading: false,
+            repaired: false,
+        });
+    }
+
+    let mut reader = {
+        let (app, path) = (app.clone(), path.clone());
+        blocking(move || ProgressReader::open(app, path)).await?
+    };
+
+    let first_page = reader
+        .linearization
+        .as_ref()
+        .filter(|_| reader.total >= PROGRESSIVE_MIN_SIZE && !damaged)
+        .map(|l| (l.first_page_end, l.page_count));
+    if let Some((end, page_count)) = first_page {
+        reader = blocking(move || {
+            reader.read_to(end, None)?;
+            Ok(reader)
+        })
+        .await?;
+        // If the first page doesn't open on its own, read the whole file
+        if let Ok(Some(_)) = validate(engine, reader.data.clone(), None).await {
+            let mut doc = OpenDocument::new(store.next_id(), path, reader.data.clone(), page_count);
+            doc.partial = true;
+            let doc = store.insert(doc);
+            eprintln!(
+                "Opened first page of document {} ({} pages): {}",
+                doc.id,
+                doc.page_count,
+                doc.path.display()
+            );
+
+            let (app, doc_id) = (app.clone(), doc.id);
+            tauri::async_runtime::spawn(async move {
+                if let Err(error) = finish_loading(&app, reader, doc_id).await {
+                    eprintln!("Failed to load document {}: {}", doc_id, error);
+                    let _ = app.emit("document-load-failed", LoadFailed { doc_id, error });
+                }
+            });
+            return Ok(OpenedDocument {
+                doc_id,
+                page_count,
+                loading: true,
+                repaired: false,
+            });
+        }
+    }
+
+    let reader = blocking(move || {
+        reader.read_all(None)?;
+        Ok(reader)
+    })
+    .await?;
+    let mut data = reader.data;
+    let mut repaired = false;
+    if damaged || xref_damaged(&data) {
+        match repair(engine, &data).await {
+            Ok(fixed) => {
+                data = fixed;
+                repaired = true;
+            }
+            Err(e) => eprintln!("Failed to repair {}: {}", path.display(), e),
+        }
+    }
+    let Some(page_count) = validate(engine, data.clone(), None)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?
+    else {
+        return open_locked(path, data.into(), store, engine, locked).await;
+    };
+
+    let mut doc = OpenDocument::new(store.next_id(), path, data, page_count);
+    doc.unsaved = repaired;
+    let doc = store.insert(doc);
+    eprintln!(
+        "Opened {}document {} ({} pages): {}",
+        if repaired { "repaired " } else { "" },
+        doc.id,
+        doc.page_count,
+        doc.path.display()
+    );
+
+    Ok(OpenedDocument {
+        doc_id: doc.id,
+        page_count,
+        loading: false,
+        repaired,
+    })
+}
+
+/// Unlocks an engine handle with `password` if the file needs one. Returns
+/// false if it stays locked.
+pub fn unlock(doc: &mut mupdf::Document, password: Option<&str>) -> Result<bool, String> {
+    if !doc.needs_password().map_err(|e| e.to_string())? {
+        return Ok(true);
+    }
+    match password {
+        Some(password) => doc.authenticate(password).map_err(|e| e.to_string()),
+        None => Ok(false),
+    }
+}
+
+// Checks that a document is usable, returning its page count, or `None` if
+// it needs a password other than `password`
+fn check_document(mut doc: mupdf::Document, password: Option<&str>) -> Result<Option<i32>, String> {
+    if !unlock(&mut doc, password)? {
+        return Ok(None);
+    }
+    let page_count = doc.page_count().map_err(|e| e.to_string())?;
+    if page_count > 0 {
+        doc.load_page(0).map_err(|e| e.to_string())?;
+    }
+    Ok(Some(page_count))
+}
+
+/// Opens the bytes on a worker to check that they are a usable PDF,
+/// returning the page count as `check_document` does.
+pub async fn validate(
+    engine: &Engine,
+    bytes: Vec<u8>,
+    password: Option<String>,
+) -> Result<Option<i32>, String> {
+    engine
+        .run(move |_| {
+            let doc = mupdf::Document::from_bytes(&bytes, "application/pdf")
+                .map_err(|e| e.to_string())?;
+            check_document(doc, password.as_deref())
+        })
+        .await
+}
+
+/// Like `validate`, for a file mupdf reads on demand.
+pub async fn validate_file(
+    engine: &Engine,
+    path: PathBuf,
+    password: Option<String>,
+) -> Result<Option<i32>, String> {
+    engine
+        .run(move |_| {
+            let doc = mupdf::Document::open(&path.to_string_lossy()).map_err(|e| e.to_string())?;
+            check_document(doc, password.as_deref())
+        })
+        .await
+}
+
+// Reads the rest of a partially opened document and swaps in the full version
+async fn finish_loading(
+    app: &AppHandle,
+    reader: ProgressReader,
+    doc_id: DocId,
+) -> Result<(), String> {
+    let reader = blocking(move || {
+        let mut reader = reader;
+        reader.read_all(Some(doc_id))?;
+        Ok(reader)
+    })
+    .await?;
+    let page_count = validate(&app.state::<Engine>(), reader.data.clone(), None)
+        .await?
+        .ok_or("The rest of the file needs a password")?;
+
+    loaded(
+        app,
+        OpenDocument::new(doc_id, reader.path, reader.data, page_count),
+    );
+    Ok(())
+}
+
+// Checks the whole of a mapped document whose first page is open, and swaps
+// in the full version
+async fn finish_mapping(
+    app: &AppHandle,
+    path: PathBuf,
+    map: memmap2::Mmap,
+    doc_id: DocId,
+) -> Result<(), String> {
+    let page_count = validate_file(&app.state::<Engine>(), path.clone(), None)
+        .await?
+        .ok_or("The rest of the file needs a password")?;
+    loaded(
+        app,
+        OpenDocument::new(doc_id, path, DocumentData::Mapped(map), page_count),
+    );
+    Ok(())
+}
+
+// Replaces a partly loaded document with its full version
+fn loaded(app: &AppHandle, doc: OpenDocument) {
+    let (doc_id, page_count) = (doc.id, doc.page_count);
+    if app.state::<DocumentStore>().replace(doc).is_none() {
+        // Closed while loading
+        return;
+    }
+    app.state::<TileCache>().clear_document(doc_id);
+    app.state::<RenderScheduler>().clear_document(doc_id);
+    eprintln!("Finished loading document {}", doc_id);
+    let _ = app.emit("document-loaded", DocumentLoaded { doc_id, page_count });
+    scan::scan_in_background(app, doc_id);
+}
+
+/// Forgets an open document, or one waiting for a password, and what was
+/// rendered of it.
+pub fn discard_document(app: &AppHandle, doc_id: DocId) {
+    // Workers drop their own handles once the last reference is gone
+    app.state::<DocumentStore>().remove(doc_id);
+    app.state::<LockedDocuments>().take(doc_id);
+    app.state::<TileCache>().clear_document(doc_id);
+    app.state::<RenderScheduler>().clear_document(doc_id);
+}
+
+#[tauri::command]
+pub async fn close_document(app: AppHandle, doc_id: DocId) -> Result<(), String> {
+    discard_document(&app, doc_id);
+    window_manager::release(&app, doc_id);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_page_size(
+    doc_id: DocId,
+    page: i32,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+) -> Result<PageSize, String> {
+    let doc = store.get(doc_id)?;
+    doc.check_page(page)?;
+    engine
+        .run(move |worker| {
+            let bounds = worker
+                .document(&doc)?
+                .load_page(page)
+                .and_then(|p| p.bounds())
+                .map_err(|e| e.to_string())?;
+            Ok(PageSize {
+                width: bounds.width(),
+                height: bounds.height(),
+            })
+        })
+        .await
+}
+
+/// Renders a page at `scale` CSS pixels per point (1.0 = 72 dpi).
+///
+/// The bitmap is rendered at `scale` times `device_pixel_ratio`, which
+/// defaults to the scale factor of the calling window, so it is sharp on HiDPI
+/// screens. `page-ready` events report this effective scale.
+///
+/// The response is a raw byte buffer: width and height as little-endian `u32`s
+/// followed by the RGBA pixels, so the frontend can wrap it in `ImageData`
+/// without a JSON round trip. After each render the neighbouring pages are
+/// prerendered in the background.
+///
+/// Passing a `job_id` allows the render to be aborted with `cancel_render`.
+/// `color_mode` selects night mode rendering (see `colors.rs`).
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn render_page(
+    app: AppHandle,
+    window: Window,
+    doc_id: DocId,
+    page: i32,
+    scale: f32,
+    job_id: Option<JobId>,
+    color_mode: Option<ColorMode>,
+    device_pixel_ratio: Option<f32>,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    scheduler: State<'_, RenderScheduler>,
+    jobs: State<'_, RenderJobs>,
+) -> Result<Response, String> {
+    let doc = store.get(doc_id)?;
+    doc.check_page(page)?;
+    let scale = scale * display::device_pixel_ratio(&window, device_pixel_ratio);
+    if !(scale > 0.0 && scale <= 32.0) {
+        return Err(format!("Invalid render scale {}", scale));
+    }
+
+    let mode = color_mode.unwrap_or_default();
+    let key = PageKey::new(doc_id, page, scale, mode);
+    let bitmap = match scheduler.cached(&key) {
+        Some(bitmap) => bitmap,
+        None => {
+            let token = jobs.begin(job_id, doc_id, page);
+            let target = doc.clone();
+            let cancel = token.clone();
+            let result = engine
+                .run(move |worker| {
+                    cancel.check()?;
+                    rasterize_page(worker, &target, page, scale, mode, &cancel)
+                })
+                .await;
+            jobs.finish(job_id, doc_id, page, &token);
+
+            let bitmap = result?;
+            scheduler.store(key, bitmap.clone());
+            bitmap
+        }
+    };
+
+    if !doc.partial {
+        scheduler.prerender_around(&app, &engine, &doc, page, scale, mode);
+    }
+    Ok(Response::new(bitmap))
+}
+
+// Rows rasterized between cancellation checks
+const RENDER_BAND_HEIGHT: i32 = 256;
+
+/// Renders a whole page into the `[width][height][rgba...]` layout.
+///
+/// The page is drawn in horizontal bands so that a cancelled job stops
+/// without finishing the rest of the page.
+pub fn rasterize_page(
+    worker: &mut Worker,
+    doc: &Arc<OpenDocument>,
+    page: i32,
+    scale: f32,
+    mode: ColorMode,
+    cancel: &CancelToken,
+) -> Result<Vec<u8>, String> {
+    let list = worker.display_list(doc, page)?;
+    let ctm = mupdf::Matrix::new_scale(scale, scale);
+    let area = list.bounds().transform(&ctm).round();
+
+    let mut pixmap = mupdf::Pixmap::new_with_rect(&mupdf::Colorspace::device_rgb(), area, false)
+        .map_err(|e| e.to_string())?;
+    pixmap.clear_with(255).map_err(|e| e.to_string())?;
+
+    let mut y = area.y0;
+    while y < area.y1 {
+        cancel.check()?;
+        let band = mupdf::IRect::new(area.x0, y, area.x1, (y + RENDER_BAND_HEIGHT).min(area.y1));
+        let device =
+            mupdf::Device::from_pixmap_with_clip(&pixmap, band).map_err(|e| e.to_string())?;
+        list.run(
+            &device,
+            &ctm,
+            mupdf::Rect::new(
+                band.x0 as f32,
+                band.y0 as f32,
+                band.x1 as f32,
+                band.y1 as f32,
+            ),
+        )
+        .map_err(|e| e.to_string())?;
+        drop(device);
+        y = band.y1;
+    }
+
+    cancel.check()?;
+    colors::apply(mode, list, &ctm, &mut pixmap).map_err(|e| e.to_string())?;
+    Ok(encode_bitmap(&pixmap))
+}
+
+/// Runs blocking work (file IO, object tree parsing) off the async runtime.
+pub async fn blocking<T, F>(f: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+{
+    tauri::async_runtime::spawn_blocking(f)
+        .await
+        .map_err(|e| format!("Background task failed: {}", e))?
+}
+
+/// Render scale in thousandths, for use in cache keys.
+pub fn zoom_key(scale: f32) -> u32 {
+    (scale * 1000.0).round() as u32
+}
+
+/// Packs a pixmap into the `[width][height][rgba...]` layout used by the
+/// render commands.
+pub fn encode_bitmap(pixmap: &mupdf::Pixmap) -> Vec<u8> {
+    let width = pixmap.width();
+    let height = pixmap.height();
+    let mut out = Vec::with_capacity(8 + (width * height * 4) as usize);
+    out.extend_from_slice(&width.to_le_bytes());
+    out.extend_from_slice(&height.to_le_bytes());
+    append_rgba(pixmap, &mut out);
+    out
+}
+
+/// Splits a bitmap in the `[width][height][rgba...]` layout into its width,
+/// height and pixels.
+pub fn decode_bitmap(bitmap: &[u8]) -> (u32, u32, &[u8]) {
+    let width = u32::from_le_bytes(bitmap[0..4].try_into().unwrap());
+    let height = u32::from_le_bytes(bitmap[4..8].try_into().unwrap());
+    (width, height, &bitmap[8..])
+}
+
+/// Appends the pixmap samples as tightly packed RGBA rows.
+pub fn append_rgba(pixmap: &mupdf::Pixmap, out: &mut Vec<u8>) {
+    let n = pixmap.n() as usize;
+    let stride = pixmap.stride() as usize;
+    let row_len = pixmap.width() as usize * n;
+    let samples = pixmap.samples();
+
+    for row in samples.chunks(stride).take(pixmap.height() as usize) {
+        for px in row[..row_len].chunks_exact(n) {
+            match n {
+                4 => out.extend_from_slice(px),
+                3 => out.extend_from_slice(&[px[0], px[1], px[2], 255]),
+                _ => out.extend_from_slice(&[px[0], px[0], px[0], 255]),
+            }
+        }
+    }
+}
+
+/// Encodes a pixmap as PNG.
+pub fn encode_png(pixmap: &mupdf::Pixmap) -> Result<Vec<u8>, String> {
+    let n = pixmap.n() as usize;
+    let color = match n {
+        1 => png::ColorType::Grayscale,
+        2 => png::ColorType::GrayscaleAlpha,
+        3 => png::ColorType::Rgb,
+        4 => png::ColorType::Rgba,
+        _ => return Err(format!("Unsupported pixmap with {} components", n)),
+    };
+
+    let stride = pixmap.stride() as usize;
+    let row_len = pixmap.width() as usize * n;
+    let mut pixels = Vec::with_capacity(row_len * pixmap.height() as usize);
+    for row in pixmap
+        .samples()
+        .chunks(stride)
+        .take(pixmap.height() as usize)
+    {
+        pixels.extend_from_slice(&row[..row_len]);
+    }
+
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, pixmap.width(), pixmap.height());
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    Ok(out)
+}
@@ -0,0 +1,1060 @@
+// PDF/A preflight and conversion.
+//
+// The preflight checks the requirements of PDF/A-1b and PDF/A-2b that can be
+// decided from the object tree: embedded fonts, transparency (1b only),
+// forbidden filters, actions and annotation types, output intents and the
+// XMP identification. It doesn't parse content streams, so things like colour
+// spaces used without a matching output intent aren't caught; a dedicated
+// validator is still the final word.
+//
+// Conversion fixes what can be fixed mechanically and runs the preflight again
+// on the result, so what is left over is reported rather than hidden. Fonts
+// are only embedded when a TrueType font with the same PostScript name is
+// installed; transparency is removed by making everything opaque, which can
+// change how pages look.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+
+use crate::compose::{write_document, WrittenDocument};
+use crate::metadata::{document_info, read_xmp, xmp_packet};
+use crate::objects;
+use crate::pdf::{blocking, DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scope;
+
+// Annotation flags (PDF 32000-1, 12.5.3)
+const INVISIBLE: i64 = 1;
+const HIDDEN: i64 = 1 << 1;
+const PRINT: i64 = 1 << 2;
+const NO_VIEW: i64 = 1 << 5;
+// Larger `name` tables than this aren't read, as their offsets are 16 bits
+const MAX_NAME_TABLE: u32 = 1 << 20;
+
+// Rule names and what they require
+type Rule = (&'static str, &'static str);
+const ENCRYPTED: Rule = ("encryption", "Files must not be encrypted");
+const FILE_ID: Rule = ("file-id", "The trailer must have a file identifier");
+const NO_XMP: Rule = ("xmp-missing", "The document must have XMP metadata");
+const XMP_ID: Rule = (
+    "xmp-identification",
+    "The XMP metadata must identify the PDF/A part and conformance level",
+);
+const OUTPUT_INTENT: Rule = (
+    "output-intent",
+    "The document must have a PDF/A output intent with an ICC profile",
+);
+const FONT_NOT_EMBEDDED: Rule = ("font-not-embedded", "Fonts must be embedded");
+const TRANSPARENCY: Rule = ("transparency", "PDF/A-1 doesn't allow transparency");
+const LZW: Rule = ("lzw", "Streams must not use LZW compression");
+const JPX: Rule = ("jpx", "PDF/A-1 doesn't allow JPEG 2000 images");
+const IMAGE_ENTRIES: Rule = (
+    "image-entries",
+    "Images must not have /Interpolate true, /Alternates or /OPI",
+);
+const XOBJECT_TYPE: Rule = (
+    "xobject-type",
+    "PostScript and reference XObjects are not allowed",
+);
+const TRANSFER: Rule = (
+    "transfer-function",
+    "Graphics states must not use transfer functions",
+);
+const JAVASCRIPT: Rule = ("javascript", "JavaScript is not allowed");
+const ACTION: Rule = (
+    "forbidden-action",
+    "Launch, sound, movie, reset and import actions are not allowed",
+);
+const ADDITIONAL_ACTIONS: Rule = (
+    "additional-actions",
+    "Additional actions (/AA) are not allowed on the catalog, pages or form fields",
+);
+const EMBEDDED_FILES: Rule = ("embedded-files", "PDF/A-1 doesn't allow embedded files");
+const OPTIONAL_CONTENT: Rule = ("optional-content", "PDF/A-1 doesn't allow layers");
+const NEED_APPEARANCES: Rule = (
+    "need-appearances",
+    "Forms must not ask viewers to generate appearances",
+);
+const XFA: Rule = ("xfa", "XFA forms are not allowed");
+const ANNOTATION_TYPE: Rule = (
+    "annotation-type",
+    "Sound, movie, screen and 3D annotations are not allowed (nor file attachments in PDF/A-1)",
+);
+const ANNOTATION_FLAGS: Rule = (
+    "annotation-flags",
+    "Annotations must be printable and must not be hidden",
+);
+const ANNOTATION_APPEARANCE: Rule = (
+    "annotation-appearance",
+    "Annotations must have an appearance stream",
+);
+
+// Actions no PDF/A part allows (ISO 19005-1, 6.6.1; ISO 19005-2, 6.6.1)
+const FORBIDDEN_ACTIONS: [&[u8]; 6] = [
+    b"Launch",
+    b"Sound",
+    b"Movie",
+    b"ResetForm",
+    b"ImportData",
+    b"JavaScript",
+];
+// Details kept per violation, to keep reports readable
+const MAX_DETAILS: usize = 20;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub enum PdfALevel {
+    #[serde(rename = "1b")]
+    A1b,
+    #[serde(rename = "2b")]
+    A2b,
+}
+
+impl PdfALevel {
+    fn part(self) -> &'static str {
+        match self {
+            PdfALevel::A1b => "1",
+            PdfALevel::A2b => "2",
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Violation {
+    pub rule: &'static str,
+    pub description: &'static str,
+    /// E.g. the names of fonts that aren't embedded
+    pub details: Vec<String>,
+    pub pages: Vec<i32>,
+    pub count: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreflightReport {
+    pub level: PdfALevel,
+    pub compliant: bool,
+    /// What the XMP metadata claims, e.g. "PDF/A-2B"
+    pub claimed: Option<String>,
+    pub violations: Vec<Violation>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversionReport {
+    pub document: WrittenDocument,
+    /// What was changed, in words
+    pub changes: Vec<String>,
+    /// Preflight of the converted file
+    pub remaining: PreflightReport,
+}
+
+struct Findings {
+    level: PdfALevel,
+    violations: Vec<Violation>,
+}
+
+impl Findings {
+    fn add(&mut self, rule: Rule, detail: Option<String>, page: Option<i32>) {
+        let index = match self.violations.iter().position(|v| v.rule == rule.0) {
+            Some(index) => index,
+            None => {
+                self.violations.push(Violation {
+                    rule: rule.0,
+                    description: rule.1,
+                    details: Vec::new(),
+                    pages: Vec::new(),
+                    count: 0,
+                });
+                self.violations.len() - 1
+            }
+        };
+        let violation = &mut self.violations[index];
+        violation.count += 1;
+        if let Some(detail) = detail {
+            if violation.details.len() < MAX_DETAILS && !violation.details.contains(&detail) {
+                violation.details.push(detail);
+            }
+        }
+        if let Some(page) = page {
+            if !violation.pages.contains(&page) {
+                violation.pages.push(page);
+            }
+        }
+    }
+
+    fn is_1b(&self) -> bool {
+        self.level == PdfALevel::A1b
+    }
+}
+
+fn dict_of(object: &Object) -> Option<&Dictionary> {
+    match object {
+        Object::Dictionary(dict) => Some(dict),
+        Object::Stream(stream) => Some(&stream.dict),
+        _ => None,
+    }
+}
+
+fn number_in(doc: &Document, dict: &Dictionary, key: &[u8]) -> Option<f32> {
+    objects::get(doc, dict, key).and_then(objects::number)
+}
+
+fn is_font(doc: &Document, dict: &Dictionary) -> bool {
+    objects::get_name(doc, dict, b"Type") == Some(b"Font")
+}
+
+// Fonts that need a font program embedded. Type 3 fonts are drawn by content
+// streams, and Type 0 fonts embed theirs in the descendant font.
+fn needs_font_file(doc: &Document, font: &Dictionary) -> bool {
+    if !is_font(doc, font) {
+        return false;
+    }
+    if matches!(
+        objects::get_name(doc, font, b"Subtype"),
+        Some(b"Type3" | b"Type0") | None
+    ) {
+        return false;
+    }
+    let descriptor = objects::get_dict(doc, font, b"FontDescriptor");
+    !descriptor.is_some_and(|d| {
+        [b"FontFile".as_slice(), b"FontFile2", b"FontFile3"]
+            .iter()
+            .any(|key| d.has(key))
+    })
+}
+
+fn font_name(doc: &Document, font: &Dictionary) -> String {
+    objects::get_name(doc, font, b"BaseFont")
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .unwrap_or_else(|| "unnamed font".to_string())
+}
+
+fn forbidden_action(doc: &Document, action: &Object) -> bool {
+    objects::resolve(doc, action)
+        .as_dict()
+        .ok()
+        .and_then(|action| objects::get_name(doc, action, b"S"))
+        .is_some_and(|kind| FORBIDDEN_ACTIONS.contains(&kind))
+}
+
+fn transparent_state(doc: &Document, state: &Dictionary) -> bool {
+    let soft_mask = objects::get(doc, state, b"SMask")
+        .is_some_and(|mask| mask.as_name().ok() != Some(b"None".as_slice()));
+    let blend = objects::get_name(doc, state, b"BM")
+        .is_some_and(|mode| !matches!(mode, b"Normal" | b"Compatible"));
+    let alpha = [b"CA".as_slice(), b"ca"]
+        .iter()
+        .any(|key| number_in(doc, state, key).is_some_and(|a| a < 1.0));
+    soft_mask || blend || alpha
+}
+
+fn transparency_group(doc: &Document, dict: &Dictionary) -> bool {
+    objects::get_dict(doc, dict, b"Group")
+        .is_some_and(|group| objects::get_name(doc, group, b"S") == Some(b"Transparency"))
+}
+
+// ExtGState dictionaries in use, found through resource dictionaries
+fn graphics_states(doc: &Document) -> Vec<ObjectId> {
+    let mut states = Vec::new();
+    for object in doc.objects.values() {
+        let Some(resources) = dict_of(object).and_then(|d| objects::get_dict(doc, d, b"ExtGState"))
+        else {
+            continue;
+        };
+        for (_, state) in resources.iter() {
+            if let Ok(id) = state.as_reference() {
+                states.push(id);
+            }
+        }
+    }
+    states.sort();
+    states.dedup();
+    states
+}
+
+fn has_output_intent(doc: &Document, catalog: &Dictionary) -> bool {
+    objects::get_array(doc, catalog, b"OutputIntents")
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|intent| objects::resolve(doc, intent).as_dict().ok())
+        .any(|intent| {
+            objects::get_name(doc, intent, b"S") == Some(b"GTS_PDFA1")
+                && intent.has(b"DestOutputProfile")
+        })
+}
+
+fn check_document(doc: &Document, findings: &mut Findings) {
+    if doc.trailer.has(b"Encrypt") {
+        findings.add(ENCRYPTED, None, None);
+    }
+    if !doc.trailer.has(b"ID") {
+        findings.add(FILE_ID, None, None);
+    }
+    let Ok(catalog) = doc.catalog() else {
+        return;
+    };
+
+    if catalog.has(b"Metadata") {
+        let xmp = read_xmp(doc);
+        let part = xmp.get("pdfaid:part").map(String::as_str);
+        let conformance = xmp.get("pdfaid:conformance").map(|c| c.to_uppercase());
+        if part != Some(findings.level.part()) || conformance.as_deref() != Some("B") {
+            findings.add(XMP_ID, None, None);
+        }
+    } else {
+        findings.add(NO_XMP, None, None);
+    }
+
+    if !has_output_intent(doc, catalog) {
+        findings.add(OUTPUT_INTENT, None, None);
+    }
+
+    if let Some(names) = objects::get_dict(doc, catalog, b"Names") {
+        if names.has(b"JavaScript") {
+            findings.add(JAVASCRIPT, None, None);
+        }
+        if findings.is_1b() && names.has(b"EmbeddedFiles") {
+            findings.add(EMBEDDED_FILES, None, None);
+        }
+    }
+    if findings.is_1b() && catalog.has(b"OCProperties") {
+        findings.add(OPTIONAL_CONTENT, None, None);
+    }
+    if catalog.has(b"AA") {
+        findings.add(ADDITIONAL_ACTIONS, Some("document catalog".into()), None);
+    }
+    if let Some(form) = objects::get_dict(doc, catalog, b"AcroForm") {
+        let need = objects::get(doc, form, b"NeedAppearances").and_then(|n| n.as_bool().ok());
+        if need == Some(true) {
+            findings.add(NEED_APPEARANCES, None, None);
+        }
+        if form.has(b"XFA") {
+            findings.add(XFA, None, None);
+        }
+    }
+}
+
+fn check_objects(doc: &Document, findings: &mut Findings) {
+    for object in doc.objects.values() {
+        let Some(dict) = dict_of(object) else {
+            continue;
+        };
+        if needs_font_file(doc, dict) {
+            findings.add(FONT_NOT_EMBEDDED, Some(font_name(doc, dict)), None);
+        }
+        for key in [b"A".as_slice(), b"OpenAction", b"Next"] {
+            if let Some(action) = dict.get(key).ok().filter(|a| forbidden_action(doc, a)) {
+                let javascript = objects::resolve(doc, action)
+                    .as_dict()
+                    .ok()
+                    .and_then(|a| objects::get_name(doc, a, b"S"))
+                    == Some(b"JavaScript");
+                findings.add(if javascript { JAVASCRIPT } else { ACTION }, None, None);
+            }
+        }
+        if findings.is_1b() && transparency_group(doc, dict) {
+            findings.add(TRANSPARENCY, Some("transparency group".into()), None);
+        }
+
+        let Object::Stream(stream) = object else {
+            continue;
+        };
+        let filters = stream.filters().unwrap_or_default();
+        if filters.iter().any(|filter| filter == "LZWDecode") {
+            findings.add(LZW, None, None);
+        }
+        match objects::get_name(doc, dict, b"Subtype") {
+            Some(b"Image") => {
+                if findings.is_1b() && filters.iter().any(|filter| filter == "JPXDecode") {
+                    findings.add(JPX, None, None);
+                }
+                let interpolate =
+                    objects::get(doc, dict, b"Interpolate").and_then(|i| i.as_bool().ok());
+                if interpolate == Some(true) || dict.has(b"Alternates") || dict.has(b"OPI") {
+                    findings.add(IMAGE_ENTRIES, None, None);
+                }
+                if findings.is_1b() && dict.has(b"SMask") {
+                    findings.add(TRANSPARENCY, Some("image soft mask".into()), None);
+                }
+            }
+            Some(b"PS") => findings.add(XOBJECT_TYPE, Some("PostScript XObject".into()), None),
+            Some(b"Form") if dict.has(b"Ref") => {
+                findings.add(XOBJECT_TYPE, Some("reference XObject".into()), None)
+            }
+            Some(b"Form") if objects::get_name(doc, dict, b"Subtype2") == Some(b"PS") => {
+                findings.add(XOBJECT_TYPE, Some("PostScript XObject".into()), None)
+            }
+            _ => {}
+        }
+    }
+
+    for id in graphics_states(doc) {
+        let Ok(state) = doc.get_dictionary(id) else {
+            continue;
+        };
+        let transfer2 = objects::get_name(doc, state, b"TR2");
+        if state.has(b"TR") || (state.has(b"TR2") && transfer2 != Some(b"Default")) {
+            findings.add(TRANSFER, None, None);
+        }
+        if findings.is_1b() && transparent_state(doc, state) {
+            findings.add(TRANSPARENCY, Some("graphics state".into()), None);
+        }
+    }
+}
+
+fn annotation_type_allowed(level: PdfALevel, subtype: Option<&[u8]>) -> bool {
+    match subtype {
+        Some(b"Sound" | b"Movie" | b"Screen" | b"3D" | b"RichMedia") => false,
+        Some(b"FileAttachment") => level != PdfALevel::A1b,
+        _ => true,
+    }
+}
+
+fn check_pages(doc: &Document, findings: &mut Findings) {
+    for (index, page_id) in doc.get_pages().into_values().enumerate() {
+        let page_number = Some(index as i32);
+        let Ok(page) = doc.get_dictionary(page_id) else {
+            continue;
+        };
+        if findings.level == PdfALevel::A2b && page.has(b"AA") {
+            findings.add(ADDITIONAL_ACTIONS, Some("page".into()), page_number);
+        }
+        for annot in objects::get_array(doc, page, b"Annots").unwrap_or(&[]) {
+            let Ok(annot) = objects::resolve(doc, annot).as_dict() else {
+                continue;
+            };
+            let subtype = objects::get_name(doc, annot, b"Subtype");
+            if !annotation_type_allowed(findings.level, subtype) {
+                let name = subtype.map(|s| String::from_utf8_lossy(s).into_owned());
+                findings.add(ANNOTATION_TYPE, name, page_number);
+            }
+            if subtype == Some(b"Popup") {
+                continue;
+            }
+            let flags = objects::get(doc, annot, b"F")
+                .and_then(|f| f.as_i64().ok())
+                .unwrap_or(0);
+            if flags & PRINT == 0 || flags & (INVISIBLE | HIDDEN | NO_VIEW) != 0 {
+                findings.add(ANNOTATION_FLAGS, None, page_number);
+            }
+            if findings.is_1b() && number_in(doc, annot, b"CA").is_some_and(|a| a < 1.0) {
+                findings.add(TRANSPARENCY, Some("annotation opacity".into()), page_number);
+            }
+            if subtype == Some(b"Widget") && annot.has(b"AA") {
+                findings.add(ADDITIONAL_ACTIONS, Some("form field".into()), page_number);
+            }
+            let appearance = objects::get_dict(doc, annot, b"AP").is_some_and(|ap| ap.has(b"N"));
+            if findings.level == PdfALevel::A2b && subtype != Some(b"Link") && !appearance {
+                findings.add(ANNOTATION_APPEARANCE, None, page_number);
+            }
+        }
+    }
+}
+
+/// Checks a document against a PDF/A level.
+pub fn preflight(doc: &Document, level: PdfALevel) -> PreflightReport {
+    let mut findings = Findings {
+        level,
+        violations: Vec::new(),
+    };
+    check_document(doc, &mut findings);
+    check_objects(doc, &mut findings);
+    check_pages(doc, &mut findings);
+
+    let xmp = read_xmp(doc);
+    let claimed = xmp.get("pdfaid:part").map(|part| {
+        let conformance = xmp.get("pdfaid:conformance").map_or("", String::as_str);
+        format!("PDF/A-{}{}", part, conformance.to_uppercase())
+    });
+    PreflightReport {
+        level,
+        compliant: findings.violations.is_empty(),
+        claimed,
+        violations: findings.violations,
+    }
+}
+
+// Big-endian helpers for reading font files
+fn be_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+// The `name` table of a TrueType font file, read from the table directory
+// without the rest of the file
+fn read_name_table(path: &Path) -> Option<Vec<u8>> {
+    let mut file = File::open(path).ok()?;
+    let mut header = [0; 12];
+    file.read_exact(&mut header).ok()?;
+    let tables = be_u16(&header, 4)? as usize;
+    let mut directory = vec![0; tables * 16];
+    file.read_exact(&mut directory).ok()?;
+    let (offset, length) = directory.chunks_exact(16).find_map(|record| {
+        (&record[..4] == b"name").then(|| Some((be_u32(record, 8)?, be_u32(record, 12)?)))?
+    })?;
+    if length > MAX_NAME_TABLE {
+        return None;
+    }
+    let mut table = vec![0; length as usize];
+    file.seek(SeekFrom::Start(offset.into())).ok()?;
+    file.read_exact(&mut table).ok()?;
+    Some(table)
+}
+
+// The PostScript name (name ID 6) in a font's `name` table
+fn postscript_name(table: &[u8]) -> Option<String> {
+    let count = be_u16(table, 2)? as usize;
+    let strings = be_u16(table, 4)? as usize;
+    for i in 0..count {
+        let record = 6 + i * 12;
+        if be_u16(table, record + 6)? != 6 {
+            continue;
+        }
+        let platform = be_u16(table, record)?;
+        let length = be_u16(table, record + 8)? as usize;
+        let start = strings + be_u16(table, record + 10)? as usize;
+        let bytes = table.get(start..start + length)?;
+        return Some(match platform {
+            // Windows and Unicode names are UTF-16BE, Mac ones single bytes
+            0 | 3 => {
+                let units: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            _ => bytes.iter().map(|&b| b as char).collect(),
+        });
+    }
+    None
+}
+
+// Lower case letters and digits only, so "Arial,Bold" matches "Arial-Bold"
+fn normalize_font_name(name: &str) -> String {
+    // Subset fonts are named like "ABCDEF+Arial"
+    let name = match name.split_once('+') {
+        Some((prefix, rest)) if prefix.len() == 6 => rest,
+        _ => name,
+    };
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn font_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = [
+        "/usr/share/fonts",
+        "/usr/local/share/fonts",
+        "/Library/Fonts",
+        "/System/Library/Fonts",
+    ]
+    .iter()
+    .map(PathBuf::from)
+    .collect();
+    if let Some(home) = std::env::var_os("HOME") {
+        let home = PathBuf::from(home);
+        dirs.extend([
+            home.join(".fonts"),
+            home.join(".local/share/fonts"),
+            home.join("Library/Fonts"),
+        ]);
+    }
+    if let Some(windows) = std::env::var_os("WINDIR") {
+        dirs.push(PathBuf::from(windows).join("Fonts"));
+    }
+    if let Some(local) = std::env::var_os("LOCALAPPDATA") {
+        dirs.push(PathBuf::from(local).join("Microsoft/Windows/Fonts"));
+    }
+    dirs
+}
+
+// Installed TrueType fonts by normalized PostScript name, looked for once a
+// run. Links to directories aren't followed, as they may lead back up
+fn installed_fonts() -> &'static HashMap<String, PathBuf> {
+    static FONTS: OnceLock<HashMap<String, PathBuf>> = OnceLock::new();
+    FONTS.get_or_init(|| {
+        let mut fonts = HashMap::new();
+        let mut pending = font_dirs();
+        while let Some(dir) = pending.pop() {
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let Ok(file_type) = entry.file_type() else {
+                    continue;
+                };
+                if file_type.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                let truetype = path
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("ttf"));
+                if !truetype || !path.is_file() {
+                    continue;
+                }
+                if let Some(name) = read_name_table(&path).and_then(|t| postscript_name(&t)) {
+                    fonts.entry(normalize_font_name(&name)).or_insert(path);
+                }
+            }
+        }
+        fonts
+    })
+}
+
+fn find_font<'a>(fonts: &'a HashMap<String, PathBuf>, name: &str) -> Option<&'a PathBuf> {
+    let name = normalize_font_name(name);
+    // Windows fonts often end in "MT" or "PS" where PDFs leave it out
+    fonts.get(&name).or_else(|| {
+        ["mt", "ps", "psmt"]
+            .iter()
+            .find_map(|suffix| fonts.get(&format!("{}{}", name, suffix)))
+    })
+}
+
+// Embeds installed TrueType fonts into simple TrueType fonts that lack one
+fn embed_fonts(doc: &mut Document, changes: &mut Vec<String>) {
+    let missing: Vec<(ObjectId, String)> = doc
+        .objects
+        .iter()
+        .filter_map(|(&id, object)| {
+            let font = object.as_dict().ok()?;
+            let truetype = objects::get_name(doc, font, b"Subtype") == Some(b"TrueType");
+            (truetype && needs_font_file(doc, font)).then(|| (id, font_name(doc, font)))
+        })
+        .collect();
+    if missing.is_empty() {
+        return;
+    }
+    let installed = installed_fonts();
+    for (id, name) in missing {
+        let Some(path) = find_font(installed, &name) else {
+            continue;
+        };
+        let mut data = Vec::new();
+        if File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut data))
+            .is_err()
+        {
+            continue;
+        }
+        let length = data.len() as i64;
+        let file = doc.add_object(Stream::new(dictionary! { "Length1" => length }, data));
+        let Ok(font) = doc.get_dictionary(id) else {
+            continue;
+        };
+        // Descriptors can be shared between fonts, so each font gets its own
+        let mut descriptor = objects::get_dict(doc, font, b"FontDescriptor")
+            .cloned()
+            .unwrap_or_else(|| {
+                dictionary! {
+                    "Type" => "FontDescriptor",
+                    "FontName" => Object::Name(name.clone().into_bytes()),
+                }
+            });
+        descriptor.set("FontFile2", file);
+        let descriptor = doc.add_object(descriptor);
+        if let Ok(font) = doc.get_dictionary_mut(id) {
+            font.set("FontDescriptor", descriptor);
+        }
+        changes.push(format!("Embedded font {} from {}", name, path.display()));
+    }
+}
+
+// A minimal ICC profile (version 2.1) for sRGB primaries, with a plain 2.2
+// gamma curve standing in for the sRGB one
+fn srgb_profile() -> Vec<u8> {
+    fn s15_fixed16(value: f64) -> [u8; 4] {
+        ((value * 65536.0).round() as i32).to_be_bytes()
+    }
+    fn xyz(x: f64, y: f64, z: f64) -> Vec<u8> {
+        let mut tag = b"XYZ \0\0\0\0".to_vec();
+        for value in [x, y, z] {
+            tag.extend(s15_fixed16(value));
+        }
+        tag
+    }
+    let description = b"sRGB IEC61966-2.1 (gamma 2.2)\0";
+    let mut desc = b"desc\0\0\0\0".to_vec();
+    desc.extend((description.len() as u32).to_be_bytes());
+    desc.extend(description);
+    // No Unicode or ScriptCode descriptions
+    desc.extend([0; 4 + 4 + 2 + 1 + 67]);
+    let mut copyright = b"text\0\0\0\0".to_vec();
+    copyright.extend(b"No copyright, use freely\0");
+    let mut curve = b"curv\0\0\0\0".to_vec();
+    curve.extend(1u32.to_be_bytes());
+    curve.extend(0x0233u16.to_be_bytes());
+
+    // Tag data, with the three curves sharing one
+    let data: [(&[u8; 4], &[u8]); 7] = [
+        (b"desc", &desc),
+        (b"cprt", &copyright),
+        (b"wtpt", &xyz(0.9642, 1.0, 0.8249)),
+        (b"rXYZ", &xyz(0.4361, 0.2225, 0.0139)),
+        (b"gXYZ", &xyz(0.3851, 0.7169, 0.0971)),
+        (b"bXYZ", &xyz(0.1431, 0.0606, 0.7141)),
+        (b"rTRC", &curve),
+    ];
+    let tag_count = data.len() + 2;
+    let mut body = Vec::new();
+    let mut table = Vec::new();
+    let start = 128 + 4 + tag_count * 12;
+    for (signature, bytes) in data {
+        let offset = (start + body.len()) as u32;
+        table.push((*signature, offset, bytes.len() as u32));
+        if signature == b"rTRC" {
+            table.push((*b"gTRC", offset, bytes.len() as u32));
+            table.push((*b"bTRC", offset, bytes.len() as u32));
+        }
+        body.extend(bytes);
+        while body.len() % 4 != 0 {
+            body.push(0);
+        }
+    }
+
+    let size = (start + body.len()) as u32;
+    let mut profile = Vec::with_capacity(size as usize);
+    profile.extend(size.to_be_bytes());
+    profile.extend([0; 4]);
+    profile.extend(0x0210_0000u32.to_be_bytes());
+    profile.extend(b"mntrRGB XYZ ");
+    // Creation date: 2000-01-01
+    for field in [2000u16, 1, 1, 0, 0, 0] {
+        profile.extend(field.to_be_bytes());
+    }
+    profile.extend(b"acsp");
+    profile.extend([0; 24]);
+    profile.extend([0; 4]);
+    for value in [0.9642, 1.0, 0.8249] {
+        profile.extend(s15_fixed16(value));
+    }
+    profile.resize(128, 0);
+    profile.extend((tag_count as u32).to_be_bytes());
+    for (signature, offset, length) in table {
+        profile.extend(signature);
+        profile.extend(offset.to_be_bytes());
+        profile.extend(length.to_be_bytes());
+    }
+    profile.extend(body);
+    profile
+}
+
+fn fix_document(doc: &mut Document, level: PdfALevel, file_id: &[u8], changes: &mut Vec<String>) {
+    if !doc.trailer.has(b"ID") {
+        let id = Object::String(file_id.to_vec(), StringFormat::Hexadecimal);
+        doc.trailer.set("ID", vec![id.clone(), id]);
+        changes.push("Added a file identifier".into());
+    }
+
+    let metadata = doc.add_object(
+        Stream::new(
+            dictionary! { "Type" => "Metadata", "Subtype" => "XML" },
+            // Carrying over the document information keeps /Info and XMP in
+            // agreement, as PDF/A requires
+            xmp_packet(&document_info(doc, 0, 0), Some((level.part(), "B"))),
+        )
+        .with_compression(false),
+    );
+    let has_intent = doc
+        .catalog()
+        .is_ok_and(|catalog| has_output_intent(doc, catalog));
+    let intent = (!has_intent).then(|| {
+        let profile = doc.add_object(Stream::new(dictionary! { "N" => 3 }, srgb_profile()));
+        dictionary! {
+            "Type" => "OutputIntent",
+            "S" => "GTS_PDFA1",
+            "OutputConditionIdentifier" => Object::string_literal("sRGB IEC61966-2.1"),
+            "RegistryName" => Object::string_literal("http://www.color.org"),
+            "DestOutputProfile" => profile,
+        }
+    });
+
+    let Ok(catalog) = doc.catalog_mut() else {
+        return;
+    };
+    catalog.set("Metadata", metadata);
+    changes.push(format!("Wrote PDF/A-{}B XMP metadata", level.part()));
+    if let Some(intent) = intent {
+        catalog.set("OutputIntents", vec![Object::Dictionary(intent)]);
+        changes.push("Added an sRGB output intent".into());
+    }
+    if catalog.remove(b"AA").is_some() {
+        changes.push("Removed document actions".into());
+    }
+    if level == PdfALevel::A1b && catalog.remove(b"OCProperties").is_some() {
+        changes.push("Removed layers, showing all their content".into());
+    }
+
+    // Names and the form may be shared objects, so they are edited as copies
+    let names = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| objects::get_dict(doc, catalog, b"Names"))
+        .cloned();
+    if let Some(mut names) = names {
+        let mut removed = names.remove(b"JavaScript").is_some();
+        if level == PdfALevel::A1b && names.remove(b"EmbeddedFiles").is_some() {
+            changes.push("Removed attached files".into());
+            removed = true;
+        }
+        if removed {
+            if let Ok(catalog) = doc.catalog_mut() {
+                catalog.set("Names", names);
+            }
+        }
+    }
+    let form = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| objects::get_dict(doc, catalog, b"AcroForm"))
+        .cloned();
+    if let Some(mut form) = form {
+        let need = form.remove(b"NeedAppearances").is_some();
+        let xfa = form.remove(b"XFA").is_some();
+        if need || xfa {
+            changes.push("Removed XFA and generated form appearances".into());
+            if let Ok(catalog) = doc.catalog_mut() {
+                catalog.set("AcroForm", form);
+            }
+        }
+    }
+
+    doc.version = match level {
+        PdfALevel::A1b => "1.4".to_string(),
+        PdfALevel::A2b if doc.version.as_str() > "1.7" => "1.7".to_string(),
+        PdfALevel::A2b => doc.version.clone(),
+    };
+}
+
+fn fix_objects(doc: &mut Document, level: PdfALevel, changes: &mut Vec<String>) {
+    let states = graphics_states(doc);
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    let ids: Vec<ObjectId> = doc.objects.keys().copied().collect();
+    for id in ids {
+        let Some(dict) = doc.objects.get(&id).and_then(dict_of) else {
+            continue;
+        };
+        let actions: Vec<&[u8]> = [b"A".as_slice(), b"OpenAction", b"Next"]
+            .into_iter()
+            .filter(|key| dict.get(key).is_ok_and(|a| forbidden_action(doc, a)))
+            .collect();
+        let group = level == PdfALevel::A1b && transparency_group(doc, dict);
+        let decoded = match doc.objects.get(&id) {
+            Some(Object::Stream(stream))
+                if stream
+                    .filters()
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|filter| filter == "LZWDecode") =>
+            {
+                stream.decompressed_content().ok()
+            }
+            _ => None,
+        };
+        let is_state = states.contains(&id);
+        let translucent = is_state && transparent_state(doc, dict);
+        let Some(object) = doc.objects.get_mut(&id) else {
+            continue;
+        };
+        if let Object::Stream(stream) = object {
+            if let Some(decoded) = decoded {
+                // Left without a filter, the stream is deflated when written
+                stream.dict.remove(b"Filter");
+                stream.dict.remove(b"DecodeParms");
+                stream.set_plain_content(decoded);
+                *counts.entry("Recompressed {} LZW streams").or_default() += 1;
+            }
+        }
+        let dict = match object {
+            Object::Dictionary(dict) => dict,
+            Object::Stream(stream) => &mut stream.dict,
+            _ => continue,
+        };
+        for key in actions {
+            dict.remove(key);
+            *counts.entry("Removed {} forbidden actions").or_default() += 1;
+        }
+        if group {
+            dict.remove(b"Group");
+            *counts.entry("Removed {} transparency groups").or_default() += 1;
+        }
+        let image = dict.get(b"Subtype").ok().and_then(|s| s.as_name().ok()) == Some(b"Image");
+        if image {
+            let mut changed = dict.remove(b"Interpolate").is_some();
+            changed |= dict.remove(b"Alternates").is_some();
+            changed |= dict.remove(b"OPI").is_some();
+            if changed {
+                *counts.entry("Cleaned up {} images").or_default() += 1;
+            }
+            if level == PdfALevel::A1b && dict.remove(b"SMask").is_some() {
+                *counts.entry("Made {} images opaque").or_default() += 1;
+            }
+        }
+        if is_state {
+            if dict.remove(b"TR").is_some() | dict.remove(b"TR2").is_some() {
+                *counts.entry("Removed {} transfer functions").or_default() += 1;
+            }
+            if level == PdfALevel::A1b && translucent {
+                dict.set("SMask", "None");
+                dict.set("BM", "Normal");
+                dict.set("CA", 1);
+                dict.set("ca", 1);
+                *counts.entry("Made {} graphics states opaque").or_default() += 1;
+            }
+        }
+    }
+    changes.extend(
+        counts
+            .into_iter()
+            .map(|(message, count)| message.replace("{}", &count.to_string())),
+    );
+}
+
+fn fix_annotations(doc: &mut Document, level: PdfALevel, changes: &mut Vec<String>) {
+    let (mut removed, mut changed) = (0, 0);
+    for page_id in doc.get_pages().into_values() {
+        let Some(annots) = doc
+            .get_dictionary(page_id)
+            .ok()
+            .and_then(|page| objects::get_array(doc, page, b"Annots"))
+            .map(|annots| annots.to_vec())
+        else {
+            continue;
+        };
+        let mut kept = Vec::new();
+        let mut updates = Vec::new();
+        for entry in annots {
+            let Ok(annot) = objects::resolve(doc, &entry).as_dict() else {
+                continue;
+            };
+            let subtype = objects::get_name(doc, annot, b"Subtype");
+            let flags = objects::get(doc, annot, b"F")
+                .and_then(|f| f.as_i64().ok())
+                .unwrap_or(0);
+            // Annotations nobody sees can go without changing the page
+            if !annotation_type_allowed(level, subtype)
+                || (subtype != Some(b"Popup") && flags & (INVISIBLE | HIDDEN | NO_VIEW) != 0)
+            {
+                removed += 1;
+                continue;
+            }
+            let opacity =
+                level == PdfALevel::A1b && number_in(doc, annot, b"CA").is_some_and(|a| a < 1.0);
+            let actions = subtype == Some(b"Widget") && annot.has(b"AA");
+            if subtype != Some(b"Popup") && (flags & PRINT == 0 || opacity || actions) {
+                if let Ok(id) = entry.as_reference() {
+                    updates.push((id, flags | PRINT, opacity, actions));
+                }
+            }
+            kept.push(entry);
+        }
+        for (id, flags, opacity, actions) in updates {
+            if let Ok(annot) = doc.get_dictionary_mut(id) {
+                annot.set("F", flags);
+                if opacity {
+                    annot.remove(b"CA");
+                }
+                if actions {
+                    annot.remove(b"AA");
+                }
+                changed += 1;
+            }
+        }
+        if let Ok(page) = doc.get_dictionary_mut(page_id) {
+            page.set("Annots", kept);
+            if level == PdfALevel::A2b {
+                page.remove(b"AA");
+            }
+        }
+    }
+    if removed > 0 {
+        changes.push(format!(
+            "Removed {} hidden or forbidden annotations",
+            removed
+        ));
+    }
+    if changed > 0 {
+        changes.push(format!("Made {} annotations printable", changed));
+    }
+}
+
+fn convert(doc: &mut Document, level: PdfALevel, file_id: &[u8]) -> Vec<String> {
+    let mut changes = Vec::new();
+    fix_document(doc, level, file_id, &mut changes);
+    fix_objects(doc, level, &mut changes);
+    fix_annotations(doc, level, &mut changes);
+    embed_fonts(doc, &mut changes);
+    doc.prune_objects();
+    changes
+}
+
+/// Checks the document against PDF/A-1b or PDF/A-2b.
+#[tauri::command]
+pub async fn preflight_pdfa(
+    doc_id: DocId,
+    level: PdfALevel,
+    store: State<'_, DocumentStore>,
+) -> Result<PreflightReport, String> {
+    let doc = store.get(doc_id)?;
+    blocking(move || doc.with_objects(|objects| Ok(preflight(objects, level)))).await
+}
+
+/// Converts the document to PDF/A as far as possible and saves it as a new
+/// file, reporting what was changed and what still doesn't comply.
+#[tauri::command]
+pub async fn convert_to_pdfa(
+    app: AppHandle,
+    doc_id: DocId,
+    level: PdfALevel,
+    output_path: String,
+    store: State<'_, DocumentStore>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<ConversionReport, String> {
+    scope::check_write(&app, Path::new(&output_path))?;
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Modify).await?;
+    blocking(move || {
+        let file_id = hex::decode(&doc.fingerprint()[..32]).unwrap_or_default();
+        let (mut converted, changes) = doc.with_objects(|objects| {
+            if objects.trailer.has(b"Encrypt") {
+                return Err("Remove the password protection before converting".to_string());
+            }
+            let mut converted = objects.clone();
+            let changes = convert(&mut converted, level, &file_id);
+            Ok((converted, changes))
+        })?;
+        write_document(&mut converted, Path::new(&output_path))?;
+        let remaining = preflight(&converted, level);
+        eprintln!(
+            "Converted document {} to PDF/A-{}B in {}: {} changes, {} open issues",
+            doc_id,
+            level.part(),
+            output_path,
+            changes.len(),
+            remaining.violations.len()
+        );
+        Ok(ConversionReport {
+            document: WrittenDocument {
+                path: output_path,
+                page_count: doc.page_count,
+            },
+            changes,
+            remaining,
+        })
+    })
+    .await
+}
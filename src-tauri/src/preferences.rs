@@ -0,0 +1,767 @@
+// The user's preferences, kept by the backend for every window to share.
+//
+// They're one typed struct saved at `<app data>/preferences.json` with the
+// version of its layout. A file from an older version is brought up to date
+// by the migrations in `MIGRATIONS`, one version at a time, after a copy of
+// it is kept as `preferences.v<version>.json`; so is a file from a newer
+// version before it's first written over, as what this version doesn't know
+// would be lost. A file that can't be read is set aside in the same way, and
+// values out of range are put back to their defaults section by section, so
+// a bad value never costs the rest. The file is written to a temporary one
+// that replaces it, so a crash while saving leaves the old one whole.
+//
+// Version 0 is the frontend's `localStorage`, which the frontend hands over
+// once with `import_legacy_preferences`. Version 1 left the launch, session,
+// shortcut and tray settings in files of their own (`launch.json` and so on),
+// which are taken over as sections from version 2 on; the files stay where
+// they are for older versions. Every change is sent to the windows as a
+// "preferences-changed" event, and the tray icon and the global shortcut
+// follow their sections at once.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::launch::LaunchSettings;
+use crate::reading::ViewMode;
+use crate::session::SessionSettings;
+use crate::shortcut::{self, ShortcutSettings};
+use crate::tray::{self, TraySettings};
+
+const FILE: &str = "preferences.json";
+// The files version 1 kept settings in, by section
+const SETTINGS_FILES: [(&str, &str); 4] = [
+    ("launch", "launch.json"),
+    ("session", "session.json"),
+    ("shortcut", "shortcut.json"),
+    ("tray", "tray.json"),
+];
+// Each brings the preferences from the version of its index to the next
+type Migration = fn(&mut Map<String, Value>);
+const MIGRATIONS: &[Migration] = &[from_local_storage, from_settings_files];
+const VERSION: u64 = MIGRATIONS.len() as u64;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum FitMode {
+    Width,
+    #[default]
+    Page,
+    Custom,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum DrawingStyle {
+    Marker,
+    #[default]
+    Pencil,
+    Pen,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Orientation {
+    #[default]
+    Auto,
+    Portrait,
+    Landscape,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum PaperSize {
+    #[default]
+    Letter,
+    A4,
+    Legal,
+    Tabloid,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum MarginPreset {
+    #[default]
+    None,
+    Narrow,
+    Normal,
+    Wide,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ScalingMode {
+    #[default]
+    Fit,
+    Actual,
+    Custom,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ViewerPreferences {
+    pub fit_mode: FitMode,
+    /// The zoom documents open at with the custom fit, 1 being 100%
+    pub zoom: f32,
+    pub view_mode: ViewMode,
+    pub show_thumbnails: bool,
+    pub show_toolbar: bool,
+}
+
+impl Default for ViewerPreferences {
+    fn default() -> Self {
+        ViewerPreferences {
+            fit_mode: FitMode::Page,
+            zoom: 1.0,
+            view_mode: ViewMode::default(),
+            show_thumbnails: true,
+            show_toolbar: true,
+        }
+    }
+}
+
+/// The settings annotation tools start with. Colors are `#rrggbb`.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ToolPreferences {
+    pub highlight_color: String,
+    pub highlight_stroke_width: f32,
+    pub highlight_opacity: f32,
+    pub drawing_style: DrawingStyle,
+    pub drawing_color: String,
+    pub drawing_stroke_width: f32,
+    pub drawing_opacity: f32,
+    pub shape_stroke_color: String,
+    pub shape_stroke_width: f32,
+    pub shape_fill_color: String,
+    pub shape_fill_opacity: f32,
+    pub arrow_head_size: f32,
+    /// How large stamps are placed, 1 being their own size
+    pub stamp_size: f32,
+}
+
+impl Default for ToolPreferences {
+    fn default() -> Self {
+        ToolPreferences {
+            highlight_color: "#FFFF00".into(),
+            highlight_stroke_width: 15.0,
+            highlight_opacity: 0.5,
+            drawing_style: DrawingStyle::Pencil,
+            drawing_color: "#000000".into(),
+            drawing_stroke_width: 3.0,
+            drawing_opacity: 1.0,
+            shape_stroke_color: "#000000".into(),
+            shape_stroke_width: 2.0,
+            shape_fill_color: "#FFFFFF".into(),
+            shape_fill_opacity: 0.0,
+            arrow_head_size: 10.0,
+            stamp_size: 0.5,
+        }
+    }
+}
+
+/// The settings the print dialog starts with.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PrintPreferences {
+    pub orientation: Orientation,
+    pub paper_size: PaperSize,
+    pub margin_preset: MarginPreset,
+    pub scaling_mode: ScalingMode,
+    /// In percent, with the custom scaling
+    pub custom_scale: f32,
+}
+
+impl Default for PrintPreferences {
+    fn default() -> Self {
+        PrintPreferences {
+            orientation: Orientation::Auto,
+            paper_size: PaperSize::Letter,
+            margin_preset: MarginPreset::None,
+            scaling_mode: ScalingMode::Fit,
+            custom_scale: 100.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Preferences {
+    pub viewer: ViewerPreferences,
+    pub tools: ToolPreferences,
+    pub print: PrintPreferences,
+    pub launch: LaunchSettings,
+    pub session: SessionSettings,
+    pub shortcut: ShortcutSettings,
+    pub tray: TraySettings,
+}
+
+// Checks a number is within a range, naming it otherwise
+fn check_range(name: &str, value: f32, min: f32, max: f32) -> Result<(), String> {
+    match value.is_finite() && (min..=max).contains(&value) {
+        true => Ok(()),
+        false => Err(format!("{} must be from {} to {}", name, min, max)),
+    }
+}
+
+fn check_color(name: &str, color: &str) -> Result<(), String> {
+    let hex = color.strip_prefix('#').unwrap_or_default();
+    match hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => Ok(()),
+        false => Err(format!("{} must be a color like #1A2B3C", name)),
+    }
+}
+
+impl ViewerPreferences {
+    fn validate(&self) -> Result<(), String> {
+        check_range("The zoom", self.zoom, 0.25, 5.0)
+    }
+}
+
+impl ToolPreferences {
+    fn validate(&self) -> Result<(), String> {
+        check_color("The highlight color", &self.highlight_color)?;
+        check_range(
+            "The highlight width",
+            self.highlight_stroke_width,
+            1.0,
+            100.0,
+        )?;
+        check_range("The highlight opacity", self.highlight_opacity, 0.0, 1.0)?;
+        check_color("The drawing color", &self.drawing_color)?;
+        check_range("The drawing width", self.drawing_stroke_width, 0.5, 100.0)?;
+        check_range("The drawing opacity", self.drawing_opacity, 0.0, 1.0)?;
+        check_color("The shape color", &self.shape_stroke_color)?;
+        check_range("The shape width", self.shape_stroke_width, 0.0, 100.0)?;
+        check_color("The shape fill color", &self.shape_fill_color)?;
+        check_range("The shape fill opacity", self.shape_fill_opacity, 0.0, 1.0)?;
+        check_range("The arrow head size", self.arrow_head_size, 1.0, 100.0)?;
+        check_range("The stamp size", self.stamp_size, 0.1, 2.0)
+    }
+}
+
+impl PrintPreferences {
+    fn validate(&self) -> Result<(), String> {
+        check_range("The print scale", self.custom_scale, 10.0, 400.0)
+    }
+}
+
+impl Preferences {
+    /// Checks every value is one the app can use.
+    pub fn validate(&self) -> Result<(), String> {
+        self.viewer.validate()?;
+        self.tools.validate()?;
+        self.print.validate()?;
+        self.shortcut.validate()
+    }
+
+    // Puts the sections with values out of range back to their defaults
+    fn sanitized(mut self) -> Self {
+        if let Err(e) = self.viewer.validate() {
+            eprintln!("Resetting the viewer preferences: {}", e);
+            self.viewer = ViewerPreferences::default();
+        }
+        if let Err(e) = self.tools.validate() {
+            eprintln!("Resetting the tool preferences: {}", e);
+            self.tools = ToolPreferences::default();
+        }
+        if let Err(e) = self.print.validate() {
+            eprintln!("Resetting the print preferences: {}", e);
+            self.print = PrintPreferences::default();
+        }
+        if let Err(e) = self.shortcut.validate() {
+            eprintln!("Resetting the shortcut: {}", e);
+            self.shortcut = ShortcutSettings::default();
+        }
+        self
+    }
+}
+
+/// The preferences in use, managed as Tauri state.
+#[derive(Default)]
+pub struct PreferencesStore {
+    current: Mutex<Preferences>,
+    // The version of the file read at launch, if newer than this one's
+    newer_version: Mutex<Option<u64>>,
+}
+
+fn data_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))
+}
+
+fn preferences_file(app: &AppHandle) -> Result<PathBuf, String> {
+    data_dir(app).map(|dir| dir.join(FILE))
+}
+
+// Keeps a copy of the file as it is, next to it
+fn back_up(file: &Path, suffix: &str) {
+    let copy = file.with_file_name(format!("preferences.{}.json", suffix));
+    match std::fs::copy(file, &copy) {
+        Ok(_) => eprintln!("Kept the preferences as {}", copy.display()),
+        Err(e) => eprintln!("Failed to keep a copy of the preferences: {}", e),
+    }
+}
+
+// Version 0 is what the frontend kept in localStorage, by store name. Only
+// the stamp size and the print settings are preferences; those were kept for
+// each document, and the ones most documents had are taken
+fn from_local_storage(values: &mut Map<String, Value>) {
+    let storage = values.remove("localStorage").unwrap_or_default();
+    // Stores keep their state as JSON strings
+    let store = |name: &str| -> Value {
+        storage
+            .get(name)
+            .and_then(Value::as_str)
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default()
+    };
+    let mut tools = Map::new();
+    if let Some(size) = store("pdf-stamp-storage").pointer("/state/stampSizeMultiplier") {
+        tools.insert("stampSize".into(), size.clone());
+    }
+    let mut print = Map::new();
+    let printed = store("civil-pdf-print-settings");
+    let documents: Vec<&Value> = printed
+        .get("settings")
+        .and_then(Value::as_object)
+        .map(|settings| settings.values().collect())
+        .unwrap_or_default();
+    for (from, to) in [
+        ("orientation", "orientation"),
+        ("pageSize", "paperSize"),
+        ("marginPreset", "marginPreset"),
+        ("scalingMode", "scalingMode"),
+        ("customScale", "customScale"),
+    ] {
+        // Custom page sizes and margins stay with their documents
+        let values: Vec<&Value> = documents
+            .iter()
+            .filter_map(|settings| settings.get(from))
+            .filter(|value| *value != "custom" || from == "scalingMode")
+            .collect();
+        let most_common = values
+            .iter()
+            .max_by_key(|value| values.iter().filter(|other| other == value).count());
+        if let Some(value) = most_common {
+            print.insert(to.into(), (*value).clone());
+        }
+    }
+    values.insert("tools".into(), Value::Object(tools));
+    values.insert("print".into(), Value::Object(print));
+}
+
+// Version 1 kept the launch, session, shortcut and tray settings apart, and
+// they come as they were kept under `settingsFiles`, read from their files or
+// from a settings archive
+fn from_settings_files(values: &mut Map<String, Value>) {
+    if let Some(Value::Object(files)) = values.remove("settingsFiles") {
+        for (section, settings) in files {
+            values.entry(section).or_insert(settings);
+        }
+    }
+}
+
+// Adds the settings files in `dir` to preferences from before version 2, for
+// `from_settings_files` to take over
+fn add_settings_files(dir: &Path, values: &mut Map<String, Value>) {
+    if values.get("version").and_then(Value::as_u64).unwrap_or(0) >= 2 {
+        return;
+    }
+    let mut files = Map::new();
+    for (section, name) in SETTINGS_FILES {
+        let settings = std::fs::read(dir.join(name))
+            .ok()
+            .and_then(|json| serde_json::from_slice::<Value>(&json).ok());
+        if let Some(settings) = settings {
+            files.insert(section.into(), settings);
+        }
+    }
+    values.insert("settingsFiles".into(), Value::Object(files));
+}
+
+// Brings stored preferences up to date, returning the version they had
+fn migrate(values: &mut Map<String, Value>) -> u64 {
+    let version = values.get("version").and_then(Value::as_u64).unwrap_or(0);
+    for migration in MIGRATIONS.iter().skip(version as usize) {
+        migration(values);
+    }
+    values.remove("version");
+    version
+}
+
+// Sets the parts of `into` that `changes` has
+fn merge(into: &mut Value, changes: Value) {
+    match (into, changes) {
+        (Value::Object(into), Value::Object(changes)) => {
+            for (key, value) in changes {
+                merge(into.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (into, changes) => *into = changes,
+    }
+}
+
+fn parse(values: Map<String, Value>) -> Result<Preferences, String> {
+    serde_json::from_value::<Preferences>(Value::Object(values))
+        .map(Preferences::sanitized)
+        .map_err(|e| e.to_string())
+}
+
+// Writes the file whole or not at all
+fn write(file: &Path, preferences: &Preferences) -> Result<(), String> {
+    let mut values = match serde_json::to_value(preferences) {
+        Ok(Value::Object(values)) => values,
+        _ => return Err("Failed to serialize the preferences".into()),
+    };
+    values.insert("version".into(), json!(VERSION));
+    let json = serde_json::to_vec_pretty(&values).map_err(|e| e.to_string())?;
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let temporary = file.with_extension("json.tmp");
+    let written = std::fs::File::create(&temporary).and_then(|mut out| {
+        std::io::Write::write_all(&mut out, &json)?;
+        out.sync_all()
+    });
+    written
+        .and_then(|_| std::fs::rename(&temporary, file))
+        .map_err(|e| format!("Failed to write {}: {}", file.display(), e))
+}
+
+// Saves new preferences to `file` and makes them the current ones, returning
+// the previous ones
+fn save(
+    store: &PreferencesStore,
+    file: &Path,
+    preferences: Preferences,
+) -> Result<Preferences, String> {
+    let mut current = store.current.lock().unwrap();
+    if let Some(version) = store.newer_version.lock().unwrap().take() {
+        back_up(file, &format!("v{}", version));
+    }
+    write(file, &preferences)?;
+    Ok(std::mem::replace(&mut *current, preferences))
+}
+
+// Makes new preferences the current ones, saving them and telling the windows
+fn apply(
+    app: &AppHandle,
+    store: &PreferencesStore,
+    preferences: Preferences,
+) -> Result<Preferences, String> {
+    preferences.validate()?;
+    let previous = save(store, &preferences_file(app)?, preferences.clone())?;
+    if previous.tray != preferences.tray {
+        if let Err(e) = tray::apply(app, &preferences.tray) {
+            eprintln!("Failed to update the tray icon: {}", e);
+        }
+    }
+    if previous.shortcut != preferences.shortcut {
+        if let Err(e) = shortcut::register(app, &preferences.shortcut) {
+            eprintln!("{}", e);
+        }
+    }
+    let _ = app.emit("preferences-changed", &preferences);
+    Ok(preferences)
+}
+
+/// The preferences in use.
+pub fn current(app: &AppHandle) -> Preferences {
+    app.state::<PreferencesStore>()
+        .current
+        .lock()
+        .unwrap()
+        .clone()
+}
+
+/// Changes the preferences in use and saves them.
+pub fn update(
+    app: &AppHandle,
+    change: impl FnOnce(&mut Preferences),
+) -> Result<Preferences, String> {
+    let mut preferences = current(app);
+    change(&mut preferences);
+    apply(app, &app.state::<PreferencesStore>(), preferences)
+}
+
+/// The launch settings saved in `dir`, the app data directory. They're needed
+/// before the app is built (see `launch.rs`), so the file is read here on its
+/// own.
+pub fn launch_settings(dir: &Path) -> LaunchSettings {
+    let mut values: Map<String, Value> = std::fs::read(dir.join(FILE))
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .unwrap_or_default();
+    add_settings_files(dir, &mut values);
+    migrate(&mut values);
+    parse(values)
+        .map(|preferences| preferences.launch)
+        .unwrap_or_default()
+}
+
+/// The preferences in use, with their version, as settings archives keep
+/// them (see `transfer.rs`).
+pub fn to_archive(store: &PreferencesStore) -> Value {
+    let mut values = match serde_json::to_value(&*store.current.lock().unwrap()) {
+        Ok(Value::Object(values)) => values,
+        _ => Map::new(),
+    };
+    values.insert("version".into(), json!(VERSION));
+    Value::Object(values)
+}
+
+/// Makes archived preferences, migrated from their version, the current ones.
+/// `settings` are the launch, session, shortcut and tray settings archives of
+/// version 1 kept apart, by section. Sections the archive doesn't have are
+/// left as they are.
+pub fn from_archive(
+    app: &AppHandle,
+    store: &PreferencesStore,
+    archived: Value,
+    settings: Map<String, Value>,
+) -> Result<Preferences, String> {
+    let Value::Object(mut values) = archived else {
+        return Err("The archived preferences aren't an object".into());
+    };
+    if !settings.is_empty() {
+        values.insert("settingsFiles".into(), Value::Object(settings));
+    }
+    migrate(&mut values);
+    let mut merged =
+        serde_json::to_value(&*store.current.lock().unwrap()).map_err(|e| e.to_string())?;
+    merge(&mut merged, Value::Object(values));
+    let Value::Object(merged) = merged else {
+        return Err("Failed to serialize the preferences".into());
+    };
+    apply(app, store, parse(merged)?)
+}
+
+/// Reads the saved preferences, migrating them from an older version. Without
+/// a file, the settings files of version 1 are taken over as they are, and
+/// nothing is written until the preferences change, so the frontend's can
+/// still be imported.
+pub fn restore(app: &AppHandle) {
+    if let Ok(dir) = data_dir(app) {
+        load(&dir, &app.state::<PreferencesStore>());
+    }
+}
+
+// Reads the preferences saved in `dir` into `store`
+fn load(dir: &Path, store: &PreferencesStore) {
+    let file = dir.join(FILE);
+    let saved = std::fs::read(&file).ok();
+    let mut values = match saved.as_deref().map(serde_json::from_slice::<Value>) {
+        None => Map::new(),
+        Some(Ok(Value::Object(values))) => values,
+        Some(_) => {
+            eprintln!("Failed to read {}, using the defaults", file.display());
+            back_up(&file, "invalid");
+            return;
+        }
+    };
+    add_settings_files(dir, &mut values);
+    let version = migrate(&mut values);
+    let preferences = match parse(values) {
+        Ok(preferences) => preferences,
+        Err(e) => {
+            eprintln!(
+                "Failed to read {}, using the defaults: {}",
+                file.display(),
+                e
+            );
+            back_up(&file, "invalid");
+            return;
+        }
+    };
+    if version > VERSION {
+        eprintln!("The preferences are from a newer version ({})", version);
+        *store.newer_version.lock().unwrap() = Some(version);
+    } else if version < VERSION && saved.is_some() {
+        eprintln!("Migrating the preferences from version {}", version);
+        back_up(&file, &format!("v{}", version));
+        if let Err(e) = write(&file, &preferences) {
+            eprintln!("{}", e);
+        }
+    }
+    *store.current.lock().unwrap() = preferences;
+}
+
+#[tauri::command]
+pub fn get_preferences(store: State<'_, PreferencesStore>) -> Preferences {
+    store.current.lock().unwrap().clone()
+}
+
+/// Replaces the preferences. Nothing changes if any value is out of range.
+#[tauri::command]
+pub fn set_preferences(
+    app: AppHandle,
+    preferences: Preferences,
+    store: State<'_, PreferencesStore>,
+) -> Result<Preferences, String> {
+    apply(&app, &store, preferences)
+}
+
+/// Changes some preferences, given as part of the preferences object, like
+/// `{"tools": {"drawingColor": "#FF0000"}}`.
+#[tauri::command]
+pub fn update_preferences(
+    app: AppHandle,
+    changes: Value,
+    store: State<'_, PreferencesStore>,
+) -> Result<Preferences, String> {
+    let mut values =
+        serde_json::to_value(&*store.current.lock().unwrap()).map_err(|e| e.to_string())?;
+    merge(&mut values, changes);
+    let preferences =
+        serde_json::from_value(values).map_err(|e| format!("Invalid preferences: {}", e))?;
+    apply(&app, &store, preferences)
+}
+
+#[tauri::command]
+pub fn reset_preferences(
+    app: AppHandle,
+    store: State<'_, PreferencesStore>,
+) -> Result<Preferences, String> {
+    apply(&app, &store, Preferences::default())
+}
+
+/// Takes over the settings the frontend kept in `localStorage`, given by key
+/// as stored. Only done while there are no saved preferences; returns whether
+/// they were taken.
+#[tauri::command]
+pub fn import_legacy_preferences(
+    app: AppHandle,
+    storage: Map<String, Value>,
+    store: State<'_, PreferencesStore>,
+) -> Result<bool, String> {
+    let dir = data_dir(&app)?;
+    if dir.join(FILE).exists() {
+        return Ok(false);
+    }
+    let mut values = Map::new();
+    values.insert("version".into(), json!(0));
+    values.insert("localStorage".into(), Value::Object(storage));
+    add_settings_files(&dir, &mut values);
+    migrate(&mut values);
+    apply(&app, &store, parse(values)?)?;
+    eprintln!("Took over the preferences kept by the frontend");
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An empty directory for one test to keep files in
+    fn empty_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "nanodoc-preferences-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn object(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(values) => values,
+            _ => panic!("not an object"),
+        }
+    }
+
+    #[test]
+    fn local_storage_is_taken_over() {
+        let printed = json!({"settings": {
+            "a.pdf": {"orientation": "landscape", "pageSize": "a4", "customScale": 150},
+            "b.pdf": {"orientation": "landscape", "pageSize": "custom", "scalingMode": "custom"},
+            "c.pdf": {"orientation": "portrait", "pageSize": "custom"},
+        }});
+        let mut values = object(json!({
+            "version": 0,
+            "localStorage": {
+                "pdf-stamp-storage": json!({"state": {"stampSizeMultiplier": 0.75}}).to_string(),
+                "civil-pdf-print-settings": printed.to_string(),
+            },
+        }));
+        assert_eq!(migrate(&mut values), 0);
+        let preferences = parse(values).unwrap();
+        assert_eq!(preferences.tools.stamp_size, 0.75);
+        assert!(preferences.print.orientation == Orientation::Landscape);
+        // The custom page sizes stay with their documents
+        assert!(preferences.print.paper_size == PaperSize::A4);
+        assert!(preferences.print.scaling_mode == ScalingMode::Custom);
+        assert_eq!(preferences.print.custom_scale, 150.0);
+        assert!(preferences.viewer == ViewerPreferences::default());
+    }
+
+    #[test]
+    fn settings_files_are_taken_over_from_version_1() {
+        let dir = empty_dir("settings-files");
+        std::fs::write(dir.join("launch.json"), r#"{"separateProcesses": true}"#).unwrap();
+        std::fs::write(dir.join("tray.json"), r#"{"enabled": true}"#).unwrap();
+
+        let mut values = object(json!({"version": 1, "viewer": {"zoom": 2.0}}));
+        add_settings_files(&dir, &mut values);
+        assert_eq!(migrate(&mut values), 1);
+        let preferences = parse(values).unwrap();
+        assert!(preferences.launch.separate_processes);
+        assert!(preferences.tray.enabled);
+        assert_eq!(preferences.viewer.zoom, 2.0);
+
+        // From version 2 on, the sections are the ones in the preferences
+        let mut values = object(json!({"version": 2, "tray": {"enabled": false}}));
+        add_settings_files(&dir, &mut values);
+        migrate(&mut values);
+        let preferences = parse(values).unwrap();
+        assert!(!preferences.launch.separate_processes);
+        assert!(!preferences.tray.enabled);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn newer_version_is_kept_before_its_written_over() {
+        let dir = empty_dir("newer-version");
+        let file = dir.join(FILE);
+        let newer = r#"{"version": 99, "viewer": {"zoom": 2.0}, "unknown": true}"#;
+        std::fs::write(&file, newer).unwrap();
+
+        let store = PreferencesStore::default();
+        load(&dir, &store);
+        assert_eq!(store.current.lock().unwrap().viewer.zoom, 2.0);
+        // Reading it changes nothing on disk
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), newer);
+        assert!(!dir.join("preferences.v99.json").exists());
+
+        save(&store, &file, Preferences::default()).unwrap();
+        let kept = dir.join("preferences.v99.json");
+        assert_eq!(std::fs::read_to_string(&kept).unwrap(), newer);
+        let saved = object(serde_json::from_slice(&std::fs::read(&file).unwrap()).unwrap());
+        assert_eq!(saved.get("version"), Some(&json!(VERSION)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sections_out_of_range_are_reset() {
+        let mut preferences = Preferences::default();
+        preferences.viewer.zoom = 99.0;
+        preferences.tools.drawing_color = "red".into();
+        preferences.tools.stamp_size = 1.5;
+        preferences.print.custom_scale = 150.0;
+        assert!(preferences.validate().is_err());
+
+        let sanitized = preferences.sanitized();
+        assert!(sanitized.viewer == ViewerPreferences::default());
+        // The whole section goes, not just the bad value
+        assert!(sanitized.tools == ToolPreferences::default());
+        assert_eq!(sanitized.print.custom_scale, 150.0);
+        assert!(sanitized.validate().is_ok());
+    }
+}
@@ -0,0 +1,141 @@
+#import <ApplicationServices/ApplicationServices.h>
+#import <Cocoa/Cocoa.h>
+#include <stdbool.h>
+
+// Printing rendered sheets with NSPrintOperation.
+//
+// The sheets are drawn by a view that is one sheet of paper per page, each
+// image scaled to fit the paper and centred on it. Sheets rendered for the
+// chosen paper fill it exactly.
+
+// A sheet from Rust: RGBA pixels and the sheet's size in points
+typedef struct {
+    const uint8_t *pixels;
+    uint32_t width;
+    uint32_t height;
+    double points_width;
+    double points_height;
+} PageImage;
+
+@interface PrintView : NSView
+@property(nonatomic, strong) NSArray<NSImage *> *pages;
+@end
+
+@implementation PrintView
+
+- (BOOL)isFlipped {
+    return YES;
+}
+
+- (BOOL)knowsPageRange:(NSRangePointer)range {
+    *range = NSMakeRange(1, self.pages.count);
+    return YES;
+}
+
+- (NSRect)rectForPage:(NSInteger)page {
+    NSSize paper = [NSPrintOperation currentOperation].printInfo.paperSize;
+    return NSMakeRect(0, (page - 1) * paper.height, paper.width, paper.height);
+}
+
+- (void)drawRect:(NSRect)dirty {
+    NSPrintOperation *operation = [NSPrintOperation currentOperation];
+    NSInteger index = operation.currentPage - 1;
+    if (index < 0 || index >= (NSInteger)self.pages.count) {
+        return;
+    }
+    NSImage *image = self.pages[index];
+    NSSize paper = operation.printInfo.paperSize;
+    CGFloat zoom = MIN(paper.width / MAX(image.size.width, 1),
+                       paper.height / MAX(image.size.height, 1));
+    NSSize size = NSMakeSize(image.size.width * zoom, image.size.height * zoom);
+    NSRect target = NSMakeRect((paper.width - size.width) / 2,
+                               index * paper.height + (paper.height - size.height) / 2,
+                               size.width, size.height);
+    [image drawInRect:target
+             fromRect:NSZeroRect
+            operation:NSCompositingOperationCopy
+             fraction:1
+       respectFlipped:YES
+                hints:nil];
+}
+
+@end
+
+static NSImage *page_image(const PageImage *page) {
+    NSBitmapImageRep *bitmap =
+        [[NSBitmapImageRep alloc] initWithBitmapDataPlanes:NULL
+                                                pixelsWide:page->width
+                                                pixelsHigh:page->height
+                                             bitsPerSample:8
+                                           samplesPerPixel:4
+                                                  hasAlpha:YES
+                                                  isPlanar:NO
+                                            colorSpaceName:NSDeviceRGBColorSpace
+                                               bytesPerRow:page->width * 4
+                                              bitsPerPixel:32];
+    if (!bitmap) {
+        return nil;
+    }
+    memcpy(bitmap.bitmapData, page->pixels, (size_t)page->width * page->height * 4);
+    NSImage *image =
+        [[NSImage alloc] initWithSize:NSMakeSize(page->points_width, page->points_height)];
+    [image addRepresentation:bitmap];
+    return image;
+}
+
+static bool fail(char *error, size_t error_len, NSString *message) {
+    snprintf(error, error_len, "%s", message.UTF8String);
+    return false;
+}
+
+// Prints the pages on `printer`, or the default printer when it's NULL, on
+// paper of the given size in points, or the printer's default when it's 0.
+// `duplex` is 1 for one side, 2 for both turning on the long edge and 3 on the
+// short edge. Must be called on the main thread; the pages are copied.
+bool print_pages(const char *title, const char *printer, uint32_t copies, uint32_t duplex,
+                 double paper_width, double paper_height, const PageImage *pages, size_t count,
+                 char *error, size_t error_len) {
+    NSMutableArray<NSImage *> *images = [NSMutableArray arrayWithCapacity:count];
+    for (size_t i = 0; i < count; i++) {
+        NSImage *image = page_image(&pages[i]);
+        if (!image) {
+            return fail(error, error_len, @"Failed to prepare a page for printing");
+        }
+        [images addObject:image];
+    }
+
+    NSPrintInfo *info = [[NSPrintInfo sharedPrintInfo] copy];
+    if (printer) {
+        NSPrinter *named = [NSPrinter printerWithName:@(printer)];
+        if (!named) {
+            return fail(error, error_len,
+                        [NSString stringWithFormat:@"There is no printer named %s", printer]);
+        }
+        info.printer = named;
+    }
+    if (paper_width > 0 && paper_height > 0) {
+        info.paperSize = NSMakeSize(paper_width, paper_height);
+    }
+    // Landscape documents go on landscape paper
+    if (count > 0 && pages[0].points_width > pages[0].points_height) {
+        info.orientation = NSPaperOrientationLandscape;
+    }
+    info.topMargin = info.bottomMargin = info.leftMargin = info.rightMargin = 0;
+    info.dictionary[NSPrintCopies] = @(copies);
+    PMDuplexMode mode = duplex == 2 ? kPMDuplexNoTumble : duplex == 3 ? kPMDuplexTumble : kPMDuplexNone;
+    PMSetDuplex((PMPrintSettings)info.PMPrintSettings, mode);
+    [info updateFromPMPrintSettings];
+
+    NSSize paper = info.paperSize;
+    PrintView *view =
+        [[PrintView alloc] initWithFrame:NSMakeRect(0, 0, paper.width, paper.height * count)];
+    view.pages = images;
+    NSPrintOperation *operation = [NSPrintOperation printOperationWithView:view printInfo:info];
+    operation.jobTitle = @(title);
+    operation.showsPrintPanel = NO;
+    operation.showsProgressPanel = YES;
+    if (![operation runOperation]) {
+        return fail(error, error_len, @"The print system refused the document");
+    }
+    return true;
+}
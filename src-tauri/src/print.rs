@@ -0,0 +1,1058 @@
+// Printing through the system's print system.
+//
+// The webview's printing lays pages out like a web page, so pages are
+// rendered here instead, as the viewer shows them. Each page is composed onto
+// a sheet of the chosen paper with its scaling and margins, and the sheets
+// are handed to the system one by one: GDI on a winspool printer on Windows,
+// an NSPrintOperation over the sheets on macOS (see `print.m`), and CUPS on
+// Linux, which is sent a PDF of them. The print preview renders the very same
+// sheets, so it shows what comes out of the printer. Without a paper size the
+// sheet is the page with its margins, which the printer fits to its paper.
+//
+// Printing needs the print permission of protected files. Files that don't
+// allow printing at full resolution are printed at a reduced one.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tauri::ipc::Response;
+use tauri::{AppHandle, Emitter, State};
+
+use crate::colors::ColorMode;
+use crate::compose::parse_page_ranges;
+use crate::engine::Engine;
+use crate::engine::Worker;
+use crate::jobs::CancelToken;
+use crate::jobs::{JobId, RenderJobs};
+use crate::pdf::{blocking, decode_bitmap, rasterize_page, DocId, DocumentStore, OpenDocument};
+use crate::permissions::{Action, PermissionPolicy};
+
+const PRINT_DPI: f32 = 300.0;
+// For files that only allow printing a degraded image
+const DRAFT_DPI: f32 = 150.0;
+const MAX_COPIES: u32 = 999;
+// Custom scales allowed, in percent
+const MIN_SCALE: f32 = 10.0;
+const MAX_SCALE: f32 = 400.0;
+
+/// Printing on one or both sides of the paper.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Duplex {
+    #[default]
+    Simplex,
+    /// Both sides, turning pages over the long edge like a book
+    LongEdge,
+    /// Both sides, turning pages over the short edge like a notepad
+    ShortEdge,
+}
+
+/// Paper sizes to print on.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum Paper {
+    A3,
+    A4,
+    A5,
+    Letter,
+    Legal,
+    Tabloid,
+}
+
+impl Paper {
+    /// Portrait width and height in points.
+    pub fn points(self) -> (f32, f32) {
+        match self {
+            Paper::A3 => (841.89, 1190.55),
+            Paper::A4 => (595.28, 841.89),
+            Paper::A5 => (419.53, 595.28),
+            Paper::Letter => (612.0, 792.0),
+            Paper::Legal => (612.0, 1008.0),
+            Paper::Tabloid => (792.0, 1224.0),
+        }
+    }
+}
+
+/// How pages are sized on the paper.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum Scaling {
+    /// Scaled up or down to fill the room within the margins
+    #[default]
+    Fit,
+    /// Only pages too large for the room are scaled down
+    ShrinkToFit,
+    /// At their own size, cut off where they don't fit
+    Actual,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PrintOptions {
+    /// One-based page ranges like "1-5,8,10-end", all pages when missing
+    pub ranges: Option<String>,
+    pub copies: u32,
+    pub duplex: Duplex,
+    /// The printer's default paper when missing
+    pub paper: Option<Paper>,
+    /// The system's default printer when missing
+    pub printer: Option<String>,
+    pub scaling: Scaling,
+    /// A scale in percent, in place of `scaling`
+    pub scale: Option<f32>,
+    /// Room left blank on each side of the paper, in points
+    pub margin: f32,
+}
+
+impl Default for PrintOptions {
+    fn default() -> Self {
+        PrintOptions {
+            ranges: None,
+            copies: 1,
+            duplex: Duplex::Simplex,
+            paper: None,
+            printer: None,
+            scaling: Scaling::Fit,
+            scale: None,
+            margin: 0.0,
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PrintProgress {
+    pub job_id: Option<JobId>,
+    /// Pages handed to the print system so far
+    pub done: usize,
+    pub total: usize,
+}
+
+/// A rendered sheet in the `[width][height][rgba...]` layout, and its size in
+/// points.
+pub struct PrintPage<'a> {
+    pub bitmap: &'a [u8],
+    pub width: u32,
+    pub height: u32,
+    pub points: (f32, f32),
+}
+
+impl<'a> PrintPage<'a> {
+    fn new(bitmap: &'a [u8], dpi: f32) -> Self {
+        let (width, height, _) = decode_bitmap(bitmap);
+        PrintPage {
+            bitmap,
+            width,
+            height,
+            points: (width as f32 * 72.0 / dpi, height as f32 * 72.0 / dpi),
+        }
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        decode_bitmap(self.bitmap).2
+    }
+}
+
+// A page laid out on its sheet, in points: the paper's size, where the page's
+// top left corner goes and how much it's scaled
+struct Layout {
+    paper: (f32, f32),
+    x: f32,
+    y: f32,
+    zoom: f32,
+}
+
+fn layout(page: (f32, f32), options: &PrintOptions) -> Layout {
+    let margin = options.margin;
+    let (width, height) = (page.0.max(1.0), page.1.max(1.0));
+    let zoom_for = |room: (f32, f32)| (room.0 / width).min(room.1 / height);
+    let paper = match options.paper {
+        // Landscape pages go on landscape paper
+        Some(paper) => {
+            let (short, long) = paper.points();
+            match width > height {
+                true => (long, short),
+                false => (short, long),
+            }
+        }
+        None => {
+            let zoom = options.scale.map_or(1.0, |scale| scale / 100.0);
+            (width * zoom + 2.0 * margin, height * zoom + 2.0 * margin)
+        }
+    };
+    let room = (
+        (paper.0 - 2.0 * margin).max(1.0),
+        (paper.1 - 2.0 * margin).max(1.0),
+    );
+    let zoom = match (options.scale, options.scaling) {
+        (Some(scale), _) => scale / 100.0,
+        (None, Scaling::Fit) => zoom_for(room),
+        (None, Scaling::ShrinkToFit) => zoom_for(room).min(1.0),
+        (None, Scaling::Actual) => 1.0,
+    };
+    Layout {
+        paper,
+        x: (paper.0 - width * zoom) / 2.0,
+        y: (paper.1 - height * zoom) / 2.0,
+        zoom,
+    }
+}
+
+// Renders a page onto a white sheet of its paper at `dpi`
+fn render_sheet(
+    worker: &mut Worker,
+    doc: &Arc<OpenDocument>,
+    page: i32,
+    options: &PrintOptions,
+    dpi: f32,
+    cancel: &CancelToken,
+) -> Result<Vec<u8>, String> {
+    let bounds = worker.display_list(doc, page)?.bounds();
+    let layout = layout((bounds.x1 - bounds.x0, bounds.y1 - bounds.y0), options);
+    let scale = dpi / 72.0;
+    let rendered = rasterize_page(
+        worker,
+        doc,
+        page,
+        layout.zoom * scale,
+        ColorMode::Normal,
+        cancel,
+    )?;
+
+    let size = |points: f32| (points * scale).round().max(1.0) as usize;
+    let (width, height) = (size(layout.paper.0), size(layout.paper.1));
+    let mut sheet = Vec::with_capacity(8 + width * height * 4);
+    sheet.extend_from_slice(&(width as u32).to_le_bytes());
+    sheet.extend_from_slice(&(height as u32).to_le_bytes());
+    sheet.resize(8 + width * height * 4, 255);
+
+    // Copies the rows of the page that land on the sheet
+    let (page_width, page_height, pixels) = decode_bitmap(&rendered);
+    let (page_width, page_height) = (page_width as usize, page_height as usize);
+    let (left, top) = (
+        (layout.x * scale).round() as isize,
+        (layout.y * scale).round() as isize,
+    );
+    let first_x = (-left).max(0) as usize;
+    let last_x = (width as isize - left).clamp(0, page_width as isize) as usize;
+    if first_x < last_x {
+        for row in 0..page_height {
+            let y = top + row as isize;
+            if y < 0 || y >= height as isize {
+                continue;
+            }
+            let from = (row * page_width + first_x) * 4;
+            let to = 8 + (y as usize * width + (left + first_x as isize) as usize) * 4;
+            let len = (last_x - first_x) * 4;
+            sheet[to..to + len].copy_from_slice(&pixels[from..from + len]);
+        }
+    }
+    Ok(sheet)
+}
+
+fn check_options(options: &PrintOptions) -> Result<(), String> {
+    if !(1..=MAX_COPIES).contains(&options.copies) {
+        return Err(format!("Choose between 1 and {} copies", MAX_COPIES));
+    }
+    if options
+        .scale
+        .is_some_and(|scale| !(MIN_SCALE..=MAX_SCALE).contains(&scale))
+    {
+        return Err(format!(
+            "Choose a scale between {}% and {}%",
+            MIN_SCALE, MAX_SCALE
+        ));
+    }
+    if !options.margin.is_finite() || options.margin < 0.0 {
+        return Err("Margins can't be negative".into());
+    }
+    Ok(())
+}
+
+// The resolution pages print at: full, unless the file only allows a
+// degraded image
+async fn print_dpi(policy: &PermissionPolicy, doc: &Arc<OpenDocument>) -> Result<f32, String> {
+    policy.check(doc, Action::Print).await?;
+    Ok(match policy.check(doc, Action::PrintHighQuality).await {
+        Ok(()) => PRINT_DPI,
+        Err(_) => DRAFT_DPI,
+    })
+}
+
+/// Renders a page as it will be printed with `options`: the whole sheet of
+/// paper at the resolution it's printed at, as the same raw buffer as
+/// `render_page`.
+///
+/// Passing a `job_id` allows the render to be aborted with `cancel_render`.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn render_print_preview(
+    doc_id: DocId,
+    page: i32,
+    options: PrintOptions,
+    job_id: Option<JobId>,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    jobs: State<'_, RenderJobs>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<Response, String> {
+    let doc = store.get(doc_id)?;
+    doc.check_page(page)?;
+    check_options(&options)?;
+    let dpi = print_dpi(&policy, &doc).await?;
+
+    let token = jobs.begin(job_id, doc_id, page);
+    let target = doc.clone();
+    let cancel = token.clone();
+    let sheet = engine
+        .run(move |worker| {
+            cancel.check()?;
+            render_sheet(worker, &target, page, &options, dpi, &cancel)
+        })
+        .await;
+    jobs.finish(job_id, doc_id, page, &token);
+    Ok(Response::new(sheet?))
+}
+
+/// Prints the pages selected by `options.ranges`, or all pages, and returns
+/// how many were printed.
+///
+/// Emits `print-progress` events after each page. Passing a `job_id` allows
+/// the print job to be aborted with `cancel_render` until all pages are
+/// handed over; nothing is printed then.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn print_document(
+    app: AppHandle,
+    doc_id: DocId,
+    options: PrintOptions,
+    job_id: Option<JobId>,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    jobs: State<'_, RenderJobs>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<usize, String> {
+    let doc = store.get(doc_id)?;
+    check_options(&options)?;
+    let dpi = print_dpi(&policy, &doc).await?;
+    let pages = match &options.ranges {
+        Some(ranges) => parse_page_ranges(ranges, doc.page_count)?,
+        None => (0..doc.page_count).collect(),
+    };
+    if pages.is_empty() {
+        return Err("No pages to print".into());
+    }
+    let title = doc.path.file_name().map_or("Document".into(), |name| {
+        name.to_string_lossy().into_owned()
+    });
+
+    let mut job = {
+        let options = options.clone();
+        blocking(move || platform::Job::start(&title, &options)).await?
+    };
+    let token = jobs.begin_batch(job_id);
+    let total = pages.len();
+    for (done, page) in pages.into_iter().enumerate() {
+        let target = doc.clone();
+        let cancel = token.clone();
+        let sheet_options = options.clone();
+        let bitmap = engine
+            .run(move |worker| {
+                cancel.check()?;
+                render_sheet(worker, &target, page, &sheet_options, dpi, &cancel)
+            })
+            .await;
+        let added = match bitmap {
+            Ok(bitmap) => {
+                blocking(move || {
+                    job.add_page(&PrintPage::new(&bitmap, dpi))?;
+                    Ok(job)
+                })
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        match added {
+            Ok(added) => job = added,
+            // A failed page takes the job with it, which cancels it
+            Err(e) => {
+                jobs.finish_batch(job_id);
+                return Err(e);
+            }
+        }
+        let _ = app.emit(
+            "print-progress",
+            PrintProgress {
+                job_id,
+                done: done + 1,
+                total,
+            },
+        );
+    }
+    jobs.finish_batch(job_id);
+
+    let handle = app.clone();
+    blocking(move || job.finish(&handle)).await?;
+    eprintln!(
+        "Printed {} pages of document {} ({} copies) on {}",
+        total,
+        doc_id,
+        options.copies,
+        options.printer.as_deref().unwrap_or("the default printer")
+    );
+    Ok(total)
+}
+
+// Where a sheet of `width` by `height` goes on paper of `paper_width` by
+// `paper_height`: scaled to fit and centred. Sheets of the printer's paper
+// fill it exactly
+#[cfg(windows)]
+fn fit(width: f32, height: f32, paper_width: f32, paper_height: f32) -> (f32, f32, f32, f32) {
+    let zoom = (paper_width / width.max(1.0)).min(paper_height / height.max(1.0));
+    let (w, h) = (width * zoom, height * zoom);
+    ((paper_width - w) / 2.0, (paper_height - h) / 2.0, w, h)
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::ffi::c_void;
+    use std::ptr;
+
+    use super::{fit, Duplex, Paper, PrintOptions, PrintPage};
+
+    // The start of DEVMODEW, as far as it's changed here
+    #[repr(C)]
+    struct DevMode {
+        device_name: [u16; 32],
+        spec_version: u16,
+        driver_version: u16,
+        size: u16,
+        driver_extra: u16,
+        fields: u32,
+        orientation: i16,
+        paper_size: i16,
+        paper_length: i16,
+        paper_width: i16,
+        scale: i16,
+        copies: i16,
+        default_source: i16,
+        print_quality: i16,
+        color: i16,
+        duplex: i16,
+        y_resolution: i16,
+        tt_option: i16,
+        collate: i16,
+    }
+
+    #[repr(C)]
+    struct DocInfo {
+        size: i32,
+        doc_name: *const u16,
+        output: *const u16,
+        datatype: *const u16,
+        kind: u32,
+    }
+
+    #[repr(C)]
+    struct BitmapInfoHeader {
+        size: u32,
+        width: i32,
+        height: i32,
+        planes: u16,
+        bit_count: u16,
+        compression: u32,
+        size_image: u32,
+        x_pels_per_meter: i32,
+        y_pels_per_meter: i32,
+        clr_used: u32,
+        clr_important: u32,
+    }
+
+    const DM_OUT_BUFFER: u32 = 2;
+    const DM_IN_BUFFER: u32 = 8;
+    const DM_PAPERSIZE: u32 = 0x2;
+    const DM_COPIES: u32 = 0x100;
+    const DM_DUPLEX: u32 = 0x1000;
+    const DM_COLLATE: u32 = 0x8000;
+    const DMDUP_SIMPLEX: i16 = 1;
+    const DMDUP_VERTICAL: i16 = 2;
+    const DMDUP_HORIZONTAL: i16 = 3;
+    const DMCOLLATE_TRUE: i16 = 1;
+    const PHYSICALWIDTH: i32 = 110;
+    const PHYSICALHEIGHT: i32 = 111;
+    const PHYSICALOFFSETX: i32 = 112;
+    const PHYSICALOFFSETY: i32 = 113;
+    const HALFTONE: i32 = 4;
+    const DIB_RGB_COLORS: u32 = 0;
+    const SRCCOPY: u32 = 0x00CC_0020;
+
+    #[link(name = "winspool")]
+    extern "system" {
+        fn GetDefaultPrinterW(buffer: *mut u16, size: *mut u32) -> i32;
+        fn OpenPrinterW(name: *const u16, printer: *mut isize, defaults: *const c_void) -> i32;
+        fn ClosePrinter(printer: isize) -> i32;
+        fn DocumentPropertiesW(
+            window: *mut c_void,
+            printer: isize,
+            device_name: *const u16,
+            output: *mut c_void,
+            input: *const c_void,
+            mode: u32,
+        ) -> i32;
+    }
+
+    #[link(name = "gdi32")]
+    extern "system" {
+        fn CreateDCW(
+            driver: *const u16,
+            device: *const u16,
+            port: *const u16,
+            devmode: *const c_void,
+        ) -> isize;
+        fn DeleteDC(dc: isize) -> i32;
+        fn StartDocW(dc: isize, info: *const DocInfo) -> i32;
+        fn EndDoc(dc: isize) -> i32;
+        fn AbortDoc(dc: isize) -> i32;
+        fn StartPage(dc: isize) -> i32;
+        fn EndPage(dc: isize) -> i32;
+        fn GetDeviceCaps(dc: isize, index: i32) -> i32;
+        fn SetStretchBltMode(dc: isize, mode: i32) -> i32;
+        fn StretchDIBits(
+            dc: isize,
+            x: i32,
+            y: i32,
+            width: i32,
+            height: i32,
+            src_x: i32,
+            src_y: i32,
+            src_width: i32,
+            src_height: i32,
+            bits: *const c_void,
+            info: *const BitmapInfoHeader,
+            usage: u32,
+            rop: u32,
+        ) -> i32;
+    }
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(Some(0)).collect()
+    }
+
+    fn default_printer() -> Result<Vec<u16>, String> {
+        let mut size = 0u32;
+        unsafe { GetDefaultPrinterW(ptr::null_mut(), &mut size) };
+        let mut name = vec![0u16; size.max(1) as usize];
+        if unsafe { GetDefaultPrinterW(name.as_mut_ptr(), &mut size) } == 0 {
+            return Err("There is no default printer".into());
+        }
+        Ok(name)
+    }
+
+    fn paper_code(paper: Paper) -> i16 {
+        match paper {
+            Paper::Letter => 1,
+            Paper::Tabloid => 3,
+            Paper::Legal => 5,
+            Paper::A3 => 8,
+            Paper::A4 => 9,
+            Paper::A5 => 11,
+        }
+    }
+
+    // The printer's settings with the options applied, as a DEVMODEW and what
+    // its driver keeps after it. u64s keep it aligned
+    fn settings(printer: &[u16], options: &PrintOptions) -> Result<Vec<u64>, String> {
+        let mut handle = 0;
+        if unsafe { OpenPrinterW(printer.as_ptr(), &mut handle, ptr::null()) } == 0 {
+            return Err(format!(
+                "There is no printer named {}",
+                String::from_utf16_lossy(&printer[..printer.len() - 1])
+            ));
+        }
+        let size = unsafe {
+            DocumentPropertiesW(
+                ptr::null_mut(),
+                handle,
+                printer.as_ptr(),
+                ptr::null_mut(),
+                ptr::null(),
+                0,
+            )
+        };
+        if size < std::mem::size_of::<DevMode>() as i32 {
+            unsafe { ClosePrinter(handle) };
+            return Err("Failed to read the printer's settings".into());
+        }
+        let mut buffer = vec![0u64; (size as usize).div_ceil(8)];
+        let devmode = buffer.as_mut_ptr() as *mut c_void;
+        let mut read = unsafe {
+            DocumentPropertiesW(
+                ptr::null_mut(),
+                handle,
+                printer.as_ptr(),
+                devmode,
+                ptr::null(),
+                DM_OUT_BUFFER,
+            )
+        };
+        if read >= 0 {
+            // SAFETY: the buffer holds a DEVMODEW the driver just wrote
+            let fields = unsafe { &mut *(devmode as *mut DevMode) };
+            fields.copies = options.copies as i16;
+            fields.collate = DMCOLLATE_TRUE;
+            fields.duplex = match options.duplex {
+                Duplex::Simplex => DMDUP_SIMPLEX,
+                Duplex::LongEdge => DMDUP_VERTICAL,
+                Duplex::ShortEdge => DMDUP_HORIZONTAL,
+            };
+            fields.fields |= DM_COPIES | DM_COLLATE | DM_DUPLEX;
+            if let Some(paper) = options.paper {
+                fields.paper_size = paper_code(paper);
+                fields.fields |= DM_PAPERSIZE;
+            }
+            // The driver settles what it can't do
+            read = unsafe {
+                DocumentPropertiesW(
+                    ptr::null_mut(),
+                    handle,
+                    printer.as_ptr(),
+                    devmode,
+                    devmode,
+                    DM_IN_BUFFER | DM_OUT_BUFFER,
+                )
+            };
+        }
+        unsafe { ClosePrinter(handle) };
+        if read < 0 {
+            return Err("Failed to apply the print settings".into());
+        }
+        Ok(buffer)
+    }
+
+    /// A document being printed on a printer's device context.
+    pub struct Job {
+        dc: isize,
+        finished: bool,
+    }
+
+    impl Job {
+        pub fn start(title: &str, options: &PrintOptions) -> Result<Job, String> {
+            let printer = match &options.printer {
+                Some(printer) => wide(printer),
+                None => default_printer()?,
+            };
+            let devmode = settings(&printer, options)?;
+            let driver = wide("WINSPOOL");
+            let dc = unsafe {
+                CreateDCW(
+                    driver.as_ptr(),
+                    printer.as_ptr(),
+                    ptr::null(),
+                    devmode.as_ptr() as *const c_void,
+                )
+            };
+            if dc == 0 {
+                return Err("Failed to connect to the printer".into());
+            }
+            let title = wide(title);
+            let info = DocInfo {
+                size: std::mem::size_of::<DocInfo>() as i32,
+                doc_name: title.as_ptr(),
+                output: ptr::null(),
+                datatype: ptr::null(),
+                kind: 0,
+            };
+            if unsafe { StartDocW(dc, &info) } <= 0 {
+                unsafe { DeleteDC(dc) };
+                return Err("The printer refused the document".into());
+            }
+            Ok(Job {
+                dc,
+                finished: false,
+            })
+        }
+
+        pub fn add_page(&mut self, page: &PrintPage) -> Result<(), String> {
+            // GDI wants BGRA rows
+            let pixels: Vec<u8> = page
+                .pixels()
+                .chunks_exact(4)
+                .flat_map(|rgba| [rgba[2], rgba[1], rgba[0], 0])
+                .collect();
+            let header = BitmapInfoHeader {
+                size: std::mem::size_of::<BitmapInfoHeader>() as u32,
+                width: page.width as i32,
+                // Negative for rows from the top down
+                height: -(page.height as i32),
+                planes: 1,
+                bit_count: 32,
+                compression: 0,
+                size_image: 0,
+                x_pels_per_meter: 0,
+                y_pels_per_meter: 0,
+                clr_used: 0,
+                clr_important: 0,
+            };
+            unsafe {
+                if StartPage(self.dc) <= 0 {
+                    return Err("The printer refused a page".into());
+                }
+                // Drawing starts at the printable area, inside the paper's
+                // edges
+                let (paper_width, paper_height) = (
+                    GetDeviceCaps(self.dc, PHYSICALWIDTH) as f32,
+                    GetDeviceCaps(self.dc, PHYSICALHEIGHT) as f32,
+                );
+                let (offset_x, offset_y) = (
+                    GetDeviceCaps(self.dc, PHYSICALOFFSETX) as f32,
+                    GetDeviceCaps(self.dc, PHYSICALOFFSETY) as f32,
+                );
+                let (x, y, w, h) = fit(
+                    page.width as f32,
+                    page.height as f32,
+                    paper_width,
+                    paper_height,
+                );
+                let (x, y) = (x - offset_x, y - offset_y);
+                SetStretchBltMode(self.dc, HALFTONE);
+                let drawn = StretchDIBits(
+                    self.dc,
+                    x as i32,
+                    y as i32,
+                    w as i32,
+                    h as i32,
+                    0,
+                    0,
+                    page.width as i32,
+                    page.height as i32,
+                    pixels.as_ptr() as *const c_void,
+                    &header,
+                    DIB_RGB_COLORS,
+                    SRCCOPY,
+                );
+                if drawn == 0 {
+                    return Err("Failed to draw a page for the printer".into());
+                }
+                if EndPage(self.dc) <= 0 {
+                    return Err("The printer refused a page".into());
+                }
+            }
+            Ok(())
+        }
+
+        pub fn finish(mut self, _app: &tauri::AppHandle) -> Result<(), String> {
+            self.finished = true;
+            match unsafe { EndDoc(self.dc) } {
+                ended if ended > 0 => Ok(()),
+                _ => Err("The printer refused the document".into()),
+            }
+        }
+    }
+
+    impl Drop for Job {
+        fn drop(&mut self) {
+            unsafe {
+                if !self.finished {
+                    AbortDoc(self.dc);
+                }
+                DeleteDC(self.dc);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::ffi::{c_char, CStr, CString};
+    use std::sync::mpsc;
+
+    use tauri::AppHandle;
+
+    use super::{Duplex, PrintOptions, PrintPage};
+
+    extern "C" {
+        fn print_pages(
+            title: *const c_char,
+            printer: *const c_char,
+            copies: u32,
+            duplex: u32,
+            paper_width: f64,
+            paper_height: f64,
+            pages: *const PageImage,
+            count: usize,
+            error: *mut c_char,
+            error_len: usize,
+        ) -> bool;
+    }
+
+    // A page as `print.m` takes it: RGBA pixels, and its size in points
+    #[repr(C)]
+    struct PageImage {
+        pixels: *const u8,
+        width: u32,
+        height: u32,
+        points_width: f64,
+        points_height: f64,
+    }
+
+    struct Page {
+        pixels: Vec<u8>,
+        width: u32,
+        height: u32,
+        points: (f32, f32),
+    }
+
+    /// The pages of a print job, printed together once all are rendered.
+    pub struct Job {
+        title: CString,
+        options: PrintOptions,
+        pages: Vec<Page>,
+    }
+
+    impl Job {
+        pub fn start(title: &str, options: &PrintOptions) -> Result<Job, String> {
+            Ok(Job {
+                title: CString::new(title).unwrap_or_default(),
+                options: options.clone(),
+                pages: Vec::new(),
+            })
+        }
+
+        pub fn add_page(&mut self, page: &PrintPage) -> Result<(), String> {
+            self.pages.push(Page {
+                pixels: page.pixels().to_vec(),
+                width: page.width,
+                height: page.height,
+                points: page.points,
+            });
+            Ok(())
+        }
+
+        pub fn finish(self, app: &AppHandle) -> Result<(), String> {
+            let (sender, receiver) = mpsc::channel();
+            // AppKit prints on the main thread
+            app.run_on_main_thread(move || {
+                let _ = sender.send(self.print());
+            })
+            .map_err(|e| e.to_string())?;
+            receiver
+                .recv()
+                .map_err(|_| "The print job was dropped".to_string())?
+        }
+
+        fn print(&self) -> Result<(), String> {
+            let printer = self
+                .options
+                .printer
+                .as_deref()
+                .map(|printer| CString::new(printer).unwrap_or_default());
+            let (paper_width, paper_height) = self.options.paper.map_or((0.0, 0.0), |paper| {
+                let (w, h) = paper.points();
+                (w as f64, h as f64)
+            });
+            let images: Vec<PageImage> = self
+                .pages
+                .iter()
+                .map(|page| PageImage {
+                    pixels: page.pixels.as_ptr(),
+                    width: page.width,
+                    height: page.height,
+                    points_width: page.points.0 as f64,
+                    points_height: page.points.1 as f64,
+                })
+                .collect();
+            let duplex = match self.options.duplex {
+                Duplex::Simplex => 1,
+                Duplex::LongEdge => 2,
+                Duplex::ShortEdge => 3,
+            };
+            let mut error = [0 as c_char; 256];
+            // SAFETY: the pages and strings outlive the call, which copies
+            // what it keeps
+            let printed = unsafe {
+                print_pages(
+                    self.title.as_ptr(),
+                    printer.as_ref().map_or(std::ptr::null(), |p| p.as_ptr()),
+                    self.options.copies,
+                    duplex,
+                    paper_width,
+                    paper_height,
+                    images.as_ptr(),
+                    images.len(),
+                    error.as_mut_ptr(),
+                    error.len(),
+                )
+            };
+            match printed {
+                true => Ok(()),
+                false => Err(unsafe { CStr::from_ptr(error.as_ptr()) }
+                    .to_string_lossy()
+                    .into_owned()),
+            }
+        }
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    use lopdf::content::{Content, Operation};
+    use lopdf::{dictionary, Document, Object, Stream};
+    use tauri::AppHandle;
+
+    use super::{Duplex, Paper, PrintOptions, PrintPage};
+    use crate::export::encode_jpeg;
+
+    /// The pages of a print job as a PDF for CUPS, sent once it's complete.
+    pub struct Job {
+        title: String,
+        options: PrintOptions,
+        doc: Document,
+        pages_id: lopdf::ObjectId,
+        kids: Vec<Object>,
+    }
+
+    fn media(paper: Paper) -> &'static str {
+        match paper {
+            Paper::A3 => "A3",
+            Paper::A4 => "A4",
+            Paper::A5 => "A5",
+            Paper::Letter => "Letter",
+            Paper::Legal => "Legal",
+            Paper::Tabloid => "Tabloid",
+        }
+    }
+
+    impl Job {
+        pub fn start(title: &str, options: &PrintOptions) -> Result<Job, String> {
+            let mut doc = Document::with_version("1.7");
+            let pages_id = doc.new_object_id();
+            Ok(Job {
+                title: title.to_owned(),
+                options: options.clone(),
+                doc,
+                pages_id,
+                kids: Vec::new(),
+            })
+        }
+
+        pub fn add_page(&mut self, page: &PrintPage) -> Result<(), String> {
+            let rgb: Vec<u8> = page
+                .pixels()
+                .chunks_exact(4)
+                .flat_map(|rgba| [rgba[0], rgba[1], rgba[2]])
+                .collect();
+            let dpi = page.width as f32 * 72.0 / page.points.0.max(1.0);
+            let jpeg = encode_jpeg(&rgb, page.width, page.height, dpi)?;
+            let image = self.doc.add_object(
+                Stream::new(
+                    dictionary! {
+                        "Type" => "XObject",
+                        "Subtype" => "Image",
+                        "Width" => page.width,
+                        "Height" => page.height,
+                        "ColorSpace" => "DeviceRGB",
+                        "BitsPerComponent" => 8,
+                        "Filter" => "DCTDecode",
+                    },
+                    jpeg,
+                )
+                .with_compression(false),
+            );
+            let (w, h) = page.points;
+            let operations = vec![
+                Operation::new("q", vec![]),
+                Operation::new(
+                    "cm",
+                    vec![w.into(), 0.into(), 0.into(), h.into(), 0.into(), 0.into()],
+                ),
+                Operation::new("Do", vec![Object::Name(b"Im1".to_vec())]),
+                Operation::new("Q", vec![]),
+            ];
+            let content = Content { operations }
+                .encode()
+                .map_err(|e| format!("Failed to write page content: {}", e))?;
+            let contents = self
+                .doc
+                .add_object(Stream::new(lopdf::Dictionary::new(), content));
+            let page_id = self.doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => self.pages_id,
+                "MediaBox" => vec![0.into(), 0.into(), w.into(), h.into()],
+                "Resources" => dictionary! {
+                    "XObject" => dictionary! { "Im1" => image },
+                },
+                "Contents" => contents,
+            });
+            self.kids.push(Object::Reference(page_id));
+            Ok(())
+        }
+
+        pub fn finish(mut self, _app: &AppHandle) -> Result<(), String> {
+            let count = self.kids.len() as i32;
+            self.doc.objects.insert(
+                self.pages_id,
+                Object::Dictionary(dictionary! {
+                    "Type" => "Pages",
+                    "Kids" => std::mem::take(&mut self.kids),
+                    "Count" => count,
+                }),
+            );
+            let catalog_id = self.doc.add_object(dictionary! {
+                "Type" => "Catalog",
+                "Pages" => self.pages_id,
+            });
+            self.doc.trailer.set("Root", catalog_id);
+
+            // Handed to lp on its standard input, so no other user gets to
+            // read or swap the file on its way to the printer
+            self.doc.compress();
+            let mut pdf = Vec::new();
+            self.doc
+                .save_to(&mut pdf)
+                .map_err(|e| format!("Failed to write the pages to print: {}", e))?;
+            let mut command = Command::new("lp");
+            if let Some(printer) = &self.options.printer {
+                command.arg("-d").arg(printer);
+            }
+            command
+                .arg("-t")
+                .arg(&self.title)
+                .arg("-n")
+                .arg(self.options.copies.to_string())
+                .arg("-o")
+                .arg(match self.options.duplex {
+                    Duplex::Simplex => "sides=one-sided",
+                    Duplex::LongEdge => "sides=two-sided-long-edge",
+                    Duplex::ShortEdge => "sides=two-sided-short-edge",
+                })
+                .args(["-o", "collate=true"]);
+            // Sheets of the chosen paper are printed as they are, others
+            // fitted to the printer's
+            match self.options.paper {
+                Some(paper) => command.arg("-o").arg(format!("media={}", media(paper))),
+                None => command.args(["-o", "fit-to-page"]),
+            };
+            let (output, written) = command
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .and_then(|mut lp| {
+                    // Dropping stdin once written ends the file for lp
+                    let written = lp.stdin.take().unwrap().write_all(&pdf);
+                    lp.wait_with_output().map(|output| (output, written))
+                })
+                .map_err(|e| format!("Failed to run lp: {}", e))?;
+            if !output.status.success() {
+                return Err(format!(
+                    "Printing failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            written.map_err(|e| format!("Failed to send the pages to lp: {}", e))
+        }
+    }
+}
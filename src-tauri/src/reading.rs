@@ -0,0 +1,279 @@
+// Where the user was in each document.
+//
+// The page, the position on it, the zoom and the view mode are saved as the
+// user reads, in a SQLite database at `<app data>/reading.sqlite3` keyed by
+// the fingerprint of the file like the annotations in `sidecar.rs`, so a
+// moved or renamed file opens where it was left. The position is kept as
+// fractions of the page's width and height, which hold whatever the zoom or
+// the size of the window. Only the documents read last are remembered.
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::pdf::{blocking, DocId, DocumentStore};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS reading_state (
+        fingerprint TEXT PRIMARY KEY,
+        page INTEGER NOT NULL,
+        scroll_x REAL NOT NULL,
+        scroll_y REAL NOT NULL,
+        zoom REAL NOT NULL,
+        view_mode TEXT NOT NULL,
+        updated INTEGER NOT NULL
+    );
+";
+// Documents remembered
+const MAX_DOCUMENTS: i64 = 1000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ViewMode {
+    SinglePage,
+    #[default]
+    Continuous,
+    TwoPage,
+    TwoPageContinuous,
+}
+
+impl ViewMode {
+    const ALL: [ViewMode; 4] = [
+        ViewMode::SinglePage,
+        ViewMode::Continuous,
+        ViewMode::TwoPage,
+        ViewMode::TwoPageContinuous,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            ViewMode::SinglePage => "singlePage",
+            ViewMode::Continuous => "continuous",
+            ViewMode::TwoPage => "twoPage",
+            ViewMode::TwoPageContinuous => "twoPageContinuous",
+        }
+    }
+
+    fn from_name(name: &str) -> Self {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.name() == name)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentState {
+    /// Counted from 0
+    pub page: i32,
+    /// How far across the page the view is, from 0 to 1
+    pub scroll_x: f64,
+    /// How far down the page the view is, from 0 to 1
+    pub scroll_y: f64,
+    pub zoom: f32,
+    pub view_mode: ViewMode,
+}
+
+/// Where the user was in a document, as settings archives keep it (see
+/// `transfer.rs`).
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedState {
+    pub fingerprint: String,
+    #[serde(flatten)]
+    pub state: DocumentState,
+    /// Seconds since the Unix epoch
+    pub updated: i64,
+}
+
+fn database_file(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("reading.sqlite3"))
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))
+}
+
+fn open(file: &Path) -> Result<Connection, String> {
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let db =
+        Connection::open(file).map_err(|e| format!("Failed to open {}: {}", file.display(), e))?;
+    db.execute_batch(SCHEMA)
+        .map_err(|e| format!("Failed to set up {}: {}", file.display(), e))?;
+    Ok(db)
+}
+
+fn fraction(value: f64) -> f64 {
+    match value.is_finite() {
+        true => value.clamp(0.0, 1.0),
+        false => 0.0,
+    }
+}
+
+/// Where the user was in every document remembered.
+pub fn to_archive(app: &AppHandle) -> Result<Vec<ArchivedState>, String> {
+    let db = open(&database_file(app)?)?;
+    let mut statement = db
+        .prepare(
+            "SELECT fingerprint, page, scroll_x, scroll_y, zoom, view_mode, updated
+             FROM reading_state ORDER BY updated DESC",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = statement
+        .query_map(params![], |row| {
+            Ok(ArchivedState {
+                fingerprint: row.get(0)?,
+                state: DocumentState {
+                    page: row.get(1)?,
+                    scroll_x: row.get(2)?,
+                    scroll_y: row.get(3)?,
+                    zoom: row.get::<_, f64>(4)? as f32,
+                    view_mode: ViewMode::from_name(&row.get::<_, String>(5)?),
+                },
+                updated: row.get(6)?,
+            })
+        })
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+}
+
+/// Takes over archived places in documents, where they're more recent than
+/// those remembered. Returns the number taken.
+pub fn from_archive(app: &AppHandle, states: Vec<ArchivedState>) -> Result<usize, String> {
+    let mut db = open(&database_file(app)?)?;
+    let transaction = db.transaction().map_err(|e| e.to_string())?;
+    let mut taken = 0;
+    for ArchivedState {
+        fingerprint,
+        state,
+        updated,
+    } in states
+    {
+        if state.page < 0 || !state.zoom.is_finite() || state.zoom <= 0.0 {
+            continue;
+        }
+        taken += transaction
+            .execute(
+                "INSERT INTO reading_state
+                     (fingerprint, page, scroll_x, scroll_y, zoom, view_mode, updated)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                 ON CONFLICT (fingerprint) DO UPDATE SET
+                     page = excluded.page, scroll_x = excluded.scroll_x,
+                     scroll_y = excluded.scroll_y, zoom = excluded.zoom,
+                     view_mode = excluded.view_mode, updated = excluded.updated
+                 WHERE excluded.updated > reading_state.updated",
+                params![
+                    fingerprint,
+                    state.page,
+                    fraction(state.scroll_x),
+                    fraction(state.scroll_y),
+                    state.zoom as f64,
+                    state.view_mode.name(),
+                    updated
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+    }
+    transaction
+        .execute(
+            "DELETE FROM reading_state WHERE fingerprint NOT IN
+                 (SELECT fingerprint FROM reading_state ORDER BY updated DESC LIMIT ?1)",
+            params![MAX_DOCUMENTS],
+        )
+        .map_err(|e| e.to_string())?;
+    transaction.commit().map_err(|e| e.to_string())?;
+    Ok(taken)
+}
+
+/// Where the user left the document, if it was read before. Pages past the
+/// end, as after pages were deleted, become the last page.
+#[tauri::command]
+pub async fn get_document_state(
+    app: AppHandle,
+    doc_id: DocId,
+    store: State<'_, DocumentStore>,
+) -> Result<Option<DocumentState>, String> {
+    let doc = store.get(doc_id)?;
+    let file = database_file(&app)?;
+    blocking(move || {
+        let db = open(&file)?;
+        let state = db
+            .query_row(
+                "SELECT page, scroll_x, scroll_y, zoom, view_mode FROM reading_state
+                 WHERE fingerprint = ?1",
+                params![doc.fingerprint()],
+                |row| {
+                    Ok(DocumentState {
+                        page: row.get(0)?,
+                        scroll_x: row.get(1)?,
+                        scroll_y: row.get(2)?,
+                        zoom: row.get::<_, f64>(3)? as f32,
+                        view_mode: ViewMode::from_name(&row.get::<_, String>(4)?),
+                    })
+                },
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        Ok(state.map(|state| DocumentState {
+            page: state.page.clamp(0, doc.page_count.max(1) - 1),
+            ..state
+        }))
+    })
+    .await
+}
+
+/// Saves where the user is in the document.
+#[tauri::command]
+pub async fn save_document_state(
+    app: AppHandle,
+    doc_id: DocId,
+    state: DocumentState,
+    store: State<'_, DocumentStore>,
+) -> Result<(), String> {
+    let doc = store.get(doc_id)?;
+    doc.check_page(state.page)?;
+    if !state.zoom.is_finite() || state.zoom <= 0.0 {
+        return Err(format!("Invalid zoom {}", state.zoom));
+    }
+    let file = database_file(&app)?;
+    blocking(move || {
+        let updated = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs() as i64);
+        let db = open(&file)?;
+        db.execute(
+            "INSERT INTO reading_state
+                 (fingerprint, page, scroll_x, scroll_y, zoom, view_mode, updated)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT (fingerprint) DO UPDATE SET
+                 page = excluded.page, scroll_x = excluded.scroll_x,
+                 scroll_y = excluded.scroll_y, zoom = excluded.zoom,
+                 view_mode = excluded.view_mode, updated = excluded.updated",
+            params![
+                doc.fingerprint(),
+                state.page,
+                fraction(state.scroll_x),
+                fraction(state.scroll_y),
+                state.zoom as f64,
+                state.view_mode.name(),
+                updated
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        // Forgets the documents read longest ago
+        db.execute(
+            "DELETE FROM reading_state WHERE fingerprint NOT IN
+                 (SELECT fingerprint FROM reading_state ORDER BY updated DESC LIMIT ?1)",
+            params![MAX_DOCUMENTS],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    })
+    .await
+}
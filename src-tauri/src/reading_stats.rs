@@ -0,0 +1,283 @@
+// How much of each document the user has read, and for how long.
+//
+// While a document is in front the frontend reports, every so often, the page
+// being read and the seconds spent on it since the last report; the time of a
+// report is capped so a window left open overnight doesn't count. The time
+// is added up per document, per page and per day in a SQLite database at
+// `<app data>/reading-stats.sqlite3`, keyed by the fingerprint of the file as
+// in `reading.rs`. A page counts as viewed once it's been in front for a few
+// seconds, so paging past it doesn't; the share of pages viewed is how far
+// through the document the user is.
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, Row};
+use serde::Serialize;
+use tauri::{AppHandle, Manager, State};
+
+use crate::pdf::{blocking, DocId, DocumentStore};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS documents (
+        fingerprint TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        path TEXT NOT NULL,
+        page_count INTEGER NOT NULL,
+        seconds REAL NOT NULL,
+        first_read INTEGER NOT NULL,
+        last_read INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS pages (
+        fingerprint TEXT NOT NULL,
+        page INTEGER NOT NULL,
+        seconds REAL NOT NULL,
+        PRIMARY KEY (fingerprint, page)
+    );
+    CREATE TABLE IF NOT EXISTS days (
+        fingerprint TEXT NOT NULL,
+        day INTEGER NOT NULL,
+        seconds REAL NOT NULL,
+        PRIMARY KEY (fingerprint, day)
+    );
+";
+// The most a single report counts for
+const MAX_REPORT_SECONDS: f64 = 120.0;
+// How long a page is in front before it counts as viewed
+const VIEWED_SECONDS: f64 = 3.0;
+// Days of daily totals returned
+const DAYS: i64 = 30;
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentStats {
+    pub fingerprint: String,
+    /// The file name it was last read under
+    pub name: String,
+    pub path: PathBuf,
+    pub page_count: i32,
+    pub pages_viewed: i32,
+    /// The share of pages viewed, from 0 to 1
+    pub completion: f64,
+    pub reading_seconds: f64,
+    /// Milliseconds since the Unix epoch
+    pub first_read: i64,
+    pub last_read: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyReading {
+    /// Days since the Unix epoch, in UTC
+    pub day: i64,
+    pub seconds: f64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadingStats {
+    /// Read most recently first
+    pub documents: Vec<DocumentStats>,
+    pub total_seconds: f64,
+    /// The last days' reading time, oldest first, days without any included
+    pub daily: Vec<DailyReading>,
+}
+
+fn database_file(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("reading-stats.sqlite3"))
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))
+}
+
+fn open(file: &Path) -> Result<Connection, String> {
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let db =
+        Connection::open(file).map_err(|e| format!("Failed to open {}: {}", file.display(), e))?;
+    db.execute_batch(SCHEMA)
+        .map_err(|e| format!("Failed to set up {}: {}", file.display(), e))?;
+    Ok(db)
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis() as i64)
+}
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<DocumentStats> {
+    let page_count: i32 = row.get(3)?;
+    let pages_viewed: i32 = row.get(4)?;
+    Ok(DocumentStats {
+        fingerprint: row.get(0)?,
+        name: row.get(1)?,
+        path: PathBuf::from(row.get::<_, String>(2)?),
+        page_count,
+        pages_viewed,
+        completion: match page_count {
+            0 => 0.0,
+            _ => (pages_viewed as f64 / page_count as f64).min(1.0),
+        },
+        reading_seconds: row.get(5)?,
+        first_read: row.get(6)?,
+        last_read: row.get(7)?,
+    })
+}
+
+/// Adds the seconds spent reading a page of a document since the last report.
+#[tauri::command]
+pub async fn record_reading(
+    app: AppHandle,
+    doc_id: DocId,
+    page: i32,
+    seconds: f64,
+    store: State<'_, DocumentStore>,
+) -> Result<(), String> {
+    let doc = store.get(doc_id)?;
+    doc.check_page(page)?;
+    if !seconds.is_finite() || seconds <= 0.0 {
+        return Ok(());
+    }
+    let seconds = seconds.min(MAX_REPORT_SECONDS);
+    let file = database_file(&app)?;
+    blocking(move || {
+        let read = now();
+        let name = doc
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut db = open(&file)?;
+        let transaction = db.transaction().map_err(|e| e.to_string())?;
+        transaction
+            .execute(
+                "INSERT INTO documents
+                     (fingerprint, name, path, page_count, seconds, first_read, last_read)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)
+                 ON CONFLICT (fingerprint) DO UPDATE SET
+                     name = excluded.name, path = excluded.path,
+                     page_count = excluded.page_count,
+                     seconds = seconds + excluded.seconds, last_read = excluded.last_read",
+                params![
+                    doc.fingerprint(),
+                    name,
+                    doc.path.to_string_lossy().into_owned(),
+                    doc.page_count,
+                    seconds,
+                    read
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+        transaction
+            .execute(
+                "INSERT INTO pages (fingerprint, page, seconds) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (fingerprint, page) DO UPDATE SET
+                     seconds = seconds + excluded.seconds",
+                params![doc.fingerprint(), page, seconds],
+            )
+            .map_err(|e| e.to_string())?;
+        transaction
+            .execute(
+                "INSERT INTO days (fingerprint, day, seconds) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (fingerprint, day) DO UPDATE SET
+                     seconds = seconds + excluded.seconds",
+                params![doc.fingerprint(), read / 1000 / SECONDS_PER_DAY, seconds],
+            )
+            .map_err(|e| e.to_string())?;
+        transaction.commit().map_err(|e| e.to_string())
+    })
+    .await
+}
+
+/// The reading statistics of every document read, or of one.
+#[tauri::command]
+pub async fn get_reading_stats(
+    app: AppHandle,
+    doc_id: Option<DocId>,
+    store: State<'_, DocumentStore>,
+) -> Result<ReadingStats, String> {
+    let fingerprint = match doc_id {
+        Some(doc_id) => Some(store.get(doc_id)?.fingerprint().to_string()),
+        None => None,
+    };
+    let file = database_file(&app)?;
+    blocking(move || {
+        let db = open(&file)?;
+        // Pages past the end, as after pages were deleted, aren't counted
+        let mut statement = db
+            .prepare(&format!(
+                "SELECT d.fingerprint, d.name, d.path, d.page_count,
+                     (SELECT COUNT(*) FROM pages p WHERE p.fingerprint = d.fingerprint
+                          AND p.page < d.page_count AND p.seconds >= {}),
+                     d.seconds, d.first_read, d.last_read
+                 FROM documents d WHERE ?1 IS NULL OR d.fingerprint = ?1
+                 ORDER BY d.last_read DESC",
+                VIEWED_SECONDS
+            ))
+            .map_err(|e| e.to_string())?;
+        let rows = statement
+            .query_map(params![fingerprint], from_row)
+            .map_err(|e| e.to_string())?;
+        let documents: Vec<DocumentStats> =
+            rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?;
+
+        let today = now() / 1000 / SECONDS_PER_DAY;
+        let mut statement = db
+            .prepare(
+                "SELECT day, SUM(seconds) FROM days
+                 WHERE day > ?1 AND (?2 IS NULL OR fingerprint = ?2)
+                 GROUP BY day",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = statement
+            .query_map(params![today - DAYS, fingerprint], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, f64>(1)?))
+            })
+            .map_err(|e| e.to_string())?;
+        let read: Vec<(i64, f64)> = rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?;
+        let daily = (today - DAYS + 1..=today)
+            .map(|day| DailyReading {
+                day,
+                seconds: read
+                    .iter()
+                    .find(|(read_day, _)| *read_day == day)
+                    .map_or(0.0, |(_, seconds)| *seconds),
+            })
+            .collect();
+
+        Ok(ReadingStats {
+            total_seconds: documents.iter().map(|doc| doc.reading_seconds).sum(),
+            documents,
+            daily,
+        })
+    })
+    .await
+}
+
+/// Forgets the reading history of a document, or of every document if the
+/// fingerprint is missing.
+#[tauri::command]
+pub async fn clear_reading_stats(
+    app: AppHandle,
+    fingerprint: Option<String>,
+) -> Result<(), String> {
+    let file = database_file(&app)?;
+    blocking(move || {
+        let mut db = open(&file)?;
+        let transaction = db.transaction().map_err(|e| e.to_string())?;
+        for table in ["documents", "pages", "days"] {
+            transaction
+                .execute(
+                    &format!("DELETE FROM {} WHERE ?1 IS NULL OR fingerprint = ?1", table),
+                    params![fingerprint],
+                )
+                .map_err(|e| e.to_string())?;
+        }
+        transaction.commit().map_err(|e| e.to_string())
+    })
+    .await
+}
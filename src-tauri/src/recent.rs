@@ -0,0 +1,564 @@
+// Recent documents in the system's own lists.
+//
+// Documents opened are reported to the system, which lists them in Explorer
+// on Windows, and the app's recent files (see `recent_files.rs`) are shown in
+// the lists the system keeps for the app. On Windows that's the taskbar Jump
+// List: the pinned and recent files, above tasks that relaunch the app with a
+// flag, which a running instance is handed (see `launch.rs`): "New Window"
+// starts a process of its own, and "Open File…" shows the open dialog. Files
+// the user removes from the Jump List are dropped from the recent files. On
+// macOS they're in the Dock menu and in the File ▸ Open Recent menu added to
+// the menu bar; choosing one there opens it like a file opened from the
+// Finder (see `app_delegate.m`), and its "Clear Menu" clears the recent
+// files. Elsewhere all of this does nothing.
+
+use std::path::{Path, PathBuf};
+
+use tauri::AppHandle;
+
+/// Sets up the Jump List tasks or the Open Recent menu.
+pub fn setup(app: &AppHandle) {
+    platform::setup(app);
+}
+
+/// Adds a document to the system's recent files.
+pub fn opened(path: &Path) {
+    platform::add_recent(path);
+}
+
+/// Shows the app's recent files, each with whether it's pinned, in the
+/// system's lists.
+pub fn update(app: &AppHandle, files: &[(PathBuf, bool)]) {
+    platform::update(app, files);
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::ffi::c_void;
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+    use std::ptr;
+
+    use tauri::AppHandle;
+
+    use crate::launch::{NEW_INSTANCE_FLAG, OPEN_FILE_FLAG};
+    use crate::recent_files;
+
+    type HResult = i32;
+
+    #[repr(C)]
+    struct Guid {
+        data1: u32,
+        data2: u16,
+        data3: u16,
+        data4: [u8; 8],
+    }
+
+    #[repr(C)]
+    struct PropertyKey {
+        fmtid: Guid,
+        pid: u32,
+    }
+
+    // A PROPVARIANT holding a string it doesn't own
+    #[repr(C)]
+    struct PropVariant {
+        vt: u16,
+        reserved: [u16; 3],
+        value: *const u16,
+        padding: usize,
+    }
+
+    const CLSID_DESTINATION_LIST: Guid = Guid {
+        data1: 0x77f10cf0,
+        data2: 0x3db5,
+        data3: 0x4966,
+        data4: [0xb5, 0x20, 0xb7, 0xc5, 0x4f, 0xd3, 0x5e, 0xd6],
+    };
+    const IID_CUSTOM_DESTINATION_LIST: Guid = Guid {
+        data1: 0x6332debf,
+        data2: 0x87b5,
+        data3: 0x4670,
+        data4: [0x90, 0xc0, 0x5e, 0x57, 0xb4, 0x08, 0xa4, 0x9e],
+    };
+    const CLSID_ENUMERABLE_OBJECT_COLLECTION: Guid = Guid {
+        data1: 0x2d3468c1,
+        data2: 0x36a7,
+        data3: 0x43b6,
+        data4: [0xac, 0x24, 0xd3, 0xf0, 0x2f, 0xd9, 0x60, 0x7a],
+    };
+    const IID_OBJECT_COLLECTION: Guid = Guid {
+        data1: 0x5632b1a4,
+        data2: 0xe38a,
+        data3: 0x400a,
+        data4: [0x92, 0x8a, 0xd4, 0xcd, 0x63, 0x23, 0x02, 0x95],
+    };
+    const IID_OBJECT_ARRAY: Guid = Guid {
+        data1: 0x92ca9dcd,
+        data2: 0x5622,
+        data3: 0x4bba,
+        data4: [0xa8, 0x05, 0x5e, 0x9f, 0x54, 0x1b, 0xd8, 0xc9],
+    };
+    const CLSID_SHELL_LINK: Guid = Guid {
+        data1: 0x00021401,
+        data2: 0x0000,
+        data3: 0x0000,
+        data4: [0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+    };
+    const IID_SHELL_LINK: Guid = Guid {
+        data1: 0x000214f9,
+        data2: 0x0000,
+        data3: 0x0000,
+        data4: [0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+    };
+    const IID_PROPERTY_STORE: Guid = Guid {
+        data1: 0x886d8eeb,
+        data2: 0x8cf2,
+        data3: 0x4446,
+        data4: [0x8d, 0x02, 0xcd, 0xba, 0x1d, 0xbd, 0xcf, 0x99],
+    };
+    const PKEY_TITLE: PropertyKey = PropertyKey {
+        fmtid: Guid {
+            data1: 0xf29f85e0,
+            data2: 0x4ff9,
+            data3: 0x1068,
+            data4: [0xab, 0x91, 0x08, 0x00, 0x2b, 0x27, 0xb3, 0xd9],
+        },
+        pid: 2,
+    };
+
+    const CLSCTX_INPROC_SERVER: u32 = 1;
+    const COINIT_APARTMENTTHREADED: u32 = 2;
+    const SHARD_PATHW: u32 = 3;
+    const VT_LPWSTR: u16 = 31;
+
+    // The methods of each interface in vtable order; those not called are
+    // only placeholders
+    #[repr(C)]
+    struct UnknownVtbl {
+        query_interface:
+            unsafe extern "system" fn(*mut c_void, *const Guid, *mut *mut c_void) -> HResult,
+        add_ref: usize,
+        release: unsafe extern "system" fn(*mut c_void) -> u32,
+    }
+
+    #[repr(C)]
+    struct DestinationListVtbl {
+        base: UnknownVtbl,
+        set_app_id: usize,
+        begin_list: unsafe extern "system" fn(
+            *mut c_void,
+            *mut u32,
+            *const Guid,
+            *mut *mut c_void,
+        ) -> HResult,
+        append_category: unsafe extern "system" fn(*mut c_void, *const u16, *mut c_void) -> HResult,
+        append_known_category: unsafe extern "system" fn(*mut c_void, i32) -> HResult,
+        add_user_tasks: unsafe extern "system" fn(*mut c_void, *mut c_void) -> HResult,
+        commit_list: unsafe extern "system" fn(*mut c_void) -> HResult,
+        get_removed_destinations: usize,
+        delete_list: usize,
+        abort_list: unsafe extern "system" fn(*mut c_void) -> HResult,
+    }
+
+    #[repr(C)]
+    struct ObjectCollectionVtbl {
+        base: UnknownVtbl,
+        get_count: usize,
+        get_at: usize,
+        add_object: unsafe extern "system" fn(*mut c_void, *mut c_void) -> HResult,
+    }
+
+    #[repr(C)]
+    struct ObjectArrayVtbl {
+        base: UnknownVtbl,
+        get_count: unsafe extern "system" fn(*mut c_void, *mut u32) -> HResult,
+        get_at:
+            unsafe extern "system" fn(*mut c_void, u32, *const Guid, *mut *mut c_void) -> HResult,
+    }
+
+    #[repr(C)]
+    struct ShellLinkVtbl {
+        base: UnknownVtbl,
+        get_path: usize,
+        get_id_list: usize,
+        set_id_list: usize,
+        get_description: usize,
+        set_description: unsafe extern "system" fn(*mut c_void, *const u16) -> HResult,
+        get_working_directory: usize,
+        set_working_directory: usize,
+        get_arguments: unsafe extern "system" fn(*mut c_void, *mut u16, i32) -> HResult,
+        set_arguments: unsafe extern "system" fn(*mut c_void, *const u16) -> HResult,
+        get_hotkey: usize,
+        set_hotkey: usize,
+        get_show_cmd: usize,
+        set_show_cmd: usize,
+        get_icon_location: usize,
+        set_icon_location: unsafe extern "system" fn(*mut c_void, *const u16, i32) -> HResult,
+        set_relative_path: usize,
+        resolve: usize,
+        set_path: unsafe extern "system" fn(*mut c_void, *const u16) -> HResult,
+    }
+
+    #[repr(C)]
+    struct PropertyStoreVtbl {
+        base: UnknownVtbl,
+        get_count: usize,
+        get_at: usize,
+        get_value: usize,
+        set_value: unsafe extern "system" fn(
+            *mut c_void,
+            *const PropertyKey,
+            *const PropVariant,
+        ) -> HResult,
+        commit: unsafe extern "system" fn(*mut c_void) -> HResult,
+    }
+
+    #[link(name = "ole32")]
+    extern "system" {
+        fn CoInitializeEx(reserved: *mut c_void, flags: u32) -> HResult;
+        fn CoUninitialize();
+        fn CoCreateInstance(
+            class: *const Guid,
+            outer: *mut c_void,
+            context: u32,
+            iid: *const Guid,
+            object: *mut *mut c_void,
+        ) -> HResult;
+    }
+
+    #[link(name = "shell32")]
+    extern "system" {
+        fn SHAddToRecentDocs(flags: u32, path: *const c_void);
+    }
+
+    // An interface pointer, released when dropped
+    struct Com(*mut c_void);
+
+    impl Com {
+        // SAFETY: `T` must be the vtable of the interface the pointer is for
+        unsafe fn vtable<T>(&self) -> &T {
+            &**(self.0 as *const *const T)
+        }
+
+        fn query(&self, iid: &Guid) -> Result<Com, String> {
+            let mut object = ptr::null_mut();
+            let hr =
+                unsafe { (self.vtable::<UnknownVtbl>().query_interface)(self.0, iid, &mut object) };
+            check(hr, "Looking up an interface")?;
+            Ok(Com(object))
+        }
+    }
+
+    impl Drop for Com {
+        fn drop(&mut self) {
+            if !self.0.is_null() {
+                unsafe { (self.vtable::<UnknownVtbl>().release)(self.0) };
+            }
+        }
+    }
+
+    fn check(hr: HResult, what: &str) -> Result<(), String> {
+        if hr < 0 {
+            Err(format!("{} failed (error {:#010x})", what, hr as u32))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn create(class: &Guid, iid: &Guid) -> Result<Com, String> {
+        let mut object = ptr::null_mut();
+        let hr = unsafe {
+            CoCreateInstance(
+                class,
+                ptr::null_mut(),
+                CLSCTX_INPROC_SERVER,
+                iid,
+                &mut object,
+            )
+        };
+        check(hr, "Creating a shell object")?;
+        Ok(Com(object))
+    }
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(Some(0)).collect()
+    }
+
+    // A link to this program with `arguments`, shown as `title`
+    fn task(exe: &[u16], title: &str, arguments: &str) -> Result<Com, String> {
+        let link = create(&CLSID_SHELL_LINK, &IID_SHELL_LINK)?;
+        let (title, arguments) = (wide(title), wide(arguments));
+        unsafe {
+            let methods = link.vtable::<ShellLinkVtbl>();
+            check(
+                (methods.set_path)(link.0, exe.as_ptr()),
+                "Setting up a task",
+            )?;
+            check(
+                (methods.set_arguments)(link.0, arguments.as_ptr()),
+                "Setting up a task",
+            )?;
+            check(
+                (methods.set_icon_location)(link.0, exe.as_ptr(), 0),
+                "Setting up a task",
+            )?;
+            check(
+                (methods.set_description)(link.0, title.as_ptr()),
+                "Setting up a task",
+            )?;
+
+            // Tasks are labelled with their title property
+            let store = link.query(&IID_PROPERTY_STORE)?;
+            let methods = store.vtable::<PropertyStoreVtbl>();
+            let value = PropVariant {
+                vt: VT_LPWSTR,
+                reserved: [0; 3],
+                value: title.as_ptr(),
+                padding: 0,
+            };
+            check(
+                (methods.set_value)(store.0, &PKEY_TITLE, &value),
+                "Naming a task",
+            )?;
+            check((methods.commit)(store.0), "Naming a task")?;
+        }
+        Ok(link)
+    }
+
+    // A collection of shell links, as the Jump List takes them
+    fn collection(links: Vec<Com>) -> Result<Com, String> {
+        let collection = create(&CLSID_ENUMERABLE_OBJECT_COLLECTION, &IID_OBJECT_COLLECTION)?;
+        for link in links {
+            let methods = unsafe { collection.vtable::<ObjectCollectionVtbl>() };
+            check(
+                unsafe { (methods.add_object)(collection.0, link.0) },
+                "Adding to the Jump List",
+            )?;
+        }
+        collection.query(&IID_OBJECT_ARRAY)
+    }
+
+    // Files are links to this program with the file as its argument, so they
+    // open here whatever opens PDFs
+    fn file_link(exe: &[u16], path: &Path) -> Result<Com, String> {
+        let title = path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy();
+        task(exe, &title, &format!("\"{}\"", path.display()))
+    }
+
+    // The files the user removed from the Jump List since it was last built
+    fn removed_files(removed: &Com) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        if removed.0.is_null() {
+            return files;
+        }
+        let methods = unsafe { removed.vtable::<ObjectArrayVtbl>() };
+        let mut count = 0;
+        if unsafe { (methods.get_count)(removed.0, &mut count) } < 0 {
+            return files;
+        }
+        for index in 0..count {
+            let mut link = ptr::null_mut();
+            if unsafe { (methods.get_at)(removed.0, index, &IID_SHELL_LINK, &mut link) } < 0 {
+                continue;
+            }
+            let link = Com(link);
+            let mut arguments = [0u16; 1024];
+            let hr = unsafe {
+                (link.vtable::<ShellLinkVtbl>().get_arguments)(
+                    link.0,
+                    arguments.as_mut_ptr(),
+                    arguments.len() as i32,
+                )
+            };
+            if hr < 0 {
+                continue;
+            }
+            let end = arguments
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(arguments.len());
+            let arguments = String::from_utf16_lossy(&arguments[..end]);
+            files.push(PathBuf::from(arguments.trim_matches('"')));
+        }
+        files
+    }
+
+    fn build(
+        list: &Com,
+        slots: u32,
+        tasks: &[(&str, &str)],
+        files: &[(PathBuf, bool)],
+    ) -> Result<(), String> {
+        let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+        let exe: Vec<u16> = exe.as_os_str().encode_wide().chain(Some(0)).collect();
+        let methods = unsafe { list.vtable::<DestinationListVtbl>() };
+        // The taskbar shows as many files as the user set it to
+        let mut left = slots as usize;
+        for (name, pinned) in [("Pinned", true), ("Recent", false)] {
+            let links = files
+                .iter()
+                .filter(|(path, is_pinned)| *is_pinned == pinned && path.is_file())
+                .take(left)
+                .map(|(path, _)| file_link(&exe, path))
+                .collect::<Result<Vec<_>, _>>()?;
+            if links.is_empty() {
+                continue;
+            }
+            left -= links.len();
+            let array = collection(links)?;
+            let name = wide(name);
+            check(
+                unsafe { (methods.append_category)(list.0, name.as_ptr(), array.0) },
+                "Adding recent files",
+            )?;
+        }
+        let links = tasks
+            .iter()
+            .map(|(title, arguments)| task(&exe, title, arguments))
+            .collect::<Result<Vec<_>, _>>()?;
+        let array = collection(links)?;
+        unsafe {
+            check(
+                (methods.add_user_tasks)(list.0, array.0),
+                "Adding the tasks",
+            )?;
+            check((methods.commit_list)(list.0), "Saving the Jump List")
+        }
+    }
+
+    pub fn setup(_app: &AppHandle) {}
+
+    // On a thread of its own, as COM is set up for it
+    pub fn update(app: &AppHandle, files: &[(PathBuf, bool)]) {
+        let (app, files) = (app.clone(), files.to_vec());
+        let spawned = std::thread::Builder::new()
+            .name("jump-list".into())
+            .spawn(move || {
+                let tasks = [
+                    ("New Window", NEW_INSTANCE_FLAG),
+                    ("Open File\u{2026}", OPEN_FILE_FLAG),
+                ];
+                match set_list(&tasks, &files) {
+                    Ok(removed) if !removed.is_empty() => recent_files::forget(&app, &removed),
+                    Ok(_) => {}
+                    Err(e) => eprintln!("Failed to set up the Jump List: {}", e),
+                }
+            });
+        if let Err(e) = spawned {
+            eprintln!("Failed to set up the Jump List: {}", e);
+        }
+    }
+
+    // Builds the Jump List, leaving out the files the user removed since the
+    // last time, and returns those
+    fn set_list(tasks: &[(&str, &str)], files: &[(PathBuf, bool)]) -> Result<Vec<PathBuf>, String> {
+        let initialized = unsafe { CoInitializeEx(ptr::null_mut(), COINIT_APARTMENTTHREADED) };
+        check(initialized, "Setting up COM")?;
+        let result =
+            create(&CLSID_DESTINATION_LIST, &IID_CUSTOM_DESTINATION_LIST).and_then(|list| {
+                let methods = unsafe { list.vtable::<DestinationListVtbl>() };
+                let mut slots = 0;
+                let mut removed = ptr::null_mut();
+                let hr = unsafe {
+                    (methods.begin_list)(list.0, &mut slots, &IID_OBJECT_ARRAY, &mut removed)
+                };
+                check(hr, "Starting the Jump List")?;
+                let removed = removed_files(&Com(removed));
+                let kept: Vec<(PathBuf, bool)> = files
+                    .iter()
+                    .filter(|(path, _)| !removed.contains(path))
+                    .cloned()
+                    .collect();
+                let built = build(&list, slots, tasks, &kept);
+                if built.is_err() {
+                    unsafe { (methods.abort_list)(list.0) };
+                }
+                built.map(|_| removed)
+            });
+        unsafe { CoUninitialize() };
+        result
+    }
+
+    pub fn add_recent(path: &Path) {
+        let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        unsafe { SHAddToRecentDocs(SHARD_PATHW, path.as_ptr() as *const c_void) };
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::ffi::{c_char, CString};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+    use std::sync::OnceLock;
+
+    use tauri::AppHandle;
+
+    use crate::recent_files;
+
+    // For "Clear Menu", which AppKit calls without it
+    static APP: OnceLock<AppHandle> = OnceLock::new();
+
+    extern "C" {
+        fn install_open_recent_menu(cleared: extern "C" fn());
+        fn note_recent_document(path: *const c_char);
+        fn set_recent_documents(paths: *const *const c_char, count: usize);
+    }
+
+    extern "C" fn menu_cleared() {
+        if let Some(app) = APP.get() {
+            recent_files::clear(app);
+        }
+    }
+
+    pub fn setup(app: &AppHandle) {
+        let _ = APP.set(app.clone());
+        // The menu bar is Tauri's, which is only in place once the event loop
+        // runs
+        let installed = app.run_on_main_thread(|| {
+            // SAFETY: called on the main thread
+            unsafe { install_open_recent_menu(menu_cleared) }
+        });
+        if let Err(e) = installed {
+            eprintln!("Failed to add the Open Recent menu: {}", e);
+        }
+    }
+
+    pub fn add_recent(path: &Path) {
+        let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+            return;
+        };
+        // SAFETY: the path is copied before the call returns
+        unsafe { note_recent_document(path.as_ptr()) };
+    }
+
+    // The system's list has no pins, so pinned files just come first
+    pub fn update(_app: &AppHandle, files: &[(PathBuf, bool)]) {
+        let (pinned, others): (Vec<_>, Vec<_>) = files.iter().partition(|(_, pinned)| *pinned);
+        let paths: Vec<CString> = pinned
+            .into_iter()
+            .chain(others)
+            .filter_map(|(path, _)| CString::new(path.as_os_str().as_bytes()).ok())
+            .collect();
+        let pointers: Vec<*const c_char> = paths.iter().map(|path| path.as_ptr()).collect();
+        // SAFETY: the paths are copied before the call returns
+        unsafe { set_recent_documents(pointers.as_ptr(), pointers.len()) };
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+mod platform {
+    use std::path::{Path, PathBuf};
+
+    use tauri::AppHandle;
+
+    pub fn setup(_app: &AppHandle) {}
+
+    pub fn add_recent(_path: &Path) {}
+
+    pub fn update(_app: &AppHandle, _files: &[(PathBuf, bool)]) {}
+}
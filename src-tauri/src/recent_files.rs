@@ -0,0 +1,294 @@
+// The app's own list of recently opened files.
+//
+// Every file opened is added at the top; files the user pins stay listed
+// until they're unpinned, however many are opened since, and others drop off
+// the bottom. The list is kept in `<app data>/recent-files.json`, and files
+// that no longer exist are pruned from it whenever it's read, except pinned
+// ones, which may be on a drive that's unplugged and are reported missing
+// instead. The start screen lists it with `get_recents`, and it's what the
+// menu bar, the tray menu, the Jump List on Windows and the Dock menu on
+// macOS show (see `menu_bar.rs`, `tray.rs` and `recent.rs`), all rebuilt
+// whenever it changes. Its files are added to the file scope on each start,
+// so they can be opened again.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::{menu_bar, recent, scope, tray};
+
+const LIST_FILE: &str = "recent-files.json";
+// What earlier versions kept instead: the files allowed lately, most recent
+// first
+const LEGACY_FILE: &str = "opened-files.json";
+// Unpinned files kept
+const MAX_RECENT: usize = 30;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    path: PathBuf,
+    /// Milliseconds since the Unix epoch
+    opened_at: u64,
+    pinned: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecentFile {
+    pub path: PathBuf,
+    pub name: String,
+    /// When it was last opened, in milliseconds since the Unix epoch
+    pub opened_at: u64,
+    pub pinned: bool,
+    /// Pinned files are listed even while they're missing
+    pub exists: bool,
+}
+
+/// The recent files, most recent first, managed as Tauri state.
+#[derive(Default)]
+pub struct RecentFiles {
+    entries: Mutex<Vec<Entry>>,
+}
+
+fn data_file(app: &AppHandle, name: &str) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join(name))
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))
+}
+
+fn list_file(app: &AppHandle) -> Result<PathBuf, String> {
+    data_file(app, LIST_FILE)
+}
+
+// The list earlier versions kept, taken over while there's no list yet
+fn legacy_entries(app: &AppHandle) -> Vec<Entry> {
+    let Ok(file) = data_file(app, LEGACY_FILE) else {
+        return Vec::new();
+    };
+    let paths: Vec<PathBuf> = std::fs::read(file)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .unwrap_or_default();
+    paths
+        .into_iter()
+        .map(|path| Entry {
+            path,
+            opened_at: 0,
+            pinned: false,
+        })
+        .collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis() as u64)
+}
+
+// Drops missing files and those past the limit, telling whether any went
+fn prune(entries: &mut Vec<Entry>) -> bool {
+    let before = entries.len();
+    let mut unpinned = 0;
+    entries.retain(|entry| {
+        if entry.pinned {
+            return true;
+        }
+        unpinned += 1;
+        unpinned <= MAX_RECENT && entry.path.is_file()
+    });
+    entries.len() != before
+}
+
+fn save(app: &AppHandle, entries: &[Entry]) -> Result<(), String> {
+    let file = list_file(app)?;
+    let json = serde_json::to_vec_pretty(entries).map_err(|e| e.to_string())?;
+    std::fs::create_dir_all(file.parent().unwrap())
+        .and_then(|_| std::fs::write(&file, json))
+        .map_err(|e| format!("Failed to write {}: {}", file.display(), e))
+}
+
+// Saves the list and rebuilds everything that shows it
+fn changed(app: &AppHandle, entries: &[Entry]) {
+    if let Err(e) = save(app, entries) {
+        eprintln!("Failed to save the recent files: {}", e);
+    }
+    menu_bar::refresh(app);
+    tray::refresh(app);
+    recent::update(
+        app,
+        &entries
+            .iter()
+            .map(|entry| (entry.path.clone(), entry.pinned))
+            .collect::<Vec<_>>(),
+    );
+}
+
+// Changes the list and passes the change on. The menus read the list as
+// they're rebuilt, so it's unlocked by then
+fn edit(app: &AppHandle, change: impl FnOnce(&mut Vec<Entry>)) -> Vec<RecentFile> {
+    let entries = {
+        let state = app.state::<RecentFiles>();
+        let mut entries = state.entries.lock().unwrap();
+        change(&mut entries);
+        prune(&mut entries);
+        entries.clone()
+    };
+    changed(app, &entries);
+    listed(&entries)
+}
+
+// Pinned files first, each part most recent first
+fn listed(entries: &[Entry]) -> Vec<RecentFile> {
+    let (pinned, others): (Vec<&Entry>, Vec<&Entry>) =
+        entries.iter().partition(|entry| entry.pinned);
+    pinned
+        .into_iter()
+        .chain(others)
+        .map(|entry| RecentFile {
+            path: entry.path.clone(),
+            name: entry.path.file_name().map_or_else(
+                || entry.path.display().to_string(),
+                |name| name.to_string_lossy().into_owned(),
+            ),
+            opened_at: entry.opened_at,
+            pinned: entry.pinned,
+            exists: entry.path.is_file(),
+        })
+        .collect()
+}
+
+/// Loads the list, or starts it from the files opened lately, and allows its
+/// files again.
+pub fn restore(app: &AppHandle) {
+    let mut entries: Vec<Entry> =
+        match list_file(app).and_then(|file| std::fs::read(file).map_err(|e| e.to_string())) {
+            Ok(json) => serde_json::from_slice(&json).unwrap_or_default(),
+            Err(_) => legacy_entries(app),
+        };
+    prune(&mut entries);
+    for entry in entries.iter().filter(|entry| entry.path.is_file()) {
+        scope::allow(app, &entry.path);
+    }
+    let state = app.state::<RecentFiles>();
+    *state.entries.lock().unwrap() = entries.clone();
+    changed(app, &entries);
+}
+
+/// Adds a file the user opened at the top of the list, and to the system's
+/// recent files.
+pub fn add(app: &AppHandle, path: &Path) {
+    recent::opened(path);
+    // The scope's resolved paths, so files aren't listed twice
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    edit(app, |entries| {
+        let pinned = entries
+            .iter()
+            .position(|entry| entry.path == path)
+            .is_some_and(|index| entries.remove(index).pinned);
+        entries.insert(
+            0,
+            Entry {
+                path,
+                opened_at: now(),
+                pinned,
+            },
+        );
+    });
+}
+
+/// The files for menus: pinned first, then the most recent, leaving out
+/// missing ones.
+pub fn paths(app: &AppHandle) -> Vec<PathBuf> {
+    let state = app.state::<RecentFiles>();
+    let entries = state.entries.lock().unwrap();
+    listed(&entries)
+        .into_iter()
+        .filter(|file| file.exists)
+        .map(|file| file.path)
+        .collect()
+}
+
+/// The file opened last that still exists.
+pub fn last_opened(app: &AppHandle) -> Option<PathBuf> {
+    let state = app.state::<RecentFiles>();
+    let entries = state.entries.lock().unwrap();
+    entries
+        .iter()
+        .filter(|entry| entry.path.is_file())
+        .max_by_key(|entry| entry.opened_at)
+        .map(|entry| entry.path.clone())
+}
+
+/// Drops files from the list, pinned or not, as when the user removes them
+/// from the Jump List.
+#[cfg(windows)]
+pub fn forget(app: &AppHandle, paths: &[PathBuf]) {
+    edit(app, |entries| {
+        entries.retain(|entry| !paths.contains(&entry.path));
+    });
+}
+
+/// Clears the list but for the pinned files.
+pub fn clear(app: &AppHandle) -> Vec<RecentFile> {
+    edit(app, |entries| entries.retain(|entry| entry.pinned))
+}
+
+/// Adds a file opened in the webview to the list.
+#[tauri::command]
+pub async fn add_recent(app: AppHandle, path: String) -> Result<Vec<RecentFile>, String> {
+    let path = PathBuf::from(path);
+    scope::check(&app, &path)?;
+    add(&app, &path);
+    get_recents(app).await
+}
+
+/// The recent files, pinned first, after pruning the missing ones.
+#[tauri::command]
+pub async fn get_recents(app: AppHandle) -> Result<Vec<RecentFile>, String> {
+    let (entries, pruned) = {
+        let state = app.state::<RecentFiles>();
+        let mut entries = state.entries.lock().unwrap();
+        let pruned = prune(&mut entries);
+        (entries.clone(), pruned)
+    };
+    if pruned {
+        changed(&app, &entries);
+    }
+    Ok(listed(&entries))
+}
+
+/// Pins a file to the list, or unpins it.
+#[tauri::command]
+pub async fn pin_recent(
+    app: AppHandle,
+    path: String,
+    pinned: bool,
+) -> Result<Vec<RecentFile>, String> {
+    let path = PathBuf::from(path);
+    let known = app
+        .state::<RecentFiles>()
+        .entries
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|entry| entry.path == path);
+    if !known {
+        return Err(format!("{} isn't a recent file", path.display()));
+    }
+    Ok(edit(&app, |entries| {
+        if let Some(entry) = entries.iter_mut().find(|entry| entry.path == path) {
+            entry.pinned = pinned;
+        }
+    }))
+}
+
+/// Clears the recent files, keeping the pinned ones.
+#[tauri::command]
+pub async fn clear_recents(app: AppHandle) -> Result<Vec<RecentFile>, String> {
+    Ok(clear(&app))
+}
@@ -0,0 +1,777 @@
+// Redaction: removing what is under marked areas from the file itself.
+//
+// Drawing a black box leaves the text and images underneath in the file, one
+// copy-paste away. Here page content is rewritten instead: glyphs whose boxes
+// touch an area are dropped (the rest of the line keeps its place), paths
+// painted over an area are dropped, and images are blanked where they are
+// covered, or dropped whole when they can't be decoded. Forms drawn across an
+// area are rewritten the same way, as new copies. Annotations touching an
+// area and page thumbnails are removed, and the areas are filled with black.
+//
+// Glyph boxes come from the font's advance widths and a fixed ascent and
+// descent, so they err on the large side. Shadings and text in annotations,
+// bookmarks or document metadata are not looked at.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::rc::Rc;
+
+use lopdf::content::{Content, Operation};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+
+use crate::compose::{write_document, WrittenDocument};
+use crate::content::{
+    concat, insert_resource, invert, transform, transform_rect, Transform, IDENTITY,
+};
+use crate::flatten::prune_form;
+use crate::objects::{self, PageSpace, MAX_DEPTH};
+use crate::optimize::decode_image;
+use crate::pdf::{blocking, Bounds, DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scope;
+
+// Glyph extent above and below the baseline, in text space units
+const ASCENT: f32 = 0.95;
+const DESCENT: f32 = -0.25;
+// Advance width for glyphs the font has no width for, in 1/1000 em
+const FALLBACK_WIDTH: f32 = 600.0;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageRedaction {
+    pub page: i32,
+    /// Areas to clear, in page space
+    pub areas: Vec<Bounds>,
+}
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RedactCounts {
+    pub glyphs: usize,
+    pub paths: usize,
+    pub images: usize,
+    pub annotations: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedactReport {
+    pub document: WrittenDocument,
+    /// What was removed
+    pub removed: RedactCounts,
+}
+
+// Advance widths of a font, in text space units
+struct Font {
+    two_byte: bool,
+    widths: HashMap<u32, f32>,
+    default_width: f32,
+}
+
+impl Font {
+    fn load(doc: &Document, font: &Dictionary) -> Self {
+        let number = |obj: &Object| objects::number(objects::resolve(doc, obj));
+        let mut widths = HashMap::new();
+
+        if objects::get_name(doc, font, b"Subtype") == Some(b"Type0") {
+            let descendant = objects::get_array(doc, font, b"DescendantFonts")
+                .and_then(|fonts| fonts.first())
+                .and_then(|f| objects::resolve(doc, f).as_dict().ok());
+            let default_width = descendant
+                .and_then(|d| objects::get(doc, d, b"DW"))
+                .and_then(number)
+                .unwrap_or(1000.0);
+            // /W holds runs like `c [w1 w2 ...]` and `c_first c_last w`
+            let entries = descendant
+                .and_then(|d| objects::get_array(doc, d, b"W"))
+                .unwrap_or(&[]);
+            let mut i = 0;
+            while i + 1 < entries.len() {
+                let first = number(&entries[i]).unwrap_or(0.0) as u32;
+                if let Object::Array(run) = objects::resolve(doc, &entries[i + 1]) {
+                    for (code, w) in (first..).zip(run) {
+                        widths.insert(code, number(w).unwrap_or(default_width) / 1000.0);
+                    }
+                    i += 2;
+                } else if i + 2 < entries.len() {
+                    let last = number(&entries[i + 1]).unwrap_or(0.0) as u32;
+                    let w = number(&entries[i + 2]).unwrap_or(default_width) / 1000.0;
+                    // Cap runs so a broken entry can't fill memory
+                    for code in first..=last.min(first.saturating_add(0xffff)) {
+                        widths.insert(code, w);
+                    }
+                    i += 3;
+                } else {
+                    break;
+                }
+            }
+            return Font {
+                two_byte: true,
+                widths,
+                default_width: default_width / 1000.0,
+            };
+        }
+
+        // Type 3 glyphs are measured in their own glyph space
+        let scale = objects::get_array(doc, font, b"FontMatrix")
+            .and_then(|m| m.first())
+            .and_then(number)
+            .unwrap_or(0.001);
+        let first = objects::get(doc, font, b"FirstChar")
+            .and_then(number)
+            .unwrap_or(0.0) as u32;
+        for (code, w) in (first..).zip(objects::get_array(doc, font, b"Widths").unwrap_or(&[])) {
+            widths.insert(code, number(w).unwrap_or(0.0) * scale);
+        }
+        let missing = objects::get_dict(doc, font, b"FontDescriptor")
+            .and_then(|d| objects::get(doc, d, b"MissingWidth"))
+            .and_then(number)
+            .filter(|&w| w > 0.0)
+            .map(|w| w * scale);
+        Font {
+            two_byte: false,
+            widths,
+            default_width: missing.unwrap_or(FALLBACK_WIDTH / 1000.0),
+        }
+    }
+
+    fn width(&self, code: u32) -> f32 {
+        self.widths
+            .get(&code)
+            .copied()
+            .unwrap_or(self.default_width)
+    }
+}
+
+// The parts of the graphics state that decide where things land
+#[derive(Clone)]
+struct GraphicsState {
+    ctm: Transform,
+    line_width: f32,
+    font: Option<Rc<Font>>,
+    font_size: f32,
+    char_spacing: f32,
+    word_spacing: f32,
+    horizontal_scale: f32,
+    leading: f32,
+    rise: f32,
+}
+
+impl GraphicsState {
+    fn new(ctm: Transform) -> Self {
+        GraphicsState {
+            ctm,
+            line_width: 1.0,
+            font: None,
+            font_size: 0.0,
+            char_spacing: 0.0,
+            word_spacing: 0.0,
+            horizontal_scale: 1.0,
+            leading: 0.0,
+            rise: 0.0,
+        }
+    }
+}
+
+fn overlaps(a: [f32; 4], b: [f32; 4]) -> bool {
+    a[0] < b[2] && b[0] < a[2] && a[1] < b[3] && b[1] < a[3]
+}
+
+fn contains(outer: [f32; 4], inner: [f32; 4]) -> bool {
+    outer[0] <= inner[0] && outer[1] <= inner[1] && outer[2] >= inner[2] && outer[3] >= inner[3]
+}
+
+fn operand_numbers(op: &Operation) -> Vec<f32> {
+    op.operands.iter().filter_map(objects::number).collect()
+}
+
+fn translate(tx: f32, ty: f32) -> Transform {
+    [1.0, 0.0, 0.0, 1.0, tx, ty]
+}
+
+// Grows `bbox` to hold the point (x, y) after `ctm`
+fn extend_box(bbox: &mut Option<[f32; 4]>, x: f32, y: f32, ctm: Transform) {
+    let [px, py, ..] = transform_rect([x, y, x, y], ctm);
+    *bbox = Some(match *bbox {
+        Some([x0, y0, x1, y1]) => [x0.min(px), y0.min(py), x1.max(px), y1.max(py)],
+        None => [px, py, px, py],
+    });
+}
+
+// Drops stream entries that only describe the old encoded data
+fn plain_stream(dict: &Dictionary, content: Vec<u8>) -> Stream {
+    let mut dict = dict.clone();
+    for key in [b"Filter".as_slice(), b"DecodeParms", b"Length"] {
+        dict.remove(key);
+    }
+    Stream::new(dict, content)
+}
+
+struct Redactor<'a> {
+    areas: &'a [[f32; 4]],
+    removed: RedactCounts,
+}
+
+impl Redactor<'_> {
+    fn hits(&self, bbox: [f32; 4]) -> bool {
+        self.areas.iter().any(|&area| overlaps(area, bbox))
+    }
+
+    // Shows text glyph by glyph, returning a TJ array without the glyphs that
+    // touch an area, or `None` if nothing was removed
+    fn show_text(
+        &mut self,
+        elements: &[Object],
+        state: &GraphicsState,
+        text_matrix: &mut Transform,
+    ) -> Option<Vec<Object>> {
+        let fallback;
+        let font = match &state.font {
+            Some(font) => font.as_ref(),
+            None => {
+                fallback = Font {
+                    two_byte: false,
+                    widths: HashMap::new(),
+                    default_width: FALLBACK_WIDTH / 1000.0,
+                };
+                &fallback
+            }
+        };
+        let size = state.font_size;
+        let scale = state.horizontal_scale;
+        let glyph_space = [size * scale, 0.0, 0.0, size, 0.0, state.rise];
+        // Converts an advance in text space to a TJ adjustment
+        let adjustment = |advance: f32| {
+            if size * scale == 0.0 {
+                0.0
+            } else {
+                -advance * 1000.0 / (size * scale)
+            }
+        };
+
+        let mut out = Vec::new();
+        let mut kept = Vec::new();
+        let mut gap = 0.0;
+        let mut removed = false;
+        let flush = |kept: &mut Vec<u8>, format: &StringFormat, out: &mut Vec<Object>| {
+            if !kept.is_empty() {
+                out.push(Object::String(std::mem::take(kept), *format));
+            }
+        };
+        for element in elements {
+            match element {
+                Object::String(bytes, format) => {
+                    let step = if font.two_byte { 2 } else { 1 };
+                    for code in bytes.chunks(step) {
+                        let value = code.iter().fold(0u32, |v, &b| v << 8 | b as u32);
+                        let width = font.width(value);
+                        let bbox = transform_rect(
+                            [0.0, DESCENT, width, ASCENT],
+                            concat(concat(glyph_space, *text_matrix), state.ctm),
+                        );
+                        let spacing = if step == 1 && value == 32 {
+                            state.char_spacing + state.word_spacing
+                        } else {
+                            state.char_spacing
+                        };
+                        let advance = (width * size + spacing) * scale;
+                        *text_matrix = concat(translate(advance, 0.0), *text_matrix);
+
+                        if self.hits(bbox) {
+                            flush(&mut kept, format, &mut out);
+                            gap += adjustment(advance);
+                            removed = true;
+                            self.removed.glyphs += 1;
+                        } else {
+                            if gap != 0.0 {
+                                flush(&mut kept, format, &mut out);
+                                out.push(Object::Real(gap));
+                                gap = 0.0;
+                            }
+                            kept.extend_from_slice(code);
+                        }
+                    }
+                    flush(&mut kept, format, &mut out);
+                }
+                other => {
+                    let Some(n) = objects::number(other) else {
+                        continue;
+                    };
+                    *text_matrix = concat(translate(-n / 1000.0 * size * scale, 0.0), *text_matrix);
+                    gap += n;
+                }
+            }
+        }
+        if gap != 0.0 {
+            out.push(Object::Real(gap));
+        }
+        removed.then_some(out)
+    }
+
+    // Blanks the covered parts of an image drawn in the unit square of `ctm`,
+    // returning the new image, or `None` if it has to go entirely
+    fn blank_image(
+        &mut self,
+        doc: &mut Document,
+        id: ObjectId,
+        ctm: Transform,
+    ) -> Option<ObjectId> {
+        let Ok(Object::Stream(stream)) = doc.get_object(id) else {
+            return None;
+        };
+        let inverse = invert(ctm)?;
+        let (mut image, _) = decode_image(doc, stream)?;
+        let (width, height) = (image.width as f32, image.height as f32);
+        for &area in self.areas {
+            // Image rows run from the top of the unit square down
+            let [u0, v0, u1, v1] = transform_rect(area, inverse);
+            let x0 = (u0 * width).floor().clamp(0.0, width) as usize;
+            let x1 = (u1 * width).ceil().clamp(0.0, width) as usize;
+            let y0 = ((1.0 - v1) * height).floor().clamp(0.0, height) as usize;
+            let y1 = ((1.0 - v0) * height).ceil().clamp(0.0, height) as usize;
+            for row in y0..y1 {
+                let start = (row * image.width + x0) * image.channels;
+                let end = (row * image.width + x1) * image.channels;
+                image.pixels[start..end].fill(0);
+            }
+        }
+        let blanked = plain_stream(&stream.dict, image.pixels);
+        Some(doc.add_object(blanked))
+    }
+
+    // Rewrites a form drawn across an area as a new form object
+    fn redact_form(
+        &mut self,
+        doc: &mut Document,
+        id: ObjectId,
+        resources: &Dictionary,
+        ctm: Transform,
+        depth: usize,
+    ) -> Result<ObjectId, String> {
+        let Ok(Object::Stream(form)) = doc.get_object(id) else {
+            return Err("Missing form XObject".into());
+        };
+        let dict = form.dict.clone();
+        let content = form
+            .decompressed_content()
+            .map_err(|e| format!("Failed to read a form XObject: {}", e))?;
+        let matrix = objects::get_array(doc, &dict, b"Matrix")
+            .and_then(|m| transform(doc, m))
+            .unwrap_or(IDENTITY);
+        // Forms without resources of their own use those of the page
+        let mut form_resources = objects::get_dict(doc, &dict, b"Resources")
+            .unwrap_or(resources)
+            .clone();
+        let operations = self.redact_content(
+            doc,
+            &content,
+            &mut form_resources,
+            concat(matrix, ctm),
+            depth + 1,
+        )?;
+        let content = Content { operations }
+            .encode()
+            .map_err(|e| format!("Failed to write a form XObject: {}", e))?;
+        let mut redacted = plain_stream(&dict, content);
+        redacted.dict.set("Resources", form_resources);
+        Ok(doc.add_object(redacted))
+    }
+
+    // Handles `Do` for an XObject touching an area. Returns the operation to
+    // keep, if any.
+    fn redact_xobject(
+        &mut self,
+        doc: &mut Document,
+        op: Operation,
+        resources: &mut Dictionary,
+        ctm: Transform,
+        depth: usize,
+    ) -> Result<Option<Operation>, String> {
+        let Some(id) = op
+            .operands
+            .first()
+            .and_then(|name| name.as_name().ok())
+            .and_then(|name| {
+                objects::get_dict(doc, resources, b"XObject")?
+                    .get(name)
+                    .ok()
+            })
+            .and_then(|xobject| xobject.as_reference().ok())
+        else {
+            return Ok(Some(op));
+        };
+        let Ok(Object::Stream(stream)) = doc.get_object(id) else {
+            return Ok(Some(op));
+        };
+        let replacement = match objects::get_name(doc, &stream.dict, b"Subtype") {
+            // Images fill the unit square of the current transform
+            Some(b"Image") => {
+                let bbox = transform_rect([0.0, 0.0, 1.0, 1.0], ctm);
+                if !self.hits(bbox) {
+                    return Ok(Some(op));
+                }
+                self.removed.images += 1;
+                if self.areas.iter().any(|&area| contains(area, bbox)) {
+                    return Ok(None);
+                }
+                match self.blank_image(doc, id, ctm) {
+                    Some(blanked) => blanked,
+                    None => return Ok(None),
+                }
+            }
+            Some(b"Form") => {
+                let matrix = objects::get_array(doc, &stream.dict, b"Matrix")
+                    .and_then(|m| transform(doc, m))
+                    .unwrap_or(IDENTITY);
+                let bbox = objects::get(doc, &stream.dict, b"BBox")
+                    .and_then(|b| objects::rect(doc, b))
+                    .map(|b| transform_rect(b, concat(matrix, ctm)));
+                if bbox.is_some_and(|bbox| !self.hits(bbox)) {
+                    return Ok(Some(op));
+                }
+                let page_resources = resources.clone();
+                self.redact_form(doc, id, &page_resources, ctm, depth)?
+            }
+            _ => return Ok(Some(op)),
+        };
+        let name = insert_resource(doc, resources, b"XObject", "Redacted", replacement.into());
+        Ok(Some(Operation::new(
+            "Do",
+            vec![Object::Name(name.into_bytes())],
+        )))
+    }
+
+    // Rewrites a content stream without what touches the areas. New XObjects
+    // are added to `resources`, and ones no longer drawn are dropped from it.
+    fn redact_content(
+        &mut self,
+        doc: &mut Document,
+        content: &[u8],
+        resources: &mut Dictionary,
+        ctm: Transform,
+        depth: usize,
+    ) -> Result<Vec<Operation>, String> {
+        if depth > MAX_DEPTH {
+            return Err("Forms are nested too deeply".into());
+        }
+        // Anything that can't be read can't be checked either, so give up
+        // rather than leave content behind
+        let content =
+            Content::decode(content).map_err(|e| format!("Failed to read page content: {}", e))?;
+
+        let mut state = GraphicsState::new(ctm);
+        let mut saved = Vec::new();
+        let mut text_matrix = IDENTITY;
+        let mut line_matrix = IDENTITY;
+        let mut path = Vec::new();
+        let mut path_box: Option<[f32; 4]> = None;
+        let mut out = Vec::new();
+        for op in content.operations {
+            let numbers = operand_numbers(&op);
+            match op.operator.as_str() {
+                "q" => saved.push(state.clone()),
+                "Q" => state = saved.pop().unwrap_or(state),
+                "cm" => {
+                    if let Some(m) = transform(doc, &op.operands) {
+                        state.ctm = concat(m, state.ctm);
+                    }
+                }
+                "w" => state.line_width = numbers.first().copied().unwrap_or(1.0),
+
+                // Paths are held back until it's known how they are painted
+                "m" | "l" | "c" | "v" | "y" => {
+                    for point in numbers.chunks_exact(2) {
+                        extend_box(&mut path_box, point[0], point[1], state.ctm);
+                    }
+                    path.push(op);
+                    continue;
+                }
+                "re" => {
+                    if let [x, y, w, h] = numbers[..] {
+                        for (px, py) in [(x, y), (x + w, y), (x, y + h), (x + w, y + h)] {
+                            extend_box(&mut path_box, px, py, state.ctm);
+                        }
+                    }
+                    path.push(op);
+                    continue;
+                }
+                "h" | "W" | "W*" => {
+                    path.push(op);
+                    continue;
+                }
+                "S" | "s" | "f" | "F" | "f*" | "B" | "B*" | "b" | "b*" | "n" => {
+                    let clips = path.iter().any(|p| p.operator == "W" || p.operator == "W*");
+                    let pad = state.line_width * state.ctm[0].hypot(state.ctm[1]) / 2.0;
+                    let hit = op.operator != "n"
+                        && path_box.is_some_and(|[x0, y0, x1, y1]| {
+                            self.hits([x0 - pad, y0 - pad, x1 + pad, y1 + pad])
+                        });
+                    path_box = None;
+                    if !hit {
+                        out.append(&mut path);
+                        out.push(op);
+                    } else if clips {
+                        // Keep the clip, which later drawing relies on
+                        out.append(&mut path);
+                        out.push(Operation::new("n", vec![]));
+                        self.removed.paths += 1;
+                    } else {
+                        path.clear();
+                        self.removed.paths += 1;
+                    }
+                    continue;
+                }
+
+                "BT" => {
+                    text_matrix = IDENTITY;
+                    line_matrix = IDENTITY;
+                }
+                "Tf" => {
+                    let font = op
+                        .operands
+                        .first()
+                        .and_then(|name| name.as_name().ok())
+                        .and_then(|name| objects::get_dict(doc, resources, b"Font")?.get(name).ok())
+                        .and_then(|font| objects::resolve(doc, font).as_dict().ok());
+                    state.font = font.map(|font| Rc::new(Font::load(doc, font)));
+                    state.font_size = numbers.last().copied().unwrap_or(0.0);
+                }
+                "Tc" => state.char_spacing = numbers.first().copied().unwrap_or(0.0),
+                "Tw" => state.word_spacing = numbers.first().copied().unwrap_or(0.0),
+                "Tz" => state.horizontal_scale = numbers.first().copied().unwrap_or(100.0) / 100.0,
+                "TL" => state.leading = numbers.first().copied().unwrap_or(0.0),
+                "Ts" => state.rise = numbers.first().copied().unwrap_or(0.0),
+                "Td" | "TD" => {
+                    if let [tx, ty] = numbers[..] {
+                        if op.operator == "TD" {
+                            state.leading = -ty;
+                        }
+                        line_matrix = concat(translate(tx, ty), line_matrix);
+                        text_matrix = line_matrix;
+                    }
+                }
+                "Tm" => {
+                    if let Some(m) = transform(doc, &op.operands) {
+                        line_matrix = m;
+                        text_matrix = m;
+                    }
+                }
+                "T*" => {
+                    line_matrix = concat(translate(0.0, -state.leading), line_matrix);
+                    text_matrix = line_matrix;
+                }
+                "Tj" | "TJ" | "'" | "\"" => {
+                    let mut prefix = Vec::new();
+                    if op.operator == "\"" {
+                        if let [aw, ac, ..] = numbers[..] {
+                            state.word_spacing = aw;
+                            state.char_spacing = ac;
+                            prefix.push(Operation::new("Tw", vec![aw.into()]));
+                            prefix.push(Operation::new("Tc", vec![ac.into()]));
+                        }
+                    }
+                    if op.operator == "'" || op.operator == "\"" {
+                        line_matrix = concat(translate(0.0, -state.leading), line_matrix);
+                        text_matrix = line_matrix;
+                        prefix.push(Operation::new("T*", vec![]));
+                    }
+                    let elements = match op.operands.last() {
+                        Some(Object::Array(elements)) => elements.clone(),
+                        Some(text) => vec![text.clone()],
+                        None => Vec::new(),
+                    };
+                    if let Some(elements) = self.show_text(&elements, &state, &mut text_matrix) {
+                        out.extend(prefix);
+                        out.push(Operation::new("TJ", vec![Object::Array(elements)]));
+                        continue;
+                    }
+                }
+
+                "Do" => {
+                    if let Some(op) = self.redact_xobject(doc, op, resources, state.ctm, depth)? {
+                        out.push(op);
+                    }
+                    continue;
+                }
+                // Inline images, like other images, fill the unit square
+                "BI" if self.hits(transform_rect([0.0, 0.0, 1.0, 1.0], state.ctm)) => {
+                    self.removed.images += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            out.push(op);
+        }
+
+        // The old versions of rewritten XObjects must not stay reachable
+        let drawn: HashSet<Vec<u8>> = out
+            .iter()
+            .filter(|op| op.operator == "Do")
+            .filter_map(|op| op.operands.first()?.as_name().ok().map(<[u8]>::to_vec))
+            .collect();
+        if let Some(xobjects) = objects::get_dict(doc, resources, b"XObject") {
+            let mut xobjects = xobjects.clone();
+            let unused: Vec<Vec<u8>> = xobjects
+                .iter()
+                .map(|(name, _)| name.clone())
+                .filter(|name| !drawn.contains(name))
+                .collect();
+            for name in unused {
+                xobjects.remove(&name);
+            }
+            resources.set("XObject", xobjects);
+        }
+        Ok(out)
+    }
+
+    // Removes annotations touching an area, noting whether any were widgets
+    fn redact_annotations(&mut self, doc: &mut Document, page_id: ObjectId, widgets: &mut bool) {
+        let Some(annots) = doc
+            .get_dictionary(page_id)
+            .ok()
+            .and_then(|page| objects::get_array(doc, page, b"Annots"))
+            .map(|annots| annots.to_vec())
+        else {
+            return;
+        };
+        let mut removed = HashSet::new();
+        for entry in &annots {
+            let Ok(annot) = objects::resolve(doc, entry).as_dict() else {
+                continue;
+            };
+            let rect = annot.get(b"Rect").ok().and_then(|r| objects::rect(doc, r));
+            if !rect.is_some_and(|rect| self.hits(rect)) {
+                continue;
+            }
+            *widgets |= objects::get_name(doc, annot, b"Subtype") == Some(b"Widget");
+            if let Ok(popup) = annot.get(b"Popup").and_then(Object::as_reference) {
+                removed.insert(popup);
+            }
+            if let Ok(id) = entry.as_reference() {
+                removed.insert(id);
+            }
+            self.removed.annotations += 1;
+        }
+        let remaining: Vec<Object> = annots
+            .into_iter()
+            .filter(|entry| !entry.as_reference().is_ok_and(|id| removed.contains(&id)))
+            .collect();
+        if let Ok(page) = doc.get_dictionary_mut(page_id) {
+            page.set("Annots", remaining);
+        }
+    }
+}
+
+fn redact_page(
+    doc: &mut Document,
+    page_id: ObjectId,
+    areas: &[[f32; 4]],
+    removed: &mut RedactCounts,
+    widgets: &mut bool,
+) -> Result<(), String> {
+    let mut redactor = Redactor {
+        areas,
+        removed: std::mem::take(removed),
+    };
+    let content = doc
+        .get_page_content(page_id)
+        .map_err(|e| format!("Failed to read page content: {}", e))?;
+    let mut resources = objects::inherited(doc, page_id, b"Resources")
+        .and_then(|r| r.as_dict().ok())
+        .cloned()
+        .unwrap_or_default();
+    let mut operations = vec![Operation::new("q", vec![])];
+    operations.extend(redactor.redact_content(doc, &content, &mut resources, IDENTITY, 0)?);
+    operations.push(Operation::new("Q", vec![]));
+
+    // Black boxes show where something was removed
+    operations.push(Operation::new("q", vec![]));
+    operations.push(Operation::new("g", vec![0.into()]));
+    for &[x0, y0, x1, y1] in areas {
+        operations.push(Operation::new(
+            "re",
+            vec![x0.into(), y0.into(), (x1 - x0).into(), (y1 - y0).into()],
+        ));
+    }
+    operations.push(Operation::new("f", vec![]));
+    operations.push(Operation::new("Q", vec![]));
+    let content = Content { operations }
+        .encode()
+        .map_err(|e| format!("Failed to write page content: {}", e))?;
+
+    redactor.redact_annotations(doc, page_id, widgets);
+    let content = doc.add_object(Stream::new(Dictionary::new(), content));
+    let page = doc.get_dictionary_mut(page_id).map_err(|e| e.to_string())?;
+    page.set("Contents", content);
+    page.set("Resources", resources);
+    // Thumbnails would still show what was removed
+    page.remove(b"Thumb");
+    *removed = redactor.removed;
+    Ok(())
+}
+
+fn redact(doc: &mut Document, redactions: &[PageRedaction]) -> Result<RedactCounts, String> {
+    let ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    let mut removed = RedactCounts::default();
+    let mut widgets = false;
+    for redaction in redactions {
+        let page_id = ids[redaction.page as usize];
+        let space = PageSpace::new(doc, page_id);
+        let areas: Vec<[f32; 4]> = redaction
+            .areas
+            .iter()
+            .filter(|area| area.width > 0.0 && area.height > 0.0)
+            .map(|&area| space.user_rect(area))
+            .collect();
+        if !areas.is_empty() {
+            redact_page(doc, page_id, &areas, &mut removed, &mut widgets)?;
+        }
+    }
+    if widgets {
+        prune_form(doc)?;
+    }
+    // Nothing from before may be left in the file, even unreferenced
+    doc.prune_objects();
+    Ok(removed)
+}
+
+/// Removes the text, images and graphics under the given areas and saves the
+/// result as a new file. The open document is left as it is.
+#[tauri::command]
+pub async fn redact_pages(
+    app: AppHandle,
+    doc_id: DocId,
+    redactions: Vec<PageRedaction>,
+    output_path: String,
+    store: State<'_, DocumentStore>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<RedactReport, String> {
+    scope::check_write(&app, Path::new(&output_path))?;
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Modify).await?;
+    for redaction in &redactions {
+        doc.check_page(redaction.page)?;
+    }
+    blocking(move || {
+        let (mut redacted, removed) = doc.with_objects(|objects| {
+            let mut redacted = objects.clone();
+            let removed = redact(&mut redacted, &redactions)?;
+            Ok((redacted, removed))
+        })?;
+        write_document(&mut redacted, Path::new(&output_path))?;
+        eprintln!(
+            "Redacted document {} into {}: {} glyphs, {} paths, {} images, {} annotations",
+            doc_id, output_path, removed.glyphs, removed.paths, removed.images, removed.annotations
+        );
+        Ok(RedactReport {
+            document: WrittenDocument {
+                path: output_path,
+                page_count: doc.page_count,
+            },
+            removed,
+        })
+    })
+    .await
+}
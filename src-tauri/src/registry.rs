@@ -0,0 +1,89 @@
+// The current user's part of the Windows registry, where the app registers
+// the links and files it opens.
+
+use std::ffi::c_void;
+use std::ptr;
+
+const HKEY_CURRENT_USER: isize = 0x80000001u32 as i32 as isize;
+const REG_SZ: u32 = 1;
+const RRF_RT_REG_SZ: u32 = 2;
+const ERROR_MORE_DATA: i32 = 234;
+
+#[link(name = "advapi32")]
+extern "system" {
+    fn RegSetKeyValueW(
+        key: isize,
+        sub_key: *const u16,
+        value_name: *const u16,
+        kind: u32,
+        data: *const c_void,
+        data_len: u32,
+    ) -> i32;
+    fn RegGetValueW(
+        key: isize,
+        sub_key: *const u16,
+        value_name: *const u16,
+        flags: u32,
+        kind: *mut u32,
+        data: *mut c_void,
+        data_len: *mut u32,
+    ) -> i32;
+}
+
+fn wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain(Some(0)).collect()
+}
+
+// A value name, null for the key's default value
+fn value_name(name: &str) -> Option<Vec<u16>> {
+    (!name.is_empty()).then(|| wide(name))
+}
+
+/// Sets a string value under HKEY_CURRENT_USER, the key's default one when
+/// `name` is empty. Missing keys are created.
+pub fn set_string(key: &str, name: &str, value: &str) -> Result<(), String> {
+    let (key_w, name_w, value_w) = (wide(key), value_name(name), wide(value));
+    let status = unsafe {
+        RegSetKeyValueW(
+            HKEY_CURRENT_USER,
+            key_w.as_ptr(),
+            name_w.as_ref().map_or(ptr::null(), |name| name.as_ptr()),
+            REG_SZ,
+            value_w.as_ptr() as *const c_void,
+            (value_w.len() * 2) as u32,
+        )
+    };
+    if status != 0 {
+        return Err(format!("Failed to write {} (error {})", key, status));
+    }
+    Ok(())
+}
+
+/// Reads a string value under HKEY_CURRENT_USER, if it's there.
+pub fn get_string(key: &str, name: &str) -> Option<String> {
+    let (key_w, name_w) = (wide(key), value_name(name));
+    let name_ptr = name_w.as_ref().map_or(ptr::null(), |name| name.as_ptr());
+    let mut data = vec![0u16; 256];
+    loop {
+        let mut len = (data.len() * 2) as u32;
+        let status = unsafe {
+            RegGetValueW(
+                HKEY_CURRENT_USER,
+                key_w.as_ptr(),
+                name_ptr,
+                RRF_RT_REG_SZ,
+                ptr::null_mut(),
+                data.as_mut_ptr() as *mut c_void,
+                &mut len,
+            )
+        };
+        match status {
+            0 => {
+                let end = data.iter().position(|&c| c == 0).unwrap_or(data.len());
+                return Some(String::from_utf16_lossy(&data[..end]));
+            }
+            ERROR_MORE_DATA => data.resize(len as usize / 2 + 1, 0),
+            _ => return None,
+        }
+    }
+}
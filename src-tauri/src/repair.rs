@@ -0,0 +1,248 @@
+// Repairing files with a damaged cross-reference table.
+//
+// The table at the end of a PDF says where each object starts. When it is
+// missing or its offsets are wrong (truncated downloads, files edited by hand,
+// broken writers), a new one is built by scanning the file for `N G obj`
+// headers, later copies of an object winning as they do in incremental
+// updates. The new table is appended, so the original bytes stay untouched.
+//
+// Objects inside object streams can't be found by scanning, so files that
+// have them are rewritten with MuPDF instead, which repairs them the same way
+// while opening them.
+
+use mupdf::pdf::PdfDocument;
+
+use crate::engine::Engine;
+use crate::pdf::blocking;
+
+// How far from the end of the file `startxref` is looked for
+const TAIL_SIZE: usize = 4096;
+// Table entries checked against the objects they point to
+const SAMPLED_ENTRIES: usize = 64;
+// Highest object number PDF allows; bigger ones come from stream data
+const MAX_OBJECT_NUMBER: u64 = 8_388_607;
+
+fn is_delimiter(c: u8) -> bool {
+    c.is_ascii_whitespace() || b"()<>[]{}/%".contains(&c)
+}
+
+fn find(data: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    data.get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|pos| pos + from)
+}
+
+fn rfind(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).rposition(|w| w == needle)
+}
+
+fn skip_whitespace(data: &[u8], mut at: usize) -> usize {
+    while data.get(at).is_some_and(u8::is_ascii_whitespace) {
+        at += 1;
+    }
+    at
+}
+
+// Reads an unsigned integer at `at`, returning it and where it ends
+fn number_at(data: &[u8], at: usize) -> Option<(u64, usize)> {
+    let end = at
+        + data
+            .get(at..)?
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+    let value = std::str::from_utf8(&data[at..end]).ok()?.parse().ok()?;
+    Some((value, end))
+}
+
+// Reads an `N G obj` header at `at`, after optional whitespace
+fn object_header(data: &[u8], at: usize) -> Option<(u64, u64)> {
+    let (number, end) = number_at(data, skip_whitespace(data, at))?;
+    let (generation, end) = number_at(data, skip_whitespace(data, end))?;
+    let end = skip_whitespace(data, end);
+    (data.get(end..end + 3)? == b"obj").then_some((number, generation))
+}
+
+// Reads an `N G R` reference following `key`, using the last one in the file
+fn last_reference(data: &[u8], key: &[u8]) -> Option<(u64, u64)> {
+    let mut end = data.len();
+    while let Some(pos) = rfind(&data[..end], key) {
+        end = pos;
+        let after = pos + key.len();
+        if data.get(after).is_some_and(|c| !is_delimiter(*c)) {
+            continue;
+        }
+        let Some((number, next)) = number_at(data, skip_whitespace(data, after)) else {
+            continue;
+        };
+        let Some((generation, next)) = number_at(data, skip_whitespace(data, next)) else {
+            continue;
+        };
+        if data.get(skip_whitespace(data, next)) == Some(&b'R') {
+            return Some((number, generation));
+        }
+    }
+    None
+}
+
+/// Checks whether the cross-reference table of a PDF is missing or points
+/// to the wrong places. Cross-reference streams are only checked for being
+/// where `startxref` says.
+pub fn xref_damaged(data: &[u8]) -> bool {
+    let tail = data.len().saturating_sub(TAIL_SIZE);
+    let Some(start) = rfind(&data[tail..], b"startxref").map(|pos| pos + tail) else {
+        return true;
+    };
+    let Some((offset, _)) = number_at(data, skip_whitespace(data, start + 9)) else {
+        return true;
+    };
+    let offset = offset as usize;
+    if offset >= data.len() {
+        return true;
+    }
+    if object_header(data, offset).is_some() {
+        return false;
+    }
+    if data.get(offset..offset + 4) != Some(b"xref") {
+        return true;
+    }
+
+    // Check the first entries of each subsection, which is where broken
+    // tables usually go wrong
+    let mut at = offset + 4;
+    let mut checked = 0;
+    while checked < SAMPLED_ENTRIES {
+        let Some((first, end)) = number_at(data, skip_whitespace(data, at)) else {
+            // The trailer follows the last subsection
+            return data.get(skip_whitespace(data, at)..skip_whitespace(data, at) + 7)
+                != Some(b"trailer");
+        };
+        let Some((count, end)) = number_at(data, skip_whitespace(data, end)) else {
+            return true;
+        };
+        at = skip_whitespace(data, end);
+        for number in first..first + count {
+            // Entries are 20 bytes: 10 digits offset, 5 digits generation, type
+            let Some(entry) = data.get(at..at + 20) else {
+                return true;
+            };
+            at += 20;
+            if entry[17] != b'n' || checked >= SAMPLED_ENTRIES {
+                continue;
+            }
+            let Some((object_offset, _)) = number_at(entry, 0) else {
+                return true;
+            };
+            checked += 1;
+            match object_header(data, object_offset as usize) {
+                Some((found, _)) if found == number => {}
+                _ => return true,
+            }
+        }
+    }
+    false
+}
+
+/// Builds a new cross-reference table by scanning for objects and appends it
+/// to `data`. Returns `None` if no document catalog can be found.
+pub fn rebuild_xref(data: &[u8]) -> Option<Vec<u8>> {
+    // Offset and generation of each object, by number
+    let mut offsets: Vec<Option<(usize, u64)>> = Vec::new();
+    let mut catalog = None;
+    let mut at = 0;
+    while let Some(pos) = find(data, b"obj", at) {
+        at = pos + 3;
+        let before = pos.checked_sub(1).map(|i| data[i]);
+        let after = data.get(pos + 3).copied();
+        if !before.is_some_and(|c| c.is_ascii_whitespace())
+            || after.is_some_and(|c| !is_delimiter(c))
+        {
+            continue;
+        }
+        // Walk back over "N G " to where the header starts
+        let mut start = pos;
+        for _ in 0..2 {
+            while start > 0 && data[start - 1].is_ascii_whitespace() {
+                start -= 1;
+            }
+            while start > 0 && data[start - 1].is_ascii_digit() {
+                start -= 1;
+            }
+        }
+        let Some((number, generation)) =
+            object_header(data, start).filter(|&(number, _)| number <= MAX_OBJECT_NUMBER)
+        else {
+            continue;
+        };
+        let number = number as usize;
+        if number >= offsets.len() {
+            offsets.resize(number + 1, None);
+        }
+        offsets[number] = Some((start, generation));
+
+        // Stream data can contain anything, so skip over it
+        let end = find(data, b"endobj", at).unwrap_or(data.len());
+        let body = &data[at..end];
+        if body.windows(8).any(|w| w == b"/Catalog") && body.windows(5).any(|w| w == b"/Type") {
+            catalog = Some((number as u64, generation));
+        }
+        if let Some(stream) = find(body, b"stream", 0) {
+            if let Some(stream_end) = find(data, b"endstream", at + stream) {
+                at = stream_end;
+            }
+        }
+    }
+
+    let root = last_reference(data, b"/Root").or(catalog)?;
+    let info = last_reference(data, b"/Info");
+
+    let mut out = data.to_vec();
+    if !out.ends_with(b"\n") {
+        out.push(b'\n');
+    }
+    let xref_offset = out.len();
+    out.extend(format!("xref\n0 {}\n", offsets.len().max(1)).as_bytes());
+    out.extend(b"0000000000 65535 f\r\n");
+    for entry in offsets.iter().skip(1) {
+        let line = match entry {
+            Some((offset, generation)) => format!("{:010} {:05} n\r\n", offset, generation),
+            None => "0000000000 00000 f\r\n".to_string(),
+        };
+        out.extend(line.as_bytes());
+    }
+    let mut trailer = format!(
+        "trailer\n<< /Size {} /Root {} {} R",
+        offsets.len().max(1),
+        root.0,
+        root.1
+    );
+    if let Some((number, generation)) = info {
+        trailer.push_str(&format!(" /Info {} {} R", number, generation));
+    }
+    trailer.push_str(&format!(" >>\nstartxref\n{}\n%%EOF\n", xref_offset));
+    out.extend(trailer.as_bytes());
+    Some(out)
+}
+
+/// Repairs a file whose cross-reference table is damaged.
+pub async fn repair(engine: &Engine, data: &[u8]) -> Result<Vec<u8>, String> {
+    let data = data.to_vec();
+    let data = if find(&data, b"/ObjStm", 0).is_some() {
+        data
+    } else {
+        match blocking(move || Ok(rebuild_xref(&data).ok_or(data))).await? {
+            Ok(repaired) => return Ok(repaired),
+            // Without a catalog, MuPDF may still find more
+            Err(data) => data,
+        }
+    };
+    engine
+        .run(move |_| {
+            let doc = PdfDocument::from_bytes(&data).map_err(|e| e.to_string())?;
+            let mut repaired = Vec::new();
+            doc.write_to(&mut repaired).map_err(|e| e.to_string())?;
+            Ok(repaired)
+        })
+        .await
+}
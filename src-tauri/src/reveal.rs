@@ -0,0 +1,122 @@
+// Showing a document in the system's file manager.
+//
+// The file is selected in its folder, not just the folder opened: the Finder
+// does that with `open -R`, Explorer with `/select`. On Linux the file
+// manager is asked over D-Bus (org.freedesktop.FileManager1, which Nautilus,
+// Dolphin, Nemo, Caja and Thunar all provide); failing that, file managers
+// that take a file to select on their command line are tried, and as a last
+// resort the folder is opened.
+
+use std::path::PathBuf;
+#[cfg(not(windows))]
+use std::process::Command;
+
+use tauri::AppHandle;
+
+use crate::pdf::blocking;
+use crate::scope;
+
+/// Shows a document in the file manager with the file selected.
+#[tauri::command]
+pub async fn reveal_in_file_manager(app: AppHandle, path: String) -> Result<(), String> {
+    let path = PathBuf::from(path);
+    scope::check(&app, &path)?;
+    if !path.exists() {
+        return Err(format!("{} no longer exists", path.display()));
+    }
+    blocking(move || platform::reveal(&path)).await
+}
+
+// Runs a command to its end, telling whether it worked
+#[cfg(not(windows))]
+fn run(command: &mut Command) -> Result<(), String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let status = command
+        .status()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(format!("{} failed ({})", program, status)),
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::path::Path;
+    use std::process::Command;
+
+    pub fn reveal(path: &Path) -> Result<(), String> {
+        super::run(Command::new("open").arg("-R").arg(path))
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::os::windows::process::CommandExt;
+    use std::path::Path;
+    use std::process::Command;
+
+    pub fn reveal(path: &Path) -> Result<(), String> {
+        // Explorer wants `/select,"path"` as one argument, quoted its own way,
+        // and exits with 1 even when it worked
+        Command::new("explorer")
+            .raw_arg(format!("/select,\"{}\"", path.display()))
+            .spawn()
+            .map(|_| ())
+            .map_err(|e| format!("Failed to run Explorer: {}", e))
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use std::path::Path;
+    use std::process::{Command, Stdio};
+
+    use tauri::Url;
+
+    use super::run;
+
+    // File managers that select a file given with these flags
+    const FILE_MANAGERS: &[(&str, &[&str])] = &[
+        ("nautilus", &["--select"]),
+        ("dolphin", &["--select"]),
+        ("caja", &["--select"]),
+        ("nemo", &[]),
+    ];
+
+    fn show_items(path: &Path) -> Result<(), String> {
+        let uri = Url::from_file_path(path)
+            .map_err(|_| format!("{} isn't an absolute path", path.display()))?;
+        run(Command::new("dbus-send")
+            .args([
+                "--session",
+                "--print-reply",
+                "--dest=org.freedesktop.FileManager1",
+                "--type=method_call",
+                "/org/freedesktop/FileManager1",
+                "org.freedesktop.FileManager1.ShowItems",
+            ])
+            .arg(format!("array:string:{}", uri))
+            .arg("string:")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null()))
+    }
+
+    pub fn reveal(path: &Path) -> Result<(), String> {
+        if show_items(path).is_ok() {
+            return Ok(());
+        }
+        for (program, flags) in FILE_MANAGERS {
+            let spawned = Command::new(program)
+                .args(*flags)
+                .arg(path)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn();
+            if spawned.is_ok() {
+                return Ok(());
+            }
+        }
+        run(Command::new("xdg-open").arg(path.parent().unwrap_or(path)))
+    }
+}
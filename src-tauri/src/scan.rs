@@ -0,0 +1,272 @@
+// Scanning opened documents for active content.
+//
+// PDFs can carry JavaScript, ask to launch programs, send form data to a
+// server or pull in content from other files. Nanodoc runs none of it, but a
+// file that tries is suspect, so every opened document is scanned in the
+// background and a `document-security-warning` event lists what was found
+// for the frontend to warn about. Documents without any of it send nothing.
+// The same pass tells about XFA forms, which Nanodoc can't show (see
+// `forms.rs`), with `document-form-capabilities`.
+//
+// Actions are found through what triggers them: the document's open action,
+// the /A of links, outline items and form fields, and additional actions
+// (/AA), which run without a click on opening, closing, printing or moving
+// over a page or field, and chained on through /Next.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use lopdf::{Document, Object};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::forms::capabilities;
+use crate::links::file_spec;
+use crate::objects::{self, MAX_DEPTH};
+use crate::outline::walk_name_tree;
+use crate::pdf::{blocking, DocId, DocumentStore};
+
+// Details kept per kind, to keep warnings readable
+const MAX_DETAILS: usize = 10;
+// Characters of a script shown in the details
+const SCRIPT_PREVIEW: usize = 80;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum ActiveContent {
+    JavaScript,
+    /// Opens a file or starts a program
+    Launch,
+    /// Sends form data to a URL
+    SubmitForm,
+    /// Reads form data from a file
+    ImportData,
+    /// Jumps into another PDF, or one embedded in it
+    RemoteGoTo,
+    /// Stream data read from another file
+    ExternalStream,
+    /// A page drawn from another PDF
+    ReferenceXObject,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Finding {
+    pub kind: ActiveContent,
+    /// How many places in the document use it
+    pub count: usize,
+    /// True if any of them runs without a click
+    pub automatic: bool,
+    /// E.g. launched files, form URLs, the start of scripts
+    pub details: Vec<String>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SecurityWarning {
+    pub doc_id: DocId,
+    pub findings: Vec<Finding>,
+}
+
+struct Scan<'a> {
+    doc: &'a Document,
+    path: &'a Path,
+    findings: BTreeMap<ActiveContent, Finding>,
+}
+
+impl Scan<'_> {
+    fn add(&mut self, kind: ActiveContent, automatic: bool, detail: Option<String>) {
+        let finding = self.findings.entry(kind).or_insert(Finding {
+            kind,
+            count: 0,
+            automatic: false,
+            details: Vec::new(),
+        });
+        finding.count += 1;
+        finding.automatic |= automatic;
+        if let Some(detail) = detail.filter(|d| !finding.details.contains(d)) {
+            if finding.details.len() < MAX_DETAILS {
+                finding.details.push(detail);
+            }
+        }
+    }
+
+    fn file(&self, spec: Option<&Object>) -> Option<String> {
+        file_spec(self.doc, spec?, self.path)
+    }
+
+    fn script(&self, script: Option<&Object>) -> Option<String> {
+        let script = match objects::resolve(self.doc, script?) {
+            Object::Stream(stream) => {
+                let content = stream
+                    .decompressed_content()
+                    .unwrap_or_else(|_| stream.content.clone());
+                objects::decode_text(&content)
+            }
+            other => objects::text_string(other)?,
+        };
+        let preview: String = script
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .take(SCRIPT_PREVIEW)
+            .collect();
+        Some(preview).filter(|p| !p.is_empty())
+    }
+
+    fn action(&mut self, action: &Object, automatic: bool, depth: usize) {
+        if depth > MAX_DEPTH {
+            return;
+        }
+        let doc = self.doc;
+        let Ok(action) = objects::resolve(doc, action).as_dict() else {
+            return;
+        };
+        let detail = match objects::get_name(doc, action, b"S") {
+            Some(b"JavaScript") => Some((
+                ActiveContent::JavaScript,
+                self.script(action.get(b"JS").ok()),
+            )),
+            Some(b"Launch") => {
+                let windows = objects::get_dict(doc, action, b"Win");
+                let file = self
+                    .file(action.get(b"F").ok())
+                    .or_else(|| windows.and_then(|w| objects::get_text(doc, w, b"F")));
+                Some((ActiveContent::Launch, file))
+            }
+            Some(b"SubmitForm") => {
+                let url = objects::get(doc, action, b"F").and_then(|spec| match spec {
+                    Object::Dictionary(spec) => objects::get_text(doc, spec, b"F"),
+                    other => objects::text_string(other),
+                });
+                Some((ActiveContent::SubmitForm, url))
+            }
+            Some(b"ImportData") => {
+                Some((ActiveContent::ImportData, self.file(action.get(b"F").ok())))
+            }
+            Some(b"GoToR") | Some(b"GoToE") => {
+                Some((ActiveContent::RemoteGoTo, self.file(action.get(b"F").ok())))
+            }
+            // Renditions and others can carry a script of their own
+            _ if action.has(b"JS") => Some((
+                ActiveContent::JavaScript,
+                self.script(action.get(b"JS").ok()),
+            )),
+            _ => None,
+        };
+        if let Some((kind, detail)) = detail {
+            self.add(kind, automatic, detail);
+        }
+        match action.get(b"Next").map(|next| objects::resolve(doc, next)) {
+            Ok(Object::Array(next)) => {
+                for next in next {
+                    self.action(next, automatic, depth + 1);
+                }
+            }
+            Ok(next) => self.action(next, automatic, depth + 1),
+            Err(_) => {}
+        }
+    }
+}
+
+/// Lists the active content of a document, by kind.
+pub fn scan(doc: &Document, path: &Path) -> Vec<Finding> {
+    let mut scan = Scan {
+        doc,
+        path,
+        findings: BTreeMap::new(),
+    };
+    if let Ok(catalog) = doc.catalog() {
+        // Document-level scripts run on opening
+        if let Some(tree) = objects::get_dict(doc, catalog, b"Names")
+            .and_then(|names| objects::get_dict(doc, names, b"JavaScript"))
+        {
+            let mut scripts = Vec::new();
+            walk_name_tree(doc, tree, 0, &mut |_, action| scripts.push(action));
+            for action in scripts {
+                scan.action(action, true, 0);
+            }
+        }
+    }
+
+    for object in doc.objects.values() {
+        let dict = match object {
+            Object::Dictionary(dict) => dict,
+            Object::Stream(stream) => {
+                let dict = &stream.dict;
+                if dict.has(b"F") {
+                    let file = scan.file(dict.get(b"F").ok());
+                    scan.add(ActiveContent::ExternalStream, true, file);
+                }
+                if objects::get_name(doc, dict, b"Subtype") == Some(b"Form") {
+                    if let Some(reference) = objects::get_dict(doc, dict, b"Ref") {
+                        let file = scan.file(reference.get(b"F").ok());
+                        scan.add(ActiveContent::ReferenceXObject, true, file);
+                    }
+                }
+                dict
+            }
+            _ => continue,
+        };
+        if let Ok(action) = dict.get(b"OpenAction") {
+            scan.action(action, true, 0);
+        }
+        if let Ok(action) = dict.get(b"A") {
+            scan.action(action, false, 0);
+        }
+        if let Some(triggers) = objects::get_dict(doc, dict, b"AA") {
+            for (_, action) in triggers.iter() {
+                scan.action(action, true, 0);
+            }
+        }
+    }
+    scan.findings.into_values().collect()
+}
+
+/// Scans a document that has just been opened, off the async runtime, and
+/// emits `document-security-warning` if it has active content and
+/// `document-form-capabilities` if it has an XFA form.
+pub fn scan_in_background(app: &AppHandle, doc_id: DocId) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let Ok(doc) = app.state::<DocumentStore>().get(doc_id) else {
+            return;
+        };
+        let scanned = blocking(move || {
+            doc.with_objects(|objects| {
+                Ok((scan(objects, &doc.path), capabilities(objects, doc_id)))
+            })
+        });
+        let (findings, form) = match scanned.await {
+            Ok((findings, form)) => (Ok(findings), Some(form)),
+            Err(e) => (Err(e), None),
+        };
+        if let Some(form) = form.filter(|form| !form.xfa_packets.is_empty()) {
+            eprintln!(
+                "Document {} has an XFA form ({:?})",
+                doc_id, form.xfa_packets
+            );
+            let _ = app.emit("document-form-capabilities", form);
+        }
+        match findings {
+            Ok(findings) if findings.is_empty() => {}
+            Ok(findings) => {
+                eprintln!(
+                    "Document {} has active content: {}",
+                    doc_id,
+                    findings
+                        .iter()
+                        .map(|f| format!("{:?} x{}", f.kind, f.count))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                let _ = app.emit(
+                    "document-security-warning",
+                    SecurityWarning { doc_id, findings },
+                );
+            }
+            Err(e) => eprintln!("Failed to scan document {}: {}", doc_id, e),
+        }
+    });
+}
@@ -0,0 +1,222 @@
+// Speculative rendering of neighbouring pages.
+//
+// Once a page has been rendered for the user, the pages around it are queued
+// as background jobs at the same scale. Finished bitmaps are kept in a page
+// cache and announced with a `page-ready` event, so that the next
+// `render_page` call for them returns immediately. The page cache budget can
+// be changed by the user with `set_page_cache_budget`.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::colors::ColorMode;
+use crate::engine::Engine;
+use crate::jobs::CancelToken;
+use crate::lru::{CacheStats, LruCache};
+use crate::pdf::{rasterize_page, zoom_key, DocId, OpenDocument};
+use crate::tiles::TileCache;
+
+const MB: usize = 1024 * 1024;
+const PAGE_CACHE_BUDGET: usize = 256 * MB;
+const MIN_PAGE_CACHE_BUDGET: usize = 64 * MB;
+const MAX_PAGE_CACHE_BUDGET: usize = 2048 * MB;
+const DEFAULT_PRERENDER_RADIUS: i32 = 2;
+const MAX_PRERENDER_RADIUS: i32 = 10;
+
+#[derive(Hash, PartialEq, Eq, Clone, Copy)]
+pub struct PageKey {
+    pub doc_id: DocId,
+    pub page: i32,
+    pub zoom: u32,
+    pub mode: ColorMode,
+}
+
+impl PageKey {
+    pub fn new(doc_id: DocId, page: i32, scale: f32, mode: ColorMode) -> Self {
+        PageKey {
+            doc_id,
+            page,
+            zoom: zoom_key(scale),
+            mode,
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PageReady {
+    pub doc_id: DocId,
+    pub page: i32,
+    pub scale: f32,
+    pub color_mode: ColorMode,
+}
+
+pub struct RenderScheduler {
+    pages: Mutex<LruCache<PageKey, Vec<u8>>>,
+    // Pages queued for prerendering that have not finished yet, with the
+    // token of the wave that queued them
+    pending: Mutex<HashMap<PageKey, CancelToken>>,
+    // Token of the latest prerender wave per document; a new wave supersedes it
+    waves: Mutex<HashMap<DocId, CancelToken>>,
+    radius: AtomicI32,
+}
+
+impl Default for RenderScheduler {
+    fn default() -> Self {
+        RenderScheduler {
+            pages: Mutex::new(LruCache::new(PAGE_CACHE_BUDGET)),
+            pending: Mutex::new(HashMap::new()),
+            waves: Mutex::new(HashMap::new()),
+            radius: AtomicI32::new(DEFAULT_PRERENDER_RADIUS),
+        }
+    }
+}
+
+impl RenderScheduler {
+    pub fn cached(&self, key: &PageKey) -> Option<Vec<u8>> {
+        self.pages.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn store(&self, key: PageKey, bitmap: Vec<u8>) {
+        let size = bitmap.len();
+        self.pages.lock().unwrap().insert(key, bitmap, size);
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.pages.lock().unwrap().used()
+    }
+
+    pub fn clear_document(&self, doc_id: DocId) {
+        self.pages
+            .lock()
+            .unwrap()
+            .retain(|key| key.doc_id != doc_id);
+        self.pending
+            .lock()
+            .unwrap()
+            .retain(|key, _| key.doc_id != doc_id);
+        if let Some(wave) = self.waves.lock().unwrap().remove(&doc_id) {
+            wave.cancel();
+        }
+    }
+
+    /// Queues the pages around `page` for background rendering, nearest first.
+    /// Prerenders still queued or running for an earlier position are
+    /// cancelled.
+    pub fn prerender_around(
+        &self,
+        app: &AppHandle,
+        engine: &Engine,
+        doc: &Arc<OpenDocument>,
+        page: i32,
+        scale: f32,
+        mode: ColorMode,
+    ) {
+        let wave = CancelToken::default();
+        if let Some(previous) = self.waves.lock().unwrap().insert(doc.id, wave.clone()) {
+            previous.cancel();
+        }
+
+        let radius = self.radius.load(Ordering::Relaxed);
+        for distance in 1..=radius {
+            for target in [page + distance, page - distance] {
+                if target < 0 || target >= doc.page_count {
+                    continue;
+                }
+                let key = PageKey::new(doc.id, target, scale, mode);
+                if self.pages.lock().unwrap().contains(&key) {
+                    continue;
+                }
+                {
+                    // Pages left over from a cancelled wave are queued again
+                    let mut pending = self.pending.lock().unwrap();
+                    if pending.get(&key).is_some_and(|t| !t.is_cancelled()) {
+                        continue;
+                    }
+                    pending.insert(key, wave.clone());
+                }
+
+                let app = app.clone();
+                let doc = doc.clone();
+                let wave = wave.clone();
+                engine.run_background(move |worker| {
+                    let scheduler = app.state::<RenderScheduler>();
+                    let result = match wave.check() {
+                        Ok(()) => rasterize_page(worker, &doc, target, scale, mode, &wave),
+                        Err(e) => Err(e),
+                    };
+                    {
+                        let mut pending = scheduler.pending.lock().unwrap();
+                        if pending.get(&key).is_some_and(|t| t.is_same(&wave)) {
+                            pending.remove(&key);
+                        }
+                    }
+                    match result {
+                        Ok(bitmap) => {
+                            scheduler.store(key, bitmap);
+                            let _ = app.emit(
+                                "page-ready",
+                                PageReady {
+                                    doc_id: doc.id,
+                                    page: target,
+                                    scale,
+                                    color_mode: mode,
+                                },
+                            );
+                        }
+                        Err(_) if wave.is_cancelled() => {}
+                        Err(e) => eprintln!("Prerender of page {} failed: {}", target, e),
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Sets how many pages before and after the current one are prerendered.
+#[tauri::command]
+pub async fn set_prerender_radius(
+    radius: i32,
+    scheduler: State<'_, RenderScheduler>,
+) -> Result<(), String> {
+    scheduler
+        .radius
+        .store(radius.clamp(0, MAX_PRERENDER_RADIUS), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Sets the memory budget of the rendered page cache, in megabytes.
+/// Returns the budget actually applied after clamping.
+#[tauri::command]
+pub async fn set_page_cache_budget(
+    megabytes: usize,
+    scheduler: State<'_, RenderScheduler>,
+) -> Result<usize, String> {
+    let budget = megabytes
+        .saturating_mul(MB)
+        .clamp(MIN_PAGE_CACHE_BUDGET, MAX_PAGE_CACHE_BUDGET);
+    scheduler.pages.lock().unwrap().set_budget(budget);
+    Ok(budget / MB)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenderCacheStats {
+    pub pages: CacheStats,
+    pub tiles: CacheStats,
+}
+
+#[tauri::command]
+pub async fn get_cache_stats(
+    scheduler: State<'_, RenderScheduler>,
+    tiles: State<'_, TileCache>,
+) -> Result<RenderCacheStats, String> {
+    Ok(RenderCacheStats {
+        pages: scheduler.pages.lock().unwrap().stats(),
+        tiles: tiles.stats(),
+    })
+}
@@ -0,0 +1,62 @@
+// Which files the webview may read and write.
+//
+// The fs plugin's scope starts out empty, and a file is only added to it once
+// the user opened it: picked in a file dialog (the dialog plugin adds those
+// itself), passed on the command line, or dropped on the window. Commands
+// that read files named by the frontend check the same scope, so neither way
+// reads a path the user didn't choose. Commands that write check it as well:
+// a save dialog adds the file it picked, a folder dialog the folder, so
+// nothing is written where the user wasn't asked.
+//
+// Files on the recent files list are allowed again on each start (see
+// `recent_files.rs`), so they can still be opened from it. Folders the user
+// added to the library are allowed as a whole, and the library allows them
+// again itself.
+
+use std::path::Path;
+
+use tauri::{AppHandle, DragDropEvent, Manager, Window, WindowEvent};
+use tauri_plugin_fs::FsExt;
+
+/// Adds a file the user opened to the scope.
+pub fn allow(app: &AppHandle, path: &Path) {
+    // The scope matches resolved paths
+    let path = &std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    match app.fs_scope().allow_file(path) {
+        Ok(()) => eprintln!("Allowed access to {}", path.display()),
+        Err(e) => eprintln!("Failed to allow {}: {}", path.display(), e),
+    }
+}
+
+/// Adds a folder of the library, and everything in it, to the scope.
+pub fn allow_folder(app: &AppHandle, path: &Path) {
+    if let Err(e) = app.fs_scope().allow_directory(path, true) {
+        eprintln!("Failed to allow {}: {}", path.display(), e);
+    }
+}
+
+/// Fails unless the user opened `path`.
+pub fn check(app: &AppHandle, path: &Path) -> Result<(), String> {
+    match app.fs_scope().is_allowed(path) {
+        true => Ok(()),
+        false => Err(format!("{} wasn't opened in Nanodoc", path.display())),
+    }
+}
+
+/// Fails unless `path` was picked in a save dialog, lies in a folder picked
+/// for the output, or is a file the user opened.
+pub fn check_write(app: &AppHandle, path: &Path) -> Result<(), String> {
+    match app.fs_scope().is_allowed(path) {
+        true => Ok(()),
+        false => Err(format!("{} wasn't chosen to save to", path.display())),
+    }
+}
+
+/// Allows the files dropped on a window.
+pub fn handle_window_event(window: &Window, event: &WindowEvent) {
+    if let WindowEvent::DragDrop(DragDropEvent::Drop { paths, .. }) = event {
+        for path in paths {
+            allow(window.app_handle(), path);
+        }
+    }
+}
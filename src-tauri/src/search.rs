@@ -0,0 +1,567 @@
+// Searching the text of a whole document, or of all open documents.
+//
+// Pages are searched on the engine's worker threads, one page per job so
+// renders of what's on screen can go in between, and the matches of each page
+// are sent as a `search-results` event as soon as it's done. Pages therefore
+// report out of order. The lines of a page are searched as one text, joined
+// by spaces, so a phrase broken across lines is found too; such a match has
+// a rectangle on each of its lines. Results are tagged with the document
+// they're from, as a search of all open documents goes through their pages
+// in turn. Pages without a text layer are searched through the text
+// recognized on them, if any (see `ocr.rs`).
+//
+// In regex mode the query is a regular expression, e.g. `INV-\d{6}`. The
+// regex crate never backtracks, so a pattern can't take exponentially long,
+// but big patterns compile to big automata; patterns are limited in length
+// and compiled size, and a regex search gives up after a while.
+//
+// In fuzzy mode words match if they are only a few typos apart, so OCR
+// errors and misspellings ("recieved") still find them. Each match has a
+// score of how close it is; exact matches score 1.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::engine::Engine;
+use crate::jobs::{CancelToken, JobId, RenderJobs};
+use crate::ocr;
+use crate::pdf::{blocking, Bounds, DocId, DocumentStore, OpenDocument};
+use crate::text::{page_runs, TextRun};
+
+// Characters of text shown on each side of a match
+const SNIPPET_CONTEXT: usize = 40;
+
+// Limits of regex searches
+const MAX_PATTERN_LENGTH: usize = 1000;
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+const REGEX_TIME_LIMIT: Duration = Duration::from_secs(30);
+
+// Words up to this long must match exactly in fuzzy mode, as a typo in them
+// makes another word more often than not
+const EXACT_WORD_LENGTH: usize = 3;
+// Words from this long may have two typos rather than one
+const LONG_WORD_LENGTH: usize = 8;
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SearchOptions {
+    pub case_sensitive: bool,
+    /// Only matches that aren't part of a longer word
+    pub whole_word: bool,
+    /// The query is a regular expression
+    pub regex: bool,
+    /// Words a few typos apart from those of the query match too
+    pub fuzzy: bool,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchMatch {
+    /// One per line the match is on, in page space
+    pub rects: Vec<Bounds>,
+    /// The match with some text around it
+    pub snippet: String,
+    /// How close the match is to the query, from 0 to 1
+    pub score: f32,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResults {
+    pub search_id: Option<JobId>,
+    pub doc_id: DocId,
+    pub page: i32,
+    pub matches: Vec<SearchMatch>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchSummary {
+    pub matches: usize,
+    /// Pages with at least one match
+    pub pages: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentSummary {
+    pub doc_id: DocId,
+    pub matches: usize,
+    /// Pages with at least one match
+    pub pages: usize,
+}
+
+// What a search looks for
+enum Pattern {
+    /// Folded to lower case unless the search is case sensitive
+    Text(Vec<char>),
+    Regex(Regex),
+    /// The words of the query, folded like text
+    Fuzzy(Vec<Vec<char>>),
+}
+
+impl Pattern {
+    fn new(query: &str, options: SearchOptions) -> Result<Self, String> {
+        if options.fuzzy {
+            if options.regex {
+                return Err("Fuzzy search doesn't work with regular expressions".to_string());
+            }
+            let words: Vec<Vec<char>> = words(query)
+                .map(|word| word.map(|c| fold(c, options.case_sensitive)).collect())
+                .collect();
+            if words.is_empty() {
+                return Err("Enter the words to search for".to_string());
+            }
+            return Ok(Pattern::Fuzzy(words));
+        }
+        if !options.regex {
+            let text: Vec<char> = query
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+                .chars()
+                .map(|c| fold(c, options.case_sensitive))
+                .collect();
+            if text.is_empty() {
+                return Err("Enter the text to search for".to_string());
+            }
+            return Ok(Pattern::Text(text));
+        }
+        if query.is_empty() {
+            return Err("Enter the pattern to search for".to_string());
+        }
+        if query.chars().count() > MAX_PATTERN_LENGTH {
+            return Err(format!(
+                "Patterns can be at most {} characters long",
+                MAX_PATTERN_LENGTH
+            ));
+        }
+        RegexBuilder::new(query)
+            .case_insensitive(!options.case_sensitive)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .dfa_size_limit(REGEX_SIZE_LIMIT)
+            .build()
+            .map(Pattern::Regex)
+            .map_err(|e| format!("Invalid pattern: {}", e))
+    }
+
+    // The ranges of characters that match, without overlaps, and how well
+    fn find(&self, text: &[char], options: SearchOptions) -> Vec<(usize, usize, f32)> {
+        let bounded = |start: usize, end: usize| {
+            !options.whole_word
+                || ((start == 0 || !is_word(text[start - 1]))
+                    && (end == text.len() || !is_word(text[end])))
+        };
+        let mut found = Vec::new();
+        match self {
+            Pattern::Text(query) => {
+                let folded: Vec<char> = text
+                    .iter()
+                    .map(|&c| fold(c, options.case_sensitive))
+                    .collect();
+                let mut start = 0;
+                while start + query.len() <= folded.len() {
+                    let end = start + query.len();
+                    if folded[start..end] == *query && bounded(start, end) {
+                        found.push((start, end, 1.0));
+                        start = end;
+                    } else {
+                        start += 1;
+                    }
+                }
+            }
+            Pattern::Regex(regex) => {
+                let haystack: String = text.iter().collect();
+                let starts: Vec<usize> = haystack.char_indices().map(|(i, _)| i).collect();
+                let index = |byte: usize| starts.partition_point(|&start| start < byte);
+                for m in regex.find_iter(&haystack).filter(|m| !m.is_empty()) {
+                    let (start, end) = (index(m.start()), index(m.end()));
+                    if bounded(start, end) {
+                        found.push((start, end, 1.0));
+                    }
+                }
+            }
+            Pattern::Fuzzy(query) => {
+                // Ranges of the words of the text
+                let mut spans = Vec::new();
+                let mut start = None;
+                for (i, &c) in text.iter().chain([&' ']).enumerate() {
+                    match (is_word(c), start) {
+                        (true, None) => start = Some(i),
+                        (false, Some(from)) => {
+                            spans.push((from, i));
+                            start = None;
+                        }
+                        _ => {}
+                    }
+                }
+                let folded: Vec<Vec<char>> = spans
+                    .iter()
+                    .map(|&(from, to)| {
+                        text[from..to]
+                            .iter()
+                            .map(|&c| fold(c, options.case_sensitive))
+                            .collect()
+                    })
+                    .collect();
+                let wanted: usize = query.iter().map(Vec::len).sum();
+                let mut first = 0;
+                while first + query.len() <= spans.len() {
+                    let edits: Option<usize> = query
+                        .iter()
+                        .zip(&folded[first..])
+                        .map(|(word, candidate)| {
+                            let edits = edit_distance(word, candidate);
+                            (edits <= allowed_edits(word.len())).then_some(edits)
+                        })
+                        .sum();
+                    match edits {
+                        Some(edits) => {
+                            let last = first + query.len() - 1;
+                            let score = 1.0 - edits as f32 / wanted.max(1) as f32;
+                            found.push((spans[first].0, spans[last].1, score));
+                            first = last + 1;
+                        }
+                        None => first += 1,
+                    }
+                }
+            }
+        }
+        found
+    }
+}
+
+fn fold(c: char, case_sensitive: bool) -> char {
+    match case_sensitive {
+        true => c,
+        false => c.to_lowercase().next().unwrap_or(c),
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// The words of a text, as runs of word characters
+fn words(text: &str) -> impl Iterator<Item = std::str::Chars<'_>> {
+    text.split(|c: char| !is_word(c))
+        .filter(|word| !word.is_empty())
+        .map(str::chars)
+}
+
+/// How many typos a word of `length` characters may have in fuzzy mode.
+pub fn allowed_edits(length: usize) -> usize {
+    match length {
+        0..=EXACT_WORD_LENGTH => 0,
+        _ if length < LONG_WORD_LENGTH => 1,
+        _ => 2,
+    }
+}
+
+/// The number of characters to insert, delete, change or swap with the next
+/// to turn `a` into `b`.
+pub fn edit_distance(a: &[char], b: &[char]) -> usize {
+    // Rows of the table for the last two characters of `a` and this one
+    let mut before: Vec<usize> = Vec::new();
+    let mut last: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (last[j] + 1).min(row[j - 1] + 1).min(last[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(before[j - 2] + 1);
+            }
+        }
+        before = std::mem::replace(&mut last, row);
+    }
+    last[b.len()]
+}
+
+/// The smallest box around all of `glyphs`.
+pub fn union(glyphs: &[Bounds]) -> Bounds {
+    let x0 = glyphs.iter().map(|g| g.x).fold(f32::INFINITY, f32::min);
+    let y0 = glyphs.iter().map(|g| g.y).fold(f32::INFINITY, f32::min);
+    let x1 = glyphs
+        .iter()
+        .map(|g| g.x + g.width)
+        .fold(f32::NEG_INFINITY, f32::max);
+    let y1 = glyphs
+        .iter()
+        .map(|g| g.y + g.height)
+        .fold(f32::NEG_INFINITY, f32::max);
+    Bounds {
+        x: x0,
+        y: y0,
+        width: x1 - x0,
+        height: y1 - y0,
+    }
+}
+
+// The boxes of a match, one per line
+fn line_rects(glyphs: &[Option<(usize, Bounds)>]) -> Vec<Bounds> {
+    let mut lines: Vec<(usize, Vec<Bounds>)> = Vec::new();
+    for &(line, glyph) in glyphs.iter().flatten() {
+        match lines.last_mut() {
+            Some((last, boxes)) if *last == line => boxes.push(glyph),
+            _ => lines.push((line, vec![glyph])),
+        }
+    }
+    lines.iter().map(|(_, boxes)| union(boxes)).collect()
+}
+
+fn snippet(text: &[char], start: usize, end: usize) -> String {
+    let from = start.saturating_sub(SNIPPET_CONTEXT);
+    let to = (end + SNIPPET_CONTEXT).min(text.len());
+    let mut snippet = String::new();
+    if from > 0 {
+        snippet.push('…');
+    }
+    snippet.extend(&text[from..to]);
+    if to < text.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+fn search_page(runs: Vec<TextRun>, pattern: &Pattern, options: SearchOptions) -> Vec<SearchMatch> {
+    // The text of the page, and where each of its characters is
+    let mut text = Vec::new();
+    let mut glyphs = Vec::new();
+    for (line, run) in runs.into_iter().enumerate() {
+        if !text.is_empty() {
+            text.push(' ');
+            glyphs.push(None);
+        }
+        for (c, glyph) in run.text.chars().zip(run.glyphs) {
+            text.push(c);
+            glyphs.push(Some((line, glyph)));
+        }
+    }
+    pattern
+        .find(&text, options)
+        .into_iter()
+        .map(|(start, end, score)| SearchMatch {
+            rects: line_rects(&glyphs[start..end]),
+            snippet: snippet(&text, start, end),
+            score,
+        })
+        .collect()
+}
+
+// A search, as the lanes it runs in share it
+struct Search {
+    app: AppHandle,
+    docs: Vec<Arc<OpenDocument>>,
+    /// The pages to search, as indices into `docs` and page numbers
+    pages: Vec<(usize, i32)>,
+    /// The text recognized on scanned pages of each document, by page
+    recognized: Vec<HashMap<i32, Vec<TextRun>>>,
+    pattern: Pattern,
+    options: SearchOptions,
+    search_id: Option<JobId>,
+    token: CancelToken,
+    /// When a regex search gives up
+    deadline: Option<Instant>,
+}
+
+// The numbers of matches and of pages with any, for each document searched
+type Found = Vec<(usize, usize)>;
+
+// Searches every `lanes`th page from `first`, as one of the lanes a search
+// runs in
+async fn search_lane(search: Arc<Search>, first: usize, lanes: usize) -> Result<Found, String> {
+    let engine = search.app.state::<Engine>();
+    let mut found = vec![(0, 0); search.docs.len()];
+    for &(index, page) in search.pages.iter().skip(first).step_by(lanes) {
+        search.token.check()?;
+        if search
+            .deadline
+            .is_some_and(|deadline| Instant::now() > deadline)
+        {
+            return Err(format!(
+                "The search took longer than {} seconds. Try a more specific pattern",
+                REGEX_TIME_LIMIT.as_secs()
+            ));
+        }
+        let job = search.clone();
+        let matches = engine
+            .run(move |worker| {
+                job.token.check()?;
+                let text_page = worker
+                    .document(&job.docs[index])?
+                    .load_page(page)
+                    .and_then(|p| p.to_text_page(mupdf::TextPageOptions::PRESERVE_WHITESPACE))
+                    .map_err(|e| e.to_string())?;
+                let mut runs = page_runs(&text_page);
+                if runs.is_empty() {
+                    if let Some(recognized) = job.recognized[index].get(&page) {
+                        runs = recognized.clone();
+                    }
+                }
+                Ok(search_page(runs, &job.pattern, job.options))
+            })
+            .await?;
+        if matches.is_empty() {
+            continue;
+        }
+        found[index].0 += matches.len();
+        found[index].1 += 1;
+        let _ = search.app.emit(
+            "search-results",
+            SearchResults {
+                search_id: search.search_id,
+                doc_id: search.docs[index].id,
+                page,
+                matches,
+            },
+        );
+    }
+    Ok(found)
+}
+
+// Searches the pages of `docs`, the first page of each, then the second and
+// so on, so every document gets results early
+async fn search_documents(
+    app: AppHandle,
+    docs: Vec<Arc<OpenDocument>>,
+    query: &str,
+    options: SearchOptions,
+    search_id: Option<JobId>,
+    engine: &Engine,
+    jobs: &RenderJobs,
+) -> Result<Found, String> {
+    let pattern = Pattern::new(query, options)?;
+    let longest = docs.iter().map(|doc| doc.page_count).max().unwrap_or(0);
+    let pages: Vec<(usize, i32)> = (0..longest)
+        .flat_map(|page| {
+            docs.iter()
+                .enumerate()
+                .filter(move |(_, doc)| page < doc.page_count)
+                .map(move |(index, _)| (index, page))
+        })
+        .collect();
+
+    // A lane per worker thread keeps every thread busy without queueing the
+    // whole document ahead of renders
+    let file = ocr::database_file(&app)?;
+    let lookup = docs.clone();
+    let recognized = blocking(move || {
+        Ok(lookup
+            .iter()
+            .map(|doc| ocr::recognized_pages(&file, doc.fingerprint()))
+            .collect())
+    })
+    .await?;
+
+    let token = jobs.begin_batch(search_id);
+    let lanes = engine.threads().min(pages.len().max(1));
+    let count = docs.len();
+    let search = Arc::new(Search {
+        app,
+        docs,
+        pages,
+        recognized,
+        pattern,
+        options,
+        search_id,
+        token: token.clone(),
+        deadline: options.regex.then(|| Instant::now() + REGEX_TIME_LIMIT),
+    });
+    let tasks: Vec<_> = (0..lanes)
+        .map(|first| tauri::async_runtime::spawn(search_lane(search.clone(), first, lanes)))
+        .collect();
+    let mut result = Ok(vec![(0, 0); count]);
+    for task in tasks {
+        let lane = task.await.map_err(|e| e.to_string()).and_then(|lane| lane);
+        match (lane, &mut result) {
+            (Ok(found), Ok(total)) => {
+                for (total, found) in total.iter_mut().zip(found) {
+                    total.0 += found.0;
+                    total.1 += found.1;
+                }
+            }
+            (Err(e), Ok(_)) => {
+                // The other lanes needn't go on
+                token.cancel();
+                result = Err(e);
+            }
+            _ => {}
+        }
+    }
+    jobs.finish_batch(search_id);
+    result
+}
+
+/// Searches all pages for `query`, text or a regular expression, sending the
+/// matches of each page as a `search-results` event, and returns how many
+/// were found once every page is done.
+///
+/// Passing a `search_id` allows the search to be aborted with
+/// `cancel_render`.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn search_document(
+    app: AppHandle,
+    doc_id: DocId,
+    query: String,
+    options: Option<SearchOptions>,
+    search_id: Option<JobId>,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    jobs: State<'_, RenderJobs>,
+) -> Result<SearchSummary, String> {
+    let doc = store.get(doc_id)?;
+    let options = options.unwrap_or_default();
+    let found =
+        search_documents(app, vec![doc], &query, options, search_id, &engine, &jobs).await?;
+    let (matches, pages) = found[0];
+    eprintln!(
+        "Found {} matches on {} pages of document {}",
+        matches, pages, doc_id
+    );
+    Ok(SearchSummary { matches, pages })
+}
+
+/// Searches every open document like `search_document`, with the pages of
+/// all of them interleaved so each gets results early. Returns how many
+/// matches each document has, in no particular order.
+#[tauri::command]
+pub async fn search_open_documents(
+    app: AppHandle,
+    query: String,
+    options: Option<SearchOptions>,
+    search_id: Option<JobId>,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    jobs: State<'_, RenderJobs>,
+) -> Result<Vec<DocumentSummary>, String> {
+    // Documents still loading have only some of their pages yet
+    let docs: Vec<_> = store.all().into_iter().filter(|doc| !doc.partial).collect();
+    if docs.is_empty() {
+        return Err("There are no documents open to search".to_string());
+    }
+    let ids: Vec<DocId> = docs.iter().map(|doc| doc.id).collect();
+    let options = options.unwrap_or_default();
+    let found = search_documents(app, docs, &query, options, search_id, &engine, &jobs).await?;
+    let total: usize = found.iter().map(|(matches, _)| matches).sum();
+    eprintln!(
+        "Found {} matches across {} open documents",
+        total,
+        ids.len()
+    );
+    Ok(ids
+        .into_iter()
+        .zip(found)
+        .map(|(doc_id, (matches, pages))| DocumentSummary {
+            doc_id,
+            matches,
+            pages,
+        })
+        .collect())
+}
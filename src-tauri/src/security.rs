@@ -0,0 +1,372 @@
+// Password-protected documents.
+//
+// A file that needs a password to open is held here, already read, while the
+// frontend asks for the password. `unlock_document` then registers it in the
+// `DocumentStore` under the id `open_document` handed out, so nothing else has
+// to deal with locked documents. The password stays with the open document,
+// as every render worker has to unlock its own engine handle. If the user
+// asks to, it is also saved in the system's password store (see
+// `passwords.rs`), and files it opens then come up unlocked.
+//
+// Encrypting is left to MuPDF, as lopdf can only read encrypted files. The
+// document is serialized in memory and handed over, so no unencrypted copy is
+// ever written to disk. Removing the protection again asks the user to
+// confirm first.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use mupdf::pdf::{Encryption, PdfWriteOptions, Permission};
+use serde::Deserialize;
+use tauri::{AppHandle, State};
+use tauri_plugin_dialog::{DialogExt, MessageDialogButtons, MessageDialogKind};
+
+use crate::compose::{write_document, write_with_options, WrittenDocument};
+use crate::edit::{replace, SavedDocument};
+use crate::engine::Engine;
+use crate::passwords;
+use crate::pdf::{
+    blocking, read_data, validate, validate_file, DocId, DocumentData, DocumentStore, OpenDocument,
+    OpenError, OpenedDocument,
+};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scan;
+use crate::scheduler::RenderScheduler;
+use crate::scope;
+use crate::tiles::TileCache;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptionOptions {
+    /// Where to save; over the document's own file if missing
+    pub output_path: Option<String>,
+    /// Needed to open the file; anyone can open it if empty
+    #[serde(default)]
+    pub user_password: String,
+    /// Needed to change the permissions or remove the protection
+    pub owner_password: String,
+    #[serde(default)]
+    pub allow_printing: bool,
+    #[serde(default)]
+    pub allow_copying: bool,
+    /// Changing pages, annotations and form fields
+    #[serde(default)]
+    pub allow_editing: bool,
+}
+
+impl EncryptionOptions {
+    fn permissions(&self) -> Permission {
+        // Screen readers may always extract the text
+        let mut permissions = Permission::ACCESSIBILITY;
+        if self.allow_printing {
+            permissions |= Permission::PRINT | Permission::PRINT_HQ;
+        }
+        if self.allow_copying {
+            permissions |= Permission::COPY;
+        }
+        if self.allow_editing {
+            permissions |=
+                Permission::MODIFY | Permission::ANNOTATE | Permission::FORM | Permission::ASSEMBLE;
+        }
+        permissions
+    }
+}
+
+/// Files waiting for their password.
+#[derive(Default)]
+pub struct LockedDocuments {
+    docs: Mutex<HashMap<DocId, (PathBuf, DocumentData)>>,
+}
+
+impl LockedDocuments {
+    pub fn insert(&self, doc_id: DocId, path: PathBuf, data: DocumentData) {
+        eprintln!("Document {} needs a password: {}", doc_id, path.display());
+        self.docs.lock().unwrap().insert(doc_id, (path, data));
+    }
+
+    pub fn take(&self, doc_id: DocId) -> Option<(PathBuf, DocumentData)> {
+        self.docs.lock().unwrap().remove(&doc_id)
+    }
+}
+
+// Page count of a locked file once opened with `password`, or None if the
+// password doesn't open it
+async fn try_password(
+    engine: &Engine,
+    path: &Path,
+    data: &DocumentData,
+    password: &str,
+) -> Result<Option<i32>, String> {
+    let checked = match data {
+        DocumentData::Owned(bytes) => {
+            validate(engine, bytes.clone(), Some(password.to_string())).await
+        }
+        DocumentData::Mapped(_) => {
+            validate_file(engine, path.to_path_buf(), Some(password.to_string())).await
+        }
+    };
+    checked.map_err(|e| format!("Failed to open {}: {}", path.display(), e))
+}
+
+fn register(
+    store: &DocumentStore,
+    doc_id: DocId,
+    path: PathBuf,
+    data: DocumentData,
+    page_count: i32,
+    password: String,
+) -> OpenedDocument {
+    let mut doc = OpenDocument::new(doc_id, path, data, page_count);
+    doc.password = Some(password);
+    let doc = store.insert(doc);
+    eprintln!(
+        "Unlocked document {} ({} pages): {}",
+        doc.id,
+        doc.page_count,
+        doc.path.display()
+    );
+    OpenedDocument {
+        doc_id,
+        page_count,
+        loading: false,
+        repaired: false,
+    }
+}
+
+/// Opens a file that needs a password with the one saved for it. Without a
+/// saved password that works, the file waits for `unlock_document`, and this
+/// fails with `PasswordRequired`.
+pub async fn open_locked(
+    path: PathBuf,
+    data: DocumentData,
+    store: &DocumentStore,
+    engine: &Engine,
+    locked: &LockedDocuments,
+) -> Result<OpenedDocument, OpenError> {
+    let doc_id = store.next_id();
+    let (data, saved) = blocking(move || {
+        let saved = passwords::load(&data.fingerprint()).unwrap_or_else(|e| {
+            eprintln!("Failed to look up a saved password: {}", e);
+            None
+        });
+        Ok((data, saved))
+    })
+    .await?;
+    if let Some(password) = saved {
+        match try_password(engine, &path, &data, &password).await? {
+            Some(page_count) => {
+                return Ok(register(store, doc_id, path, data, page_count, password));
+            }
+            None => eprintln!("The saved password doesn't open {}", path.display()),
+        }
+    }
+    locked.insert(doc_id, path, data);
+    Err(OpenError::PasswordRequired { doc_id })
+}
+
+/// Opens a document that `open_document` reported as `PasswordRequired`, and
+/// with `remember`, saves the password in the system's password store for
+/// the next time the file is opened.
+///
+/// A wrong password fails with `WrongPassword`, and the document can be tried
+/// again. A file that turns out to be damaged once unlocked fails with
+/// `Failed` and is forgotten.
+#[tauri::command]
+pub async fn unlock_document(
+    app: AppHandle,
+    doc_id: DocId,
+    password: String,
+    remember: Option<bool>,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    locked: State<'_, LockedDocuments>,
+) -> Result<OpenedDocument, OpenError> {
+    let (path, data) = locked
+        .take(doc_id)
+        .ok_or_else(|| format!("Document {} isn't waiting for a password", doc_id))?;
+    let Some(page_count) = try_password(&engine, &path, &data, &password).await? else {
+        locked.insert(doc_id, path, data);
+        return Err(OpenError::WrongPassword);
+    };
+    let opened = register(&store, doc_id, path, data, page_count, password);
+    scan::scan_in_background(&app, doc_id);
+
+    if remember == Some(true) {
+        let doc = store.get(doc_id)?;
+        let saved = blocking(move || {
+            let name = doc.path.file_name().unwrap_or_default().to_string_lossy();
+            passwords::save(
+                doc.fingerprint(),
+                &format!("Password for {}", name),
+                doc.password.as_deref().unwrap_or_default(),
+            )
+        })
+        .await;
+        // The document is open either way
+        if let Err(e) = saved {
+            eprintln!("Failed to save the password of document {}: {}", doc_id, e);
+        }
+    }
+    Ok(opened)
+}
+
+/// Forgets the password saved for a document, so that opening the file asks
+/// for it again.
+#[tauri::command]
+pub async fn forget_password(doc_id: DocId, store: State<'_, DocumentStore>) -> Result<(), String> {
+    let doc = store.get(doc_id)?;
+    blocking(move || passwords::delete(doc.fingerprint())).await?;
+    eprintln!("Forgot the saved password of document {}", doc_id);
+    Ok(())
+}
+
+/// Saves the document with its edits, encrypted with AES-256, to the output
+/// path or over its own file. The open document then refers to the saved file
+/// and is unlocked with the user password. A file whose permissions are
+/// restricted needs its owner password given first (see `permissions.rs`).
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn save_encrypted(
+    app: AppHandle,
+    doc_id: DocId,
+    options: EncryptionOptions,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<SavedDocument, String> {
+    if options.owner_password.is_empty() {
+        return Err("Encrypting a document needs an owner password".to_string());
+    }
+    if options.owner_password == options.user_password {
+        return Err("The owner password must differ from the user password".to_string());
+    }
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::ChangeSecurity).await?;
+    let path = options
+        .output_path
+        .as_ref()
+        .map_or_else(|| doc.path.clone(), PathBuf::from);
+    scope::check_write(&app, &path)?;
+
+    let (objects, bytes) = blocking(move || {
+        let mut objects = doc.with_objects(|objects| Ok(objects.clone()))?;
+        // Edits leave pages and page tree nodes behind that nothing refers to
+        objects.prune_objects();
+        objects.compress();
+        let mut bytes = Vec::new();
+        objects.save_to(&mut bytes).map_err(|e| e.to_string())?;
+        Ok((objects, bytes))
+    })
+    .await?;
+
+    let mut write_options = PdfWriteOptions::default();
+    write_options
+        .set_encryption(Encryption::Aes256)
+        .set_permissions(options.permissions())
+        .set_owner_password(&options.owner_password)
+        .set_user_password(&options.user_password)
+        .set_garbage_level(1)
+        .set_compress(true);
+    {
+        let path = path.clone();
+        engine
+            .run(move |_| write_with_options(&bytes, &path, write_options))
+            .await?;
+    }
+    eprintln!("Saved document {} encrypted to {}", doc_id, path.display());
+
+    let data = {
+        let path = path.clone();
+        blocking(move || read_data(&path)).await?
+    };
+    let page_count = objects.get_pages().len() as i32;
+    let mut saved = OpenDocument::new(doc_id, path, data, page_count).parsed(objects);
+    if !options.user_password.is_empty() {
+        saved.password = Some(options.user_password);
+    }
+    Ok(SavedDocument {
+        document: replace(saved, &store, &tiles, &scheduler)?,
+        linearized: false,
+    })
+}
+
+// Whether `password` opens or owns the document. Edits are kept unencrypted,
+// so an edited document is checked against the password it was opened with.
+fn check_password(doc: &OpenDocument, password: &str) -> Result<bool, String> {
+    let mut parsed = lopdf::Document::load_mem(&doc.data)
+        .map_err(|e| format!("Failed to parse {}: {}", doc.path.display(), e))?;
+    if parsed.is_encrypted() {
+        return Ok(parsed.decrypt(password).is_ok());
+    }
+    match &doc.password {
+        Some(known) => Ok(known == password),
+        None => Err(format!("{} isn't password protected", doc.path.display())),
+    }
+}
+
+/// Writes an unencrypted copy of a protected document, with its edits, to
+/// `output_path` once the user confirms. `password` has to be one that
+/// opens the document, and restricted permissions need the owner password
+/// given first. Returns `None` if the user cancelled. The open document is
+/// left as it is.
+#[tauri::command]
+pub async fn decrypt_and_save(
+    app: AppHandle,
+    doc_id: DocId,
+    password: String,
+    output_path: String,
+    store: State<'_, DocumentStore>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<Option<WrittenDocument>, String> {
+    scope::check_write(&app, Path::new(&output_path))?;
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::ChangeSecurity).await?;
+    {
+        let doc = doc.clone();
+        if !blocking(move || check_password(&doc, &password)).await? {
+            return Err("The password is incorrect".to_string());
+        }
+    }
+
+    let file_name = doc.path.file_name().map_or_else(
+        || doc.path.display().to_string(),
+        |n| n.to_string_lossy().into_owned(),
+    );
+    // Dialogs block until closed, so keep them off the async runtime
+    blocking(move || {
+        let confirmed = app
+            .dialog()
+            .message(format!(
+                "The copy of \"{}\" will not be protected. Anyone who gets the file can \
+                 open, print, copy and change it without a password.",
+                file_name
+            ))
+            .title("Remove Password")
+            .kind(MessageDialogKind::Warning)
+            .buttons(MessageDialogButtons::OkCancelCustom(
+                "Remove Password".to_string(),
+                "Cancel".to_string(),
+            ))
+            .blocking_show();
+        if !confirmed {
+            return Ok(None);
+        }
+
+        let mut objects = doc.with_objects(|objects| Ok(objects.clone()))?;
+        // Edits leave pages and page tree nodes behind that nothing refers to
+        objects.prune_objects();
+        write_document(&mut objects, Path::new(&output_path))?;
+        eprintln!(
+            "Saved document {} without its password to {}",
+            doc_id, output_path
+        );
+        Ok(Some(WrittenDocument {
+            path: output_path,
+            page_count: objects.get_pages().len() as i32,
+        }))
+    })
+    .await
+}
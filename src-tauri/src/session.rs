@@ -0,0 +1,355 @@
+// Restoring the windows and documents of the last session.
+//
+// The frontend reports each window's tabs with `update_session` whenever they
+// change. Every change is appended to a journal at `<app data>/session.journal` as a line
+// of JSON holding the whole state of that window, flushed to disk before the
+// command returns, so the state survives a crash up to the last change; a
+// line cut short by one is skipped. A clean exit adds a last line saying so.
+// The journal is rewritten from the current state once it grows long.
+//
+// On the next launch the journal tells the previous session, and whether it
+// ended in a crash. The tabs are restored as the user set with
+// `set_session_settings`, a section of the preferences: at once, never,
+// or, by default, once the user accepts the offer the frontend makes from
+// `get_previous_session`, with `restore_session` or `discard_session`. Until
+// then the previous session is kept in `<app data>/session-previous.json`,
+// so another crash doesn't lose it. Documents open where they were left (see
+// `reading.rs`), and windows come back where they were (see
+// `window_state.rs`): the main window's tabs open in it, and those of the
+// other windows in new windows with the same labels (see
+// `window_manager.rs`). A window the user closes while others stay open is
+// forgotten.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, RunEvent, State, Window};
+
+use crate::launch::{self, Location};
+use crate::preferences;
+use crate::window_manager::{self, MAIN_WINDOW};
+
+const JOURNAL_FILE: &str = "session.journal";
+const PREVIOUS_FILE: &str = "session-previous.json";
+// Lines the journal grows to before it's rewritten
+const MAX_JOURNAL_LINES: usize = 500;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum RestoreMode {
+    /// The frontend offers to restore the previous session
+    #[default]
+    Ask,
+    Always,
+    Never,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SessionSettings {
+    pub restore: RestoreMode,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionTab {
+    pub path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowSession {
+    pub tabs: Vec<SessionTab>,
+    /// The index of the tab in front
+    pub active: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviousSession {
+    /// By window label
+    pub windows: BTreeMap<String, WindowSession>,
+    /// The session ended without a clean exit
+    pub crashed: bool,
+}
+
+// A line of the journal
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+enum Record {
+    #[serde(rename_all = "camelCase")]
+    Window {
+        label: String,
+        #[serde(flatten)]
+        window: WindowSession,
+    },
+    Exit,
+}
+
+#[derive(Default)]
+struct Journal {
+    file: Option<File>,
+    lines: usize,
+    // The state written so far, to rewrite the journal from
+    windows: BTreeMap<String, WindowSession>,
+}
+
+/// The journal of this session and what's left of the previous one, managed
+/// as Tauri state.
+#[derive(Default)]
+pub struct Session {
+    journal: Mutex<Journal>,
+    previous: Mutex<Option<PreviousSession>>,
+}
+
+fn data_file(app: &AppHandle, name: &str) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join(name))
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))
+}
+
+fn write_json(file: &Path, value: &impl Serialize) -> Result<(), String> {
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let json = serde_json::to_vec_pretty(value).map_err(|e| e.to_string())?;
+    std::fs::write(file, json).map_err(|e| format!("Failed to write {}: {}", file.display(), e))
+}
+
+// The session a journal records. Lines that don't parse, as the last one
+// may not after a crash, are skipped
+fn read_journal(file: &Path) -> Option<PreviousSession> {
+    let reader = BufReader::new(File::open(file).ok()?);
+    let mut session = PreviousSession {
+        crashed: true,
+        ..Default::default()
+    };
+    for line in reader.lines().map_while(Result::ok) {
+        match serde_json::from_str(&line) {
+            Ok(Record::Window { label, window }) => {
+                session.crashed = true;
+                session.windows.insert(label, window);
+            }
+            Ok(Record::Exit) => session.crashed = false,
+            Err(_) => {}
+        }
+    }
+    Some(session)
+}
+
+impl Journal {
+    // Starts the journal over with the current state
+    fn rewrite(&mut self, file: &Path) -> Result<(), String> {
+        let temporary = file.with_extension("journal.tmp");
+        let mut lines = String::new();
+        for (label, window) in &self.windows {
+            let record = Record::Window {
+                label: label.clone(),
+                window: window.clone(),
+            };
+            lines += &serde_json::to_string(&record).map_err(|e| e.to_string())?;
+            lines.push('\n');
+        }
+        std::fs::write(&temporary, lines)
+            .and_then(|_| std::fs::rename(&temporary, file))
+            .map_err(|e| format!("Failed to write {}: {}", file.display(), e))?;
+        self.file = Some(
+            OpenOptions::new()
+                .append(true)
+                .open(file)
+                .map_err(|e| format!("Failed to open {}: {}", file.display(), e))?,
+        );
+        self.lines = self.windows.len();
+        Ok(())
+    }
+
+    fn append(&mut self, file: &Path, record: &Record) -> Result<(), String> {
+        if self.file.is_none() || self.lines >= MAX_JOURNAL_LINES {
+            return self.rewrite(file);
+        }
+        let mut line = serde_json::to_string(record).map_err(|e| e.to_string())?;
+        line.push('\n');
+        let journal = self.file.as_mut().unwrap();
+        journal
+            .write_all(line.as_bytes())
+            .and_then(|_| journal.sync_data())
+            .map_err(|e| format!("Failed to write {}: {}", file.display(), e))?;
+        self.lines += 1;
+        Ok(())
+    }
+}
+
+// Records a window's new state
+fn record(app: &AppHandle, label: &str, change: impl FnOnce(&mut WindowSession)) {
+    let result = data_file(app, JOURNAL_FILE).and_then(|file| {
+        let state = app.state::<Session>();
+        let mut journal = state.journal.lock().unwrap();
+        let window = journal.windows.entry(label.to_string()).or_default();
+        change(window);
+        let record = Record::Window {
+            label: label.to_string(),
+            window: window.clone(),
+        };
+        journal.append(&file, &record)
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to record the session: {}", e);
+    }
+}
+
+// Opens the tabs of the previous session, each window's in a window with its
+// label. Those of windows that aren't document windows go to the main window
+fn open_tabs(app: &AppHandle, session: &PreviousSession) {
+    let mut main = Vec::new();
+    for (label, window) in &session.windows {
+        let paths: Vec<PathBuf> = window
+            .tabs
+            .iter()
+            .map(|tab| tab.path.clone())
+            .filter(|path| {
+                let exists = path.is_file();
+                if !exists {
+                    eprintln!("Not restoring {}, which is gone", path.display());
+                }
+                exists
+            })
+            .collect();
+        if label == MAIN_WINDOW || !window_manager::is_document_window(label) {
+            main.extend(paths);
+        } else if !paths.is_empty() {
+            let locations = paths.into_iter().map(Location::file).collect();
+            if let Err(e) = window_manager::open_window(app, Some(label), locations) {
+                eprintln!("{}", e);
+            }
+        }
+    }
+    launch::open_files(app, main);
+}
+
+fn has_tabs(session: &PreviousSession) -> bool {
+    session
+        .windows
+        .values()
+        .any(|window| !window.tabs.is_empty())
+}
+
+// Forgets the previous session once it's restored or turned down
+fn settle(app: &AppHandle) -> Option<PreviousSession> {
+    if let Ok(file) = data_file(app, PREVIOUS_FILE) {
+        let _ = std::fs::remove_file(file);
+    }
+    app.state::<Session>().previous.lock().unwrap().take()
+}
+
+/// Reads the previous session, puts its windows back and restores or offers
+/// its tabs, then starts the journal of this one.
+pub fn setup(app: &AppHandle) {
+    let (Ok(journal), Ok(previous_file)) =
+        (data_file(app, JOURNAL_FILE), data_file(app, PREVIOUS_FILE))
+    else {
+        return;
+    };
+    // When the last session had no documents open, one not yet settled
+    // before it is offered again
+    let previous = read_journal(&journal)
+        .filter(has_tabs)
+        .or_else(|| {
+            std::fs::read(&previous_file)
+                .ok()
+                .and_then(|json| serde_json::from_slice(&json).ok())
+        })
+        .filter(|session: &PreviousSession| !session.windows.is_empty());
+
+    if let Some(session) = previous {
+        match preferences::current(app).session.restore {
+            RestoreMode::Always if has_tabs(&session) => open_tabs(app, &session),
+            RestoreMode::Ask if has_tabs(&session) => {
+                eprintln!(
+                    "Offering to restore the previous session{}",
+                    if session.crashed {
+                        ", which crashed"
+                    } else {
+                        ""
+                    }
+                );
+                if let Err(e) = write_json(&previous_file, &session) {
+                    eprintln!("{}", e);
+                }
+                *app.state::<Session>().previous.lock().unwrap() = Some(session);
+            }
+            _ => {}
+        }
+    }
+
+    let state = app.state::<Session>();
+    let mut journal_state = state.journal.lock().unwrap();
+    if let Err(e) = journal_state.rewrite(&journal) {
+        eprintln!("Failed to start the session journal: {}", e);
+    }
+}
+
+/// Records a clean exit. The app's run event handler.
+pub fn handle_run_event(app: &AppHandle, event: &RunEvent) {
+    if !matches!(event, RunEvent::Exit) {
+        return;
+    }
+    let Ok(file) = data_file(app, JOURNAL_FILE) else {
+        return;
+    };
+    let state = app.state::<Session>();
+    let mut journal = state.journal.lock().unwrap();
+    if let Err(e) = journal.append(&file, &Record::Exit) {
+        eprintln!("Failed to record the exit: {}", e);
+    }
+}
+
+/// Forgets a window the user closed, so it isn't restored.
+pub fn forget_window(app: &AppHandle, label: &str) {
+    record(app, label, |session| *session = WindowSession::default());
+}
+
+/// Records the tabs of the calling window, in order.
+#[tauri::command]
+pub fn update_session(window: Window, tabs: Vec<SessionTab>, active: Option<usize>) {
+    record(window.app_handle(), window.label(), |session| {
+        session.active = active.filter(|index| *index < tabs.len());
+        session.tabs = tabs;
+    });
+}
+
+/// The previous session, while the user hasn't restored or discarded it.
+#[tauri::command]
+pub fn get_previous_session(session: State<'_, Session>) -> Option<PreviousSession> {
+    session.previous.lock().unwrap().clone()
+}
+
+/// Opens the tabs of the previous session.
+#[tauri::command]
+pub async fn restore_session(app: AppHandle) -> Result<(), String> {
+    let session = settle(&app).ok_or("There's no session to restore")?;
+    open_tabs(&app, &session);
+    Ok(())
+}
+
+/// Forgets the previous session without restoring it.
+#[tauri::command]
+pub fn discard_session(app: AppHandle) {
+    settle(&app);
+}
+
+#[tauri::command]
+pub async fn get_session_settings(app: AppHandle) -> Result<SessionSettings, String> {
+    Ok(preferences::current(&app).session)
+}
+
+/// Saves the session settings, which apply from the next launch on.
+#[tauri::command]
+pub async fn set_session_settings(app: AppHandle, settings: SessionSettings) -> Result<(), String> {
+    preferences::update(&app, |preferences| preferences.session = settings).map(|_| ())
+}
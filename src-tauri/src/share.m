@@ -0,0 +1,21 @@
+#import <Cocoa/Cocoa.h>
+
+// Showing the share sheet for a file over a window.
+
+// The picker shown last, kept alive while it's on screen
+static NSSharingServicePicker *shown_picker;
+
+void share_file(void *ns_window, const char *path) {
+    NSWindow *window = (__bridge NSWindow *)ns_window;
+    NSView *view = window.contentView;
+    if (view == nil) {
+        return;
+    }
+    NSURL *url = [NSURL fileURLWithPath:[NSString stringWithUTF8String:path]];
+    shown_picker = [[NSSharingServicePicker alloc] initWithItems:@[ url ]];
+    // Anchored to the middle of the window's top edge
+    NSRect bounds = view.bounds;
+    CGFloat top = view.isFlipped ? NSMinY(bounds) : NSMaxY(bounds) - 1;
+    NSRect anchor = NSMakeRect(NSMidX(bounds) - 1, top, 2, 1);
+    [shown_picker showRelativeToRect:anchor ofView:view preferredEdge:NSRectEdgeMinY];
+}
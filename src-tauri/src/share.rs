@@ -0,0 +1,275 @@
+// Sharing a document through the system's share sheet.
+//
+// On macOS that's NSSharingServicePicker (see `share.m`), which offers
+// AirDrop, Mail, Messages and the sharing extensions installed, shown over
+// the window. On Windows it's the Share window Explorer opens for files,
+// reached through the file's shell context menu, which offers Nearby Sharing,
+// mail and the apps that take files. Linux has no share sheet, so the file is
+// attached to a new message in the user's mail client with `xdg-email`. The
+// file is shared as saved on disk; unsaved edits aren't in it.
+
+use std::path::PathBuf;
+
+use tauri::{State, Window};
+
+use crate::pdf::{DocId, DocumentStore};
+
+/// Shows the share sheet for an open document's file.
+#[tauri::command]
+pub async fn share_document(
+    window: Window,
+    doc_id: DocId,
+    store: State<'_, DocumentStore>,
+) -> Result<(), String> {
+    let path: PathBuf = store.get(doc_id)?.path.clone();
+    if !path.is_file() {
+        return Err(format!("{} no longer exists", path.display()));
+    }
+    eprintln!("Sharing {}", path.display());
+    platform::share(&window, path)
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::ffi::{c_char, c_void, CString};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::PathBuf;
+
+    use tauri::Window;
+
+    extern "C" {
+        fn share_file(window: *mut c_void, path: *const c_char);
+    }
+
+    pub fn share(window: &Window, path: PathBuf) -> Result<(), String> {
+        let ns_window = window.ns_window().map_err(|e| e.to_string())? as usize;
+        let path = CString::new(path.as_os_str().as_bytes()).map_err(|e| e.to_string())?;
+        // AppKit's pickers are shown from the main thread
+        window
+            .run_on_main_thread(move || unsafe {
+                share_file(ns_window as *mut c_void, path.as_ptr())
+            })
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::ffi::{c_char, c_void};
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::PathBuf;
+    use std::ptr;
+    use std::sync::mpsc;
+
+    use tauri::Window;
+
+    type HResult = i32;
+
+    #[repr(C)]
+    struct Guid {
+        data1: u32,
+        data2: u16,
+        data3: u16,
+        data4: [u8; 8],
+    }
+
+    const IID_SHELL_ITEM: Guid = Guid {
+        data1: 0x43826d1e,
+        data2: 0xe718,
+        data3: 0x42ee,
+        data4: [0xbc, 0x55, 0xa1, 0xe2, 0x61, 0xc3, 0x7b, 0xfe],
+    };
+    const IID_CONTEXT_MENU: Guid = Guid {
+        data1: 0x000214e4,
+        data2: 0x0000,
+        data3: 0x0000,
+        data4: [0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+    };
+    const BHID_SF_UI_OBJECT: Guid = Guid {
+        data1: 0x3981e225,
+        data2: 0xf559,
+        data3: 0x11d3,
+        data4: [0x8e, 0x3a, 0x00, 0xc0, 0x4f, 0x68, 0x37, 0xd5],
+    };
+    const COINIT_APARTMENTTHREADED: u32 = 0x2;
+    const CMF_NORMAL: u32 = 0;
+    const SW_SHOWNORMAL: i32 = 1;
+    // Explorer's "Share" command
+    const SHARE_VERB: &[u8] = b"Windows.ModernShare\0";
+
+    #[repr(C)]
+    struct UnknownVtbl {
+        query_interface: usize,
+        add_ref: usize,
+        release: unsafe extern "system" fn(*mut c_void) -> u32,
+    }
+
+    #[repr(C)]
+    struct ShellItemVtbl {
+        base: UnknownVtbl,
+        bind_to_handler: unsafe extern "system" fn(
+            *mut c_void,
+            *mut c_void,
+            *const Guid,
+            *const Guid,
+            *mut *mut c_void,
+        ) -> HResult,
+    }
+
+    #[repr(C)]
+    struct ContextMenuVtbl {
+        base: UnknownVtbl,
+        query_context_menu:
+            unsafe extern "system" fn(*mut c_void, *mut c_void, u32, u32, u32, u32) -> HResult,
+        invoke_command: unsafe extern "system" fn(*mut c_void, *const InvokeCommandInfo) -> HResult,
+    }
+
+    #[repr(C)]
+    struct InvokeCommandInfo {
+        size: u32,
+        mask: u32,
+        window: isize,
+        verb: *const c_char,
+        parameters: *const c_char,
+        directory: *const c_char,
+        show: i32,
+        hotkey: u32,
+        icon: *mut c_void,
+    }
+
+    #[link(name = "ole32")]
+    extern "system" {
+        fn CoInitializeEx(reserved: *mut c_void, flags: u32) -> HResult;
+        fn CoUninitialize();
+    }
+
+    #[link(name = "shell32")]
+    extern "system" {
+        fn SHCreateItemFromParsingName(
+            path: *const u16,
+            context: *mut c_void,
+            iid: *const Guid,
+            item: *mut *mut c_void,
+        ) -> HResult;
+    }
+
+    #[link(name = "user32")]
+    extern "system" {
+        fn CreatePopupMenu() -> *mut c_void;
+        fn DestroyMenu(menu: *mut c_void) -> i32;
+    }
+
+    // An interface pointer, released when dropped
+    struct Com(*mut c_void);
+
+    impl Com {
+        // SAFETY: `T` must be the vtable of the interface the pointer is for
+        unsafe fn vtable<T>(&self) -> &T {
+            &**(self.0 as *const *const T)
+        }
+    }
+
+    impl Drop for Com {
+        fn drop(&mut self) {
+            if !self.0.is_null() {
+                unsafe { (self.vtable::<UnknownVtbl>().release)(self.0) };
+            }
+        }
+    }
+
+    fn check(hr: HResult, what: &str) -> Result<(), String> {
+        if hr < 0 {
+            Err(format!("{} failed (error {:#010x})", what, hr as u32))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn invoke_share(window: isize, path: &[u16]) -> Result<(), String> {
+        let mut item = ptr::null_mut();
+        let hr = unsafe {
+            SHCreateItemFromParsingName(path.as_ptr(), ptr::null_mut(), &IID_SHELL_ITEM, &mut item)
+        };
+        check(hr, "Looking up the file")?;
+        let item = Com(item);
+        let mut menu = ptr::null_mut();
+        let hr = unsafe {
+            (item.vtable::<ShellItemVtbl>().bind_to_handler)(
+                item.0,
+                ptr::null_mut(),
+                &BHID_SF_UI_OBJECT,
+                &IID_CONTEXT_MENU,
+                &mut menu,
+            )
+        };
+        check(hr, "Getting the file's commands")?;
+        let menu = Com(menu);
+        let methods = unsafe { menu.vtable::<ContextMenuVtbl>() };
+        // The shell only knows its commands once they're added to a menu
+        let popup = unsafe { CreatePopupMenu() };
+        let hr = unsafe { (methods.query_context_menu)(menu.0, popup, 0, 1, 0x7fff, CMF_NORMAL) };
+        let result = check(hr, "Getting the file's commands").and_then(|_| {
+            let info = InvokeCommandInfo {
+                size: std::mem::size_of::<InvokeCommandInfo>() as u32,
+                mask: 0,
+                window,
+                verb: SHARE_VERB.as_ptr() as *const c_char,
+                parameters: ptr::null(),
+                directory: ptr::null(),
+                show: SW_SHOWNORMAL,
+                hotkey: 0,
+                icon: ptr::null_mut(),
+            };
+            check(
+                unsafe { (methods.invoke_command)(menu.0, &info) },
+                "Opening the Share window",
+            )
+        });
+        unsafe { DestroyMenu(popup) };
+        result
+    }
+
+    pub fn share(window: &Window, path: PathBuf) -> Result<(), String> {
+        let hwnd = window.hwnd().map_err(|e| e.to_string())?.0 as isize;
+        let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let (sender, receiver) = mpsc::channel();
+        // The Share window belongs to ours, on its thread
+        window
+            .run_on_main_thread(move || {
+                let initialized =
+                    unsafe { CoInitializeEx(ptr::null_mut(), COINIT_APARTMENTTHREADED) };
+                let result = check(initialized, "Setting up COM").and_then(|_| {
+                    let shared = invoke_share(hwnd, &path);
+                    unsafe { CoUninitialize() };
+                    shared
+                });
+                let _ = sender.send(result);
+            })
+            .map_err(|e| e.to_string())?;
+        receiver
+            .recv()
+            .map_err(|_| "The Share window never opened".to_string())?
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    use tauri::Window;
+
+    pub fn share(_window: &Window, path: PathBuf) -> Result<(), String> {
+        let subject = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        // Mail clients may take a while to come up, so it's not waited for
+        Command::new("xdg-email")
+            .args(["--subject", &subject, "--attach"])
+            .arg(&path)
+            .spawn()
+            .map(|_| ())
+            .map_err(|e| format!("Failed to run xdg-email: {}", e))
+    }
+}
@@ -0,0 +1,104 @@
+// The global shortcut that summons Nanodoc from any app.
+//
+// Pressing it brings the window to the front, showing it if it was hidden
+// to the tray (see `tray.rs`); with no document open, the most recently
+// opened one is opened again. The shortcut is set with
+// `set_shortcut_settings`, written the way the global shortcut plugin parses
+// them ("CommandOrControl+Alt+N"), and turned off with none. It's a section of
+// the preferences (see `preferences.rs`) and registered with the system at
+// once. Another app may hold the same keys already, in which case the old one
+// is kept.
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutEvent, ShortcutState};
+
+use crate::pdf::DocumentStore;
+use crate::{launch, preferences, recent_files};
+
+const DEFAULT_SHORTCUT: &str = "CommandOrControl+Alt+N";
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ShortcutSettings {
+    /// The keys that summon Nanodoc, none to turn the shortcut off
+    pub shortcut: Option<String>,
+}
+
+impl Default for ShortcutSettings {
+    fn default() -> Self {
+        ShortcutSettings {
+            shortcut: Some(DEFAULT_SHORTCUT.into()),
+        }
+    }
+}
+
+fn parse(keys: &str) -> Result<Shortcut, String> {
+    keys.parse()
+        .map_err(|e| format!("Invalid shortcut {}: {}", keys, e))
+}
+
+impl ShortcutSettings {
+    /// Checks the shortcut is written the way the plugin parses it.
+    pub fn validate(&self) -> Result<(), String> {
+        self.shortcut.as_deref().map(parse).transpose().map(|_| ())
+    }
+}
+
+/// Replaces the registered shortcut, if any, with the one in `settings`.
+pub fn register(app: &AppHandle, settings: &ShortcutSettings) -> Result<(), String> {
+    let shortcut = settings.shortcut.as_deref().map(parse).transpose()?;
+    let manager = app.global_shortcut();
+    manager.unregister_all().map_err(|e| e.to_string())?;
+    match shortcut {
+        Some(shortcut) => manager.register(shortcut).map_err(|e| {
+            format!(
+                "Failed to register {}, which may be taken by another app: {}",
+                settings.shortcut.as_deref().unwrap_or_default(),
+                e
+            )
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Registers the saved shortcut.
+pub fn setup(app: &AppHandle) {
+    if let Err(e) = register(app, &preferences::current(app).shortcut) {
+        eprintln!("{}", e);
+    }
+}
+
+/// Summons Nanodoc when the shortcut is pressed. The global shortcut plugin's
+/// handler.
+pub fn handle(app: &AppHandle, _shortcut: &Shortcut, event: ShortcutEvent) {
+    if event.state() != ShortcutState::Pressed {
+        return;
+    }
+    if app.state::<DocumentStore>().all().is_empty() {
+        if let Some(path) = recent_files::last_opened(app) {
+            launch::open_files(app, vec![path]);
+        }
+    }
+    launch::show_window(app);
+}
+
+#[tauri::command]
+pub async fn get_shortcut_settings(app: AppHandle) -> Result<ShortcutSettings, String> {
+    Ok(preferences::current(&app).shortcut)
+}
+
+/// Registers a new shortcut and saves it, keeping the old one if the system
+/// refuses it.
+#[tauri::command]
+pub async fn set_shortcut_settings(
+    app: AppHandle,
+    settings: ShortcutSettings,
+) -> Result<(), String> {
+    let saved = preferences::current(&app).shortcut;
+    if let Err(e) = register(&app, &settings) {
+        let _ = register(&app, &saved);
+        return Err(e);
+    }
+    preferences::update(&app, |preferences| preferences.shortcut = settings).map(|_| ())
+}
@@ -0,0 +1,234 @@
+// Annotations kept beside a document instead of in it.
+//
+// Some files shouldn't be changed: they sit on a read-only share, are signed,
+// or belong to someone else. Their annotations can go in a SQLite database at
+// `<app data>/annotations.sqlite3` instead, keyed by the fingerprint of the
+// file, so they come back whenever the same file is opened again, wherever
+// it's opened from. Baking writes a copy of the document with them drawn in
+// as regular annotations (see `annotations.rs`); the file itself and the
+// stored annotations stay as they are.
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use tauri::{AppHandle, Manager, State};
+
+use crate::annotations::{write_annotation, NewAnnotation};
+use crate::compose::{write_document, WrittenDocument};
+use crate::metadata::{format_pdf_date, parse_pdf_date};
+use crate::pdf::{blocking, DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scope;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS annotations (
+        id INTEGER PRIMARY KEY,
+        fingerprint TEXT NOT NULL,
+        page INTEGER NOT NULL,
+        annotation TEXT NOT NULL,
+        created TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS annotations_by_file ON annotations (fingerprint);
+";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SidecarAnnotation {
+    pub id: i64,
+    pub page: i32,
+    pub annotation: NewAnnotation,
+    /// ISO 8601
+    pub created: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BakedDocument {
+    pub document: WrittenDocument,
+    pub annotations: usize,
+}
+
+fn database_file(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("annotations.sqlite3"))
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))
+}
+
+fn open(file: &Path) -> Result<Connection, String> {
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let db =
+        Connection::open(file).map_err(|e| format!("Failed to open {}: {}", file.display(), e))?;
+    db.execute_batch(SCHEMA)
+        .map_err(|e| format!("Failed to set up {}: {}", file.display(), e))?;
+    Ok(db)
+}
+
+fn stored(db: &Connection, fingerprint: &str) -> Result<Vec<SidecarAnnotation>, String> {
+    let mut query = db
+        .prepare(
+            "SELECT id, page, annotation, created FROM annotations
+             WHERE fingerprint = ?1 ORDER BY page, id",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = query
+        .query_map(params![fingerprint], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i32>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut annotations = Vec::new();
+    for row in rows {
+        let (id, page, annotation, created) = row.map_err(|e| e.to_string())?;
+        // Rows written by a later version may not read; skip those rather
+        // than losing sight of the rest
+        match serde_json::from_str(&annotation) {
+            Ok(annotation) => annotations.push(SidecarAnnotation {
+                id,
+                page,
+                annotation,
+                created: parse_pdf_date(&created),
+            }),
+            Err(e) => eprintln!("Skipped stored annotation {}: {}", id, e),
+        }
+    }
+    Ok(annotations)
+}
+
+/// Lists the annotations stored beside the document's file.
+#[tauri::command]
+pub async fn get_sidecar_annotations(
+    app: AppHandle,
+    doc_id: DocId,
+    store: State<'_, DocumentStore>,
+) -> Result<Vec<SidecarAnnotation>, String> {
+    let doc = store.get(doc_id)?;
+    let file = database_file(&app)?;
+    blocking(move || stored(&open(&file)?, doc.fingerprint())).await
+}
+
+/// Stores an annotation for `page` beside the document's file, leaving the
+/// file as it is.
+#[tauri::command]
+pub async fn add_sidecar_annotation(
+    app: AppHandle,
+    doc_id: DocId,
+    page: i32,
+    annotation: NewAnnotation,
+    store: State<'_, DocumentStore>,
+) -> Result<SidecarAnnotation, String> {
+    let doc = store.get(doc_id)?;
+    doc.check_page(page)?;
+    let file = database_file(&app)?;
+    blocking(move || {
+        let db = open(&file)?;
+        let json = serde_json::to_string(&annotation).map_err(|e| e.to_string())?;
+        let created = format_pdf_date(SystemTime::now());
+        db.execute(
+            "INSERT INTO annotations (fingerprint, page, annotation, created)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![doc.fingerprint(), page, json, created],
+        )
+        .map_err(|e| format!("Failed to store the annotation: {}", e))?;
+        let id = db.last_insert_rowid();
+        eprintln!(
+            "Stored annotation {} for page {} of document {} beside the file",
+            id, page, doc_id
+        );
+        Ok(SidecarAnnotation {
+            id,
+            page,
+            annotation,
+            created: parse_pdf_date(&created),
+        })
+    })
+    .await
+}
+
+/// Removes an annotation stored beside the document's file.
+#[tauri::command]
+pub async fn delete_sidecar_annotation(
+    app: AppHandle,
+    doc_id: DocId,
+    id: i64,
+    store: State<'_, DocumentStore>,
+) -> Result<(), String> {
+    let doc = store.get(doc_id)?;
+    let file = database_file(&app)?;
+    blocking(move || {
+        let deleted = open(&file)?
+            .execute(
+                "DELETE FROM annotations WHERE id = ?1 AND fingerprint = ?2",
+                params![id, doc.fingerprint()],
+            )
+            .map_err(|e| format!("Failed to delete the annotation: {}", e))?;
+        if deleted == 0 {
+            return Err(format!(
+                "Document {} has no stored annotation {}",
+                doc_id, id
+            ));
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Writes a copy of the document to `output_path` with the annotations
+/// stored beside it drawn in.
+#[tauri::command]
+pub async fn bake_sidecar_annotations(
+    app: AppHandle,
+    doc_id: DocId,
+    output_path: String,
+    store: State<'_, DocumentStore>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<BakedDocument, String> {
+    scope::check_write(&app, Path::new(&output_path))?;
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Annotate).await?;
+    let file = database_file(&app)?;
+    blocking(move || {
+        let annotations = stored(&open(&file)?, doc.fingerprint())?;
+        if annotations.is_empty() {
+            return Err(format!(
+                "{} has no annotations stored beside it",
+                doc.path.display()
+            ));
+        }
+        let count = annotations.len();
+        let mut baked = doc.with_objects(|objects| Ok(objects.clone()))?;
+        let pages = baked.get_pages();
+        for stored in annotations {
+            let Some(&page_id) = pages.get(&(stored.page as u32 + 1)) else {
+                return Err(format!(
+                    "Stored annotation {} is on page {}, which the document doesn't have",
+                    stored.id, stored.page
+                ));
+            };
+            write_annotation(&mut baked, page_id, None, &stored.annotation.into_spec())?;
+        }
+        write_document(&mut baked, Path::new(&output_path))?;
+        eprintln!(
+            "Baked {} stored annotations of document {} into {}",
+            count, doc_id, output_path
+        );
+        Ok(BakedDocument {
+            document: WrittenDocument {
+                path: output_path,
+                page_count: pages.len() as i32,
+            },
+            annotations: count,
+        })
+    })
+    .await
+}
@@ -0,0 +1,590 @@
+// Checking the digital signatures of a document.
+//
+// A signature field holds a CMS signature over the bytes of the file listed
+// in its /ByteRange, which is everything but the signature itself. Checking
+// it takes three steps: the signed bytes hash to what was signed, the
+// signature matches the signer's certificate, and that certificate chains up
+// to a root the operating system trusts, as of the time of signing. A
+// signature over less than the whole file means that more was written after
+// signing, as happens when a second signature is added.
+//
+// A timestamp from a trusted authority fixes the time of signing, so the
+// signer's certificate is checked as of then, and the signature stays valid
+// after the certificate expires. Without one, the time the signer claims is
+// all there is.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use rustls_pki_types::{CertificateDer, TrustAnchor, UnixTime};
+use serde::Serialize;
+use tauri::State;
+use webpki::{
+    EndEntityCert, ExtendedKeyUsageValidator, KeyPurposeIdIter, KeyUsage,
+    RequiredEkuNotFoundContext,
+};
+
+use crate::cms::{iso_time, unix_time, Certificate, SignedData};
+use crate::metadata::parse_pdf_date;
+use crate::objects::{self, MAX_DEPTH};
+use crate::pdf::{blocking, DocId, DocumentStore};
+
+// Extended key usages of certificates meant for signing documents, besides
+// having none at all: document signing, email protection, Microsoft and
+// Adobe document signing, and any purpose
+const SIGNING_USAGES: [&[usize]; 5] = [
+    &[1, 3, 6, 1, 5, 5, 7, 3, 36],
+    &[1, 3, 6, 1, 5, 5, 7, 3, 4],
+    &[1, 3, 6, 1, 4, 1, 311, 10, 3, 12],
+    &[1, 2, 840, 113583, 1, 1, 5],
+    &[2, 5, 29, 37, 0],
+];
+// id-kp-documentSigning and id-kp-timeStamping, as encoded
+const ID_DOCUMENT_SIGNING: &[u8] = &[0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x24];
+const ID_TIME_STAMPING: &[u8] = &[0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x08];
+const TIME_STAMPING_USAGE: &[usize] = &[1, 3, 6, 1, 5, 5, 7, 3, 8];
+
+// Formats of signature values that can be checked
+const SUB_FILTERS: [&str; 3] = [
+    "adbe.pkcs7.detached",
+    "adbe.pkcs7.sha1",
+    "ETSI.CAdES.detached",
+];
+
+static TRUST_ANCHORS: OnceLock<Vec<TrustAnchor<'static>>> = OnceLock::new();
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum SignatureVerdict {
+    /// Intact, covering the whole file, and by a trusted signer
+    Valid,
+    /// The signed bytes changed, or more was written after signing
+    Modified,
+    /// Intact, but the signer's certificate isn't trusted
+    Untrusted,
+    /// The signature can't be checked
+    Invalid,
+    /// An empty signature field
+    Unsigned,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CertificateInfo {
+    pub subject: String,
+    pub issuer: String,
+    /// ISO 8601
+    pub not_before: String,
+    pub not_after: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureInfo {
+    /// Fully qualified field name
+    pub field: String,
+    /// Page of the field's widget, if it has one
+    pub page: Option<i32>,
+    pub verdict: SignatureVerdict,
+    /// True if the signed bytes are unchanged and the signature matches them
+    pub intact: bool,
+    /// True if the signature covers the whole file
+    pub covers_document: bool,
+    /// True if the signer's certificate chains to a trusted root
+    pub trusted: bool,
+    /// Why the signature isn't valid, for display
+    pub problem: Option<String>,
+    pub signer: Option<String>,
+    /// ISO 8601, as claimed by the signer
+    pub signing_time: Option<String>,
+    /// ISO 8601, as vouched for by a trusted timestamp authority
+    pub timestamp: Option<String>,
+    pub timestamp_authority: Option<String>,
+    pub reason: Option<String>,
+    pub location: Option<String>,
+    pub contact_info: Option<String>,
+    pub sub_filter: Option<String>,
+    /// Certificates included in the signature, the signer's first
+    pub certificates: Vec<CertificateInfo>,
+}
+
+// What a certificate is checked for. Signers may have no extended key usages
+// at all, or one for signing; timestamp authorities need theirs.
+#[derive(Clone, Copy)]
+enum Purpose {
+    DocumentSigning,
+    TimeStamping,
+}
+
+impl ExtendedKeyUsageValidator for Purpose {
+    fn validate(&self, usages: KeyPurposeIdIter<'_, '_>) -> Result<(), webpki::Error> {
+        let (allowed, required): (&[&[usize]], _) = match self {
+            Purpose::DocumentSigning => (&SIGNING_USAGES, ID_DOCUMENT_SIGNING),
+            Purpose::TimeStamping => (&[TIME_STAMPING_USAGE], ID_TIME_STAMPING),
+        };
+        let mut present = Vec::new();
+        for usage in usages {
+            let usage = usage?.to_decoded_oid();
+            if allowed.contains(&usage.as_slice()) {
+                return Ok(());
+            }
+            present.push(usage);
+        }
+        if present.is_empty() && matches!(self, Purpose::DocumentSigning) {
+            return Ok(());
+        }
+        Err(webpki::Error::RequiredEkuNotFoundContext(
+            RequiredEkuNotFoundContext {
+                required: KeyUsage::required(required),
+                present,
+            },
+        ))
+    }
+}
+
+// The root certificates of the operating system, loaded once
+fn trust_anchors() -> &'static [TrustAnchor<'static>] {
+    TRUST_ANCHORS.get_or_init(|| {
+        let found = rustls_native_certs::load_native_certs();
+        for e in &found.errors {
+            eprintln!("Failed to load trusted certificates: {}", e);
+        }
+        let anchors: Vec<TrustAnchor<'static>> = found
+            .certs
+            .iter()
+            .filter_map(|cert| webpki::anchor_from_trusted_cert(cert).ok())
+            .map(|anchor| anchor.to_owned())
+            .collect();
+        eprintln!("Loaded {} trusted root certificates", anchors.len());
+        anchors
+    })
+}
+
+fn describe(error: webpki::Error) -> String {
+    match error {
+        webpki::Error::UnknownIssuer => {
+            "The signer's certificate isn't issued by an authority this computer trusts".to_string()
+        }
+        webpki::Error::CertExpired { .. } => {
+            "The signer's certificate had expired when the document was signed".to_string()
+        }
+        webpki::Error::CertNotValidYet { .. } => {
+            "The signer's certificate wasn't valid yet when the document was signed".to_string()
+        }
+        webpki::Error::RequiredEkuNotFoundContext(_) => {
+            "The signer's certificate isn't meant for signing documents".to_string()
+        }
+        e => format!("The signer's certificate couldn't be verified: {}", e),
+    }
+}
+
+// Checks that the signer's certificate chains to a trusted root at `time`,
+// taking the other certificates as intermediates
+fn check_chain(
+    certificates: &[Certificate<'_>],
+    signer: usize,
+    time: i64,
+    purpose: Purpose,
+) -> Result<(), String> {
+    let anchors = trust_anchors();
+    if anchors.is_empty() {
+        return Err("No trusted root certificates were found on this computer".to_string());
+    }
+    let der = CertificateDer::from(certificates[signer].der);
+    let end_entity = EndEntityCert::try_from(&der).map_err(describe)?;
+    let intermediates: Vec<CertificateDer> = certificates
+        .iter()
+        .enumerate()
+        .filter(|&(index, _)| index != signer)
+        .map(|(_, certificate)| CertificateDer::from(certificate.der))
+        .collect();
+    end_entity
+        .verify_for_usage(
+            webpki::ALL_VERIFICATION_ALGS,
+            anchors,
+            &intermediates,
+            UnixTime::since_unix_epoch(Duration::from_secs(time.max(0) as u64)),
+            purpose,
+            None,
+            None,
+        )
+        .map(|_| ())
+        .map_err(describe)
+}
+
+// Seconds since 1970 of a PDF date
+fn pdf_date_time(raw: &str) -> Option<i64> {
+    let iso = parse_pdf_date(raw)?;
+    let field = |at: usize| iso.get(at..at + 2).and_then(|f| f.parse::<i64>().ok());
+    let year = iso.get(0..4)?.parse().ok()?;
+    let local = unix_time(
+        year,
+        field(5)?,
+        field(8)?,
+        field(11)?,
+        field(14)?,
+        field(17)?,
+    );
+    let offset = field(20).unwrap_or(0) * 3600 + field(23).unwrap_or(0) * 60;
+    Some(match iso.as_bytes().get(19) {
+        Some(b'+') => local - offset,
+        Some(b'-') => local + offset,
+        _ => local,
+    })
+}
+
+fn certificate_info(certificate: &Certificate<'_>) -> CertificateInfo {
+    CertificateInfo {
+        subject: certificate.subject_name(),
+        issuer: certificate.issuer_name(),
+        not_before: iso_time(certificate.not_before),
+        not_after: iso_time(certificate.not_after),
+    }
+}
+
+// Checks a signature value, filling in `info`. Fails if the signature can't
+// be checked at all.
+fn check(
+    doc: &Document,
+    value: &Dictionary,
+    data: &[u8],
+    info: &mut SignatureInfo,
+) -> Result<(), String> {
+    if let Some(sub_filter) = &info.sub_filter {
+        if !SUB_FILTERS.contains(&sub_filter.as_str()) {
+            return Err(format!(
+                "Signatures of type {} can't be checked",
+                sub_filter
+            ));
+        }
+    }
+    let range: Vec<usize> = objects::get_array(doc, value, b"ByteRange")
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|v| objects::resolve(doc, v).as_i64().ok())
+        .filter_map(|v| usize::try_from(v).ok())
+        .collect();
+    let &[start, first_len, second, second_len] = range.as_slice() else {
+        return Err("The signature doesn't say which bytes it signs".to_string());
+    };
+    let first_end = start.saturating_add(first_len);
+    let end = second.saturating_add(second_len);
+    if first_end > second || end > data.len() {
+        return Err("The bytes the signature signs are outside the file".to_string());
+    }
+    let Some(Object::String(contents, _)) = objects::get(doc, value, b"Contents") else {
+        return Err("The signature field holds no signature".to_string());
+    };
+
+    let signed_data = SignedData::parse(contents)?;
+    let signer = signed_data.signer();
+    if let Some(index) = signer {
+        let certificate = &signed_data.certificates[index];
+        info.signer = Some(certificate.subject_name());
+        info.certificates.push(certificate_info(certificate));
+    }
+    for (index, certificate) in signed_data.certificates.iter().enumerate() {
+        if Some(index) != signer {
+            info.certificates.push(certificate_info(certificate));
+        }
+    }
+    let claimed = signed_data
+        .signing_time()
+        .or_else(|| objects::get_text(doc, value, b"M").and_then(|date| pdf_date_time(&date)));
+    info.signing_time = claimed.map(iso_time);
+    let timestamp = signed_data
+        .timestamp()
+        .and_then(|timestamp| {
+            let Some(timestamp) = timestamp else {
+                return Ok(None);
+            };
+            let authority = timestamp
+                .token
+                .signer()
+                .ok_or("The timestamp authority's certificate isn't included")?;
+            let certificates = &timestamp.token.certificates;
+            check_chain(
+                certificates,
+                authority,
+                timestamp.time,
+                Purpose::TimeStamping,
+            )?;
+            Ok(Some((
+                timestamp.time,
+                certificates[authority].subject_name(),
+            )))
+        })
+        .unwrap_or_else(|e: String| {
+            eprintln!("Ignoring the timestamp of {}: {}", info.field, e);
+            None
+        });
+    let time = timestamp.as_ref().map(|(time, _)| *time).or(claimed);
+    if let Some((time, authority)) = timestamp {
+        info.timestamp = Some(iso_time(time));
+        info.timestamp_authority = Some(authority);
+    }
+
+    info.intact = signed_data.verify(&[&data[start..first_end], &data[second..end]])?;
+    // Only the signature itself may be left out of the signed bytes
+    let gap = &data[first_end..second];
+    let only_signature = gap.len() >= 2
+        && gap.first() == Some(&b'<')
+        && gap.last() == Some(&b'>')
+        && gap[1..gap.len() - 1].iter().all(u8::is_ascii_hexdigit);
+    info.covers_document =
+        start == 0 && only_signature && data[end..].iter().all(u8::is_ascii_whitespace);
+    if !info.intact {
+        info.problem = Some("The document has been changed since it was signed".to_string());
+        return Ok(());
+    }
+    if !info.covers_document {
+        info.problem = Some("The document was changed after it was signed".to_string());
+    }
+
+    let Some(signer) = signer else {
+        info.problem
+            .get_or_insert("The signer's certificate isn't included".to_string());
+        return Ok(());
+    };
+    let time = time.unwrap_or_else(|| UnixTime::now().as_secs() as i64);
+    match check_chain(
+        &signed_data.certificates,
+        signer,
+        time,
+        Purpose::DocumentSigning,
+    ) {
+        Ok(()) => info.trusted = true,
+        Err(e) => {
+            info.problem.get_or_insert(e);
+        }
+    }
+    Ok(())
+}
+
+fn signature_info(
+    doc: &Document,
+    field: String,
+    page: Option<i32>,
+    value: Option<&Dictionary>,
+    data: &[u8],
+) -> SignatureInfo {
+    let text = |key: &[u8]| value.and_then(|value| objects::get_text(doc, value, key));
+    let mut info = SignatureInfo {
+        field,
+        page,
+        verdict: SignatureVerdict::Unsigned,
+        intact: false,
+        covers_document: false,
+        trusted: false,
+        problem: None,
+        signer: text(b"Name"),
+        signing_time: None,
+        timestamp: None,
+        timestamp_authority: None,
+        reason: text(b"Reason"),
+        location: text(b"Location"),
+        contact_info: text(b"ContactInfo"),
+        sub_filter: value
+            .and_then(|value| objects::get_name(doc, value, b"SubFilter"))
+            .map(|name| String::from_utf8_lossy(name).into_owned()),
+        certificates: Vec::new(),
+    };
+    let Some(value) = value else {
+        return info;
+    };
+    if let Err(e) = check(doc, value, data, &mut info) {
+        info.verdict = SignatureVerdict::Invalid;
+        info.problem = Some(e);
+        return info;
+    }
+    info.verdict = match (info.intact && info.covers_document, info.trusted) {
+        (true, true) => SignatureVerdict::Valid,
+        (true, false) => SignatureVerdict::Untrusted,
+        (false, _) => SignatureVerdict::Modified,
+    };
+    info
+}
+
+// Signature fields under `field`, with their full names and the ids of their
+// widgets
+fn collect_fields<'a>(
+    doc: &'a Document,
+    field: &'a Object,
+    parent: Option<&str>,
+    field_type: Option<&'a [u8]>,
+    depth: usize,
+    found: &mut Vec<(String, &'a Dictionary, Vec<ObjectId>)>,
+) {
+    let Ok(dict) = objects::resolve(doc, field).as_dict() else {
+        return;
+    };
+    if depth > MAX_DEPTH {
+        return;
+    }
+    let name = match (parent, objects::get_text(doc, dict, b"T")) {
+        (Some(parent), Some(own)) => format!("{}.{}", parent, own),
+        (None, Some(own)) => own,
+        (parent, None) => parent.unwrap_or_default().to_string(),
+    };
+    let field_type = objects::get_name(doc, dict, b"FT").or(field_type);
+    let kids = objects::get_array(doc, dict, b"Kids").unwrap_or(&[]);
+    // Kids without names are the field's widgets
+    let (fields, widgets): (Vec<&Object>, Vec<&Object>) = kids.iter().partition(|kid| {
+        objects::resolve(doc, kid)
+            .as_dict()
+            .is_ok_and(|kid| kid.has(b"T"))
+    });
+    for kid in fields {
+        collect_fields(doc, kid, Some(&name), field_type, depth + 1, found);
+    }
+    if field_type != Some(b"Sig") || (!kids.is_empty() && widgets.is_empty()) {
+        return;
+    }
+    let widgets = match widgets.is_empty() {
+        // The field is its own widget
+        true => field.as_reference().into_iter().collect(),
+        false => widgets
+            .iter()
+            .filter_map(|widget| widget.as_reference().ok())
+            .collect(),
+    };
+    found.push((name, dict, widgets));
+}
+
+fn signatures(doc: &Document, data: &[u8]) -> Vec<SignatureInfo> {
+    let Some(form) = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| objects::get_dict(doc, catalog, b"AcroForm"))
+    else {
+        return Vec::new();
+    };
+    let mut fields = Vec::new();
+    for field in objects::get_array(doc, form, b"Fields").unwrap_or(&[]) {
+        collect_fields(doc, field, None, None, 0, &mut fields);
+    }
+
+    let mut widget_pages = HashMap::new();
+    for (number, page_id) in doc.get_pages() {
+        let Ok(page) = doc.get_dictionary(page_id) else {
+            continue;
+        };
+        for annot in objects::get_array(doc, page, b"Annots").unwrap_or(&[]) {
+            if let Ok(id) = annot.as_reference() {
+                widget_pages.insert(id, number as i32 - 1);
+            }
+        }
+    }
+    fields
+        .into_iter()
+        .map(|(name, dict, widgets)| {
+            let page = widgets.iter().find_map(|id| widget_pages.get(id).copied());
+            let value = objects::get_dict(doc, dict, b"V");
+            signature_info(doc, name, page, value, data)
+        })
+        .collect()
+}
+
+/// Lists the signature fields of a document and checks their signatures.
+/// Signatures are checked against the document's current bytes, so unsaved
+/// edits show up as changes made after signing.
+#[tauri::command]
+pub async fn get_signatures(
+    doc_id: DocId,
+    store: State<'_, DocumentStore>,
+) -> Result<Vec<SignatureInfo>, String> {
+    let doc = store.get(doc_id)?;
+    blocking(move || {
+        let signatures = doc.with_objects(|objects| Ok(signatures(objects, &doc.data)))?;
+        eprintln!(
+            "Checked {} signature fields of document {}",
+            signatures.len(),
+            doc_id
+        );
+        Ok(signatures)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::dictionary;
+
+    use super::*;
+    use crate::fixtures::{self, Signer};
+    use crate::incremental::{self, Update};
+    use crate::signing::{self, SignatureOptions};
+
+    fn signed_pdf() -> Vec<u8> {
+        let data = fixtures::pdf(1);
+        let doc = Document::load_mem(&data).unwrap();
+        let signer = Signer::new("Test Signer");
+        let certificate = Certificate::from_der(&signer.der).unwrap();
+        let options = SignatureOptions {
+            certificate_id: String::new(),
+            field_name: None,
+            reason: None,
+            location: None,
+            contact_info: None,
+            appearance: None,
+            timestamp_url: None,
+            output_path: String::new(),
+        };
+        signing::prepare(&doc, &data, &certificate, &options)
+            .unwrap()
+            .sign(&certificate, None, |attributes| Ok(signer.sign(attributes)))
+            .unwrap()
+    }
+
+    fn check(data: &[u8]) -> SignatureInfo {
+        let doc = Document::load_mem(data).unwrap();
+        let mut found = signatures(&doc, data);
+        assert_eq!(found.len(), 1);
+        found.remove(0)
+    }
+
+    #[test]
+    fn signed_file_is_intact() {
+        let info = check(&signed_pdf());
+        assert!(info.intact && info.covers_document);
+        // The fixture's certificate is self-signed
+        assert!(info.verdict == SignatureVerdict::Untrusted);
+        assert_eq!(info.signer.as_deref(), Some("Test Signer"));
+    }
+
+    #[test]
+    fn changed_byte_is_modified() {
+        let mut data = signed_pdf();
+        let at = data.windows(8).position(|w| w == b"Untitled").unwrap();
+        data[at] = b'V';
+        let info = check(&data);
+        assert!(!info.intact);
+        assert!(info.verdict == SignatureVerdict::Modified);
+    }
+
+    #[test]
+    fn later_update_isnt_covered() {
+        let data = signed_pdf();
+        let doc = Document::load_mem(&data).unwrap();
+        let info_id = doc.trailer.get(b"Info").unwrap().as_reference().unwrap();
+        let mut update = Update::new(&data);
+        update.write(
+            info_id,
+            &Object::Dictionary(dictionary! {
+                "Title" => Object::string_literal("Changed"),
+            }),
+        );
+        let updated = update.finish(&dictionary! {
+            "Size" => doc.max_id as i64 + 1,
+            "Root" => doc.trailer.get(b"Root").unwrap().clone(),
+            "Info" => info_id,
+            "Prev" => incremental::last_startxref(&data).unwrap() as i64,
+        });
+
+        let info = check(&updated);
+        assert!(info.intact && !info.covers_document);
+        assert!(info.verdict == SignatureVerdict::Modified);
+    }
+}
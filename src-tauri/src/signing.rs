@@ -0,0 +1,503 @@
+// Signing documents with a certificate from the operating system's keystore.
+//
+// The signature goes into an incremental update, so the bytes of the file,
+// and any signatures already over them, stay as they are. The update adds a
+// signature field with its widget, visible on a page or not, and the
+// signature value: a CAdES detached signature (PAdES baseline B) over every
+// byte of the finished file except the value itself. Space for the value is
+// reserved first, the file is hashed around it, and the signature is written
+// into the gap.
+//
+// With a timestamp authority (RFC 3161) configured, the signature value is
+// sent off to it, and the token it returns goes into the signature too. That
+// keeps the signature checkable after the signer's certificate expires.
+
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+
+use crate::cms::{self, iso_time, Certificate, Digest};
+use crate::compose::{write_file, WrittenDocument};
+use crate::content::upright_transform;
+use crate::incremental::{self, Update};
+use crate::keystore;
+use crate::metadata::format_pdf_date;
+use crate::objects::{self, PageSpace};
+use crate::pdf::{blocking, Bounds, DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scope;
+use crate::stamp::{text_width, win_ansi, StandardFont};
+
+// Room for "0 a b c" with offsets of up to ten digits
+const BYTE_RANGE_WIDTH: usize = 48;
+// Room in the signature value for what comes besides the certificate
+const SIGNATURE_OVERHEAD: usize = 8192;
+// Room for a timestamp token, which carries the authority's certificates
+const TIMESTAMP_SPACE: usize = 16384;
+const TIMESTAMP_TIMEOUT: Duration = Duration::from_secs(30);
+// Annotation flags: print, locked
+const WIDGET_FLAGS: i64 = 4 | 128;
+// AcroForm /SigFlags: signatures exist, append only
+const SIG_FLAGS: i64 = 3;
+
+/// A certificate the keystore can sign with.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SigningCertificate {
+    /// SHA-256 of the certificate, to pass to `sign_document`
+    pub id: String,
+    pub subject: String,
+    pub issuer: String,
+    /// ISO 8601
+    pub not_before: String,
+    pub not_after: String,
+}
+
+/// Where a visible signature is shown.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureAppearance {
+    pub page: i32,
+    /// In page space
+    pub bounds: Bounds,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureOptions {
+    /// Id of a certificate from `list_signing_certificates`
+    pub certificate_id: String,
+    /// Name of the new signature field; "Signature1" or the next free one
+    /// by default
+    pub field_name: Option<String>,
+    pub reason: Option<String>,
+    pub location: Option<String>,
+    pub contact_info: Option<String>,
+    /// Where to show the signature; invisible if not given
+    pub appearance: Option<SignatureAppearance>,
+    /// URL of an RFC 3161 timestamp authority to timestamp the signature
+    pub timestamp_url: Option<String>,
+    pub output_path: String,
+}
+
+/// A file with a signature field whose value is still to be filled in, and
+/// where its placeholders ended up.
+pub struct Unsigned {
+    file: Vec<u8>,
+    byte_range: usize,
+    contents: usize,
+    contents_len: usize,
+}
+
+fn certificate_id(der: &[u8]) -> String {
+    hex::encode(Digest::Sha256.hash(&[der]))
+}
+
+/// Lists the certificates in the system keystore that have a private key to
+/// sign with.
+#[tauri::command]
+pub async fn list_signing_certificates() -> Result<Vec<SigningCertificate>, String> {
+    blocking(|| {
+        let certificates = keystore::identities()?
+            .iter()
+            .filter_map(|der| match Certificate::from_der(der) {
+                Ok(certificate) => Some(SigningCertificate {
+                    id: certificate_id(der),
+                    subject: certificate.subject_name(),
+                    issuer: certificate.issuer_name(),
+                    not_before: iso_time(certificate.not_before),
+                    not_after: iso_time(certificate.not_after),
+                }),
+                Err(e) => {
+                    eprintln!("Skipping a keystore certificate: {}", e);
+                    None
+                }
+            })
+            .collect();
+        Ok(certificates)
+    })
+    .await
+}
+
+// Names of the top-level form fields
+fn field_names(doc: &Document, fields: &[Object]) -> Vec<String> {
+    fields
+        .iter()
+        .filter_map(|field| objects::resolve(doc, field).as_dict().ok())
+        .filter_map(|field| objects::get_text(doc, field, b"T"))
+        .collect()
+}
+
+// Adds `entry` to the array under `key`. An array of its own is written as
+// it is; otherwise the array is set in `dict`, and the result is true if
+// `dict` needs writing
+fn append_to_array(
+    doc: &Document,
+    update: &mut Update<'_>,
+    dict: &mut Dictionary,
+    key: &str,
+    entry: Object,
+) -> bool {
+    if let Ok(&Object::Reference(id)) = dict.get(key.as_bytes()) {
+        if let Ok(Object::Array(items)) = doc.get_object(id) {
+            let mut items = items.clone();
+            items.push(entry);
+            update.write(id, &Object::Array(items));
+            return false;
+        }
+    }
+    let mut items = objects::get_array(doc, dict, key.as_bytes())
+        .map(<[Object]>::to_vec)
+        .unwrap_or_default();
+    items.push(entry);
+    dict.set(key, items);
+    true
+}
+
+// The shown content of a visible signature, `width` by `height` points
+fn appearance_content(lines: &[String], width: f32, height: f32) -> Result<Vec<u8>, String> {
+    let font = StandardFont::Helvetica;
+    let margin = 2.0;
+    let lines: Vec<Vec<u8>> = lines.iter().map(|line| win_ansi(line)).collect();
+    let widest = lines
+        .iter()
+        .map(|line| text_width(font, line, 1.0))
+        .fold(0.0, f32::max);
+    let font_size = ((height - 2.0 * margin) / (lines.len() as f32 * 1.2))
+        .min((width - 2.0 * margin) / widest.max(1.0))
+        .min(12.0);
+
+    let mut operations = vec![
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec!["F1".into(), font_size.into()]),
+    ];
+    for (i, line) in lines.into_iter().enumerate() {
+        let y = height - margin - font_size * (1.2 * i as f32 + 1.0);
+        operations.push(Operation::new(
+            "Tm",
+            vec![
+                1.into(),
+                0.into(),
+                0.into(),
+                1.into(),
+                margin.into(),
+                y.into(),
+            ],
+        ));
+        operations.push(Operation::new(
+            "Tj",
+            vec![Object::String(line, StringFormat::Literal)],
+        ));
+    }
+    operations.push(Operation::new("ET", vec![]));
+    Content { operations }
+        .encode()
+        .map_err(|e| format!("Failed to write the signature appearance: {}", e))
+}
+
+/// Writes the signature field, its widget and value, and the objects that
+/// point to them after `data`, the file `doc` was parsed from.
+pub fn prepare(
+    doc: &Document,
+    data: &[u8],
+    signer: &Certificate<'_>,
+    options: &SignatureOptions,
+) -> Result<Unsigned, String> {
+    let previous = incremental::last_startxref(data)
+        .ok_or_else(|| "The document's cross-reference table can't be found".to_string())?;
+    let catalog_id = doc
+        .trailer
+        .get(b"Root")
+        .and_then(Object::as_reference)
+        .map_err(|_| "The document has no catalog".to_string())?;
+    let mut catalog = doc
+        .get_dictionary(catalog_id)
+        .map_err(|e| e.to_string())?
+        .clone();
+    let (form_id, mut form) = match catalog.get(b"AcroForm") {
+        Ok(&Object::Reference(id)) => (
+            Some(id),
+            doc.get_dictionary(id).cloned().unwrap_or_default(),
+        ),
+        _ => (
+            None,
+            objects::get_dict(doc, &catalog, b"AcroForm")
+                .cloned()
+                .unwrap_or_default(),
+        ),
+    };
+
+    let existing = field_names(
+        doc,
+        objects::get_array(doc, &form, b"Fields").unwrap_or(&[]),
+    );
+    let name = match &options.field_name {
+        Some(name) if existing.contains(name) => {
+            return Err(format!("A field named {} already exists", name));
+        }
+        Some(name) if name.trim().is_empty() || name.contains('.') => {
+            return Err("Field names can't be empty or contain periods".to_string());
+        }
+        Some(name) => name.clone(),
+        None => (1..)
+            .map(|n| format!("Signature{}", n))
+            .find(|name| !existing.contains(name))
+            .unwrap_or_default(),
+    };
+
+    let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    let page_id = *page_ids
+        .get(options.appearance.as_ref().map_or(0, |a| a.page) as usize)
+        .ok_or_else(|| "The page to sign on is out of range".to_string())?;
+
+    let size = doc
+        .trailer
+        .get(b"Size")
+        .and_then(Object::as_i64)
+        .map_or(0, |size| size.max(1) as u32 - 1);
+    let mut next_id = doc.max_id.max(size) + 1;
+    let mut new_id = || {
+        next_id += 1;
+        (next_id - 1, 0)
+    };
+    let value_id = new_id();
+    let widget_id = new_id();
+    let mut update = Update::new(data);
+
+    // The signature value, serialized by hand to know where its
+    // placeholders land
+    let now = SystemTime::now();
+    let mut value = dictionary! {
+        "Type" => "Sig",
+        "Filter" => "Adobe.PPKLite",
+        "SubFilter" => "ETSI.CAdES.detached",
+        "M" => Object::string_literal(format_pdf_date(now)),
+        "Name" => objects::encode_text(&signer.subject_name()),
+    };
+    for (key, text) in [
+        ("Reason", &options.reason),
+        ("Location", &options.location),
+        ("ContactInfo", &options.contact_info),
+    ] {
+        if let Some(text) = text.as_deref().filter(|text| !text.trim().is_empty()) {
+            value.set(key, objects::encode_text(text));
+        }
+    }
+    let mut serialized = Vec::new();
+    incremental::serialize(&Object::Dictionary(value), &mut serialized);
+    serialized.truncate(serialized.len() - 2);
+    serialized.extend_from_slice(b"/ByteRange[");
+    let byte_range = serialized.len();
+    serialized.extend(std::iter::repeat_n(b' ', BYTE_RANGE_WIDTH));
+    serialized.extend_from_slice(b"]/Contents");
+    let contents = serialized.len();
+    let mut contents_len = signer.der.len() + SIGNATURE_OVERHEAD;
+    if options.timestamp_url.is_some() {
+        contents_len += TIMESTAMP_SPACE;
+    }
+    serialized.push(b'<');
+    serialized.extend(std::iter::repeat_n(b'0', 2 * contents_len));
+    serialized.extend_from_slice(b">>>");
+    let start = update.write_raw(value_id, &serialized);
+
+    let mut widget = dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Widget",
+        "FT" => "Sig",
+        "T" => objects::encode_text(&name),
+        "V" => Object::Reference(value_id),
+        "F" => WIDGET_FLAGS,
+        "P" => Object::Reference(page_id),
+        "Rect" => vec![0.into(), 0.into(), 0.into(), 0.into()],
+    };
+    if let Some(appearance) = &options.appearance {
+        let Bounds { width, height, .. } = appearance.bounds;
+        if width < 1.0 || height < 1.0 {
+            return Err("The signature needs an area to be shown in".to_string());
+        }
+        let rect = PageSpace::new(doc, page_id).user_rect(appearance.bounds);
+        // Drawn upright, as the page is shown, then turned with the page
+        let ([a, b, c, d, _, _], _) = upright_transform(doc, page_id);
+        let seconds = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64);
+        let mut lines = vec![
+            format!("Digitally signed by {}", signer.subject_name()),
+            format!("Date: {}", iso_time(seconds)),
+        ];
+        if let Some(reason) = options.reason.as_deref().filter(|r| !r.trim().is_empty()) {
+            lines.push(format!("Reason: {}", reason));
+        }
+        if let Some(location) = options.location.as_deref().filter(|l| !l.trim().is_empty()) {
+            lines.push(format!("Location: {}", location));
+        }
+        let stream = Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Form",
+                "BBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+                "Matrix" => vec![a.into(), b.into(), c.into(), d.into(), 0.into(), 0.into()],
+                "Resources" => dictionary! {
+                    "Font" => dictionary! { "F1" => StandardFont::Helvetica.font_dict() },
+                },
+            },
+            appearance_content(&lines, width, height)?,
+        );
+        let stream_id = new_id();
+        update.write(stream_id, &Object::Stream(stream));
+        widget.set("Rect", rect.map(Object::from).to_vec());
+        widget.set("AP", dictionary! { "N" => Object::Reference(stream_id) });
+    }
+    update.write(widget_id, &Object::Dictionary(widget));
+
+    let mut page = doc
+        .get_dictionary(page_id)
+        .map_err(|e| e.to_string())?
+        .clone();
+    if append_to_array(doc, &mut update, &mut page, "Annots", widget_id.into()) {
+        update.write(page_id, &Object::Dictionary(page));
+    }
+    append_to_array(doc, &mut update, &mut form, "Fields", widget_id.into());
+    form.set("SigFlags", SIG_FLAGS);
+    match form_id {
+        Some(form_id) => update.write(form_id, &Object::Dictionary(form)),
+        // The form lives in the catalog, so the catalog is written with it
+        None => {
+            catalog.set("AcroForm", form);
+            update.write(catalog_id, &Object::Dictionary(catalog));
+        }
+    }
+
+    let mut trailer = dictionary! {
+        "Size" => next_id as i64,
+        "Root" => Object::Reference(catalog_id),
+        "Prev" => previous as i64,
+    };
+    for key in [b"Info".as_slice(), b"ID"] {
+        if let Ok(entry) = doc.trailer.get(key) {
+            trailer.set(key.to_vec(), entry.clone());
+        }
+    }
+    Ok(Unsigned {
+        file: update.finish(&trailer),
+        byte_range: start + byte_range,
+        contents: start + contents,
+        contents_len,
+    })
+}
+
+// Asks the timestamp authority at `url` for a token over `signature`
+fn request_timestamp(url: &str, signature: &[u8]) -> Result<Vec<u8>, String> {
+    // For authorities reached over HTTPS
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let (request, nonce) = cms::timestamp_request(signature)?;
+    let response = reqwest::blocking::Client::new()
+        .post(url)
+        .header("Content-Type", "application/timestamp-query")
+        .timeout(TIMESTAMP_TIMEOUT)
+        .body(request)
+        .send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.bytes())
+        .map_err(|e| format!("Failed to reach the timestamp authority: {}", e))?;
+    cms::timestamp_token(&response, signature, &nonce)
+}
+
+impl Unsigned {
+    /// Fills in the signature by `signer`, whose key signs the signed
+    /// attributes with `sign`, timestamped by the authority at
+    /// `timestamp_url` if one is given. Returns the signed file.
+    pub fn sign(
+        mut self,
+        signer: &Certificate<'_>,
+        timestamp_url: Option<&str>,
+        sign: impl FnOnce(&[u8]) -> Result<Vec<u8>, String>,
+    ) -> Result<Vec<u8>, String> {
+        let file = &mut self.file;
+        let contents_end = self.contents + 2 * self.contents_len + 2;
+        let byte_range = format!(
+            "0 {} {} {}",
+            self.contents,
+            contents_end,
+            file.len() - contents_end
+        );
+        file[self.byte_range..self.byte_range + byte_range.len()]
+            .copy_from_slice(byte_range.as_bytes());
+
+        let digest = Digest::Sha256.hash(&[&file[..self.contents], &file[contents_end..]]);
+        let attributes = cms::signed_attributes(signer, &digest);
+        let signature = sign(&attributes)?;
+        let token = match timestamp_url {
+            Some(url) => Some(request_timestamp(url, &signature)?),
+            None => None,
+        };
+        let signed = cms::signed_data(signer, &attributes, &signature, token.as_deref())?;
+        if signed.len() > self.contents_len {
+            return Err("The signature is too big for the space reserved for it".to_string());
+        }
+        let encoded = hex::encode_upper(&signed);
+        file[self.contents + 1..self.contents + 1 + encoded.len()]
+            .copy_from_slice(encoded.as_bytes());
+        Ok(self.file)
+    }
+}
+
+/// Signs a document with a certificate from the system keystore and saves
+/// the result as a new file. The open document is left as it is.
+///
+/// The keystore may ask the user to allow the use of the key, or for the PIN
+/// of a smart card, before signing.
+#[tauri::command]
+pub async fn sign_document(
+    app: AppHandle,
+    doc_id: DocId,
+    options: SignatureOptions,
+    store: State<'_, DocumentStore>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<WrittenDocument, String> {
+    scope::check_write(&app, Path::new(&options.output_path))?;
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::FillForms).await?;
+    if let Some(appearance) = &options.appearance {
+        doc.check_page(appearance.page)?;
+    }
+    blocking(move || {
+        // Signing writes after the bytes as they are, so encrypted files would
+        // need the update encrypted too
+        let encrypted = doc.password.is_some()
+            || Document::load_mem(&doc.data).is_ok_and(|parsed| parsed.is_encrypted());
+        if encrypted {
+            return Err(format!(
+                "{} is password protected. Remove the password before signing it",
+                doc.path.display()
+            ));
+        }
+        let der = keystore::identities()?
+            .into_iter()
+            .find(|der| certificate_id(der) == options.certificate_id)
+            .ok_or_else(|| "The certificate is no longer in the keystore".to_string())?;
+        let signer = Certificate::from_der(&der)?;
+        let unsigned =
+            doc.with_objects(|objects| prepare(objects, &doc.data, &signer, &options))?;
+        let file = unsigned.sign(&signer, options.timestamp_url.as_deref(), |attributes| {
+            keystore::sign(&der, attributes)
+        })?;
+
+        write_file(Path::new(&options.output_path), &file)?;
+        eprintln!(
+            "Signed document {} as {} into {}",
+            doc_id,
+            signer.subject_name(),
+            options.output_path
+        );
+        Ok(WrittenDocument {
+            path: options.output_path,
+            page_count: doc.page_count,
+        })
+    })
+    .await
+}
@@ -0,0 +1,501 @@
+// Watermarks and stamps ("DRAFT", "CONFIDENTIAL", logos) drawn over pages.
+//
+// Stamps are laid out in the page as the reader sees it, so "top right" and
+// the rotation angle mean the same thing on pages with a /Rotate entry. Text
+// uses one of the standard fonts, which every viewer has, so nothing needs to
+// be embedded.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use lopdf::content::Operation;
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use serde::Deserialize;
+use tauri::{AppHandle, State};
+
+use crate::compose::{parse_page_ranges, write_document, WrittenDocument};
+use crate::content::{add_resource, append_content, concat, upright_transform, Transform};
+use crate::pdf::{blocking, DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scope;
+
+// Advance widths for ASCII 32 to 126, in 1/1000 em
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667,
+    611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500,
+    222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+const HELVETICA_BOLD_WIDTHS: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611, 975, 722, 722, 722, 722, 667,
+    611, 778, 722, 278, 556, 722, 611, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 333, 278, 333, 584, 556, 333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556,
+    278, 889, 611, 611, 611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584,
+];
+const TIMES_ROMAN_WIDTHS: [u16; 95] = [
+    250, 333, 408, 500, 500, 833, 778, 180, 333, 333, 500, 564, 250, 333, 250, 278, 500, 500, 500,
+    500, 500, 500, 500, 500, 500, 500, 278, 278, 564, 564, 564, 444, 921, 722, 667, 667, 722, 611,
+    556, 722, 722, 333, 389, 722, 611, 889, 722, 722, 556, 722, 667, 556, 611, 722, 722, 944, 722,
+    722, 611, 333, 278, 333, 469, 500, 333, 444, 500, 444, 500, 444, 333, 500, 500, 278, 278, 500,
+    278, 778, 500, 500, 500, 500, 333, 389, 278, 500, 500, 722, 500, 500, 444, 480, 200, 480, 541,
+];
+const COURIER_WIDTH: u16 = 600;
+
+/// One of the standard fonts viewers provide themselves.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum StandardFont {
+    Helvetica,
+    #[default]
+    HelveticaBold,
+    TimesRoman,
+    Courier,
+}
+
+impl StandardFont {
+    fn base_font(self) -> &'static str {
+        match self {
+            StandardFont::Helvetica => "Helvetica",
+            StandardFont::HelveticaBold => "Helvetica-Bold",
+            StandardFont::TimesRoman => "Times-Roman",
+            StandardFont::Courier => "Courier",
+        }
+    }
+
+    /// Height of capital letters, in em.
+    pub fn cap_height(self) -> f32 {
+        match self {
+            StandardFont::Helvetica | StandardFont::HelveticaBold => 0.718,
+            StandardFont::TimesRoman => 0.662,
+            StandardFont::Courier => 0.571,
+        }
+    }
+
+    // Width of a character in 1/1000 em. That of "a" is close enough for the
+    // accented letters of the Latin-1 range
+    fn width(self, b: u8) -> u16 {
+        let widths = match self {
+            StandardFont::Helvetica => &HELVETICA_WIDTHS,
+            StandardFont::HelveticaBold => &HELVETICA_BOLD_WIDTHS,
+            StandardFont::TimesRoman => &TIMES_ROMAN_WIDTHS,
+            StandardFont::Courier => return COURIER_WIDTH,
+        };
+        match b {
+            0x20..=0x7e => widths[(b - 0x20) as usize],
+            _ => widths[(b'a' - 0x20) as usize],
+        }
+    }
+
+    /// A font dictionary for the font with the WinAnsi encoding.
+    pub fn font_dict(self) -> Dictionary {
+        dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => self.base_font(),
+            "Encoding" => "WinAnsiEncoding",
+        }
+    }
+
+    /// Adds the font's dictionary to a document.
+    pub fn add_to(self, doc: &mut Document) -> ObjectId {
+        doc.add_object(self.font_dict())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum StampContent {
+    #[serde(rename_all = "camelCase")]
+    Text {
+        text: String,
+        font_size: f32,
+        /// RGB, each from 0 to 1
+        color: [f32; 3],
+        #[serde(default)]
+        font: StandardFont,
+    },
+    /// A PNG or JPEG file, `width` points wide (its size at 72 dpi by default)
+    Image { path: String, width: Option<f32> },
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum StampPosition {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    #[default]
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StampOptions {
+    pub content: StampContent,
+    /// From 0 (invisible) to 1
+    pub opacity: f32,
+    /// Counter-clockwise, in degrees
+    #[serde(default)]
+    pub rotation: f32,
+    #[serde(default)]
+    pub position: StampPosition,
+    /// Distance from the page edges, in points
+    #[serde(default)]
+    pub margin: f32,
+    /// One-based ranges like "1-5,8,10-end"; all pages if missing
+    pub ranges: Option<String>,
+}
+
+// A stamp's drawing, centred on the origin, and its size in points
+struct Drawing {
+    operations: Vec<Operation>,
+    width: f32,
+    height: f32,
+}
+
+/// Encodes text for the WinAnsi encoding, which matches Latin-1 for the
+/// characters it shares with it.
+pub fn win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c as u32 {
+            code @ (0x20..=0x7e | 0xa0..=0xff) => code as u8,
+            _ => b'?',
+        })
+        .collect()
+}
+
+/// Width in points of WinAnsi-encoded text.
+pub fn text_width(font: StandardFont, bytes: &[u8], font_size: f32) -> f32 {
+    let units: u32 = bytes.iter().map(|&b| font.width(b) as u32).sum();
+    units as f32 / 1000.0 * font_size
+}
+
+/// An image file added to a document as an image XObject.
+pub struct EmbeddedImage {
+    pub id: ObjectId,
+    /// Size in pixels
+    pub width: u32,
+    pub height: u32,
+    /// Resolution in pixels per inch, if the file records it
+    pub dpi: Option<f32>,
+}
+
+// Reads the resolution in the JFIF header of a JPEG file
+fn jfif_dpi(data: &[u8]) -> Option<f32> {
+    let header = data.get(2..18)?;
+    if header[..2] != [0xff, 0xe0] || &header[4..9] != b"JFIF\0" {
+        return None;
+    }
+    let density = u16::from_be_bytes([header[12], header[13]]) as f32;
+    match header[11] {
+        1 => Some(density),
+        2 => Some(density * 2.54),
+        _ => None,
+    }
+    .filter(|dpi| *dpi > 0.0)
+}
+
+/// Reads a JPEG or PNG file into an image XObject. JPEG data is embedded as
+/// it is; PNG transparency becomes a soft mask.
+pub fn image_xobject(doc: &mut Document, path: &Path) -> Result<EmbeddedImage, String> {
+    let error = |e: String| format!("Failed to read {}: {}", path.display(), e);
+    let mut data = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut data))
+        .map_err(|e| error(e.to_string()))?;
+
+    if data.starts_with(&[0xff, 0xd8]) {
+        // JPEG data can be embedded as it is
+        let mut decoder = jpeg_decoder::Decoder::new(data.as_slice());
+        decoder.read_info().map_err(|e| error(e.to_string()))?;
+        let info = decoder
+            .info()
+            .ok_or_else(|| error("not a JPEG image".into()))?;
+        let space = match info.pixel_format {
+            jpeg_decoder::PixelFormat::L8 | jpeg_decoder::PixelFormat::L16 => "DeviceGray",
+            jpeg_decoder::PixelFormat::RGB24 => "DeviceRGB",
+            jpeg_decoder::PixelFormat::CMYK32 => "DeviceCMYK",
+        };
+        let (width, height) = (info.width as u32, info.height as u32);
+        let dpi = jfif_dpi(&data);
+        let image = Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => width,
+                "Height" => height,
+                "ColorSpace" => space,
+                "BitsPerComponent" => 8,
+                "Filter" => "DCTDecode",
+            },
+            data,
+        )
+        .with_compression(false);
+        return Ok(EmbeddedImage {
+            id: doc.add_object(image),
+            width,
+            height,
+            dpi,
+        });
+    }
+
+    let mut decoder = png::Decoder::new(data.as_slice());
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|e| error(e.to_string()))?;
+    let dpi = reader
+        .info()
+        .pixel_dims
+        .filter(|dims| dims.unit == png::Unit::Meter && dims.xppu > 0)
+        .map(|dims| dims.xppu as f32 * 0.0254);
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let frame = reader
+        .next_frame(&mut pixels)
+        .map_err(|e| error(e.to_string()))?;
+    pixels.truncate(frame.buffer_size());
+
+    // PDF images keep transparency in a separate soft mask
+    let (channels, space) = match frame.color_type {
+        png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => (1, "DeviceGray"),
+        _ => (3, "DeviceRGB"),
+    };
+    let has_alpha = matches!(
+        frame.color_type,
+        png::ColorType::GrayscaleAlpha | png::ColorType::Rgba
+    );
+    let mut dict = dictionary! {
+        "Type" => "XObject",
+        "Subtype" => "Image",
+        "Width" => frame.width,
+        "Height" => frame.height,
+        "ColorSpace" => space,
+        "BitsPerComponent" => 8,
+    };
+    if has_alpha {
+        let stride = channels + 1;
+        let alpha: Vec<u8> = pixels.chunks_exact(stride).map(|p| p[channels]).collect();
+        pixels = pixels
+            .chunks_exact(stride)
+            .flat_map(|p| p[..channels].to_vec())
+            .collect();
+        let mask = doc.add_object(Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => frame.width,
+                "Height" => frame.height,
+                "ColorSpace" => "DeviceGray",
+                "BitsPerComponent" => 8,
+            },
+            alpha,
+        ));
+        dict.set("SMask", mask);
+    }
+    Ok(EmbeddedImage {
+        id: doc.add_object(Stream::new(dict, pixels)),
+        width: frame.width,
+        height: frame.height,
+        dpi,
+    })
+}
+
+// Adds the resources a stamp needs to `doc` and builds its drawing. Names in
+// the drawing are resolved per page by `page_drawing`.
+fn prepare(doc: &mut Document, content: &StampContent) -> Result<(Drawing, ObjectId), String> {
+    match content {
+        StampContent::Text {
+            text,
+            font_size,
+            color,
+            font,
+        } => {
+            let bytes = win_ansi(text);
+            let width = text_width(*font, &bytes, *font_size);
+            let height = font_size * font.cap_height();
+            let font = font.add_to(doc);
+            let [r, g, b] = color.map(|c| c.clamp(0.0, 1.0));
+            let operations = vec![
+                Operation::new("BT", vec![]),
+                Operation::new("Tf", vec![Object::Name(vec![]), (*font_size).into()]),
+                Operation::new("rg", vec![r.into(), g.into(), b.into()]),
+                Operation::new("Td", vec![(-width / 2.0).into(), (-height / 2.0).into()]),
+                Operation::new("Tj", vec![Object::String(bytes, StringFormat::Literal)]),
+                Operation::new("ET", vec![]),
+            ];
+            Ok((
+                Drawing {
+                    operations,
+                    width,
+                    height,
+                },
+                font,
+            ))
+        }
+        StampContent::Image { path, width } => {
+            let image = image_xobject(doc, Path::new(path))?;
+            let width = width.unwrap_or(image.width as f32).max(1.0);
+            let height = width * image.height as f32 / image.width.max(1) as f32;
+            // Images fill the unit square
+            let operations = vec![
+                Operation::new(
+                    "cm",
+                    vec![
+                        width.into(),
+                        0.into(),
+                        0.into(),
+                        height.into(),
+                        (-width / 2.0).into(),
+                        (-height / 2.0).into(),
+                    ],
+                ),
+                Operation::new("Do", vec![Object::Name(vec![])]),
+            ];
+            Ok((
+                Drawing {
+                    operations,
+                    width,
+                    height,
+                },
+                image.id,
+            ))
+        }
+    }
+}
+
+/// Where the centre of a `width` x `height` stamp goes on a page of the given
+/// visible size.
+pub fn anchor(
+    position: StampPosition,
+    margin: f32,
+    size: (f32, f32),
+    page: (f32, f32),
+) -> (f32, f32) {
+    let (width, height) = size;
+    let (page_width, page_height) = page;
+    let left = margin + width / 2.0;
+    let right = page_width - margin - width / 2.0;
+    let bottom = margin + height / 2.0;
+    let top = page_height - margin - height / 2.0;
+    let (middle, centre) = (page_height / 2.0, page_width / 2.0);
+    match position {
+        StampPosition::TopLeft => (left, top),
+        StampPosition::Top => (centre, top),
+        StampPosition::TopRight => (right, top),
+        StampPosition::Left => (left, middle),
+        StampPosition::Center => (centre, middle),
+        StampPosition::Right => (right, middle),
+        StampPosition::BottomLeft => (left, bottom),
+        StampPosition::Bottom => (centre, bottom),
+        StampPosition::BottomRight => (right, bottom),
+    }
+}
+
+fn stamp(doc: &mut Document, pages: &[i32], options: &StampOptions) -> Result<(), String> {
+    let (drawing, resource) = prepare(doc, &options.content)?;
+    let opacity = options.opacity.clamp(0.0, 1.0);
+    let state = doc.add_object(dictionary! {
+        "Type" => "ExtGState",
+        "ca" => opacity,
+        "CA" => opacity,
+    });
+    let (category, prefix) = match options.content {
+        StampContent::Text { .. } => (b"Font".as_slice(), "StampF"),
+        StampContent::Image { .. } => (b"XObject".as_slice(), "StampIm"),
+    };
+    let radians = options.rotation.to_radians();
+    let rotate: Transform = [
+        radians.cos(),
+        radians.sin(),
+        -radians.sin(),
+        radians.cos(),
+        0.0,
+        0.0,
+    ];
+
+    let ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    for &page in pages {
+        let page_id = ids[page as usize];
+        let (upright, page_size) = upright_transform(doc, page_id);
+        let (x, y) = anchor(
+            options.position,
+            options.margin,
+            (drawing.width, drawing.height),
+            page_size,
+        );
+        let placement = concat(concat(rotate, [1.0, 0.0, 0.0, 1.0, x, y]), upright);
+
+        let state_name = add_resource(doc, page_id, b"ExtGState", "StampGS", state.into())?;
+        let name = add_resource(doc, page_id, category, prefix, resource.into())?;
+        let mut operations = vec![
+            Operation::new("q", vec![]),
+            Operation::new("gs", vec![Object::Name(state_name.into_bytes())]),
+            Operation::new("cm", placement.iter().map(|&v| v.into()).collect()),
+        ];
+        // Point the drawing's font or image operand at this page's name
+        operations.extend(drawing.operations.iter().cloned().map(|mut op| {
+            if op.operator == "Tf" || op.operator == "Do" {
+                op.operands[0] = Object::Name(name.clone().into_bytes());
+            }
+            op
+        }));
+        operations.push(Operation::new("Q", vec![]));
+        append_content(doc, page_id, operations)?;
+    }
+    Ok(())
+}
+
+/// Draws a text or image stamp on the selected pages and saves the result
+/// as a new file. The open document is left as it is.
+#[tauri::command]
+pub async fn stamp_pages(
+    app: AppHandle,
+    doc_id: DocId,
+    options: StampOptions,
+    output_path: String,
+    store: State<'_, DocumentStore>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<WrittenDocument, String> {
+    scope::check_write(&app, Path::new(&output_path))?;
+    match &options.content {
+        StampContent::Text {
+            text, font_size, ..
+        } => {
+            if text.trim().is_empty() || *font_size <= 0.0 {
+                return Err("Stamp text needs some text and a positive font size".into());
+            }
+        }
+        StampContent::Image { path, .. } => scope::check(&app, Path::new(path))?,
+    }
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Modify).await?;
+    let pages = match &options.ranges {
+        Some(ranges) => parse_page_ranges(ranges, doc.page_count)?,
+        None => (0..doc.page_count).collect(),
+    };
+    blocking(move || {
+        let mut stamped = doc.with_objects(|objects| {
+            let mut stamped = objects.clone();
+            stamp(&mut stamped, &pages, &options)?;
+            Ok(stamped)
+        })?;
+        write_document(&mut stamped, Path::new(&output_path))?;
+        eprintln!(
+            "Stamped {} pages of document {} into {}",
+            pages.len(),
+            doc_id,
+            output_path
+        );
+        Ok(WrittenDocument {
+            path: output_path,
+            page_count: doc.page_count,
+        })
+    })
+    .await
+}
@@ -0,0 +1,524 @@
+// Tags and collections to organize the library.
+//
+// Tags are names the user gives documents of the library, any number each,
+// matched ignoring case. A collection is a saved filter of the library, by
+// tags, author or folder, so documents join it as they're tagged or indexed;
+// `list_library` shows one given its id. Both are kept in the library's
+// database (see `library.rs`) by the path of the document, so they last when
+// a changed file is indexed again; the tags of documents that leave the
+// library, as when their folder is removed from it, go with them. Changes
+// are sent to the windows as "library-changed" events, like indexing's.
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter};
+
+use crate::library::{self, LibraryQuery};
+use crate::pdf::blocking;
+
+pub const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS tags (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE COLLATE NOCASE
+    );
+    CREATE TABLE IF NOT EXISTS document_tags (
+        path TEXT NOT NULL,
+        tag INTEGER NOT NULL,
+        PRIMARY KEY (path, tag)
+    );
+    CREATE INDEX IF NOT EXISTS document_tags_tag ON document_tags (tag);
+    CREATE TABLE IF NOT EXISTS collections (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        rule TEXT NOT NULL
+    );
+";
+const MAX_NAME_CHARS: usize = 100;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tag {
+    pub id: i64,
+    pub name: String,
+    /// Documents with the tag
+    pub documents: usize,
+}
+
+/// Which documents a collection holds. Empty, it holds the whole library.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CollectionRule {
+    pub tags: Vec<String>,
+    /// Documents with any of the tags belong, rather than only those with all
+    pub any_tag: bool,
+    /// Documents by an author with this in their name, ignoring case
+    pub author: Option<String>,
+    /// Documents in this library folder
+    pub folder: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Collection {
+    pub id: i64,
+    pub name: String,
+    pub rule: CollectionRule,
+    /// Documents it holds now
+    pub documents: usize,
+}
+
+/// A document's tags, by name, as settings archives keep them (see
+/// `transfer.rs`).
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedDocumentTags {
+    pub path: String,
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedCollection {
+    pub name: String,
+    pub rule: CollectionRule,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ArchivedTags {
+    /// Every tag, those no document has included
+    pub tags: Vec<String>,
+    pub document_tags: Vec<ArchivedDocumentTags>,
+    pub collections: Vec<ArchivedCollection>,
+}
+
+fn checked_name(name: &str, what: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(format!("A {} needs a name", what));
+    }
+    if name.chars().count() > MAX_NAME_CHARS {
+        return Err(format!(
+            "The name of a {} can have {} characters at most",
+            what, MAX_NAME_CHARS
+        ));
+    }
+    Ok(name.to_string())
+}
+
+fn changed(app: &AppHandle) {
+    let _ = app.emit("library-changed", ());
+}
+
+/// Drops the tags of documents no longer in the library.
+pub fn prune(db: &Connection) -> Result<(), String> {
+    db.execute(
+        "DELETE FROM document_tags WHERE path NOT IN (SELECT path FROM documents)",
+        params![],
+    )
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+/// The rule of a collection.
+pub fn collection_rule(db: &Connection, id: i64) -> Result<CollectionRule, String> {
+    let rule = db
+        .query_row(
+            "SELECT rule FROM collections WHERE id = ?1",
+            params![id],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("No collection {}", id))?;
+    serde_json::from_str(&rule).map_err(|e| format!("Collection {} is damaged: {}", id, e))
+}
+
+/// Every tag and collection, and the tags of every document.
+pub fn to_archive(db: &Connection) -> Result<ArchivedTags, String> {
+    let tags = tags(db)?.into_iter().map(|tag| tag.name).collect();
+    let tagged: Vec<(String, String)> = {
+        let mut statement = db
+            .prepare(
+                "SELECT x.path, g.name FROM document_tags x JOIN tags g ON g.id = x.tag
+                 ORDER BY x.path, g.name COLLATE NOCASE",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = statement
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+    };
+    let mut document_tags: Vec<ArchivedDocumentTags> = Vec::new();
+    for (path, tag) in tagged {
+        match document_tags.last_mut() {
+            Some(document) if document.path == path => document.tags.push(tag),
+            _ => document_tags.push(ArchivedDocumentTags {
+                path,
+                tags: vec![tag],
+            }),
+        }
+    }
+    let collections = collections(db)?
+        .into_iter()
+        .map(|collection| ArchivedCollection {
+            name: collection.name,
+            rule: collection.rule,
+        })
+        .collect();
+    Ok(ArchivedTags {
+        tags,
+        document_tags,
+        collections,
+    })
+}
+
+/// Adds archived tags and collections to those there are. Documents may not
+/// be indexed yet; collections named like one there is already are left out.
+/// Returns the numbers of tags and of collections added.
+pub fn from_archive(
+    app: &AppHandle,
+    db: &mut Connection,
+    archived: ArchivedTags,
+) -> Result<(usize, usize), String> {
+    let transaction = db.transaction().map_err(|e| e.to_string())?;
+    let mut tags = 0;
+    let named = archived
+        .document_tags
+        .iter()
+        .flat_map(|document| &document.tags);
+    for name in archived.tags.iter().chain(named) {
+        let Ok(name) = checked_name(name, "tag") else {
+            continue;
+        };
+        tags += transaction
+            .execute(
+                "INSERT OR IGNORE INTO tags (name) VALUES (?1)",
+                params![name],
+            )
+            .map_err(|e| e.to_string())?;
+    }
+    for document in &archived.document_tags {
+        for name in &document.tags {
+            transaction
+                .execute(
+                    "INSERT OR IGNORE INTO document_tags (path, tag)
+                     SELECT ?1, id FROM tags WHERE name = ?2",
+                    params![document.path, name.trim()],
+                )
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    let mut collections = 0;
+    for collection in archived.collections {
+        let Ok(name) = checked_name(&collection.name, "collection") else {
+            continue;
+        };
+        let json = serde_json::to_string(&collection.rule).map_err(|e| e.to_string())?;
+        collections += transaction
+            .execute(
+                "INSERT INTO collections (name, rule) SELECT ?1, ?2 WHERE NOT EXISTS
+                     (SELECT 1 FROM collections WHERE name = ?1 COLLATE NOCASE)",
+                params![name, json],
+            )
+            .map_err(|e| e.to_string())?;
+    }
+    transaction.commit().map_err(|e| e.to_string())?;
+    changed(app);
+    Ok((tags, collections))
+}
+
+fn tags(db: &Connection) -> Result<Vec<Tag>, String> {
+    let mut statement = db
+        .prepare(
+            "SELECT g.id, g.name, COUNT(x.path) FROM tags g
+             LEFT JOIN document_tags x ON x.tag = g.id
+             GROUP BY g.id ORDER BY g.name COLLATE NOCASE",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = statement
+        .query_map(params![], |row| {
+            Ok(Tag {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                documents: row.get::<_, i64>(2)? as usize,
+            })
+        })
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+}
+
+fn collection(db: &Connection, id: i64, name: String) -> Result<Collection, String> {
+    let query = LibraryQuery {
+        collection: Some(id),
+        limit: Some(1),
+        ..Default::default()
+    };
+    Ok(Collection {
+        id,
+        name,
+        rule: collection_rule(db, id)?,
+        documents: library::list(db, &query)?.total,
+    })
+}
+
+fn collections(db: &Connection) -> Result<Vec<Collection>, String> {
+    let named: Vec<(i64, String)> = {
+        let mut statement = db
+            .prepare("SELECT id, name FROM collections ORDER BY name COLLATE NOCASE")
+            .map_err(|e| e.to_string())?;
+        let rows = statement
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+    };
+    named
+        .into_iter()
+        .map(|(id, name)| collection(db, id, name))
+        .collect()
+}
+
+// Every path must be a document of the library
+fn check_documents(db: &Connection, paths: &[String]) -> Result<(), String> {
+    for path in paths {
+        let known = db
+            .query_row(
+                "SELECT 1 FROM documents WHERE path = ?1",
+                params![path],
+                |_| Ok(()),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if known.is_none() {
+            return Err(format!("{} isn't in the library", path));
+        }
+    }
+    Ok(())
+}
+
+/// Every tag, by name, with the number of documents that have it.
+#[tauri::command]
+pub async fn get_tags(app: AppHandle) -> Result<Vec<Tag>, String> {
+    blocking(move || tags(&library::open_database(&app)?)).await
+}
+
+/// The tags of a document of the library, by name.
+#[tauri::command]
+pub async fn get_document_tags(app: AppHandle, path: String) -> Result<Vec<String>, String> {
+    blocking(move || {
+        let db = library::open_database(&app)?;
+        let mut statement = db
+            .prepare(
+                "SELECT g.name FROM document_tags x JOIN tags g ON g.id = x.tag
+                 WHERE x.path = ?1 ORDER BY g.name COLLATE NOCASE",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = statement
+            .query_map(params![path], |row| row.get::<_, String>(0))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+    })
+    .await
+}
+
+/// Gives documents of the library tags, making the tags that are new.
+#[tauri::command]
+pub async fn tag_documents(
+    app: AppHandle,
+    paths: Vec<String>,
+    tags: Vec<String>,
+) -> Result<Vec<Tag>, String> {
+    let names = tags
+        .iter()
+        .map(|name| checked_name(name, "tag"))
+        .collect::<Result<Vec<_>, _>>()?;
+    blocking(move || {
+        let mut db = library::open_database(&app)?;
+        check_documents(&db, &paths)?;
+        let transaction = db.transaction().map_err(|e| e.to_string())?;
+        for name in &names {
+            transaction
+                .execute(
+                    "INSERT OR IGNORE INTO tags (name) VALUES (?1)",
+                    params![name],
+                )
+                .map_err(|e| e.to_string())?;
+            for path in &paths {
+                transaction
+                    .execute(
+                        "INSERT OR IGNORE INTO document_tags (path, tag)
+                         SELECT ?1, id FROM tags WHERE name = ?2",
+                        params![path, name],
+                    )
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        transaction.commit().map_err(|e| e.to_string())?;
+        changed(&app);
+        self::tags(&db)
+    })
+    .await
+}
+
+/// Takes tags off documents. The tags remain, for other documents.
+#[tauri::command]
+pub async fn untag_documents(
+    app: AppHandle,
+    paths: Vec<String>,
+    tags: Vec<String>,
+) -> Result<Vec<Tag>, String> {
+    blocking(move || {
+        let mut db = library::open_database(&app)?;
+        let transaction = db.transaction().map_err(|e| e.to_string())?;
+        for name in &tags {
+            for path in &paths {
+                transaction
+                    .execute(
+                        "DELETE FROM document_tags WHERE path = ?1
+                         AND tag = (SELECT id FROM tags WHERE name = ?2)",
+                        params![path, name.trim()],
+                    )
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        transaction.commit().map_err(|e| e.to_string())?;
+        changed(&app);
+        self::tags(&db)
+    })
+    .await
+}
+
+/// Renames a tag, in the collections that use it too.
+#[tauri::command]
+pub async fn rename_tag(app: AppHandle, id: i64, name: String) -> Result<Vec<Tag>, String> {
+    let name = checked_name(&name, "tag")?;
+    blocking(move || {
+        let mut db = library::open_database(&app)?;
+        let old = db
+            .query_row("SELECT name FROM tags WHERE id = ?1", params![id], |row| {
+                row.get::<_, String>(0)
+            })
+            .optional()
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("No tag {}", id))?;
+        let taken = db
+            .query_row(
+                "SELECT 1 FROM tags WHERE name = ?1 AND id != ?2",
+                params![name, id],
+                |_| Ok(()),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if taken.is_some() {
+            return Err(format!("There's a tag named {} already", name));
+        }
+        let renamed: Vec<(i64, CollectionRule)> = collections(&db)?
+            .into_iter()
+            .filter(|collection| {
+                collection
+                    .rule
+                    .tags
+                    .iter()
+                    .any(|tag| tag.eq_ignore_ascii_case(&old))
+            })
+            .map(|mut collection| {
+                for tag in &mut collection.rule.tags {
+                    if tag.eq_ignore_ascii_case(&old) {
+                        *tag = name.clone();
+                    }
+                }
+                (collection.id, collection.rule)
+            })
+            .collect();
+        let transaction = db.transaction().map_err(|e| e.to_string())?;
+        transaction
+            .execute("UPDATE tags SET name = ?2 WHERE id = ?1", params![id, name])
+            .map_err(|e| e.to_string())?;
+        for (collection, rule) in renamed {
+            let rule = serde_json::to_string(&rule).map_err(|e| e.to_string())?;
+            transaction
+                .execute(
+                    "UPDATE collections SET rule = ?2 WHERE id = ?1",
+                    params![collection, rule],
+                )
+                .map_err(|e| e.to_string())?;
+        }
+        transaction.commit().map_err(|e| e.to_string())?;
+        changed(&app);
+        tags(&db)
+    })
+    .await
+}
+
+/// Deletes a tag, taking it off every document.
+#[tauri::command]
+pub async fn delete_tag(app: AppHandle, id: i64) -> Result<Vec<Tag>, String> {
+    blocking(move || {
+        let mut db = library::open_database(&app)?;
+        let transaction = db.transaction().map_err(|e| e.to_string())?;
+        transaction
+            .execute("DELETE FROM document_tags WHERE tag = ?1", params![id])
+            .and_then(|_| transaction.execute("DELETE FROM tags WHERE id = ?1", params![id]))
+            .map_err(|e| e.to_string())?;
+        transaction.commit().map_err(|e| e.to_string())?;
+        changed(&app);
+        tags(&db)
+    })
+    .await
+}
+
+/// Every collection, by name, with the number of documents it holds.
+#[tauri::command]
+pub async fn get_collections(app: AppHandle) -> Result<Vec<Collection>, String> {
+    blocking(move || collections(&library::open_database(&app)?)).await
+}
+
+/// Makes a collection, or changes the one with `id`.
+#[tauri::command]
+pub async fn save_collection(
+    app: AppHandle,
+    id: Option<i64>,
+    name: String,
+    rule: CollectionRule,
+) -> Result<Collection, String> {
+    let name = checked_name(&name, "collection")?;
+    blocking(move || {
+        let db = library::open_database(&app)?;
+        let json = serde_json::to_string(&rule).map_err(|e| e.to_string())?;
+        let id = match id {
+            Some(id) => {
+                let updated = db
+                    .execute(
+                        "UPDATE collections SET name = ?2, rule = ?3 WHERE id = ?1",
+                        params![id, name, json],
+                    )
+                    .map_err(|e| e.to_string())?;
+                if updated == 0 {
+                    return Err(format!("No collection {}", id));
+                }
+                id
+            }
+            None => {
+                db.execute(
+                    "INSERT INTO collections (name, rule) VALUES (?1, ?2)",
+                    params![name, json],
+                )
+                .map_err(|e| e.to_string())?;
+                db.last_insert_rowid()
+            }
+        };
+        collection(&db, id, name)
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn delete_collection(app: AppHandle, id: i64) -> Result<(), String> {
+    blocking(move || {
+        library::open_database(&app)?
+            .execute("DELETE FROM collections WHERE id = ?1", params![id])
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    })
+    .await
+}
@@ -0,0 +1,365 @@
+// Text extraction.
+//
+// The text layer is built per page on demand, so the frontend never has to hold
+// the text of the whole document. Scanned pages have no text layer; the text
+// recognized in them (see `ocr.rs`) stands in for it.
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+
+use crate::engine::Engine;
+use crate::ocr;
+use crate::pdf::{Bounds, DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+
+// Minimum whitespace (in points) that separates two regions of a page
+const MIN_CUT_GAP: f32 = 6.0;
+
+/// One line of text with the box of every character in it.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TextRun {
+    pub text: String,
+    pub bounds: Bounds,
+    pub font_size: f32,
+    /// True for vertical writing mode
+    pub vertical: bool,
+    /// One box per `char` of `text`, in the same order
+    pub glyphs: Vec<Bounds>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageTextLayout {
+    pub width: f32,
+    pub height: f32,
+    pub runs: Vec<TextRun>,
+    /// True if the runs were recognized in a scanned page (see `ocr.rs`)
+    pub recognized: bool,
+}
+
+/// Collects the text lines of a parsed page.
+pub fn page_runs(text_page: &mupdf::TextPage) -> Vec<TextRun> {
+    let mut runs = Vec::new();
+    for block in text_page.blocks() {
+        if block.r#type() != mupdf::TextBlockType::Text {
+            continue;
+        }
+        for line in block.lines() {
+            let mut text = String::new();
+            let mut glyphs = Vec::new();
+            let mut font_size: f32 = 0.0;
+            for ch in line.chars() {
+                let Some(c) = ch.char() else {
+                    continue;
+                };
+                text.push(c);
+                glyphs.push(Bounds::from(ch.quad()));
+                font_size = font_size.max(ch.size());
+            }
+            if text.trim().is_empty() {
+                continue;
+            }
+            runs.push(TextRun {
+                text,
+                bounds: line.bounds().into(),
+                font_size,
+                vertical: line.wmode() != 0,
+                glyphs,
+            });
+        }
+    }
+    runs
+}
+
+/// Returns the text lines of a page, or the text recognized in it if it's a
+/// scan without a text layer.
+#[tauri::command]
+pub async fn get_page_text_layout(
+    app: AppHandle,
+    doc_id: DocId,
+    page: i32,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+) -> Result<PageTextLayout, String> {
+    let doc = store.get(doc_id)?;
+    doc.check_page(page)?;
+    let file = ocr::database_file(&app)?;
+    engine
+        .run(move |worker| {
+            let loaded = worker
+                .document(&doc)?
+                .load_page(page)
+                .map_err(|e| e.to_string())?;
+            let bounds = loaded.bounds().map_err(|e| e.to_string())?;
+            let text_page = loaded
+                .to_text_page(mupdf::TextPageOptions::PRESERVE_WHITESPACE)
+                .map_err(|e| e.to_string())?;
+            let mut runs = page_runs(&text_page);
+            let mut recognized = false;
+            if runs.is_empty() {
+                if let Some(lines) = ocr::recognized_page(&file, doc.fingerprint(), page) {
+                    runs = lines;
+                    recognized = true;
+                }
+            }
+            Ok(PageTextLayout {
+                width: bounds.width(),
+                height: bounds.height(),
+                runs,
+                recognized,
+            })
+        })
+        .await
+}
+
+/// Inclusive, zero-based range of pages.
+#[derive(Deserialize, Clone, Copy)]
+pub struct PageRange {
+    pub start: i32,
+    pub end: i32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageText {
+    pub page: i32,
+    pub text: String,
+}
+
+/// A block of text, such as a paragraph or heading.
+pub struct Paragraph {
+    pub lines: Vec<String>,
+    /// Average size of its characters
+    pub font_size: f32,
+}
+
+impl Paragraph {
+    /// The lines joined with end-of-line hyphenation undone.
+    pub fn text(&self) -> String {
+        join_lines(&self.lines)
+    }
+}
+
+// A text block in "reading space", where lines run left to right and stack
+// top to bottom regardless of how the page is rotated.
+struct Block {
+    x0: f32,
+    y0: f32,
+    x1: f32,
+    y1: f32,
+    paragraph: Paragraph,
+}
+
+/// Returns the page text in reading order.
+pub fn page_text(text_page: &mupdf::TextPage) -> String {
+    page_paragraphs(text_page)
+        .iter()
+        .map(Paragraph::text)
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Returns the text blocks of a page in reading order.
+///
+/// Blocks are ordered with a recursive XY-cut: the page is split at
+/// horizontal whitespace bands first (headers, paragraphs) and then at
+/// vertical gaps (columns), so multi-column layouts read column by column.
+pub fn page_paragraphs(text_page: &mupdf::TextPage) -> Vec<Paragraph> {
+    // Work out the dominant text direction so rotated pages read correctly
+    let mut votes = [0usize; 4];
+    let mut raw = Vec::new();
+    for block in text_page.blocks() {
+        if block.r#type() != mupdf::TextBlockType::Text {
+            continue;
+        }
+        let mut lines = Vec::new();
+        let (mut size, mut count) = (0.0, 0);
+        for line in block.lines() {
+            let chars: Vec<_> = line.chars().collect();
+            if let (Some(first), Some(last)) = (chars.first(), chars.last()) {
+                let (a, b) = (first.origin(), last.origin());
+                votes[direction(b.x - a.x, b.y - a.y)] += chars.len();
+            }
+            size += chars.iter().map(|c| c.size()).sum::<f32>();
+            count += chars.len();
+            let text: String = chars.iter().filter_map(|c| c.char()).collect();
+            lines.push(text);
+        }
+        let paragraph = Paragraph {
+            lines,
+            font_size: if count > 0 { size / count as f32 } else { 0.0 },
+        };
+        raw.push((block.bounds(), paragraph));
+    }
+
+    let dir = (0..4).max_by_key(|&d| votes[d]).unwrap_or(0);
+    let mut blocks: Vec<Block> = raw
+        .into_iter()
+        .filter(|(_, paragraph)| !paragraph.text().trim().is_empty())
+        .map(|(rect, paragraph)| {
+            let (ax, ay) = to_reading_space(dir, rect.x0, rect.y0);
+            let (bx, by) = to_reading_space(dir, rect.x1, rect.y1);
+            Block {
+                x0: ax.min(bx),
+                y0: ay.min(by),
+                x1: ax.max(bx),
+                y1: ay.max(by),
+                paragraph,
+            }
+        })
+        .collect();
+
+    let mut ordered = Vec::new();
+    xy_cut(&mut blocks, &mut ordered);
+    ordered
+}
+
+// 0 = left to right, 1 = top to bottom, 2 = right to left, 3 = bottom to top
+fn direction(dx: f32, dy: f32) -> usize {
+    if dx.abs() >= dy.abs() {
+        if dx >= 0.0 {
+            0
+        } else {
+            2
+        }
+    } else if dy >= 0.0 {
+        1
+    } else {
+        3
+    }
+}
+
+fn to_reading_space(dir: usize, x: f32, y: f32) -> (f32, f32) {
+    match dir {
+        1 => (y, -x),
+        2 => (-x, -y),
+        3 => (-y, x),
+        _ => (x, y),
+    }
+}
+
+// Joins the lines of a block, undoing end-of-line hyphenation. Only a hyphen
+// after a letter and before a lower case one is taken for it; others, like
+// those of "1990-" or before a name, are kept
+fn join_lines(lines: &[String]) -> String {
+    let mut out = String::new();
+    for line in lines {
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        let hyphenated = out
+            .strip_suffix('-')
+            .and_then(|start| start.chars().next_back())
+            .is_some_and(char::is_alphabetic)
+            && line
+                .trim_start()
+                .chars()
+                .next()
+                .is_some_and(char::is_lowercase);
+        if hyphenated {
+            out.pop();
+            out.push_str(line.trim_start());
+            continue;
+        }
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(line);
+    }
+    out
+}
+
+fn xy_cut(blocks: &mut Vec<Block>, out: &mut Vec<Paragraph>) {
+    if blocks.len() <= 1 {
+        out.extend(blocks.drain(..).map(|b| b.paragraph));
+        return;
+    }
+
+    for horizontal in [true, false] {
+        let groups = split_at_gaps(blocks, horizontal);
+        if groups.len() > 1 {
+            for mut group in groups {
+                xy_cut(&mut group, out);
+            }
+            return;
+        }
+    }
+
+    // No clean cut: fall back to top-to-bottom, left-to-right
+    blocks.sort_by(|a, b| a.y0.total_cmp(&b.y0).then(a.x0.total_cmp(&b.x0)));
+    out.extend(blocks.drain(..).map(|b| b.paragraph));
+}
+
+// Splits blocks into bands separated by whitespace along one axis
+fn split_at_gaps(blocks: &mut Vec<Block>, horizontal: bool) -> Vec<Vec<Block>> {
+    let span = |b: &Block| {
+        if horizontal {
+            (b.y0, b.y1)
+        } else {
+            (b.x0, b.x1)
+        }
+    };
+    blocks.sort_by(|a, b| span(a).0.total_cmp(&span(b).0));
+
+    let mut groups: Vec<Vec<Block>> = Vec::new();
+    let mut end = f32::NEG_INFINITY;
+    for block in blocks.drain(..) {
+        let (start, stop) = span(&block);
+        match groups.last_mut() {
+            Some(group) if start < end + MIN_CUT_GAP => group.push(block),
+            _ => groups.push(vec![block]),
+        }
+        end = end.max(stop);
+    }
+    groups
+}
+
+/// Returns the plain text of each page in `page_range` (all pages if omitted).
+#[tauri::command]
+pub async fn extract_text(
+    app: AppHandle,
+    doc_id: DocId,
+    page_range: Option<PageRange>,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<Vec<PageText>, String> {
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Copy).await?;
+    let (start, end) = match page_range {
+        Some(range) => {
+            doc.check_page(range.start)?;
+            doc.check_page(range.end)?;
+            (range.start, range.end)
+        }
+        None => (0, doc.page_count - 1),
+    };
+    let file = ocr::database_file(&app)?;
+
+    engine
+        .run(move |worker| {
+            let handle = worker.document(&doc)?;
+            let mut pages = Vec::new();
+            for index in start..=end {
+                let text_page = handle
+                    .load_page(index)
+                    .and_then(|p| p.to_text_page(mupdf::TextPageOptions::empty()))
+                    .map_err(|e| e.to_string())?;
+                let mut text = page_text(&text_page);
+                if text.trim().is_empty() {
+                    if let Some(lines) = ocr::recognized_page(&file, doc.fingerprint(), index) {
+                        text = lines
+                            .into_iter()
+                            .map(|line| line.text)
+                            .collect::<Vec<_>>()
+                            .join("\n");
+                    }
+                }
+                pages.push(PageText { page: index, text });
+            }
+            Ok(pages)
+        })
+        .await
+}
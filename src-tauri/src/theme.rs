@@ -0,0 +1,60 @@
+// Following the system's light or dark appearance.
+//
+// The window reports the system theme when it changes, which is passed on to
+// the frontend as a `theme-changed` event; `get_system_theme` tells the
+// current one at startup. Both carry the color mode to render pages in, so
+// night mode (see `colors.rs`) comes on and off with dark mode.
+
+use serde::Serialize;
+use tauri::{Emitter, Theme, Window, WindowEvent};
+
+use crate::colors::ColorMode;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Appearance {
+    Light,
+    Dark,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SystemTheme {
+    pub appearance: Appearance,
+    /// How pages are rendered to match
+    pub color_mode: ColorMode,
+}
+
+impl From<Theme> for SystemTheme {
+    fn from(theme: Theme) -> Self {
+        // Themes added to Tauri later count as light
+        let appearance = match theme {
+            Theme::Dark => Appearance::Dark,
+            _ => Appearance::Light,
+        };
+        SystemTheme {
+            appearance,
+            color_mode: match appearance {
+                Appearance::Dark => ColorMode::Night,
+                Appearance::Light => ColorMode::Normal,
+            },
+        }
+    }
+}
+
+/// The system's appearance, as the calling window sees it.
+#[tauri::command]
+pub fn get_system_theme(window: Window) -> Result<SystemTheme, String> {
+    window
+        .theme()
+        .map(SystemTheme::from)
+        .map_err(|e| format!("Failed to read the system theme: {}", e))
+}
+
+pub fn handle_window_event(window: &Window, event: &WindowEvent) {
+    if let WindowEvent::ThemeChanged(theme) = event {
+        let theme = SystemTheme::from(*theme);
+        eprintln!("System appearance changed to {:?}", theme.appearance);
+        let _ = window.emit("theme-changed", theme);
+    }
+}
@@ -0,0 +1,126 @@
+// Page thumbnails with an on-disk cache.
+//
+// Thumbnails are stored as PNG files under
+// `<app data>/thumbnails/<file hash>-<mtime>/`, so re-opening a large document
+// only renders pages that have never been shown in the sidebar before. Those
+// of edits that aren't saved yet are rendered every time. Whenever a file gets
+// a directory of its own, those added to longest ago are deleted until the
+// cache fits `THUMBNAIL_CACHE_BUDGET`; that also clears out the ones of
+// versions of a file that have since been saved over.
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use tauri::ipc::Response;
+use tauri::{AppHandle, Manager, State};
+
+use crate::engine::Engine;
+use crate::pdf::{encode_png, DocId, DocumentStore};
+
+const MIN_THUMBNAIL_PX: u32 = 16;
+const MAX_THUMBNAIL_PX: u32 = 1024;
+const THUMBNAIL_CACHE_BUDGET: u64 = 256 * 1024 * 1024;
+
+fn thumbnail_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("thumbnails"))
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))
+}
+
+// Deletes the cache directories added to longest ago, other than `keep`, until
+// the cache fits the budget
+fn prune(root: &Path, keep: &Path) {
+    let Ok(entries) = std::fs::read_dir(root) else {
+        return;
+    };
+    let mut dirs: Vec<(SystemTime, u64, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let modified = entry.metadata().ok()?.modified().ok()?;
+            let size = std::fs::read_dir(entry.path())
+                .ok()?
+                .flatten()
+                .filter_map(|file| file.metadata().ok())
+                .map(|metadata| metadata.len())
+                .sum();
+            Some((modified, size, entry.path()))
+        })
+        .collect();
+    let mut used: u64 = dirs.iter().map(|(_, size, _)| size).sum();
+    dirs.sort();
+    for (_, size, dir) in dirs {
+        if used <= THUMBNAIL_CACHE_BUDGET {
+            break;
+        }
+        if dir == keep {
+            continue;
+        }
+        match std::fs::remove_dir_all(&dir) {
+            Ok(()) => used -= size,
+            Err(e) => eprintln!("Failed to delete thumbnails {}: {}", dir.display(), e),
+        }
+    }
+}
+
+/// Returns a PNG preview of `page` whose longest side is `max_px` pixels.
+#[tauri::command]
+pub async fn get_thumbnail(
+    app: AppHandle,
+    doc_id: DocId,
+    page: i32,
+    max_px: u32,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+) -> Result<Response, String> {
+    let doc = store.get(doc_id)?;
+    doc.check_page(page)?;
+    let max_px = max_px.clamp(MIN_THUMBNAIL_PX, MAX_THUMBNAIL_PX);
+    let root = thumbnail_dir(&app)?;
+
+    let png = engine
+        .run(move |worker| {
+            // Hashing happens here so a large file doesn't block the IPC thread
+            let file = doc
+                .cache_key()
+                .map(|key| root.join(key).join(format!("{}-{}.png", page, max_px)));
+            if let Some(png) = file.as_ref().and_then(|file| std::fs::read(file).ok()) {
+                return Ok(png);
+            }
+
+            let page = worker
+                .document(&doc)?
+                .load_page(page)
+                .map_err(|e| e.to_string())?;
+            let bounds = page.bounds().map_err(|e| e.to_string())?;
+            let longest = bounds.width().max(bounds.height()).max(1.0);
+            let scale = max_px as f32 / longest;
+            let pixmap = page
+                .to_pixmap(
+                    &mupdf::Matrix::new_scale(scale, scale),
+                    &mupdf::Colorspace::device_rgb(),
+                    0.0,
+                    true,
+                )
+                .map_err(|e| e.to_string())?;
+            let png = encode_png(&pixmap)?;
+
+            // A failed cache write only costs a re-render next time
+            if let Some(file) = file {
+                let dir = file.parent().unwrap();
+                let new = !dir.exists();
+                let written =
+                    std::fs::create_dir_all(dir).and_then(|_| std::fs::write(&file, &png));
+                if let Err(e) = written {
+                    eprintln!("Failed to cache thumbnail {}: {}", file.display(), e);
+                }
+                if new {
+                    prune(&root, dir);
+                }
+            }
+            Ok(png)
+        })
+        .await?;
+
+    Ok(Response::new(png))
+}
@@ -0,0 +1,160 @@
+// Tiled rendering for very large pages.
+//
+// Architectural and CAD drawings can be several metres wide, so rendering the
+// whole page at a readable zoom is not an option. The frontend instead asks for
+// the tiles that are currently visible and the backend keeps recently rendered
+// tiles in an LRU cache.
+
+use std::sync::Mutex;
+
+use serde::Deserialize;
+use tauri::ipc::Response;
+use tauri::{State, Window};
+
+use crate::colors::{self, ColorMode};
+use crate::display;
+use crate::engine::Engine;
+use crate::lru::{CacheStats, LruCache};
+use crate::pdf::{encode_bitmap, zoom_key, DocId, DocumentStore};
+
+const TILE_CACHE_BUDGET: usize = 128 * 1024 * 1024;
+const MAX_TILE_SIZE: i32 = 4096;
+
+/// A tile in device pixels at the effective scale (see `render_tile`),
+/// relative to the top-left corner of the rendered page.
+#[derive(Deserialize, Clone, Copy)]
+pub struct TileRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+#[derive(Hash, PartialEq, Eq, Clone)]
+struct TileKey {
+    doc_id: DocId,
+    page: i32,
+    // Scale in thousandths so it can be hashed
+    zoom: u32,
+    tile: (i32, i32, i32, i32),
+    mode: ColorMode,
+}
+
+pub struct TileCache {
+    tiles: Mutex<LruCache<TileKey, Vec<u8>>>,
+}
+
+impl Default for TileCache {
+    fn default() -> Self {
+        TileCache {
+            tiles: Mutex::new(LruCache::new(TILE_CACHE_BUDGET)),
+        }
+    }
+}
+
+impl TileCache {
+    pub fn memory_used(&self) -> usize {
+        self.tiles.lock().unwrap().used()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.tiles.lock().unwrap().stats()
+    }
+
+    /// Forgets every tile of a document, e.g. when it is closed or edited.
+    pub fn clear_document(&self, doc_id: DocId) {
+        self.tiles
+            .lock()
+            .unwrap()
+            .retain(|key| key.doc_id != doc_id);
+    }
+}
+
+/// Renders one tile of a page.
+///
+/// As with `render_page`, `scale` is multiplied by the device pixel ratio and
+/// the response uses the `[width][height][rgba...]` layout.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn render_tile(
+    window: Window,
+    doc_id: DocId,
+    page: i32,
+    rect: TileRect,
+    scale: f32,
+    color_mode: Option<ColorMode>,
+    device_pixel_ratio: Option<f32>,
+    store: State<'_, DocumentStore>,
+    engine: State<'_, Engine>,
+    cache: State<'_, TileCache>,
+) -> Result<Response, String> {
+    let doc = store.get(doc_id)?;
+    doc.check_page(page)?;
+    let scale = scale * display::device_pixel_ratio(&window, device_pixel_ratio);
+    if !(scale > 0.0 && scale <= 64.0) {
+        return Err(format!("Invalid render scale {}", scale));
+    }
+    if rect.width <= 0
+        || rect.height <= 0
+        || rect.width > MAX_TILE_SIZE
+        || rect.height > MAX_TILE_SIZE
+    {
+        return Err(format!("Invalid tile size {}x{}", rect.width, rect.height));
+    }
+
+    let mode = color_mode.unwrap_or_default();
+    let key = TileKey {
+        doc_id,
+        page,
+        zoom: zoom_key(scale),
+        tile: (rect.x, rect.y, rect.width, rect.height),
+        mode,
+    };
+    if let Some(bitmap) = cache.tiles.lock().unwrap().get(&key) {
+        return Ok(Response::new(bitmap.clone()));
+    }
+
+    let bitmap = engine
+        .run(move |worker| {
+            let list = worker.display_list(&doc, page)?;
+            render_region(list, rect, scale, mode).map_err(|e| e.to_string())
+        })
+        .await?;
+
+    let size = bitmap.len();
+    cache
+        .tiles
+        .lock()
+        .unwrap()
+        .insert(key, bitmap.clone(), size);
+    Ok(Response::new(bitmap))
+}
+
+fn render_region(
+    list: &mupdf::DisplayList,
+    rect: TileRect,
+    scale: f32,
+    mode: ColorMode,
+) -> Result<Vec<u8>, mupdf::Error> {
+    let area = mupdf::IRect::new(rect.x, rect.y, rect.x + rect.width, rect.y + rect.height);
+    let mut pixmap = mupdf::Pixmap::new_with_rect(&mupdf::Colorspace::device_rgb(), area, false)?;
+    pixmap.clear_with(255)?;
+
+    // The pixmap origin sits at the tile offset, so only this region is drawn
+    let ctm = mupdf::Matrix::new_scale(scale, scale);
+    let device = mupdf::Device::from_pixmap(&pixmap)?;
+    list.run(
+        &device,
+        &ctm,
+        mupdf::Rect::new(
+            area.x0 as f32,
+            area.y0 as f32,
+            area.x1 as f32,
+            area.y1 as f32,
+        ),
+    )?;
+    drop(device);
+
+    colors::apply(mode, list, &ctm, &mut pixmap)?;
+    Ok(encode_bitmap(&pixmap))
+}
@@ -0,0 +1,233 @@
+// Moving the user's settings and data to another computer.
+//
+// `export_settings` writes a single JSON archive with the preferences (which
+// hold the launch, session, shortcut and tray settings), the bookmarks and
+// the places the user was in documents, and the library's folders, tags and
+// collections. Window positions, recent files and the last session stay
+// behind, as they only make sense on the computer they're from.
+// `import_settings` reads one back: the preferences replace those there are,
+// section by section, while bookmarks, places, tags and collections are added
+// to them. Archives of version 1 kept the settings apart from the
+// preferences, and they're taken over with them. Bookmarks and places are
+// keyed by the fingerprint of the file (see `reading.rs`), so they find their
+// documents wherever they are; library paths are moved with the prefixes the
+// user maps, e.g. from `C:\Users\ann\Papers` to `/home/ann/Papers`, and
+// folders that aren't there after that are left out. The library's folders
+// only join it once the user picks each again (see `library.rs`), as an
+// archive could name any folder.
+
+use std::path::{Path, PathBuf, MAIN_SEPARATOR_STR};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tauri::{AppHandle, Manager};
+
+use crate::bookmarks::{self, ArchivedBookmark};
+use crate::library;
+use crate::pdf::blocking;
+use crate::preferences::{self, PreferencesStore};
+use crate::reading::{self, ArchivedState};
+use crate::scope;
+use crate::tags::{self, ArchivedTags};
+
+const FORMAT: &str = "nanodoc-settings";
+const VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct ArchivedLibrary {
+    folders: Vec<String>,
+    #[serde(flatten)]
+    tags: ArchivedTags,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct Archive {
+    format: String,
+    version: u32,
+    /// Milliseconds since the Unix epoch
+    exported: i64,
+    preferences: Option<Value>,
+    /// The launch, session, shortcut and tray settings of version 1
+    #[serde(skip_serializing)]
+    settings: Map<String, Value>,
+    bookmarks: Vec<ArchivedBookmark>,
+    reading: Vec<ArchivedState>,
+    library: ArchivedLibrary,
+}
+
+/// What an archive holds.
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveContents {
+    pub preferences: bool,
+    pub bookmarks: usize,
+    /// Documents with a place kept
+    pub reading: usize,
+    /// Library folders, which wait to be picked again once imported
+    pub folders: usize,
+    pub tags: usize,
+    pub collections: usize,
+}
+
+/// A prefix of paths on the computer exported from, and what it is on this
+/// one.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PathMapping {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportedSettings {
+    /// What was added or replaced
+    pub imported: ArchiveContents,
+    /// Library folders that aren't on this computer, after mapping
+    pub skipped_folders: Vec<PathBuf>,
+    /// Settings that couldn't be applied, and why
+    pub errors: Vec<String>,
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis() as i64)
+}
+
+// The path moved by the first mapping whose prefix it starts with, its
+// separators made this system's
+fn map_path(path: &str, mappings: &[PathMapping]) -> String {
+    let separator = |c: char| c == '/' || c == '\\';
+    for mapping in mappings {
+        let from = mapping.from.trim_end_matches(separator);
+        let Some(rest) = path.strip_prefix(from) else {
+            continue;
+        };
+        if !from.is_empty() && (rest.is_empty() || rest.starts_with(separator)) {
+            let rest = rest.replace(separator, MAIN_SEPARATOR_STR);
+            return format!("{}{}", mapping.to.trim_end_matches(separator), rest);
+        }
+    }
+    path.to_string()
+}
+
+fn read_archive(path: &Path) -> Result<Archive, String> {
+    let json =
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let archive: Archive = serde_json::from_slice(&json)
+        .map_err(|e| format!("{} isn't a settings archive: {}", path.display(), e))?;
+    if archive.format != FORMAT {
+        return Err(format!("{} isn't a settings archive", path.display()));
+    }
+    if archive.version > VERSION {
+        return Err(format!(
+            "{} is from a newer version of the app; update it to import the settings",
+            path.display()
+        ));
+    }
+    Ok(archive)
+}
+
+/// Writes the settings and data of the app to an archive, to import on
+/// another computer.
+#[tauri::command]
+pub async fn export_settings(app: AppHandle, path: String) -> Result<ArchiveContents, String> {
+    scope::check_write(&app, Path::new(&path))?;
+    let preferences = preferences::to_archive(&app.state::<PreferencesStore>());
+    blocking(move || {
+        let db = library::open_database(&app)?;
+        let archive = Archive {
+            format: FORMAT.into(),
+            version: VERSION,
+            exported: now(),
+            preferences: Some(preferences),
+            settings: Map::new(),
+            bookmarks: bookmarks::to_archive(&app)?,
+            reading: reading::to_archive(&app)?,
+            library: ArchivedLibrary {
+                folders: library::folders(&db)?,
+                tags: tags::to_archive(&db)?,
+            },
+        };
+        let json = serde_json::to_vec_pretty(&archive).map_err(|e| e.to_string())?;
+        std::fs::write(&path, json).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        eprintln!("Exported the settings to {}", path);
+        Ok(ArchiveContents {
+            preferences: true,
+            bookmarks: archive.bookmarks.len(),
+            reading: archive.reading.len(),
+            folders: archive.library.folders.len(),
+            tags: archive.library.tags.tags.len(),
+            collections: archive.library.tags.collections.len(),
+        })
+    })
+    .await
+}
+
+/// Imports an archive written by `export_settings`, moving the library's
+/// paths with `path_mapping`. Preferences that can't be applied here are
+/// reported and the rest imported.
+#[tauri::command]
+pub async fn import_settings(
+    app: AppHandle,
+    path: String,
+    path_mapping: Option<Vec<PathMapping>>,
+) -> Result<ImportedSettings, String> {
+    scope::check(&app, Path::new(&path))?;
+    let archive = blocking({
+        let path = path.clone();
+        move || read_archive(Path::new(&path))
+    })
+    .await?;
+    let mappings = path_mapping.unwrap_or_default();
+    let mut imported = ArchiveContents::default();
+    let mut errors = Vec::new();
+
+    if archive.preferences.is_some() || !archive.settings.is_empty() {
+        let store = app.state::<PreferencesStore>();
+        let archived = archive.preferences.unwrap_or(Value::Object(Map::new()));
+        match preferences::from_archive(&app, &store, archived, archive.settings) {
+            Ok(_) => imported.preferences = true,
+            Err(e) => errors.push(format!("Preferences: {}", e)),
+        }
+    }
+
+    let bookmarks = archive.bookmarks;
+    let reading = archive.reading;
+    let mut library = archive.library;
+    blocking(move || {
+        imported.bookmarks = bookmarks::from_archive(&app, bookmarks)?;
+        imported.reading = reading::from_archive(&app, reading)?;
+
+        let folders: Vec<PathBuf> = library
+            .folders
+            .iter()
+            .map(|folder| PathBuf::from(map_path(folder, &mappings)))
+            .collect();
+        for document in &mut library.tags.document_tags {
+            document.path = map_path(&document.path, &mappings);
+        }
+        for collection in &mut library.tags.collections {
+            if let Some(folder) = &mut collection.rule.folder {
+                *folder = map_path(folder, &mappings);
+            }
+        }
+        // The tags go in first, so they're there when the folders are indexed
+        let mut db = library::open_database(&app)?;
+        (imported.tags, imported.collections) = tags::from_archive(&app, &mut db, library.tags)?;
+        let skipped_folders = library::add_pending_folders(&db, &folders)?;
+        imported.folders = folders.len() - skipped_folders.len();
+
+        eprintln!("Imported the settings from {}", path);
+        Ok(ImportedSettings {
+            imported,
+            skipped_folders,
+            errors,
+        })
+    })
+    .await
+}
@@ -0,0 +1,182 @@
+// The tray icon, or menu bar icon on macOS.
+//
+// When the user turns it on with `set_tray_settings`, the icon's menu lists
+// the recently opened files (see `recent_files.rs`), "Open File…" and "Quit".
+// Closing the window then only hides it: the app stays running, so files open
+// at once from the tray, from the command line of a later launch, or from the
+// Finder. Clicking the icon brings the window back on Windows; on macOS and
+// Linux the menu has an item for that. The menu is rebuilt whenever the
+// recent files change. The setting is a section of the preferences (see
+// `preferences.rs`) and applies at once.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use tauri::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem, Submenu};
+use tauri::tray::{MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent};
+use tauri::{AppHandle, Manager, Window, WindowEvent};
+
+use crate::window_manager::MAIN_WINDOW;
+use crate::{launch, preferences, recent_files};
+
+const TRAY_ID: &str = "main";
+// Menu items, and the prefix of the recent files' ids, which end in the path
+const SHOW_ITEM: &str = "show";
+const OPEN_ITEM: &str = "open-file";
+const QUIT_ITEM: &str = "quit";
+const RECENT_PREFIX: &str = "recent:";
+// As many recent files as fit a menu comfortably
+const MENU_FILES: usize = 10;
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TraySettings {
+    /// Shows the icon and keeps the app running with the window closed
+    pub enabled: bool,
+}
+
+fn build_menu(app: &AppHandle) -> tauri::Result<Menu<tauri::Wry>> {
+    let menu = Menu::new(app)?;
+    menu.append(&MenuItem::with_id(
+        app,
+        SHOW_ITEM,
+        "Show Nanodoc",
+        true,
+        None::<&str>,
+    )?)?;
+    menu.append(&PredefinedMenuItem::separator(app)?)?;
+
+    let files: Vec<PathBuf> = recent_files::paths(app)
+        .into_iter()
+        .take(MENU_FILES)
+        .collect();
+    let recent = Submenu::with_id(app, "recent", "Open Recent", !files.is_empty())?;
+    for path in &files {
+        let name = path.file_name().map_or_else(
+            || path.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        recent.append(&MenuItem::with_id(
+            app,
+            format!("{}{}", RECENT_PREFIX, path.display()),
+            name,
+            true,
+            None::<&str>,
+        )?)?;
+    }
+    menu.append(&recent)?;
+    menu.append(&MenuItem::with_id(
+        app,
+        OPEN_ITEM,
+        "Open File…",
+        true,
+        None::<&str>,
+    )?)?;
+    menu.append(&PredefinedMenuItem::separator(app)?)?;
+    menu.append(&MenuItem::with_id(
+        app,
+        QUIT_ITEM,
+        "Quit",
+        true,
+        None::<&str>,
+    )?)?;
+    Ok(menu)
+}
+
+fn menu_event(app: &AppHandle, event: MenuEvent) {
+    match event.id().as_ref() {
+        SHOW_ITEM => launch::show_window(app),
+        OPEN_ITEM => {
+            launch::show_window(app);
+            launch::choose_files(app);
+        }
+        QUIT_ITEM => app.exit(0),
+        id => {
+            if let Some(path) = id.strip_prefix(RECENT_PREFIX) {
+                launch::open_files(app, vec![PathBuf::from(path)]);
+                launch::show_window(app);
+            }
+        }
+    }
+}
+
+fn icon_event(tray: &TrayIcon, event: TrayIconEvent) {
+    if let TrayIconEvent::Click {
+        button: MouseButton::Left,
+        button_state: MouseButtonState::Up,
+        ..
+    } = event
+    {
+        launch::show_window(tray.app_handle());
+    }
+}
+
+fn show(app: &AppHandle) -> Result<(), String> {
+    if app.tray_by_id(TRAY_ID).is_some() {
+        return Ok(());
+    }
+    let menu = build_menu(app).map_err(|e| e.to_string())?;
+    let mut builder = TrayIconBuilder::with_id(TRAY_ID)
+        .tooltip("Nanodoc")
+        .menu(&menu)
+        // The menu bar's icons show their menu on any click
+        .show_menu_on_left_click(cfg!(target_os = "macos"))
+        .on_menu_event(menu_event)
+        .on_tray_icon_event(icon_event);
+    if let Some(icon) = app.default_window_icon() {
+        builder = builder.icon(icon.clone());
+    }
+    builder.build(app).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Shows or removes the icon as the settings say.
+pub fn apply(app: &AppHandle, settings: &TraySettings) -> Result<(), String> {
+    match settings.enabled {
+        true => show(app),
+        false => {
+            app.remove_tray_by_id(TRAY_ID);
+            Ok(())
+        }
+    }
+}
+
+/// Shows the icon if the user turned it on.
+pub fn setup(app: &AppHandle) {
+    if let Err(e) = apply(app, &preferences::current(app).tray) {
+        eprintln!("Failed to show the tray icon: {}", e);
+    }
+}
+
+/// Rebuilds the icon's menu with the recent files.
+pub fn refresh(app: &AppHandle) {
+    let Some(tray) = app.tray_by_id(TRAY_ID) else {
+        return;
+    };
+    if let Err(e) = build_menu(app).and_then(|menu| tray.set_menu(Some(menu))) {
+        eprintln!("Failed to update the tray menu: {}", e);
+    }
+}
+
+/// Hides the window instead of closing it while the icon is shown.
+pub fn handle_window_event(window: &Window, event: &WindowEvent) {
+    if let WindowEvent::CloseRequested { api, .. } = event {
+        if window.label() == MAIN_WINDOW && window.app_handle().tray_by_id(TRAY_ID).is_some() {
+            api.prevent_close();
+            let _ = window.hide();
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn get_tray_settings(app: AppHandle) -> Result<TraySettings, String> {
+    Ok(preferences::current(&app).tray)
+}
+
+/// Saves the tray settings and shows or removes the icon.
+#[tauri::command]
+pub async fn set_tray_settings(app: AppHandle, settings: TraySettings) -> Result<(), String> {
+    let shown = settings.clone();
+    preferences::update(&app, |preferences| preferences.tray = settings)?;
+    apply(&app, &shown)
+}
@@ -0,0 +1,335 @@
+// Windows of their own for documents.
+//
+// At launch there's only the main window (see `tauri.conf.json`).
+// `open_in_new_window` opens a file in a new window, and
+// `move_document_to_window` moves a document's tab to another window or to a
+// new one. A moved document stays open under the same id, so it isn't read
+// again: the window it goes to gets a `document-moved-in` event to show it in
+// a tab, and the one it leaves a `document-moved-out` event to drop the tab
+// without closing it. Both carry the document's path, which is how the
+// frontend tells its tabs apart. New windows are labelled `document-<n>` and
+// load the same frontend as the main window. What they're to show waits here
+// until they report ready with `frontend_ready`, as the main window does (see
+// `launch.rs`), and again whenever they reload.
+//
+// Each window's state goes by its label: its tabs in the session (see
+// `session.rs`), its place on screen (see `window_state.rs`) and the
+// documents opened in it, which are closed with it. A window's title names
+// the document in front, as its frontend reports with `set_window_document`.
+// Events meant for one window, like those of the menu bar and of context
+// menus, go to the window in front or to the one with the document.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::{
+    AppHandle, Emitter, Manager, State, WebviewUrl, WebviewWindow, WebviewWindowBuilder, Window,
+    WindowEvent,
+};
+
+use crate::launch::{self, Location};
+use crate::pdf::{self, DocId, DocumentStore};
+use crate::{recent_files, scope, session, window_state};
+
+/// The label of the window the app starts with.
+pub const MAIN_WINDOW: &str = "main";
+// Document windows' labels start with this, as `tauri.conf.json` gives them
+// the main window's capability by it
+const DOCUMENT_PREFIX: &str = "document-";
+// The size of a new window, in logical pixels, like the main window's
+const WIDTH: f64 = 1200.0;
+const HEIGHT: f64 = 800.0;
+const MIN_WIDTH: f64 = 800.0;
+const MIN_HEIGHT: f64 = 600.0;
+
+/// A document moved to a window, already open.
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MovedDocument {
+    pub doc_id: DocId,
+    pub path: PathBuf,
+    pub page_count: i32,
+}
+
+// Something for a window to show
+enum Arrival {
+    Open(Location),
+    MovedIn(MovedDocument),
+}
+
+/// The windows' documents, managed as Tauri state.
+#[derive(Default)]
+pub struct WindowManager {
+    // What windows that aren't ready yet are to show, by label
+    waiting: Mutex<HashMap<String, Vec<Arrival>>>,
+    // The window each document is in
+    owners: Mutex<HashMap<DocId, String>>,
+    next: AtomicU32,
+}
+
+/// Whether the label is one `open_window` gives.
+pub fn is_document_window(label: &str) -> bool {
+    label.starts_with(DOCUMENT_PREFIX)
+}
+
+fn title(app: &AppHandle, path: Option<&Path>) -> String {
+    let product = &app.package_info().name;
+    match path.and_then(Path::file_name) {
+        Some(name) => format!("{} — {}", name.to_string_lossy(), product),
+        None => product.clone(),
+    }
+}
+
+// A label no window has
+fn new_label(app: &AppHandle) -> String {
+    let manager = app.state::<WindowManager>();
+    loop {
+        let label = format!(
+            "{}{}",
+            DOCUMENT_PREFIX,
+            manager.next.fetch_add(1, Ordering::Relaxed) + 1
+        );
+        if app.get_webview_window(&label).is_none() {
+            return label;
+        }
+    }
+}
+
+fn deliver(app: &AppHandle, label: &str, arrival: Arrival) {
+    if let Some(waiting) = app
+        .state::<WindowManager>()
+        .waiting
+        .lock()
+        .unwrap()
+        .get_mut(label)
+    {
+        waiting.push(arrival);
+        return;
+    }
+    match arrival {
+        Arrival::Open(location) => launch::send(app, label, location),
+        Arrival::MovedIn(moved) => {
+            let _ = app.emit_to(label, "document-moved-in", moved);
+        }
+    }
+}
+
+fn build(app: &AppHandle, label: Option<&str>, arrivals: Vec<Arrival>) -> Result<String, String> {
+    let label = label.map_or_else(|| new_label(app), str::to_string);
+    let path = arrivals.first().map(|arrival| match arrival {
+        Arrival::Open(location) => location.path.clone(),
+        Arrival::MovedIn(moved) => moved.path.clone(),
+    });
+    let manager = app.state::<WindowManager>();
+    manager
+        .waiting
+        .lock()
+        .unwrap()
+        .insert(label.clone(), arrivals);
+    let built = WebviewWindowBuilder::new(app, &label, WebviewUrl::App("index.html".into()))
+        .title(title(app, path.as_deref()))
+        .inner_size(WIDTH, HEIGHT)
+        .min_inner_size(MIN_WIDTH, MIN_HEIGHT)
+        .visible(false)
+        .build();
+    match built {
+        Ok(window) => {
+            window_state::show_new(&window);
+            eprintln!("Opened window {}", label);
+            Ok(label)
+        }
+        Err(e) => {
+            manager.waiting.lock().unwrap().remove(&label);
+            Err(format!("Failed to open a window: {}", e))
+        }
+    }
+}
+
+/// Opens a window, with the label given or a new one, and has it open the
+/// files. Returns its label.
+pub fn open_window(
+    app: &AppHandle,
+    label: Option<&str>,
+    locations: Vec<Location>,
+) -> Result<String, String> {
+    build(
+        app,
+        label,
+        locations.into_iter().map(Arrival::Open).collect(),
+    )
+}
+
+/// Sends a document window what waited for it to be ready.
+pub fn window_ready(app: &AppHandle, label: &str) {
+    let arrivals = app
+        .state::<WindowManager>()
+        .waiting
+        .lock()
+        .unwrap()
+        .remove(label)
+        .unwrap_or_default();
+    for arrival in arrivals {
+        deliver(app, label, arrival);
+    }
+}
+
+/// Holds back what's for a document window again while it (re)loads.
+pub fn page_loading(app: &AppHandle, label: &str) {
+    app.state::<WindowManager>()
+        .waiting
+        .lock()
+        .unwrap()
+        .entry(label.to_string())
+        .or_default();
+}
+
+/// Records that a document was opened in a window.
+pub fn adopt(app: &AppHandle, label: &str, doc_id: DocId) {
+    app.state::<WindowManager>()
+        .owners
+        .lock()
+        .unwrap()
+        .insert(doc_id, label.to_string());
+}
+
+/// Forgets the window of a closed document.
+pub fn release(app: &AppHandle, doc_id: DocId) {
+    app.state::<WindowManager>()
+        .owners
+        .lock()
+        .unwrap()
+        .remove(&doc_id);
+}
+
+/// The label of the window a document is in, the main window's if unknown.
+pub fn owner(app: &AppHandle, doc_id: DocId) -> String {
+    app.state::<WindowManager>()
+        .owners
+        .lock()
+        .unwrap()
+        .get(&doc_id)
+        .cloned()
+        .unwrap_or_else(|| MAIN_WINDOW.to_string())
+}
+
+/// The label of the window in front, the main window's if none is.
+pub fn focused(app: &AppHandle) -> String {
+    app.webview_windows()
+        .into_iter()
+        .find(|(_, window)| window.is_focused().unwrap_or(false))
+        .map_or_else(|| MAIN_WINDOW.to_string(), |(label, _)| label)
+}
+
+/// Forgets a window the user closes while others stay open, and closes the
+/// documents of a window that's gone.
+pub fn handle_window_event(window: &Window, event: &WindowEvent) {
+    let app = window.app_handle();
+    match event {
+        WindowEvent::CloseRequested { .. }
+            if window.label() != MAIN_WINDOW && app.webview_windows().len() > 1 =>
+        {
+            session::forget_window(app, window.label());
+        }
+        WindowEvent::Destroyed => {
+            let manager = app.state::<WindowManager>();
+            manager.waiting.lock().unwrap().remove(window.label());
+            let documents: Vec<DocId> = {
+                let mut owners = manager.owners.lock().unwrap();
+                let documents = owners
+                    .iter()
+                    .filter(|(_, label)| *label == window.label())
+                    .map(|(doc_id, _)| *doc_id)
+                    .collect();
+                owners.retain(|_, label| label != window.label());
+                documents
+            };
+            for doc_id in documents {
+                pdf::discard_document(app, doc_id);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Opens a file in a new window. Returns the window's label.
+#[tauri::command]
+pub async fn open_in_new_window(app: AppHandle, path: String) -> Result<String, String> {
+    let path = PathBuf::from(path);
+    scope::check(&app, &path)?;
+    if !path.is_file() {
+        return Err(format!("{} doesn't exist", path.display()));
+    }
+    recent_files::add(&app, &path);
+    open_window(&app, None, vec![Location::file(path)])
+}
+
+/// Moves a document of the calling window to the window labelled
+/// `window_label`, or to a new window without one. Returns the label of the
+/// window it went to. Documents of other windows are left where they are.
+#[tauri::command]
+pub async fn move_document_to_window(
+    app: AppHandle,
+    window: Window,
+    doc_id: DocId,
+    window_label: Option<String>,
+    store: State<'_, DocumentStore>,
+) -> Result<String, String> {
+    let doc = store.get(doc_id)?;
+    if owner(&app, doc_id) != window.label() {
+        return Err(format!("Document {} isn't in this window", doc_id));
+    }
+    let moved = MovedDocument {
+        doc_id,
+        path: doc.path.clone(),
+        page_count: doc.page_count,
+    };
+    let label = match window_label {
+        Some(label) if label == window.label() => {
+            return Err(format!("Document {} is in {} already", doc_id, label));
+        }
+        Some(label) => {
+            if app.get_webview_window(&label).is_none() {
+                return Err(format!("No window {}", label));
+            }
+            deliver(&app, &label, Arrival::MovedIn(moved.clone()));
+            label
+        }
+        None => build(&app, None, vec![Arrival::MovedIn(moved.clone())])?,
+    };
+    adopt(&app, &label, doc_id);
+    let _ = app.emit_to(window.label(), "document-moved-out", moved);
+    eprintln!(
+        "Moved document {} from {} to {}",
+        doc_id,
+        window.label(),
+        label
+    );
+    Ok(label)
+}
+
+/// The labels of the open windows, the main window's first.
+#[tauri::command]
+pub fn get_windows(app: AppHandle) -> Vec<String> {
+    let mut labels: Vec<String> = app.webview_windows().into_keys().collect();
+    labels.sort_by_key(|label| (label != MAIN_WINDOW, label.clone()));
+    labels
+}
+
+/// Titles the calling window after the document in front of it, or after
+/// the app when it has none.
+#[tauri::command]
+pub fn set_window_document(
+    window: WebviewWindow,
+    doc_id: Option<DocId>,
+    store: State<'_, DocumentStore>,
+) -> Result<(), String> {
+    let path = doc_id.map(|doc_id| store.get(doc_id)).transpose()?;
+    let title = title(
+        window.app_handle(),
+        path.as_ref().map(|doc| doc.path.as_path()),
+    );
+    window.set_title(&title).map_err(|e| e.to_string())
+}
@@ -0,0 +1,225 @@
+// Where each window was on screen, so it opens there again.
+//
+// The size and position of every window, whether it was maximized or full
+// screen and the monitor it was on are followed as they change, by window
+// label, and saved at `<app data>/window-state.json` when a window closes and
+// when the app exits. Sizes and positions are in physical pixels, with the
+// scale factor of the monitor, as monitors side by side may scale
+// differently. The size kept while a window is maximized is the one it goes
+// back to.
+//
+// Windows start hidden (see `tauri.conf.json` and `window_manager.rs`) and
+// are shown once placed. A window whose monitor is gone, or that wouldn't
+// show enough of itself on the monitors there are, as when their arrangement
+// changed, is centered on the primary monitor instead, at its size scaled to
+// that monitor and no larger than it.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{
+    AppHandle, Manager, Monitor, PhysicalPosition, PhysicalSize, RunEvent, WebviewWindow, Window,
+    WindowEvent,
+};
+
+// How much of a window, in physical pixels each way, must be on a monitor
+const MIN_VISIBLE: i64 = 100;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowGeometry {
+    /// The outer position
+    pub x: i32,
+    pub y: i32,
+    /// The inner size
+    pub width: u32,
+    pub height: u32,
+    pub maximized: bool,
+    #[serde(default)]
+    pub fullscreen: bool,
+    pub monitor: Option<String>,
+    pub scale_factor: f64,
+}
+
+/// The geometry of every window, managed as Tauri state.
+#[derive(Default)]
+pub struct WindowStates {
+    windows: Mutex<BTreeMap<String, WindowGeometry>>,
+}
+
+fn state_file(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("window-state.json"))
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))
+}
+
+fn save(app: &AppHandle) {
+    let saved = state_file(app).and_then(|file| {
+        let state = app.state::<WindowStates>();
+        let json = serde_json::to_vec_pretty(&*state.windows.lock().unwrap())
+            .map_err(|e| e.to_string())?;
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        std::fs::write(&file, json)
+            .map_err(|e| format!("Failed to write {}: {}", file.display(), e))
+    });
+    if let Err(e) = saved {
+        eprintln!("Failed to save the window positions: {}", e);
+    }
+}
+
+// How many pixels of a window would be on a monitor, each way
+fn overlap(geometry: &WindowGeometry, monitor: &Monitor) -> (i64, i64) {
+    let area = monitor.work_area();
+    let (left, top) = (area.position.x as i64, area.position.y as i64);
+    let (right, bottom) = (left + area.size.width as i64, top + area.size.height as i64);
+    let (x, y) = (geometry.x as i64, geometry.y as i64);
+    let width = (x + geometry.width as i64).min(right) - x.max(left);
+    let height = (y + geometry.height as i64).min(bottom) - y.max(top);
+    (width, height)
+}
+
+// Where a window goes, moved onto the primary monitor when it wouldn't show
+// where it was
+fn fitted(window: &WebviewWindow, geometry: &WindowGeometry) -> WindowGeometry {
+    let monitors = window.available_monitors().unwrap_or_default();
+    let on_its_monitor = monitors
+        .iter()
+        .any(|monitor| monitor.name() == geometry.monitor.as_ref());
+    let visible = monitors.iter().any(|monitor| {
+        let (width, height) = overlap(geometry, monitor);
+        width >= MIN_VISIBLE && height >= MIN_VISIBLE
+    });
+    if on_its_monitor && visible {
+        return geometry.clone();
+    }
+    let Some(monitor) = window
+        .primary_monitor()
+        .ok()
+        .flatten()
+        .or_else(|| monitors.into_iter().next())
+    else {
+        return geometry.clone();
+    };
+    eprintln!(
+        "Window {} wouldn't show where it was, moving it to {}",
+        window.label(),
+        monitor
+            .name()
+            .map_or("the primary monitor", |name| name.as_str())
+    );
+    let area = monitor.work_area();
+    let scale = monitor.scale_factor() / geometry.scale_factor.max(0.1);
+    let width = ((geometry.width as f64 * scale) as u32).min(area.size.width);
+    let height = ((geometry.height as f64 * scale) as u32).min(area.size.height);
+    WindowGeometry {
+        x: area.position.x + ((area.size.width - width) / 2) as i32,
+        y: area.position.y + ((area.size.height - height) / 2) as i32,
+        width,
+        height,
+        monitor: monitor.name().cloned(),
+        scale_factor: monitor.scale_factor(),
+        ..geometry.clone()
+    }
+}
+
+fn place(window: &WebviewWindow, geometry: &WindowGeometry) {
+    let geometry = fitted(window, geometry);
+    let _ = window.set_size(PhysicalSize::new(geometry.width, geometry.height));
+    let _ = window.set_position(PhysicalPosition::new(geometry.x, geometry.y));
+    if geometry.maximized {
+        let _ = window.maximize();
+    }
+    if geometry.fullscreen {
+        let _ = window.set_fullscreen(true);
+    }
+}
+
+/// Puts the windows back where they were and shows them.
+pub fn setup(app: &AppHandle) {
+    let saved: BTreeMap<String, WindowGeometry> = state_file(app)
+        .ok()
+        .and_then(|file| std::fs::read(file).ok())
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .unwrap_or_default();
+    for (label, window) in app.webview_windows() {
+        if let Some(geometry) = saved.get(&label) {
+            place(&window, geometry);
+        }
+        let _ = window.show();
+    }
+    *app.state::<WindowStates>().windows.lock().unwrap() = saved;
+}
+
+/// Puts a window opened after launch where the one with its label was, and
+/// shows it.
+pub fn show_new(window: &WebviewWindow) {
+    let saved = window
+        .state::<WindowStates>()
+        .windows
+        .lock()
+        .unwrap()
+        .get(window.label())
+        .cloned();
+    if let Some(geometry) = saved {
+        place(window, &geometry);
+    }
+    let _ = window.show();
+}
+
+// The window's geometry now, keeping the size and position it had before it
+// was maximized or made full screen
+fn current(window: &Window, previous: Option<&WindowGeometry>) -> Option<WindowGeometry> {
+    if window.is_minimized().unwrap_or(false) {
+        return None;
+    }
+    let maximized = window.is_maximized().unwrap_or(false);
+    let fullscreen = window.is_fullscreen().unwrap_or(false);
+    if let (true, Some(previous)) = (maximized || fullscreen, previous) {
+        return Some(WindowGeometry {
+            maximized,
+            fullscreen,
+            ..previous.clone()
+        });
+    }
+    let position = window.outer_position().ok()?;
+    let size = window.inner_size().ok()?;
+    let monitor = window.current_monitor().ok().flatten();
+    Some(WindowGeometry {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+        maximized,
+        fullscreen,
+        monitor: monitor.as_ref().and_then(|monitor| monitor.name().cloned()),
+        scale_factor: monitor.map_or(1.0, |monitor| monitor.scale_factor()),
+    })
+}
+
+/// Follows windows as they move and saves where they are when they close.
+pub fn handle_window_event(window: &Window, event: &WindowEvent) {
+    match event {
+        WindowEvent::Moved(_) | WindowEvent::Resized(_) => {
+            let state = window.state::<WindowStates>();
+            let mut windows = state.windows.lock().unwrap();
+            if let Some(geometry) = current(window, windows.get(window.label())) {
+                windows.insert(window.label().to_string(), geometry);
+            }
+        }
+        WindowEvent::CloseRequested { .. } | WindowEvent::Destroyed => save(window.app_handle()),
+        _ => {}
+    }
+}
+
+/// Saves where the windows are as the app exits.
+pub fn handle_run_event(app: &AppHandle, event: &RunEvent) {
+    if matches!(event, RunEvent::Exit) {
+        save(app);
+    }
+}
@@ -0,0 +1,513 @@
+// Exchanging annotations with XFDF files.
+//
+// XFDF is the XML format Acrobat and most other PDF tools pass comments
+// around in, without the PDF they belong to. Exporting writes the
+// annotations of the kinds `annotations.rs` handles, in PDF user space as the
+// format has them; importing draws those of a file into the document.
+// Annotations keep their unique names (/NM), so importing a colleague's copy
+// back changes the ones already there instead of adding them twice.
+// Annotations of other kinds, and pop-ups, are skipped.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use lopdf::{Document, ObjectId};
+use quick_xml::escape::{escape, resolve_predefined_entity};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, XmlVersion};
+use serde::Serialize;
+use tauri::{AppHandle, State};
+
+use crate::annotations::{write_annotation, AnnotationKind, Spec};
+use crate::edit::{apply_edit_with, EditedDocument};
+use crate::objects::{self, PageSpace};
+use crate::pdf::{blocking, Bounds, DocId, DocumentStore};
+use crate::permissions::{Action, PermissionPolicy};
+use crate::scheduler::RenderScheduler;
+use crate::scope;
+use crate::tiles::TileCache;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportedAnnotations {
+    pub document: EditedDocument,
+    pub added: usize,
+    /// Annotations that were in the document already, by name
+    pub updated: usize,
+    /// Annotations of kinds Nanodoc doesn't draw, or on missing pages
+    pub skipped: usize,
+}
+
+fn element(kind: AnnotationKind) -> &'static str {
+    match kind {
+        AnnotationKind::Highlight => "highlight",
+        AnnotationKind::Underline => "underline",
+        AnnotationKind::StrikeOut => "strikeout",
+        AnnotationKind::Note => "text",
+        AnnotationKind::Rectangle => "square",
+        AnnotationKind::Ink => "ink",
+    }
+}
+
+fn kind_of(element: &str) -> Option<AnnotationKind> {
+    match element {
+        "highlight" => Some(AnnotationKind::Highlight),
+        "underline" => Some(AnnotationKind::Underline),
+        "strikeout" => Some(AnnotationKind::StrikeOut),
+        "text" => Some(AnnotationKind::Note),
+        "square" => Some(AnnotationKind::Rectangle),
+        "ink" => Some(AnnotationKind::Ink),
+        _ => None,
+    }
+}
+
+// Numbers as XFDF lists them, to a thousandth of a point
+fn join(values: &[f32]) -> String {
+    values
+        .iter()
+        .map(|v| ((v * 1000.0).round() / 1000.0).to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn split(list: &str) -> Vec<f32> {
+    list.split([',', ';', ' '])
+        .filter_map(|v| v.trim().parse().ok())
+        .collect()
+}
+
+fn hex_color(color: [f32; 3]) -> String {
+    let [r, g, b] = color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+    format!("#{:02X}{:02X}{:02X}", r, g, b)
+}
+
+fn parse_color(color: &str) -> Option<[f32; 3]> {
+    let hex = color.trim().strip_prefix('#')?;
+    let channel = |i: usize| {
+        let value = u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()?;
+        Some(value as f32 / 255.0)
+    };
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+fn numbers(doc: &Document, values: &[lopdf::Object]) -> Vec<f32> {
+    values
+        .iter()
+        .filter_map(|v| objects::number(objects::resolve(doc, v)))
+        .collect()
+}
+
+fn to_xfdf(doc: &Document, pdf_name: &str) -> (String, usize) {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <xfdf xmlns=\"http://ns.adobe.com/xfdf/\" xml:space=\"preserve\">\n",
+    );
+    out.push_str(&format!("  <f href=\"{}\"/>\n", escape(pdf_name)));
+    out.push_str("  <annots>\n");
+    let mut count = 0;
+    for (number, page_id) in doc.get_pages() {
+        let Ok(page) = doc.get_dictionary(page_id) else {
+            continue;
+        };
+        for annot in objects::get_array(doc, page, b"Annots").unwrap_or(&[]) {
+            let Ok(annot) = objects::resolve(doc, annot).as_dict() else {
+                continue;
+            };
+            let Some(kind) =
+                objects::get_name(doc, annot, b"Subtype").and_then(AnnotationKind::from_subtype)
+            else {
+                continue;
+            };
+            let Some(rect) = objects::get(doc, annot, b"Rect").and_then(|r| objects::rect(doc, r))
+            else {
+                continue;
+            };
+
+            let mut attributes = vec![("page", (number - 1).to_string()), ("rect", join(&rect))];
+            let color = objects::get_array(doc, annot, b"C").map(|c| numbers(doc, c));
+            if let Some(&[r, g, b]) = color.as_deref() {
+                attributes.push(("color", hex_color([r, g, b])));
+            }
+            if let Some(opacity) = objects::get(doc, annot, b"CA").and_then(objects::number) {
+                attributes.push(("opacity", opacity.to_string()));
+            }
+            let texts: [(&str, &[u8]); 3] = [("name", b"NM"), ("title", b"T"), ("date", b"M")];
+            for (attribute, key) in texts {
+                if let Some(text) = objects::get_text(doc, annot, key) {
+                    attributes.push((attribute, text));
+                }
+            }
+            match kind {
+                AnnotationKind::Highlight
+                | AnnotationKind::Underline
+                | AnnotationKind::StrikeOut => {
+                    if let Some(quads) = objects::get_array(doc, annot, b"QuadPoints") {
+                        attributes.push(("coords", join(&numbers(doc, quads))));
+                    }
+                }
+                AnnotationKind::Note => {
+                    let icon = objects::get_name(doc, annot, b"Name").unwrap_or(b"Note");
+                    attributes.push(("icon", String::from_utf8_lossy(icon).into_owned()));
+                }
+                AnnotationKind::Rectangle | AnnotationKind::Ink => {
+                    if let Some(width) = objects::get_dict(doc, annot, b"BS")
+                        .and_then(|bs| objects::get(doc, bs, b"W"))
+                        .and_then(objects::number)
+                    {
+                        attributes.push(("width", width.to_string()));
+                    }
+                }
+            }
+
+            let attributes: String = attributes
+                .iter()
+                .map(|(name, value)| format!(" {}=\"{}\"", name, escape(value.as_str())))
+                .collect();
+            out.push_str(&format!("    <{}{}>\n", element(kind), attributes));
+            if let Some(contents) = objects::get_text(doc, annot, b"Contents") {
+                out.push_str(&format!(
+                    "      <contents>{}</contents>\n",
+                    escape(contents.as_str())
+                ));
+            }
+            if kind == AnnotationKind::Ink {
+                out.push_str("      <inklist>\n");
+                for stroke in objects::get_array(doc, annot, b"InkList").unwrap_or(&[]) {
+                    let Ok(stroke) = objects::resolve(doc, stroke).as_array() else {
+                        continue;
+                    };
+                    let points: Vec<String> =
+                        numbers(doc, stroke).chunks_exact(2).map(join).collect();
+                    out.push_str(&format!(
+                        "        <gesture>{}</gesture>\n",
+                        points.join(";")
+                    ));
+                }
+                out.push_str("      </inklist>\n");
+            }
+            out.push_str(&format!("    </{}>\n", element(kind)));
+            count += 1;
+        }
+    }
+    out.push_str("  </annots>\n</xfdf>\n");
+    (out, count)
+}
+
+// An annotation read from XFDF, in PDF user space
+struct Imported {
+    kind: AnnotationKind,
+    attributes: HashMap<String, String>,
+    contents: Option<String>,
+    gestures: Vec<String>,
+}
+
+impl Imported {
+    fn new(kind: AnnotationKind, tag: &BytesStart) -> Self {
+        let attributes = tag
+            .attributes()
+            .flatten()
+            .filter_map(|attr| {
+                let value = attr.normalized_value(XmlVersion::Implicit1_0).ok()?;
+                Some((attr.key.into_inner().to_string(), value.into_owned()))
+            })
+            .collect();
+        Imported {
+            kind,
+            attributes,
+            contents: None,
+            gestures: Vec::new(),
+        }
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+}
+
+// Where text being read goes
+enum Reading {
+    Nothing,
+    Contents,
+    Gesture,
+}
+
+fn read_xfdf(xml: &str) -> Result<(Vec<Imported>, usize), String> {
+    let mut reader = Reader::from_str(xml);
+    let mut annotations = Vec::new();
+    let mut skipped = 0;
+    // Depth of the element being read, and of <annots>
+    let mut depth = 0;
+    let mut annots = None;
+    let mut current: Option<Imported> = None;
+    let mut reading = Reading::Nothing;
+    let mut text = String::new();
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(tag)) => {
+                let name = tag.name().into_inner().to_string();
+                depth += 1;
+                if name == "annots" && annots.is_none() {
+                    annots = Some(depth);
+                } else if annots.is_some_and(|annots| depth == annots + 1) {
+                    match kind_of(&name) {
+                        Some(kind) => current = Some(Imported::new(kind, &tag)),
+                        None => skipped += 1,
+                    }
+                } else if current.is_some() {
+                    reading = match name.as_str() {
+                        "contents" => Reading::Contents,
+                        "gesture" => Reading::Gesture,
+                        _ => Reading::Nothing,
+                    };
+                    text.clear();
+                }
+            }
+            Ok(Event::Empty(tag)) => {
+                let name = tag.name().into_inner().to_string();
+                if annots.is_some_and(|annots| depth == annots) {
+                    match kind_of(&name) {
+                        Some(kind) => annotations.push(Imported::new(kind, &tag)),
+                        None => skipped += 1,
+                    }
+                }
+            }
+            Ok(Event::End(_)) => {
+                if let Some(annotation) = current.as_mut() {
+                    match std::mem::replace(&mut reading, Reading::Nothing) {
+                        Reading::Contents => annotation.contents = Some(std::mem::take(&mut text)),
+                        Reading::Gesture => annotation.gestures.push(std::mem::take(&mut text)),
+                        Reading::Nothing => {}
+                    }
+                }
+                if annots.is_some_and(|annots| depth == annots + 1) {
+                    annotations.extend(current.take());
+                }
+                if annots == Some(depth) {
+                    annots = None;
+                }
+                depth -= 1;
+            }
+            Ok(Event::Text(content)) if !matches!(reading, Reading::Nothing) => {
+                text.push_str(&content.xml10_content());
+            }
+            Ok(Event::CData(content)) if !matches!(reading, Reading::Nothing) => {
+                text.push_str(&content.xml10_content());
+            }
+            Ok(Event::GeneralRef(entity)) if !matches!(reading, Reading::Nothing) => {
+                if let Ok(Some(c)) = entity.resolve_char_ref() {
+                    text.push(c);
+                } else if let Some(s) = resolve_predefined_entity(&entity) {
+                    text.push_str(s);
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(format!("The XFDF file is malformed: {}", e)),
+            _ => {}
+        }
+    }
+    Ok((annotations, skipped))
+}
+
+// The rectangle around a quadrilateral or a rect attribute
+fn user_rect(values: &[f32]) -> [f32; 4] {
+    let xs = values.iter().step_by(2);
+    let ys = values.iter().skip(1).step_by(2);
+    [
+        xs.clone().copied().fold(f32::INFINITY, f32::min),
+        ys.clone().copied().fold(f32::INFINITY, f32::min),
+        xs.copied().fold(f32::NEG_INFINITY, f32::max),
+        ys.copied().fold(f32::NEG_INFINITY, f32::max),
+    ]
+}
+
+// What to write an imported annotation from, in page space
+fn spec(imported: Imported, space: &PageSpace) -> Option<Spec> {
+    let kind = imported.kind;
+    let rect = split(imported.attribute("rect")?);
+    if rect.len() != 4 {
+        return None;
+    }
+    let bounds: Bounds = space.rect(user_rect(&rect));
+    let quads = imported.attribute("coords").map(split).unwrap_or_default();
+    let rects = match kind {
+        AnnotationKind::Highlight | AnnotationKind::Underline | AnnotationKind::StrikeOut
+            if quads.len() >= 8 =>
+        {
+            quads
+                .chunks_exact(8)
+                .map(|quad| space.rect(user_rect(quad)))
+                .collect()
+        }
+        _ => vec![bounds],
+    };
+    let strokes = imported
+        .gestures
+        .iter()
+        .map(|gesture| {
+            split(gesture)
+                .chunks_exact(2)
+                .map(|point| {
+                    let (x, y) = space.point(point[0], point[1]);
+                    [x, y]
+                })
+                .collect()
+        })
+        .collect();
+    let number = |name: &str| imported.attribute(name).and_then(|v| v.trim().parse().ok());
+    Some(Spec {
+        kind,
+        rects,
+        strokes,
+        color: imported
+            .attribute("color")
+            .and_then(parse_color)
+            .unwrap_or(kind.default_color()),
+        opacity: number("opacity").unwrap_or(1.0),
+        width: number("width").unwrap_or(kind.default_width()),
+        contents: imported.contents.clone(),
+        author: imported.attribute("title").map(str::to_string),
+        name: imported.attribute("name").map(str::to_string),
+        modified: imported.attribute("date").map(str::to_string),
+    })
+}
+
+// Adds the annotations, or changes those with the same name on the same
+// page. Returns the numbers added, changed and skipped.
+fn import(doc: &mut Document, annotations: Vec<Imported>) -> Result<(usize, usize, usize), String> {
+    let pages: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    let mut named: HashMap<(String, ObjectId), ObjectId> = HashMap::new();
+    for &page_id in &pages {
+        let Ok(page) = doc.get_dictionary(page_id) else {
+            continue;
+        };
+        for annot in objects::get_array(doc, page, b"Annots").unwrap_or(&[]) {
+            let Ok(id) = annot.as_reference() else {
+                continue;
+            };
+            let name = doc
+                .get_dictionary(id)
+                .ok()
+                .and_then(|annot| objects::get_text(doc, annot, b"NM"));
+            if let Some(name) = name {
+                named.insert((name, page_id), id);
+            }
+        }
+    }
+
+    let (mut added, mut updated, mut skipped) = (0, 0, 0);
+    for imported in annotations {
+        let page_id = imported
+            .attribute("page")
+            .and_then(|page| page.trim().parse::<usize>().ok())
+            .and_then(|page| pages.get(page).copied());
+        let Some(page_id) = page_id else {
+            skipped += 1;
+            continue;
+        };
+        let space = PageSpace::new(doc, page_id);
+        let Some(spec) = spec(imported, &space) else {
+            skipped += 1;
+            continue;
+        };
+        let existing = spec
+            .name
+            .clone()
+            .and_then(|name| named.get(&(name, page_id)).copied());
+        let id = write_annotation(doc, page_id, existing, &spec)?;
+        match existing {
+            Some(_) => updated += 1,
+            None => {
+                added += 1;
+                if let Some(name) = spec.name {
+                    named.insert((name, page_id), id);
+                }
+            }
+        }
+    }
+    if added + updated == 0 {
+        return Err("The file has no annotations Nanodoc can add".to_string());
+    }
+    Ok((added, updated, skipped))
+}
+
+/// Saves the annotations of a document at `path`, as XFDF. Returns the
+/// number saved.
+#[tauri::command]
+pub async fn export_annotations_xfdf(
+    app: AppHandle,
+    doc_id: DocId,
+    path: String,
+    store: State<'_, DocumentStore>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<usize, String> {
+    scope::check_write(&app, Path::new(&path))?;
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Copy).await?;
+    blocking(move || {
+        let pdf_name = doc
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let (xml, count) = doc.with_objects(|objects| Ok(to_xfdf(objects, &pdf_name)))?;
+        if count == 0 {
+            return Err(format!(
+                "{} has no annotations to export",
+                doc.path.display()
+            ));
+        }
+        std::fs::write(Path::new(&path), xml)
+            .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        eprintln!(
+            "Exported {} annotations of document {} to {}",
+            count, doc_id, path
+        );
+        Ok(count)
+    })
+    .await
+}
+
+/// Adds the annotations of the XFDF file at `path` to a document. Like other
+/// edits, they are written when the document is saved.
+#[tauri::command]
+pub async fn import_annotations_xfdf(
+    app: AppHandle,
+    doc_id: DocId,
+    path: String,
+    store: State<'_, DocumentStore>,
+    tiles: State<'_, TileCache>,
+    scheduler: State<'_, RenderScheduler>,
+    policy: State<'_, PermissionPolicy>,
+) -> Result<ImportedAnnotations, String> {
+    scope::check(&app, Path::new(&path))?;
+    let doc = store.get(doc_id)?;
+    policy.check(&doc, Action::Annotate).await?;
+    let (annotations, unknown) = {
+        let path = path.clone();
+        blocking(move || {
+            let xml = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            read_xfdf(&xml)
+        })
+        .await?
+    };
+    let (document, (added, updated, skipped)) =
+        apply_edit_with(doc, &store, &tiles, &scheduler, move |objects| {
+            import(objects, annotations)
+        })
+        .await?;
+    eprintln!(
+        "Imported {} annotations into document {} from {} ({} changed, {} skipped)",
+        added + updated,
+        doc_id,
+        path,
+        updated,
+        skipped + unknown
+    );
+    Ok(ImportedAnnotations {
+        document,
+        added,
+        updated,
+        skipped: skipped + unknown,
+    })
+}
@@ -5,7 +5,7 @@
  * Allows navigating through individual search matches with highlighting.
  */
 
-import { useState, useEffect } from "react";
+import { useState, useEffect, useRef } from "react";
 import { Search, X, ChevronUp, ChevronDown } from "lucide-react";
 import { Button } from "@/components/ui/button";
 import { Input } from "@/components/ui/input";
@@ -14,6 +14,7 @@ import { usePDFStore, type SearchMatch, type SearchResultData } from "@/shared/s
 export function SearchBar() {
   const [query, setQuery] = useState("");
   const [isSearching, setIsSearching] = useState(false);
+  const inputRef = useRef<HTMLInputElement>(null);
   const { 
     getCurrentDocument, 
     setCurrentPage, 
@@ -121,6 +122,22 @@ export function SearchBar() {
     setCurrentSearchResult(-1);
   };
 
+  // Find, Find Next and Find Previous of the menu bar (see Editor.tsx)
+  useEffect(() => {
+    const handleFind = () => {
+      inputRef.current?.focus();
+      inputRef.current?.select();
+    };
+    window.addEventListener("openSearch", handleFind);
+    window.addEventListener("findNext", handleNext);
+    window.addEventListener("findPrevious", handlePrevious);
+    return () => {
+      window.removeEventListener("openSearch", handleFind);
+      window.removeEventListener("findNext", handleNext);
+      window.removeEventListener("findPrevious", handlePrevious);
+    };
+  });
+
   if (!currentDocument) return null;
 
   return (
@@ -128,6 +145,7 @@ export function SearchBar() {
       <div className="relative w-full">
         <Search className="absolute left-2 top-1/2 transform -translate-y-1/2 h-4 w-4 text-muted-foreground" />
         <Input
+          ref={inputRef}
           type="text"
           placeholder="Search text in document"
           value={query}
@@ -1107,6 +1107,21 @@ export function ThumbnailCarousel() {
     setPagesToRotate([]);
   };
 
+  // Rotate Left and Rotate Right of the menu bar turn the selected pages, or
+  // the current one (see Editor.tsx)
+  useEffect(() => {
+    const handleRotate = (e: Event) => {
+      const pages = selectedPages.size > 0
+        ? Array.from(selectedPages).sort((a, b) => a - b)
+        : [currentPage];
+      handleRotatePages(pages, (e as CustomEvent<number>).detail);
+    };
+    window.addEventListener("rotatePages", handleRotate);
+    return () => {
+      window.removeEventListener("rotatePages", handleRotate);
+    };
+  });
+
   if (!showThumbnails || !currentDocument || !renderer) {
     return null;
   }
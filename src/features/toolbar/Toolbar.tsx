@@ -385,6 +385,23 @@ export function Toolbar() {
     setShowPrintDialog(true);
   };
 
+  // Save, Save As, Export and Print of the menu bar (see Editor.tsx)
+  useEffect(() => {
+    const handleExport = () => {
+      if (getCurrentDocument()) setShowExportDialog(true);
+    };
+    window.addEventListener("saveFile", handleSaveFile);
+    window.addEventListener("saveFileAs", handleSaveAs);
+    window.addEventListener("openExport", handleExport);
+    window.addEventListener("printFile", handlePrint);
+    return () => {
+      window.removeEventListener("saveFile", handleSaveFile);
+      window.removeEventListener("saveFileAs", handleSaveAs);
+      window.removeEventListener("openExport", handleExport);
+      window.removeEventListener("printFile", handlePrint);
+    };
+  });
+
   const handleExecutePrint = async (
     settings: PrintSettings,
     startPage: number,
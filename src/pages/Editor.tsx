@@ -1,4 +1,4 @@
-import React, { useEffect, useState } from "react";
+import React, { useEffect, useRef, useState } from "react";
 import { useFileSystem } from "@/shared/hooks/useFileSystem";
 import { usePDF } from "@/shared/hooks/usePDF";
 import { useDragDrop } from "@/shared/hooks/useDragDrop";
@@ -38,7 +38,11 @@ function Editor() {
   const { getRecentFiles } = useRecentFilesStore();
   const { readMode, activeTool } = useUIStore();
   const fileSystem = useFileSystem();
-  const { loadPDF, loading } = usePDF();
+  const { loadPDF, loading, closeCurrentDocument } = usePDF();
+  // For the menu bar's Close Tab, without setting up its listener again
+  // whenever the document in front changes
+  const closeCurrentDocumentRef = useRef(closeCurrentDocument);
+  closeCurrentDocumentRef.current = closeCurrentDocument;
   const { showNotification } = useNotificationStore();
   const [showRecentFilesOnStartup, setShowRecentFilesOnStartup] = useState(false);
   const [showStampCreator, setShowStampCreator] = useState(false);
@@ -514,11 +518,10 @@ function Editor() {
       }
 
         if (listen) {
-          // Listen for open-pdf-file event
-          const unlisten = listen("open-pdf-file", async (event: any) => {
-            const filePath = event.payload;
-            console.log("Received open-pdf-file event:", filePath);
-
+          // Files opened together arrive as one event each; they're opened
+          // one after another so each gets a tab of its own
+          let opening: Promise<void> = Promise.resolve();
+          const openFromSystem = async (filePath: unknown) => {
             if (filePath && typeof filePath === "string") {
               const pdfStore = usePDFStore.getState();
               try {
@@ -547,10 +550,160 @@ function Editor() {
             } else {
               console.warn("Invalid file path received:", filePath);
             }
+          };
+
+          // Combines the PDFs picked, in the order picked, into a new file
+          // the backend writes, and opens that
+          const combineFiles = async () => {
+            const { open, save } = await import("@tauri-apps/plugin-dialog");
+            const filters = [{ name: "PDF", extensions: ["pdf"] }];
+            const picked = await open({ multiple: true, filters });
+            if (!Array.isArray(picked)) return;
+            if (picked.length < 2) {
+              showNotification("Select at least two documents to combine", "error");
+              return;
+            }
+            const outputPath = await save({ defaultPath: "Combined.pdf", filters });
+            if (!outputPath) return;
+            try {
+              const { invoke } = await import("@tauri-apps/api/core");
+              await invoke("merge_documents", { paths: picked, outputPath });
+              opening = opening.then(() => openFromSystem(outputPath));
+            } catch (error) {
+              showNotification(String(error), "error");
+            }
+          };
+
+          // Listen for open-pdf-file event
+          const unlisten = listen("open-pdf-file", (event: any) => {
+            console.log("Received open-pdf-file event:", event.payload);
+            opening = opening.then(() => openFromSystem(event.payload));
+          });
+
+          // nanodoc:// links open a file at a page (counted from 1) and zoom
+          // (in percent)
+          const unlistenLocation = listen("open-pdf-location", (event: any) => {
+            const { path, page, zoom } = event.payload ?? {};
+            opening = opening
+              .then(() => openFromSystem(path))
+              .then(() => {
+                if (typeof page === "number") {
+                  usePDFStore.getState().setCurrentPage(page - 1);
+                }
+                if (typeof zoom === "number") {
+                  useUIStore.getState().setZoomLevel(zoom / 100);
+                }
+              });
           });
 
+          // A document moved here from another window gets a tab of its own
+          const unlistenMovedIn = listen("document-moved-in", (event: any) => {
+            opening = opening.then(() => openFromSystem(event.payload?.path));
+          });
+
+          // A document moved to another window leaves its tab here, as
+          // closing the tab would
+          const unlistenMovedOut = listen("document-moved-out", (event: any) => {
+            const path = event.payload?.path;
+            const pdfStore = usePDFStore.getState();
+            const tabStore = useTabStore.getState();
+            for (const tab of tabStore.tabs) {
+              if (path && pdfStore.getDocumentPath(tab.documentId) === path) {
+                import("@/shared/stores/printStore").then(({ usePrintStore }) => {
+                  usePrintStore.getState().removeDocumentSettings(tab.documentId);
+                });
+                pdfStore.removeDocument(tab.documentId);
+                tabStore.removeTab(tab.id);
+              }
+            }
+          });
+
+          // The menu bar's items, by id (see `menu_bar.rs`); most are carried
+          // out by the components that have them, as their shortcuts are
+          const unlistenMenu = listen("menu-action", (event: any) => {
+            const send = (name: string, detail?: unknown) =>
+              window.dispatchEvent(new CustomEvent(name, { detail }));
+            const ui = useUIStore.getState();
+            const pdfStore = usePDFStore.getState();
+            const tabStore = useTabStore.getState();
+            const switchTab = (step: number) => {
+              const count = tabStore.tabs.length;
+              const index = tabStore.tabs.findIndex((tab) => tab.id === tabStore.activeTabId);
+              if (index < 0) return;
+              const tab = tabStore.tabs[(index + step + count) % count];
+              tabStore.setActiveTab(tab.id);
+              pdfStore.setCurrentDocument(tab.documentId);
+            };
+            switch (event.payload) {
+              case "save":
+                return send("saveFile");
+              case "save-as":
+                return send("saveFileAs");
+              case "export":
+                return send("openExport");
+              case "print":
+                return send("printFile");
+              case "combine-files":
+                return combineFiles();
+              case "reveal": {
+                const id = pdfStore.currentDocumentId;
+                const path = id ? pdfStore.getDocumentPath(id) : null;
+                if (path) {
+                  import("@tauri-apps/api/core")
+                    .then(({ invoke }) => invoke("reveal_in_file_manager", { path }))
+                    .catch((error) => showNotification(String(error), "error"));
+                }
+                return;
+              }
+              case "close-tab":
+                return closeCurrentDocumentRef.current();
+              case "find":
+                return send("openSearch");
+              case "find-next":
+                return send("findNext");
+              case "find-previous":
+                return send("findPrevious");
+              case "zoom-in":
+                return ui.setZoomLevel(ui.zoomLevel + 0.25);
+              case "zoom-out":
+                return ui.setZoomLevel(ui.zoomLevel - 0.25);
+              case "actual-size":
+                return ui.setZoomLevel(1.0);
+              case "fit-page":
+                return ui.setFitMode("page");
+              case "fit-width":
+                return ui.setFitMode("width");
+              // As the rotate dialog of the thumbnails turns them
+              case "rotate-left":
+                return send("rotatePages", 270);
+              case "rotate-right":
+                return send("rotatePages", 90);
+              case "toggle-sidebar":
+                return ui.toggleThumbnails();
+              case "next-tab":
+                return switchTab(1);
+              case "previous-tab":
+                return switchTab(-1);
+              case "shortcuts":
+                return send("openHelp");
+              default:
+                console.warn("Unknown menu item:", event.payload);
+            }
+          });
+
+          // Files opened from the system are held back until the listeners
+          // are in place, so none are missed while the window loads
+          Promise.all([unlisten, unlistenLocation, unlistenMovedIn, unlistenMovedOut, unlistenMenu])
+            .then(() => import("@tauri-apps/api/core"))
+            .then(({ invoke }) => invoke("frontend_ready"))
+            .catch((error) => console.warn("Could not report the window ready:", error));
+
           return () => {
             unlisten.then((fn: () => void) => fn());
+            unlistenLocation.then((fn: () => void) => fn());
+            unlistenMovedIn.then((fn: () => void) => fn());
+            unlistenMovedOut.then((fn: () => void) => fn());
+            unlistenMenu.then((fn: () => void) => fn());
           };
       } else {
         console.warn("Tauri event listener not available despite Tauri detection");